@@ -0,0 +1,209 @@
+//! Benchmarks of the scans of large inventories, on synthetic inventories (see `cloud_scanner_cli::synthetic_inventory`): the summary of the impacts, the serialization of the results, and the estimation of the impacts against a mocked Boavizta API (answering the same impacts to every request).
+//!
+//! Run with `cargo bench -p cloud-scanner-cli`, and compare with the results of the previous release (criterion reports the changes since the last run).
+use cloud_scanner_cli::result_envelope::{ResultMetadata, ScanParameters};
+use cloud_scanner_cli::result_stream::{ResultStream, StreamFormat};
+use cloud_scanner_cli::synthetic_inventory::{with_synthetic_impacts, InventoryShape};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+const INSTANCE_IMPACTS: &str =
+    include_str!("../test-data/DEFAULT_RAW_IMPACTS_OF_M6GXLARGE_1HRS_FR.json");
+const HDD_IMPACTS: &str = include_str!("../test-data/DEFAULT_RAW_IMPACTS_OF_HDD.json");
+const SSD_IMPACTS: &str = include_str!("../test-data/DEFAULT_RAW_IMPACTS_OF_SSD_1000GB_1HR.json");
+
+const USE_DURATION_HOURS: f32 = 730.0;
+
+fn shape(resources: usize) -> InventoryShape {
+    InventoryShape {
+        instances: resources * 2 / 3,
+        volumes: resources / 3,
+        regions: vec![
+            "eu-west-1".to_string(),
+            "eu-west-3".to_string(),
+            "us-east-1".to_string(),
+        ],
+        accounts: 4,
+        ..InventoryShape::default()
+    }
+}
+
+fn regions() -> Vec<String> {
+    shape(0).regions
+}
+
+fn summary(c: &mut Criterion) {
+    let mut group = c.benchmark_group("summary");
+    for resources in [1_000, 10_000, 50_000] {
+        let estimated_inventory =
+            with_synthetic_impacts(shape(resources).generate(), USE_DURATION_HOURS);
+        group.throughput(Throughput::Elements(resources as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(resources),
+            &estimated_inventory,
+            |b, estimated_inventory| {
+                b.iter(|| {
+                    cloud_scanner_cli::build_summary_of_regions(
+                        estimated_inventory,
+                        &regions(),
+                        &USE_DURATION_HOURS,
+                    )
+                    .unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn serialization(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialization");
+    let metadata = ResultMetadata::new(chrono::Utc::now(), ScanParameters::default(), None);
+    for resources in [1_000, 10_000, 50_000] {
+        let estimated_inventory =
+            with_synthetic_impacts(shape(resources).generate(), USE_DURATION_HOURS);
+        let summary = cloud_scanner_cli::build_summary_of_regions(
+            &estimated_inventory,
+            &regions(),
+            &USE_DURATION_HOURS,
+        )
+        .unwrap();
+        group.throughput(Throughput::Elements(resources as u64));
+        group.bench_with_input(
+            BenchmarkId::new("json", resources),
+            &estimated_inventory,
+            |b, estimated_inventory| {
+                b.iter(|| {
+                    cloud_scanner_cli::impacts_to_json_string(
+                        &metadata,
+                        estimated_inventory,
+                        &summary,
+                        false,
+                    )
+                    .unwrap()
+                })
+            },
+        );
+        for (name, format) in [
+            ("stream_json", StreamFormat::Json),
+            ("stream_ndjson", StreamFormat::Ndjson),
+        ] {
+            group.bench_with_input(
+                BenchmarkId::new(name, resources),
+                &estimated_inventory,
+                |b, estimated_inventory| {
+                    b.iter(|| {
+                        let stream = ResultStream::start(
+                            std::io::sink(),
+                            format,
+                            &metadata,
+                            summary.clone(),
+                        )
+                        .unwrap();
+                        for page in estimated_inventory.impacting_resources.chunks(500) {
+                            stream.write_page(page).unwrap();
+                        }
+                        let summary = stream.summary();
+                        stream.finish(None, &[], &summary).unwrap()
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+/// Answers a connection to the mocked Boavizta API
+async fn answer(stream: TcpStream) -> std::io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    loop {
+        let mut request_line = String::new();
+        if stream.read_line(&mut request_line).await? == 0 {
+            return Ok(());
+        }
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            stream.read_line(&mut header).await?;
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        stream.read_exact(&mut body).await?;
+        let impacts = match request_line.split_whitespace().nth(1).unwrap_or_default() {
+            path if path.contains("/component/hdd") => HDD_IMPACTS,
+            path if path.contains("/component/ssd") => SSD_IMPACTS,
+            _ => INSTANCE_IMPACTS,
+        };
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            impacts.len(),
+            impacts
+        );
+        stream.get_mut().write_all(response.as_bytes()).await?;
+    }
+}
+
+/// Starts a mocked Boavizta API on a local port, returns its URL
+async fn mocked_boavizta_api() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(answer(stream));
+        }
+    });
+    url
+}
+
+fn estimation(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let api_url = runtime.block_on(mocked_boavizta_api());
+    // Failed requests would be faster than the estimation
+    let estimated_inventory = runtime
+        .block_on(cloud_scanner_cli::estimate_inventory_impacts(
+            shape(10).generate(),
+            &USE_DURATION_HOURS,
+            &api_url,
+            false,
+        ))
+        .unwrap();
+    assert!(estimated_inventory
+        .impacting_resources
+        .iter()
+        .all(|resource| resource.impacts_values.is_some()));
+    let mut group = c.benchmark_group("estimation");
+    group.sample_size(10);
+    for resources in [100, 1_000] {
+        let inventory = shape(resources).generate();
+        group.throughput(Throughput::Elements(resources as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(resources),
+            &inventory,
+            |b, inventory| {
+                b.to_async(&runtime).iter(|| async {
+                    cloud_scanner_cli::estimate_inventory_impacts(
+                        inventory.clone(),
+                        &USE_DURATION_HOURS,
+                        &api_url,
+                        false,
+                    )
+                    .await
+                    .unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, summary, serialization, estimation);
+criterion_main!(benches);
@@ -0,0 +1,9 @@
+// Generates the gRPC server code and the messages of the Prometheus remote-write protocol from the protobuf definitions, with a vendored protoc so that no install is needed.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::configure().build_client(false).compile(
+        &["proto/cloud_scanner.proto", "proto/remote_write.proto"],
+        &["proto"],
+    )?;
+    Ok(())
+}
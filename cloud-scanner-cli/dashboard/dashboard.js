@@ -0,0 +1,200 @@
+// Dashboard of the standalone server: the latest stored scan, its breakdown by tag and the trend of the stored scans (from /scans and /scans/{id}).
+"use strict";
+
+const CRITERIA = {
+  gwp: { unit: "kgCO2eq", use: "gwp_use_kgco2eq", manufacture: "gwp_manufacture_kgco2eq" },
+  pe: { unit: "MJ", use: "pe_use_megajoules", manufacture: "pe_manufacture_megajoules" },
+  adp: { unit: "kgSbeq", use: "adp_use_kgsbeq", manufacture: "adp_manufacture_kgsbeq" },
+};
+const API_KEY_STORAGE = "cloud-scanner-api-key";
+const NO_TAG = "(no value)";
+
+const apiKeyInput = document.getElementById("api-key");
+const criterionSelect = document.getElementById("criterion");
+const tagKeySelect = document.getElementById("tag-key");
+let latestResources = [];
+
+function showMessage(text) {
+  const message = document.getElementById("message");
+  message.textContent = text;
+  message.hidden = !text;
+}
+
+async function fetchJson(path) {
+  const headers = {};
+  if (apiKeyInput.value) {
+    headers["Authorization"] = "Bearer " + apiKeyInput.value;
+  }
+  const response = await fetch(path, { headers });
+  if (response.status === 401) {
+    throw new Error("Enter a valid API key to load the scans.");
+  }
+  if (response.status === 403) {
+    throw new Error("The history of scans is not available to tenants.");
+  }
+  if (response.status === 404) {
+    throw new Error("The server has no result store: configure `store` in the [server] section to keep the history of scans.");
+  }
+  if (!response.ok) {
+    throw new Error("Cannot load " + path + ": " + response.status + " " + (await response.text()));
+  }
+  return response.json();
+}
+
+function format(value) {
+  if (value === 0) {
+    return "0";
+  }
+  return Math.abs(value) >= 100 ? value.toFixed(0) : value.toPrecision(3);
+}
+
+function card(label, value) {
+  const element = document.createElement("div");
+  element.className = "card";
+  element.innerHTML = '<div class="value"></div><div class="label"></div>';
+  element.querySelector(".value").textContent = value;
+  element.querySelector(".label").textContent = label;
+  return element;
+}
+
+function renderLatest(scan, criterion) {
+  const summary = scan.summary;
+  const c = CRITERIA[criterion];
+  document.getElementById("latest-date").textContent =
+    "Scan " + scan.id + " of " + new Date(scan.timestamp).toLocaleString() +
+    ", for " + summary.duration_of_use_hours + " hours of use";
+  const cards = document.getElementById("latest-cards");
+  cards.replaceChildren(
+    card("Resources (assessed / total)", summary.number_of_resources_assessed + " / " + summary.number_of_resources_total),
+    card("Use (" + c.unit + ")", format(summary[c.use])),
+    card("Manufacture (" + c.unit + ")", format(summary[c.manufacture])),
+    card("Total (" + c.unit + ")", format(summary[c.use] + summary[c.manufacture])),
+  );
+}
+
+function tagKeys(resources) {
+  const keys = new Set();
+  for (const resource of resources) {
+    for (const tag of resource.cloud_resource.tags) {
+      keys.add(tag.key);
+    }
+  }
+  return [...keys].sort();
+}
+
+function renderTagKeys(resources) {
+  const selected = tagKeySelect.value;
+  const keys = tagKeys(resources);
+  tagKeySelect.replaceChildren(...keys.map((key) => new Option(key, key)));
+  if (keys.includes(selected)) {
+    tagKeySelect.value = selected;
+  }
+}
+
+function renderBreakdown(resources, tagKey, criterion) {
+  const c = CRITERIA[criterion];
+  const groups = new Map();
+  for (const resource of resources) {
+    const tag = resource.cloud_resource.tags.find((t) => t.key === tagKey);
+    const value = (tag && tag.value) || NO_TAG;
+    const group = groups.get(value) || { count: 0, use: 0, manufacture: 0 };
+    group.count += 1;
+    if (resource.impacts_values) {
+      group.use += resource.impacts_values[c.use];
+      group.manufacture += resource.impacts_values[c.manufacture];
+    }
+    groups.set(value, group);
+  }
+  const rows = [...groups.entries()].sort((a, b) => b[1].use + b[1].manufacture - (a[1].use + a[1].manufacture));
+  const max = Math.max(...rows.map(([, g]) => g.use + g.manufacture), 0);
+  const tbody = document.querySelector("#breakdown-table tbody");
+  tbody.replaceChildren(
+    ...rows.map(([value, group]) => {
+      const total = group.use + group.manufacture;
+      const row = document.createElement("tr");
+      for (const text of [value, group.count, format(group.use), format(group.manufacture), format(total) + " " + c.unit]) {
+        const cell = document.createElement("td");
+        cell.textContent = text;
+        row.appendChild(cell);
+      }
+      const bar = document.createElement("td");
+      bar.className = "bar";
+      bar.innerHTML = "<div></div>";
+      bar.firstChild.style.width = (max > 0 ? (100 * total) / max : 0) + "%";
+      row.appendChild(bar);
+      return row;
+    }),
+  );
+}
+
+function svgElement(name, attributes, text) {
+  const element = document.createElementNS("http://www.w3.org/2000/svg", name);
+  for (const [key, value] of Object.entries(attributes)) {
+    element.setAttribute(key, value);
+  }
+  if (text !== undefined) {
+    element.textContent = text;
+  }
+  return element;
+}
+
+function renderTrend(scans, criterion) {
+  const c = CRITERIA[criterion];
+  const chart = document.getElementById("trend-chart");
+  const [width, height, margin] = [800, 260, 40];
+  const points = scans.map((scan) => ({
+    time: new Date(scan.timestamp).getTime(),
+    value: scan.summary[c.use] + scan.summary[c.manufacture],
+  }));
+  chart.replaceChildren();
+  if (points.length === 0) {
+    return;
+  }
+  const [minTime, maxTime] = [points[0].time, points[points.length - 1].time];
+  const maxValue = Math.max(...points.map((p) => p.value)) || 1;
+  const x = (time) => margin + (maxTime > minTime ? ((time - minTime) / (maxTime - minTime)) * (width - 2 * margin) : (width - 2 * margin) / 2);
+  const y = (value) => height - margin - (value / maxValue) * (height - 2 * margin);
+
+  chart.appendChild(svgElement("line", { class: "axis", x1: margin, y1: height - margin, x2: width - margin, y2: height - margin }));
+  chart.appendChild(svgElement("line", { class: "axis", x1: margin, y1: margin, x2: margin, y2: height - margin }));
+  chart.appendChild(svgElement("text", { x: 4, y: margin - 8 }, format(maxValue) + " " + c.unit));
+  chart.appendChild(svgElement("text", { x: margin, y: height - 12 }, new Date(minTime).toLocaleDateString()));
+  chart.appendChild(svgElement("text", { x: width - margin, y: height - 12, "text-anchor": "end" }, new Date(maxTime).toLocaleDateString()));
+  chart.appendChild(svgElement("polyline", { class: "line", points: points.map((p) => x(p.time) + "," + y(p.value)).join(" ") }));
+  for (const p of points) {
+    const point = svgElement("circle", { class: "point", cx: x(p.time), cy: y(p.value), r: 3 });
+    point.appendChild(svgElement("title", {}, new Date(p.time).toLocaleString() + ": " + format(p.value) + " " + c.unit));
+    chart.appendChild(point);
+  }
+}
+
+async function refresh() {
+  const criterion = criterionSelect.value;
+  try {
+    const { scans } = await fetchJson("/scans?fields=id,timestamp,summary");
+    if (scans.length === 0) {
+      showMessage("No scan is stored yet: configure scheduled scans to fill the history.");
+      return;
+    }
+    const latest = scans[scans.length - 1];
+    const details = await fetchJson("/scans/" + latest.id + "?fields=cloud_resource.tags,impacts_values");
+    latestResources = details.impactingResources;
+    showMessage("");
+    renderLatest(latest, criterion);
+    renderTagKeys(latestResources);
+    renderBreakdown(latestResources, tagKeySelect.value, criterion);
+    renderTrend(scans, criterion);
+  } catch (error) {
+    showMessage(error.message);
+  }
+}
+
+apiKeyInput.value = localStorage.getItem(API_KEY_STORAGE) || "";
+document.getElementById("settings").addEventListener("submit", (event) => {
+  event.preventDefault();
+  localStorage.setItem(API_KEY_STORAGE, apiKeyInput.value);
+  refresh();
+});
+criterionSelect.addEventListener("change", refresh);
+tagKeySelect.addEventListener("change", () => renderBreakdown(latestResources, tagKeySelect.value, criterionSelect.value));
+refresh();
@@ -0,0 +1,372 @@
+//! Structured access logs of the standalone server, so that operators can correlate API calls with the scans they trigger.
+//!
+//! Each request gets a request id (the `X-Request-Id` header of the client when valid, or a random one), returned in the `X-Request-Id` header of the response. A fairing prints one json line per request on stdout (method, path, tenant, status, duration and request id). The logs of the scans of a request are prefixed with its request id.
+//!
+//! The other logs are written on stderr, as text or as json lines (see [LogFormat]) so that the logs of the Lambda and of containers can be parsed.
+//!
+//! Without the `server` feature (like in the Lambda), only the loggers are available.
+use log::Log;
+#[cfg(feature = "server")]
+use rocket::fairing::{Fairing, Info, Kind};
+#[cfg(feature = "server")]
+use rocket::http::Header;
+#[cfg(feature = "server")]
+use rocket::request::{FromRequest, Outcome};
+#[cfg(feature = "server")]
+use rocket::{Request, Response};
+#[cfg(feature = "server")]
+use rocket_okapi::gen::OpenApiGenerator;
+#[cfg(feature = "server")]
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use serde::Serialize;
+use std::future::Future;
+use std::str::FromStr;
+#[cfg(feature = "server")]
+use std::time::Instant;
+
+/// Header carrying the request id, in requests and responses
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+tokio::task_local! {
+    static CURRENT_REQUEST_ID: String;
+}
+
+/// The id of a request, as a request guard
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Keeps the id passed by the client when it is valid (up to 128 letters, digits, `-`, `_` or `.`), or generates one
+    #[cfg(feature = "server")]
+    fn new(from_client: Option<&str>) -> Self {
+        let is_valid = |id: &&str| {
+            !id.is_empty()
+                && id.len() <= 128
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        };
+        match from_client.filter(is_valid) {
+            Some(id) => RequestId(id.to_string()),
+            None => RequestId(uuid::Uuid::new_v4().to_string()),
+        }
+    }
+
+    /// Returns the id of a request (generated the first time it is requested)
+    #[cfg(feature = "server")]
+    fn of<'r>(request: &'r Request<'_>) -> &'r RequestId {
+        request.local_cache(|| RequestId::new(request.headers().get_one(REQUEST_ID_HEADER)))
+    }
+
+    /// Runs a future (like the scan of the request) with this request id, so that its logs are prefixed with it
+    pub async fn scope<F: Future>(&self, future: F) -> F::Output {
+        CURRENT_REQUEST_ID.scope(self.0.clone(), future).await
+    }
+}
+
+/// Returns the id of the request whose scan is running, if any
+pub fn current_request_id() -> Option<String> {
+    CURRENT_REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+#[cfg(feature = "server")]
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestId {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(RequestId::of(request).clone())
+    }
+}
+
+#[cfg(feature = "server")]
+impl<'r> OpenApiFromRequest<'r> for RequestId {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
+}
+
+/// The tenant of an authenticated request, recorded by authentication for the access log
+#[cfg(feature = "server")]
+pub(crate) struct RequestTenant(pub Option<String>);
+
+/// When the server started to handle a request
+#[cfg(feature = "server")]
+struct RequestStart(Option<Instant>);
+
+/// A line of the access log
+#[cfg(feature = "server")]
+#[derive(Debug, Serialize)]
+struct AccessLogLine<'a> {
+    request_id: &'a str,
+    method: &'a str,
+    path: &'a str,
+    tenant: Option<&'a str>,
+    status: u16,
+    duration_ms: f64,
+}
+
+/// A fairing assigning request ids, and printing an access log line per request when enabled
+#[cfg(feature = "server")]
+pub struct AccessLog {
+    enabled: bool,
+}
+
+#[cfg(feature = "server")]
+impl AccessLog {
+    pub fn new(enabled: bool) -> Self {
+        AccessLog { enabled }
+    }
+}
+
+#[cfg(feature = "server")]
+#[rocket::async_trait]
+impl Fairing for AccessLog {
+    fn info(&self) -> Info {
+        Info {
+            name: "Access log",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut rocket::Data<'_>) {
+        request.local_cache(|| RequestStart(Some(Instant::now())));
+        RequestId::of(request);
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let RequestId(request_id) = RequestId::of(request);
+        response.set_header(Header::new(REQUEST_ID_HEADER, request_id.clone()));
+        if !self.enabled {
+            return;
+        }
+        let RequestStart(start) = request.local_cache(|| RequestStart(None));
+        let RequestTenant(tenant) = request.local_cache(|| RequestTenant(None));
+        let line = AccessLogLine {
+            request_id,
+            method: request.method().as_str(),
+            path: request.uri().path().as_str(),
+            tenant: tenant.as_deref(),
+            status: response.status().code,
+            duration_ms: start.map_or(0.0, |start| start.elapsed().as_secs_f64() * 1000.0),
+        };
+        match serde_json::to_string(&line) {
+            Ok(line) => println!("{}", line),
+            Err(e) => warn!("Cannot write access log: {}", e),
+        }
+    }
+}
+
+/// Format of the logs
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LogFormat {
+    /// Lines of text, like `INFO: message`
+    #[default]
+    Text,
+    /// One json object per line, with the timestamp, level, target, message and request id (when logged by the scan of a request)
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => anyhow::bail!("Unknown log format '{}', expecting text or json", format),
+        }
+    }
+}
+
+/// A log as a json line
+#[derive(Serialize)]
+struct JsonLogLine<'a> {
+    timestamp: String,
+    level: &'a str,
+    target: &'a str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+impl<'a> JsonLogLine<'a> {
+    fn new(record: &'a log::Record, request_id: Option<String>) -> Self {
+        JsonLogLine {
+            timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            level: record.level().as_str(),
+            target: record.target(),
+            message: record.args().to_string(),
+            request_id,
+        }
+    }
+}
+
+/// A logger prefixing the logs of the scans of requests with their request id
+struct RequestIdLogger {
+    inner: loggerv::Logger,
+    format: LogFormat,
+}
+
+impl Log for RequestIdLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.format == LogFormat::Json {
+            if self.enabled(record.metadata()) {
+                let line = JsonLogLine::new(record, current_request_id());
+                if let Ok(line) = serde_json::to_string(&line) {
+                    eprintln!("{}", line);
+                }
+            }
+            return;
+        }
+        match current_request_id() {
+            Some(id) => self.inner.log(
+                &log::Record::builder()
+                    .args(format_args!("[request_id={}] {}", id, record.args()))
+                    .metadata(record.metadata().clone())
+                    .target(record.target())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            ),
+            None => self.inner.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// Returns the level of the logs for a verbosity (number of `-v`, errors only by default), no logs are written when quiet
+pub fn log_level(verbosity: u64, quiet: bool) -> log::LevelFilter {
+    if quiet {
+        return log::LevelFilter::Off;
+    }
+    match verbosity {
+        0 => log::LevelFilter::Error,
+        1 => log::LevelFilter::Warn,
+        2 => log::LevelFilter::Info,
+        3 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    }
+}
+
+/// Initializes logging on stderr (like `loggerv::init_with_verbosity`), with the request id in the logs of the scans of requests.
+///
+/// Text logs are colored when the terminal supports it, unless `colors` is false.
+pub fn init_logger(
+    level: log::LevelFilter,
+    format: LogFormat,
+    colors: bool,
+) -> Result<(), log::SetLoggerError> {
+    let mut inner = loggerv::Logger::new().max_level(level.to_level().unwrap_or(log::Level::Error));
+    if !colors {
+        inner = inner.colors(false);
+    }
+    log::set_max_level(level);
+    log::set_boxed_logger(Box::new(RequestIdLogger { inner, format }))
+}
+
+/// Initializes logging with the level and format of the `CLOUD_SCANNER_LOG_LEVEL` (like `info`, the default, or `off`) and `CLOUD_SCANNER_LOG_FORMAT` (`text` or `json`) environment variables, like in the Lambda
+pub fn init_logger_from_env(default_format: LogFormat) -> anyhow::Result<()> {
+    let level = match std::env::var("CLOUD_SCANNER_LOG_LEVEL") {
+        Ok(level) => log::LevelFilter::from_str(&level)
+            .map_err(|_| anyhow::anyhow!("Unknown log level '{}'", level))?,
+        Err(_) => log::LevelFilter::Info,
+    };
+    let format = match std::env::var("CLOUD_SCANNER_LOG_FORMAT") {
+        Ok(format) => format.parse()?,
+        Err(_) => default_format,
+    };
+    init_logger(level, format, false)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "server")]
+    use rocket::http::Status;
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn invalid_request_ids_are_replaced() {
+        assert_eq!(
+            RequestId("abc-123_4.5".to_string()),
+            RequestId::new(Some("abc-123_4.5"))
+        );
+        for invalid in [None, Some(""), Some("a b"), Some("a\nb")] {
+            let RequestId(id) = RequestId::new(invalid);
+            assert!(uuid::Uuid::parse_str(&id).is_ok());
+        }
+        assert_ne!("x".repeat(129), RequestId::new(Some(&"x".repeat(129))).0);
+    }
+
+    #[test]
+    fn logs_are_formatted_as_json_lines() {
+        assert_eq!(LogFormat::Json, "json".parse().unwrap());
+        assert!("yaml".parse::<LogFormat>().is_err());
+        assert_eq!(log::LevelFilter::Off, log_level(2, true));
+        assert_eq!(log::LevelFilter::Info, log_level(2, false));
+
+        let args = format_args!("Scanning {}", "eu-west-3");
+        let record = log::Record::builder()
+            .args(args)
+            .level(log::Level::Info)
+            .target("cloud_scanner_cli")
+            .build();
+        let line =
+            serde_json::to_value(JsonLogLine::new(&record, Some("abc".to_string()))).unwrap();
+        assert_eq!("INFO", line["level"]);
+        assert_eq!("Scanning eu-west-3", line["message"]);
+        assert_eq!("abc", line["request_id"]);
+        assert!(line["timestamp"].as_str().unwrap().ends_with('Z'));
+    }
+
+    #[cfg(feature = "server")]
+    #[rocket::get("/scan")]
+    async fn scan(request_id: RequestId) -> String {
+        request_id
+            .scope(async { current_request_id().unwrap_or_default() })
+            .await
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn request_ids_are_returned_and_propagated_to_scans() {
+        let rocket = rocket::build()
+            .mount("/", rocket::routes![scan])
+            .attach(AccessLog::new(true));
+        let client = rocket::local::blocking::Client::untracked(rocket).unwrap();
+
+        let response = client
+            .get("/scan")
+            .header(Header::new(REQUEST_ID_HEADER, "from-client"))
+            .dispatch();
+        assert_eq!(Status::Ok, response.status());
+        assert_eq!(
+            Some("from-client"),
+            response.headers().get_one(REQUEST_ID_HEADER)
+        );
+        assert_eq!("from-client", response.into_string().unwrap());
+
+        let response = client.get("/scan").dispatch();
+        let id = response
+            .headers()
+            .get_one(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_string();
+        assert_eq!(id, response.into_string().unwrap());
+        assert_eq!(None, current_request_id());
+    }
+}
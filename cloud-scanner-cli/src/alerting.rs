@@ -0,0 +1,650 @@
+//! Alert rules (`[alerts]` of the configuration file) evaluated after each scan of `estimate --alert` and `watch --alert`: impacts above a threshold, impacts increasing by more than a percentage compared to a baseline (or to the previous scan), and resources entering the top emitters.
+//!
+//! ```toml
+//! [alerts]
+//! channels = ["notifications", "email", "webhook"]
+//! webhook_urls = ["https://alerts.example.com/cloud-scanner"]
+//! renotify_after_hours = "24h"
+//!
+//! [[alerts.rules]]
+//! kind = "threshold"
+//! name = "gwp-above-100"
+//! max = 100.0
+//!
+//! [[alerts.rules]]
+//! kind = "increase"
+//! name = "gwp-up-20-percent"
+//! max_percent = 20.0
+//! baseline = "2024-q1"
+//!
+//! [[alerts.rules]]
+//! kind = "new_top_emitter"
+//! name = "new-top-5"
+//! top = 5
+//! ```
+//!
+//! Alerts fire to the Slack or Teams webhooks of `[notifications]`, the recipients of `[email]` and json webhooks. They are deduplicated with a state file, which also keeps the totals and top emitters of the previous scan: an alert raised by consecutive scans only fires again after `renotify_after_hours`, and an alert no longer raised is resolved.
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::baselines::BaselineStore;
+use crate::email_sender::EmailConfig;
+use crate::model::EstimatedInventory;
+use crate::notifier::{self, NotificationConfig};
+use crate::top_emitters::{self, RankingCriterion};
+
+/// File of the state of the alerts, relative to the working directory
+pub const DEFAULT_STATE_FILE: &str = ".cloud-scanner/alerts.json";
+
+fn default_criterion() -> RankingCriterion {
+    RankingCriterion::Gwp
+}
+
+fn default_top() -> usize {
+    5
+}
+
+fn default_renotify_after_hours() -> f32 {
+    24.0
+}
+
+fn default_state_file() -> String {
+    DEFAULT_STATE_FILE.to_string()
+}
+
+fn default_baselines_dir() -> String {
+    crate::baselines::DEFAULT_DIR.to_string()
+}
+
+/// A rule raising alerts from the impacts of a scan (a total of the resources for a criterion, like gwp)
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum AlertRule {
+    /// The total is above a value
+    Threshold {
+        name: String,
+        #[serde(default = "default_criterion")]
+        criterion: RankingCriterion,
+        max: f64,
+    },
+    /// The total increased by more than a percentage compared to a baseline, or to the previous scan without baseline
+    Increase {
+        name: String,
+        #[serde(default = "default_criterion")]
+        criterion: RankingCriterion,
+        max_percent: f64,
+        /// Name of a saved baseline (see `baseline save`)
+        baseline: Option<String>,
+    },
+    /// A resource is part of the top emitters and was not at the previous scan
+    NewTopEmitter {
+        name: String,
+        #[serde(default = "default_criterion")]
+        criterion: RankingCriterion,
+        #[serde(default = "default_top")]
+        top: usize,
+    },
+}
+
+impl AlertRule {
+    pub fn name(&self) -> &str {
+        match self {
+            AlertRule::Threshold { name, .. }
+            | AlertRule::Increase { name, .. }
+            | AlertRule::NewTopEmitter { name, .. } => name,
+        }
+    }
+}
+
+/// A channel receiving the alerts
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertChannel {
+    /// The Slack or Teams webhooks of the `[notifications]` section
+    Notifications,
+    /// The recipients of the `[email]` section
+    Email,
+    /// The `webhook_urls` of the alerts, receiving the alerts as json
+    Webhook,
+}
+
+/// Settings of the alerts
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AlertsConfig {
+    pub rules: Vec<AlertRule>,
+    pub channels: Vec<AlertChannel>,
+    /// Urls receiving the alerts as json (`webhook` channel)
+    #[serde(default)]
+    pub webhook_urls: Vec<String>,
+    /// Delay before an alert raised by consecutive scans fires again (24 hours by default)
+    #[serde(
+        default = "default_renotify_after_hours",
+        deserialize_with = "crate::duration::deserialize_hours"
+    )]
+    pub renotify_after_hours: f32,
+    /// State of the alerts (defaults to [DEFAULT_STATE_FILE])
+    #[serde(default = "default_state_file")]
+    pub state_file: String,
+    /// Directory of the baselines of the `increase` rules
+    #[serde(default = "default_baselines_dir")]
+    pub baselines_dir: String,
+}
+
+/// An alert raised by a rule
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct Alert {
+    pub rule: String,
+    /// Identifies the alert when deduplicating: the rule, and the resource of new top emitters
+    pub key: String,
+    pub message: String,
+}
+
+/// Alerts raised by the previous scans and what the next scan is compared to
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct AlertState {
+    /// Totals of the criteria at the previous scan
+    pub previous_totals: BTreeMap<String, f64>,
+    /// Top emitters at the previous scan, by criterion and size of the top (like `gwp:5`)
+    pub previous_top_emitters: BTreeMap<String, Vec<String>>,
+    /// Alerts raised by the previous scan, with the date they last fired
+    pub active: BTreeMap<String, DateTime<Utc>>,
+}
+
+impl AlertState {
+    /// Loads the state, empty when the file does not exist
+    pub fn load(path: &str) -> Result<Self> {
+        if !Path::new(path).exists() {
+            return Ok(AlertState::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read the state of the alerts {}", path))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Cannot parse the state of the alerts {}", path))
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        if let Some(dir) = Path::new(path)
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Cannot create directory {}", dir.display()))?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Cannot write the state of the alerts {}", path))
+    }
+
+    /// Records the totals and top emitters of a scan, compared to by the next scan
+    pub fn record_scan(&mut self, rules: &[AlertRule], estimated_inventory: &EstimatedInventory) {
+        for rule in rules.iter() {
+            match rule {
+                AlertRule::Threshold { .. } => {}
+                AlertRule::Increase { criterion, .. } => {
+                    self.previous_totals.insert(
+                        criterion.name().to_string(),
+                        total(*criterion, estimated_inventory),
+                    );
+                }
+                AlertRule::NewTopEmitter { criterion, top, .. } => {
+                    self.previous_top_emitters.insert(
+                        top_key(*criterion, *top),
+                        top_emitter_ids(estimated_inventory, *criterion, *top),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Returns the alerts to fire: the alerts not raised by the previous scan, or that last fired more than `renotify_after` ago. Updates the active alerts, the alerts no longer raised being resolved.
+    pub fn deduplicate(
+        &mut self,
+        alerts: Vec<Alert>,
+        now: DateTime<Utc>,
+        renotify_after: chrono::Duration,
+    ) -> Vec<Alert> {
+        let raised: BTreeSet<String> = alerts.iter().map(|alert| alert.key.clone()).collect();
+        self.active.retain(|key, _| {
+            let still_raised = raised.contains(key);
+            if !still_raised {
+                info!("Alert {} resolved", key);
+            }
+            still_raised
+        });
+        alerts
+            .into_iter()
+            .filter(|alert| {
+                let fire = self
+                    .active
+                    .get(&alert.key)
+                    .is_none_or(|last_fired| now - *last_fired >= renotify_after);
+                if fire {
+                    self.active.insert(alert.key.clone(), now);
+                } else {
+                    debug!("Alert {} already fired", alert.key);
+                }
+                fire
+            })
+            .collect()
+    }
+}
+
+/// Returns the total of a criterion for the assessed resources
+fn total(criterion: RankingCriterion, estimated_inventory: &EstimatedInventory) -> f64 {
+    estimated_inventory
+        .impacting_resources
+        .iter()
+        .filter_map(|resource| resource.impacts_values.as_ref())
+        .map(|impacts| criterion.value(impacts))
+        .sum()
+}
+
+fn top_key(criterion: RankingCriterion, top: usize) -> String {
+    format!("{}:{}", criterion, top)
+}
+
+fn top_emitter_ids(
+    estimated_inventory: &EstimatedInventory,
+    criterion: RankingCriterion,
+    top: usize,
+) -> Vec<String> {
+    top_emitters::top_emitters(estimated_inventory, criterion, top)
+        .resources
+        .into_iter()
+        .map(|resource| resource.resource_id)
+        .collect()
+}
+
+/// Returns the alerts raised by the rules for a scan, compared to the baselines (by name) and to the previous scan of the state
+pub fn evaluate_rules(
+    rules: &[AlertRule],
+    estimated_inventory: &EstimatedInventory,
+    baselines: &BTreeMap<String, EstimatedInventory>,
+    state: &AlertState,
+) -> Vec<Alert> {
+    let mut alerts = Vec::new();
+    for rule in rules.iter() {
+        match rule {
+            AlertRule::Threshold {
+                name,
+                criterion,
+                max,
+            } => {
+                let value = total(*criterion, estimated_inventory);
+                if value > *max {
+                    alerts.push(Alert {
+                        rule: name.clone(),
+                        key: name.clone(),
+                        message: format!(
+                            "{}: {} of {:.3} {} exceeds {} {}",
+                            name,
+                            criterion,
+                            value,
+                            criterion.unit(),
+                            max,
+                            criterion.unit()
+                        ),
+                    });
+                }
+            }
+            AlertRule::Increase {
+                name,
+                criterion,
+                max_percent,
+                baseline,
+            } => {
+                let (reference, compared_to) = match baseline {
+                    Some(baseline) => (
+                        baselines
+                            .get(baseline)
+                            .map(|baseline| total(*criterion, baseline)),
+                        format!("baseline {}", baseline),
+                    ),
+                    None => (
+                        state.previous_totals.get(criterion.name()).copied(),
+                        "the previous scan".to_string(),
+                    ),
+                };
+                let Some(reference) = reference.filter(|reference| *reference > 0.0) else {
+                    continue;
+                };
+                let value = total(*criterion, estimated_inventory);
+                let increase_percent = (value - reference) / reference * 100.0;
+                if increase_percent > *max_percent {
+                    alerts.push(Alert {
+                        rule: name.clone(),
+                        key: name.clone(),
+                        message: format!(
+                            "{}: {} increased by {:.1} % compared to {} ({:.3} {} instead of {:.3}), above {} %",
+                            name,
+                            criterion,
+                            increase_percent,
+                            compared_to,
+                            value,
+                            criterion.unit(),
+                            reference,
+                            max_percent
+                        ),
+                    });
+                }
+            }
+            AlertRule::NewTopEmitter {
+                name,
+                criterion,
+                top,
+            } => {
+                // The first scan has nothing to compare to
+                let Some(previous) = state.previous_top_emitters.get(&top_key(*criterion, *top))
+                else {
+                    continue;
+                };
+                let ranking = top_emitters::top_emitters(estimated_inventory, *criterion, *top);
+                for resource in ranking.resources.iter() {
+                    if previous.contains(&resource.resource_id) {
+                        continue;
+                    }
+                    alerts.push(Alert {
+                        rule: name.clone(),
+                        key: format!("{}:{}", name, resource.resource_id),
+                        message: format!(
+                            "{}: {} ({}) entered the top {} emitters by {} at rank {}, with {:.3} {}",
+                            name,
+                            resource.resource_id,
+                            resource.resource_kind,
+                            top,
+                            criterion,
+                            resource.rank,
+                            resource.value,
+                            ranking.unit
+                        ),
+                    });
+                }
+            }
+        }
+    }
+    alerts
+}
+
+/// Returns the text of the alerts sent to the channels
+pub fn alerts_message(alerts: &[Alert]) -> String {
+    let mut message = format!("Cloud impacts alerts ({})", alerts.len());
+    for alert in alerts.iter() {
+        message.push_str(&format!("\n:rotating_light: {}", alert.message));
+    }
+    message
+}
+
+/// The alert rules with the channels they fire to
+#[derive(Clone, Debug)]
+pub struct Alerting {
+    pub config: AlertsConfig,
+    pub notifications: Option<NotificationConfig>,
+    pub email: Option<EmailConfig>,
+}
+
+impl Alerting {
+    /// Checks that the channels of the alerts are configured
+    pub fn new(
+        config: AlertsConfig,
+        notifications: Option<NotificationConfig>,
+        email: Option<EmailConfig>,
+    ) -> Result<Self> {
+        if config.channels.is_empty() {
+            anyhow::bail!("No channel for the alerts (expecting notifications, email or webhook)");
+        }
+        for channel in config.channels.iter() {
+            match channel {
+                AlertChannel::Notifications if notifications.is_none() => {
+                    anyhow::bail!("Alerts fire to notifications without [notifications] section")
+                }
+                AlertChannel::Email if email.is_none() => {
+                    anyhow::bail!("Alerts fire to email without [email] section")
+                }
+                AlertChannel::Webhook if config.webhook_urls.is_empty() => {
+                    anyhow::bail!("Alerts fire to webhook without webhook_urls")
+                }
+                _ => {}
+            }
+        }
+        Ok(Alerting {
+            config,
+            notifications,
+            email,
+        })
+    }
+
+    /// Evaluates the rules for a scan and fires the new alerts to the channels, returns the alerts fired
+    pub async fn evaluate(&self, estimated_inventory: &EstimatedInventory) -> Result<Vec<Alert>> {
+        let mut baselines = BTreeMap::new();
+        let store = BaselineStore::new(&self.config.baselines_dir);
+        for rule in self.config.rules.iter() {
+            if let AlertRule::Increase {
+                baseline: Some(baseline),
+                ..
+            } = rule
+            {
+                if !baselines.contains_key(baseline) {
+                    baselines.insert(baseline.clone(), store.load(baseline)?.estimated_inventory);
+                }
+            }
+        }
+        let mut state = AlertState::load(&self.config.state_file)?;
+        let alerts = evaluate_rules(&self.config.rules, estimated_inventory, &baselines, &state);
+        let renotify_after =
+            chrono::Duration::seconds((self.config.renotify_after_hours * 3600.0) as i64);
+        let alerts = state.deduplicate(alerts, Utc::now(), renotify_after);
+        state.record_scan(&self.config.rules, estimated_inventory);
+        if !alerts.is_empty() {
+            self.fire(&alerts).await?;
+        }
+        // Saved once fired, so that alerts that could not be sent fire again at the next scan
+        state.save(&self.config.state_file)?;
+        Ok(alerts)
+    }
+
+    /// Sends alerts to the channels
+    async fn fire(&self, alerts: &[Alert]) -> Result<()> {
+        let message = alerts_message(alerts);
+        let client = reqwest::Client::new();
+        for channel in self.config.channels.iter() {
+            match channel {
+                AlertChannel::Notifications => {
+                    for webhook in self
+                        .notifications
+                        .iter()
+                        .flat_map(|notifications| notifications.webhooks.iter())
+                    {
+                        client
+                            .post(&webhook.url)
+                            .json(&notifier::webhook_payload(webhook.kind, &message, true))
+                            .send()
+                            .await
+                            .and_then(|response| response.error_for_status())
+                            .with_context(|| {
+                                format!("Cannot post alerts to {:?} webhook", webhook.kind)
+                            })?;
+                    }
+                }
+                AlertChannel::Email => {
+                    if let Some(email) = &self.email {
+                        let subject = message.lines().next().unwrap_or_default();
+                        crate::email_sender::send_text(email, subject, &message).await?;
+                    }
+                }
+                AlertChannel::Webhook => {
+                    for url in self.config.webhook_urls.iter() {
+                        client
+                            .post(url)
+                            .json(&json!({ "alerts": alerts }))
+                            .send()
+                            .await
+                            .and_then(|response| response.error_for_status())
+                            .with_context(|| format!("Cannot post alerts to {}", url))?;
+                    }
+                }
+            }
+        }
+        info!("{} alerts fired", alerts.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+    use crate::model::{CloudProvider, CloudResource, ResourceDetails};
+    use crate::usage_location::UsageLocation;
+
+    fn inventory(buckets: &[(&str, f64)]) -> EstimatedInventory {
+        EstimatedInventory {
+            impacting_resources: buckets
+                .iter()
+                .map(|(id, gwp_use_kgco2eq)| CloudResourceWithImpacts {
+                    cloud_resource: CloudResource {
+                        provider: CloudProvider::AWS,
+                        id: id.to_string(),
+                        location: UsageLocation::try_from("eu-west-3").unwrap(),
+                        resource_details: ResourceDetails::ObjectStorage,
+                        tags: Vec::new(),
+                        account_id: None,
+                        relationships: Vec::new(),
+                    },
+                    impacts_values: Some(ImpactsValues {
+                        gwp_use_kgco2eq: *gwp_use_kgco2eq,
+                        ..Default::default()
+                    }),
+                    impacts_duration_hours: 1.0,
+                    cost: None,
+                })
+                .collect(),
+            execution_statistics: None,
+            errors: Vec::new(),
+        }
+    }
+
+    fn rules() -> Vec<AlertRule> {
+        let config: AlertsConfig = toml::from_str(
+            r#"
+channels = ["webhook"]
+webhook_urls = ["https://alerts.example.com"]
+
+[[rules]]
+kind = "threshold"
+name = "gwp-above-10"
+max = 10.0
+
+[[rules]]
+kind = "increase"
+name = "gwp-up"
+max_percent = 20.0
+
+[[rules]]
+kind = "new_top_emitter"
+name = "new-top"
+top = 2
+"#,
+        )
+        .unwrap();
+        assert_eq!(24.0, config.renotify_after_hours);
+        assert_eq!(DEFAULT_STATE_FILE, config.state_file);
+        assert!(
+            toml::from_str::<AlertRule>("kind = \"threshold\"\nname = \"a\"\nmax_gwp = 1.0")
+                .is_err()
+        );
+        config.rules
+    }
+
+    #[test]
+    fn rules_are_evaluated_against_the_previous_scan() {
+        let rules = rules();
+        let mut state = AlertState::default();
+        let first = inventory(&[("a", 4.0), ("b", 3.0), ("c", 1.0)]);
+        // Nothing to compare to at the first scan
+        assert!(evaluate_rules(&rules, &first, &BTreeMap::new(), &state).is_empty());
+        state.record_scan(&rules, &first);
+
+        let second = inventory(&[("a", 4.0), ("b", 3.0), ("c", 5.0)]);
+        let alerts = evaluate_rules(&rules, &second, &BTreeMap::new(), &state);
+        let keys: Vec<&str> = alerts.iter().map(|alert| alert.key.as_str()).collect();
+        assert_eq!(vec!["gwp-above-10", "gwp-up", "new-top:c"], keys);
+        assert!(alerts[1].message.contains("increased by 50.0 %"));
+    }
+
+    #[test]
+    fn increases_are_compared_to_baselines() {
+        let rules = vec![AlertRule::Increase {
+            name: "gwp-up".to_string(),
+            criterion: RankingCriterion::Gwp,
+            max_percent: 10.0,
+            baseline: Some("2024-q1".to_string()),
+        }];
+        let baselines = BTreeMap::from([("2024-q1".to_string(), inventory(&[("a", 10.0)]))]);
+        let state = AlertState::default();
+        assert!(evaluate_rules(&rules, &inventory(&[("a", 10.5)]), &baselines, &state).is_empty());
+        let alerts = evaluate_rules(&rules, &inventory(&[("a", 12.0)]), &baselines, &state);
+        assert!(alerts[0].message.contains("compared to baseline 2024-q1"));
+    }
+
+    #[test]
+    fn active_alerts_fire_again_after_the_renotify_delay() {
+        let alert = |key: &str| Alert {
+            rule: key.to_string(),
+            key: key.to_string(),
+            message: key.to_string(),
+        };
+        let now: DateTime<Utc> = "2024-05-01T10:00:00Z".parse().unwrap();
+        let renotify_after = chrono::Duration::hours(24);
+        let mut state = AlertState::default();
+
+        assert_eq!(
+            1,
+            state
+                .deduplicate(vec![alert("a")], now, renotify_after)
+                .len()
+        );
+        let later = now + chrono::Duration::hours(1);
+        assert!(state
+            .deduplicate(vec![alert("a")], later, renotify_after)
+            .is_empty());
+        let next_day = now + chrono::Duration::hours(25);
+        assert_eq!(
+            1,
+            state
+                .deduplicate(vec![alert("a")], next_day, renotify_after)
+                .len()
+        );
+        // Resolved, then raised again
+        assert!(state
+            .deduplicate(vec![], next_day, renotify_after)
+            .is_empty());
+        assert_eq!(
+            1,
+            state
+                .deduplicate(vec![alert("a")], next_day, renotify_after)
+                .len()
+        );
+    }
+
+    #[test]
+    fn channels_must_be_configured() {
+        let config = AlertsConfig {
+            rules: rules(),
+            channels: vec![AlertChannel::Email],
+            webhook_urls: Vec::new(),
+            renotify_after_hours: 24.0,
+            state_file: DEFAULT_STATE_FILE.to_string(),
+            baselines_dir: crate::baselines::DEFAULT_DIR.to_string(),
+        };
+        assert!(Alerting::new(config.clone(), None, None).is_err());
+        let config = AlertsConfig {
+            channels: Vec::new(),
+            ..config
+        };
+        assert!(Alerting::new(config, None, None).is_err());
+    }
+}
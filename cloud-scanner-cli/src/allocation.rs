@@ -0,0 +1,175 @@
+//! Allocation rules (`[[allocations]]` of the configuration file) splitting the impacts of shared resources across several groups by percentage, like a cluster shared 60/40 by two teams, when impacts are grouped by the values of a tag (`estimate --group-by`, `aggregate --group-by-tag` and budgets per value of a tag).
+//!
+//! ```toml
+//! # The nodes of the shared cluster are used at 60% by the platform team and 40% by the data team
+//! [[allocations]]
+//! resources = "cluster~shared-*"
+//! tag = "team"
+//! shares = { platform = 60, data = 40 }
+//! ```
+//!
+//! `resources` is a tag filter expression (see [crate::tag_filter]). When grouping by the `tag` of a rule, the impacts of the resources matching it are allocated to the values of `shares` (in percent, summing to 100) instead of their own value of the tag. Resources matching several rules of the tag are allocated by the first one.
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::model::CloudResource;
+use crate::tag_filter::TagFilter;
+
+/// An allocation rule of the configuration file
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AllocationRuleConfig {
+    /// Tag filter expression of the resources of the rule, like `cluster~shared-*`
+    pub resources: String,
+    /// Key of the tag of the groups the impacts are allocated to, like `team`
+    pub tag: String,
+    /// Percentages of the impacts allocated to values of the tag, summing to 100
+    pub shares: BTreeMap<String, f64>,
+}
+
+/// A checked allocation rule
+#[derive(Clone, Debug, PartialEq)]
+struct AllocationRule {
+    resources: TagFilter,
+    tag: String,
+    /// Shares of the values of the tag, between 0 and 1
+    shares: Vec<(String, f64)>,
+}
+
+/// The allocation rules (none keeps the impacts of each resource in the group of its value of the tag)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AllocationRules {
+    rules: Vec<AllocationRule>,
+}
+
+impl AllocationRules {
+    /// Checks the rules of the configuration file: valid filters and shares summing to 100%
+    pub fn from_config(rules: &[AllocationRuleConfig]) -> Result<Self> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let resources = TagFilter::parse(&rule.resources)
+                    .context("Invalid resources of an allocation rule")?;
+                if rule.shares.values().any(|share| *share <= 0.0) {
+                    anyhow::bail!(
+                        "Shares of the allocation rule of {} must be positive",
+                        rule.resources
+                    );
+                }
+                let total: f64 = rule.shares.values().sum();
+                if (total - 100.0).abs() > 0.01 {
+                    anyhow::bail!(
+                        "Shares of the allocation rule of {} sum to {}% instead of 100%",
+                        rule.resources,
+                        total
+                    );
+                }
+                Ok(AllocationRule {
+                    resources,
+                    tag: rule.tag.clone(),
+                    shares: rule
+                        .shares
+                        .iter()
+                        .map(|(value, share)| (value.clone(), share / 100.0))
+                        .collect(),
+                })
+            })
+            .collect::<Result<Vec<AllocationRule>>>()?;
+        Ok(AllocationRules { rules })
+    }
+
+    /// Returns the values of the tag `tag_key` the impacts of a resource are allocated to, with their share (between 0 and 1): the shares of the first rule of the tag matching the resource, or its own value of the tag (none when it does not have the tag)
+    pub fn shares(&self, resource: &CloudResource, tag_key: &str) -> Vec<(Option<String>, f64)> {
+        match self
+            .rules
+            .iter()
+            .find(|rule| rule.tag == tag_key && rule.resources.matches(&resource.tags))
+        {
+            Some(rule) => rule
+                .shares
+                .iter()
+                .map(|(value, share)| (Some(value.clone()), *share))
+                .collect(),
+            None => {
+                let value = resource
+                    .tags
+                    .iter()
+                    .find(|tag| tag.key == tag_key)
+                    .and_then(|tag| tag.value.clone());
+                vec![(value, 1.0)]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{CloudProvider, CloudResourceTag, ResourceDetails};
+    use crate::usage_location::UsageLocation;
+
+    fn resource(tags: &[(&str, &str)]) -> CloudResource {
+        CloudResource {
+            provider: CloudProvider::AWS,
+            id: "i-1".to_string(),
+            location: UsageLocation::try_from("eu-west-3").unwrap(),
+            resource_details: ResourceDetails::Instance {
+                instance_type: "t3.micro".to_string(),
+                usage: None,
+            },
+            tags: tags
+                .iter()
+                .map(|(key, value)| CloudResourceTag {
+                    key: key.to_string(),
+                    value: Some(value.to_string()),
+                })
+                .collect(),
+            account_id: None,
+            relationships: Vec::new(),
+        }
+    }
+
+    fn rule(shares: &[(&str, f64)]) -> AllocationRuleConfig {
+        AllocationRuleConfig {
+            resources: "cluster~shared-*".to_string(),
+            tag: "team".to_string(),
+            shares: shares
+                .iter()
+                .map(|(value, share)| (value.to_string(), *share))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn shared_resources_are_allocated_by_percentage() {
+        let rules =
+            AllocationRules::from_config(&[rule(&[("platform", 60.0), ("data", 40.0)])]).unwrap();
+        let shared = resource(&[("cluster", "shared-eu"), ("team", "platform")]);
+        assert_eq!(
+            vec![
+                (Some("data".to_string()), 0.4),
+                (Some("platform".to_string()), 0.6)
+            ],
+            rules.shares(&shared, "team")
+        );
+        // Rules only apply when grouping by their tag
+        assert_eq!(
+            vec![(Some("shared-eu".to_string()), 1.0)],
+            rules.shares(&shared, "cluster")
+        );
+        assert_eq!(
+            vec![(Some("web".to_string()), 1.0)],
+            rules.shares(&resource(&[("team", "web")]), "team")
+        );
+        assert_eq!(vec![(None, 1.0)], rules.shares(&resource(&[]), "team"));
+    }
+
+    #[test]
+    fn shares_must_sum_to_100_percent() {
+        assert!(AllocationRules::from_config(&[rule(&[("platform", 60.0)])]).is_err());
+        assert!(
+            AllocationRules::from_config(&[rule(&[("platform", 110.0), ("data", -10.0)])]).is_err()
+        );
+    }
+}
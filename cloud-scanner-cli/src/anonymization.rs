@@ -0,0 +1,194 @@
+//! Anonymization of results (`--anonymize`), so that they can be shared publicly or with consultants: the ids of resources, their names (`Name` tag) and the ids of accounts are replaced by salted hashes, while their kinds, types, regions, other tags and impacts are kept.
+//!
+//! Hashes are HMAC-SHA256 of the values keyed by the salt (truncated to 16 hex characters), keeping the prefix of AWS ids (like `i-` or `vol-`). The same value always gets the same hash with the same salt, so that relationships between resources, roll-ups and comparisons of scans anonymized with the same salt still work. Without a salt, a random salt is used: the hashes differ between runs.
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::impact_provider::ImpactsSummary;
+use crate::model::{
+    CloudResource, EstimatedInventory, Inventory, ResourceDetails, ResourceRelationship,
+};
+
+/// Length of the prefixes of ids kept by hashes (like `vol-`)
+const MAX_ID_PREFIX_LENGTH: usize = 4;
+
+/// Replaces identifying values of results by salted hashes
+#[derive(Clone, Debug)]
+pub struct Anonymizer {
+    salt: String,
+}
+
+impl Anonymizer {
+    /// Returns an anonymizer with this salt, or with a random salt (hashes are then different on each run)
+    pub fn new(salt: Option<&str>) -> Self {
+        let salt = match salt {
+            Some(salt) => salt.to_string(),
+            None => {
+                warn!("Anonymizing with a random salt, pass a salt for hashes that are stable between runs");
+                uuid::Uuid::new_v4().to_string()
+            }
+        };
+        Anonymizer { salt }
+    }
+
+    /// Returns the salted hash of a value, keeping the prefix of AWS ids (like `i-0123456789abcdef0`)
+    pub fn hash(&self, value: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.salt.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(value.as_bytes());
+        let digest = hex::encode(mac.finalize().into_bytes());
+        let prefix = value
+            .split_once('-')
+            .map(|(prefix, _)| prefix)
+            .filter(|prefix| {
+                !prefix.is_empty()
+                    && prefix.len() <= MAX_ID_PREFIX_LENGTH
+                    && prefix.chars().all(|c| c.is_ascii_lowercase())
+            });
+        match prefix {
+            Some(prefix) => format!("{}-{}", prefix, &digest[..16]),
+            None => digest[..16].to_string(),
+        }
+    }
+
+    /// Returns the resource with its id, account, name and the ids of its parents hashed
+    pub fn anonymize_resource(&self, mut resource: CloudResource) -> CloudResource {
+        resource.id = self.hash(&resource.id);
+        resource.account_id = resource.account_id.map(|id| self.hash(&id));
+        for tag in resource.tags.iter_mut() {
+            if tag.key.eq_ignore_ascii_case("name") {
+                tag.value = tag.value.as_deref().map(|name| self.hash(name));
+            }
+        }
+        if let ResourceDetails::BlockStorage {
+            attached_instances: Some(attached_instances),
+            ..
+        } = &mut resource.resource_details
+        {
+            for attachment in attached_instances.iter_mut() {
+                attachment.instance_id = self.hash(&attachment.instance_id);
+            }
+        }
+        for relationship in resource.relationships.iter_mut() {
+            match relationship {
+                ResourceRelationship::AttachedTo { instance_id } => {
+                    *instance_id = self.hash(instance_id)
+                }
+                ResourceRelationship::MemberOfAutoScalingGroup { name }
+                | ResourceRelationship::MemberOfCluster { name } => *name = self.hash(name),
+            }
+        }
+        resource
+    }
+
+    pub fn anonymize_inventory(&self, mut inventory: Inventory) -> Inventory {
+        inventory.resources = inventory
+            .resources
+            .into_iter()
+            .map(|resource| self.anonymize_resource(resource))
+            .collect();
+        inventory
+    }
+
+    /// Returns the inventory with its resources anonymized, and the resource and account ids of its errors hashed (in their messages too)
+    pub fn anonymize_estimated_inventory(
+        &self,
+        mut estimated_inventory: EstimatedInventory,
+    ) -> EstimatedInventory {
+        for resource in estimated_inventory.impacting_resources.iter_mut() {
+            resource.cloud_resource = self.anonymize_resource(resource.cloud_resource.clone());
+        }
+        for error in estimated_inventory.errors.iter_mut() {
+            for id in [error.resource_id.as_mut(), error.account_id.as_mut()]
+                .into_iter()
+                .flatten()
+            {
+                let hash = self.hash(id);
+                error.message = error.message.replace(id.as_str(), &hash);
+                *id = hash;
+            }
+        }
+        estimated_inventory
+    }
+
+    /// Returns the summary with the accounts of its regions, the names of its groups (by `Name` tag) and the ids of its parents hashed
+    pub fn anonymize_summary(&self, mut summary: ImpactsSummary) -> ImpactsSummary {
+        for region in summary.regions.iter_mut() {
+            region.account_id = region.account_id.as_deref().map(|id| self.hash(id));
+        }
+        for group in summary.groups.iter_mut() {
+            if group.tag_key.eq_ignore_ascii_case("name") {
+                group.tag_value = group.tag_value.as_deref().map(|name| self.hash(name));
+            }
+        }
+        for parent in summary.parents.iter_mut() {
+            parent.parent_id = self.hash(&parent.parent_id);
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{CloudProvider, CloudResourceTag, StorageAttachment};
+    use crate::usage_location::UsageLocation;
+
+    #[test]
+    fn identifying_values_are_hashed() {
+        let anonymizer = Anonymizer::new(Some("salt"));
+        let hash = anonymizer.hash("i-0123456789abcdef0");
+        assert!(hash.starts_with("i-"));
+        assert_eq!(18, hash.len());
+        assert_eq!(hash, anonymizer.hash("i-0123456789abcdef0"));
+        assert_ne!(
+            hash,
+            Anonymizer::new(Some("other")).hash("i-0123456789abcdef0")
+        );
+        assert_eq!(16, anonymizer.hash("111111111111").len());
+
+        let volume = CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: Some("111111111111".to_string()),
+            id: "vol-0123456789abcdef0".to_string(),
+            location: UsageLocation::try_from("eu-west-3").unwrap(),
+            resource_details: ResourceDetails::BlockStorage {
+                storage_type: "gp3".to_string(),
+                usage: None,
+                attached_instances: Some(vec![StorageAttachment {
+                    instance_id: "i-0123456789abcdef0".to_string(),
+                }]),
+            },
+            tags: vec![
+                CloudResourceTag {
+                    key: "Name".to_string(),
+                    value: Some("billing-db".to_string()),
+                },
+                CloudResourceTag {
+                    key: "team".to_string(),
+                    value: Some("web".to_string()),
+                },
+            ],
+            relationships: vec![ResourceRelationship::AttachedTo {
+                instance_id: "i-0123456789abcdef0".to_string(),
+            }],
+        };
+        let anonymized = anonymizer.anonymize_resource(volume);
+        assert!(anonymized.id.starts_with("vol-"));
+        assert_eq!(Some(anonymizer.hash("111111111111")), anonymized.account_id);
+        assert_eq!(
+            Some(anonymizer.hash("billing-db")),
+            anonymized.tags[0].value
+        );
+        assert_eq!(Some("web".to_string()), anonymized.tags[1].value);
+        assert_eq!(
+            vec![ResourceRelationship::AttachedTo { instance_id: hash }],
+            anonymized.relationships
+        );
+        assert_eq!("eu-west-3", anonymized.location.aws_region);
+        assert_eq!(
+            Some("gp3".to_string()),
+            anonymized.resource_details.resource_type()
+        );
+    }
+}
@@ -0,0 +1,430 @@
+//! Inventories of Ansible, so that the fleets of virtual machines and bare metal servers managed with Ansible are estimated as servers ([ResourceDetails::Server]), with their hardware when their facts have been gathered.
+//!
+//! Inventories are the json (or yaml) output of `ansible-inventory --list` (any inventory source, like ini files or dynamic inventories), or yaml inventories (`all` with its `hosts`, `vars` and `children`):
+//!
+//! ```sh
+//! ansible-inventory -i hosts.ini --list > inventory.json
+//! ansible all -i hosts.ini -m ansible.builtin.setup --tree facts/
+//! ```
+//!
+//! The facts of hosts are read from a directory of a file per host (named after the host, with or without `.json`), like the files of `--tree` or of the `jsonfile` fact cache: the hardware of a host is its `ansible_processor_count` CPUs of `ansible_processor_cores` cores, its `ansible_memtotal_mb` and the size of its disks (`ansible_devices`, without loop, ram and optical devices), and its model is its `ansible_product_name`. Hosts without facts are estimated with the hardware of their variables `cpu_units`, `cpu_cores`, `memory_gb` and `storage_gb`, or the default server of Boavizta API.
+//!
+//! Resources are identified by their inventory hostname and tagged with their groups (keys without values). Their region is their `site` variable, in the country of their `country` variable (ISO 3166 code) or of the default country. CPU loads are not part of the inventories: hosts are estimated with the default load of Boavizta API.
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+use std::path::Path;
+
+use crate::aws_cloud_provider::ResourceSelection;
+use crate::model::{
+    json_integer, json_number, CloudProvider, CloudResource, CloudResourceTag, Inventory,
+    ResourceDetails, ServerHardware,
+};
+use crate::tag_filter::TagFilter;
+use crate::tag_normalization;
+use crate::usage_location::UsageLocation;
+
+/// Groups of every host, that are not tags of the resources
+const IMPLICIT_GROUPS: [&str; 2] = ["all", "ungrouped"];
+
+/// Region of the hosts without `site` variable
+const UNKNOWN_SITE: &str = "unknown";
+
+/// Model of the hosts without facts
+const UNKNOWN_MODEL: &str = "unknown";
+
+/// Prefixes of the names of the devices that are not disks (loop devices, ram disks, optical drives and device mapper volumes)
+const NON_DISK_DEVICES: [&str; 5] = ["loop", "ram", "sr", "dm-", "zram"];
+
+/// A host of an inventory, with the groups it belongs to (and their parents) and its variables
+#[derive(Clone, Debug, Default, PartialEq)]
+struct AnsibleHost {
+    name: String,
+    groups: Vec<String>,
+    vars: Map<String, Value>,
+}
+
+impl AnsibleHost {
+    /// Adds the groups and variables of another appearance of the host in the inventory, its new variables overriding the previous ones
+    fn merge(&mut self, groups: &[String], vars: &Map<String, Value>) {
+        for group in groups {
+            if !self.groups.contains(group) {
+                self.groups.push(group.clone());
+            }
+        }
+        self.vars
+            .extend(vars.iter().map(|(key, value)| (key.clone(), value.clone())));
+    }
+}
+
+/// Returns the hosts of an inventory, in the order of their first appearance
+fn inventory_hosts(inventory: &Value) -> Vec<AnsibleHost> {
+    let mut hosts = Vec::new();
+    // The output of ansible-inventory --list lists the hosts of groups, and their variables in _meta
+    if inventory.get("_meta").is_some() || inventory["all"]["children"].is_array() {
+        list_group(inventory, "all", &mut Vec::new(), &mut hosts);
+    } else {
+        yaml_group(
+            "all",
+            &inventory["all"],
+            &mut Vec::new(),
+            &Map::new(),
+            &mut hosts,
+        );
+    }
+    hosts
+}
+
+/// Returns the host of this name, added to the hosts if it is not yet one of them
+fn host<'a>(hosts: &'a mut Vec<AnsibleHost>, name: &str) -> &'a mut AnsibleHost {
+    let position = match hosts.iter().position(|host| host.name == name) {
+        Some(position) => position,
+        None => {
+            hosts.push(AnsibleHost {
+                name: name.to_string(),
+                ..Default::default()
+            });
+            hosts.len() - 1
+        }
+    };
+    &mut hosts[position]
+}
+
+/// Adds the hosts of a group of the output of ansible-inventory --list (and of its children), whose variables are merged in `_meta.hostvars`
+fn list_group(
+    inventory: &Value,
+    name: &str,
+    groups: &mut Vec<String>,
+    hosts: &mut Vec<AnsibleHost>,
+) {
+    // Groups cannot be nested in cycles, the guard only protects from malformed inventories
+    if groups.iter().any(|group| group == name) {
+        return;
+    }
+    groups.push(name.to_string());
+    let group = &inventory[name];
+    let no_vars = Map::new();
+    for host_name in group["hosts"].as_array().into_iter().flatten() {
+        let Some(host_name) = host_name.as_str() else {
+            continue;
+        };
+        let vars = inventory["_meta"]["hostvars"][host_name]
+            .as_object()
+            .unwrap_or(&no_vars);
+        host(hosts, host_name).merge(groups, vars);
+    }
+    for child in group["children"].as_array().into_iter().flatten() {
+        if let Some(child) = child.as_str() {
+            list_group(inventory, child, groups, hosts);
+        }
+    }
+    groups.pop();
+}
+
+/// Adds the hosts of a group of a yaml inventory (and of its children), with the variables of the group and of its parents
+fn yaml_group(
+    name: &str,
+    group: &Value,
+    groups: &mut Vec<String>,
+    parent_vars: &Map<String, Value>,
+    hosts: &mut Vec<AnsibleHost>,
+) {
+    if groups.iter().any(|group| group == name) {
+        return;
+    }
+    groups.push(name.to_string());
+    let mut vars = parent_vars.clone();
+    if let Some(group_vars) = group["vars"].as_object() {
+        vars.extend(group_vars.clone());
+    }
+    for (host_name, host_vars) in group["hosts"].as_object().into_iter().flatten() {
+        let mut vars = vars.clone();
+        if let Some(host_vars) = host_vars.as_object() {
+            vars.extend(host_vars.clone());
+        }
+        host(hosts, host_name).merge(groups, &vars);
+    }
+    for (child, child_group) in group["children"].as_object().into_iter().flatten() {
+        yaml_group(child, child_group, groups, &vars, hosts);
+    }
+    groups.pop();
+}
+
+/// Returns a fact of a host, named with or without its `ansible_` prefix (like in fact caches)
+fn fact<'a>(facts: &'a Value, name: &str) -> &'a Value {
+    let prefixed = &facts[format!("ansible_{}", name)];
+    match prefixed.is_null() {
+        true => &facts[name],
+        false => prefixed,
+    }
+}
+
+/// Returns the hardware of the facts of a host
+fn facts_hardware(facts: &Value) -> ServerHardware {
+    let storage_bytes: f64 = fact(facts, "devices")
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(name, device)| {
+            !NON_DISK_DEVICES
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+                && device["removable"].as_str() != Some("1")
+        })
+        .filter_map(|(_, device)| {
+            Some(json_number(&device["sectors"])? * json_number(&device["sectorsize"])?)
+        })
+        .sum();
+    let gib = (1024 * 1024 * 1024) as f64;
+    ServerHardware {
+        cpu_units: json_integer(fact(facts, "processor_count")),
+        cpu_cores: json_integer(fact(facts, "processor_cores")),
+        memory_gb: json_integer(fact(facts, "memtotal_mb")).map(|mb| (mb + 1023) / 1024),
+        storage_gb: (storage_bytes > 0.0).then(|| (storage_bytes / gib).ceil() as i32),
+    }
+}
+
+/// Reads the facts of a host in a directory of facts (in its file, named after the host with or without `.json`), None when it has no facts
+fn read_facts(directory: &Path, host_name: &str) -> Result<Option<Value>> {
+    let candidates = [
+        directory.join(host_name),
+        directory.join(format!("{}.json", host_name)),
+    ];
+    let Some(path) = candidates.iter().find(|path| path.is_file()) else {
+        return Ok(None);
+    };
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read the facts of {}", host_name))?;
+    let facts: Value = serde_json::from_str(&content)
+        .with_context(|| format!("Cannot parse the facts of {}", host_name))?;
+    // The files of --tree are the results of the setup module
+    Ok(Some(match facts.get("ansible_facts") {
+        Some(facts) => facts.clone(),
+        None => facts,
+    }))
+}
+
+/// Parses the hosts of an inventory (the output of ansible-inventory --list or a yaml inventory), with the facts of `facts` (by host name) when known
+pub fn parse_ansible_inventory(
+    content: &str,
+    facts: impl Fn(&str) -> Result<Option<Value>>,
+    default_country: Option<&str>,
+    tags: &[String],
+    selection: &ResourceSelection,
+) -> Result<Inventory> {
+    let inventory: Value =
+        serde_yaml::from_str(content).context("Expected an inventory of Ansible")?;
+    if !inventory.is_object() {
+        anyhow::bail!("Expected an inventory of Ansible (an object of groups)");
+    }
+    let mut resources = Vec::new();
+    // Hosts have no state, they are running
+    let selects_running = selection.instance_states.is_empty()
+        || selection.instance_states.iter().any(|s| s == "running");
+    if selection.skip_instances || !selects_running {
+        return Ok(Inventory {
+            resources,
+            execution_statistics: None,
+        });
+    }
+    let filter = TagFilter::parse_all(tags)?;
+    for host in inventory_hosts(&inventory) {
+        let vars = Value::Object(host.vars);
+        let facts = facts(&host.name)?;
+        let vars_hardware = ServerHardware::from_fields(&vars).unwrap_or_default();
+        let (model, hardware) = match &facts {
+            Some(facts) => (
+                fact(facts, "product_name")
+                    .as_str()
+                    .unwrap_or(UNKNOWN_MODEL),
+                facts_hardware(facts).or(vars_hardware),
+            ),
+            None => (UNKNOWN_MODEL, vars_hardware),
+        };
+        let country = vars["country"].as_str().or(default_country);
+        let location = country
+            .map(|country| {
+                UsageLocation::of_country(vars["site"].as_str().unwrap_or(UNKNOWN_SITE), country)
+            })
+            .transpose()
+            .with_context(|| format!("Invalid country of {}", host.name))?
+            .with_context(|| {
+                format!(
+                    "Unknown country of {} (set its country variable, or a default country)",
+                    host.name
+                )
+            })?;
+        let host_tags = host
+            .groups
+            .iter()
+            .filter(|group| !IMPLICIT_GROUPS.contains(&group.as_str()))
+            .map(|group| CloudResourceTag {
+                key: group.clone(),
+                value: None,
+            })
+            .collect();
+        let resource = CloudResource {
+            provider: CloudProvider::OnPremises,
+            account_id: None,
+            id: host.name,
+            location,
+            resource_details: ResourceDetails::Server {
+                model: model.to_string(),
+                hardware: (hardware != ServerHardware::default()).then_some(hardware),
+                usage: None,
+            },
+            tags: tag_normalization::normalize(host_tags),
+            relationships: Vec::new(),
+        };
+        if filter.matches(&resource.tags) {
+            resources.push(resource);
+        } else {
+            debug!("Filtered {} (tags do not match)", resource.id);
+        }
+    }
+    Ok(Inventory {
+        resources,
+        execution_statistics: None,
+    })
+}
+
+/// Reads an inventory of Ansible, with the facts of its hosts of a directory of facts, see [parse_ansible_inventory]
+pub fn read_ansible_inventory(
+    path: &str,
+    facts_directory: Option<&str>,
+    default_country: Option<&str>,
+    tags: &[String],
+    selection: &ResourceSelection,
+) -> Result<Inventory> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read Ansible inventory {}", path))?;
+    let facts = |host_name: &str| match facts_directory {
+        Some(directory) => read_facts(Path::new(directory), host_name),
+        None => Ok(None),
+    };
+    parse_ansible_inventory(&content, facts, default_country, tags, selection)
+        .with_context(|| format!("Cannot parse Ansible inventory {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LISTED: &str = r#"{
+        "_meta": {"hostvars": {
+            "db-1": {"country": "FR", "site": "paris-1"},
+            "web-1": {"cpu_units": 1, "cpu_cores": 4, "memory_gb": 16}
+        }},
+        "all": {"children": ["ungrouped", "production"]},
+        "production": {"children": ["databases", "webservers"]},
+        "databases": {"hosts": ["db-1"]},
+        "webservers": {"hosts": ["web-1"]}
+    }"#;
+
+    const FACTS: &str = r#"{"ansible_facts": {
+        "ansible_product_name": "PowerEdge R640", "ansible_processor_count": 2, "ansible_processor_cores": 16,
+        "ansible_memtotal_mb": 257000,
+        "ansible_devices": {
+            "sda": {"removable": "0", "sectors": "1875385008", "sectorsize": "512"},
+            "loop0": {"removable": "0", "sectors": "8", "sectorsize": "512"},
+            "sr0": {"removable": "1", "sectors": "2097151", "sectorsize": "512"}
+        }
+    }, "changed": false}"#;
+
+    #[test]
+    fn hosts_are_inventoried_with_their_facts() {
+        let facts = |host_name: &str| -> Result<Option<Value>> {
+            Ok((host_name == "db-1")
+                .then(|| serde_json::from_str::<Value>(FACTS).unwrap()["ansible_facts"].clone()))
+        };
+        let all = ResourceSelection::default();
+        let inventory = parse_ansible_inventory(LISTED, facts, Some("DEU"), &[], &all).unwrap();
+        assert_eq!(2, inventory.resources.len());
+
+        let db = &inventory.resources[0];
+        assert_eq!("db-1", db.id);
+        assert_eq!("paris-1", db.location.aws_region);
+        assert_eq!("FRA", db.location.iso_country_code);
+        assert_eq!(
+            vec!["production", "databases"],
+            db.tags.iter().map(|t| t.key.as_str()).collect::<Vec<_>>()
+        );
+        let ResourceDetails::Server {
+            model, hardware, ..
+        } = &db.resource_details
+        else {
+            panic!("Expected a server");
+        };
+        assert_eq!("PowerEdge R640", model);
+        assert_eq!(
+            &Some(ServerHardware {
+                cpu_units: Some(2),
+                cpu_cores: Some(16),
+                memory_gb: Some(251),
+                storage_gb: Some(895),
+            }),
+            hardware
+        );
+
+        let web = &inventory.resources[1];
+        assert_eq!("DEU", web.location.iso_country_code);
+        assert_eq!(
+            Some("unknown".to_string()),
+            web.resource_details.resource_type()
+        );
+
+        let webservers = parse_ansible_inventory(
+            LISTED,
+            |_: &str| Ok(None),
+            Some("DEU"),
+            &["webservers".to_string()],
+            &all,
+        )
+        .unwrap();
+        assert_eq!(1, webservers.resources.len());
+        assert!(parse_ansible_inventory(LISTED, |_: &str| Ok(None), None, &[], &all).is_err());
+    }
+
+    #[test]
+    fn yaml_inventories_inherit_the_variables_of_their_groups() {
+        let yaml = r#"
+all:
+  vars:
+    country: BE
+  hosts:
+    bastion:
+  children:
+    production:
+      vars:
+        site: brussels
+      children:
+        webservers:
+          hosts:
+            web-1:
+            web-2:
+              site: antwerp
+"#;
+        let inventory = parse_ansible_inventory(
+            yaml,
+            |_: &str| Ok(None),
+            None,
+            &[],
+            &ResourceSelection::default(),
+        )
+        .unwrap();
+        let regions: Vec<(&str, &str)> = inventory
+            .resources
+            .iter()
+            .map(|r| (r.id.as_str(), r.location.aws_region.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("bastion", "unknown"),
+                ("web-1", "brussels"),
+                ("web-2", "antwerp")
+            ],
+            regions
+        );
+        assert_eq!("BEL", inventory.resources[2].location.iso_country_code);
+        assert!(inventory.resources[0].tags.is_empty());
+    }
+}
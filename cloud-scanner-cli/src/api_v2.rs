@@ -0,0 +1,578 @@
+//! Version 2 of the API of the standalone server, mounted under `/v2` with its own OpenAPI document (`/v2/openapi.json`).
+//!
+//! Scan endpoints take every parameter of the scan (provider, regions, duration of use, tag filters, impact criteria) as query parameters (GET) or as a json body (POST), and return the results of each region, with impacts by criterion.
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::{get, post, FromForm, FromFormField, State};
+use rocket_okapi::openapi;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::access_log::RequestId;
+use crate::aws_cloud_provider::AwsAccount;
+use crate::impact_provider::{CloudResourceWithImpacts, ImpactsSummary, ImpactsValues};
+use crate::model::{CloudResource, EstimatedInventory, Inventory};
+use crate::rate_limit::RateLimited;
+use crate::response_cache::{cache_key, ResponseCache};
+use crate::server_auth::Authenticated;
+use crate::standalone_server::{
+    selected_account, validate_filter_tags, within_request_timeout, Config,
+};
+use crate::tenants::TenantConfig;
+
+/// Cloud provider of a scan
+#[derive(
+    Clone, Copy, Debug, Default, FromFormField, Deserialize, Serialize, JsonSchema, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    #[default]
+    Aws,
+}
+
+/// An impact criterion (as named by Boavizta API)
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    FromFormField,
+    Deserialize,
+    Serialize,
+    JsonSchema,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Criterion {
+    /// Global warming potential
+    Gwp,
+    /// Primary energy
+    Pe,
+    /// Abiotic depletion potential
+    Adp,
+}
+
+impl Criterion {
+    pub const ALL: [Criterion; 3] = [Criterion::Gwp, Criterion::Pe, Criterion::Adp];
+
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Criterion::Gwp => "kgCO2eq",
+            Criterion::Pe => "MJ",
+            Criterion::Adp => "kgSbeq",
+        }
+    }
+
+    fn impacts(&self, manufacture: f64, use_: f64) -> CriterionImpacts {
+        CriterionImpacts {
+            unit: self.unit().to_string(),
+            manufacture,
+            use_,
+            total: manufacture + use_,
+        }
+    }
+
+    fn of_resource(&self, values: &ImpactsValues) -> CriterionImpacts {
+        match self {
+            Criterion::Gwp => self.impacts(values.gwp_manufacture_kgco2eq, values.gwp_use_kgco2eq),
+            Criterion::Pe => {
+                self.impacts(values.pe_manufacture_megajoules, values.pe_use_megajoules)
+            }
+            Criterion::Adp => self.impacts(values.adp_manufacture_kgsbeq, values.adp_use_kgsbeq),
+        }
+    }
+
+    fn of_summary(&self, summary: &ImpactsSummary) -> CriterionImpacts {
+        match self {
+            Criterion::Gwp => {
+                self.impacts(summary.gwp_manufacture_kgco2eq, summary.gwp_use_kgco2eq)
+            }
+            Criterion::Pe => {
+                self.impacts(summary.pe_manufacture_megajoules, summary.pe_use_megajoules)
+            }
+            Criterion::Adp => self.impacts(summary.adp_manufacture_kgsbeq, summary.adp_use_kgsbeq),
+        }
+    }
+}
+
+/// Parameters of a scan of the v2 API
+#[derive(Clone, Debug, FromForm, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct ScanQuery {
+    /// Defaults to aws
+    #[serde(default)]
+    pub provider: Option<Provider>,
+    /// Regions to scan (at least one)
+    pub regions: Vec<String>,
+    /// Defaults to one hour
+    #[serde(default)]
+    pub use_duration_hours: Option<f32>,
+    /// Tags written as tag_name=tag_value
+    #[serde(default)]
+    pub filter_tags: Vec<String>,
+    /// Criteria of the impacts returned, all of them when none is selected
+    #[serde(default)]
+    pub criteria: Vec<Criterion>,
+    #[serde(default)]
+    pub include_block_storage: bool,
+    /// Id of the account to scan (from the accounts allowed by the server)
+    #[serde(default)]
+    pub account_id: Option<String>,
+    /// Role to assume to scan another account (from the accounts allowed by the server)
+    #[serde(default)]
+    pub role_arn: Option<String>,
+}
+
+impl ScanQuery {
+    fn use_duration_hours(&self) -> f32 {
+        self.use_duration_hours.unwrap_or(1.0)
+    }
+
+    fn selected_criteria(&self) -> Vec<Criterion> {
+        let mut criteria = if self.criteria.is_empty() {
+            Criterion::ALL.to_vec()
+        } else {
+            self.criteria.clone()
+        };
+        criteria.sort();
+        criteria.dedup();
+        criteria
+    }
+
+    /// Returns the account selected by the query, fails with 400 when no region is requested and 403 when the account or a region is not allowed
+    fn validate<'a>(
+        &self,
+        config: &'a Config,
+        tenant: Option<&'a TenantConfig>,
+    ) -> Result<Option<&'a AwsAccount>, status::Custom<String>> {
+        if self.regions.is_empty() {
+            return Err(status::Custom(
+                Status::BadRequest,
+                "At least one region is required".to_string(),
+            ));
+        }
+        validate_filter_tags(&self.filter_tags)?;
+        let regions: Vec<&str> = self.regions.iter().map(String::as_str).collect();
+        selected_account(
+            config,
+            tenant,
+            &regions,
+            self.account_id.as_deref(),
+            self.role_arn.as_deref(),
+        )
+        .map_err(|status::Forbidden(message)| status::Custom(Status::Forbidden, message))
+    }
+}
+
+/// Impacts of a criterion, over the duration of use
+#[derive(Clone, Debug, Serialize, JsonSchema, PartialEq)]
+pub struct CriterionImpacts {
+    pub unit: String,
+    pub manufacture: f64,
+    #[serde(rename = "use")]
+    pub use_: f64,
+    pub total: f64,
+}
+
+/// A resource with its impacts by criterion
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct ResourceImpacts {
+    pub cloud_resource: CloudResource,
+    /// None when the resource could not be assessed
+    pub impacts: Option<BTreeMap<Criterion, CriterionImpacts>>,
+}
+
+/// Impacts of the resources of a region
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct RegionImpacts {
+    pub region: String,
+    pub number_of_resources_total: usize,
+    pub number_of_resources_assessed: usize,
+    pub number_of_resources_not_assessed: usize,
+    /// Impacts of all the resources of the region
+    pub impacts: BTreeMap<Criterion, CriterionImpacts>,
+    pub resources: Vec<ResourceImpacts>,
+}
+
+/// Impacts of a scan of the v2 API
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct ImpactsResponse {
+    pub provider: Provider,
+    pub use_duration_hours: f32,
+    pub criteria: Vec<Criterion>,
+    pub regions: Vec<RegionImpacts>,
+}
+
+/// Resources of a region
+#[derive(Clone, Serialize, JsonSchema)]
+pub struct RegionInventory {
+    pub region: String,
+    pub resources: Vec<CloudResource>,
+}
+
+/// Inventory of a scan of the v2 API
+#[derive(Clone, Serialize, JsonSchema)]
+pub struct InventoryResponse {
+    pub provider: Provider,
+    pub regions: Vec<RegionInventory>,
+}
+
+fn resource_impacts(
+    resource: &CloudResourceWithImpacts,
+    criteria: &[Criterion],
+) -> ResourceImpacts {
+    ResourceImpacts {
+        cloud_resource: resource.cloud_resource.clone(),
+        impacts: resource.impacts_values.as_ref().map(|values| {
+            criteria
+                .iter()
+                .map(|criterion| (*criterion, criterion.of_resource(values)))
+                .collect()
+        }),
+    }
+}
+
+/// Returns the impacts of a region by criterion
+pub fn region_impacts(
+    region: &str,
+    estimated_inventory: &EstimatedInventory,
+    summary: &ImpactsSummary,
+    criteria: &[Criterion],
+) -> RegionImpacts {
+    RegionImpacts {
+        region: region.to_string(),
+        number_of_resources_total: summary.number_of_resources_total,
+        number_of_resources_assessed: summary.number_of_resources_assessed,
+        number_of_resources_not_assessed: summary.number_of_resources_not_assessed,
+        impacts: criteria
+            .iter()
+            .map(|criterion| (*criterion, criterion.of_summary(summary)))
+            .collect(),
+        resources: estimated_inventory
+            .impacting_resources
+            .iter()
+            .map(|resource| resource_impacts(resource, criteria))
+            .collect(),
+    }
+}
+
+fn internal_error(e: anyhow::Error) -> status::Custom<String> {
+    error!("Scan failed: {:#}", e);
+    status::Custom(Status::InternalServerError, format!("{:#}", e))
+}
+
+async fn estimate_region(
+    config: &Config,
+    cache: &ResponseCache<EstimatedInventory>,
+    account: Option<&AwsAccount>,
+    query: &ScanQuery,
+    region: &str,
+) -> anyhow::Result<EstimatedInventory> {
+    let key = cache_key(
+        account,
+        region,
+        &query.filter_tags,
+        Some(query.use_duration_hours()),
+        false,
+        query.include_block_storage,
+    );
+    if let Some(estimated_inventory) = cache.get(&key) {
+        info!("Returning cached impacts of {}", region);
+        return Ok(estimated_inventory);
+    }
+    let estimated_inventory = crate::estimate_impacts_in_account(
+        account,
+        &query.use_duration_hours(),
+        &query.filter_tags,
+        region,
+        &config.boavizta_url,
+        false,
+        query.include_block_storage,
+    )
+    .await?;
+    // Partial results are not cached, the next request scans again
+    if estimated_inventory.errors.is_empty() {
+        cache.insert(key, estimated_inventory.clone());
+    }
+    Ok(estimated_inventory)
+}
+
+async fn scan_impacts(
+    config: &Config,
+    request_id: &RequestId,
+    tenant: Option<&TenantConfig>,
+    cache: &ResponseCache<EstimatedInventory>,
+    query: ScanQuery,
+) -> Result<Json<ImpactsResponse>, status::Custom<String>> {
+    let account = query.validate(config, tenant)?;
+    let criteria = query.selected_criteria();
+    info!(
+        "Scanning regions {:?} for {} hours",
+        query.regions,
+        query.use_duration_hours()
+    );
+    let regions = within_request_timeout(
+        config,
+        request_id,
+        crate::concurrency::scan_regions(query.regions.iter().map(|region| async {
+            let estimated_inventory =
+                estimate_region(config, cache, account, &query, region).await?;
+            let summary =
+                crate::build_summary(&estimated_inventory, region, &query.use_duration_hours())?;
+            Ok::<_, anyhow::Error>(region_impacts(
+                region,
+                &estimated_inventory,
+                &summary,
+                &criteria,
+            ))
+        })),
+    )
+    .await?
+    .map_err(internal_error)?;
+    Ok(Json(ImpactsResponse {
+        provider: query.provider.unwrap_or_default(),
+        use_duration_hours: query.use_duration_hours(),
+        criteria,
+        regions,
+    }))
+}
+
+/// # Returns the impacts of the resources of each region, by criterion.
+///
+/// At least one region is required. Criteria default to all (gwp, pe and adp), duration of use to one hour.
+///
+/// Example query: http://localhost:8000/v2/impacts?regions=eu-west-3&regions=eu-west-1&criteria=gwp&use_duration_hours=24&filter_tags=Env=prod
+#[openapi(tag = "impacts")]
+#[get("/impacts?<query..>")]
+async fn impacts(
+    auth: Authenticated,
+    _rate: RateLimited,
+    request_id: RequestId,
+    config: &State<Config>,
+    cache: &State<ResponseCache<EstimatedInventory>>,
+    query: ScanQuery,
+) -> Result<Json<ImpactsResponse>, status::Custom<String>> {
+    scan_impacts(config, &request_id, auth.tenant.as_ref(), cache, query).await
+}
+
+/// # Returns the impacts of the resources of each region, by criterion (parameters in the body).
+///
+/// Example body: {"regions": ["eu-west-3", "eu-west-1"], "criteria": ["gwp"], "use_duration_hours": 24, "filter_tags": ["Env=prod"]}
+#[openapi(tag = "impacts")]
+#[post("/impacts", data = "<query>")]
+async fn post_impacts(
+    auth: Authenticated,
+    _rate: RateLimited,
+    request_id: RequestId,
+    config: &State<Config>,
+    cache: &State<ResponseCache<EstimatedInventory>>,
+    query: Json<ScanQuery>,
+) -> Result<Json<ImpactsResponse>, status::Custom<String>> {
+    scan_impacts(
+        config,
+        &request_id,
+        auth.tenant.as_ref(),
+        cache,
+        query.into_inner(),
+    )
+    .await
+}
+
+/// # Returns the resources of each region.
+///
+/// At least one region is required. The duration of use and criteria are ignored.
+///
+/// Example query: http://localhost:8000/v2/inventory?regions=eu-west-3&filter_tags=Env=prod&include_block_storage=true
+#[openapi(tag = "inventory")]
+#[get("/inventory?<query..>")]
+async fn inventory(
+    auth: Authenticated,
+    _rate: RateLimited,
+    request_id: RequestId,
+    config: &State<Config>,
+    cache: &State<ResponseCache<Inventory>>,
+    query: ScanQuery,
+) -> Result<Json<InventoryResponse>, status::Custom<String>> {
+    let account = query.validate(config, auth.tenant.as_ref())?;
+    let regions = within_request_timeout(
+        config,
+        &request_id,
+        crate::concurrency::scan_regions(query.regions.iter().map(|region| async {
+            let key = cache_key(
+                account,
+                region,
+                &query.filter_tags,
+                None,
+                false,
+                query.include_block_storage,
+            );
+            let inventory = match cache.get(&key) {
+                Some(inventory) => inventory,
+                None => {
+                    let inventory = crate::get_inventory_in_account(
+                        account,
+                        &query.filter_tags,
+                        region,
+                        query.include_block_storage,
+                    )
+                    .await?;
+                    cache.insert(key, inventory.clone());
+                    inventory
+                }
+            };
+            Ok::<_, anyhow::Error>(RegionInventory {
+                region: region.clone(),
+                resources: inventory.resources,
+            })
+        })),
+    )
+    .await?
+    .map_err(internal_error)?;
+    Ok(Json(InventoryResponse {
+        provider: query.provider.unwrap_or_default(),
+        regions,
+    }))
+}
+
+/// Returns the routes of the v2 API (with the route of its OpenAPI document)
+pub fn routes() -> Vec<rocket::Route> {
+    rocket_okapi::openapi_get_routes![impacts, post_impacts, inventory]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{CloudProvider, ResourceDetails};
+    use crate::usage_location::UsageLocation;
+
+    #[test]
+    fn query_has_defaults() {
+        let query: ScanQuery = serde_json::from_str(r#"{"regions": ["eu-west-3"]}"#).unwrap();
+        assert_eq!(None, query.provider);
+        assert_eq!(1.0, query.use_duration_hours());
+        assert_eq!(Criterion::ALL.to_vec(), query.selected_criteria());
+
+        let query: ScanQuery = serde_json::from_str(
+            r#"{"provider": "aws", "regions": ["eu-west-3"], "criteria": ["pe", "gwp", "pe"]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![Criterion::Gwp, Criterion::Pe],
+            query.selected_criteria()
+        );
+        assert!(serde_json::from_str::<ScanQuery>(
+            r#"{"provider": "gcp", "regions": ["eu-west-3"]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn routes_validate_queries_and_are_documented() {
+        let config = Config {
+            boavizta_url: "http://localhost:5000".to_string(),
+            address: None,
+            port: None,
+            auth: Default::default(),
+            tls: None,
+            scheduled_scans: Vec::new(),
+            store: None,
+            cache_ttl: std::time::Duration::ZERO,
+            accounts: Vec::new(),
+            rate_limit: None,
+            cors: None,
+            grpc_port: None,
+            callback_secret: None,
+            callback_allowed_hosts: Vec::new(),
+            tenants: Vec::new(),
+            graphql: false,
+            workers: None,
+            request_timeout: None,
+            shutdown_grace: None,
+            compression: Default::default(),
+            access_log: false,
+            metric_options: crate::metric_exporter::MetricOptions::default(),
+        };
+        let rocket = rocket::build()
+            .mount("/v2", routes())
+            .manage(config)
+            .manage(ResponseCache::<Inventory>::new(std::time::Duration::ZERO))
+            .manage(ResponseCache::<EstimatedInventory>::new(
+                std::time::Duration::ZERO,
+            ));
+        let client = rocket::local::blocking::Client::untracked(rocket).unwrap();
+        let response = client.get("/v2/impacts?criteria=gwp").dispatch();
+        assert_eq!(Status::BadRequest, response.status());
+        let response = client
+            .get("/v2/impacts?regions=eu-west-3&criteria=co2")
+            .dispatch();
+        assert_eq!(Status::UnprocessableEntity, response.status());
+        let response = client
+            .get("/v2/inventory?regions=eu-west-3&filter_tags=env%3Dprod%20AND")
+            .dispatch();
+        assert_eq!(Status::BadRequest, response.status());
+
+        let response = client.get("/v2/openapi.json").dispatch();
+        let document: serde_json::Value = response.into_json().unwrap();
+        let paths = document["paths"].as_object().unwrap();
+        assert!(paths["/impacts"]["get"].is_object());
+        assert!(paths["/impacts"]["post"].is_object());
+        assert!(paths["/inventory"]["get"].is_object());
+    }
+
+    #[test]
+    fn impacts_are_returned_for_selected_criteria() {
+        let resource = CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: "i-1".to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details: ResourceDetails::Instance {
+                    instance_type: "m6g.xlarge".to_string(),
+                    usage: None,
+                },
+                tags: Vec::new(),
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues {
+                gwp_manufacture_kgco2eq: 1.0,
+                gwp_use_kgco2eq: 0.5,
+                ..Default::default()
+            }),
+            impacts_duration_hours: 1.0,
+            cost: None,
+        };
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![resource],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let region = region_impacts(
+            "eu-west-3",
+            &estimated_inventory,
+            &summary,
+            &[Criterion::Gwp],
+        );
+        assert_eq!(1, region.number_of_resources_assessed);
+        assert_eq!(
+            vec![&Criterion::Gwp],
+            region.impacts.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(1.5, region.impacts[&Criterion::Gwp].total);
+        let impacts = region.resources[0].impacts.as_ref().unwrap();
+        assert_eq!("kgCO2eq", impacts[&Criterion::Gwp].unit);
+
+        let json = serde_json::to_value(&region).unwrap();
+        assert_eq!(1.0, json["impacts"]["gwp"]["manufacture"]);
+        assert_eq!(0.5, json["impacts"]["gwp"]["use"]);
+    }
+}
@@ -1,29 +1,165 @@
 //! A module to perform inventory of  AWS cloud resources.
+use std::collections::HashMap;
 use std::time::Instant;
 
 use crate::cloud_provider::Inventoriable;
+use crate::progress::{self, Phase};
+use crate::scan_errors::{ScanError, ScanPhase};
+use crate::server_telemetry::ScanStage;
+use crate::tag_filter::TagFilter;
 use crate::usage_location::*;
 
-use anyhow::{Context, Error, Result};
-use aws_sdk_cloudwatch::operation::get_metric_statistics::GetMetricStatisticsOutput;
-use aws_sdk_cloudwatch::types::{Dimension, StandardUnit, Statistic};
+use anyhow::{Context, Result};
+use aws_sdk_cloudwatch::types::{Dimension, Metric, MetricDataQuery, MetricStat, StandardUnit};
 use aws_sdk_ec2::config::Region;
 use aws_sdk_ec2::types::Volume;
 use aws_sdk_ec2::types::{Instance, InstanceStateName};
+use aws_sdk_organizations::types::AccountStatus;
 use chrono::TimeDelta;
 use chrono::Utc;
 
 use crate::model::{
     CloudProvider, CloudResource, CloudResourceTag, ExecutionStatistics, InstanceState,
-    InstanceUsage, Inventory, ResourceDetails, StorageAttachment, StorageUsage,
+    InstanceUsage, Inventory, ResourceDetails, ResourceRelationship, StorageAttachment,
+    StorageUsage,
 };
 use async_trait::async_trait;
 use aws_types::SdkConfig;
+use futures::stream::{self, StreamExt};
+use serde::Deserialize;
+use tokio::sync::mpsc::Sender;
+use tracing::Instrument;
+
+/// Number of instances (or volumes) requested in each page of DescribeInstances (or DescribeVolumes)
+const PAGE_SIZE: i32 = 500;
+
+/// Maximum number of queries (one per instance) of a GetMetricData request
+pub const MAX_METRIC_QUERIES: usize = 500;
+
+/// Minutes of CPU utilization averaged into the CPU load of an instance
+const CPU_LOAD_WINDOW_MINUTES: i64 = 10;
+
+/// An AWS account whose resources can be scanned by assuming a role of the account
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AwsAccount {
+    pub account_id: String,
+    /// Role assumed to scan the account, like `arn:aws:iam::123456789012:role/cloud-scanner`
+    pub role_arn: String,
+    /// External id required by the trust policy of the role (if any)
+    pub external_id: Option<String>,
+}
+
+impl AwsAccount {
+    /// Returns the account of a role ARN, like `arn:aws:iam::123456789012:role/cloud-scanner` (without external id)
+    pub fn from_role_arn(role_arn: &str) -> Result<AwsAccount> {
+        let parts: Vec<&str> = role_arn.split(':').collect();
+        let is_role = parts.len() == 6
+            && parts[0] == "arn"
+            && parts[2] == "iam"
+            && parts[4].len() == 12
+            && parts[4].chars().all(|c| c.is_ascii_digit())
+            && parts[5].starts_with("role/");
+        if !is_role {
+            anyhow::bail!(
+                "Invalid role ARN '{}', expecting arn:aws:iam::<account id>:role/<name>",
+                role_arn
+            );
+        }
+        Ok(AwsAccount {
+            account_id: parts[4].to_string(),
+            role_arn: role_arn.to_string(),
+            external_id: None,
+        })
+    }
+
+    /// Returns the account of an organization scanned by assuming a role of the same name in every account, the partition being read from the ARN of the account (like `arn:aws:organizations::...`)
+    fn in_organization(account_id: &str, account_arn: Option<&str>, role_name: &str) -> Self {
+        let partition = account_arn
+            .and_then(|arn| arn.split(':').nth(1))
+            .filter(|partition| !partition.is_empty())
+            .unwrap_or("aws");
+        AwsAccount {
+            account_id: account_id.to_string(),
+            role_arn: format!("arn:{}:iam::{}:role/{}", partition, account_id, role_name),
+            external_id: None,
+        }
+    }
+
+    /// Returns the active accounts of the AWS Organization of the credentials of the environment (which must be allowed to list the accounts, like in the management account), to scan by assuming a role of the same name in every account
+    pub async fn list_organization_accounts(role_name: &str) -> Result<Vec<AwsAccount>> {
+        let sdk_config = crate::aws_settings::config_loader().load().await;
+        let client = aws_sdk_organizations::Client::new(&sdk_config);
+        let mut accounts = Vec::new();
+        let mut next_token: Option<String> = None;
+        loop {
+            let response = client
+                .list_accounts()
+                .set_next_token(next_token)
+                .send()
+                .await
+                .context("Cannot list the accounts of the organization")?;
+            for account in response.accounts() {
+                let Some(account_id) = account.id() else {
+                    continue;
+                };
+                if account.status() != Some(&AccountStatus::Active) {
+                    info!("Skipping account {} ({:?})", account_id, account.status());
+                    continue;
+                }
+                accounts.push(AwsAccount::in_organization(
+                    account_id,
+                    account.arn(),
+                    role_name,
+                ));
+            }
+            next_token = response.next_token().map(str::to_string);
+            if next_token.is_none() {
+                break;
+            }
+        }
+        accounts.sort_by(|a, b| a.account_id.cmp(&b.account_id));
+        Ok(accounts)
+    }
+
+    /// Returns the account of the allow-list selected by an account id or a role ARN (`Ok(None)` when none is selected).
+    ///
+    /// Fails when the account or role is not in the allow-list, or when the account id and role ARN designate different accounts.
+    pub fn select<'a>(
+        allowed: &'a [AwsAccount],
+        account_id: Option<&str>,
+        role_arn: Option<&str>,
+    ) -> Result<Option<&'a AwsAccount>> {
+        if account_id.is_none() && role_arn.is_none() {
+            return Ok(None);
+        }
+        allowed
+            .iter()
+            .find(|account| {
+                account_id.is_none_or(|id| id == account.account_id)
+                    && role_arn.is_none_or(|arn| arn == account.role_arn)
+            })
+            .map(Some)
+            .with_context(|| {
+                format!(
+                    "Account {} / role {} is not allowed",
+                    account_id.unwrap_or("-"),
+                    role_arn.unwrap_or("-")
+                )
+            })
+    }
+}
 
 ///  An service to perform inventory of AWS resources.
 #[derive(Clone, Debug)]
 pub struct AwsCloudProvider {
     aws_region: String,
+    /// Account of the resources (empty for the account of the environment)
+    account_id: String,
+    /// Name of the scan in the progress of scans (region, prefixed by the account when assuming a role)
+    progress_label: String,
+    /// Resources listed by the inventory
+    selection: ResourceSelection,
     ec2_client: aws_sdk_ec2::Client,
     cloudwatch_client: aws_sdk_cloudwatch::Client,
 }
@@ -33,16 +169,94 @@ impl AwsCloudProvider {
     ///
     /// Initializes it with a specific region and configures the SDK's that will query your account to perform the inventory of resources.
     pub async fn new(aws_region: &str) -> Self {
-        let shared_config = Self::load_aws_config(aws_region).await;
+        Self::new_in_account(aws_region, None).await
+    }
+
+    /// Creates a service to perform inventory of the resources of another AWS account, by assuming the role of the account.
+    ///
+    /// Uses the credentials of the environment when no account is provided.
+    pub async fn new_in_account(aws_region: &str, account: Option<&AwsAccount>) -> Self {
+        let mut shared_config = Self::load_aws_config(aws_region).await;
+        if let Some(account) = account {
+            shared_config = Self::assume_role(&shared_config, account).await;
+        }
         let retained_region = Self::get_configured_region_or_exit_if_unsupported(&shared_config);
+        let progress_label = match account {
+            Some(account) => format!("{}/{}", account.account_id, retained_region),
+            None => retained_region.clone(),
+        };
 
         AwsCloudProvider {
             aws_region: retained_region,
+            account_id: account
+                .map(|account| account.account_id.clone())
+                .unwrap_or_default(),
+            progress_label,
+            selection: ResourceSelection::default(),
             ec2_client: aws_sdk_ec2::Client::new(&shared_config),
             cloudwatch_client: aws_sdk_cloudwatch::Client::new(&shared_config),
         }
     }
 
+    /// Returns the name of the scan in the progress of scans
+    pub fn progress_label(&self) -> &str {
+        &self.progress_label
+    }
+
+    /// Only lists the selected resources
+    pub fn with_selection(mut self, selection: &ResourceSelection) -> Self {
+        self.selection = selection.clone();
+        self
+    }
+
+    /// Returns an EC2 client with the credentials of the environment, in its default region (or `us-east-1` when there is none)
+    async fn default_ec2_client() -> aws_sdk_ec2::Client {
+        let mut sdk_config = crate::aws_settings::config_loader().load().await;
+        if sdk_config.region().is_none() {
+            sdk_config = sdk_config
+                .to_builder()
+                .region(Region::new("us-east-1"))
+                .build();
+        }
+        aws_sdk_ec2::Client::new(&sdk_config)
+    }
+
+    /// Checks that the credentials of the environment are valid, by listing the regions of EC2 (in the default region of the environment, or `us-east-1` when there is none)
+    pub async fn check_credentials() -> Result<()> {
+        Self::default_ec2_client()
+            .await
+            .describe_regions()
+            .send()
+            .await
+            .context("Cannot list AWS regions with the credentials of the environment")?;
+        Ok(())
+    }
+
+    /// Returns the regions enabled in the account (sorted), skipping the regions whose usage location is unknown
+    pub async fn list_enabled_regions() -> Result<Vec<String>> {
+        let response = Self::default_ec2_client()
+            .await
+            .describe_regions()
+            .send()
+            .await
+            .context("Cannot list the regions enabled in the account")?;
+        let mut regions: Vec<String> = response
+            .regions()
+            .iter()
+            .filter_map(|region| region.region_name())
+            .filter(|region| match UsageLocation::try_from(*region) {
+                Ok(_) => true,
+                Err(e) => {
+                    warn!("Skipping region {}: {:#}", region, e);
+                    false
+                }
+            })
+            .map(str::to_string)
+            .collect();
+        regions.sort();
+        Ok(regions)
+    }
+
     /// Initialize a AWS SDK config with default credentials from the environment and  a region passed as argument.
     ///
     /// - If region is empty, uses the default region from environment.
@@ -50,7 +264,7 @@ impl AwsCloudProvider {
     async fn load_aws_config(aws_region: &str) -> SdkConfig {
         if aws_region.is_empty() {
             // Use default region (from environment, if any)
-            let sdk_config = aws_config::load_from_env().await;
+            let sdk_config = crate::aws_settings::config_loader().load().await;
             warn!(
                 "Cannot initialize AWS client from an empty region, falling back to using default region from environment [{}]",
                 sdk_config.region().unwrap()
@@ -59,7 +273,7 @@ impl AwsCloudProvider {
             sdk_config
         } else {
             // Use the region passed in argument
-            let sdk_config = aws_config::from_env()
+            let sdk_config = crate::aws_settings::config_loader()
                 .region(Region::new(aws_region.to_string()))
                 .load()
                 .await;
@@ -68,6 +282,27 @@ impl AwsCloudProvider {
         }
     }
 
+    /// Returns a SDK config whose credentials are the ones of the role of the account (refreshed when they expire)
+    async fn assume_role(sdk_config: &SdkConfig, account: &AwsAccount) -> SdkConfig {
+        let mut builder = aws_config::sts::AssumeRoleProvider::builder(&account.role_arn)
+            .session_name("cloud-scanner")
+            .configure(sdk_config);
+        if let Some(external_id) = &account.external_id {
+            builder = builder.external_id(external_id);
+        }
+        let provider = builder.build().await;
+        info!(
+            "Assuming role [{}] of account [{}]",
+            account.role_arn, account.account_id
+        );
+        sdk_config
+            .to_builder()
+            .credentials_provider(aws_types::sdk_config::SharedCredentialsProvider::new(
+                provider,
+            ))
+            .build()
+    }
+
     /// Util function that panics with error message if the region cannot be set or is not supported by cloud-scanner
     fn get_configured_region_or_exit_if_unsupported(sdk_config: &SdkConfig) -> String {
         if let Some(retained_region) = sdk_config.region() {
@@ -97,67 +332,137 @@ impl AwsCloudProvider {
             let v = nt.value.to_owned();
             cs_tags.push(CloudResourceTag { key: k, value: v });
         }
-        cs_tags
+        crate::tag_normalization::normalize(cs_tags)
     }
 
-    /// Perform inventory of all aws instances of the region
-    async fn get_instances_with_usage_data(&self, tags: &[String]) -> Result<Vec<CloudResource>> {
-        let instances: Vec<Instance> = self
-            .clone()
-            .list_instances(tags)
-            .await
-            .context("Cannot list instances")
-            .unwrap();
+    /// Sends the instances of the region (with their usage data), page by page as soon as each page of DescribeInstances is listed
+    async fn send_instance_pages(
+        &self,
+        tags: &[String],
+        pages: &Sender<Vec<CloudResource>>,
+    ) -> Result<()> {
+        let filter = TagFilter::parse_all(tags)?;
         let location = UsageLocation::try_from(self.aws_region.as_str())?;
+        progress::report(&self.progress_label, Phase::Inventory, 0, 0);
 
         // Just to display statistics
         let cpu_info_timer = Instant::now();
 
-        let mut inventory: Vec<CloudResource> = Vec::new();
-        for instance in instances {
-            let instance_id = instance.instance_id().unwrap().to_string();
-            let cpuload: f64 = self
-                .clone()
-                .get_average_cpu(&instance_id)
+        let (mut done, mut total) = (0, 0);
+        let mut next_token: Option<String> = None;
+        loop {
+            crate::aws_settings::pace_api_call(&self.account_id).await;
+            if !crate::aws_settings::spend_api_call("DescribeInstances") {
+                let message = format!(
+                    "Budget of AWS API calls spent, not listing the next instances of {}",
+                    self.progress_label
+                );
+                warn!("{}", message);
+                crate::scan_errors::record(ScanError::budget_exhausted(
+                    ScanPhase::Inventory,
+                    &self.account_id,
+                    &self.aws_region,
+                    message,
+                ));
+                break;
+            }
+            let (instances, token) = self
+                .list_instances_page(next_token)
                 .await
-                .context("Cannot get CPU load of instance")
-                .unwrap();
+                .context("Cannot list instances")?;
+            total += instances.len();
+            let number_of_instances = instances.len();
+            let utilization_metrics = async {
+                let mut page: Vec<CloudResource> = Vec::new();
+                for instance in instances {
+                    let instance_id = instance.instance_id().unwrap().to_string();
 
-            let usage: InstanceUsage = InstanceUsage {
-                average_cpu_load: cpuload,
-                usage_duration_seconds: 300,
-                state: Self::aws_state_to_generic(instance.clone()),
-            };
+                    let usage: InstanceUsage = InstanceUsage {
+                        average_cpu_load: 0.0,
+                        usage_duration_seconds: 300,
+                        state: Self::aws_state_to_generic(instance.clone()),
+                    };
 
-            let cloud_resource_tags = Self::cloud_resource_tags_from_aws_tags(instance.tags());
+                    let cloud_resource_tags =
+                        Self::cloud_resource_tags_from_aws_tags(instance.tags());
 
+                    let resource_details = ResourceDetails::Instance {
+                        instance_type: instance.instance_type().unwrap().as_str().to_owned(),
+                        usage: Some(usage),
+                    };
+                    let inst = CloudResource {
+                        provider: CloudProvider::AWS,
+                        account_id: None,
+                        id: instance_id,
+                        location: location.clone(),
+                        relationships: ResourceRelationship::derive(
+                            &resource_details,
+                            &cloud_resource_tags,
+                        ),
+                        resource_details,
+                        tags: cloud_resource_tags,
+                    };
+
+                    if filter.matches(&inst.tags) {
+                        debug!("Resource matched on tags: {:?}", inst.id);
+                        page.push(inst);
+                    } else {
+                        debug!("Filtered instance (tags do not match: {:?}", inst);
+                    }
+                    //if cs matches the tags passed in param keep it (push it, otherwise skip it)
+                }
+
+                // Unchanged instances of incremental scans keep the CPU load of the last scan
+                let previous_cpu_loads: Vec<Option<f64>> = page
+                    .iter()
+                    .map(crate::incremental::previous_cpu_load)
+                    .collect();
+                let queried: Vec<String> = page
+                    .iter()
+                    .zip(previous_cpu_loads.iter())
+                    .filter(|(_, previous)| previous.is_none())
+                    .map(|(inst, _)| inst.id.clone())
+                    .collect();
+                progress::report(&self.progress_label, Phase::UtilizationMetrics, done, total);
+                let cpu_loads = self
+                    .get_average_cpu_of_instances(&queried)
+                    .await
+                    .context("Cannot get CPU load of instances")?;
+                for (inst, previous) in page.iter_mut().zip(previous_cpu_loads) {
+                    let cpuload = previous.or_else(|| cpu_loads.get(&inst.id).copied());
+                    if let ResourceDetails::Instance {
+                        usage: Some(usage), ..
+                    } = &mut inst.resource_details
+                    {
+                        usage.average_cpu_load = cpuload.unwrap_or_default();
+                    }
+                }
+                Ok::<_, anyhow::Error>(page)
+            };
+            let page = utilization_metrics
+                .instrument(tracing::info_span!(
+                    "utilization_metrics",
+                    cloud.region = %self.aws_region,
+                    cloud_scanner.instances = number_of_instances,
+                ))
+                .await?;
+            done = total;
+            progress::report(&self.progress_label, Phase::UtilizationMetrics, done, total);
             info!(
                 "Total time spend querying CPU load of instances: {:?}",
                 cpu_info_timer.elapsed()
             );
+            pages
+                .send(page)
+                .await
+                .context("The pages of instances are no longer processed")?;
 
-            let inst = CloudResource {
-                provider: CloudProvider::AWS,
-                id: instance_id,
-                location: location.clone(),
-                resource_details: ResourceDetails::Instance {
-                    instance_type: instance.instance_type().unwrap().as_str().to_owned(),
-                    usage: Some(usage),
-                },
-
-                tags: cloud_resource_tags,
-            };
-
-            if inst.has_matching_tags(tags) {
-                debug!("Resource matched on tags: {:?}", inst.id);
-                inventory.push(inst);
-            } else {
-                debug!("Filtered instance (tags do not match: {:?}", inst);
+            next_token = token;
+            if next_token.is_none() {
+                break;
             }
-            //if cs matches the tags passed in param keep it (push it, otherwise skip it)
         }
-
-        Ok(inventory)
+        Ok(())
     }
 
     /// We consider that an instance is running unless explicitly stopped or terminated
@@ -177,67 +482,153 @@ impl AwsCloudProvider {
         }
     }
 
-    /// List all ec2 instances of the current account / region
+    /// List a page of the ec2 instances of the current account / region, with the token of the next page (if any)
     ///
-    /// ⚠  Filtering instance on tags during query is not yet implemented. All instances (running or stopped) are returned.
-    async fn list_instances(self, _tags: &[String]) -> Result<Vec<Instance>> {
+    /// ⚠  Filtering instance on tags during query is not yet implemented. All instances in the selected states (any state by default) are returned.
+    async fn list_instances_page(
+        &self,
+        next_token: Option<String>,
+    ) -> Result<(Vec<Instance>, Option<String>)> {
         let client = &self.ec2_client;
-        let mut instances: Vec<Instance> = Vec::new();
         // Filter: AND on name, OR on values
-        //let filters :std::vec::Vec<aws_sdk_ec2::model::Filter>;
-
-        let resp = client
+        let mut request = client
             .describe_instances()
-            //set_filters() // Use filters for tags
+            .max_results(PAGE_SIZE)
+            .set_next_token(next_token);
+        if !self.selection.instance_states.is_empty() {
+            request = request.filters(
+                aws_sdk_ec2::types::Filter::builder()
+                    .name("instance-state-name")
+                    .set_values(Some(self.selection.instance_states.clone()))
+                    .build(),
+            );
+        }
+
+        let resp = request
             .send()
-            .await?;
+            .instrument(tracing::info_span!(
+                "ec2.DescribeInstances",
+                otel.kind = "client",
+                cloud.region = %self.aws_region,
+            ))
+            .await
+            .inspect_err(|_| crate::server_telemetry::record_provider_error("DescribeInstances"))?;
 
-        for reservation in resp.reservations() {
-            for instance in reservation.instances() {
-                instances.push(instance.clone());
-            }
-        }
-        Ok(instances)
+        let instances: Vec<Instance> = resp
+            .reservations()
+            .iter()
+            .flat_map(|reservation| reservation.instances().iter().cloned())
+            .collect();
+        Ok((instances, resp.next_token().map(str::to_string)))
     }
 
-    /// Returns average CPU load of a given instance.
+    /// Returns the average CPU load of instances on the last [CPU_LOAD_WINDOW_MINUTES] minutes, by instance, queried by batches of [MAX_METRIC_QUERIES] instances (one after the other, or concurrently in adaptive mode, see [crate::concurrency::set_adaptive])
     ///
-    async fn get_average_cpu(self, instance_id: &str) -> Result<f64> {
-        let res = self
-            .get_average_cpu_usage_of_last_10_minutes(instance_id)
-            .await
-            .with_context(|| {
-                format!(
-                    "Cannot retrieve average CPU load of instance: {}",
-                    instance_id
-                )
-            })?;
-        if let Some(points) = res.datapoints {
-            if !points.is_empty() {
-                debug!("Averaging cpu load data point: {:#?}", points);
-                let mut sum: f64 = 0.0;
-                for x in &points {
-                    sum += x.average().unwrap();
+    /// The CPU load of the instances without data points (likely stopped) is 0. Loads read by recent scans (of this process, or of other processes sharing the cache) are reused (see [crate::utilization_cache]). The instances of the batches that CloudWatch fails to return are left out (their load being 0), the error being reported in the results of the scan (see [crate::scan_errors]).
+    async fn get_average_cpu_of_instances(
+        &self,
+        instance_ids: &[String],
+    ) -> Result<HashMap<String, f64>> {
+        let mut cpu_loads: HashMap<String, f64> = HashMap::new();
+        let mut queried: Vec<String> = Vec::new();
+        for instance_id in instance_ids {
+            match crate::utilization_cache::cached_cpu_load(instance_id, CPU_LOAD_WINDOW_MINUTES) {
+                Some(cpu_load) => {
+                    cpu_loads.insert(instance_id.clone(), cpu_load);
                 }
-                let avg = sum / points.len() as f64;
-                return Ok(avg);
+                None => queried.push(instance_id.clone()),
             }
         }
-        warn!(
-            "Unable to get CPU load of  instance {}, it is likely stopped, using 0 as load",
-            instance_id
-        );
-        Ok(0 as f64)
+        if !cpu_loads.is_empty() {
+            debug!(
+                "Reusing the cached CPU load of {} instances of {}",
+                cpu_loads.len(),
+                self.progress_label
+            );
+        }
+        #[cfg(feature = "shared-cache")]
+        {
+            let shared =
+                crate::utilization_cache::shared_cpu_loads(&queried, CPU_LOAD_WINDOW_MINUTES).await;
+            if !shared.is_empty() {
+                debug!(
+                    "Reusing the shared CPU load of {} instances of {}",
+                    shared.len(),
+                    self.progress_label
+                );
+                queried.retain(|instance_id| !shared.contains_key(instance_id));
+                cpu_loads.extend(shared);
+            }
+        }
+        #[cfg(feature = "shared-cache")]
+        let mut measured = Vec::new();
+        // Collected first, so that the scan does not hold the closure (and remains Send for the server)
+        let requests: Vec<_> = queried
+            .chunks(MAX_METRIC_QUERIES)
+            .map(|batch| async move {
+                let points = self.get_cpu_utilization_of_last_10_minutes(batch).await;
+                (batch, points)
+            })
+            .collect();
+        let mut batches =
+            stream::iter(requests).buffered(crate::concurrency::cloudwatch_requests_per_region());
+        while let Some((batch, points)) = batches.next().await {
+            let points = match points {
+                Ok(points) => points,
+                Err(e) => {
+                    let message = format!(
+                        "Cannot retrieve average CPU load of {} instances, using 0 as load: {:#}",
+                        batch.len(),
+                        e
+                    );
+                    warn!("{}", message);
+                    crate::scan_errors::record(ScanError::of_region(
+                        ScanPhase::UtilizationMetrics,
+                        &self.account_id,
+                        &self.aws_region,
+                        message,
+                    ));
+                    continue;
+                }
+            };
+            for instance_id in batch {
+                let average = match points.get(instance_id) {
+                    Some(points) if !points.is_empty() => {
+                        debug!("Averaging cpu load data point: {:#?}", points);
+                        let average = points.iter().sum::<f64>() / points.len() as f64;
+                        crate::utilization_cache::cache_cpu_load(
+                            instance_id,
+                            CPU_LOAD_WINDOW_MINUTES,
+                            average,
+                        );
+                        #[cfg(feature = "shared-cache")]
+                        measured.push((instance_id.clone(), average));
+                        average
+                    }
+                    _ => {
+                        warn!(
+                            "Unable to get CPU load of  instance {}, it is likely stopped, using 0 as load",
+                            instance_id
+                        );
+                        0 as f64
+                    }
+                };
+                cpu_loads.insert(instance_id.clone(), average);
+            }
+        }
+        #[cfg(feature = "shared-cache")]
+        crate::utilization_cache::share_cpu_loads(&measured, CPU_LOAD_WINDOW_MINUTES).await;
+        Ok(cpu_loads)
     }
 
-    /// Returns the instance CPU utilization usage on the last 10 minutes
-    async fn get_average_cpu_usage_of_last_10_minutes(
-        self,
-        instance_id: &str,
-    ) -> Result<GetMetricStatisticsOutput, Error> {
+    /// Returns the CPU utilization data points of instances (at most [MAX_METRIC_QUERIES]) on the last [CPU_LOAD_WINDOW_MINUTES] minutes, by instance, with one GetMetricData request (and its next pages)
+    async fn get_cpu_utilization_of_last_10_minutes(
+        &self,
+        instance_ids: &[String],
+    ) -> Result<HashMap<String, Vec<f64>>> {
         // We want statistics about the last 10 minutes using  5min  sample
         let measure_duration: chrono::TimeDelta =
-            TimeDelta::try_minutes(10).context("Unsupported duration")?;
+            TimeDelta::try_minutes(CPU_LOAD_WINDOW_MINUTES).context("Unsupported duration")?;
         let sample_period_seconds = 300; // 5*60 (the default granularity of cloudwatch standard CPU metrics)
         let now: chrono::DateTime<Utc> = Utc::now();
         let start_time: chrono::DateTime<Utc> = now - measure_duration;
@@ -245,103 +636,293 @@ impl AwsCloudProvider {
         let cpu_metric_name = String::from("CPUUtilization");
         let ec2_namespace = "AWS/EC2";
 
-        let dimensions = vec![Dimension::builder()
-            .name("InstanceId")
-            .value(instance_id)
-            .build()];
+        // The ids of the queries are the positions of the instances
+        let queries = instance_ids
+            .iter()
+            .enumerate()
+            .map(|(index, instance_id)| {
+                let metric = Metric::builder()
+                    .namespace(ec2_namespace)
+                    .metric_name(&cpu_metric_name)
+                    .dimensions(
+                        Dimension::builder()
+                            .name("InstanceId")
+                            .value(instance_id)
+                            .build(),
+                    )
+                    .build();
+                let metric_stat = MetricStat::builder()
+                    .metric(metric)
+                    .period(sample_period_seconds)
+                    .stat("Average")
+                    .unit(StandardUnit::Percent)
+                    .build();
+                MetricDataQuery::builder()
+                    .id(format!("cpu{}", index))
+                    .metric_stat(metric_stat)
+                    .return_data(true)
+                    .build()
+            })
+            .collect::<Vec<MetricDataQuery>>();
 
         let end_time_aws: aws_sdk_cloudwatch::primitives::DateTime =
             aws_sdk_cloudwatch::primitives::DateTime::from_secs(now.timestamp());
         let start_time_aws: aws_sdk_cloudwatch::primitives::DateTime =
             aws_sdk_cloudwatch::primitives::DateTime::from_secs(start_time.timestamp());
 
-        let resp: GetMetricStatisticsOutput = self
-            .cloudwatch_client
-            .get_metric_statistics()
-            .end_time(end_time_aws)
-            .metric_name(cpu_metric_name)
-            .namespace(ec2_namespace)
-            .period(sample_period_seconds)
-            .set_dimensions(Some(dimensions))
-            .start_time(start_time_aws)
-            .statistics(Statistic::Average)
-            .unit(StandardUnit::Percent)
-            .send()
-            .await
-            .context("Trying to get cloudwatch statistics")?;
+        let number_of_instances = instance_ids.len();
+        let mut points: HashMap<String, Vec<f64>> = HashMap::new();
+        let mut next_token: Option<String> = None;
+        loop {
+            // Instances without data points have a CPU load of 0
+            crate::aws_settings::pace_api_call(&self.account_id).await;
+            if !crate::aws_settings::spend_api_call("GetMetricData") {
+                let message = format!(
+                    "Budget of AWS API calls spent, not reading the CPU load of instances of {}",
+                    self.progress_label
+                );
+                warn!("{}", message);
+                crate::scan_errors::record(ScanError::budget_exhausted(
+                    ScanPhase::UtilizationMetrics,
+                    &self.account_id,
+                    &self.aws_region,
+                    message,
+                ));
+                break;
+            }
+            let permit = crate::concurrency::CLOUDWATCH_LIMIT.acquire().await;
+            let started = Instant::now();
+            let request = self
+                .cloudwatch_client
+                .get_metric_data()
+                .set_metric_data_queries(Some(queries.clone()))
+                .start_time(start_time_aws)
+                .end_time(end_time_aws)
+                .set_next_token(next_token);
+            let resp = request
+                .send()
+                .instrument(tracing::info_span!(
+                    "cloudwatch.GetMetricData",
+                    otel.kind = "client",
+                    cloud.region = %self.aws_region,
+                    cloud_scanner.instances = number_of_instances,
+                ))
+                .await;
+            permit.finish(resp.is_ok());
+            crate::server_telemetry::record_scan_stage(
+                ScanStage::CloudwatchQuery,
+                Some(&self.aws_region),
+                started.elapsed(),
+            );
+            let resp = resp
+                .inspect_err(|_| crate::server_telemetry::record_provider_error("GetMetricData"))
+                .context("Trying to get cloudwatch metric data")?;
 
-        Ok(resp)
+            for result in resp.metric_data_results() {
+                let instance_id = result
+                    .id()
+                    .and_then(|id| id.strip_prefix("cpu"))
+                    .and_then(|index| index.parse::<usize>().ok())
+                    .and_then(|index| instance_ids.get(index));
+                if let Some(instance_id) = instance_id {
+                    points
+                        .entry(instance_id.clone())
+                        .or_default()
+                        .extend(result.values());
+                }
+            }
+            next_token = resp.next_token().map(str::to_string);
+            if next_token.is_none() {
+                break;
+            }
+        }
+        Ok(points)
     }
 
-    /// List all Volumes of current account.
+    /// List a page of the Volumes of current account, with the token of the next page (if any)
     ///
-    /// ⚠  Filtering on tags is not yet implemented.
-    async fn list_volumes(self, tags: &[String]) -> Result<Vec<Volume>> {
-        warn!(
-            "Warning: filtering volumes on tags is not implemented {:?}",
-            tags
-        );
+    /// ⚠  Filtering volumes on tags during query is not yet implemented, they are filtered after listing.
+    async fn list_volumes_page(
+        &self,
+        next_token: Option<String>,
+    ) -> Result<(Vec<Volume>, Option<String>)> {
         let client = &self.ec2_client;
-        let mut volumes: Vec<Volume> = Vec::new();
         // Filter: AND on name, OR on values
         //let filters :std::vec::Vec<aws_sdk_ec2::model::Filter>;
-        let resp = client
+        //set_filters() // Use filters for tags
+        let request = client
             .describe_volumes()
-            //set_filters() // Use filters for tags
+            .max_results(PAGE_SIZE)
+            .set_next_token(next_token);
+        let resp = request
             .send()
-            .await?;
-        for v in resp.volumes() {
-            volumes.push(v.clone());
-        }
-        Ok(volumes)
+            .instrument(tracing::info_span!(
+                "ec2.DescribeVolumes",
+                otel.kind = "client",
+                cloud.region = %self.aws_region,
+            ))
+            .await
+            .inspect_err(|_| crate::server_telemetry::record_provider_error("DescribeVolumes"))?;
+        Ok((
+            resp.volumes().to_vec(),
+            resp.next_token().map(str::to_string),
+        ))
     }
 
-    /// Perform inventory of all aws volumes of the region
-    async fn get_volumes_with_usage_data(&self, tags: &[String]) -> Result<Vec<CloudResource>> {
+    /// Sends the volumes of the region (with their usage data), page by page as soon as each page of DescribeVolumes is listed
+    async fn send_volume_pages(
+        &self,
+        tags: &[String],
+        pages: &Sender<Vec<CloudResource>>,
+    ) -> Result<()> {
+        let filter = TagFilter::parse_all(tags)?;
         let location = UsageLocation::try_from(self.aws_region.as_str())?;
-        let volumes = self.clone().list_volumes(tags).await.unwrap();
-        let mut resources: Vec<CloudResource> = Vec::new();
+        let mut next_token: Option<String> = None;
+        loop {
+            crate::aws_settings::pace_api_call(&self.account_id).await;
+            if !crate::aws_settings::spend_api_call("DescribeVolumes") {
+                let message = format!(
+                    "Budget of AWS API calls spent, not listing the next volumes of {}",
+                    self.progress_label
+                );
+                warn!("{}", message);
+                crate::scan_errors::record(ScanError::budget_exhausted(
+                    ScanPhase::Inventory,
+                    &self.account_id,
+                    &self.aws_region,
+                    message,
+                ));
+                break;
+            }
+            let (volumes, token) = self
+                .list_volumes_page(next_token)
+                .await
+                .context("Cannot list volumes")?;
+            let mut page: Vec<CloudResource> = Vec::new();
 
-        for volume in volumes {
-            let volume_id = volume.volume_id().unwrap();
+            for volume in volumes {
+                let volume_id = volume.volume_id().unwrap();
 
-            let usage: StorageUsage = StorageUsage {
-                size_gb: volume.size().unwrap(),
-                usage_duration_seconds: 3600,
-            };
+                let usage: StorageUsage = StorageUsage {
+                    size_gb: volume.size().unwrap(),
+                    usage_duration_seconds: 3600,
+                };
 
-            let volume_type: String = volume.volume_type().unwrap().as_str().to_string();
-            let mut attached_instances: Option<Vec<StorageAttachment>> = None;
+                let volume_type: String = volume.volume_type().unwrap().as_str().to_string();
+                let mut attached_instances: Option<Vec<StorageAttachment>> = None;
 
-            if let Some(all_volume_attachments) = volume.attachments.clone() {
-                for single_attachment in all_volume_attachments {
-                    let mut attachment_list: Vec<StorageAttachment> = Vec::new();
+                if let Some(all_volume_attachments) = volume.attachments.clone() {
+                    for single_attachment in all_volume_attachments {
+                        let mut attachment_list: Vec<StorageAttachment> = Vec::new();
 
-                    if let Some(instance_id) = single_attachment.instance_id {
-                        attachment_list.push(StorageAttachment { instance_id });
+                        if let Some(instance_id) = single_attachment.instance_id {
+                            attachment_list.push(StorageAttachment { instance_id });
+                        }
+                        attached_instances = Some(attachment_list);
                     }
-                    attached_instances = Some(attachment_list);
                 }
-            }
 
-            let disk = CloudResource {
-                provider: CloudProvider::AWS,
-                id: volume_id.into(),
-                location: location.clone(),
-                resource_details: ResourceDetails::BlockStorage {
+                let resource_details = ResourceDetails::BlockStorage {
                     storage_type: volume_type,
                     usage: Some(usage),
                     attached_instances,
-                },
-                tags: Self::cloud_resource_tags_from_aws_tags(volume.tags()),
-            };
-            resources.push(disk);
+                };
+                let tags = Self::cloud_resource_tags_from_aws_tags(volume.tags());
+                let disk = CloudResource {
+                    provider: CloudProvider::AWS,
+                    account_id: None,
+                    id: volume_id.into(),
+                    location: location.clone(),
+                    relationships: ResourceRelationship::derive(&resource_details, &tags),
+                    resource_details,
+                    tags,
+                };
+                if filter.matches(&disk.tags) {
+                    page.push(disk);
+                } else {
+                    debug!("Filtered volume (tags do not match): {:?}", disk.id);
+                }
+            }
+            pages
+                .send(page)
+                .await
+                .context("The pages of volumes are no longer processed")?;
+
+            next_token = token;
+            if next_token.is_none() {
+                break;
+            }
         }
+        Ok(())
+    }
 
-        Ok(resources)
+    /// Sends the resources of the region whose tags match passed tags, page by page as soon as each page is listed (so that the impacts of a page can be estimated while the next pages are listed)
+    ///
+    /// Listing stops with an error when the receiver of the pages is dropped.
+    pub async fn list_resource_pages(
+        &self,
+        tags: &[String],
+        include_block_storage: bool,
+        pages: Sender<Vec<CloudResource>>,
+    ) -> Result<()> {
+        let start = Instant::now();
+        let list = async {
+            if !self.selection.skip_instances {
+                self.send_instance_pages(tags, &pages).await?;
+            }
+            if include_block_storage {
+                self.send_volume_pages(tags, &pages).await?;
+            }
+            Ok::<_, anyhow::Error>(())
+        };
+        list.instrument(tracing::info_span!(
+            "inventory",
+            cloud.region = %self.aws_region,
+        ))
+        .await?;
+        crate::server_telemetry::record_scan_stage(
+            ScanStage::Inventory,
+            Some(&self.aws_region),
+            start.elapsed(),
+        );
+        Ok(())
+    }
+}
+
+/// The kinds of resources that can be selected by the inventory (volumes being block storage)
+pub const RESOURCE_KINDS: [&str; 2] = ["instances", "volumes"];
+
+/// Selection of the resources listed by the inventory, in addition to the filters on tags (the default selection lists all instances)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResourceSelection {
+    /// Does not list instances, like when only volumes are selected
+    pub skip_instances: bool,
+    /// States of the listed instances (see [INSTANCE_STATES]), all states when empty
+    pub instance_states: Vec<String>,
+}
+
+impl ResourceSelection {
+    /// Returns the selection of the kinds of resources (see [RESOURCE_KINDS]) and states of instances, all kinds being listed when none is selected, with true when volumes are selected
+    pub fn of_kinds(resource_kinds: &[String], instance_states: &[String]) -> (Self, bool) {
+        let selects = |kind: &str| resource_kinds.iter().any(|k| k == kind);
+        let selection = ResourceSelection {
+            skip_instances: !resource_kinds.is_empty() && !selects("instances"),
+            instance_states: instance_states.to_vec(),
+        };
+        (selection, selects("volumes"))
     }
 }
 
+/// The states of EC2 instances, that can be selected by the inventory
+pub const INSTANCE_STATES: [&str; 6] = [
+    "pending",
+    "running",
+    "shutting-down",
+    "terminated",
+    "stopping",
+    "stopped",
+];
+
 #[async_trait]
 impl Inventoriable for AwsCloudProvider {
     /// List resources whose tags match passed tags
@@ -352,14 +933,17 @@ impl Inventoriable for AwsCloudProvider {
     ) -> Result<Inventory> {
         let start = Instant::now();
 
-        let mut resources: Vec<CloudResource> = Vec::new();
-
-        let mut instances = self.clone().get_instances_with_usage_data(tags).await?;
-        resources.append(&mut instances);
-        if include_block_storage {
-            let mut volumes = self.clone().get_volumes_with_usage_data(tags).await?;
-            resources.append(&mut volumes);
-        }
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+        let listing = self.list_resource_pages(tags, include_block_storage, sender);
+        let collect = async {
+            let mut resources: Vec<CloudResource> = Vec::new();
+            while let Some(mut page) = receiver.recv().await {
+                resources.append(&mut page);
+            }
+            resources
+        };
+        let (listed, resources) = tokio::join!(listing, collect);
+        listed?;
         let stats = ExecutionStatistics {
             inventory_duration: start.elapsed(),
             impact_estimation_duration: std::time::Duration::from_millis(0),
@@ -382,16 +966,195 @@ mod tests {
 
     static RUNNING_INSTANCE_ID: &str = "i-03c8f84a6318a8186";
 
+    #[test]
+    fn selection_of_resource_kinds() {
+        assert_eq!(
+            (ResourceSelection::default(), false),
+            ResourceSelection::of_kinds(&[], &[])
+        );
+        let (selection, volumes) = ResourceSelection::of_kinds(&["volumes".to_string()], &[]);
+        assert!(selection.skip_instances && volumes);
+        let kinds = vec!["instances".to_string(), "volumes".to_string()];
+        let (selection, volumes) = ResourceSelection::of_kinds(&kinds, &["running".to_string()]);
+        assert!(!selection.skip_instances && volumes);
+        assert_eq!(vec!["running"], selection.instance_states);
+    }
+
+    #[test]
+    fn select_accounts_of_the_allow_list() {
+        let allowed = vec![
+            AwsAccount {
+                account_id: "111111111111".to_string(),
+                role_arn: "arn:aws:iam::111111111111:role/cloud-scanner".to_string(),
+                external_id: None,
+            },
+            AwsAccount {
+                account_id: "222222222222".to_string(),
+                role_arn: "arn:aws:iam::222222222222:role/cloud-scanner".to_string(),
+                external_id: Some("scanner".to_string()),
+            },
+        ];
+        assert_eq!(None, AwsAccount::select(&allowed, None, None).unwrap());
+        assert_eq!(
+            Some(&allowed[1]),
+            AwsAccount::select(&allowed, Some("222222222222"), None).unwrap()
+        );
+        assert_eq!(
+            Some(&allowed[0]),
+            AwsAccount::select(
+                &allowed,
+                None,
+                Some("arn:aws:iam::111111111111:role/cloud-scanner")
+            )
+            .unwrap()
+        );
+        assert!(AwsAccount::select(&allowed, Some("333333333333"), None).is_err());
+        assert!(AwsAccount::select(
+            &allowed,
+            Some("111111111111"),
+            Some("arn:aws:iam::222222222222:role/cloud-scanner")
+        )
+        .is_err());
+        assert!(AwsAccount::select(&[], Some("111111111111"), None).is_err());
+    }
+
+    #[test]
+    fn accounts_are_parsed_from_role_arns() {
+        assert_eq!(
+            AwsAccount {
+                account_id: "111111111111".to_string(),
+                role_arn: "arn:aws:iam::111111111111:role/path/cloud-scanner".to_string(),
+                external_id: None,
+            },
+            AwsAccount::from_role_arn("arn:aws:iam::111111111111:role/path/cloud-scanner").unwrap()
+        );
+        for invalid in [
+            "",
+            "111111111111",
+            "arn:aws:iam::1111:role/cloud-scanner",
+            "arn:aws:iam::111111111111:user/cloud-scanner",
+            "arn:aws:s3:::bucket",
+        ] {
+            assert!(AwsAccount::from_role_arn(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn accounts_of_an_organization_use_the_role_name() {
+        assert_eq!(
+            "arn:aws-cn:iam::111111111111:role/cloud-scanner",
+            AwsAccount::in_organization(
+                "111111111111",
+                Some("arn:aws-cn:organizations::999999999999:account/o-abc/111111111111"),
+                "cloud-scanner"
+            )
+            .role_arn
+        );
+        assert_eq!(
+            "arn:aws:iam::111111111111:role/cloud-scanner",
+            AwsAccount::in_organization("111111111111", None, "cloud-scanner").role_arn
+        );
+    }
+
+    /// Serves a response to DescribeInstances listing one running instance, returning the URL of this fake EC2 API
+    async fn fake_ec2_api() -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        const DESCRIBE_INSTANCES: &str = r#"<DescribeInstancesResponse xmlns="http://ec2.amazonaws.com/doc/2016-11-15/">
+<requestId>1</requestId>
+<reservationSet><item><reservationId>r-1</reservationId><instancesSet><item>
+<instanceId>i-1</instanceId><instanceType>t3.micro</instanceType>
+<instanceState><code>16</code><name>running</name></instanceState>
+</item></instancesSet></item></reservationSet>
+</DescribeInstancesResponse>"#;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                // Reads the headers and body of the request before responding
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+                loop {
+                    let read = socket.read(&mut buffer).await.unwrap_or(0);
+                    request.extend_from_slice(&buffer[..read]);
+                    let text = String::from_utf8_lossy(&request).to_lowercase();
+                    let complete = text.split_once("\r\n\r\n").is_some_and(|(headers, body)| {
+                        headers
+                            .lines()
+                            .find_map(|line| line.strip_prefix("content-length:"))
+                            .and_then(|length| length.trim().parse::<usize>().ok())
+                            .is_none_or(|length| body.len() >= length)
+                    });
+                    if read == 0 || complete {
+                        break;
+                    }
+                }
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: text/xml\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    DESCRIBE_INSTANCES.len(),
+                    DESCRIBE_INSTANCES
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn scans_that_spend_their_budget_of_api_calls_are_partial() {
+        let shared_config = SdkConfig::builder()
+            .behavior_version(aws_config::BehaviorVersion::latest())
+            .region(Region::new("eu-west-1"))
+            .credentials_provider(aws_types::sdk_config::SharedCredentialsProvider::new(
+                aws_sdk_ec2::config::Credentials::new("AKIDTEST", "secret", None, None, "test"),
+            ))
+            .endpoint_url(fake_ec2_api().await)
+            .build();
+        let aws = AwsCloudProvider {
+            aws_region: "eu-west-1".to_string(),
+            account_id: String::new(),
+            progress_label: "eu-west-1".to_string(),
+            selection: ResourceSelection::default(),
+            ec2_client: aws_sdk_ec2::Client::new(&shared_config),
+            cloudwatch_client: aws_sdk_cloudwatch::Client::new(&shared_config),
+        };
+
+        // The only call lists the instances, their CPU loads and the volumes are not read
+        let (inventory, errors) = crate::aws_settings::with_max_api_calls(
+            Some(1),
+            crate::scan_errors::collect(aws.list_resources(&[], true)),
+        )
+        .await;
+        let resources = inventory.unwrap().resources;
+        assert_eq!(1, resources.len());
+        assert_eq!("i-1", resources[0].id);
+        let phases: Vec<ScanPhase> = errors.iter().map(|error| error.phase).collect();
+        assert_eq!(
+            vec![ScanPhase::UtilizationMetrics, ScanPhase::Inventory],
+            phases
+        );
+        assert!(errors.iter().all(|error| error.budget_exhausted));
+        assert!(errors[1].message.contains("not listing the next volumes"));
+
+        // Scans within their budget are not cut off (CloudWatch is not faked)
+        let (_, errors) = crate::aws_settings::with_max_api_calls(
+            Some(10),
+            crate::scan_errors::collect(aws.list_resources(&[], false)),
+        )
+        .await;
+        assert!(errors.iter().all(|error| !error.budget_exhausted));
+    }
+
     #[tokio::test]
     #[ignore]
     async fn inventory_should_return_correct_number_of_instances() {
         let aws: AwsCloudProvider = AwsCloudProvider::new("eu-west-1").await;
         let filtertags: Vec<String> = Vec::new();
         let res: Vec<CloudResource> = aws
-            .get_instances_with_usage_data(&filtertags)
+            .list_resources(&filtertags, false)
             .await
             .context("Failed to list")
-            .unwrap();
+            .unwrap()
+            .resources;
         assert_eq!(4, res.len());
 
         let inst = res.first().unwrap();
@@ -421,12 +1184,12 @@ mod tests {
     async fn get_cpu_usage_metrics_of_running_instance_should_return_right_number_of_data_points() {
         let aws: AwsCloudProvider = AwsCloudProvider::new("eu-west-1").await;
         let res = aws
-            .get_average_cpu_usage_of_last_10_minutes(&RUNNING_INSTANCE_ID)
+            .get_cpu_utilization_of_last_10_minutes(&[RUNNING_INSTANCE_ID.to_string()])
             .await
             .unwrap();
-        let datapoints = res.datapoints.unwrap();
+        let datapoints = res.get(RUNNING_INSTANCE_ID).cloned().unwrap_or_default();
         assert!(
-            0 < datapoints.len() && datapoints.len() < 3,
+            !datapoints.is_empty() && datapoints.len() < 3,
             "Strange number of datapoint returned for instance {}, is it really up ?. I was expecting 1 or 2  but got {} .\n {:#?}",
             &RUNNING_INSTANCE_ID,
             datapoints.len(),
@@ -440,10 +1203,10 @@ mod tests {
         let aws: AwsCloudProvider = AwsCloudProvider::new("eu-west-1").await;
         let instance_id = "i-03e0b3b1246001382";
         let res = aws
-            .get_average_cpu_usage_of_last_10_minutes(instance_id)
+            .get_cpu_utilization_of_last_10_minutes(&[instance_id.to_string()])
             .await
             .unwrap();
-        let datapoints = res.datapoints.unwrap();
+        let datapoints = res.get(instance_id).cloned().unwrap_or_default();
         assert_eq!(0, datapoints.len(), "Wrong number of datapoint returned");
     }
 
@@ -453,10 +1216,10 @@ mod tests {
         let aws: AwsCloudProvider = AwsCloudProvider::new("eu-west-1").await;
         let instance_id = "IDONOTEXISTS";
         let res = aws
-            .get_average_cpu_usage_of_last_10_minutes(instance_id)
+            .get_cpu_utilization_of_last_10_minutes(&[instance_id.to_string()])
             .await
             .unwrap();
-        let datapoints = res.datapoints.unwrap();
+        let datapoints = res.get(instance_id).cloned().unwrap_or_default();
         assert_eq!(0, datapoints.len());
     }
 
@@ -466,7 +1229,10 @@ mod tests {
         // This instance  needs to be running for the test to pass
         let aws: AwsCloudProvider = AwsCloudProvider::new("eu-west-1").await;
 
-        let avg_cpu_load = aws.get_average_cpu(&RUNNING_INSTANCE_ID).await.unwrap();
+        let avg_cpu_load = aws
+            .get_average_cpu_of_instances(&[RUNNING_INSTANCE_ID.to_string()])
+            .await
+            .unwrap()[RUNNING_INSTANCE_ID];
         assert_ne!(
             0 as f64, avg_cpu_load,
             "CPU load of instance {} is zero, is it really running ?",
@@ -474,7 +1240,7 @@ mod tests {
         );
         println!("{:#?}", avg_cpu_load);
         assert!((0 as f64) < avg_cpu_load);
-        assert!((100 as f64) > avg_cpu_load);
+        assert!(100_f64 > avg_cpu_load);
     }
 
     #[tokio::test]
@@ -482,7 +1248,10 @@ mod tests {
     async fn test_average_cpu_load_of_non_existing_instance_is_zero() {
         let instance_id = "IDONOTEXISTS";
         let aws: AwsCloudProvider = AwsCloudProvider::new("eu-west-1").await;
-        let res = aws.get_average_cpu(instance_id).await.unwrap();
+        let res = aws
+            .get_average_cpu_of_instances(&[instance_id.to_string()])
+            .await
+            .unwrap()[instance_id];
         assert_eq!(0 as f64, res);
     }
 
@@ -491,16 +1260,25 @@ mod tests {
     async fn test_average_cpu_load_of_shutdown_instance_is_zero() {
         let aws: AwsCloudProvider = AwsCloudProvider::new("eu-west-1").await;
         let instance_id = "i-03e0b3b1246001382";
-        let res = aws.get_average_cpu(instance_id).await.unwrap();
+        let res = aws
+            .get_average_cpu_of_instances(&[instance_id.to_string()])
+            .await
+            .unwrap()[instance_id];
         assert_eq!(0 as f64, res);
     }
 
     #[tokio::test]
     #[ignore]
     async fn returns_the_right_number_of_volumes() {
-        let aws: AwsCloudProvider = AwsCloudProvider::new("eu-west-1").await;
+        let aws: AwsCloudProvider =
+            AwsCloudProvider::new("eu-west-1")
+                .await
+                .with_selection(&ResourceSelection {
+                    skip_instances: true,
+                    ..Default::default()
+                });
         let filtertags: Vec<String> = Vec::new();
-        let res = aws.list_volumes(&filtertags).await.unwrap();
-        assert_eq!(4, res.len());
+        let res = aws.list_resources(&filtertags, true).await.unwrap();
+        assert_eq!(4, res.resources.len());
     }
 }
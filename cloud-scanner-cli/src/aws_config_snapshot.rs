@@ -0,0 +1,232 @@
+//! Inventories of the configuration snapshots of AWS Config (the json files delivered to the S3 bucket of its delivery channel, gzipped or not), so that organizations centralizing their Config data estimate impacts without granting describe permissions to the scanner.
+//!
+//! The configuration items of instances (`AWS::EC2::Instance`) and volumes (`AWS::EC2::Volume`) are inventoried with their account, region and tags, deleted resources being ignored. CPU loads are not part of the snapshots: instances are estimated with the default load of Boavizta API.
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io::Read;
+
+use crate::aws_cloud_provider::ResourceSelection;
+use crate::model::{
+    CloudProvider, CloudResource, CloudResourceTag, Inventory, ResourceDetails, StorageAttachment,
+    StorageUsage,
+};
+use crate::tag_filter::TagFilter;
+use crate::tag_normalization;
+use crate::usage_location::UsageLocation;
+
+/// Statuses of the configuration items of resources that no longer exist
+const DELETED_STATUSES: [&str; 2] = ["ResourceDeleted", "ResourceDeletedNotRecorded"];
+
+/// Parses the instances (unless skipped by the selection, and in the selected states), and with `include_block_storage` the volumes of a configuration snapshot
+pub fn parse_config_snapshot(
+    content: &str,
+    tags: &[String],
+    include_block_storage: bool,
+    selection: &ResourceSelection,
+) -> Result<Inventory> {
+    let json: Value =
+        serde_json::from_str(content).context("Expected a configuration snapshot of AWS Config")?;
+    let items = json["configurationItems"]
+        .as_array()
+        .context("Expected a configuration snapshot of AWS Config (no configurationItems)")?;
+    let filter = TagFilter::parse_all(tags)?;
+
+    let mut resources = Vec::new();
+    for item in items {
+        let status = item["configurationItemStatus"].as_str().unwrap_or_default();
+        let Some(id) = item["resourceId"].as_str() else {
+            continue;
+        };
+        if DELETED_STATUSES.contains(&status) {
+            continue;
+        }
+        let configuration = &item["configuration"];
+        let resource_details = match item["resourceType"].as_str() {
+            Some("AWS::EC2::Instance") if !selection.skip_instances => {
+                let state = configuration["state"]["name"].as_str().unwrap_or_default();
+                if !selection.instance_states.is_empty()
+                    && !selection.instance_states.iter().any(|s| s == state)
+                {
+                    debug!("Filtered instance {} (in state {})", id, state);
+                    continue;
+                }
+                let Some(instance_type) = configuration["instanceType"].as_str() else {
+                    warn!("Ignoring instance {} (no instance type)", id);
+                    continue;
+                };
+                ResourceDetails::Instance {
+                    instance_type: instance_type.to_string(),
+                    usage: None,
+                }
+            }
+            Some("AWS::EC2::Volume") if include_block_storage => {
+                let Some(size_gb) = configuration["size"].as_i64() else {
+                    warn!("Ignoring volume {} (no size)", id);
+                    continue;
+                };
+                let attached_instances: Vec<StorageAttachment> = configuration["attachments"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|attachment| attachment["instanceId"].as_str())
+                    .map(|instance_id| StorageAttachment {
+                        instance_id: instance_id.to_string(),
+                    })
+                    .collect();
+                ResourceDetails::BlockStorage {
+                    storage_type: configuration["volumeType"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    usage: Some(StorageUsage {
+                        size_gb: size_gb as i32,
+                        usage_duration_seconds: 3600,
+                    }),
+                    attached_instances: (!attached_instances.is_empty())
+                        .then_some(attached_instances),
+                }
+            }
+            _ => continue,
+        };
+        let aws_region = item["awsRegion"].as_str().unwrap_or_default();
+        let resource = CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: item["awsAccountId"].as_str().map(str::to_string),
+            id: id.to_string(),
+            location: UsageLocation::try_from(aws_region)
+                .with_context(|| format!("Unsupported region of {}", id))?,
+            resource_details,
+            tags: tag_normalization::normalize(item_tags(item)),
+            relationships: Vec::new(),
+        };
+        if filter.matches(&resource.tags) {
+            resources.push(resource);
+        } else {
+            debug!("Filtered {} (tags do not match)", id);
+        }
+    }
+    Ok(Inventory {
+        resources,
+        execution_statistics: None,
+    })
+}
+
+/// Reads configuration snapshots (json files, gzipped when their name ends with `.gz`), the resources found in several snapshots being inventoried once, see [parse_config_snapshot]
+pub fn read_config_snapshots(
+    paths: &[String],
+    tags: &[String],
+    include_block_storage: bool,
+    selection: &ResourceSelection,
+) -> Result<Inventory> {
+    let mut inventories = Vec::new();
+    for path in paths {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Cannot read Config snapshot {}", path))?;
+        let mut content = String::new();
+        if path.ends_with(".gz") {
+            flate2::read::MultiGzDecoder::new(bytes.as_slice())
+                .read_to_string(&mut content)
+                .with_context(|| format!("Cannot decompress Config snapshot {}", path))?;
+        } else {
+            content = String::from_utf8(bytes)
+                .with_context(|| format!("Cannot read Config snapshot {}", path))?;
+        }
+        let inventory = parse_config_snapshot(&content, tags, include_block_storage, selection)
+            .with_context(|| format!("Cannot parse Config snapshot {}", path))?;
+        inventories.push(inventory);
+    }
+    let mut seen = HashSet::new();
+    let mut inventory = Inventory::combine(inventories);
+    inventory
+        .resources
+        .retain(|resource| seen.insert((resource.account_id.clone(), resource.id.clone())));
+    Ok(inventory)
+}
+
+/// Returns the tags of a configuration item (a map of their keys to their values)
+fn item_tags(item: &Value) -> Vec<CloudResourceTag> {
+    item["tags"]
+        .as_object()
+        .map(|tags| {
+            tags.iter()
+                .map(|(key, value)| CloudResourceTag {
+                    key: key.clone(),
+                    value: value.as_str().map(str::to_string),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SNAPSHOT: &str = r#"{
+        "fileVersion": "1.0",
+        "configSnapshotId": "1b2c3d4e-5f6a-7b8c-9d0e-1f2a3b4c5d6e",
+        "configurationItems": [
+            {"configurationItemStatus": "OK", "awsAccountId": "111111111111", "awsRegion": "eu-west-3",
+             "resourceType": "AWS::EC2::Instance", "resourceId": "i-0123456789abcdef0", "tags": {"team": "web"},
+             "configuration": {"instanceType": "m6g.xlarge", "state": {"code": 16, "name": "running"}}},
+            {"configurationItemStatus": "OK", "awsAccountId": "111111111111", "awsRegion": "eu-west-3",
+             "resourceType": "AWS::EC2::Instance", "resourceId": "i-0fedcba9876543210", "tags": {},
+             "configuration": {"instanceType": "t3.micro", "state": {"code": 80, "name": "stopped"}}},
+            {"configurationItemStatus": "ResourceDeleted", "awsAccountId": "111111111111", "awsRegion": "eu-west-3",
+             "resourceType": "AWS::EC2::Instance", "resourceId": "i-0aaaaaaaaaaaaaaaa", "configuration": null},
+            {"configurationItemStatus": "ResourceDiscovered", "awsAccountId": "222222222222", "awsRegion": "eu-west-1",
+             "resourceType": "AWS::EC2::Volume", "resourceId": "vol-0123456789abcdef0", "tags": {"team": "web"},
+             "configuration": {"size": 100, "volumeType": "gp3", "attachments": [{"instanceId": "i-0123456789abcdef0"}]}},
+            {"configurationItemStatus": "OK", "awsAccountId": "111111111111", "awsRegion": "eu-west-3",
+             "resourceType": "AWS::S3::Bucket", "resourceId": "logs", "configuration": {}}
+        ]
+    }"#;
+
+    #[test]
+    fn snapshots_are_inventoried() {
+        let all = ResourceSelection::default();
+        let inventory = parse_config_snapshot(SNAPSHOT, &[], false, &all).unwrap();
+        let ids: Vec<&str> = inventory.resources.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(vec!["i-0123456789abcdef0", "i-0fedcba9876543210"], ids);
+        assert_eq!(
+            Some("111111111111".to_string()),
+            inventory.resources[0].account_id
+        );
+        assert_eq!("eu-west-3", inventory.resources[0].location.aws_region);
+
+        let (running, _) = ResourceSelection::of_kinds(&[], &["running".to_string()]);
+        let inventory =
+            parse_config_snapshot(SNAPSHOT, &["team=web".to_string()], true, &running).unwrap();
+        let ids: Vec<&str> = inventory.resources.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(vec!["i-0123456789abcdef0", "vol-0123456789abcdef0"], ids);
+        assert_eq!("eu-west-1", inventory.resources[1].location.aws_region);
+        assert_eq!(
+            Some("gp3".to_string()),
+            inventory.resources[1].resource_details.resource_type()
+        );
+
+        assert!(parse_config_snapshot(r#"{"items": []}"#, &[], false, &all).is_err());
+    }
+
+    #[test]
+    fn resources_of_several_snapshots_are_inventoried_once() {
+        let dir = std::env::temp_dir().join("cloud-scanner-config-snapshots");
+        std::fs::create_dir_all(&dir).unwrap();
+        let plain = dir.join("snapshot.json");
+        std::fs::write(&plain, SNAPSHOT).unwrap();
+        let gzipped = dir.join("snapshot.json.gz");
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, SNAPSHOT.as_bytes()).unwrap();
+        std::fs::write(&gzipped, encoder.finish().unwrap()).unwrap();
+
+        let paths = vec![
+            plain.to_string_lossy().to_string(),
+            gzipped.to_string_lossy().to_string(),
+        ];
+        let inventory =
+            read_config_snapshots(&paths, &[], true, &ResourceSelection::default()).unwrap();
+        assert_eq!(3, inventory.resources.len());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+//! Settings of the AWS SDK (retries and timeouts) and budget of calls to AWS APIs, set once for the process (with the `[aws]` section of the configuration file), so that scans behave predictably when AWS APIs throttle them.
+//!
+//! Throttled or failed calls are retried by the SDK (`standard` retries, or `adaptive` retries that also slow down the client when throttled), at most `max_attempts` times each, and every call and attempt can be bounded by timeouts.
+//!
+//! With `max_api_calls`, a scan (of all its accounts and regions) makes at most this number of calls to the AWS APIs that list resources (DescribeInstances, DescribeVolumes) and read their utilization (GetMetricData). When the budget is spent, the scan stops listing the next pages and reading CPU loads (the load of the instances not read is 0): its results are partial instead of the scan failing or lasting unpredictably.
+//!
+//! With `max_api_calls_per_second`, the calls of each account (of all its regions scanned concurrently) are paced at this rate, so that scanning many regions of an account at once stays below its API rate limits.
+use aws_config::retry::RetryConfig;
+use aws_config::timeout::TimeoutConfig;
+use aws_config::ConfigLoader;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Retry strategy of the SDK
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RetryMode {
+    /// Retries with exponential backoff
+    #[default]
+    Standard,
+    /// Standard retries, with client side rate limiting when throttled
+    Adaptive,
+}
+
+/// Settings of the AWS SDK and budget of calls of the scans
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AwsSettings {
+    /// Retry strategy of the SDK (`standard` by default)
+    #[serde(default)]
+    pub retry_mode: RetryMode,
+    /// Maximum number of attempts of each call, including the first one (3 by default)
+    pub max_attempts: Option<u32>,
+    /// Seconds to establish a connection to an AWS API
+    pub connect_timeout_seconds: Option<u64>,
+    /// Seconds of each attempt of a call
+    pub operation_attempt_timeout_seconds: Option<u64>,
+    /// Seconds of each call, retries included
+    pub operation_timeout_seconds: Option<u64>,
+    /// Maximum number of calls to AWS APIs of each scan, whose results are partial when it is reached (no limit by default)
+    pub max_api_calls: Option<usize>,
+    /// Maximum number of calls to AWS APIs per second of each account (no limit by default)
+    pub max_api_calls_per_second: Option<u32>,
+}
+
+impl AwsSettings {
+    /// Returns the retry configuration of the SDK
+    fn retry_config(&self) -> RetryConfig {
+        let retry_config = match self.retry_mode {
+            RetryMode::Standard => RetryConfig::standard(),
+            RetryMode::Adaptive => RetryConfig::adaptive(),
+        };
+        match self.max_attempts {
+            Some(max_attempts) => retry_config.with_max_attempts(max_attempts),
+            None => retry_config,
+        }
+    }
+
+    /// Returns the timeouts of the SDK (none unless set)
+    fn timeout_config(&self) -> TimeoutConfig {
+        let mut builder = TimeoutConfig::builder();
+        if let Some(seconds) = self.connect_timeout_seconds {
+            builder = builder.connect_timeout(Duration::from_secs(seconds));
+        }
+        if let Some(seconds) = self.operation_attempt_timeout_seconds {
+            builder = builder.operation_attempt_timeout(Duration::from_secs(seconds));
+        }
+        if let Some(seconds) = self.operation_timeout_seconds {
+            builder = builder.operation_timeout(Duration::from_secs(seconds));
+        }
+        builder.build()
+    }
+}
+
+static SETTINGS: OnceLock<AwsSettings> = OnceLock::new();
+
+/// Sets the settings of the AWS SDK and the budget of calls of the scans (only the first settings are kept)
+pub fn configure(settings: AwsSettings) {
+    if SETTINGS.set(settings).is_err() {
+        warn!("AWS settings are already configured, ignoring new settings");
+    }
+}
+
+fn settings() -> &'static AwsSettings {
+    SETTINGS.get_or_init(AwsSettings::default)
+}
+
+/// Returns a loader of the SDK configuration of the environment, with the retries and timeouts of the settings
+pub(crate) fn config_loader() -> ConfigLoader {
+    let settings = settings();
+    aws_config::from_env()
+        .retry_config(settings.retry_config())
+        .timeout_config(settings.timeout_config())
+}
+
+/// Calls to AWS APIs that a scan can still make
+#[derive(Debug)]
+struct ApiBudget {
+    remaining: AtomicUsize,
+    exhausted: AtomicBool,
+}
+
+impl ApiBudget {
+    fn new(max_api_calls: usize) -> Self {
+        ApiBudget {
+            remaining: AtomicUsize::new(max_api_calls),
+            exhausted: AtomicBool::new(false),
+        }
+    }
+
+    /// Spends a call, returning false when none remains
+    fn spend(&self) -> bool {
+        let spent = self
+            .remaining
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| {
+                remaining.checked_sub(1)
+            })
+            .is_ok();
+        if !spent {
+            self.exhausted.store(true, Ordering::Relaxed);
+        }
+        spent
+    }
+}
+
+/// Pacing of the calls to the AWS APIs of an account
+#[derive(Debug)]
+struct RateLimiter {
+    interval: Duration,
+    next_call: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(calls_per_second: u32) -> Self {
+        RateLimiter {
+            interval: Duration::from_secs(1) / calls_per_second.max(1),
+            next_call: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Reserves the next slot of a call, returning how long to wait for it
+    fn reserve(&self) -> Duration {
+        let mut next_call = self.next_call.lock().unwrap();
+        let now = Instant::now();
+        let slot = (*next_call).max(now);
+        *next_call = slot + self.interval;
+        slot - now
+    }
+}
+
+/// Pacing of the calls of each account, by account id (empty for the account of the environment)
+static RATE_LIMITERS: Mutex<BTreeMap<String, Arc<RateLimiter>>> = Mutex::new(BTreeMap::new());
+
+/// Waits for the next call to an AWS API of an account (empty for the account of the environment) allowed by `max_api_calls_per_second` of the settings (if any)
+pub(crate) async fn pace_api_call(account_id: &str) {
+    let Some(calls_per_second) = settings().max_api_calls_per_second else {
+        return;
+    };
+    let limiter = RATE_LIMITERS
+        .lock()
+        .unwrap()
+        .entry(account_id.to_string())
+        .or_insert_with(|| Arc::new(RateLimiter::new(calls_per_second)))
+        .clone();
+    let wait = limiter.reserve();
+    if !wait.is_zero() {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+tokio::task_local! {
+    static API_BUDGET: Arc<ApiBudget>;
+}
+
+/// Runs a scan with the budget of calls of the settings (if any), or with the budget of the scan it is part of
+pub(crate) async fn with_api_budget<F: Future>(future: F) -> F::Output {
+    with_max_api_calls(settings().max_api_calls, future).await
+}
+
+/// Runs a scan with a budget of `max_api_calls` calls (if any), or with the budget of the scan it is part of
+pub(crate) async fn with_max_api_calls<F: Future>(
+    max_api_calls: Option<usize>,
+    future: F,
+) -> F::Output {
+    let Some(max_api_calls) = max_api_calls else {
+        return future.await;
+    };
+    if API_BUDGET.try_with(|_| ()).is_ok() {
+        return future.await;
+    }
+    let budget = Arc::new(ApiBudget::new(max_api_calls));
+    let output = API_BUDGET.scope(budget.clone(), future).await;
+    if budget.exhausted.load(Ordering::Relaxed) {
+        warn!(
+            "The scan reached its budget of {} AWS API calls, its results are partial",
+            max_api_calls
+        );
+    }
+    output
+}
+
+/// Spends a call to an AWS API of the budget of the current scan, returning false (and the call should not be made) when the budget is spent
+pub(crate) fn spend_api_call(operation: &str) -> bool {
+    let spent = API_BUDGET.try_with(|budget| budget.spend()).unwrap_or(true);
+    if !spent {
+        debug!(
+            "Skipping {}, the budget of AWS API calls is spent",
+            operation
+        );
+    }
+    spent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_are_parsed_and_budget_is_spent() {
+        let settings: AwsSettings = toml::from_str(
+            r#"
+retry_mode = "adaptive"
+max_attempts = 5
+operation_timeout_seconds = 30
+max_api_calls = 2
+"#,
+        )
+        .unwrap();
+        assert_eq!(RetryMode::Adaptive, settings.retry_mode);
+        assert_eq!(5, settings.retry_config().max_attempts());
+        assert_eq!(
+            Some(Duration::from_secs(30)),
+            settings.timeout_config().operation_timeout()
+        );
+        assert_eq!(None, settings.timeout_config().connect_timeout());
+        assert!(toml::from_str::<AwsSettings>("retry_mode = \"legacy\"").is_err());
+
+        let budget = ApiBudget::new(2);
+        assert!(budget.spend());
+        assert!(budget.spend());
+        assert!(!budget.exhausted.load(Ordering::Relaxed));
+        assert!(!budget.spend());
+        assert!(budget.exhausted.load(Ordering::Relaxed));
+        // Calls are not limited outside of a scan with a budget
+        assert!(spend_api_call("DescribeInstances"));
+
+        // Calls are paced, those not waiting being the first ones
+        let limiter = RateLimiter::new(10);
+        assert!(limiter.reserve().is_zero());
+        assert!(limiter.reserve() > Duration::from_millis(90));
+        assert!(limiter.reserve() > Duration::from_millis(190));
+    }
+}
@@ -0,0 +1,226 @@
+//! Inventories of Azure Resource Graph query exports, so that Azure users estimate the impacts of their virtual machines and managed disks without a live Azure provider.
+//!
+//! Exports are the json output of `az graph query` (an object with a `data` array, or an array of rows) of the `Resources` table:
+//!
+//! ```sh
+//! az graph query -q "Resources | where type in~ ('microsoft.compute/virtualmachines', 'microsoft.compute/disks')" --first 1000 -o json > resources.json
+//! ```
+//!
+//! Virtual machines are inventoried with their size (`properties.hardwareProfile.vmSize`) and their power state when the export has it (`properties.extended.instanceView.powerState.code`), disks with their size (`properties.diskSizeGB`), sku and the virtual machine they are attached to (`managedBy`). Resources are identified by their Azure resource ids, in lower case (Azure ids are case insensitive), and their account is their subscription. CPU loads are not part of the exports: virtual machines are estimated with the default load of Boavizta API.
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::HashSet;
+
+use crate::aws_cloud_provider::ResourceSelection;
+use crate::model::{
+    CloudProvider, CloudResource, CloudResourceTag, Inventory, ResourceDetails,
+    ResourceRelationship, StorageAttachment, StorageUsage,
+};
+use crate::tag_filter::TagFilter;
+use crate::tag_normalization;
+use crate::usage_location::UsageLocation;
+
+const VIRTUAL_MACHINE_TYPE: &str = "microsoft.compute/virtualmachines";
+const DISK_TYPE: &str = "microsoft.compute/disks";
+
+/// Returns the state of an instance (see [crate::aws_cloud_provider::INSTANCE_STATES]) of the power state of a virtual machine (like `PowerState/deallocated`)
+fn instance_state(power_state: &str) -> &'static str {
+    match power_state.trim_start_matches("PowerState/") {
+        "starting" => "pending",
+        "running" => "running",
+        "stopping" | "deallocating" => "stopping",
+        "stopped" | "deallocated" => "stopped",
+        _ => "",
+    }
+}
+
+/// Parses the virtual machines (unless skipped by the selection, and in the selected states), and with `include_block_storage` the managed disks of a Resource Graph export
+pub fn parse_resource_graph_export(
+    content: &str,
+    tags: &[String],
+    include_block_storage: bool,
+    selection: &ResourceSelection,
+) -> Result<Inventory> {
+    let json: Value =
+        serde_json::from_str(content).context("Expected a json export of Azure Resource Graph")?;
+    let rows = json
+        .as_array()
+        .or_else(|| json["data"].as_array())
+        .context("Expected a json export of Azure Resource Graph (no data)")?;
+    let filter = TagFilter::parse_all(tags)?;
+
+    let mut resources = Vec::new();
+    for row in rows {
+        let Some(id) = row["id"].as_str().map(str::to_lowercase) else {
+            continue;
+        };
+        let properties = &row["properties"];
+        let resource_type = row["type"].as_str().unwrap_or_default().to_lowercase();
+        let resource_details = match resource_type.as_str() {
+            VIRTUAL_MACHINE_TYPE if !selection.skip_instances => {
+                let power_state = properties["extended"]["instanceView"]["powerState"]["code"]
+                    .as_str()
+                    .unwrap_or_default();
+                let state = instance_state(power_state);
+                if !selection.instance_states.is_empty()
+                    && !selection.instance_states.iter().any(|s| s == state)
+                {
+                    debug!("Filtered virtual machine {} (in state {})", id, power_state);
+                    continue;
+                }
+                let Some(vm_size) = properties["hardwareProfile"]["vmSize"].as_str() else {
+                    warn!("Ignoring virtual machine {} (no size)", id);
+                    continue;
+                };
+                ResourceDetails::Instance {
+                    instance_type: vm_size.to_string(),
+                    usage: None,
+                }
+            }
+            DISK_TYPE if include_block_storage => {
+                let Some(size_gb) = properties["diskSizeGB"].as_i64() else {
+                    warn!("Ignoring disk {} (no size)", id);
+                    continue;
+                };
+                let attached_instances = row["managedBy"]
+                    .as_str()
+                    .filter(|managed_by| !managed_by.is_empty())
+                    .map(|managed_by| {
+                        vec![StorageAttachment {
+                            instance_id: managed_by.to_lowercase(),
+                        }]
+                    });
+                ResourceDetails::BlockStorage {
+                    storage_type: row["sku"]["name"].as_str().unwrap_or_default().to_string(),
+                    usage: Some(StorageUsage {
+                        size_gb: size_gb as i32,
+                        usage_duration_seconds: 3600,
+                    }),
+                    attached_instances,
+                }
+            }
+            _ => continue,
+        };
+        let region = row["location"].as_str().unwrap_or_default();
+        let tags = tag_normalization::normalize(row_tags(row));
+        let resource = CloudResource {
+            provider: CloudProvider::Azure,
+            account_id: row["subscriptionId"].as_str().map(str::to_string),
+            location: UsageLocation::try_from(region)
+                .with_context(|| format!("Unsupported region of {}", id))?,
+            id,
+            relationships: ResourceRelationship::derive(&resource_details, &tags),
+            resource_details,
+            tags,
+        };
+        if filter.matches(&resource.tags) {
+            resources.push(resource);
+        } else {
+            debug!("Filtered {} (tags do not match)", resource.id);
+        }
+    }
+    Ok(Inventory {
+        resources,
+        execution_statistics: None,
+    })
+}
+
+/// Reads Resource Graph exports, the resources found in several exports being inventoried once, see [parse_resource_graph_export]
+pub fn read_resource_graph_exports(
+    paths: &[String],
+    tags: &[String],
+    include_block_storage: bool,
+    selection: &ResourceSelection,
+) -> Result<Inventory> {
+    let mut inventories = Vec::new();
+    for path in paths {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read Resource Graph export {}", path))?;
+        let inventory =
+            parse_resource_graph_export(&content, tags, include_block_storage, selection)
+                .with_context(|| format!("Cannot parse Resource Graph export {}", path))?;
+        inventories.push(inventory);
+    }
+    let mut seen = HashSet::new();
+    let mut inventory = Inventory::combine(inventories);
+    inventory
+        .resources
+        .retain(|resource| seen.insert(resource.id.clone()));
+    Ok(inventory)
+}
+
+/// Returns the tags of a row (a map of their keys to their values)
+fn row_tags(row: &Value) -> Vec<CloudResourceTag> {
+    row["tags"]
+        .as_object()
+        .map(|tags| {
+            tags.iter()
+                .map(|(key, value)| CloudResourceTag {
+                    key: key.clone(),
+                    value: value.as_str().map(str::to_string),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT: &str = r#"{"count": 4, "data": [
+        {"id": "/subscriptions/sub-1/resourceGroups/WEB/providers/Microsoft.Compute/virtualMachines/web-1",
+         "name": "web-1", "type": "microsoft.compute/virtualmachines", "location": "westeurope",
+         "subscriptionId": "sub-1", "resourceGroup": "web", "tags": {"team": "web"},
+         "properties": {"hardwareProfile": {"vmSize": "Standard_D2s_v3"},
+                        "extended": {"instanceView": {"powerState": {"code": "PowerState/running"}}}}},
+        {"id": "/subscriptions/sub-1/resourceGroups/web/providers/Microsoft.Compute/virtualMachines/batch-1",
+         "name": "batch-1", "type": "microsoft.compute/virtualmachines", "location": "francecentral",
+         "subscriptionId": "sub-1", "tags": null,
+         "properties": {"hardwareProfile": {"vmSize": "Standard_F4s_v2"},
+                        "extended": {"instanceView": {"powerState": {"code": "PowerState/deallocated"}}}}},
+        {"id": "/subscriptions/sub-1/resourceGroups/web/providers/Microsoft.Compute/disks/web-1-os",
+         "name": "web-1-os", "type": "microsoft.compute/disks", "location": "westeurope",
+         "subscriptionId": "sub-1", "sku": {"name": "Premium_LRS"},
+         "managedBy": "/subscriptions/sub-1/resourceGroups/WEB/providers/Microsoft.Compute/virtualMachines/web-1",
+         "tags": {"team": "web"}, "properties": {"diskSizeGB": 128}},
+        {"id": "/subscriptions/sub-1/resourceGroups/web/providers/Microsoft.Storage/storageAccounts/logs",
+         "type": "microsoft.storage/storageaccounts", "location": "westeurope", "properties": {}}
+    ]}"#;
+
+    #[test]
+    fn exports_are_inventoried() {
+        let all = ResourceSelection::default();
+        let inventory = parse_resource_graph_export(EXPORT, &[], false, &all).unwrap();
+        assert_eq!(2, inventory.resources.len());
+        let vm = &inventory.resources[0];
+        assert_eq!(
+            "/subscriptions/sub-1/resourcegroups/web/providers/microsoft.compute/virtualmachines/web-1",
+            vm.id
+        );
+        assert_eq!(Some("sub-1".to_string()), vm.account_id);
+        assert_eq!("NLD", vm.location.iso_country_code);
+        assert_eq!(
+            Some("Standard_D2s_v3".to_string()),
+            vm.resource_details.resource_type()
+        );
+
+        let (running, _) = ResourceSelection::of_kinds(&[], &["running".to_string()]);
+        let inventory =
+            parse_resource_graph_export(EXPORT, &["team=web".to_string()], true, &running).unwrap();
+        assert_eq!(2, inventory.resources.len());
+        let disk = &inventory.resources[1];
+        assert_eq!(
+            Some("Premium_LRS".to_string()),
+            disk.resource_details.resource_type()
+        );
+        assert_eq!(
+            vec![ResourceRelationship::AttachedTo {
+                instance_id: inventory.resources[0].id.clone()
+            }],
+            disk.relationships
+        );
+
+        assert!(parse_resource_graph_export(r#"{"value": []}"#, &[], false, &all).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+//! A module to format the summary of a scan as a [shields.io endpoint](https://shields.io/badges/endpoint-badge) json, to display a live badge of the carbon footprint.
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::impact_provider::ImpactsSummary;
+
+const DEFAULT_LABEL: &str = "carbon footprint";
+const COLOR: &str = "informational";
+
+/// The json expected by the shields.io endpoint badge
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Badge {
+    pub schema_version: u8,
+    pub label: String,
+    pub message: String,
+    pub color: String,
+}
+
+/// Formats a value with a precision adapted to its magnitude
+fn format_value(value: f64) -> String {
+    if value == 0.0 || value.abs() >= 10.0 {
+        format!("{:.1}", value)
+    } else if value.abs() >= 0.1 {
+        format!("{:.2}", value)
+    } else {
+        format!("{:.2e}", value)
+    }
+}
+
+/// Returns a badge displaying the GWP (manufacture and use) of the summary per day of use
+pub fn build_badge(summary: &ImpactsSummary, label: Option<&str>) -> Badge {
+    let total_gwp_kgco2eq = summary.gwp_manufacture_kgco2eq + summary.gwp_use_kgco2eq;
+    let message = if summary.duration_of_use_hours > 0.0 {
+        let per_day = total_gwp_kgco2eq / summary.duration_of_use_hours * 24.0;
+        format!("{} kgCO2eq/day", format_value(per_day))
+    } else {
+        format!("{} kgCO2eq", format_value(total_gwp_kgco2eq))
+    };
+    Badge {
+        schema_version: 1,
+        label: label.unwrap_or(DEFAULT_LABEL).to_string(),
+        message,
+        color: COLOR.to_string(),
+    }
+}
+
+/// Returns the badge of the summary as shields.io endpoint json
+pub fn get_badge_json(summary: &ImpactsSummary, label: Option<&str>) -> Result<String> {
+    serde_json::to_string(&build_badge(summary, label)).context("Cannot format badge as json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::EstimatedInventory;
+
+    #[test]
+    fn badge_shows_gwp_per_day() {
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let mut summary = ImpactsSummary::new(
+            "eu-west-1".to_string(),
+            "IRL".to_string(),
+            &estimated_inventory,
+            168.0,
+        );
+        summary.gwp_manufacture_kgco2eq = 60.0;
+        summary.gwp_use_kgco2eq = 26.8;
+
+        assert_eq!(
+            r#"{"schemaVersion":1,"label":"carbon footprint","message":"12.4 kgCO2eq/day","color":"informational"}"#,
+            get_badge_json(&summary, None).unwrap()
+        );
+        assert_eq!("prod", build_badge(&summary, Some("prod")).label);
+        assert_eq!("0.25", format_value(0.25));
+        assert_eq!("1.20e-3", format_value(0.0012));
+    }
+}
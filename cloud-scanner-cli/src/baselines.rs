@@ -0,0 +1,229 @@
+//! Named reference scans (`baseline` command): a scan saved under a name (like `2024-q1`) in a directory of baselines, to compare later scans to it.
+//!
+//! Baselines are json outputs of the `estimate` command, so they can also be passed to `diff` or `--baseline`.
+use anyhow::{Context, Result};
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use crate::model::EstimatedInventory;
+use crate::result_envelope::ResultMetadata;
+use crate::scan_diff::ScanDiff;
+
+/// Directory of the baselines, relative to the working directory
+pub const DEFAULT_DIR: &str = ".cloud-scanner/baselines";
+
+/// A saved reference scan
+#[derive(Clone)]
+pub struct Baseline {
+    pub name: String,
+    /// Metadata of the scan (none for scans written before the metadata envelope)
+    pub metadata: Option<ResultMetadata>,
+    pub estimated_inventory: EstimatedInventory,
+}
+
+impl Baseline {
+    /// Returns the total GWP (manufacture and use) of the resources of the baseline
+    pub fn total_gwp_kgco2eq(&self) -> f64 {
+        self.estimated_inventory
+            .impacting_resources
+            .iter()
+            .filter_map(|resource| resource.impacts_values.as_ref())
+            .fold(0.0, |total, impacts| {
+                total + impacts.gwp_manufacture_kgco2eq + impacts.gwp_use_kgco2eq
+            })
+    }
+
+    /// Returns the duration of use of the scan of the baseline, if known
+    pub fn use_duration_hours(&self) -> Option<f32> {
+        self.metadata
+            .as_ref()
+            .and_then(|metadata| metadata.parameters.use_duration_hours)
+    }
+}
+
+/// Directory of the baselines, one json file per baseline
+#[derive(Clone, Debug)]
+pub struct BaselineStore {
+    dir: PathBuf,
+}
+
+impl BaselineStore {
+    pub fn new(dir: &str) -> Self {
+        BaselineStore {
+            dir: PathBuf::from(dir),
+        }
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf> {
+        let is_valid = !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !is_valid {
+            anyhow::bail!(
+                "Invalid baseline name '{}' (expecting letters, digits, '-', '_' or '.', like 2024-q1)",
+                name
+            );
+        }
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+
+    /// Saves a scan (json output of the `estimate` command) as a baseline, refusing to replace an existing baseline unless `force`
+    pub fn save(&self, name: &str, scan: &str, force: bool) -> Result<PathBuf> {
+        let path = self.path(name)?;
+        crate::scan_diff::parse_scan(scan)
+            .with_context(|| format!("Cannot save baseline {}", name))?;
+        if !force && path.exists() {
+            anyhow::bail!(
+                "Baseline {} already exists (use --force to replace it)",
+                name
+            );
+        }
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Cannot create directory {}", self.dir.display()))?;
+        std::fs::write(&path, scan)
+            .with_context(|| format!("Cannot write baseline {}", path.display()))?;
+        Ok(path)
+    }
+
+    /// Loads a baseline
+    pub fn load(&self, name: &str) -> Result<Baseline> {
+        let path = self.path(name)?;
+        if !path.exists() {
+            anyhow::bail!(
+                "No baseline {} in {} (saved baselines: {})",
+                name,
+                self.dir.display(),
+                self.names()?.join(", ")
+            );
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Cannot read baseline {}", path.display()))?;
+        let (metadata, estimated_inventory) = crate::scan_diff::parse_scan(&content)
+            .with_context(|| format!("Cannot parse baseline {}", path.display()))?;
+        Ok(Baseline {
+            name: name.to_string(),
+            metadata,
+            estimated_inventory,
+        })
+    }
+
+    /// Returns the names of the saved baselines, sorted
+    pub fn names(&self) -> Result<Vec<String>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let entries = std::fs::read_dir(&self.dir)
+            .with_context(|| format!("Cannot list baselines of {}", self.dir.display()))?;
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+}
+
+fn scan_description(baseline: &Baseline) -> String {
+    let scanned = match &baseline.metadata {
+        Some(metadata) => format!(
+            "scanned {}",
+            metadata.scan_timestamp.format("%Y-%m-%d %H:%M")
+        ),
+        None => "scan date unknown".to_string(),
+    };
+    let duration = match baseline.use_duration_hours() {
+        Some(hours) => format!(", {} hours", hours),
+        None => "".to_string(),
+    };
+    format!(
+        "{}{}, {} resources, GWP {:.3} kgCO2eq",
+        scanned,
+        duration,
+        baseline.estimated_inventory.impacting_resources.len(),
+        baseline.total_gwp_kgco2eq()
+    )
+}
+
+/// Formats the saved baselines as text, one line per baseline
+pub fn to_text(baselines: &[Baseline]) -> String {
+    let width = baselines
+        .iter()
+        .map(|baseline| baseline.name.len())
+        .max()
+        .unwrap_or(0);
+    baselines
+        .iter()
+        .map(|baseline| {
+            format!(
+                "{:width$}  {}\n",
+                baseline.name,
+                scan_description(baseline),
+                width = width
+            )
+        })
+        .collect()
+}
+
+/// Formats the differences between a baseline and a recent scan as text
+pub fn comparison_to_text(baseline: &Baseline, diff: &ScanDiff) -> String {
+    let mut text = String::new();
+    let _ = writeln!(
+        text,
+        "Compared to baseline {} ({})\n",
+        baseline.name,
+        scan_description(baseline)
+    );
+    text.push_str(&crate::scan_diff::to_text(diff));
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCAN: &str = r#"{
+  "metadata": {
+    "schema_version": 1,
+    "cloud_scanner_version": "4.0.0",
+    "provider": "aws",
+    "scan_timestamp": "2024-03-31T10:00:00Z",
+    "parameters": { "aws_region": "eu-west-3", "use_duration_hours": 730.0, "filter_tags": [], "include_block_storage": false, "verbose": false }
+  },
+  "data": { "impactingResources": [], "executionStatistics": null }
+}"#;
+
+    #[test]
+    fn baselines_are_saved_listed_and_loaded_by_name() {
+        let dir =
+            std::env::temp_dir().join(format!("cloud-scanner-baselines-{}", std::process::id()));
+        let store = BaselineStore::new(dir.to_str().unwrap());
+        assert!(store.names().unwrap().is_empty());
+
+        store.save("2024-q1", SCAN, false).unwrap();
+        assert!(store.save("2024-q1", SCAN, false).is_err());
+        store.save("2024-q1", SCAN, true).unwrap();
+        assert!(store.save("2024-q2", "not a scan", false).is_err());
+        assert!(store.save("../q2", SCAN, false).is_err());
+        assert_eq!(vec!["2024-q1"], store.names().unwrap());
+
+        let baseline = store.load("2024-q1").unwrap();
+        assert_eq!(Some(730.0), baseline.use_duration_hours());
+        assert_eq!(
+            "2024-q1  scanned 2024-03-31 10:00, 730 hours, 0 resources, GWP 0.000 kgCO2eq\n",
+            to_text(&[baseline])
+        );
+        let missing = store.load("2023-q4").err().unwrap().to_string();
+        assert!(missing.contains("saved baselines: 2024-q1"), "{}", missing);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+//! A module to export the resources and their impacts into a BigQuery table (one row per resource).
+//!
+//! Rows are sent with the streaming insert API (`tabledata.insertAll`). They can also be formatted as newline delimited json, to be loaded from a file or Cloud Storage (`bq load --source_format=NEWLINE_DELIMITED_JSON`).
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::model::{EstimatedInventory, InstanceState, ResourceDetails};
+
+/// Public BigQuery REST API endpoint
+pub const BIGQUERY_API_URL: &str = "https://bigquery.googleapis.com/bigquery/v2";
+
+/// Maximum number of rows sent in a single streaming insert request (recommended by BigQuery)
+const MAX_ROWS_PER_REQUEST: usize = 500;
+
+///  Settings of the BigQuery export
+#[derive(Clone, Debug)]
+pub struct BigQueryConfig {
+    /// Base url of the API (see [BIGQUERY_API_URL])
+    pub api_url: String,
+    pub project_id: String,
+    pub dataset_id: String,
+    pub table_id: String,
+    /// OAuth2 access token (like the output of `gcloud auth print-access-token`)
+    pub access_token: String,
+}
+
+impl BigQueryConfig {
+    /// Returns a configuration for a table identified like `project.dataset.table`
+    pub fn new(table: &str, access_token: &str) -> Result<Self> {
+        let parts: Vec<&str> = table.split('.').collect();
+        match parts[..] {
+            [project_id, dataset_id, table_id]
+                if !project_id.is_empty() && !dataset_id.is_empty() && !table_id.is_empty() =>
+            {
+                Ok(BigQueryConfig {
+                    api_url: BIGQUERY_API_URL.to_string(),
+                    project_id: project_id.to_string(),
+                    dataset_id: dataset_id.to_string(),
+                    table_id: table_id.to_string(),
+                    access_token: access_token.to_string(),
+                })
+            }
+            _ => anyhow::bail!(
+                "Invalid BigQuery table {} (should be like project.dataset.table)",
+                table
+            ),
+        }
+    }
+
+    fn insert_all_url(&self) -> String {
+        format!(
+            "{}/projects/{}/datasets/{}/tables/{}/insertAll",
+            self.api_url, self.project_id, self.dataset_id, self.table_id
+        )
+    }
+}
+
+/// A row of the BigQuery table (a resource and its impacts)
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct BigQueryRow {
+    /// Timestamp of the scan (RFC 3339)
+    pub scan_timestamp: String,
+    pub resource_id: String,
+    pub resource_kind: String,
+    pub resource_type: Option<String>,
+    pub aws_region: String,
+    pub country: String,
+    pub resource_state: Option<String>,
+    pub average_cpu_load: Option<f64>,
+    pub storage_size_gb: Option<i64>,
+    pub tags: String,
+    pub impacts_duration_hours: f64,
+    pub adp_manufacture_kgsbeq: Option<f64>,
+    pub adp_use_kgsbeq: Option<f64>,
+    pub pe_manufacture_megajoules: Option<f64>,
+    pub pe_use_megajoules: Option<f64>,
+    pub gwp_manufacture_kgco2eq: Option<f64>,
+    pub gwp_use_kgco2eq: Option<f64>,
+}
+
+/// Returns the rows of the BigQuery table for an estimated inventory
+pub fn get_bigquery_rows(
+    timestamp: DateTime<Utc>,
+    estimated_inventory: &EstimatedInventory,
+) -> Vec<BigQueryRow> {
+    let scan_timestamp = timestamp.to_rfc3339_opts(SecondsFormat::Secs, true);
+    estimated_inventory
+        .impacting_resources
+        .iter()
+        .map(|resource| {
+            let cloud_resource = &resource.cloud_resource;
+            let details = &cloud_resource.resource_details;
+            let (resource_state, average_cpu_load, storage_size_gb) = match details {
+                ResourceDetails::Instance {
+                    usage: Some(usage), ..
+                } => {
+                    let state = match usage.state {
+                        InstanceState::Running => "Running",
+                        InstanceState::Stopped => "Stopped",
+                    };
+                    (Some(state.to_string()), Some(usage.average_cpu_load), None)
+                }
+                ResourceDetails::BlockStorage {
+                    usage: Some(usage), ..
+                } => (None, None, Some(usage.size_gb.into())),
+                _ => (None, None, None),
+            };
+            let impacts = resource.impacts_values.as_ref();
+            BigQueryRow {
+                scan_timestamp: scan_timestamp.clone(),
+                resource_id: cloud_resource.id.clone(),
+                resource_kind: details.kind().to_string(),
+                resource_type: details.resource_type(),
+                aws_region: cloud_resource.location.aws_region.clone(),
+                country: cloud_resource.location.iso_country_code.clone(),
+                resource_state,
+                average_cpu_load,
+                storage_size_gb,
+                tags: cloud_resource.tags_as_metric_label_value(),
+                impacts_duration_hours: resource.impacts_duration_hours.into(),
+                adp_manufacture_kgsbeq: impacts.map(|i| i.adp_manufacture_kgsbeq),
+                adp_use_kgsbeq: impacts.map(|i| i.adp_use_kgsbeq),
+                pe_manufacture_megajoules: impacts.map(|i| i.pe_manufacture_megajoules),
+                pe_use_megajoules: impacts.map(|i| i.pe_use_megajoules),
+                gwp_manufacture_kgco2eq: impacts.map(|i| i.gwp_manufacture_kgco2eq),
+                gwp_use_kgco2eq: impacts.map(|i| i.gwp_use_kgco2eq),
+            }
+        })
+        .collect()
+}
+
+/// Formats rows as newline delimited json (the format expected by BigQuery load jobs)
+pub fn rows_to_ndjson(rows: &[BigQueryRow]) -> Result<String> {
+    let mut ndjson = String::new();
+    for row in rows {
+        ndjson.push_str(&serde_json::to_string(row)?);
+        ndjson.push('\n');
+    }
+    Ok(ndjson)
+}
+
+/// Body of a streaming insert request
+fn insert_all_request(rows: &[BigQueryRow]) -> serde_json::Value {
+    let rows: Vec<serde_json::Value> = rows
+        .iter()
+        .map(|row| {
+            serde_json::json!({
+                // Lets BigQuery deduplicate rows if a request is retried
+                "insertId": format!("{}-{}", row.resource_id, row.scan_timestamp),
+                "json": row,
+            })
+        })
+        .collect();
+    serde_json::json!({ "rows": rows })
+}
+
+/// Sends rows to BigQuery using the streaming insert API
+pub async fn insert_rows(config: &BigQueryConfig, rows: &[BigQueryRow]) -> Result<()> {
+    let client = reqwest::Client::new();
+    for chunk in rows.chunks(MAX_ROWS_PER_REQUEST) {
+        let response = client
+            .post(config.insert_all_url())
+            .bearer_auth(&config.access_token)
+            .json(&insert_all_request(chunk))
+            .send()
+            .await
+            .context("Cannot send rows to BigQuery")?;
+        let status = response.status();
+        let body: serde_json::Value = response
+            .json()
+            .await
+            .context("Cannot read BigQuery response")?;
+        if !status.is_success() {
+            anyhow::bail!("BigQuery returned an error ({}): {}", status, body);
+        }
+        if let Some(errors) = body.get("insertErrors") {
+            anyhow::bail!("BigQuery rejected some rows: {}", errors);
+        }
+    }
+    info!(
+        "Inserted {} rows into BigQuery table {}.{}.{}",
+        rows.len(),
+        config.project_id,
+        config.dataset_id,
+        config.table_id
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+    use crate::model::{CloudProvider, CloudResource, InstanceUsage};
+    use crate::usage_location::UsageLocation;
+    use chrono::TimeZone;
+
+    fn estimated_inventory() -> EstimatedInventory {
+        let instance = CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: None,
+            id: "inst-1".to_string(),
+            location: UsageLocation::try_from("eu-west-1").unwrap(),
+            resource_details: ResourceDetails::Instance {
+                instance_type: "m6g.xlarge".to_string(),
+                usage: Some(InstanceUsage {
+                    average_cpu_load: 42.0,
+                    usage_duration_seconds: 3600,
+                    state: InstanceState::Running,
+                }),
+            },
+            tags: Vec::new(),
+            relationships: Vec::new(),
+        };
+        EstimatedInventory {
+            impacting_resources: vec![CloudResourceWithImpacts {
+                cloud_resource: instance,
+                impacts_values: Some(ImpactsValues {
+                    adp_manufacture_kgsbeq: 0.1,
+                    adp_use_kgsbeq: 0.2,
+                    pe_manufacture_megajoules: 0.3,
+                    pe_use_megajoules: 0.4,
+                    gwp_manufacture_kgco2eq: 0.5,
+                    gwp_use_kgco2eq: 0.6,
+                    raw_data: None,
+                }),
+                impacts_duration_hours: 1.0,
+                cost: None,
+            }],
+            execution_statistics: None,
+            errors: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parse_table_identifier() {
+        let config = BigQueryConfig::new("my-project.reporting.impacts", "token").unwrap();
+        assert_eq!(
+            "https://bigquery.googleapis.com/bigquery/v2/projects/my-project/datasets/reporting/tables/impacts/insertAll",
+            config.insert_all_url()
+        );
+        assert!(BigQueryConfig::new("reporting.impacts", "token").is_err());
+        assert!(BigQueryConfig::new("my-project..impacts", "token").is_err());
+    }
+
+    #[test]
+    fn format_rows_as_ndjson() {
+        let timestamp = Utc.with_ymd_and_hms(2024, 4, 12, 10, 15, 0).unwrap();
+        let rows = get_bigquery_rows(timestamp, &estimated_inventory());
+        assert_eq!(1, rows.len());
+        assert_eq!("2024-04-12T10:15:00Z", rows[0].scan_timestamp);
+        assert_eq!(Some("m6g.xlarge".to_string()), rows[0].resource_type);
+        assert_eq!(Some("Running".to_string()), rows[0].resource_state);
+        assert_eq!(None, rows[0].storage_size_gb);
+
+        let ndjson = rows_to_ndjson(&rows).unwrap();
+        assert_eq!(1, ndjson.lines().count());
+        let parsed: BigQueryRow = serde_json::from_str(ndjson.trim_end()).unwrap();
+        assert_eq!(rows[0], parsed);
+    }
+
+    #[test]
+    fn insert_request_uses_a_stable_insert_id() {
+        let timestamp = Utc.with_ymd_and_hms(2024, 4, 12, 10, 15, 0).unwrap();
+        let rows = get_bigquery_rows(timestamp, &estimated_inventory());
+        let request = insert_all_request(&rows);
+        assert_eq!(
+            "inst-1-2024-04-12T10:15:00Z",
+            request["rows"][0]["insertId"]
+        );
+        assert_eq!("inst-1", request["rows"][0]["json"]["resource_id"]);
+    }
+}
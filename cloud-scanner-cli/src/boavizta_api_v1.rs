@@ -1,58 +1,448 @@
 //!  A service to retrieve cloud resource impacts from Boavizta API.
 use crate::impact_provider::{CloudResourceWithImpacts, ImpactProvider, ImpactsValues};
-use anyhow::Result;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
 use boavizta_api_sdk::apis::cloud_api;
 use boavizta_api_sdk::apis::component_api;
 use boavizta_api_sdk::apis::configuration;
+use boavizta_api_sdk::apis::server_api;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::{Duration, Instant};
+use tracing::Instrument;
 
 use crate::model::{
-    CloudResource, EstimatedInventory, ExecutionStatistics, Inventory, ResourceDetails,
+    CloudProvider, CloudResource, EstimatedInventory, ExecutionStatistics, InstanceUsage,
+    Inventory, ResourceDetails, ServerHardware, StorageUsage,
 };
-use boavizta_api_sdk::models::{Cloud, Disk, UsageCloud};
+use crate::scan_errors::{ScanError, ScanPhase};
+use crate::server_telemetry::{ScanStage, UnassessedReason};
+use crate::supported_types::SupportedTypes;
+use crate::usage_location::UsageLocation;
+use boavizta_api_sdk::models::{
+    Cloud, ConfigurationServer, Cpu, Disk, Ram, Server, UsageCloud, UsageServer,
+};
+
+/// Criteria of the impacts queried from Boavizta API
+const CRITERIA: [&str; 3] = ["gwp", "adp", "pe"];
+
+/// Archetype of the components of Boavizta API used for volumes
+const DISK_ARCHETYPE: &str = "DEFAULT";
+
+/// Maximum number of idle connections to Boavizta API kept open for reuse, unless set with [configure_http_client]
+pub const DEFAULT_HTTP_POOL_SIZE: usize = 32;
+
+/// Duration an idle connection to Boavizta API is kept open for reuse, unless set with [configure_http_client]
+pub const DEFAULT_HTTP_KEEP_ALIVE: Duration = Duration::from_secs(90);
+
+/// Client of Boavizta API shared by all the scans of the process, so that the connections are reused by the requests of all the resources and regions
+static HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+fn build_http_client(pool_size: usize, keep_alive: Duration) -> reqwest::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .pool_max_idle_per_host(pool_size)
+        .pool_idle_timeout(keep_alive)
+        .tcp_keepalive(keep_alive)
+        .build()
+}
+
+/// Sets the size of the pool of connections to Boavizta API (0 opens a connection per request) and their keep-alive, before the first request
+pub fn configure_http_client(pool_size: usize, keep_alive: Duration) -> Result<()> {
+    let client = build_http_client(pool_size, keep_alive)
+        .context("Cannot create the HTTP client of Boavizta API")?;
+    HTTP_CLIENT
+        .set(client)
+        .map_err(|_| anyhow::anyhow!("The HTTP client of Boavizta API is already in use"))
+}
+
+/// Width (in percent) of the buckets of CPU load of the queries of instances, 0 querying the measured load, unless set with [set_load_bucket]
+static LOAD_BUCKET: AtomicU8 = AtomicU8::new(0);
+
+/// Queries the impacts of instances with their CPU load rounded to the nearest multiple of `percent` (0 queries the measured load), so that more instances share the same query
+pub fn set_load_bucket(percent: u8) {
+    LOAD_BUCKET.store(percent, Ordering::Relaxed);
+}
+
+/// Whether the impacts of resources keep the raw response of Boavizta API (`raw_data`), unless set with [set_keep_raw_data]
+static KEEP_RAW_DATA: AtomicBool = AtomicBool::new(true);
+
+/// Keeps (or drops) the raw response of Boavizta API in the impacts of each resource: dropping it divides the size of results and the memory of large scans several-fold
+pub fn set_keep_raw_data(keep: bool) {
+    KEEP_RAW_DATA.store(keep, Ordering::Relaxed);
+}
+
+/// Returns a resource with its impacts, without the raw response of Boavizta API
+fn without_raw_data(
+    mut resource_with_impacts: CloudResourceWithImpacts,
+) -> CloudResourceWithImpacts {
+    if let Some(impacts_values) = resource_with_impacts.impacts_values.as_mut() {
+        impacts_values.raw_data = None;
+    }
+    resource_with_impacts
+}
+
+/// Returns the resource queried to Boavizta API: its CPU load rounded to the nearest multiple of the bucket (if any), the resource itself when there is nothing to round
+fn with_bucketed_load(resource: &CloudResource, bucket: u8) -> Cow<'_, CloudResource> {
+    let ResourceDetails::Instance {
+        usage: Some(usage), ..
+    } = &resource.resource_details
+    else {
+        return Cow::Borrowed(resource);
+    };
+    if bucket == 0 {
+        return Cow::Borrowed(resource);
+    }
+    let bucket = bucket as f64;
+    let average_cpu_load = ((usage.average_cpu_load / bucket).round() * bucket).min(100.0);
+    let mut resource = resource.clone();
+    if let ResourceDetails::Instance {
+        usage: Some(usage), ..
+    } = &mut resource.resource_details
+    {
+        usage.average_cpu_load = average_cpu_load;
+    }
+    Cow::Owned(resource)
+}
+
+/// Returns the client of Boavizta API shared by all the scans (cloning it shares its pool of connections)
+pub fn http_client() -> reqwest::Client {
+    HTTP_CLIENT
+        .get_or_init(|| {
+            build_http_client(DEFAULT_HTTP_POOL_SIZE, DEFAULT_HTTP_KEEP_ALIVE).unwrap_or_default()
+        })
+        .clone()
+}
+
+/// A query of Boavizta API estimating the impacts of a resource
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ImpactsQuery {
+    /// Path of the endpoint (queried with POST), like `/v1/cloud/instance`
+    pub path: String,
+    /// Query string parameters
+    pub parameters: Vec<(String, String)>,
+    /// Json body describing the resource
+    pub body: serde_json::Value,
+}
+
+/// Types of volumes estimated as hdd (the throughput optimized and cold HDD volumes of AWS, and the Standard HDD disks of Azure and GCP)
+pub const HDD_VOLUME_TYPES: [&str; 5] =
+    ["st1", "sc1", "Standard_LRS", "Standard_ZRS", "pd-standard"];
+
+/// Types of volumes estimated as ssd, other types being estimated as ssd by default
+pub const SSD_VOLUME_TYPES: [&str; 11] = [
+    "gp2",
+    "gp3",
+    "Premium_LRS",
+    "Premium_ZRS",
+    "PremiumV2_LRS",
+    "StandardSSD_LRS",
+    "StandardSSD_ZRS",
+    "UltraSSD_LRS",
+    "pd-balanced",
+    "pd-ssd",
+    "pd-extreme",
+];
+
+/// Returns the provider of the instances of Boavizta API: `azure` for Azure, `gcp` for GCP, `aws` for the other providers
+pub(crate) fn cloud_provider(provider: &CloudProvider) -> &'static str {
+    match provider {
+        CloudProvider::Azure => "azure",
+        CloudProvider::GCP => "gcp",
+        _ => "aws",
+    }
+}
+
+/// Returns the instance described to Boavizta API: its provider, type, the country of its region and its cpu load (if known)
+fn cloud_instance(
+    provider: &CloudProvider,
+    instance_type: &str,
+    usage: &Option<InstanceUsage>,
+    location: &UsageLocation,
+) -> Cloud {
+    let mut usage_cloud: UsageCloud = UsageCloud::new();
+
+    //usage_cloud.hours_life_time = Some(usage_duration_hours.to_owned());
+    usage_cloud.usage_location = Some(location.iso_country_code.to_owned());
+
+    if let Some(instance_usage) = usage {
+        usage_cloud.time_workload = Some(instance_usage.average_cpu_load as f32);
+    }
+
+    let mut cloud: Cloud = Cloud::new();
+    cloud.provider = Some(String::from(cloud_provider(provider)));
+    cloud.instance_type = Some(instance_type.to_owned());
+    cloud.usage = Some(Box::new(usage_cloud));
+    cloud
+}
+
+/// Returns the server described to Boavizta API: its hardware (if known, Boavizta API completing it with its default archetype), the country of its location and its cpu load (if known)
+fn server(
+    hardware: &Option<ServerHardware>,
+    usage: &Option<InstanceUsage>,
+    location: &UsageLocation,
+) -> Server {
+    let configuration = hardware.as_ref().map(|hardware| {
+        let mut configuration = ConfigurationServer::new();
+        if hardware.cpu_units.is_some() || hardware.cpu_cores.is_some() {
+            let mut cpu = Cpu::new();
+            cpu.units = hardware.cpu_units;
+            cpu.core_units = hardware.cpu_cores;
+            configuration.cpu = Some(Box::new(cpu));
+        }
+        if let Some(memory_gb) = hardware.memory_gb {
+            let mut ram = Ram::new();
+            ram.units = Some(1);
+            ram.capacity = Some(memory_gb);
+            configuration.ram = Some(vec![ram]);
+        }
+        if let Some(storage_gb) = hardware.storage_gb {
+            let mut disk = disk(&Some(StorageUsage {
+                size_gb: storage_gb,
+                usage_duration_seconds: 0,
+            }));
+            disk.units = Some(1);
+            disk.r#type = Some("ssd".to_string());
+            configuration.disk = Some(vec![disk]);
+        }
+        Box::new(configuration)
+    });
+
+    let mut usage_server = UsageServer::new();
+    usage_server.usage_location = Some(location.iso_country_code.to_owned());
+    if let Some(usage) = usage {
+        usage_server.time_workload = Some(usage.average_cpu_load as f32);
+    }
+
+    let mut server = Server::new();
+    server.configuration = configuration;
+    server.usage = Some(Box::new(usage_server));
+    server
+}
+
+/// Returns the disk described to Boavizta API (its capacity)
+fn disk(usage: &Option<StorageUsage>) -> Disk {
+    Disk {
+        capacity: usage.as_ref().map(|usage| usage.size_gb),
+        units: None,
+        usage: None,
+        r#type: None,
+        density: None,
+        manufacturer: None,
+        model: None,
+        layers: None,
+    }
+}
+
+/// Returns the component of Boavizta API estimating the impacts of a type of volume: `hdd` for the [HDD_VOLUME_TYPES], `ssd` for the other types
+fn disk_component(storage_type: &str) -> &'static str {
+    if HDD_VOLUME_TYPES.contains(&storage_type) {
+        "hdd"
+    } else {
+        "ssd"
+    }
+}
+
+/// Returns the query of Boavizta API estimating the impacts of a resource for the duration of use (hours), None if the resource is not supported
+pub fn impacts_query(
+    resource: &CloudResource,
+    usage_duration_hours: &f32,
+    verbose: bool,
+) -> Option<ImpactsQuery> {
+    let mut parameters = vec![
+        ("verbose".to_string(), verbose.to_string()),
+        ("duration".to_string(), usage_duration_hours.to_string()),
+    ];
+    let (path, body) = match &resource.resource_details {
+        ResourceDetails::Instance {
+            instance_type,
+            usage,
+        } => (
+            "/v1/cloud/instance".to_string(),
+            serde_json::to_value(cloud_instance(
+                &resource.provider,
+                instance_type,
+                usage,
+                &resource.location,
+            ))
+            .ok()?,
+        ),
+        ResourceDetails::BlockStorage {
+            storage_type,
+            usage,
+            attached_instances: _,
+        } => {
+            parameters.push(("archetype".to_string(), DISK_ARCHETYPE.to_string()));
+            (
+                format!("/v1/component/{}", disk_component(storage_type)),
+                serde_json::to_value(disk(usage)).ok()?,
+            )
+        }
+        ResourceDetails::ObjectStorage => return None,
+        ResourceDetails::Server {
+            hardware, usage, ..
+        } => (
+            "/v1/server/".to_string(),
+            serde_json::to_value(server(hardware, usage, &resource.location)).ok()?,
+        ),
+    };
+    parameters.extend(
+        CRITERIA
+            .iter()
+            .map(|criteria| ("criteria".to_string(), criteria.to_string())),
+    );
+    Some(ImpactsQuery {
+        path,
+        parameters,
+        body,
+    })
+}
+
+/// Returns the version of the Boavizta API deployed at `api_url`, or None if it cannot be retrieved
+pub async fn get_api_version(api_url: &str) -> Option<String> {
+    let url = format!("{}/v1/utils/version", api_url);
+    let response = http_client()
+        .get(&url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status());
+    match response {
+        Ok(response) => response.json::<String>().await.ok(),
+        Err(e) => {
+            warn!(
+                "Cannot retrieve the version of Boavizta API from {}: {}",
+                url, e
+            );
+            None
+        }
+    }
+}
+
+/// Raw impacts of the queries already answered, by query (concurrent identical queries waiting for the first one)
+type Memo = Mutex<HashMap<String, Arc<tokio::sync::OnceCell<serde_json::Value>>>>;
 
 /// Access data of Boavizta API
 pub struct BoaviztaApiV1 {
     configuration: boavizta_api_sdk::apis::configuration::Configuration,
+    /// Identical resources (same type, country, load and duration) are only queried once by the same instance (like during the scan of a region)
+    memo: Memo,
 }
 
 /// Create a new instance of service to access Boavizta API by passing API URL.
+/// Logs the failure of a query of the impacts of a resource, and reports it in the errors of the scan (see [crate::scan_errors])
+fn failed_query(resource: &CloudResource, message: String) -> Option<serde_json::Value> {
+    warn!("Warning: {}", message);
+    crate::scan_errors::record(ScanError::of_resource(
+        ScanPhase::ImpactEstimation,
+        resource,
+        message,
+    ));
+    None
+}
+
 impl BoaviztaApiV1 {
     pub fn new(api_url: &str) -> Self {
         let mut configuration = configuration::Configuration::new();
         configuration.base_path = api_url.to_string();
-        BoaviztaApiV1 { configuration }
+        configuration.client = http_client();
+        BoaviztaApiV1 {
+            configuration,
+            memo: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the raw impacts of a resource, queried once for all the resources with the same query (and shared with other processes, when the cache is shared, see [crate::shared_cache])
+    async fn get_memoized_raw_impacts(
+        &self,
+        resource: &CloudResource,
+        usage_duration_hours: &f32,
+        verbose: bool,
+    ) -> Option<serde_json::Value> {
+        let resource = with_bucketed_load(resource, LOAD_BUCKET.load(Ordering::Relaxed));
+        // Resources that are not queried (unsupported) are not memoized
+        let Some(key) = impacts_query(&resource, usage_duration_hours, verbose)
+            .and_then(|query| serde_json::to_string(&query).ok())
+        else {
+            return self
+                .get_raws_impacts(&resource, usage_duration_hours, verbose)
+                .await;
+        };
+        #[cfg(feature = "shared-cache")]
+        let shared_key = crate::shared_cache::impacts_key(&self.configuration.base_path, &key);
+        let cell = self.memo.lock().unwrap().entry(key).or_default().clone();
+        let mut queried = false;
+        // Failed queries are not memoized, the next identical resource queries again
+        let raw_impacts = cell
+            .get_or_try_init(|| {
+                queried = true;
+                async {
+                    #[cfg(feature = "shared-cache")]
+                    if let Some(raw_impacts) = self.shared_raw_impacts(&shared_key).await {
+                        return Ok(raw_impacts);
+                    }
+                    let permit = crate::concurrency::BOAVIZTA_API_LIMIT.acquire().await;
+                    let raw_impacts = self
+                        .get_raws_impacts(&resource, usage_duration_hours, verbose)
+                        .await;
+                    permit.finish(raw_impacts.is_some());
+                    #[cfg(feature = "shared-cache")]
+                    if let Some(raw_impacts) = &raw_impacts {
+                        let shared = (shared_key.clone(), raw_impacts.to_string());
+                        crate::shared_cache::set_all(&[shared], crate::shared_cache::impacts_ttl())
+                            .await;
+                    }
+                    raw_impacts.ok_or(())
+                }
+            })
+            .await
+            .ok()
+            .cloned();
+        if !queried {
+            debug!(
+                "Reusing the impacts of an identical query for {}",
+                resource.id
+            );
+        }
+        raw_impacts
+    }
+
+    /// Returns the raw impacts of a query queried by another process, when the cache is shared
+    #[cfg(feature = "shared-cache")]
+    async fn shared_raw_impacts(&self, shared_key: &str) -> Option<serde_json::Value> {
+        if !crate::shared_cache::is_configured() || crate::shared_cache::impacts_ttl().is_zero() {
+            return None;
+        }
+        let shared = crate::shared_cache::get_all(&[shared_key.to_string()])
+            .await
+            .pop()
+            .flatten()?;
+        serde_json::from_str(&shared).ok()
     }
 
     // Returns the raw impacts (json) of an instance from Boavizta API for the duration of use (hours)
-    async fn get_raws_impacts(
+    pub(crate) async fn get_raws_impacts(
         &self,
-        cr: CloudResource,
+        cr: &CloudResource,
         usage_duration_hours: &f32,
         verbose: bool,
     ) -> Option<serde_json::Value> {
-        let resource_details = cr.resource_details;
-        let criteria = vec!["gwp".to_owned(), "adp".to_owned(), "pe".to_owned()];
+        let resource_details = &cr.resource_details;
+        let criteria: Vec<String> = CRITERIA
+            .iter()
+            .map(|criteria| criteria.to_string())
+            .collect();
 
         match resource_details {
             ResourceDetails::Instance {
                 instance_type,
                 usage,
             } => {
-                let mut usage_cloud: UsageCloud = UsageCloud::new();
-
-                //usage_cloud.hours_life_time = Some(usage_duration_hours.to_owned());
-                usage_cloud.usage_location = Some(cr.location.iso_country_code.to_owned());
-
-                if let Some(instance_usage) = usage {
-                    usage_cloud.time_workload = Some(instance_usage.average_cpu_load as f32);
-                }
-
-                let mut cloud: Cloud = Cloud::new();
-                cloud.provider = Some(String::from("aws"));
-                cloud.instance_type = Some(instance_type.clone());
-                cloud.usage = Some(Box::new(usage_cloud));
+                let cloud = cloud_instance(&cr.provider, instance_type, usage, &cr.location);
 
+                let started = Instant::now();
                 let res = cloud_api::instance_cloud_impact_v1_cloud_instance_post(
                     &self.configuration,
                     Some(verbose),
@@ -61,16 +451,21 @@ impl BoaviztaApiV1 {
                     Some(cloud),
                 )
                 .await;
+                crate::server_telemetry::record_boavizta_call(
+                    "instance",
+                    started.elapsed(),
+                    res.is_ok(),
+                );
 
                 match res {
                     Ok(res) => Some(res),
-                    Err(e) => {
-                        warn!(
-                            "Warning: Cannot get impacts from API for instance type {}: {}",
+                    Err(e) => failed_query(
+                        cr,
+                        format!(
+                            "Cannot get impacts from API for instance type {}: {}",
                             instance_type, e
-                        );
-                        None
-                    }
+                        ),
+                    ),
                 }
             }
 
@@ -80,60 +475,63 @@ impl BoaviztaApiV1 {
                 attached_instances: _,
             } => {
                 //let duration: f32 = usage.unwrap().usage_duration_seconds.into();
-                let disk = Disk {
-                    capacity: Some(usage.unwrap().size_gb),
-                    units: None,
-                    usage: None,
-                    r#type: None,
-                    density: None,
-                    manufacturer: None,
-                    model: None,
-                    layers: None,
-                };
+                let disk = disk(usage);
 
                 match storage_type.as_str() {
-                    "st1" | "sc1" => {
+                    hdd if HDD_VOLUME_TYPES.contains(&hdd) => {
                         // This is a HDD
+                        let started = Instant::now();
                         let res = component_api::disk_impact_bottom_up_v1_component_hdd_post(
                             &self.configuration,
                             Some(verbose),
                             Some(usage_duration_hours.to_owned()),
-                            Some("DEFAULT"),
+                            Some(DISK_ARCHETYPE),
                             Some(criteria),
                             Some(disk),
                         )
                         .await;
+                        crate::server_telemetry::record_boavizta_call(
+                            "hdd",
+                            started.elapsed(),
+                            res.is_ok(),
+                        );
                         match res {
                             Ok(res) => Some(res),
-                            Err(e) => {
-                                warn!(
-                                    "Warning: Cannot get HHD impact from API for type {}: {}",
+                            Err(e) => failed_query(
+                                cr,
+                                format!(
+                                    "Cannot get HHD impact from API for type {}: {}",
                                     storage_type, e
-                                );
-                                None
-                            }
+                                ),
+                            ),
                         }
                     }
-                    "gp2" | "gp3" => {
+                    ssd if SSD_VOLUME_TYPES.contains(&ssd) => {
                         // Use impacts of an SSD
+                        let started = Instant::now();
                         let res = component_api::disk_impact_bottom_up_v1_component_ssd_post(
                             &self.configuration,
                             Some(verbose),
                             Some(usage_duration_hours.to_owned()),
-                            Some("DEFAULT"),
+                            Some(DISK_ARCHETYPE),
                             Some(criteria),
                             Some(disk),
                         )
                         .await;
+                        crate::server_telemetry::record_boavizta_call(
+                            "ssd",
+                            started.elapsed(),
+                            res.is_ok(),
+                        );
                         match res {
                             Ok(res) => Some(res),
-                            Err(e) => {
-                                warn!(
-                                    "Warning: Cannot get SSD impact from API for type {}: {}",
+                            Err(e) => failed_query(
+                                cr,
+                                format!(
+                                    "Cannot get SSD impact from API for type {}: {}",
                                     storage_type, e
-                                );
-                                None
-                            }
+                                ),
+                            ),
                         }
                     }
                     _ => {
@@ -143,28 +541,65 @@ impl BoaviztaApiV1 {
                             disk
                         );
                         // All other types are considered SSD
+                        let started = Instant::now();
                         let res = component_api::disk_impact_bottom_up_v1_component_ssd_post(
                             &self.configuration,
                             Some(verbose),
                             Some(usage_duration_hours.to_owned()),
-                            Some("DEFAULT"),
+                            Some(DISK_ARCHETYPE),
                             Some(criteria),
                             Some(disk),
                         )
                         .await;
+                        crate::server_telemetry::record_boavizta_call(
+                            "ssd",
+                            started.elapsed(),
+                            res.is_ok(),
+                        );
                         match res {
                             Ok(res) => Some(res),
-                            Err(e) => {
-                                warn!(
-                                    "Warning: Cannot get SSD impact from API for type {}: {}",
+                            Err(e) => failed_query(
+                                cr,
+                                format!(
+                                    "Cannot get SSD impact from API for type {}: {}",
                                     storage_type, e
-                                );
-                                None
-                            }
+                                ),
+                            ),
                         }
                     }
                 }
             }
+            ResourceDetails::Server {
+                model,
+                hardware,
+                usage,
+            } => {
+                let server = server(hardware, usage, &cr.location);
+
+                let started = Instant::now();
+                let res = server_api::server_impact_from_configuration_v1_server_post(
+                    &self.configuration,
+                    Some(verbose),
+                    Some(usage_duration_hours.to_owned()),
+                    None,
+                    Some(criteria),
+                    Some(server),
+                )
+                .await;
+                crate::server_telemetry::record_boavizta_call(
+                    "server",
+                    started.elapsed(),
+                    res.is_ok(),
+                );
+
+                match res {
+                    Ok(res) => Some(res),
+                    Err(e) => failed_query(
+                        cr,
+                        format!("Cannot get impacts from API for server {}: {}", model, e),
+                    ),
+                }
+            }
             _ => {
                 warn!("Warning: This type of cloud resource is not supported.");
                 None
@@ -180,37 +615,62 @@ impl BoaviztaApiV1 {
         verbose: bool,
     ) -> CloudResourceWithImpacts {
         let raw_impacts = self
-            .get_raws_impacts(resource.clone(), usage_duration_hours, verbose)
+            .get_memoized_raw_impacts(resource, usage_duration_hours, verbose)
+            .instrument(tracing::info_span!(
+                "boavizta.impacts",
+                otel.kind = "client",
+                cloud_scanner.resource.id = %resource.id,
+                cloud_scanner.resource.kind = resource.resource_details.kind(),
+            ))
             .await;
-        boa_impacts_to_cloud_resource_with_impacts(resource, &raw_impacts, usage_duration_hours)
+        if raw_impacts.is_none() {
+            let reason = match resource.resource_details {
+                ResourceDetails::ObjectStorage => UnassessedReason::UnsupportedResource,
+                _ => UnassessedReason::BoaviztaError,
+            };
+            crate::server_telemetry::record_unassessed_resource(reason);
+        }
+        let resource_with_impacts = boa_impacts_to_cloud_resource_with_impacts(
+            resource,
+            &raw_impacts,
+            usage_duration_hours,
+        );
+        match KEEP_RAW_DATA.load(Ordering::Relaxed) {
+            true => resource_with_impacts,
+            false => without_raw_data(resource_with_impacts),
+        }
     }
-}
 
-#[async_trait]
-impl ImpactProvider for BoaviztaApiV1 {
-    /// Get cloud resources impacts from the Boavizta API
-    /// The usage_duration_hours parameters allow to retrieve the impacts for a given duration.
-    async fn get_impacts(
+    /// Get cloud resources impacts from the Boavizta API, calling `on_progress` with the number of resources estimated and the total after each resource.
+    ///
+    /// The impacts of several resources are requested at the same time (see [crate::concurrency::estimation_concurrency], a limit of each region scanned concurrently), resources keeping the order of the inventory. The failed queries are returned in the errors of the estimated inventory.
+    pub async fn get_impacts_with_progress(
         &self,
         inventory: Inventory,
         usage_duration_hours: &f32,
         verbose: bool,
+        mut on_progress: impl FnMut(usize, usize) + Send,
     ) -> Result<EstimatedInventory> {
         let impact_query_start_time = Instant::now();
 
-        let mut v: Vec<CloudResourceWithImpacts> = Vec::new();
-        for resource in inventory.resources.iter() {
-            let cri = self
-                .get_resource_with_impacts(resource, usage_duration_hours, verbose)
-                .await;
-            v.push(cri.clone());
-        }
+        let (v, errors) = crate::scan_errors::collect(self.get_resources_with_impacts(
+            &inventory.resources,
+            usage_duration_hours,
+            verbose,
+            &mut on_progress,
+        ))
+        .await;
 
         let mut inventory_duration = Duration::from_millis(0);
         if let Some(exec_stats) = inventory.execution_statistics {
             inventory_duration = exec_stats.inventory_duration;
         }
         let impact_estimation_duration = impact_query_start_time.elapsed();
+        crate::server_telemetry::record_scan_stage(
+            ScanStage::ImpactEstimation,
+            None,
+            impact_estimation_duration,
+        );
         let execution_statistics = ExecutionStatistics {
             inventory_duration,
             impact_estimation_duration,
@@ -220,9 +680,108 @@ impl ImpactProvider for BoaviztaApiV1 {
         let estimated_inventory: EstimatedInventory = EstimatedInventory {
             impacting_resources: v,
             execution_statistics: Some(execution_statistics),
+            errors,
         };
         Ok(estimated_inventory)
     }
+
+    /// Returns resources (like a page of an inventory) with their impacts, requested at most [crate::concurrency::estimation_concurrency] at the same time (or within the adaptive limit of Boavizta API, see [crate::concurrency::set_adaptive]), reporting the number of estimated resources after each of them
+    pub async fn get_resources_with_impacts(
+        &self,
+        resources: &[CloudResource],
+        usage_duration_hours: &f32,
+        verbose: bool,
+        mut on_progress: impl FnMut(usize, usize) + Send,
+    ) -> Vec<CloudResourceWithImpacts> {
+        let total = resources.len();
+        let mut v: Vec<CloudResourceWithImpacts> = Vec::new();
+        let estimation = async {
+            // Collected first, so that the estimation does not hold the closure (and remains Send for the server)
+            let requests: Vec<_> = resources
+                .iter()
+                .map(|resource| {
+                    self.get_resource_with_impacts(resource, usage_duration_hours, verbose)
+                })
+                .collect();
+            let mut estimations = stream::iter(requests)
+                .buffered(crate::concurrency::estimation_requests_per_region());
+            while let Some(cri) = estimations.next().await {
+                v.push(cri);
+                on_progress(v.len(), total);
+            }
+        };
+        estimation
+            .instrument(tracing::info_span!(
+                "impact_estimation",
+                cloud_scanner.resources = total,
+            ))
+            .await;
+        v
+    }
+}
+
+#[async_trait]
+impl ImpactProvider for BoaviztaApiV1 {
+    /// Get cloud resources impacts from the Boavizta API
+    /// The usage_duration_hours parameters allow to retrieve the impacts for a given duration.
+    async fn get_impacts(
+        &self,
+        inventory: Inventory,
+        usage_duration_hours: &f32,
+        verbose: bool,
+    ) -> Result<EstimatedInventory> {
+        self.get_impacts_with_progress(inventory, usage_duration_hours, verbose, |_, _| {})
+            .await
+    }
+
+    /// Get the providers of Boavizta API with their instance types, and the archetypes of servers
+    async fn supported_types(&self) -> Result<SupportedTypes> {
+        let providers =
+            cloud_api::server_get_all_provider_name_v1_cloud_instance_all_providers_get(
+                &self.configuration,
+            )
+            .await
+            .context("Cannot list the cloud providers of Boavizta API")?;
+        let mut instance_types = BTreeMap::new();
+        for provider in providers.as_array().into_iter().flatten() {
+            let Some(provider) = provider.as_str() else {
+                continue;
+            };
+            let types =
+                cloud_api::server_get_all_archetype_name_v1_cloud_instance_all_instances_get(
+                    &self.configuration,
+                    Some(provider),
+                )
+                .await
+                .with_context(|| {
+                    format!(
+                        "Cannot list the instance types of {} in Boavizta API",
+                        provider
+                    )
+                })?;
+            instance_types.insert(provider.to_string(), json_strings(&types));
+        }
+        let archetypes =
+            server_api::server_get_all_archetype_name_v1_server_archetypes_get(&self.configuration)
+                .await
+                .context("Cannot list the server archetypes of Boavizta API")?;
+        Ok(SupportedTypes {
+            instance_types,
+            server_archetypes: json_strings(&archetypes),
+        })
+    }
+}
+
+/// Returns the strings of a json array, sorted
+fn json_strings(array: &serde_json::Value) -> Vec<String> {
+    let mut strings: Vec<String> = array
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|value| value.as_str().map(str::to_string))
+        .collect();
+    strings.sort();
+    strings
 }
 
 /// Convert raw results from Boavizta API into model objects
@@ -240,10 +799,7 @@ pub fn boa_impacts_to_cloud_resource_with_impacts(
         let resource_details = cloud_resource.resource_details.clone();
 
         match resource_details {
-            ResourceDetails::Instance {
-                instance_type: _,
-                usage: _,
-            } => {
+            ResourceDetails::Instance { .. } | ResourceDetails::Server { .. } => {
                 resource_impacts = Some(ImpactsValues {
                     adp_manufacture_kgsbeq: impacts["adp"]["embedded"]["value"].as_f64().unwrap(),
                     adp_use_kgsbeq: impacts["adp"]["use"]["value"].as_f64().unwrap(),
@@ -286,6 +842,7 @@ pub fn boa_impacts_to_cloud_resource_with_impacts(
         cloud_resource: cloud_resource.clone(),
         impacts_values: resource_impacts,
         impacts_duration_hours: impacts_duration_hours.to_owned(),
+        cost: None,
     }
 }
 
@@ -335,6 +892,7 @@ mod tests {
     async fn should_retrieve_raw_default_impacts_aws_fr() {
         let instance1: CloudResource = CloudResource {
             provider: CloudProvider::AWS,
+            account_id: None,
             id: "inst-1".to_string(),
             location: UsageLocation::try_from("eu-west-3").unwrap(),
             resource_details: ResourceDetails::Instance {
@@ -346,11 +904,12 @@ mod tests {
                 }),
             },
             tags: Vec::new(),
+            relationships: Vec::new(),
         };
         let api: BoaviztaApiV1 = BoaviztaApiV1::new(TEST_API_URL);
-        let one_hour = 1.0 as f32;
+        let one_hour = 1.0_f32;
         let res = api
-            .get_raws_impacts(instance1, &one_hour, false)
+            .get_raws_impacts(&instance1, &one_hour, false)
             .await
             .unwrap();
 
@@ -363,6 +922,7 @@ mod tests {
     async fn get_verbose_raw_impacts_of_a_hdd() {
         let hdd: CloudResource = CloudResource {
             provider: CloudProvider::AWS,
+            account_id: None,
             id: "disk-1".to_string(),
 
             location: UsageLocation::try_from("eu-west-3").unwrap(),
@@ -375,11 +935,12 @@ mod tests {
                 attached_instances: None,
             },
             tags: Vec::new(),
+            relationships: Vec::new(),
         };
 
         let api: BoaviztaApiV1 = BoaviztaApiV1::new(TEST_API_URL);
-        let one_hour = 1.0 as f32;
-        let res = api.get_raws_impacts(hdd, &one_hour, true).await.unwrap();
+        let one_hour = 1.0_f32;
+        let res = api.get_raws_impacts(&hdd, &one_hour, true).await.unwrap();
 
         let expected: serde_json::Value = serde_json::from_str(DEFAULT_RAW_IMPACTS_OF_HDD).unwrap();
 
@@ -392,6 +953,7 @@ mod tests {
     async fn get_verbose_raw_impacts_of_a_ssd() {
         let ssd: CloudResource = CloudResource {
             provider: CloudProvider::AWS,
+            account_id: None,
             id: "disk-1".to_string(),
             location: UsageLocation::try_from("eu-west-3").unwrap(),
             resource_details: ResourceDetails::BlockStorage {
@@ -403,11 +965,12 @@ mod tests {
                 attached_instances: None,
             },
             tags: Vec::new(),
+            relationships: Vec::new(),
         };
 
         let api: BoaviztaApiV1 = BoaviztaApiV1::new(TEST_API_URL);
-        let one_hour = 1.0 as f32;
-        let res = api.get_raws_impacts(ssd, &one_hour, true).await.unwrap();
+        let one_hour = 1.0_f32;
+        let res = api.get_raws_impacts(&ssd, &one_hour, true).await.unwrap();
 
         let expected: serde_json::Value =
             serde_json::from_str(DEFAULT_RAW_IMPACTS_OF_SSD_1000GB_1HR).unwrap();
@@ -420,6 +983,7 @@ mod tests {
     async fn returns_different_pe_impacts_for_different_cpu_load() {
         let instance1: CloudResource = CloudResource {
             provider: CloudProvider::AWS,
+            account_id: None,
             id: "inst-1".to_string(),
             location: UsageLocation::try_from("eu-west-3").unwrap(),
             resource_details: ResourceDetails::Instance {
@@ -431,10 +995,12 @@ mod tests {
                 }),
             },
             tags: Vec::new(),
+            relationships: Vec::new(),
         };
 
         let instance1_1percent = CloudResource {
             provider: CloudProvider::AWS,
+            account_id: None,
             id: "inst-2".to_string(),
             location: UsageLocation::try_from("eu-west-3").unwrap(),
             resource_details: ResourceDetails::Instance {
@@ -446,14 +1012,13 @@ mod tests {
                 }),
             },
             tags: Vec::new(),
+            relationships: Vec::new(),
         };
 
         let api: BoaviztaApiV1 = BoaviztaApiV1::new(TEST_API_URL);
-        let one_hour = 1.0 as f32;
+        let one_hour = 1.0_f32;
 
-        let mut instances: Vec<CloudResource> = Vec::new();
-        instances.push(instance1);
-        instances.push(instance1_1percent);
+        let instances: Vec<CloudResource> = vec![instance1, instance1_1percent];
 
         let inventory = Inventory {
             resources: instances,
@@ -472,6 +1037,7 @@ mod tests {
     async fn should_retrieve_multiple_default_impacts_fr() {
         let instance1: CloudResource = CloudResource {
             provider: CloudProvider::AWS,
+            account_id: None,
             id: "inst-1".to_string(),
             location: UsageLocation::try_from("eu-west-3").unwrap(),
             resource_details: ResourceDetails::Instance {
@@ -483,10 +1049,12 @@ mod tests {
                 }),
             },
             tags: Vec::new(),
+            relationships: Vec::new(),
         };
 
         let instance2: CloudResource = CloudResource {
             provider: CloudProvider::AWS,
+            account_id: None,
             id: "inst-2".to_string(),
             location: UsageLocation::try_from("eu-west-3").unwrap(),
             resource_details: ResourceDetails::Instance {
@@ -498,10 +1066,12 @@ mod tests {
                 }),
             },
             tags: Vec::new(),
+            relationships: Vec::new(),
         };
 
         let instance3: CloudResource = CloudResource {
             provider: CloudProvider::AWS,
+            account_id: None,
             id: "inst-3".to_string(),
             location: UsageLocation::try_from("eu-west-3").unwrap(),
             resource_details: ResourceDetails::Instance {
@@ -513,13 +1083,11 @@ mod tests {
                 }),
             },
             tags: Vec::new(),
+            relationships: Vec::new(),
         };
 
-        let mut instances: Vec<CloudResource> = Vec::new();
-        instances.push(instance1);
-        instances.push(instance2);
-        instances.push(instance3);
-        let one_hour = 1.0 as f32;
+        let instances: Vec<CloudResource> = vec![instance1, instance2, instance3];
+        let one_hour = 1.0_f32;
 
         let inventory = Inventory {
             resources: instances,
@@ -544,10 +1112,193 @@ mod tests {
         );
     }
 
+    #[test]
+    fn queries_describe_the_resources() {
+        let instance = CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: None,
+            id: "inst-1".to_string(),
+            location: UsageLocation::try_from("eu-west-3").unwrap(),
+            resource_details: ResourceDetails::Instance {
+                instance_type: "m6g.xlarge".to_string(),
+                usage: Some(InstanceUsage {
+                    average_cpu_load: 42.0,
+                    usage_duration_seconds: 300,
+                    state: InstanceState::Running,
+                }),
+            },
+            tags: Vec::new(),
+            relationships: Vec::new(),
+        };
+        let query = impacts_query(&instance, &2.0, true).unwrap();
+        assert_eq!("/v1/cloud/instance", query.path);
+        assert_eq!(
+            ("duration".to_string(), "2".to_string()),
+            query.parameters[1]
+        );
+        assert_eq!("m6g.xlarge", query.body["instance_type"]);
+        assert_eq!("FRA", query.body["usage"]["usage_location"]);
+        assert_eq!(42.0, query.body["usage"]["time_workload"]);
+
+        let hdd = CloudResource {
+            id: "vol-1".to_string(),
+            resource_details: ResourceDetails::BlockStorage {
+                storage_type: "st1".to_string(),
+                usage: Some(StorageUsage {
+                    size_gb: 500,
+                    usage_duration_seconds: 3600,
+                }),
+                attached_instances: None,
+            },
+            ..instance
+        };
+        let query = impacts_query(&hdd, &1.0, false).unwrap();
+        assert_eq!("/v1/component/hdd", query.path);
+        assert!(query
+            .parameters
+            .contains(&("archetype".to_string(), "DEFAULT".to_string())));
+        assert_eq!(500, query.body["capacity"]);
+
+        let server = CloudResource {
+            id: "device-1".to_string(),
+            resource_details: ResourceDetails::Server {
+                model: "PowerEdge R640".to_string(),
+                hardware: Some(ServerHardware {
+                    cpu_units: Some(2),
+                    cpu_cores: Some(16),
+                    memory_gb: Some(256),
+                    storage_gb: None,
+                }),
+                usage: None,
+            },
+            ..hdd
+        };
+        let query = impacts_query(&server, &1.0, false).unwrap();
+        assert_eq!("/v1/server/", query.path);
+        assert_eq!(16, query.body["configuration"]["cpu"]["core_units"]);
+        assert_eq!(256, query.body["configuration"]["ram"][0]["capacity"]);
+        assert!(query.body["configuration"]["disk"].is_null());
+        assert_eq!("FRA", query.body["usage"]["usage_location"]);
+    }
+
+    #[tokio::test]
+    async fn identical_queries_are_sent_once() {
+        let instance = |id: &str, average_cpu_load: f64| CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: None,
+            id: id.to_string(),
+            location: UsageLocation::try_from("eu-west-3").unwrap(),
+            resource_details: ResourceDetails::Instance {
+                instance_type: "m6g.xlarge".to_string(),
+                usage: Some(InstanceUsage {
+                    average_cpu_load,
+                    usage_duration_seconds: 300,
+                    state: InstanceState::Running,
+                }),
+            },
+            tags: Vec::new(),
+            relationships: Vec::new(),
+        };
+        let query = |resource: &CloudResource, bucket: u8| {
+            impacts_query(&with_bucketed_load(resource, bucket), &1.0, false).unwrap()
+        };
+        assert_ne!(
+            query(&instance("i-1", 41.6), 0),
+            query(&instance("i-2", 42.4), 0)
+        );
+        assert_eq!(
+            query(&instance("i-1", 41.6), 5),
+            query(&instance("i-2", 42.4), 5)
+        );
+        assert_eq!(
+            40.0,
+            query(&instance("i-1", 41.6), 5).body["usage"]["time_workload"]
+        );
+        assert_eq!(
+            100.0,
+            query(&instance("i-1", 100.0), 40).body["usage"]["time_workload"]
+        );
+        // Resources are not copied when there is nothing to round
+        assert!(matches!(
+            with_bucketed_load(&instance("i-1", 41.6), 0),
+            Cow::Borrowed(_)
+        ));
+
+        // Nothing listens on this port: failed queries are not memoized
+        let api = BoaviztaApiV1::new("http://127.0.0.1:1");
+        assert!(api
+            .get_memoized_raw_impacts(&instance("i-1", 42.0), &1.0, false)
+            .await
+            .is_none());
+        let key = serde_json::to_string(&query(&instance("i-1", 42.0), 0)).unwrap();
+        let answered = api.memo.lock().unwrap()[&key].clone();
+        answered
+            .set(serde_json::from_str(DEFAULT_RAW_IMPACTS_OF_M6GXLARGE_1HRS_FR).unwrap())
+            .unwrap();
+        let raw_impacts = api
+            .get_memoized_raw_impacts(&instance("i-2", 42.0), &1.0, false)
+            .await
+            .unwrap();
+        assert_eq!(0.212, raw_impacts["impacts"]["pe"]["use"]["value"]);
+    }
+
+    /// Records the names of the spans, after the name of their parent (like `scan > inventory`)
+    #[derive(Clone, Default)]
+    struct SpanNames(Arc<Mutex<Vec<String>>>);
+
+    impl<S> tracing_subscriber::Layer<S> for SpanNames
+    where
+        S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        fn on_new_span(
+            &self,
+            _attributes: &tracing::span::Attributes<'_>,
+            id: &tracing::span::Id,
+            context: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let span = context.span(id).unwrap();
+            let name = match span.parent() {
+                Some(parent) => format!("{} > {}", parent.name(), span.name()),
+                None => span.name().to_string(),
+            };
+            self.0.lock().unwrap().push(name);
+        }
+    }
+
+    #[tokio::test]
+    async fn estimation_of_impacts_is_traced() {
+        use tracing_subscriber::layer::SubscriberExt;
+        let span_names = SpanNames::default();
+        let _subscriber = tracing::subscriber::set_default(
+            tracing_subscriber::registry().with(span_names.clone()),
+        );
+        let bucket = CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: None,
+            id: "bucket-1".to_string(),
+            location: UsageLocation::try_from("eu-west-3").unwrap(),
+            resource_details: ResourceDetails::ObjectStorage,
+            tags: Vec::new(),
+            relationships: Vec::new(),
+        };
+        let inventory = Inventory {
+            resources: vec![bucket],
+            execution_statistics: None,
+        };
+        // Object storage is not sent to the API
+        let api = BoaviztaApiV1::new("http://127.0.0.1:1");
+        api.get_impacts(inventory, &1.0, false).await.unwrap();
+        assert_eq!(
+            vec!["impact_estimation", "impact_estimation > boavizta.impacts"],
+            *span_names.0.lock().unwrap()
+        );
+    }
+
     #[test]
     fn should_convert_basic_results_to_impacts() {
         let instance1: CloudResource = CloudResource {
             provider: CloudProvider::AWS,
+            account_id: None,
             id: "inst-1".to_string(),
             location: UsageLocation::try_from("eu-west-3").unwrap(),
             resource_details: ResourceDetails::Instance {
@@ -559,11 +1310,12 @@ mod tests {
                 }),
             },
             tags: Vec::new(),
+            relationships: Vec::new(),
         };
 
         let raw_impacts =
             Some(serde_json::from_str(DEFAULT_RAW_IMPACTS_OF_M6GXLARGE_1HRS_FR).unwrap());
-        let one_hour: f32 = 1 as f32;
+        let one_hour: f32 = 1_f32;
         let cloud_resource_with_impacts: CloudResourceWithImpacts =
             boa_impacts_to_cloud_resource_with_impacts(&instance1, &raw_impacts, &one_hour);
         assert!(
@@ -584,17 +1336,27 @@ mod tests {
             0.212,
             cloud_resource_with_impacts
                 .impacts_values
+                .as_ref()
                 .unwrap()
                 .raw_data
+                .as_ref()
                 .unwrap()["impacts"]["pe"]["use"]["value"]
                 .as_f64()
                 .unwrap()
         );
+
+        // Impacts are kept without the raw response
+        let impacts_values = without_raw_data(cloud_resource_with_impacts)
+            .impacts_values
+            .unwrap();
+        assert!(impacts_values.raw_data.is_none());
+        assert_eq!(0.212, impacts_values.pe_use_megajoules);
     }
     #[test]
     fn convert_verbose_results_to_impacts() {
         let instance1: CloudResource = CloudResource {
             provider: CloudProvider::AWS,
+            account_id: None,
             id: "inst-1".to_string(),
             location: UsageLocation::try_from("eu-west-3").unwrap(),
             resource_details: ResourceDetails::Instance {
@@ -606,11 +1368,12 @@ mod tests {
                 }),
             },
             tags: Vec::new(),
+            relationships: Vec::new(),
         };
 
         let raw_impacts =
             Some(serde_json::from_str(DEFAULT_RAW_IMPACTS_OF_M6GXLARGE_1HRS_FR_VERBOSE).unwrap());
-        let one_hour: f32 = 1 as f32;
+        let one_hour: f32 = 1_f32;
         let cloud_resource_with_impacts: CloudResourceWithImpacts =
             boa_impacts_to_cloud_resource_with_impacts(&instance1, &raw_impacts, &one_hour);
         assert!(
@@ -0,0 +1,389 @@
+//! Carbon budgets (`[[budgets]]` of the configuration file): the GWP (manufacture and use) a set of resources may emit over a period, compared by `budget status` to the scans of a result store, with the projection of the consumption to the end of the period.
+//!
+//! ```toml
+//! # 500 kgCO2eq a month for each value of the team tag
+//! [[budgets]]
+//! name = "teams"
+//! tag = "team"
+//! max_kgco2eq = 500.0
+//! period = "monthly"
+//!
+//! # 50 kgCO2eq a week for the resources tagged env=dev
+//! [[budgets]]
+//! name = "dev"
+//! tag = "env=dev"
+//! max_kgco2eq = 50.0
+//! period = "weekly"
+//! ```
+//!
+//! The consumption of the current period is aggregated from its start as in [crate::scan_aggregation]. It is projected to the end of the period at the rate of the hours covered by scans, a budget being projected to overrun when the projection exceeds it.
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use prometheus_client::encoding::text::encode;
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::registry::Registry;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64};
+
+use crate::allocation::AllocationRules;
+use crate::metric_exporter::DEFAULT_NAMESPACE;
+use crate::model::EstimatedInventory;
+use crate::scan_aggregation::{aggregate_scans, hours_between, AggregationPeriod, CoveredScan};
+
+fn default_period() -> String {
+    "monthly".to_string()
+}
+
+/// A carbon budget of the configuration file
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BudgetConfig {
+    pub name: String,
+    /// Resources of the budget: the resources with a tag (like `env=dev`), or a budget for each value of a tag (like `team`), all the resources when none
+    pub tag: Option<String>,
+    /// GWP (manufacture and use) allowed over a period
+    pub max_kgco2eq: f64,
+    /// Period of the budget: daily, weekly (starting on Monday) or monthly (default), in UTC
+    #[serde(default = "default_period")]
+    pub period: String,
+}
+
+impl BudgetConfig {
+    /// Returns the key of the tag of the budget and its value, if any
+    fn tag_filter(&self) -> (Option<&str>, Option<&str>) {
+        match self.tag.as_deref().map(|tag| tag.split_once('=')) {
+            None => (None, None),
+            Some(Some((key, value))) => (Some(key), Some(value)),
+            Some(None) => (self.tag.as_deref(), None),
+        }
+    }
+
+    fn aggregation_period(&self) -> Result<AggregationPeriod> {
+        AggregationPeriod::try_from(self.period.as_str())
+            .with_context(|| format!("Invalid period of budget {}", self.name))
+    }
+
+    /// Returns the start of the current period of the budget
+    pub fn period_start(&self, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+        Ok(self.aggregation_period()?.start_of(now))
+    }
+}
+
+/// State of a budget over its current period
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetState {
+    WithinBudget,
+    ProjectedOverrun,
+    Exceeded,
+}
+
+/// Consumption of a budget over its current period (of a value of the tag, for budgets per value of a tag)
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BudgetStatus {
+    pub budget: String,
+    /// Value of the tag of the budget, for budgets per value of a tag
+    pub tag_value: Option<String>,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub max_kgco2eq: f64,
+    /// GWP of the resources since the start of the period
+    pub consumed_kgco2eq: f64,
+    pub consumed_percent: f64,
+    /// Hours since the start of the period covered by scans
+    pub covered_hours: f64,
+    /// GWP at the end of the period, at the rate of the covered hours
+    pub projected_kgco2eq: f64,
+    /// When the budget is projected to be exceeded, for budgets projected to overrun
+    pub projected_overrun_at: Option<DateTime<Utc>>,
+    pub state: BudgetState,
+}
+
+/// Returns the status of the budgets at a date, from the scans (with their estimated inventory) of their current periods, the shared resources being allocated to the budgets per value of a tag by the allocation rules
+pub fn budget_statuses(
+    budgets: &[BudgetConfig],
+    allocations: &AllocationRules,
+    scans: &[(CoveredScan, EstimatedInventory)],
+    now: DateTime<Utc>,
+) -> Result<Vec<BudgetStatus>> {
+    let mut statuses = Vec::new();
+    for budget in budgets.iter() {
+        let period = budget.aggregation_period()?;
+        let period_start = period.start_of(now);
+        let period_end = period.next(period_start);
+        let (tag_key, tag_value) = budget.tag_filter();
+        let rows = aggregate_scans(
+            scans,
+            period_start,
+            now,
+            period,
+            tag_key,
+            allocations,
+            false,
+        );
+        let covered_hours = rows.first().map(|row| row.covered_hours).unwrap_or(0.0);
+        let mut consumption: BTreeMap<Option<String>, f64> = BTreeMap::new();
+        for row in rows.iter() {
+            let key = match (tag_key, tag_value) {
+                (None, _) => None,
+                // Resources without the tag are not part of budgets of tags
+                (Some(_), _) if row.tag_value.is_none() => continue,
+                (Some(_), Some(value)) if row.tag_value.as_deref() != Some(value) => continue,
+                (Some(_), Some(_)) => None,
+                (Some(_), None) => row.tag_value.clone(),
+            };
+            *consumption.entry(key).or_default() +=
+                row.gwp_manufacture_kgco2eq + row.gwp_use_kgco2eq;
+        }
+        // Budgets of a set of resources are reported even before any consumption
+        if (consumption.is_empty() && tag_value.is_some()) || tag_key.is_none() {
+            consumption.entry(None).or_default();
+        }
+        for (tag_value, consumed_kgco2eq) in consumption.into_iter() {
+            let rate = if covered_hours > 0.0 {
+                consumed_kgco2eq / covered_hours
+            } else {
+                0.0
+            };
+            let projected_kgco2eq = consumed_kgco2eq + rate * hours_between(now, period_end);
+            let (state, projected_overrun_at) = if consumed_kgco2eq > budget.max_kgco2eq {
+                (BudgetState::Exceeded, None)
+            } else if projected_kgco2eq > budget.max_kgco2eq {
+                let hours = (budget.max_kgco2eq - consumed_kgco2eq) / rate;
+                (
+                    BudgetState::ProjectedOverrun,
+                    Some(now + chrono::Duration::seconds((hours * 3600.0) as i64)),
+                )
+            } else {
+                (BudgetState::WithinBudget, None)
+            };
+            statuses.push(BudgetStatus {
+                budget: budget.name.clone(),
+                tag_value,
+                period_start,
+                period_end,
+                max_kgco2eq: budget.max_kgco2eq,
+                consumed_kgco2eq,
+                consumed_percent: if budget.max_kgco2eq > 0.0 {
+                    consumed_kgco2eq / budget.max_kgco2eq * 100.0
+                } else {
+                    0.0
+                },
+                covered_hours,
+                projected_kgco2eq,
+                projected_overrun_at,
+                state,
+            });
+        }
+    }
+    Ok(statuses)
+}
+
+/// Returns the status of the budgets as text, one line per budget (and value of its tag)
+pub fn to_text(statuses: &[BudgetStatus]) -> String {
+    let mut text = String::new();
+    for status in statuses.iter() {
+        let _ = writeln!(
+            text,
+            "{}{} ({} to {}): {:.3} of {:.3} kgCO2eq ({:.1} %), projected {:.3} kgCO2eq, {}",
+            status.budget,
+            status
+                .tag_value
+                .as_ref()
+                .map(|value| format!(" [{}]", value))
+                .unwrap_or_default(),
+            status.period_start.format("%Y-%m-%d"),
+            status.period_end.format("%Y-%m-%d"),
+            status.consumed_kgco2eq,
+            status.max_kgco2eq,
+            status.consumed_percent,
+            status.projected_kgco2eq,
+            match (status.state, status.projected_overrun_at) {
+                (BudgetState::Exceeded, _) => "exceeded".to_string(),
+                (BudgetState::ProjectedOverrun, Some(at)) =>
+                    format!("projected to overrun on {}", at.to_rfc3339()),
+                _ => "within budget".to_string(),
+            }
+        );
+    }
+    text
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+struct BudgetLabels {
+    budget: String,
+    /// Empty for the budgets that are not per value of a tag
+    tag_value: String,
+}
+
+/// Returns the status of the budgets as metrics in the prometheus format
+pub fn get_budget_metrics(statuses: &[BudgetStatus]) -> Result<String> {
+    let mut registry = Registry::with_prefix(DEFAULT_NAMESPACE);
+    let max = Family::<BudgetLabels, Gauge<f64, AtomicU64>>::default();
+    let consumed = Family::<BudgetLabels, Gauge<f64, AtomicU64>>::default();
+    let projected = Family::<BudgetLabels, Gauge<f64, AtomicU64>>::default();
+    let overrun = Family::<BudgetLabels, Gauge<i64, AtomicI64>>::default();
+    for status in statuses.iter() {
+        let labels = BudgetLabels {
+            budget: status.budget.clone(),
+            tag_value: status.tag_value.clone().unwrap_or_default(),
+        };
+        max.get_or_create(&labels).set(status.max_kgco2eq);
+        consumed.get_or_create(&labels).set(status.consumed_kgco2eq);
+        projected
+            .get_or_create(&labels)
+            .set(status.projected_kgco2eq);
+        if let Some(at) = status.projected_overrun_at {
+            overrun.get_or_create(&labels).set(at.timestamp());
+        }
+    }
+    registry.register(
+        "budget_max_kgco2eq",
+        "GWP allowed by the budget over its period",
+        max,
+    );
+    registry.register(
+        "budget_consumed_kgco2eq",
+        "GWP of the resources of the budget since the start of its period",
+        consumed,
+    );
+    registry.register(
+        "budget_projected_kgco2eq",
+        "GWP of the resources of the budget projected to the end of its period",
+        projected,
+    );
+    registry.register(
+        "budget_projected_overrun_timestamp_seconds",
+        "Date when the budget is projected to be exceeded, for the budgets projected to overrun",
+        overrun,
+    );
+    let mut buffer = String::new();
+    encode(&mut buffer, &registry).context("Fails to encode the budgets into metrics")?;
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+    use crate::model::{CloudProvider, CloudResource, CloudResourceTag, ResourceDetails};
+    use crate::scan_aggregation::covered_scans;
+    use crate::usage_location::UsageLocation;
+
+    fn date(day: u32, hour: u32) -> DateTime<Utc> {
+        format!("2024-05-{:02}T{:02}:00:00Z", day, hour)
+            .parse()
+            .unwrap()
+    }
+
+    /// Daily scans from the 1st of May, each resource emitting 10 kgCO2eq a day
+    fn daily_scans(days: u32, teams: &[&str]) -> Vec<(CoveredScan, EstimatedInventory)> {
+        let inventory = EstimatedInventory {
+            impacting_resources: teams
+                .iter()
+                .enumerate()
+                .map(|(index, team)| CloudResourceWithImpacts {
+                    cloud_resource: CloudResource {
+                        provider: CloudProvider::AWS,
+                        id: format!("i-{}", index),
+                        location: UsageLocation::try_from("eu-west-3").unwrap(),
+                        resource_details: ResourceDetails::Instance {
+                            instance_type: "t3.micro".to_string(),
+                            usage: None,
+                        },
+                        tags: vec![CloudResourceTag {
+                            key: "team".to_string(),
+                            value: Some(team.to_string()),
+                        }],
+                        account_id: None,
+                        relationships: Vec::new(),
+                    },
+                    impacts_values: Some(ImpactsValues {
+                        gwp_manufacture_kgco2eq: 4.0,
+                        gwp_use_kgco2eq: 6.0,
+                        ..Default::default()
+                    }),
+                    impacts_duration_hours: 24.0,
+                    cost: None,
+                })
+                .collect(),
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let scans: Vec<(i64, DateTime<Utc>, f64)> = (1..=days)
+            .map(|day| (day as i64, date(day, 0), 24.0))
+            .collect();
+        covered_scans(&scans, date(1, 0), date(days + 1, 0))
+            .into_iter()
+            .map(|scan| (scan, inventory.clone()))
+            .collect()
+    }
+
+    fn budget(tag: Option<&str>, max_kgco2eq: f64) -> BudgetConfig {
+        BudgetConfig {
+            name: "teams".to_string(),
+            tag: tag.map(str::to_string),
+            max_kgco2eq,
+            period: default_period(),
+        }
+    }
+
+    #[test]
+    fn budgets_per_tag_value_are_projected_to_the_end_of_the_period() {
+        let scans = daily_scans(10, &["a", "b", "b"]);
+        let statuses = budget_statuses(
+            &[budget(Some("team"), 500.0)],
+            &AllocationRules::default(),
+            &scans,
+            date(11, 0),
+        )
+        .unwrap();
+        assert_eq!(2, statuses.len());
+
+        let a = &statuses[0];
+        assert_eq!(Some("a"), a.tag_value.as_deref());
+        assert_eq!(100.0, a.consumed_kgco2eq);
+        // 10 kgCO2eq a day until the 1st of June
+        assert!((a.projected_kgco2eq - 310.0).abs() < 1e-9);
+        assert_eq!(BudgetState::WithinBudget, a.state);
+
+        let b = &statuses[1];
+        assert_eq!(200.0, b.consumed_kgco2eq);
+        assert_eq!(BudgetState::ProjectedOverrun, b.state);
+        // 300 kgCO2eq left at 20 kgCO2eq a day
+        assert_eq!(Some(date(26, 0)), b.projected_overrun_at);
+
+        let metrics = get_budget_metrics(&statuses).unwrap();
+        assert!(metrics
+            .contains("boavizta_budget_consumed_kgco2eq{budget=\"teams\",tag_value=\"b\"} 200.0"));
+        assert!(to_text(&statuses).contains("projected to overrun on 2024-05-26"));
+    }
+
+    #[test]
+    fn budgets_of_a_tag_value_or_of_all_resources() {
+        let scans = daily_scans(10, &["a", "b", "b"]);
+        let statuses = budget_statuses(
+            &[budget(Some("team=a"), 50.0), budget(None, 1000.0)],
+            &AllocationRules::default(),
+            &scans,
+            date(11, 0),
+        )
+        .unwrap();
+        assert_eq!(2, statuses.len());
+        assert_eq!(BudgetState::Exceeded, statuses[0].state);
+        assert_eq!(None, statuses[0].tag_value);
+        assert_eq!(300.0, statuses[1].consumed_kgco2eq);
+
+        let invalid = BudgetConfig {
+            period: "yearly".to_string(),
+            ..budget(None, 1.0)
+        };
+        assert!(
+            budget_statuses(&[invalid], &AllocationRules::default(), &scans, date(11, 0)).is_err()
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Checkpoints of long scans: the progress of a scan (the regions already scanned, and the resources of the regions being scanned that are already estimated) is saved periodically to a local checkpoint file, so that an interrupted scan of many accounts and regions resumes where it stopped instead of restarting from zero.
+//!
+//! A resumed scan reuses the results of the regions scanned completely, and lists the other regions again, reusing the impacts of their resources estimated before the interruption (when they did not change). The checkpoint is ignored when the scan uses other parameters, and removed once the scan succeeds.
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::aws_cloud_provider::AwsAccount;
+use crate::impact_provider::CloudResourceWithImpacts;
+use crate::model::{CloudResource, EstimatedInventory};
+
+/// Checkpoint file used when none is passed
+pub const DEFAULT_CHECKPOINT_FILE: &str = ".cloud-scanner-checkpoint.json";
+
+/// Minimum duration between two saves of the checkpoint while regions are being estimated (it is also saved when a region is scanned completely)
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Parameters of a scan that its results depend on
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CheckpointParameters {
+    pub use_duration_hours: f32,
+    pub api_url: String,
+    pub verbose: bool,
+    pub include_block_storage: bool,
+    pub filter_tags: Vec<String>,
+}
+
+/// Results of a region scanned completely
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ScannedRegion {
+    pub estimated_inventory: EstimatedInventory,
+    /// Number of resources excluded by the ignore rules
+    pub excluded: usize,
+}
+
+/// The progress of a scan
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub parameters: CheckpointParameters,
+    /// The regions scanned completely, by account and region (see [scan_key])
+    pub scanned: BTreeMap<String, ScannedRegion>,
+    /// The resources already estimated of the regions being scanned, by id
+    pub estimated: BTreeMap<String, CloudResourceWithImpacts>,
+}
+
+/// Returns the key of the scan of a region of an account (the account of the environment being empty)
+pub fn scan_key(account: Option<&AwsAccount>, aws_region: &str) -> String {
+    format!(
+        "{}|{}",
+        account
+            .map(|account| account.account_id.as_str())
+            .unwrap_or_default(),
+        aws_region
+    )
+}
+
+impl Checkpoint {
+    /// Returns an empty checkpoint
+    pub fn new(parameters: CheckpointParameters) -> Self {
+        Checkpoint {
+            parameters,
+            scanned: BTreeMap::new(),
+            estimated: BTreeMap::new(),
+        }
+    }
+
+    /// Reads a checkpoint file, an empty checkpoint being returned when it does not exist or was written by a scan with other parameters
+    pub fn load(path: &str, parameters: &CheckpointParameters) -> Result<Checkpoint> {
+        if !Path::new(path).exists() {
+            return Ok(Checkpoint::new(parameters.clone()));
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read checkpoint file {}", path))?;
+        let checkpoint: Checkpoint = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid checkpoint file {}", path))?;
+        if &checkpoint.parameters != parameters {
+            info!(
+                "Checkpoint file {} was written by a scan with other parameters, scanning from the start",
+                path
+            );
+            return Ok(Checkpoint::new(parameters.clone()));
+        }
+        info!(
+            "Resuming the scan of checkpoint file {} ({} regions scanned, {} resources estimated)",
+            path,
+            checkpoint.scanned.len(),
+            checkpoint.estimated.len()
+        );
+        Ok(checkpoint)
+    }
+
+    /// Writes the checkpoint file (replaced at once, so that an interruption while writing keeps the previous checkpoint)
+    pub fn save(&self, path: &str) -> Result<()> {
+        let content = serde_json::to_string(self)?;
+        let temporary = format!("{}.tmp", path);
+        std::fs::write(&temporary, content)
+            .and_then(|_| std::fs::rename(&temporary, path))
+            .with_context(|| format!("Cannot write checkpoint file {}", path))
+    }
+
+    /// Returns the impacts of a resource estimated before the interruption, if the resource did not change
+    pub fn estimated_impacts(&self, resource: &CloudResource) -> Option<CloudResourceWithImpacts> {
+        let estimated = self.estimated.get(&resource.id)?;
+        let unchanged = serde_json::to_value(&estimated.cloud_resource).ok()
+            == serde_json::to_value(resource).ok();
+        unchanged.then(|| estimated.clone())
+    }
+
+    /// Records a region scanned completely, whose resources no longer need to be kept one by one
+    pub fn record_scanned_region(&mut self, key: String, scanned_region: ScannedRegion) {
+        for resource_with_impacts in scanned_region
+            .estimated_inventory
+            .impacting_resources
+            .iter()
+        {
+            self.estimated
+                .remove(&resource_with_impacts.cloud_resource.id);
+        }
+        self.scanned.insert(key, scanned_region);
+    }
+}
+
+/// The checkpoint of the scan of the process (if enabled)
+struct CheckpointedScan {
+    path: String,
+    checkpoint: Checkpoint,
+    saved_at: Instant,
+}
+
+impl CheckpointedScan {
+    fn save(&mut self) {
+        if let Err(e) = self.checkpoint.save(&self.path) {
+            warn!("Cannot save the progress of the scan: {:#}", e);
+        }
+        self.saved_at = Instant::now();
+    }
+}
+
+static CHECKPOINT: Mutex<Option<CheckpointedScan>> = Mutex::new(None);
+
+/// Checkpoints the progress of scans to a file from now on, resuming the scan it was saved by (if any)
+pub fn enable(path: &str, parameters: CheckpointParameters) -> Result<()> {
+    let checkpoint = Checkpoint::load(path, &parameters)?;
+    *CHECKPOINT.lock().unwrap() = Some(CheckpointedScan {
+        path: path.to_string(),
+        checkpoint,
+        saved_at: Instant::now(),
+    });
+    Ok(())
+}
+
+/// Returns the results of a region scanned completely before the interruption (None when checkpoints are not enabled)
+pub fn scanned_region(account: Option<&AwsAccount>, aws_region: &str) -> Option<ScannedRegion> {
+    CHECKPOINT.lock().unwrap().as_ref().and_then(|scan| {
+        scan.checkpoint
+            .scanned
+            .get(&scan_key(account, aws_region))
+            .cloned()
+    })
+}
+
+/// Returns the impacts of an unchanged resource estimated before the interruption (None when checkpoints are not enabled)
+pub fn estimated_impacts(resource: &CloudResource) -> Option<CloudResourceWithImpacts> {
+    CHECKPOINT
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|scan| scan.checkpoint.estimated_impacts(resource))
+}
+
+/// Adds estimated resources to the checkpoint (if enabled), that is saved when the last save is old enough
+pub fn record(resources_with_impacts: &[CloudResourceWithImpacts]) {
+    if let Some(scan) = CHECKPOINT.lock().unwrap().as_mut() {
+        for resource_with_impacts in resources_with_impacts {
+            scan.checkpoint.estimated.insert(
+                resource_with_impacts.cloud_resource.id.clone(),
+                resource_with_impacts.clone(),
+            );
+        }
+        if scan.saved_at.elapsed() >= SAVE_INTERVAL {
+            scan.save();
+        }
+    }
+}
+
+/// Adds a region scanned completely to the checkpoint (if enabled), and saves it
+pub fn record_scanned_region(
+    account: Option<&AwsAccount>,
+    aws_region: &str,
+    estimated_inventory: &EstimatedInventory,
+    excluded: usize,
+) {
+    if let Some(scan) = CHECKPOINT.lock().unwrap().as_mut() {
+        scan.checkpoint.record_scanned_region(
+            scan_key(account, aws_region),
+            ScannedRegion {
+                estimated_inventory: estimated_inventory.clone(),
+                excluded,
+            },
+        );
+        scan.save();
+    }
+}
+
+/// Removes the checkpoint file of a scan that succeeded (if checkpoints are enabled)
+pub fn finish() -> Result<()> {
+    if let Some(scan) = CHECKPOINT.lock().unwrap().take() {
+        if Path::new(&scan.path).exists() {
+            std::fs::remove_file(&scan.path)
+                .with_context(|| format!("Cannot remove checkpoint file {}", scan.path))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::ImpactsValues;
+    use crate::model::{CloudProvider, InstanceState, InstanceUsage, ResourceDetails};
+    use crate::usage_location::UsageLocation;
+
+    fn instance(id: &str, instance_type: &str) -> CloudResourceWithImpacts {
+        CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: id.to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details: ResourceDetails::Instance {
+                    instance_type: instance_type.to_string(),
+                    usage: Some(InstanceUsage {
+                        average_cpu_load: 42.0,
+                        usage_duration_seconds: 300,
+                        state: InstanceState::Running,
+                    }),
+                },
+                tags: Vec::new(),
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues {
+                gwp_use_kgco2eq: 1.5,
+                ..ImpactsValues::default()
+            }),
+            impacts_duration_hours: 730.0,
+            cost: None,
+        }
+    }
+
+    fn parameters(use_duration_hours: f32) -> CheckpointParameters {
+        CheckpointParameters {
+            use_duration_hours,
+            api_url: "https://api.boavizta.org".to_string(),
+            verbose: false,
+            include_block_storage: false,
+            filter_tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn interrupted_scans_resume_from_checkpoint() {
+        let mut checkpoint = Checkpoint::new(parameters(730.0));
+        checkpoint
+            .estimated
+            .insert("i-1".to_string(), instance("i-1", "m6g.xlarge"));
+        checkpoint
+            .estimated
+            .insert("i-2".to_string(), instance("i-2", "m6g.xlarge"));
+
+        let resource = instance("i-1", "m6g.xlarge").cloud_resource;
+        assert!(checkpoint.estimated_impacts(&resource).is_some());
+        // Changed resources are estimated again
+        let changed = instance("i-1", "m6g.2xlarge").cloud_resource;
+        assert!(checkpoint.estimated_impacts(&changed).is_none());
+
+        checkpoint.record_scanned_region(
+            scan_key(None, "eu-west-3"),
+            ScannedRegion {
+                estimated_inventory: EstimatedInventory {
+                    impacting_resources: vec![instance("i-1", "m6g.xlarge")],
+                    execution_statistics: None,
+                    errors: Vec::new(),
+                },
+                excluded: 2,
+            },
+        );
+        assert_eq!(vec!["i-2"], checkpoint.estimated.keys().collect::<Vec<_>>());
+
+        let path =
+            std::env::temp_dir().join(format!("cloud-scanner-checkpoint-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        checkpoint.save(path).unwrap();
+        let loaded = Checkpoint::load(path, &parameters(730.0)).unwrap();
+        assert_eq!(2, loaded.scanned["|eu-west-3"].excluded);
+        assert_eq!(1, loaded.estimated.len());
+        // Results depend on the duration of use
+        assert!(Checkpoint::load(path, &parameters(24.0))
+            .unwrap()
+            .scanned
+            .is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+//! Conditions failing a scan (with a specific exit code), so that carbon budgets can gate CI pipelines like test failures do.
+use crate::impact_provider::ImpactsSummary;
+use crate::model::EstimatedInventory;
+use crate::notifier::ThresholdBreach;
+use crate::scan_diff;
+
+/// Exit code of the CLI when a condition is breached (errors exit with 1)
+pub const BREACH_EXIT_CODE: i32 = 2;
+
+/// Conditions on the total GWP of a scan (manufacture and use, over the duration of use)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FailConditions {
+    pub max_gwp_kgco2eq: Option<f64>,
+    /// Maximum increase of the GWP compared to a baseline scan, in percent
+    pub max_increase_percent: Option<f64>,
+}
+
+impl FailConditions {
+    /// Returns the conditions breached by a scan, the increase being checked against the baseline scan (if any)
+    pub fn check(
+        &self,
+        summary: &ImpactsSummary,
+        estimated_inventory: &EstimatedInventory,
+        baseline: Option<&EstimatedInventory>,
+    ) -> Vec<ThresholdBreach> {
+        let mut breaches = Vec::new();
+        let gwp = summary.gwp_manufacture_kgco2eq + summary.gwp_use_kgco2eq;
+        if let Some(max_gwp) = self.max_gwp_kgco2eq {
+            if gwp > max_gwp {
+                breaches.push(ThresholdBreach {
+                    impact: "GWP".to_string(),
+                    unit: "kgCO2eq".to_string(),
+                    value: gwp,
+                    threshold: max_gwp,
+                });
+            }
+        }
+        if let (Some(max_increase), Some(baseline)) = (self.max_increase_percent, baseline) {
+            let totals = scan_diff::diff_scans(baseline, estimated_inventory).totals;
+            let old = totals.gwp_manufacture_kgco2eq.old + totals.gwp_use_kgco2eq.old;
+            let new = totals.gwp_manufacture_kgco2eq.new + totals.gwp_use_kgco2eq.new;
+            match scan_diff::ValueDelta::new(old, new).percent {
+                Some(increase) if increase > max_increase => breaches.push(ThresholdBreach {
+                    impact: "GWP increase".to_string(),
+                    unit: "%".to_string(),
+                    value: increase,
+                    threshold: max_increase,
+                }),
+                Some(_) => {}
+                None => warn!("The GWP of the baseline is 0, cannot compute the increase of GWP"),
+            }
+        }
+        breaches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+    use crate::model::{CloudProvider, CloudResource, ResourceDetails};
+    use crate::usage_location::UsageLocation;
+
+    fn scan(gwp_of_resources: &[f64]) -> EstimatedInventory {
+        let impacting_resources = gwp_of_resources
+            .iter()
+            .enumerate()
+            .map(|(i, gwp)| CloudResourceWithImpacts {
+                cloud_resource: CloudResource {
+                    provider: CloudProvider::AWS,
+                    account_id: None,
+                    id: format!("i-{}", i),
+                    location: UsageLocation::try_from("eu-west-3").unwrap(),
+                    resource_details: ResourceDetails::Instance {
+                        instance_type: "m5.large".to_string(),
+                        usage: None,
+                    },
+                    tags: Vec::new(),
+                    relationships: Vec::new(),
+                },
+                impacts_values: Some(ImpactsValues {
+                    gwp_use_kgco2eq: *gwp,
+                    ..Default::default()
+                }),
+                impacts_duration_hours: 1.0,
+                cost: None,
+            })
+            .collect();
+        EstimatedInventory {
+            impacting_resources,
+            execution_statistics: None,
+            errors: Vec::new(),
+        }
+    }
+
+    fn summary(scan: &EstimatedInventory) -> ImpactsSummary {
+        ImpactsSummary::new("eu-west-3".to_string(), "FRA".to_string(), scan, 1.0)
+    }
+
+    #[test]
+    fn gwp_above_the_maximum_is_breached() {
+        let conditions = FailConditions {
+            max_gwp_kgco2eq: Some(2.0),
+            ..Default::default()
+        };
+        let small = scan(&[1.0, 0.5]);
+        assert!(conditions.check(&summary(&small), &small, None).is_empty());
+        let large = scan(&[1.0, 1.5]);
+        let breaches = conditions.check(&summary(&large), &large, None);
+        assert_eq!(
+            vec!["GWP of 2.500 kgCO2eq exceeds the threshold of 2 kgCO2eq"],
+            breaches.iter().map(|b| b.to_string()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn increase_above_the_maximum_is_breached() {
+        let conditions = FailConditions {
+            max_increase_percent: Some(10.0),
+            ..Default::default()
+        };
+        let baseline = scan(&[1.0, 1.0]);
+        let stable = scan(&[1.0, 1.1]);
+        assert!(conditions
+            .check(&summary(&stable), &stable, Some(&baseline))
+            .is_empty());
+        let increased = scan(&[1.0, 1.0, 0.5]);
+        let breaches = conditions.check(&summary(&increased), &increased, Some(&baseline));
+        assert_eq!(1, breaches.len());
+        assert_eq!("GWP increase", breaches[0].impact);
+        assert!((breaches[0].value - 25.0).abs() < 1e-9);
+        assert!(conditions
+            .check(&summary(&increased), &increased, Some(&scan(&[])))
+            .is_empty());
+    }
+}
@@ -0,0 +1,485 @@
+//! Hypothetical inventories of the resources of a CloudFormation (or SAM) template, so that the footprint of a stack is estimated when the template is reviewed, before the stack is deployed.
+//!
+//! Templates are read as yaml or json, with their short form intrinsic functions (like `!Ref`). References to parameters (their default values, or the values passed) and to `AWS::Region` are resolved, the other intrinsic functions are not: resources whose type or size is not resolved are ignored (with a warning). Conditions of resources are not evaluated (all the resources are estimated).
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+use crate::model::{
+    CloudProvider, CloudResource, CloudResourceTag, Inventory, ResourceDetails,
+    ResourceRelationship, StorageAttachment, StorageUsage, DEFAULT_VOLUME_TYPE,
+};
+use crate::tag_filter::TagFilter;
+use crate::tag_normalization;
+use crate::usage_location::UsageLocation;
+
+/// Parses the resources of a template: instances (including the instances of auto scaling groups, at their desired capacity), and with `include_block_storage` volumes (including the block device mappings of instances).
+///
+/// `parameters` override the default values of the parameters of the template (like `InstanceType=m6g.xlarge`), and resources are located in `aws_region`.
+pub fn parse_cloudformation_template(
+    content: &str,
+    aws_region: &str,
+    parameters: &HashMap<String, String>,
+    tags: &[String],
+    include_block_storage: bool,
+) -> Result<Inventory> {
+    let yaml: serde_yaml::Value = serde_yaml::from_str(content)
+        .context("Expected a CloudFormation template (yaml or json)")?;
+    let template = to_json(yaml);
+    let template_resources = template["Resources"]
+        .as_object()
+        .context("Expected a CloudFormation template (no Resources)")?;
+    let location = UsageLocation::try_from(aws_region)?;
+    let filter = TagFilter::parse_all(tags)?;
+
+    let mut values: HashMap<String, String> = HashMap::new();
+    if let Some(template_parameters) = template["Parameters"].as_object() {
+        for (name, parameter) in template_parameters {
+            if let Some(default) = as_string(&parameter["Default"]) {
+                values.insert(name.clone(), default);
+            }
+        }
+    }
+    values.extend(parameters.clone());
+    values.insert("AWS::Region".to_string(), aws_region.to_string());
+    let template = Template {
+        resources: template_resources,
+        parameters: values,
+    };
+
+    let mut resources = Vec::new();
+    for (logical_id, resource) in template_resources {
+        let properties = &resource["Properties"];
+        let resource_tags = tag_normalization::normalize(template.tags(properties));
+        if !filter.matches(&resource_tags) {
+            debug!("Filtered {} (tags do not match)", logical_id);
+            continue;
+        }
+        let resource_with = |id: String, resource_details: ResourceDetails| CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: None,
+            id,
+            location: location.clone(),
+            resource_details,
+            tags: resource_tags.clone(),
+            relationships: Vec::new(),
+        };
+        match resource["Type"].as_str() {
+            Some("AWS::EC2::Instance") => {
+                // The instance type of a launch template is overridden by the instance
+                let launch_template = template.launch_template(properties);
+                let instance_type = template.string(&properties["InstanceType"]).or_else(|| {
+                    launch_template.and_then(|data| template.string(&data["InstanceType"]))
+                });
+                let Some(instance_type) = instance_type else {
+                    warn!(
+                        "Ignoring instance {} (unresolved instance type)",
+                        logical_id
+                    );
+                    continue;
+                };
+                let mappings = match properties["BlockDeviceMappings"].is_null() {
+                    true => launch_template.map(|data| &data["BlockDeviceMappings"]),
+                    false => Some(&properties["BlockDeviceMappings"]),
+                };
+                resources.push(resource_with(
+                    logical_id.clone(),
+                    instance_details(&instance_type),
+                ));
+                if include_block_storage {
+                    for (id, details) in template.block_devices(logical_id, mappings) {
+                        resources.push(resource_with(id, details));
+                    }
+                }
+            }
+            Some("AWS::AutoScaling::AutoScalingGroup") => {
+                let launch_data = template
+                    .launch_template(properties)
+                    .or_else(|| template.launch_configuration(properties));
+                let instance_type =
+                    launch_data.and_then(|data| template.string(&data["InstanceType"]));
+                let Some(instance_type) = instance_type else {
+                    warn!(
+                        "Ignoring auto scaling group {} (unresolved instance type)",
+                        logical_id
+                    );
+                    continue;
+                };
+                let capacity = template
+                    .count(&properties["DesiredCapacity"])
+                    .or_else(|| template.count(&properties["MinSize"]))
+                    .unwrap_or(1);
+                for index in 0..capacity {
+                    let id = format!("{}[{}]", logical_id, index);
+                    let mut member = resource_with(id.clone(), instance_details(&instance_type));
+                    member.relationships = vec![ResourceRelationship::MemberOfAutoScalingGroup {
+                        name: logical_id.clone(),
+                    }];
+                    resources.push(member);
+                    if include_block_storage {
+                        let mappings = launch_data.map(|data| &data["BlockDeviceMappings"]);
+                        for (volume_id, details) in template.block_devices(&id, mappings) {
+                            resources.push(resource_with(volume_id, details));
+                        }
+                    }
+                }
+            }
+            Some("AWS::EC2::Volume") if include_block_storage => {
+                match template.volume_details(&properties["VolumeType"], &properties["Size"]) {
+                    Some(details) => resources.push(resource_with(logical_id.clone(), details)),
+                    None => warn!("Ignoring volume {} (unresolved size)", logical_id),
+                }
+            }
+            _ => debug!(
+                "Ignoring {} (not an estimated kind of resource)",
+                logical_id
+            ),
+        }
+    }
+    Ok(Inventory {
+        resources,
+        execution_statistics: None,
+    })
+}
+
+/// Reads the resources of a template from a yaml or json file, see [parse_cloudformation_template]
+pub fn read_cloudformation_template(
+    path: &str,
+    aws_region: &str,
+    parameters: &HashMap<String, String>,
+    tags: &[String],
+    include_block_storage: bool,
+) -> Result<Inventory> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read CloudFormation template {}", path))?;
+    parse_cloudformation_template(
+        &content,
+        aws_region,
+        parameters,
+        tags,
+        include_block_storage,
+    )
+    .with_context(|| format!("Cannot parse CloudFormation template {}", path))
+}
+
+/// Parses the parameters of a template passed as `Name=Value`
+pub fn parse_parameters(parameters: &[String]) -> Result<HashMap<String, String>> {
+    parameters
+        .iter()
+        .map(|parameter| {
+            parameter
+                .split_once('=')
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .with_context(|| format!("Expected a parameter like Name=Value, not {}", parameter))
+        })
+        .collect()
+}
+
+/// Converts yaml to json, short form intrinsic functions (like `!Ref Name`) becoming their long form (like `{"Ref": "Name"}`)
+fn to_json(yaml: serde_yaml::Value) -> Value {
+    match yaml {
+        serde_yaml::Value::Null => Value::Null,
+        serde_yaml::Value::Bool(b) => Value::Bool(b),
+        serde_yaml::Value::Number(n) => serde_json::to_value(n).unwrap_or(Value::Null),
+        serde_yaml::Value::String(s) => Value::String(s),
+        serde_yaml::Value::Sequence(values) => {
+            Value::Array(values.into_iter().map(to_json).collect())
+        }
+        serde_yaml::Value::Mapping(mapping) => Value::Object(
+            mapping
+                .into_iter()
+                .filter_map(|(key, value)| match to_json(key) {
+                    Value::String(key) => Some((key, to_json(value))),
+                    Value::Number(key) => Some((key.to_string(), to_json(value))),
+                    _ => None,
+                })
+                .collect::<Map<String, Value>>(),
+        ),
+        serde_yaml::Value::Tagged(tagged) => {
+            let name = tagged.tag.to_string();
+            let name = name.trim_start_matches('!');
+            let key = match name {
+                "Ref" | "Condition" => name.to_string(),
+                _ => format!("Fn::{}", name),
+            };
+            let mut function = Map::new();
+            function.insert(key, to_json(tagged.value));
+            Value::Object(function)
+        }
+    }
+}
+
+/// The resources and the values of the parameters of a template
+struct Template<'a> {
+    resources: &'a Map<String, Value>,
+    parameters: HashMap<String, String>,
+}
+
+impl Template<'_> {
+    /// Returns a value as a string, resolving references to parameters (`None` when unresolved)
+    fn string(&self, value: &Value) -> Option<String> {
+        match value.get("Ref").and_then(Value::as_str) {
+            Some(name) => self.parameters.get(name).cloned(),
+            None => as_string(value),
+        }
+    }
+
+    fn count(&self, value: &Value) -> Option<usize> {
+        self.string(value)?.parse().ok()
+    }
+
+    /// Returns the resource of the template referenced by a value (`!Ref` or `!GetAtt`)
+    fn referenced(&self, value: &Value) -> Option<&Value> {
+        let name = match value.get("Fn::GetAtt") {
+            Some(Value::String(attribute)) => attribute.split('.').next(),
+            Some(Value::Array(attribute)) => attribute.first().and_then(Value::as_str),
+            _ => value.get("Ref").and_then(Value::as_str),
+        }?;
+        self.resources.get(name)
+    }
+
+    /// Returns the data of the launch template of an instance or auto scaling group (`LaunchTemplate` property)
+    fn launch_template(&self, properties: &Value) -> Option<&Value> {
+        let specification = &properties["LaunchTemplate"];
+        let reference = match specification.get("LaunchTemplateId") {
+            Some(id) => id,
+            None => specification.get("LaunchTemplateName")?,
+        };
+        let launch_template = self.referenced(reference)?;
+        Some(&launch_template["Properties"]["LaunchTemplateData"])
+    }
+
+    /// Returns the properties of the launch configuration of an auto scaling group
+    fn launch_configuration(&self, properties: &Value) -> Option<&Value> {
+        let launch_configuration = self.referenced(&properties["LaunchConfigurationName"])?;
+        Some(&launch_configuration["Properties"])
+    }
+
+    /// Returns the tags of a resource (`Key` and `Value` of its `Tags`)
+    fn tags(&self, properties: &Value) -> Vec<CloudResourceTag> {
+        let Some(tags) = properties["Tags"].as_array() else {
+            return Vec::new();
+        };
+        tags.iter()
+            .filter_map(|tag| {
+                Some(CloudResourceTag {
+                    key: self.string(&tag["Key"])?,
+                    value: self.string(&tag["Value"]),
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the EBS volumes of block device mappings, attached to an instance
+    fn block_devices(
+        &self,
+        instance_id: &str,
+        mappings: Option<&Value>,
+    ) -> Vec<(String, ResourceDetails)> {
+        let Some(mappings) = mappings.and_then(Value::as_array) else {
+            return Vec::new();
+        };
+        let mut volumes = Vec::new();
+        for (index, mapping) in mappings.iter().enumerate() {
+            let ebs = &mapping["Ebs"];
+            if ebs.is_null() {
+                continue;
+            }
+            let device = self
+                .string(&mapping["DeviceName"])
+                .unwrap_or_else(|| index.to_string());
+            let id = format!("{}.BlockDeviceMappings[{}]", instance_id, device);
+            match self.volume_details(&ebs["VolumeType"], &ebs["VolumeSize"]) {
+                Some(ResourceDetails::BlockStorage {
+                    storage_type,
+                    usage,
+                    ..
+                }) => volumes.push((
+                    id,
+                    ResourceDetails::BlockStorage {
+                        storage_type,
+                        usage,
+                        attached_instances: Some(vec![StorageAttachment {
+                            instance_id: instance_id.to_string(),
+                        }]),
+                    },
+                )),
+                _ => warn!("Ignoring volume {} (unresolved size)", id),
+            }
+        }
+        volumes
+    }
+
+    fn volume_details(&self, volume_type: &Value, size: &Value) -> Option<ResourceDetails> {
+        let size_gb: i32 = self.string(size)?.parse().ok()?;
+        let storage_type = match volume_type.is_null() {
+            true => DEFAULT_VOLUME_TYPE.to_string(),
+            false => self.string(volume_type)?,
+        };
+        Some(ResourceDetails::BlockStorage {
+            storage_type,
+            usage: Some(StorageUsage {
+                size_gb,
+                usage_duration_seconds: 3600,
+            }),
+            attached_instances: None,
+        })
+    }
+}
+
+fn as_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// The CPU loads of hypothetical instances are unknown, and estimated with the default load of Boavizta API
+fn instance_details(instance_type: &str) -> ResourceDetails {
+    ResourceDetails::Instance {
+        instance_type: instance_type.to_string(),
+        usage: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = r#"
+AWSTemplateFormatVersion: "2010-09-09"
+Transform: AWS::Serverless-2016-10-31
+Parameters:
+  WebInstanceType:
+    Type: String
+    Default: m6g.xlarge
+  Capacity:
+    Type: Number
+    Default: 2
+Resources:
+  WebServer:
+    Type: AWS::EC2::Instance
+    Properties:
+      InstanceType: !Ref WebInstanceType
+      BlockDeviceMappings:
+        - DeviceName: /dev/xvda
+          Ebs:
+            VolumeSize: 20
+            VolumeType: gp3
+      Tags:
+        - Key: team
+          Value: web
+  WorkerTemplate:
+    Type: AWS::EC2::LaunchTemplate
+    Properties:
+      LaunchTemplateData:
+        InstanceType: c5.large
+  Workers:
+    Type: AWS::AutoScaling::AutoScalingGroup
+    Properties:
+      DesiredCapacity: !Ref Capacity
+      MaxSize: 10
+      LaunchTemplate:
+        LaunchTemplateId: !Ref WorkerTemplate
+        Version: !GetAtt WorkerTemplate.LatestVersionNumber
+      Tags:
+        - Key: team
+          Value: batch
+          PropagateAtLaunch: true
+  Data:
+    Type: AWS::EC2::Volume
+    Properties:
+      Size: 100
+      AvailabilityZone: !Select [0, !GetAZs ""]
+  Api:
+    Type: AWS::Serverless::Function
+    Properties:
+      Runtime: python3.12
+  Unresolved:
+    Type: AWS::EC2::Instance
+    Properties:
+      InstanceType: !FindInMap [Types, !Ref "AWS::Region", Web]
+"#;
+
+    #[test]
+    fn templates_are_inventoried() {
+        let no_parameters = HashMap::new();
+        let inventory =
+            parse_cloudformation_template(TEMPLATE, "eu-west-3", &no_parameters, &[], false)
+                .unwrap();
+        let ids: Vec<&str> = inventory.resources.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(vec!["WebServer", "Workers[0]", "Workers[1]"], ids);
+        let types: Vec<Option<String>> = inventory
+            .resources
+            .iter()
+            .map(|r| r.resource_details.resource_type())
+            .collect();
+        assert_eq!(
+            vec![
+                Some("m6g.xlarge".to_string()),
+                Some("c5.large".to_string()),
+                Some("c5.large".to_string())
+            ],
+            types
+        );
+        assert_eq!("eu-west-3", inventory.resources[0].location.aws_region);
+        assert_eq!(
+            "batch",
+            inventory.resources[1].tags[0].value.as_deref().unwrap()
+        );
+
+        let parameters = parse_parameters(&[
+            "Capacity=3".to_string(),
+            "WebInstanceType=t3.micro".to_string(),
+        ])
+        .unwrap();
+        let inventory = parse_cloudformation_template(
+            TEMPLATE,
+            "eu-west-3",
+            &parameters,
+            &["team=web".to_string()],
+            true,
+        )
+        .unwrap();
+        let ids: Vec<&str> = inventory.resources.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(
+            vec!["WebServer", "WebServer.BlockDeviceMappings[/dev/xvda]"],
+            ids
+        );
+        assert_eq!(
+            Some("t3.micro".to_string()),
+            inventory.resources[0].resource_details.resource_type()
+        );
+
+        let inventory =
+            parse_cloudformation_template(TEMPLATE, "eu-west-3", &parameters, &[], true).unwrap();
+        assert_eq!(
+            3,
+            inventory
+                .resources
+                .iter()
+                .filter(|r| r.id.starts_with("Workers"))
+                .count()
+        );
+        assert!(inventory.resources.iter().any(|r| r.id == "Data"));
+    }
+
+    #[test]
+    fn json_templates_and_invalid_parameters() {
+        let template = r#"{"Resources": {"App": {"Type": "AWS::EC2::Instance", "Properties": {"InstanceType": "t3.micro"}}}}"#;
+        let inventory =
+            parse_cloudformation_template(template, "eu-west-1", &HashMap::new(), &[], false)
+                .unwrap();
+        assert_eq!(1, inventory.resources.len());
+        assert!(parse_parameters(&["Capacity".to_string()]).is_err());
+        assert!(parse_cloudformation_template(
+            "Outputs: {}",
+            "eu-west-1",
+            &HashMap::new(),
+            &[],
+            false
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,285 @@
+//! A module to publish the summary of impacts as CloudWatch custom metrics, so that CloudWatch alarms and dashboards can watch the footprint (the natural destination of the Lambda deployment).
+//!
+//! The totals of the summary are published with the dimensions of the settings only, and the summary of each region with the `AwsRegion`, `Country` (and `AccountId`) dimensions added, as CloudWatch does not aggregate metrics across dimensions.
+use anyhow::{Context, Result};
+use aws_sdk_cloudwatch::primitives::DateTime;
+use aws_sdk_cloudwatch::types::{Dimension, MetricDatum, StandardUnit};
+
+use crate::impact_provider::ImpactsSummary;
+
+/// Namespace of the custom metrics
+pub const DEFAULT_NAMESPACE: &str = "Boavizta/CloudScanner";
+
+/// Maximum number of metrics of a PutMetricData request
+const MAX_DATA_PER_REQUEST: usize = 1000;
+
+///  Settings of the CloudWatch export
+#[derive(Clone, Debug)]
+pub struct CloudWatchConfig {
+    /// Namespace of the custom metrics (like `Boavizta/CloudScanner`)
+    pub namespace: String,
+    /// Dimensions added to every metric (like `Environment=prod`)
+    pub dimensions: Vec<(String, String)>,
+}
+
+/// Parses a dimension written like `Environment=prod`
+pub fn parse_dimension(dimension: &str) -> Result<(String, String)> {
+    match dimension.split_once('=') {
+        Some((name, value)) if !name.is_empty() && !value.is_empty() => {
+            Ok((name.to_string(), value.to_string()))
+        }
+        _ => anyhow::bail!(
+            "Invalid dimension '{}' (expecting Name=Value, like Environment=prod)",
+            dimension
+        ),
+    }
+}
+
+/// Returns the values of a (sub) summary, by name and unit
+fn values(
+    number_of_resources: [usize; 3],
+    impacts: [f64; 6],
+    duration_of_use_hours: f64,
+) -> Vec<(&'static str, f64, StandardUnit)> {
+    let [total, assessed, not_assessed] = number_of_resources;
+    let [adp_manufacture, adp_use, pe_manufacture, pe_use, gwp_manufacture, gwp_use] = impacts;
+    vec![
+        (
+            "number_of_resources_total",
+            total as f64,
+            StandardUnit::Count,
+        ),
+        (
+            "number_of_resources_assessed",
+            assessed as f64,
+            StandardUnit::Count,
+        ),
+        (
+            "number_of_resources_not_assessed",
+            not_assessed as f64,
+            StandardUnit::Count,
+        ),
+        (
+            "duration_of_use_hours",
+            duration_of_use_hours,
+            StandardUnit::None,
+        ),
+        (
+            "adp_manufacture_kgsbeq",
+            adp_manufacture,
+            StandardUnit::None,
+        ),
+        ("adp_use_kgsbeq", adp_use, StandardUnit::None),
+        (
+            "pe_manufacture_megajoules",
+            pe_manufacture,
+            StandardUnit::None,
+        ),
+        ("pe_use_megajoules", pe_use, StandardUnit::None),
+        (
+            "gwp_manufacture_kgco2eq",
+            gwp_manufacture,
+            StandardUnit::None,
+        ),
+        ("gwp_use_kgco2eq", gwp_use, StandardUnit::None),
+    ]
+}
+
+fn dimension(name: &str, value: &str) -> Dimension {
+    Dimension::builder().name(name).value(value).build()
+}
+
+/// Return an ImpactsSummary as CloudWatch metrics: the totals, then the summary of each region
+pub fn get_summary_metric_data(
+    summary: &ImpactsSummary,
+    config: &CloudWatchConfig,
+    timestamp: DateTime,
+) -> Vec<MetricDatum> {
+    let datum = |(name, value, unit): (&str, f64, StandardUnit), dimensions: &[Dimension]| {
+        MetricDatum::builder()
+            .metric_name(name)
+            .set_dimensions(Some(dimensions.to_vec()))
+            .value(value)
+            .unit(unit)
+            .timestamp(timestamp)
+            .build()
+    };
+    let dimensions: Vec<Dimension> = config
+        .dimensions
+        .iter()
+        .map(|(name, value)| dimension(name, value))
+        .collect();
+
+    let mut data: Vec<MetricDatum> = values(
+        [
+            summary.number_of_resources_total,
+            summary.number_of_resources_assessed,
+            summary.number_of_resources_not_assessed,
+        ],
+        [
+            summary.adp_manufacture_kgsbeq,
+            summary.adp_use_kgsbeq,
+            summary.pe_manufacture_megajoules,
+            summary.pe_use_megajoules,
+            summary.gwp_manufacture_kgco2eq,
+            summary.gwp_use_kgco2eq,
+        ],
+        summary.duration_of_use_hours,
+    )
+    .into_iter()
+    .map(|value| datum(value, &dimensions))
+    .collect();
+    if let Some(sci) = &summary.sci {
+        data.push(datum(
+            (
+                "sci_kgco2eq_per_unit",
+                sci.sci_kgco2eq_per_unit,
+                StandardUnit::None,
+            ),
+            &dimensions,
+        ));
+    }
+
+    for region in summary.regions.iter() {
+        let mut region_dimensions = dimensions.clone();
+        region_dimensions.push(dimension("AwsRegion", &region.aws_region));
+        region_dimensions.push(dimension("Country", &region.country));
+        if let Some(account_id) = &region.account_id {
+            region_dimensions.push(dimension("AccountId", account_id));
+        }
+        data.extend(
+            values(
+                [
+                    region.number_of_resources_total,
+                    region.number_of_resources_assessed,
+                    region.number_of_resources_not_assessed,
+                ],
+                [
+                    region.adp_manufacture_kgsbeq,
+                    region.adp_use_kgsbeq,
+                    region.pe_manufacture_megajoules,
+                    region.pe_use_megajoules,
+                    region.gwp_manufacture_kgco2eq,
+                    region.gwp_use_kgco2eq,
+                ],
+                summary.duration_of_use_hours,
+            )
+            .into_iter()
+            .map(|value| datum(value, &region_dimensions)),
+        );
+    }
+    data
+}
+
+/// Publishes the summary as CloudWatch custom metrics, in the region of the environment
+pub async fn publish_to_cloudwatch(
+    config: &CloudWatchConfig,
+    summary: &ImpactsSummary,
+) -> Result<()> {
+    let data = get_summary_metric_data(
+        summary,
+        config,
+        DateTime::from_secs(chrono::Utc::now().timestamp()),
+    );
+    let sdk_config = aws_config::load_from_env().await;
+    let client = aws_sdk_cloudwatch::Client::new(&sdk_config);
+    for chunk in data.chunks(MAX_DATA_PER_REQUEST) {
+        client
+            .put_metric_data()
+            .namespace(&config.namespace)
+            .set_metric_data(Some(chunk.to_vec()))
+            .send()
+            .await
+            .with_context(|| {
+                format!(
+                    "Cannot publish metrics to CloudWatch namespace {}",
+                    config.namespace
+                )
+            })?;
+    }
+    info!(
+        "Published {} metrics to CloudWatch namespace {}",
+        data.len(),
+        config.namespace
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::RegionSummary;
+
+    #[test]
+    fn summary_is_published_as_totals_and_per_region() {
+        assert_eq!(
+            ("Environment".to_string(), "prod".to_string()),
+            parse_dimension("Environment=prod").unwrap()
+        );
+        assert!(parse_dimension("Environment").is_err());
+        assert!(parse_dimension("=prod").is_err());
+
+        let summary = ImpactsSummary {
+            number_of_resources_total: 2,
+            number_of_resources_assessed: 2,
+            number_of_resources_not_assessed: 0,
+            number_of_resources_excluded: None,
+            duration_of_use_hours: 730.0,
+            adp_manufacture_kgsbeq: 0.0001,
+            adp_use_kgsbeq: 0.0,
+            pe_manufacture_megajoules: 1234.5,
+            pe_use_megajoules: 100.0,
+            gwp_manufacture_kgco2eq: 12.4,
+            gwp_use_kgco2eq: 1.5,
+            regions: vec![RegionSummary {
+                gwp_use_kgco2eq: 1.5,
+                number_of_resources_total: 2,
+                ..RegionSummary::new(
+                    Some("111111111111".to_string()),
+                    "eu-west-3".to_string(),
+                    "FRA".to_string(),
+                )
+            }],
+            sci: None,
+            groups: Vec::new(),
+            cost: None,
+            kubernetes: None,
+            parents: Vec::new(),
+        };
+        let config = CloudWatchConfig {
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            dimensions: vec![("Environment".to_string(), "prod".to_string())],
+        };
+        let data = get_summary_metric_data(&summary, &config, DateTime::from_secs(0));
+        assert_eq!(20, data.len());
+
+        let total = &data[9];
+        assert_eq!(Some("gwp_use_kgco2eq"), total.metric_name());
+        assert_eq!(Some(1.5), total.value());
+        assert_eq!(1, total.dimensions().len());
+        assert_eq!(Some(&StandardUnit::Count), data[0].unit());
+
+        let region = &data[10];
+        assert_eq!(Some("number_of_resources_total"), region.metric_name());
+        assert_eq!(Some(2.0), region.value());
+        let dimensions: Vec<(&str, &str)> = region
+            .dimensions()
+            .iter()
+            .map(|dimension| {
+                (
+                    dimension.name().unwrap_or_default(),
+                    dimension.value().unwrap_or_default(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("Environment", "prod"),
+                ("AwsRegion", "eu-west-3"),
+                ("Country", "FRA"),
+                ("AccountId", "111111111111")
+            ],
+            dimensions
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! Compression of the responses of the standalone server (brotli or gzip, as accepted by the client), as estimates of thousands of resources weigh tens of MB in json.
+//!
+//! A fairing compresses the responses whose size is known (streams like job events are left untouched) and above a minimum size.
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{ContentType, Header};
+use rocket::{Request, Response};
+use serde::Deserialize;
+use std::io::{Cursor, Write};
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_min_size_bytes() -> usize {
+    1024
+}
+
+/// Settings of the compression of responses
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CompressionConfig {
+    /// Defaults to true
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Responses smaller than this are sent uncompressed, defaults to 1 KiB
+    #[serde(default = "default_min_size_bytes")]
+    pub min_size_bytes: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            enabled: default_enabled(),
+            min_size_bytes: default_min_size_bytes(),
+        }
+    }
+}
+
+/// Content encodings supported by the server
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn name(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    fn compress(&self, body: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+                encoder.write_all(body)?;
+                Ok(encoder.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Returns the encoding preferred by a client from its `Accept-Encoding` header (brotli on ties), none when it accepts neither
+pub fn preferred_encoding(accept_encoding: &str) -> Option<Encoding> {
+    let mut preferred: Option<(Encoding, f32)> = None;
+    for entry in accept_encoding.split(',') {
+        let mut parts = entry.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let quality = parts
+            .find_map(|parameter| parameter.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        let encodings: &[Encoding] = match name.to_ascii_lowercase().as_str() {
+            "br" => &[Encoding::Brotli],
+            "gzip" | "x-gzip" => &[Encoding::Gzip],
+            "*" => &[Encoding::Brotli, Encoding::Gzip],
+            _ => &[],
+        };
+        for encoding in encodings {
+            let is_preferred = preferred.is_none_or(|(_, best)| {
+                quality > best || (quality == best && *encoding == Encoding::Brotli)
+            });
+            if quality > 0.0 && is_preferred {
+                preferred = Some((*encoding, quality));
+            }
+        }
+    }
+    preferred.map(|(encoding, _)| encoding)
+}
+
+/// Whether responses of a content type are worth compressing (text formats)
+fn is_compressible(content_type: &ContentType) -> bool {
+    content_type.top() == "text"
+        || content_type.is_json()
+        || content_type.is_javascript()
+        || content_type.sub().as_str().ends_with("+json")
+}
+
+/// A fairing compressing responses
+pub struct Compression {
+    config: CompressionConfig,
+}
+
+impl Compression {
+    pub fn new(config: CompressionConfig) -> Self {
+        Compression { config }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Compression {
+    fn info(&self) -> Info {
+        Info {
+            name: "Compression",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let Some(encoding) = request
+            .headers()
+            .get_one("Accept-Encoding")
+            .and_then(preferred_encoding)
+        else {
+            return;
+        };
+        let compressible = response.content_type().is_some_and(|c| is_compressible(&c));
+        let large_enough = response
+            .body()
+            .preset_size()
+            .is_some_and(|size| size >= self.config.min_size_bytes);
+        if !compressible || !large_enough || response.headers().contains("Content-Encoding") {
+            return;
+        }
+        let body = match response.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Cannot read response to compress it: {}", e);
+                return;
+            }
+        };
+        let compressed =
+            rocket::tokio::task::spawn_blocking(move || (encoding.compress(&body), body)).await;
+        match compressed {
+            Ok((Ok(compressed), _)) => {
+                response.set_header(Header::new("Content-Encoding", encoding.name()));
+                response.set_sized_body(compressed.len(), Cursor::new(compressed));
+            }
+            Ok((Err(e), body)) => {
+                warn!("Cannot compress response: {}", e);
+                response.set_sized_body(body.len(), Cursor::new(body));
+            }
+            Err(e) => error!("Compression of response failed: {}", e),
+        }
+        response.adjoin_header(Header::new("Vary", "Accept-Encoding"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Status;
+    use std::io::Read;
+
+    #[test]
+    fn preferred_encoding_follows_accept_encoding() {
+        assert_eq!(
+            Some(Encoding::Brotli),
+            preferred_encoding("gzip, deflate, br")
+        );
+        assert_eq!(
+            Some(Encoding::Gzip),
+            preferred_encoding("gzip;q=1.0, br;q=0.5")
+        );
+        assert_eq!(Some(Encoding::Gzip), preferred_encoding("br;q=0, gzip"));
+        assert_eq!(Some(Encoding::Brotli), preferred_encoding("*"));
+        assert_eq!(None, preferred_encoding("identity"));
+        assert_eq!(None, preferred_encoding(""));
+    }
+
+    #[rocket::get("/large")]
+    fn large() -> rocket::serde::json::Json<Vec<String>> {
+        rocket::serde::json::Json(vec!["cloud-scanner".to_string(); 1000])
+    }
+
+    #[rocket::get("/small")]
+    fn small() -> rocket::serde::json::Json<Vec<String>> {
+        rocket::serde::json::Json(vec!["cloud-scanner".to_string()])
+    }
+
+    #[test]
+    fn large_json_responses_are_compressed() {
+        let rocket = rocket::build()
+            .mount("/", rocket::routes![large, small])
+            .attach(Compression::new(CompressionConfig::default()));
+        let client = rocket::local::blocking::Client::untracked(rocket).unwrap();
+        let expected = serde_json::to_vec(&vec!["cloud-scanner"; 1000]).unwrap();
+
+        let response = client
+            .get("/large")
+            .header(Header::new("Accept-Encoding", "gzip"))
+            .dispatch();
+        assert_eq!(Status::Ok, response.status());
+        assert_eq!(Some("gzip"), response.headers().get_one("Content-Encoding"));
+        let compressed = response.into_bytes().unwrap();
+        assert!(compressed.len() < expected.len() / 10);
+        let mut body = Vec::new();
+        flate2::read::GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut body)
+            .unwrap();
+        assert_eq!(expected, body);
+
+        let response = client
+            .get("/large")
+            .header(Header::new("Accept-Encoding", "br, gzip"))
+            .dispatch();
+        assert_eq!(Some("br"), response.headers().get_one("Content-Encoding"));
+        let mut body = Vec::new();
+        brotli::Decompressor::new(response.into_bytes().unwrap().as_slice(), 4096)
+            .read_to_end(&mut body)
+            .unwrap();
+        assert_eq!(expected, body);
+
+        let response = client.get("/large").dispatch();
+        assert_eq!(None, response.headers().get_one("Content-Encoding"));
+        let response = client
+            .get("/small")
+            .header(Header::new("Accept-Encoding", "gzip"))
+            .dispatch();
+        assert_eq!(None, response.headers().get_one("Content-Encoding"));
+    }
+}
@@ -0,0 +1,361 @@
+//! Limits of the concurrency of scans, set once for the process (with the options of the command line).
+//!
+//! Regions (of all the scanned accounts) are scanned concurrently, at most [region_concurrency] at the same time, so that large organizations are scanned quickly without being throttled by the AWS APIs.
+//! In each region, the impacts of at most [estimation_concurrency] resources are requested from Boavizta API at the same time: up to [region_concurrency] times [estimation_concurrency] requests are in flight (80 by default).
+//!
+//! In adaptive mode (see [set_adaptive]), the calls to Boavizta API and to CloudWatch GetMetricData are bounded by limits shared by all the regions instead, that are tuned while scanning: a limit grows by about one call each time the calls in flight complete quickly, and shrinks when the latency of the calls grows beyond twice the fastest latency observed (by a tenth) or when a call fails or is throttled (by half). The limits start at the concurrency options and stay between 1 and [MAX_ADAPTIVE_CONCURRENCY].
+use anyhow::Result;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Number of regions scanned at the same time, unless set with [set_region_concurrency]
+pub const DEFAULT_REGION_CONCURRENCY: NonZeroUsize = NonZeroUsize::new(8).unwrap();
+
+static REGION_CONCURRENCY: AtomicUsize = AtomicUsize::new(DEFAULT_REGION_CONCURRENCY.get());
+
+/// Number of requests of impacts sent to Boavizta API at the same time (for each region), unless set with [set_estimation_concurrency]
+pub const DEFAULT_ESTIMATION_CONCURRENCY: NonZeroUsize = NonZeroUsize::new(10).unwrap();
+
+static ESTIMATION_CONCURRENCY: AtomicUsize = AtomicUsize::new(DEFAULT_ESTIMATION_CONCURRENCY.get());
+
+/// Sets the number of regions scanned at the same time
+pub fn set_region_concurrency(limit: NonZeroUsize) {
+    REGION_CONCURRENCY.store(limit.get(), Ordering::Relaxed);
+}
+
+/// Returns the number of regions scanned at the same time
+pub fn region_concurrency() -> usize {
+    REGION_CONCURRENCY.load(Ordering::Relaxed)
+}
+
+/// Sets the number of requests of impacts sent to Boavizta API at the same time (for each region)
+pub fn set_estimation_concurrency(limit: NonZeroUsize) {
+    ESTIMATION_CONCURRENCY.store(limit.get(), Ordering::Relaxed);
+}
+
+/// Returns the number of requests of impacts sent to Boavizta API at the same time (for each region)
+pub fn estimation_concurrency() -> usize {
+    ESTIMATION_CONCURRENCY.load(Ordering::Relaxed)
+}
+
+/// Highest limit of the calls to an API in flight reached in adaptive mode
+pub const MAX_ADAPTIVE_CONCURRENCY: usize = 64;
+
+/// Latency of the calls, relative to the fastest call observed, above which a limit shrinks
+const LATENCY_TOLERANCE: f64 = 2.0;
+
+/// Weight of the last call in the smoothed latency
+const LATENCY_SMOOTHING: f64 = 0.2;
+
+static ADAPTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Limit of the requests of impacts sent to Boavizta API at the same time, by all the regions (in adaptive mode)
+pub(crate) static BOAVIZTA_API_LIMIT: AdaptiveLimit = AdaptiveLimit::new("Boavizta API");
+
+/// Limit of the GetMetricData calls to CloudWatch at the same time, by all the regions (in adaptive mode)
+pub(crate) static CLOUDWATCH_LIMIT: AdaptiveLimit = AdaptiveLimit::new("CloudWatch");
+
+/// Tunes the concurrency of the calls to Boavizta API and CloudWatch from now on, starting from the concurrency of the estimation and of the regions (to be called after setting them)
+pub fn set_adaptive(enabled: bool) {
+    ADAPTIVE.store(enabled, Ordering::Relaxed);
+    if enabled {
+        BOAVIZTA_API_LIMIT.reset(estimation_concurrency());
+        CLOUDWATCH_LIMIT.reset(region_concurrency());
+    }
+}
+
+/// Returns true when the concurrency of the calls to APIs is tuned while scanning
+pub fn is_adaptive() -> bool {
+    ADAPTIVE.load(Ordering::Relaxed)
+}
+
+/// Returns the number of requests of impacts of a region started at the same time: bounded by [estimation_concurrency], or by [BOAVIZTA_API_LIMIT] in adaptive mode
+pub(crate) fn estimation_requests_per_region() -> usize {
+    match is_adaptive() {
+        true => MAX_ADAPTIVE_CONCURRENCY,
+        false => estimation_concurrency(),
+    }
+}
+
+/// Returns the number of batches of utilization metrics of a region read at the same time: one after the other, or bounded by [CLOUDWATCH_LIMIT] in adaptive mode
+pub(crate) fn cloudwatch_requests_per_region() -> usize {
+    match is_adaptive() {
+        true => MAX_ADAPTIVE_CONCURRENCY,
+        false => 1,
+    }
+}
+
+struct AdaptiveState {
+    /// The limit, grown by fractions of calls
+    limit: f64,
+    in_flight: usize,
+    fastest_latency: Option<Duration>,
+    smoothed_latency: Option<Duration>,
+    /// When the limit last shrank: it shrinks at most once per smoothed latency, the calls in flight at that time having seen the same congestion
+    shrunk_at: Option<Instant>,
+}
+
+impl AdaptiveState {
+    /// Tunes the limit with the latency of a call completed at `now`, and whether it succeeded
+    fn observe(&mut self, latency: Duration, succeeded: bool, now: Instant) {
+        let may_shrink = match (self.shrunk_at, self.smoothed_latency) {
+            (Some(shrunk_at), Some(smoothed)) => now.duration_since(shrunk_at) >= smoothed,
+            _ => true,
+        };
+        if !succeeded {
+            if may_shrink {
+                self.limit = (self.limit / 2.0).max(1.0);
+                self.shrunk_at = Some(now);
+            }
+            return;
+        }
+        let fastest = self
+            .fastest_latency
+            .map_or(latency, |fastest| fastest.min(latency));
+        self.fastest_latency = Some(fastest);
+        let smoothed = match self.smoothed_latency {
+            Some(smoothed) => {
+                smoothed.mul_f64(1.0 - LATENCY_SMOOTHING) + latency.mul_f64(LATENCY_SMOOTHING)
+            }
+            None => latency,
+        };
+        self.smoothed_latency = Some(smoothed);
+        if smoothed <= fastest.mul_f64(LATENCY_TOLERANCE) {
+            self.limit = (self.limit + 1.0 / self.limit).min(MAX_ADAPTIVE_CONCURRENCY as f64);
+        } else if may_shrink {
+            self.limit = (self.limit * 0.9).max(1.0);
+            self.shrunk_at = Some(now);
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.in_flight >= self.limit as usize
+    }
+}
+
+/// A limit of the calls to an API in flight, tuned with the latencies and failures of the calls
+pub(crate) struct AdaptiveLimit {
+    name: &'static str,
+    state: Mutex<AdaptiveState>,
+    released: Notify,
+}
+
+impl AdaptiveLimit {
+    const fn new(name: &'static str) -> Self {
+        AdaptiveLimit {
+            name,
+            state: Mutex::new(AdaptiveState {
+                limit: DEFAULT_ESTIMATION_CONCURRENCY.get() as f64,
+                in_flight: 0,
+                fastest_latency: None,
+                smoothed_latency: None,
+                shrunk_at: None,
+            }),
+            released: Notify::const_new(),
+        }
+    }
+
+    /// Restarts tuning from a limit
+    fn reset(&self, limit: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.limit = limit.clamp(1, MAX_ADAPTIVE_CONCURRENCY) as f64;
+        state.fastest_latency = None;
+        state.smoothed_latency = None;
+        state.shrunk_at = None;
+    }
+
+    /// Waits until a call fits in the limit (immediately when not in adaptive mode), the call being observed when its permit is finished
+    pub(crate) async fn acquire(&'static self) -> Permit {
+        if !is_adaptive() {
+            return Permit {
+                limit: None,
+                started: Instant::now(),
+            };
+        }
+        loop {
+            let released = self.released.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if !state.is_full() {
+                    state.in_flight += 1;
+                    break;
+                }
+            }
+            released.await;
+        }
+        Permit {
+            limit: Some(self),
+            started: Instant::now(),
+        }
+    }
+
+    fn release(&self, observed: Option<bool>, latency: Duration) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.in_flight -= 1;
+            if let Some(succeeded) = observed {
+                let before = state.limit as usize;
+                state.observe(latency, succeeded, Instant::now());
+                if state.limit as usize != before {
+                    debug!(
+                        "Concurrency of the calls to {}: {}",
+                        self.name, state.limit as usize
+                    );
+                }
+            }
+        }
+        self.released.notify_waiters();
+    }
+}
+
+/// The permission of a call to an API, to be finished with its outcome (a permit dropped before, like a cancelled call, is released without tuning the limit)
+pub(crate) struct Permit {
+    limit: Option<&'static AdaptiveLimit>,
+    started: Instant,
+}
+
+impl Permit {
+    /// Releases the permit, tuning the limit with the latency of the call and whether it succeeded (failures and throttling shrink the limit)
+    pub(crate) fn finish(mut self, succeeded: bool) {
+        if let Some(limit) = self.limit.take() {
+            limit.release(Some(succeeded), self.started.elapsed());
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(limit) = self.limit.take() {
+            limit.release(None, self.started.elapsed());
+        }
+    }
+}
+
+/// Runs futures, at most `limit` at the same time, and returns their results in the order of the futures (or the first error)
+///
+/// The next future starts as soon as any running future completes, so that a slow future (like the scan of a large account) does not hold back the others.
+fn join_bounded<T, F>(
+    futures: impl IntoIterator<Item = F>,
+    limit: usize,
+) -> impl Future<Output = Result<Vec<T>>>
+where
+    F: Future<Output = Result<T>>,
+{
+    // Collected first, so that the returned future does not hold the iterator (and its closures)
+    let futures: Vec<_> = futures
+        .into_iter()
+        .enumerate()
+        .map(|(index, future)| async move { future.await.map(|value| (index, value)) })
+        .collect();
+    async move {
+        let mut results: Vec<(usize, T)> = stream::iter(futures)
+            .buffer_unordered(limit)
+            .try_collect()
+            .await?;
+        results.sort_by_key(|(index, _)| *index);
+        Ok(results.into_iter().map(|(_, value)| value).collect())
+    }
+}
+
+/// Runs the scans of regions, at most [region_concurrency] at the same time, and returns their results in the order of the scans (or the first error)
+pub fn scan_regions<T, F>(
+    scans: impl IntoIterator<Item = F>,
+) -> impl Future<Output = Result<Vec<T>>>
+where
+    F: Future<Output = Result<T>>,
+{
+    join_bounded(scans, region_concurrency())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn scans_are_bounded_and_keep_their_order() {
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+        let scans = (0..10).map(|i| {
+            let (running, max_running) = (&running, &max_running);
+            async move {
+                let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now_running, Ordering::SeqCst);
+                // The first scans are the slowest
+                tokio::time::sleep(Duration::from_millis(20 - i)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(i)
+            }
+        });
+        let results = join_bounded(scans, 3).await.unwrap();
+        assert_eq!((0..10).collect::<Vec<u64>>(), results);
+        assert_eq!(3, max_running.load(Ordering::SeqCst));
+
+        // A slow scan does not hold back the next ones
+        let started = std::time::Instant::now();
+        let scans = (0..6).map(|i| async move {
+            let duration = if i == 0 { 200 } else { 20 };
+            tokio::time::sleep(Duration::from_millis(duration)).await;
+            Ok(i)
+        });
+        assert_eq!(
+            vec![0, 1, 2, 3, 4, 5],
+            join_bounded(scans, 2).await.unwrap()
+        );
+        assert!(started.elapsed() < Duration::from_millis(230));
+
+        let failing = (0..3).map(|i| async move {
+            match i {
+                1 => anyhow::bail!("Cannot scan region {}", i),
+                _ => Ok(i),
+            }
+        });
+        assert!(join_bounded(failing, 2).await.is_err());
+    }
+
+    #[test]
+    fn adaptive_limits_follow_latencies_and_failures() {
+        let mut state = AdaptiveState {
+            limit: 4.0,
+            in_flight: 0,
+            fastest_latency: None,
+            smoothed_latency: None,
+            shrunk_at: None,
+        };
+        let start = Instant::now();
+        let fast = Duration::from_millis(100);
+        // Fast calls grow the limit by one call per round of calls
+        for _ in 0..4 {
+            state.observe(fast, true, start);
+        }
+        assert!((state.limit - 5.0).abs() < 0.1);
+
+        // A throttled call halves the limit, the other calls failing at the same time do not shrink it further
+        state.observe(fast, false, start);
+        let halved = state.limit;
+        assert!((halved - 2.5).abs() < 0.1);
+        state.observe(fast, false, start + Duration::from_millis(50));
+        assert_eq!(halved, state.limit);
+        state.observe(fast, false, start + Duration::from_millis(200));
+        assert_eq!(halved / 2.0, state.limit);
+
+        // Latencies slowing down shrink the limit, which never goes below one call
+        let later = start + Duration::from_secs(1);
+        for i in 0..50 {
+            state.observe(
+                Duration::from_secs(2),
+                true,
+                later + Duration::from_secs(i * 3),
+            );
+        }
+        assert_eq!(1.0, state.limit);
+        state.in_flight = 1;
+        assert!(state.is_full());
+    }
+}
@@ -0,0 +1,598 @@
+//! A module to read the configuration file of cloud scanner (TOML), for settings that do not fit on a command line (like email delivery).
+//!
+//! The file is passed with `--config`, or read from `cloud-scanner.toml` in the working directory when it exists.
+//!
+//! ```toml
+//! # Options of the commands, selected with `--profile prod`
+//! [profiles.prod]
+//! provider = "aws"
+//! aws_region = "eu-west-3"
+//! boavizta_api_url = "https://api.boavizta.org"
+//! filter_tags = ["Env=prod"]
+//! use_duration_hours = 730
+//! summary_only = true
+//! output = "s3://reports/prod/"
+//!
+//! # Accounts scanned with `--all-accounts`
+//! [[accounts]]
+//! account_id = "111111111111"
+//! role_arn = "arn:aws:iam::111111111111:role/cloud-scanner"
+//! external_id = "optional-external-id"
+//!
+//! # Retries and timeouts of the AWS SDK, and budget of AWS API calls of each scan
+//! [aws]
+//! retry_mode = "adaptive"
+//! max_attempts = 5
+//! operation_timeout_seconds = 60
+//! max_api_calls = 1000
+//! max_api_calls_per_second = 20
+//!
+//! # Normalization of the tags of the inventoried resources
+//! [tags]
+//! key_case = "lower"
+//! synonyms = { team = ["owner_team"] }
+//! defaults = { env = "unknown" }
+//!
+//! [email]
+//! smtp_host = "smtp.example.com"
+//! smtp_username = "cloud-scanner@example.com"
+//! from = "Cloud scanner <cloud-scanner@example.com>"
+//! to = ["green-it@example.com"]
+//!
+//! # Optional: send a report periodically in server mode
+//! [email.schedule]
+//! interval_hours = 168
+//! aws_region = "eu-west-1"
+//!
+//! [metrics]
+//! constant_labels = { env = "prod" }
+//! tag_labels = { Team = "team", Service = "service" }
+//!
+//! # Commands run before and after the scans of the estimate command
+//! [hooks]
+//! post_scan = ["./create-ticket.sh"]
+//!
+//! # Carbon budgets, compared to the scans of a result store by `budget status`
+//! [[budgets]]
+//! name = "teams"
+//! tag = "team"
+//! max_kgco2eq = 500.0
+//! period = "monthly"
+//!
+//! # Impacts of shared resources split across the values of a tag in grouped reports
+//! [[allocations]]
+//! resources = "cluster~shared-*"
+//! tag = "team"
+//! shares = { platform = 60, data = 40 }
+//!
+//! # Scans of the result stores kept 90 days, then aggregated into monthly totals
+//! [retention]
+//! keep_scans = "90d"
+//! aggregate_period = "monthly"
+//!
+//! [notifications]
+//! webhooks = [{ kind = "slack", url = "https://hooks.slack.com/services/..." }]
+//! thresholds = { max_gwp_kgco2eq = 100.0 }
+//!
+//! # Alert rules evaluated after the scans of estimate --alert and watch --alert
+//! [alerts]
+//! channels = ["notifications"]
+//!
+//! [[alerts.rules]]
+//! kind = "increase"
+//! name = "gwp-up-20-percent"
+//! max_percent = 20.0
+//!
+//! [server]
+//! address = "0.0.0.0"
+//! port = 8000
+//! cache_ttl_minutes = 5
+//!
+//! [server.auth]
+//! api_keys = ["a-long-random-key"]
+//! oidc = { issuer = "https://accounts.example.com", audience = "cloud-scanner" }
+//!
+//! [server.tls]
+//! certs = "/etc/letsencrypt/live/scanner.example.com/fullchain.pem"
+//! key = "/etc/letsencrypt/live/scanner.example.com/privkey.pem"
+//!
+//! [server.cors]
+//! allowed_origins = ["https://dashboard.example.com"]
+//!
+//! [server.rate_limit]
+//! requests_per_minute = 10
+//!
+//! [[server.scheduled_scans]]
+//! aws_region = "eu-west-3"
+//! interval_minutes = 60
+//!
+//! [[server.accounts]]
+//! account_id = "123456789012"
+//! role_arn = "arn:aws:iam::123456789012:role/cloud-scanner"
+//!
+//! [[server.tenants]]
+//! name = "team-a"
+//! api_keys = ["a-key-of-team-a"]
+//! allowed_regions = ["eu-west-3"]
+//! account = { account_id = "210987654321", role_arn = "arn:aws:iam::210987654321:role/cloud-scanner" }
+//! ```
+//!
+//! Every setting can be overridden by an environment variable named after its path, with the `CLOUD_SCANNER__` prefix and `__` between keys (like `CLOUD_SCANNER__SERVER__PORT=9000`). Values are read as TOML values (like `true`, `5` or `["a", "b"]`), or as strings when they are not valid TOML.
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use toml::{Table, Value};
+
+use crate::alerting::AlertsConfig;
+use crate::allocation::AllocationRuleConfig;
+use crate::aws_cloud_provider::AwsAccount;
+use crate::aws_settings::AwsSettings;
+use crate::carbon_budgets::BudgetConfig;
+use crate::compression::CompressionConfig;
+use crate::cors::CorsConfig;
+use crate::email_sender::EmailConfig;
+use crate::metric_exporter::MetricsConfig;
+use crate::notifier::NotificationConfig;
+use crate::rate_limit::RateLimitConfig;
+use crate::retention::RetentionConfig;
+use crate::scan_hooks::HooksConfig;
+use crate::scheduler::ScheduledScan;
+use crate::server_auth::AuthConfig;
+use crate::standalone_server::TlsFiles;
+use crate::tag_normalization::TagNormalization;
+use crate::tenants::TenantConfig;
+
+/// Content of the configuration file
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Delivery of reports by email
+    pub email: Option<EmailConfig>,
+    /// Notifications to Slack or Teams webhooks
+    pub notifications: Option<NotificationConfig>,
+    /// Alert rules evaluated after the scans, firing to the notification channels
+    pub alerts: Option<AlertsConfig>,
+    /// Settings of the metrics (like tags mapped to labels)
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    /// Settings of the standalone server (serve command)
+    pub server: Option<ServerSettings>,
+    /// Named sets of options of the commands
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Accounts scanned together with `--all-accounts`, by assuming their roles
+    #[serde(default)]
+    pub accounts: Vec<AwsAccount>,
+    /// Retries and timeouts of the AWS SDK, and budget of AWS API calls of the scans
+    #[serde(default)]
+    pub aws: AwsSettings,
+    /// Normalization of the tags of the inventoried resources
+    #[serde(default)]
+    pub tags: TagNormalization,
+    /// Commands run before and after the scans of the estimate command
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    /// Carbon budgets of sets of resources over periods
+    #[serde(default)]
+    pub budgets: Vec<BudgetConfig>,
+    /// Rules splitting the impacts of shared resources across the values of a tag
+    #[serde(default)]
+    pub allocations: Vec<AllocationRuleConfig>,
+    /// Retention of the scans of the result stores
+    pub retention: Option<RetentionConfig>,
+}
+
+/// Options of the commands, selected by name with `--profile` (options of the command line take precedence)
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Cloud provider scanned, only `aws` is supported
+    pub provider: Option<String>,
+    pub aws_region: Option<String>,
+    /// Regions scanned together, with combined results (instead of `aws_region`)
+    #[serde(default)]
+    pub regions: Vec<String>,
+    pub boavizta_api_url: Option<String>,
+    #[serde(default)]
+    pub filter_tags: Vec<String>,
+    /// Hours of use of the estimations (hours, or text like `30d`)
+    #[serde(
+        default,
+        deserialize_with = "crate::duration::deserialize_optional_hours"
+    )]
+    pub use_duration_hours: Option<f32>,
+    #[serde(default)]
+    pub include_block_storage: bool,
+    /// Output the details of Boavizta API
+    #[serde(default)]
+    pub output_verbose_json: bool,
+    /// Output only the summary of the estimations
+    #[serde(default)]
+    pub summary_only: bool,
+    /// Output the estimations as OpenMetrics
+    #[serde(default)]
+    pub as_metrics: bool,
+    /// Destination of the estimations (a path, file://, http(s):// or s3://bucket/prefix/)
+    pub output: Option<String>,
+}
+
+/// Settings of the standalone server
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ServerSettings {
+    /// Address the server listens on (defaults to 127.0.0.1, or `ROCKET_ADDRESS`)
+    pub address: Option<IpAddr>,
+    /// Port of the server (defaults to 8000, or `ROCKET_PORT`)
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    /// Serves HTTPS with these certificate and key files
+    pub tls: Option<TlsFiles>,
+    /// Scans run periodically, `/metrics` serves their latest results
+    #[serde(default)]
+    pub scheduled_scans: Vec<ScheduledScan>,
+    /// Path of the result store (SQLite) where scheduled scans are saved
+    pub store: Option<String>,
+    /// Minutes during which identical requests to `/metrics`, `/inventory` and `/impacts` reuse the last response (0 disables the cache)
+    #[serde(default)]
+    pub cache_ttl_minutes: u64,
+    /// Accounts that requests can select (by `account_id` or `role_arn`), scanned by assuming their role
+    #[serde(default)]
+    pub accounts: Vec<AwsAccount>,
+    /// Limits the rate of requests of each client (by API key or IP address)
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Allows browser-based dashboards of other origins to call the API
+    pub cors: Option<CorsConfig>,
+    /// Port of the gRPC API (not served when none)
+    pub grpc_port: Option<u16>,
+    /// Secret used to sign the callbacks of scan jobs (HMAC-SHA256)
+    pub callback_secret: Option<String>,
+    /// Hosts (names, addresses or networks like `10.0.0.0/8`) that callbacks of scan jobs can be posted to despite being internal (loopback, private or link-local), with http too
+    #[serde(default)]
+    pub callback_allowed_hosts: Vec<String>,
+    /// Tenants sharing the server, each with its API keys, account and regions
+    #[serde(default)]
+    pub tenants: Vec<TenantConfig>,
+    /// Serve a GraphQL endpoint on `/graphql`
+    #[serde(default)]
+    pub graphql: bool,
+    /// Number of worker threads serving requests (defaults to the number of CPUs, or `ROCKET_WORKERS`)
+    pub workers: Option<usize>,
+    /// Seconds after which requests scanning synchronously fail with 504 (no timeout by default)
+    pub request_timeout_seconds: Option<u64>,
+    /// Seconds during which requests and scans in flight can finish when the server stops (defaults to 2, or `ROCKET_SHUTDOWN`)
+    pub shutdown_grace_seconds: Option<u64>,
+    /// Compression of the responses (brotli or gzip), enabled by default
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    /// Print a json access log line per request on stdout (enabled by default)
+    pub access_log: Option<bool>,
+}
+
+impl ConfigFile {
+    /// Parses the content of a configuration file
+    pub fn parse(content: &str) -> Result<Self> {
+        toml::from_str(content).context("Invalid configuration")
+    }
+
+    /// Reads a configuration file
+    pub fn read(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read configuration file {}", path))?;
+        ConfigFile::parse(&content)
+            .with_context(|| format!("Cannot parse configuration file {}", path))
+    }
+
+    /// Parses the content of a configuration file, with the settings overridden by `CLOUD_SCANNER__*` variables (among `vars`)
+    pub fn parse_with_overrides(
+        content: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self> {
+        let mut table: Table = toml::from_str(content).context("Invalid configuration")?;
+        let mut overrides: Vec<(String, String)> = vars
+            .into_iter()
+            .filter_map(|(name, value)| Some((name.strip_prefix(ENV_PREFIX)?.to_string(), value)))
+            .collect();
+        // Tables are set before the keys they contain
+        overrides.sort();
+        for (path, value) in overrides {
+            set_override(&mut table, &path, &value)
+                .with_context(|| format!("Invalid variable {}{}", ENV_PREFIX, path))?;
+        }
+        table.try_into().context("Invalid configuration")
+    }
+
+    /// Loads the configuration file (`cloud-scanner.toml` when no path is provided, if it exists), with the overrides of the environment variables
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        let path = path.or_else(|| {
+            std::path::Path::new(DEFAULT_PATH)
+                .is_file()
+                .then_some(DEFAULT_PATH)
+        });
+        let content = match path {
+            Some(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("Cannot read configuration file {}", path))?,
+            None => String::new(),
+        };
+        ConfigFile::parse_with_overrides(&content, std::env::vars())
+            .with_context(|| format!("Cannot load configuration {}", path.unwrap_or_default()))
+    }
+
+    /// Returns a profile, or fails with the names of the profiles when it is not defined
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        let profile = self.profiles.get(name).with_context(|| {
+            let names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            format!(
+                "No profile {} in the configuration file (profiles: {:?})",
+                name, names
+            )
+        })?;
+        if let Some(provider) = profile.provider.as_deref().filter(|p| *p != "aws") {
+            anyhow::bail!(
+                "Profile {} uses provider {}, only aws is supported",
+                name,
+                provider
+            );
+        }
+        Ok(profile)
+    }
+}
+
+/// Configuration file read when `--config` is not passed
+pub const DEFAULT_PATH: &str = "cloud-scanner.toml";
+
+/// Prefix of the environment variables overriding settings, like `CLOUD_SCANNER__SERVER__PORT` for the `port` of the `[server]` section
+pub const ENV_PREFIX: &str = "CLOUD_SCANNER__";
+
+/// Reads the value of a variable as TOML, or as a string when it is not valid TOML (like `eu-west-3`)
+fn parse_override(value: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+/// Sets the value at a path of keys separated by `__` (like `SERVER__AUTH__API_KEYS`), creating the missing tables
+fn set_override(table: &mut Table, path: &str, value: &str) -> Result<()> {
+    let keys: Vec<String> = path.split("__").map(str::to_ascii_lowercase).collect();
+    let Some((key, parents)) = keys.split_last() else {
+        anyhow::bail!("Missing key");
+    };
+    let mut current = table;
+    for parent in parents {
+        current = match current
+            .entry(parent.clone())
+            .or_insert_with(|| Value::Table(Table::new()))
+        {
+            Value::Table(nested) => nested,
+            _ => anyhow::bail!("{} is not a table", parent),
+        };
+    }
+    current.insert(key.clone(), parse_override(value));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_are_selected_by_name() {
+        let config = ConfigFile::parse(
+            r#"
+[profiles.prod]
+provider = "aws"
+aws_region = "eu-west-3"
+filter_tags = ["Env=prod"]
+use_duration_hours = 730
+summary_only = true
+output = "s3://reports/prod/"
+
+[profiles.azure]
+provider = "azure"
+
+[[accounts]]
+account_id = "111111111111"
+role_arn = "arn:aws:iam::111111111111:role/cloud-scanner"
+"#,
+        )
+        .unwrap();
+        let prod = config.profile("prod").unwrap();
+        assert_eq!(Some("eu-west-3"), prod.aws_region.as_deref());
+        assert_eq!(vec!["Env=prod"], prod.filter_tags);
+        assert_eq!(Some(730.0), prod.use_duration_hours);
+        assert!(prod.summary_only);
+        assert!(!prod.include_block_storage);
+        assert_eq!(Some("s3://reports/prod/"), prod.output.as_deref());
+
+        let unknown = config.profile("dev").unwrap_err().to_string();
+        assert!(unknown.contains(r#"["azure", "prod"]"#), "{}", unknown);
+        assert!(config.profile("azure").is_err());
+        assert_eq!(
+            vec!["111111111111"],
+            config
+                .accounts
+                .iter()
+                .map(|a| a.account_id.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn environment_variables_override_settings() {
+        let vars = [
+            ("CLOUD_SCANNER__SERVER__PORT", "9000"),
+            ("CLOUD_SCANNER__SERVER__ADDRESS", "0.0.0.0"),
+            ("CLOUD_SCANNER__SERVER__CACHE_TTL_MINUTES", "15"),
+            (
+                "CLOUD_SCANNER__SERVER__AUTH__API_KEYS",
+                r#"["key-1", "key-2"]"#,
+            ),
+            ("CLOUD_SCANNER__SERVER__STORE", "/data/scans.sqlite"),
+            ("CLOUD_SCANNER_GRPC_PORT", "50051"),
+            ("HOME", "/root"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+        let config = ConfigFile::parse_with_overrides(
+            r#"
+[server]
+port = 8080
+cache_ttl_minutes = 5
+graphql = true
+"#,
+            vars.clone(),
+        )
+        .unwrap();
+        let server = config.server.unwrap();
+        assert_eq!(Some(9000), server.port);
+        assert_eq!(Some("0.0.0.0".parse().unwrap()), server.address);
+        assert_eq!(15, server.cache_ttl_minutes);
+        assert_eq!(vec!["key-1", "key-2"], server.auth.api_keys);
+        assert_eq!(Some("/data/scans.sqlite".to_string()), server.store);
+        assert!(server.graphql);
+        assert_eq!(None, server.grpc_port);
+
+        let config = ConfigFile::parse_with_overrides("", vars).unwrap();
+        assert_eq!(Some(9000), config.server.unwrap().port);
+
+        let invalid = [(
+            "CLOUD_SCANNER__SERVER__PORT".to_string(),
+            "http".to_string(),
+        )];
+        assert!(ConfigFile::parse_with_overrides("", invalid).is_err());
+        let not_a_table = [(
+            "CLOUD_SCANNER__SERVER__PORT__VALUE".to_string(),
+            "1".to_string(),
+        )];
+        assert!(ConfigFile::parse_with_overrides("[server]\nport = 1", not_a_table).is_err());
+    }
+
+    #[test]
+    fn parse_email_configuration() {
+        let config = ConfigFile::parse(
+            r#"
+[email]
+smtp_host = "smtp.example.com"
+from = "cloud-scanner@example.com"
+to = ["a@example.com", "b@example.com"]
+
+[email.schedule]
+interval_hours = 24
+aws_region = "eu-west-1"
+use_duration_hours = "1d"
+"#,
+        )
+        .unwrap();
+        let email = config.email.unwrap();
+        assert_eq!("smtp.example.com", email.smtp_host);
+        assert_eq!(587, email.smtp_port);
+        assert!(email.starttls);
+        assert_eq!(2, email.to.len());
+        let schedule = email.schedule.unwrap();
+        assert_eq!(24, schedule.interval_hours);
+        assert_eq!(24.0, schedule.use_duration_hours);
+
+        assert_eq!(None, config.notifications);
+
+        let config = ConfigFile::parse(
+            r#"
+[notifications]
+only_on_breach = true
+webhooks = [{ kind = "teams", url = "https://example.webhook.office.com/webhookb2/id" }]
+
+[notifications.thresholds]
+max_gwp_kgco2eq = 10.0
+"#,
+        )
+        .unwrap();
+        let notifications = config.notifications.unwrap();
+        assert_eq!(
+            crate::notifier::WebhookKind::Teams,
+            notifications.webhooks[0].kind
+        );
+        assert_eq!(Some(10.0), notifications.thresholds.max_gwp_kgco2eq);
+        assert!(notifications.only_on_breach);
+
+        let config = ConfigFile::parse(
+            r#"
+[server.auth]
+api_keys = ["key-1"]
+oidc = { issuer = "https://accounts.example.com", audience = "cloud-scanner" }
+"#,
+        )
+        .unwrap();
+        let server = config.server.unwrap();
+        assert_eq!(None, server.tls);
+        assert!(server.scheduled_scans.is_empty());
+        let auth = server.auth;
+        assert_eq!(vec!["key-1".to_string()], auth.api_keys);
+        assert_eq!("cloud-scanner", auth.oidc.unwrap().audience);
+
+        let config = ConfigFile::parse(
+            r#"
+[server]
+store = "scans.sqlite"
+cache_ttl_minutes = 5
+callback_secret = "a-shared-secret"
+callback_allowed_hosts = ["ci.internal", "10.0.0.0/8"]
+grpc_port = 50051
+graphql = true
+workers = 4
+request_timeout_seconds = 120
+shutdown_grace_seconds = 60
+compression = { min_size_bytes = 4096 }
+access_log = false
+rate_limit = { requests_per_minute = 10, burst = 20 }
+cors = { allowed_origins = ["*"] }
+
+[[server.scheduled_scans]]
+aws_region = "eu-west-3"
+interval_minutes = 30
+filter_tags = ["Env=prod"]
+
+[[server.accounts]]
+account_id = "123456789012"
+role_arn = "arn:aws:iam::123456789012:role/cloud-scanner"
+external_id = "scanner"
+
+[[server.tenants]]
+name = "team-a"
+api_keys = ["key-a"]
+allowed_regions = ["eu-west-3"]
+account = { account_id = "210987654321", role_arn = "arn:aws:iam::210987654321:role/cloud-scanner" }
+"#,
+        )
+        .unwrap();
+        let server = config.server.unwrap();
+        assert_eq!(Some("scans.sqlite".to_string()), server.store);
+        assert_eq!(5, server.cache_ttl_minutes);
+        assert_eq!(Some("a-shared-secret".to_string()), server.callback_secret);
+        assert_eq!(
+            vec!["ci.internal", "10.0.0.0/8"],
+            server.callback_allowed_hosts
+        );
+        assert_eq!(Some(50051), server.grpc_port);
+        assert!(server.graphql);
+        assert_eq!(Some(4), server.workers);
+        assert_eq!(Some(120), server.request_timeout_seconds);
+        assert_eq!(Some(60), server.shutdown_grace_seconds);
+        assert!(server.compression.enabled);
+        assert_eq!(4096, server.compression.min_size_bytes);
+        assert_eq!(Some(false), server.access_log);
+        assert_eq!(Some(20), server.rate_limit.unwrap().burst);
+        assert_eq!(vec!["GET", "POST"], server.cors.unwrap().allowed_methods);
+        assert_eq!(30, server.scheduled_scans[0].interval_minutes);
+        assert_eq!(1.0, server.scheduled_scans[0].use_duration_hours);
+        assert_eq!("123456789012", server.accounts[0].account_id);
+        assert_eq!(Some("scanner".to_string()), server.accounts[0].external_id);
+        let tenant = &server.tenants[0];
+        assert_eq!("team-a", tenant.name);
+        assert!(tenant.allows_region("eu-west-3"));
+        assert!(!tenant.allows_region("us-east-1"));
+        assert_eq!("210987654321", tenant.account.as_ref().unwrap().account_id);
+
+        assert_eq!(ConfigFile::default(), ConfigFile::parse("").unwrap());
+        assert!(ConfigFile::parse("[unknown]").is_err());
+    }
+}
@@ -0,0 +1,181 @@
+//! Cross-origin resource sharing (CORS) policy of the standalone server, so that browser-based dashboards can call the API directly.
+//!
+//! The policy is applied by a fairing: it adds the CORS headers to the responses to allowed origins, and answers preflight requests (`OPTIONS` with `Access-Control-Request-Method`).
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Method, Status};
+use rocket::{Request, Response};
+use serde::Deserialize;
+use std::io::Cursor;
+
+fn default_allowed_methods() -> Vec<String> {
+    vec!["GET".to_string(), "POST".to_string()]
+}
+
+fn default_allowed_headers() -> Vec<String> {
+    vec![
+        "Authorization".to_string(),
+        "Content-Type".to_string(),
+        "X-API-Key".to_string(),
+    ]
+}
+
+/// Settings of CORS, cross-origin requests are not allowed when no origin is configured
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Origins allowed to call the API (like `https://dashboard.example.com`), `*` allows any origin
+    pub allowed_origins: Vec<String>,
+    /// Defaults to GET and POST
+    #[serde(default = "default_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+    /// Request headers allowed, defaults to `Authorization`, `Content-Type` and `X-API-Key`
+    #[serde(default = "default_allowed_headers")]
+    pub allowed_headers: Vec<String>,
+    /// Duration during which browsers can cache the answer to a preflight request
+    pub max_age_seconds: Option<u64>,
+}
+
+impl CorsConfig {
+    /// Returns a policy allowing the origins, with the default methods and headers
+    pub fn new(allowed_origins: Vec<String>) -> Self {
+        CorsConfig {
+            allowed_origins,
+            allowed_methods: default_allowed_methods(),
+            allowed_headers: default_allowed_headers(),
+            max_age_seconds: None,
+        }
+    }
+
+    /// Returns the value of the `Access-Control-Allow-Origin` header for the origin of a request, none when the origin is not allowed
+    fn allow_origin(&self, origin: &str) -> Option<String> {
+        self.allowed_origins.iter().find_map(|allowed| {
+            if allowed == "*" {
+                Some("*".to_string())
+            } else if allowed.trim_end_matches('/').eq_ignore_ascii_case(origin) {
+                Some(origin.to_string())
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// A fairing applying a CORS policy
+pub struct Cors {
+    config: CorsConfig,
+}
+
+impl Cors {
+    pub fn new(config: CorsConfig) -> Self {
+        Cors { config }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let Some(origin) = request.headers().get_one("Origin") else {
+            return;
+        };
+        let Some(allow_origin) = self.config.allow_origin(origin) else {
+            warn!("Origin {} is not allowed by the CORS policy", origin);
+            return;
+        };
+        response.set_header(Header::new("Access-Control-Allow-Origin", allow_origin));
+        response.adjoin_header(Header::new("Vary", "Origin"));
+
+        let is_preflight = request.method() == Method::Options
+            && request.headers().contains("Access-Control-Request-Method");
+        if is_preflight {
+            // No route handles OPTIONS: the 404 of Rocket is replaced by an empty answer
+            response.set_status(Status::NoContent);
+            response.set_sized_body(0, Cursor::new(""));
+            response.remove_header("Content-Type");
+            response.set_header(Header::new(
+                "Access-Control-Allow-Methods",
+                self.config.allowed_methods.join(", "),
+            ));
+            response.set_header(Header::new(
+                "Access-Control-Allow-Headers",
+                self.config.allowed_headers.join(", "),
+            ));
+            if let Some(max_age) = self.config.max_age_seconds {
+                response.set_header(Header::new("Access-Control-Max-Age", max_age.to_string()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::local::blocking::Client;
+
+    #[rocket::get("/")]
+    fn index() -> &'static str {
+        "ok"
+    }
+
+    fn client(allowed_origins: &[&str]) -> Client {
+        let config = CorsConfig {
+            max_age_seconds: Some(600),
+            ..CorsConfig::new(allowed_origins.iter().map(|o| o.to_string()).collect())
+        };
+        let rocket = rocket::build()
+            .mount("/", rocket::routes![index])
+            .attach(Cors::new(config));
+        Client::tracked(rocket).unwrap()
+    }
+
+    #[test]
+    fn allowed_origins_receive_cors_headers() {
+        let client = client(&["https://dashboard.example.com"]);
+        let response = client
+            .get("/")
+            .header(Header::new("Origin", "https://dashboard.example.com"))
+            .dispatch();
+        assert_eq!(
+            Some("https://dashboard.example.com"),
+            response.headers().get_one("Access-Control-Allow-Origin")
+        );
+
+        let response = client
+            .get("/")
+            .header(Header::new("Origin", "https://other.example.com"))
+            .dispatch();
+        assert_eq!(Status::Ok, response.status());
+        assert!(!response.headers().contains("Access-Control-Allow-Origin"));
+
+        let response = client.get("/").dispatch();
+        assert!(!response.headers().contains("Access-Control-Allow-Origin"));
+    }
+
+    #[test]
+    fn preflight_requests_are_answered() {
+        let client = client(&["*"]);
+        let response = client
+            .options("/metrics")
+            .header(Header::new("Origin", "https://dashboard.example.com"))
+            .header(Header::new("Access-Control-Request-Method", "GET"))
+            .dispatch();
+        assert_eq!(Status::NoContent, response.status());
+        let headers = response.headers();
+        assert_eq!(Some("*"), headers.get_one("Access-Control-Allow-Origin"));
+        assert_eq!(
+            Some("GET, POST"),
+            headers.get_one("Access-Control-Allow-Methods")
+        );
+        assert_eq!(
+            Some("Authorization, Content-Type, X-API-Key"),
+            headers.get_one("Access-Control-Allow-Headers")
+        );
+        assert_eq!(Some("600"), headers.get_one("Access-Control-Max-Age"));
+    }
+}
@@ -0,0 +1,452 @@
+//! Inventories of the Cost and Usage Reports of AWS (csv files of the legacy CUR or of CUR 2.0 data exports, gzipped or not), so that the impacts of a past billing period are estimated from what was billed rather than from the resources that exist when scanning.
+//!
+//! Reports include the ids of resources only when the report is configured to include them. The instance-hours of instances (`BoxUsage`, `SpotUsage` and `DedicatedUsage` line items) and the GB-months of volumes (`EBS:VolumeUsage` line items) are summed per resource over the period. The impacts estimated over the whole period are then prorated to the hours each resource was billed, see [CostAndUsage::prorate]. CPU loads are not part of the reports: instances are estimated with the default load of Boavizta API.
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+
+use crate::model::{
+    CloudProvider, CloudResource, CloudResourceTag, EstimatedInventory, Inventory, ResourceDetails,
+    StorageUsage,
+};
+use crate::tag_filter::TagFilter;
+use crate::tag_normalization;
+use crate::usage_location::UsageLocation;
+
+/// Types of the line items of the usage of resources (the other types are taxes, fees, credits or refunds)
+const USAGE_LINE_ITEM_TYPES: [&str; 3] = ["Usage", "DiscountedUsage", "SavingsPlanCoveredUsage"];
+
+/// Usage types of the running hours of instances (after the prefix of their region, like EUW3-BoxUsage:m6g.xlarge)
+const INSTANCE_USAGE_TYPES: [&str; 3] = ["BoxUsage", "SpotUsage", "DedicatedUsage"];
+
+/// Hours of a month in the GB-months of volumes
+const HOURS_PER_MONTH: f64 = 730.0;
+
+/// The usage of the instances and volumes of Cost and Usage Reports over their billing period
+#[derive(Clone)]
+pub struct CostAndUsage {
+    /// The instances and volumes billed during the period
+    pub inventory: Inventory,
+    /// Duration of the billing period (hours)
+    pub period_hours: f32,
+    /// Hours billed for each resource (by id)
+    usage_hours: HashMap<String, f32>,
+}
+
+impl CostAndUsage {
+    /// Returns the hours for which a resource was billed during the period
+    pub fn usage_hours(&self, id: &str) -> Option<f32> {
+        self.usage_hours.get(id).copied()
+    }
+
+    /// Prorates the impacts of the resources, estimated for the whole billing period, to the hours for which each resource was billed
+    pub fn prorate(&self, estimated_inventory: EstimatedInventory) -> EstimatedInventory {
+        estimated_inventory.prorate(&self.usage_hours, self.period_hours)
+    }
+}
+
+/// Kind of a billed resource
+#[derive(Clone, Debug, PartialEq)]
+enum BilledKind {
+    Instance { instance_type: String },
+    Volume { storage_type: String },
+}
+
+/// A resource and its usage summed over the line items of the reports
+#[derive(Clone, Debug)]
+struct BilledResource {
+    kind: BilledKind,
+    account_id: Option<String>,
+    aws_region: String,
+    tags: Vec<CloudResourceTag>,
+    /// Instance-hours of instances, hours of the line items of volumes
+    hours: f64,
+    gb_months: f64,
+}
+
+/// Usage of the resources of the reports read so far
+#[derive(Default)]
+struct UsageReports {
+    resources: BTreeMap<String, BilledResource>,
+    period_start: Option<DateTime<Utc>>,
+    period_end: Option<DateTime<Utc>>,
+}
+
+impl UsageReports {
+    fn add_report(&mut self, content: &str, include_block_storage: bool) -> Result<()> {
+        let mut reader = csv::Reader::from_reader(content.as_bytes());
+        let headers = reader
+            .headers()
+            .context("Expected a Cost and Usage Report (csv)")?
+            .clone();
+        let columns: HashMap<String, usize> = headers
+            .iter()
+            .enumerate()
+            .map(|(index, header)| (column_name(header), index))
+            .collect();
+        let column = |names: &[&str]| names.iter().find_map(|name| columns.get(*name).copied());
+        let resource_id = column(&["lineitemresourceid"])
+            .context("Expected a Cost and Usage Report (no resource id column, the report must include resource ids)")?;
+        let usage_type =
+            column(&["lineitemusagetype"]).context("Expected a Cost and Usage Report")?;
+        let usage_amount =
+            column(&["lineitemusageamount"]).context("Expected a Cost and Usage Report")?;
+        let line_item_type = column(&["lineitemlineitemtype"]);
+        let account_id = column(&["lineitemusageaccountid"]);
+        let region = column(&["productregioncode", "productregion"]);
+        let instance_type = column(&["productinstancetype"]);
+        let volume_type = column(&["productvolumeapiname"]);
+        let usage_start = column(&["lineitemusagestartdate"]);
+        let usage_end = column(&["lineitemusageenddate"]);
+        let period_start = column(&["billbillingperiodstartdate"]);
+        let period_end = column(&["billbillingperiodenddate"]);
+        let resource_tags = column(&["resourcetags"]);
+        let tag_columns: Vec<(String, usize)> = headers
+            .iter()
+            .enumerate()
+            .filter_map(|(index, header)| {
+                header
+                    .strip_prefix("resourceTags/")
+                    .map(|key| (key.strip_prefix("user:").unwrap_or(key).to_string(), index))
+            })
+            .collect();
+
+        for record in reader.records() {
+            let record = record.context("Cannot read a line item of the Cost and Usage Report")?;
+            let field = |index: Option<usize>| {
+                index
+                    .and_then(|index| record.get(index))
+                    .unwrap_or_default()
+            };
+            self.extend_period(
+                timestamp(field(period_start)).or_else(|| timestamp(field(usage_start))),
+                timestamp(field(period_end)).or_else(|| timestamp(field(usage_end))),
+            );
+            if line_item_type.is_some() && !USAGE_LINE_ITEM_TYPES.contains(&field(line_item_type)) {
+                continue;
+            }
+            let id = field(Some(resource_id));
+            if id.is_empty() {
+                continue;
+            }
+            let usage_type = field(Some(usage_type));
+            // Usage types start with the prefix of their region, like EUW3-BoxUsage:m6g.xlarge
+            let unprefixed = usage_type
+                .split_once('-')
+                .map(|(_, usage)| usage)
+                .unwrap_or(usage_type);
+            let amount: f64 = field(Some(usage_amount)).parse().unwrap_or_default();
+            let kind = if INSTANCE_USAGE_TYPES
+                .iter()
+                .any(|instance_usage| unprefixed.starts_with(instance_usage))
+            {
+                let instance_type = match field(instance_type) {
+                    "" => unprefixed
+                        .split_once(':')
+                        .map(|(_, t)| t)
+                        .unwrap_or_default(),
+                    instance_type => instance_type,
+                };
+                if instance_type.is_empty() {
+                    warn!("Ignoring instance {} (unknown instance type)", id);
+                    continue;
+                }
+                BilledKind::Instance {
+                    instance_type: instance_type.to_string(),
+                }
+            } else if unprefixed.starts_with("EBS:VolumeUsage") && include_block_storage {
+                let storage_type = match field(volume_type) {
+                    "" => volume_usage_type(unprefixed),
+                    volume_type => volume_type.to_string(),
+                };
+                BilledKind::Volume { storage_type }
+            } else {
+                continue;
+            };
+            let aws_region = field(region);
+            if aws_region.is_empty() {
+                warn!("Ignoring {} (unknown region)", id);
+                continue;
+            }
+            let mut tags: Vec<CloudResourceTag> = tag_columns
+                .iter()
+                .filter_map(|(key, index)| {
+                    let value = record.get(*index).unwrap_or_default();
+                    (!value.is_empty()).then(|| CloudResourceTag {
+                        key: key.clone(),
+                        value: Some(value.to_string()),
+                    })
+                })
+                .collect();
+            tags.extend(data_export_tags(field(resource_tags)));
+
+            let resource = self
+                .resources
+                .entry(id.to_string())
+                .or_insert_with(|| BilledResource {
+                    kind: kind.clone(),
+                    account_id: None,
+                    aws_region: aws_region.to_string(),
+                    tags: Vec::new(),
+                    hours: 0.0,
+                    gb_months: 0.0,
+                });
+            resource.kind = kind;
+            let account_id = field(account_id);
+            if !account_id.is_empty() {
+                resource.account_id = Some(account_id.to_string());
+            }
+            if !tags.is_empty() {
+                resource.tags = tags;
+            }
+            match resource.kind {
+                BilledKind::Instance { .. } => resource.hours += amount,
+                BilledKind::Volume { .. } => {
+                    resource.gb_months += amount;
+                    if let (Some(start), Some(end)) =
+                        (timestamp(field(usage_start)), timestamp(field(usage_end)))
+                    {
+                        resource.hours += (end - start).num_seconds() as f64 / 3600.0;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn extend_period(&mut self, start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>) {
+        if let Some(start) = start {
+            self.period_start = Some(self.period_start.map_or(start, |s| s.min(start)));
+        }
+        if let Some(end) = end {
+            self.period_end = Some(self.period_end.map_or(end, |e| e.max(end)));
+        }
+    }
+
+    fn finish(self, tags: &[String]) -> Result<CostAndUsage> {
+        let (Some(start), Some(end)) = (self.period_start, self.period_end) else {
+            anyhow::bail!("Cannot find the billing period of the Cost and Usage Report (no dates)");
+        };
+        let period_hours = (end - start).num_seconds() as f32 / 3600.0;
+        let filter = TagFilter::parse_all(tags)?;
+
+        let mut resources = Vec::new();
+        let mut usage_hours = HashMap::new();
+        for (id, billed) in self.resources {
+            if billed.hours <= 0.0 {
+                continue;
+            }
+            let resource_details = match billed.kind {
+                BilledKind::Instance { instance_type } => ResourceDetails::Instance {
+                    instance_type,
+                    usage: None,
+                },
+                BilledKind::Volume { storage_type } => ResourceDetails::BlockStorage {
+                    storage_type,
+                    usage: Some(StorageUsage {
+                        // The average size over the hours of the volume
+                        size_gb: ((billed.gb_months * HOURS_PER_MONTH / billed.hours).round()
+                            as i32)
+                            .max(1),
+                        usage_duration_seconds: (billed.hours * 3600.0) as u32,
+                    }),
+                    attached_instances: None,
+                },
+            };
+            let resource = CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: billed.account_id,
+                id: id.clone(),
+                location: UsageLocation::try_from(billed.aws_region.as_str())
+                    .with_context(|| format!("Unsupported region of {}", id))?,
+                resource_details,
+                tags: tag_normalization::normalize(billed.tags),
+                relationships: Vec::new(),
+            };
+            if filter.matches(&resource.tags) {
+                usage_hours.insert(id, billed.hours as f32);
+                resources.push(resource);
+            } else {
+                debug!("Filtered {} (tags do not match)", id);
+            }
+        }
+        Ok(CostAndUsage {
+            inventory: Inventory {
+                resources,
+                execution_statistics: None,
+            },
+            period_hours,
+            usage_hours,
+        })
+    }
+}
+
+/// Parses the instances, and with `include_block_storage` the volumes billed by a Cost and Usage Report (csv)
+pub fn parse_cost_and_usage_report(
+    content: &str,
+    tags: &[String],
+    include_block_storage: bool,
+) -> Result<CostAndUsage> {
+    let mut reports = UsageReports::default();
+    reports.add_report(content, include_block_storage)?;
+    reports.finish(tags)
+}
+
+/// Reads the files of Cost and Usage Reports (csv files, gzipped when their name ends with `.gz`, like the several files of a billing period), see [parse_cost_and_usage_report]
+pub fn read_cost_and_usage_reports(
+    paths: &[String],
+    tags: &[String],
+    include_block_storage: bool,
+) -> Result<CostAndUsage> {
+    let mut reports = UsageReports::default();
+    for path in paths {
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("Cannot read Cost and Usage Report {}", path))?;
+        let mut content = String::new();
+        if path.ends_with(".gz") {
+            flate2::read::MultiGzDecoder::new(bytes.as_slice())
+                .read_to_string(&mut content)
+                .with_context(|| format!("Cannot decompress Cost and Usage Report {}", path))?;
+        } else {
+            content = String::from_utf8(bytes)
+                .with_context(|| format!("Cannot read Cost and Usage Report {}", path))?;
+        }
+        reports
+            .add_report(&content, include_block_storage)
+            .with_context(|| format!("Cannot parse Cost and Usage Report {}", path))?;
+    }
+    reports.finish(tags)
+}
+
+/// Returns the name of a column shared by the legacy reports and the data exports (like lineitemresourceid for lineItem/ResourceId and line_item_resource_id)
+fn column_name(header: &str) -> String {
+    header
+        .chars()
+        .filter(|c| *c != '/' && *c != '_')
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Returns the time of a date of a report (like 2024-05-01T00:00:00Z, or 2024-05-01 00:00:00.000 in data exports)
+fn timestamp(date: &str) -> Option<DateTime<Utc>> {
+    if date.is_empty() {
+        return None;
+    }
+    DateTime::parse_from_rfc3339(date)
+        .map(|date| date.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S%.f")
+                .ok()
+                .map(|date| date.and_utc())
+        })
+}
+
+/// Returns the type of volume of an EBS usage type (like gp3 for EBS:VolumeUsage.gp3, or standard for the magnetic volumes of EBS:VolumeUsage)
+fn volume_usage_type(usage_type: &str) -> String {
+    match usage_type.split_once('.') {
+        Some((_, "piops")) => "io1".to_string(),
+        Some((_, volume_type)) => volume_type.to_string(),
+        None => "standard".to_string(),
+    }
+}
+
+/// Returns the user tags of the resource_tags column of data exports (a json map of keys like user_team to their values)
+fn data_export_tags(resource_tags: &str) -> Vec<CloudResourceTag> {
+    let Ok(serde_json::Value::Object(tags)) = serde_json::from_str(resource_tags) else {
+        return Vec::new();
+    };
+    tags.iter()
+        .map(|(key, value)| CloudResourceTag {
+            key: key.strip_prefix("user_").unwrap_or(key).to_string(),
+            value: value.as_str().map(str::to_string),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+
+    const REPORT: &str = "\
+identity/LineItemId,bill/BillingPeriodStartDate,bill/BillingPeriodEndDate,lineItem/UsageAccountId,lineItem/LineItemType,lineItem/UsageStartDate,lineItem/UsageEndDate,lineItem/ProductCode,lineItem/UsageType,lineItem/ResourceId,lineItem/UsageAmount,product/instanceType,product/region,product/volumeApiName,resourceTags/user:team
+1,2024-04-01T00:00:00Z,2024-05-01T00:00:00Z,111111111111,Usage,2024-04-01T00:00:00Z,2024-04-02T00:00:00Z,AmazonEC2,EUW3-BoxUsage:m6g.xlarge,i-0123456789abcdef0,24,m6g.xlarge,eu-west-3,,web
+2,2024-04-01T00:00:00Z,2024-05-01T00:00:00Z,111111111111,DiscountedUsage,2024-04-02T00:00:00Z,2024-04-03T00:00:00Z,AmazonEC2,EUW3-BoxUsage:m6g.xlarge,i-0123456789abcdef0,12,m6g.xlarge,eu-west-3,,web
+3,2024-04-01T00:00:00Z,2024-05-01T00:00:00Z,111111111111,Usage,2024-04-01T00:00:00Z,2024-04-16T00:00:00Z,AmazonEC2,EUW3-EBS:VolumeUsage.gp3,vol-0123456789abcdef0,49.3150684,,eu-west-3,gp3,web
+4,2024-04-01T00:00:00Z,2024-05-01T00:00:00Z,222222222222,Usage,2024-04-01T00:00:00Z,2024-05-01T00:00:00Z,AmazonEC2,BoxUsage:t3.micro,i-0fedcba9876543210,720,,us-east-1,,batch
+5,2024-04-01T00:00:00Z,2024-05-01T00:00:00Z,111111111111,Tax,2024-04-01T00:00:00Z,2024-05-01T00:00:00Z,AmazonEC2,,,1,,,,
+6,2024-04-01T00:00:00Z,2024-05-01T00:00:00Z,111111111111,Usage,2024-04-01T00:00:00Z,2024-04-02T00:00:00Z,AmazonEC2,EUW3-EBS:SnapshotUsage,snap-0123456789abcdef0,2,,eu-west-3,,web
+";
+
+    #[test]
+    fn reports_are_inventoried_with_their_usage() {
+        let usage = parse_cost_and_usage_report(REPORT, &[], false).unwrap();
+        assert_eq!(720.0, usage.period_hours);
+        let ids: Vec<&str> = usage
+            .inventory
+            .resources
+            .iter()
+            .map(|r| r.id.as_str())
+            .collect();
+        assert_eq!(vec!["i-0123456789abcdef0", "i-0fedcba9876543210"], ids);
+        assert_eq!(Some(36.0), usage.usage_hours("i-0123456789abcdef0"));
+        assert_eq!(
+            Some("t3.micro".to_string()),
+            usage.inventory.resources[1]
+                .resource_details
+                .resource_type()
+        );
+        assert_eq!(
+            Some("222222222222".to_string()),
+            usage.inventory.resources[1].account_id
+        );
+        assert_eq!("team", usage.inventory.resources[0].tags[0].key);
+
+        let usage = parse_cost_and_usage_report(REPORT, &["team=web".to_string()], true).unwrap();
+        assert_eq!(2, usage.inventory.resources.len());
+        let volume = &usage.inventory.resources[1];
+        assert_eq!("vol-0123456789abcdef0", volume.id);
+        assert_eq!("eu-west-3", volume.location.aws_region);
+        assert_eq!(Some(360.0), usage.usage_hours(&volume.id));
+        let ResourceDetails::BlockStorage { usage, .. } = &volume.resource_details else {
+            panic!("Expected a volume");
+        };
+        assert_eq!(100, usage.as_ref().unwrap().size_gb);
+
+        assert!(parse_cost_and_usage_report("a,b\n1,2\n", &[], false).is_err());
+    }
+
+    #[test]
+    fn impacts_are_prorated_to_the_billed_hours() {
+        let export = "\
+bill_billing_period_start_date,bill_billing_period_end_date,line_item_line_item_type,line_item_usage_type,line_item_resource_id,line_item_usage_amount,product_instance_type,product_region_code,resource_tags
+2024-04-01 00:00:00.000,2024-05-01 00:00:00.000,Usage,EUW3-SpotUsage:c5.large,i-0123456789abcdef0,180,c5.large,eu-west-3,\"{\"\"user_team\"\": \"\"batch\"\"}\"
+";
+        let usage =
+            parse_cost_and_usage_report(export, &["team=batch".to_string()], false).unwrap();
+        assert_eq!(1, usage.inventory.resources.len());
+
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![CloudResourceWithImpacts {
+                cloud_resource: usage.inventory.resources[0].clone(),
+                impacts_values: Some(ImpactsValues {
+                    gwp_use_kgco2eq: 8.0,
+                    gwp_manufacture_kgco2eq: 4.0,
+                    ..Default::default()
+                }),
+                impacts_duration_hours: 720.0,
+                cost: None,
+            }],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let prorated = usage.prorate(estimated_inventory);
+        let resource = &prorated.impacting_resources[0];
+        assert_eq!(180.0, resource.impacts_duration_hours);
+        let impacts = resource.impacts_values.as_ref().unwrap();
+        assert_eq!(2.0, impacts.gwp_use_kgco2eq);
+        assert_eq!(1.0, impacts.gwp_manufacture_kgco2eq);
+    }
+}
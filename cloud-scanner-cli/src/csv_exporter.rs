@@ -0,0 +1,162 @@
+//!  A module to format the resources and their impacts as CSV (one row per resource).
+use anyhow::{Context, Result};
+
+use crate::model::{EstimatedInventory, InstanceState, ResourceDetails};
+
+/// Columns of the CSV output
+const HEADERS: [&str; 16] = [
+    "resource_id",
+    "resource_kind",
+    "resource_type",
+    "aws_region",
+    "country",
+    "resource_state",
+    "average_cpu_load",
+    "storage_size_gb",
+    "tags",
+    "impacts_duration_hours",
+    "adp_manufacture_kgsbeq",
+    "adp_use_kgsbeq",
+    "pe_manufacture_megajoules",
+    "pe_use_megajoules",
+    "gwp_manufacture_kgco2eq",
+    "gwp_use_kgco2eq",
+];
+
+/// Returns the resources of an estimated inventory as CSV (with a header line).
+///
+/// Impacts columns are left empty for resources that could not be assessed.
+pub fn get_resources_csv(estimated_inventory: &EstimatedInventory) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(HEADERS)?;
+
+    for resource in estimated_inventory.impacting_resources.iter() {
+        let cloud_resource = &resource.cloud_resource;
+        let details = &cloud_resource.resource_details;
+
+        let (state, cpu_load, size_gb) = match details {
+            ResourceDetails::Instance {
+                usage: Some(usage), ..
+            } => {
+                let state = match usage.state {
+                    InstanceState::Running => "Running",
+                    InstanceState::Stopped => "Stopped",
+                };
+                (
+                    state.to_string(),
+                    usage.average_cpu_load.to_string(),
+                    "".to_string(),
+                )
+            }
+            ResourceDetails::BlockStorage {
+                usage: Some(usage), ..
+            } => ("".to_string(), "".to_string(), usage.size_gb.to_string()),
+            _ => ("".to_string(), "".to_string(), "".to_string()),
+        };
+
+        let impacts: Vec<String> = match &resource.impacts_values {
+            Some(i) => [
+                i.adp_manufacture_kgsbeq,
+                i.adp_use_kgsbeq,
+                i.pe_manufacture_megajoules,
+                i.pe_use_megajoules,
+                i.gwp_manufacture_kgco2eq,
+                i.gwp_use_kgco2eq,
+            ]
+            .iter()
+            .map(|v| v.to_string())
+            .collect(),
+            None => vec!["".to_string(); 6],
+        };
+
+        let mut record: Vec<String> = vec![
+            cloud_resource.id.clone(),
+            details.kind().to_string(),
+            details.resource_type().unwrap_or_default(),
+            cloud_resource.location.aws_region.clone(),
+            cloud_resource.location.iso_country_code.clone(),
+            state,
+            cpu_load,
+            size_gb,
+            cloud_resource.tags_as_metric_label_value(),
+            resource.impacts_duration_hours.to_string(),
+        ];
+        record.extend(impacts);
+        writer.write_record(&record)?;
+    }
+
+    let bytes = writer
+        .into_inner()
+        .context("Cannot write resources as CSV")?;
+    String::from_utf8(bytes).context("CSV output is not valid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+    use crate::model::{CloudProvider, CloudResource, CloudResourceTag, StorageUsage};
+    use crate::usage_location::UsageLocation;
+
+    #[test]
+    fn format_resources_as_csv() {
+        let volume: CloudResource = CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: None,
+            id: "vol-1".to_string(),
+            location: UsageLocation::try_from("eu-west-3").unwrap(),
+            resource_details: ResourceDetails::BlockStorage {
+                storage_type: "gp2".to_string(),
+                usage: Some(StorageUsage {
+                    size_gb: 42,
+                    usage_duration_seconds: 3600,
+                }),
+                attached_instances: None,
+            },
+            tags: vec![CloudResourceTag {
+                key: "Name".to_string(),
+                value: Some("a, b".to_string()),
+            }],
+            relationships: Vec::new(),
+        };
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![
+                CloudResourceWithImpacts {
+                    cloud_resource: volume.clone(),
+                    impacts_values: Some(ImpactsValues {
+                        adp_manufacture_kgsbeq: 0.1,
+                        adp_use_kgsbeq: 0.0,
+                        pe_manufacture_megajoules: 0.3,
+                        pe_use_megajoules: 0.0,
+                        gwp_manufacture_kgco2eq: 0.5,
+                        gwp_use_kgco2eq: 0.0,
+                        raw_data: None,
+                    }),
+                    impacts_duration_hours: 1.0,
+                    cost: None,
+                },
+                CloudResourceWithImpacts {
+                    cloud_resource: volume,
+                    impacts_values: None,
+                    impacts_duration_hours: 1.0,
+                    cost: None,
+                },
+            ],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+
+        let csv = get_resources_csv(&estimated_inventory).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(3, lines.len());
+        assert_eq!(HEADERS.join(","), lines[0]);
+        assert_eq!(
+            "vol-1,BlockStorage,gp2,eu-west-3,FRA,,,42,\"Name:a, b;\",1,0.1,0,0.3,0,0.5,0",
+            lines[1]
+        );
+        assert_eq!(
+            "vol-1,BlockStorage,gp2,eu-west-3,FRA,,,42,\"Name:a, b;\",1,,,,,,",
+            lines[2]
+        );
+    }
+}
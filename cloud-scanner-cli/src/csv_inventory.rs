@@ -0,0 +1,401 @@
+//! Inventories of csv files (like the exports of a CMDB or spreadsheets), whose columns are mapped to the fields of resources, so that resources that cloud-scanner cannot list are estimated too.
+//!
+//! The fields of a resource are ([FIELDS]):
+//! - `id` and `type` (the instance type of instances, or the volume type of volumes), required
+//! - `kind` (`instance` or `volume`, volumes being the resources of a `size` otherwise) and `size` (GB, of volumes)
+//! - `region` (the region of the scan otherwise) and `account`
+//! - `hours` (of use, like 720 or 30d, the duration of the estimation otherwise) and `load` (average cpu load of instances in percent, the default load of Boavizta API otherwise)
+//!
+//! Each field is read from the column of its name unless mapped to another column (see [ColumnMapping]), and the tags of resources are read from the columns mapped to `tag:<key>`.
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+
+use crate::model::{
+    CloudProvider, CloudResource, CloudResourceTag, EstimatedInventory, InstanceState,
+    InstanceUsage, Inventory, ResourceDetails, StorageUsage, DEFAULT_VOLUME_TYPE,
+};
+use crate::tag_filter::TagFilter;
+use crate::tag_normalization;
+use crate::usage_location::UsageLocation;
+
+/// Fields of the resources of a csv inventory
+pub const FIELDS: [&str; 8] = [
+    "id", "kind", "type", "size", "region", "account", "hours", "load",
+];
+
+/// Mapping of the fields of resources to the columns of a csv file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ColumnMapping {
+    /// Columns of the fields (by field), the columns of their names otherwise
+    columns: HashMap<String, String>,
+    /// Keys of the tags and their columns
+    tags: Vec<(String, String)>,
+}
+
+impl ColumnMapping {
+    /// Parses mappings like `id=Hostname`, `load=CPU %` or `tag:team=Owner team`
+    pub fn parse(mappings: &[String]) -> Result<Self> {
+        let mut mapping = ColumnMapping::default();
+        for field_and_column in mappings {
+            let (field, column) = field_and_column.split_once('=').with_context(|| {
+                format!(
+                    "Invalid column mapping {} (expected field=column)",
+                    field_and_column
+                )
+            })?;
+            let (field, column) = (field.trim(), column.trim());
+            if let Some(key) = field.strip_prefix("tag:") {
+                mapping.tags.push((key.to_string(), column.to_string()));
+            } else if FIELDS.contains(&field) {
+                mapping
+                    .columns
+                    .insert(field.to_string(), column.to_string());
+            } else {
+                anyhow::bail!(
+                    "Unknown field {} in column mapping {} (expected one of {} or tag:<key>)",
+                    field,
+                    field_and_column,
+                    FIELDS.join(", ")
+                );
+            }
+        }
+        Ok(mapping)
+    }
+
+    /// Returns the column of a field
+    fn column<'a>(&'a self, field: &'a str) -> &'a str {
+        self.columns.get(field).map(String::as_str).unwrap_or(field)
+    }
+}
+
+/// The resources of a csv inventory and their hours of use
+#[derive(Clone)]
+pub struct CsvInventory {
+    pub inventory: Inventory,
+    /// Hours of use of the resources of an `hours` (by id)
+    usage_hours: HashMap<String, f32>,
+}
+
+impl CsvInventory {
+    /// Prorates the impacts of the resources of an `hours`, estimated for `use_duration_hours`, to their hours of use
+    pub fn prorate(
+        &self,
+        estimated_inventory: EstimatedInventory,
+        use_duration_hours: f32,
+    ) -> EstimatedInventory {
+        estimated_inventory.prorate(&self.usage_hours, use_duration_hours)
+    }
+}
+
+/// Parses the instances, and with `include_block_storage` the volumes of a csv inventory (separated by commas, semicolons or tabs), located in `default_region` unless their region is set
+pub fn parse_csv_inventory(
+    content: &str,
+    mapping: &ColumnMapping,
+    default_region: &str,
+    tags: &[String],
+    include_block_storage: bool,
+) -> Result<CsvInventory> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter(content))
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes());
+    let headers = reader
+        .headers()
+        .context("Expected a csv inventory")?
+        .clone();
+    let index_of = |column: &str| {
+        headers
+            .iter()
+            .position(|header| header.eq_ignore_ascii_case(column))
+    };
+    let mut fields = HashMap::new();
+    for field in FIELDS {
+        let column = mapping.column(field);
+        match index_of(column) {
+            Some(index) => {
+                fields.insert(field, index);
+            }
+            None if ["id", "type"].contains(&field) || mapping.columns.contains_key(field) => {
+                anyhow::bail!(
+                    "No column {} (of the {} of resources) in the csv inventory",
+                    column,
+                    field
+                )
+            }
+            None => {}
+        }
+    }
+    let tag_columns = mapping
+        .tags
+        .iter()
+        .map(|(key, column)| {
+            index_of(column).map(|index| (key, index)).with_context(|| {
+                format!("No column {} (of tag {}) in the csv inventory", column, key)
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let filter = TagFilter::parse_all(tags)?;
+
+    let mut resources = Vec::new();
+    let mut usage_hours = HashMap::new();
+    for (line, record) in reader.records().enumerate() {
+        // The first line of resources is the second line of the file
+        let line = line + 2;
+        let record =
+            record.with_context(|| format!("Cannot read line {} of the csv inventory", line))?;
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .and_then(|index| record.get(*index))
+                .unwrap_or_default()
+        };
+        let id = field("id");
+        if id.is_empty() {
+            debug!("Ignoring line {} of the csv inventory (no id)", line);
+            continue;
+        }
+        let hours = match field("hours") {
+            "" => None,
+            hours => Some(
+                crate::duration::parse_hours(hours)
+                    .with_context(|| format!("Invalid hours of {} (line {})", id, line))?,
+            ),
+        };
+        let usage_duration_seconds = hours.map_or(3600, |hours| (hours * 3600.0) as u32);
+        let size = field("size");
+        let is_volume = match field("kind").to_lowercase().as_str() {
+            "" => !size.is_empty(),
+            "instance" | "instances" | "ec2" => false,
+            "volume" | "volumes" | "ebs" | "blockstorage" => true,
+            kind => {
+                debug!("Ignoring {} (unsupported kind {})", id, kind);
+                continue;
+            }
+        };
+        let resource_details = if is_volume {
+            if !include_block_storage {
+                continue;
+            }
+            let size_gb: i32 = size
+                .parse()
+                .with_context(|| format!("Invalid size of volume {} (line {})", id, line))?;
+            ResourceDetails::BlockStorage {
+                storage_type: match field("type") {
+                    "" => DEFAULT_VOLUME_TYPE.to_string(),
+                    storage_type => storage_type.to_string(),
+                },
+                usage: Some(StorageUsage {
+                    size_gb,
+                    usage_duration_seconds,
+                }),
+                attached_instances: None,
+            }
+        } else {
+            let instance_type = field("type");
+            if instance_type.is_empty() {
+                warn!("Ignoring instance {} (no instance type)", id);
+                continue;
+            }
+            let usage = match field("load").trim_end_matches('%').trim() {
+                "" => None,
+                load => {
+                    let average_cpu_load: f64 = load
+                        .parse()
+                        .with_context(|| format!("Invalid load of {} (line {})", id, line))?;
+                    if !(0.0..=100.0).contains(&average_cpu_load) {
+                        anyhow::bail!(
+                            "The load of {} must be between 0 and 100 (%), not {} (line {})",
+                            id,
+                            average_cpu_load,
+                            line
+                        );
+                    }
+                    Some(InstanceUsage {
+                        average_cpu_load,
+                        usage_duration_seconds,
+                        state: InstanceState::Running,
+                    })
+                }
+            };
+            ResourceDetails::Instance {
+                instance_type: instance_type.to_string(),
+                usage,
+            }
+        };
+        let region = match field("region") {
+            "" => default_region,
+            region => region,
+        };
+        let resource = CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: Some(field("account"))
+                .filter(|account| !account.is_empty())
+                .map(str::to_string),
+            id: id.to_string(),
+            location: UsageLocation::try_from(region)
+                .with_context(|| format!("Unsupported region of {} (line {})", id, line))?,
+            resource_details,
+            tags: tag_normalization::normalize(
+                tag_columns
+                    .iter()
+                    .filter_map(|(key, index)| {
+                        let value = record.get(*index).unwrap_or_default();
+                        (!value.is_empty()).then(|| CloudResourceTag {
+                            key: key.to_string(),
+                            value: Some(value.to_string()),
+                        })
+                    })
+                    .collect(),
+            ),
+            relationships: Vec::new(),
+        };
+        if !filter.matches(&resource.tags) {
+            debug!("Filtered {} (tags do not match)", id);
+            continue;
+        }
+        if let Some(hours) = hours {
+            usage_hours.insert(id.to_string(), hours);
+        }
+        resources.push(resource);
+    }
+    Ok(CsvInventory {
+        inventory: Inventory {
+            resources,
+            execution_statistics: None,
+        },
+        usage_hours,
+    })
+}
+
+/// Reads a csv inventory from a file, see [parse_csv_inventory]
+pub fn read_csv_inventory(
+    path: &str,
+    mapping: &ColumnMapping,
+    default_region: &str,
+    tags: &[String],
+    include_block_storage: bool,
+) -> Result<CsvInventory> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read csv inventory {}", path))?;
+    parse_csv_inventory(
+        &content,
+        mapping,
+        default_region,
+        tags,
+        include_block_storage,
+    )
+    .with_context(|| format!("Cannot parse csv inventory {}", path))
+}
+
+/// Returns the delimiter of the header of a csv file: a semicolon (like the exports of spreadsheets in many locales) or a tab when there are more of them than commas
+fn delimiter(content: &str) -> u8 {
+    let header = content.lines().next().unwrap_or_default();
+    let count = |c: char| header.matches(c).count();
+    [b';', b'\t']
+        .into_iter()
+        .filter(|d| count(*d as char) > count(','))
+        .max_by_key(|d| count(*d as char))
+        .unwrap_or(b',')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+
+    #[test]
+    fn mapped_columns_are_inventoried() {
+        let export = "\
+Hostname;Model;Size;Region;Hours;CPU %;Team
+web-01;m6g.xlarge;;eu-west-3;30d;25 %;web
+web-01-data;gp3;100;eu-west-3;360;;web
+batch-01;c5.large;;;;;batch
+";
+        let mapping = ColumnMapping::parse(&[
+            "id=Hostname".to_string(),
+            "type=Model".to_string(),
+            "load=CPU %".to_string(),
+            "tag:team=Team".to_string(),
+        ])
+        .unwrap();
+        let csv = parse_csv_inventory(export, &mapping, "us-east-1", &[], false).unwrap();
+        let ids: Vec<&str> = csv
+            .inventory
+            .resources
+            .iter()
+            .map(|r| r.id.as_str())
+            .collect();
+        assert_eq!(vec!["web-01", "batch-01"], ids);
+        assert_eq!("us-east-1", csv.inventory.resources[1].location.aws_region);
+        let ResourceDetails::Instance { usage, .. } = &csv.inventory.resources[0].resource_details
+        else {
+            panic!("Expected an instance");
+        };
+        assert_eq!(25.0, usage.as_ref().unwrap().average_cpu_load);
+        assert_eq!("team", csv.inventory.resources[0].tags[0].key);
+
+        let web = vec!["team=web".to_string()];
+        let csv = parse_csv_inventory(export, &mapping, "us-east-1", &web, true).unwrap();
+        assert_eq!(2, csv.inventory.resources.len());
+        assert_eq!(
+            Some("gp3".to_string()),
+            csv.inventory.resources[1].resource_details.resource_type()
+        );
+
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: csv
+                .inventory
+                .resources
+                .iter()
+                .map(|resource| CloudResourceWithImpacts {
+                    cloud_resource: resource.clone(),
+                    impacts_values: Some(ImpactsValues {
+                        gwp_use_kgco2eq: 2.0,
+                        ..Default::default()
+                    }),
+                    impacts_duration_hours: 720.0,
+                    cost: None,
+                })
+                .collect(),
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let prorated = csv.prorate(estimated_inventory, 720.0);
+        let impacts: Vec<f64> = prorated
+            .impacting_resources
+            .iter()
+            .map(|r| r.impacts_values.as_ref().unwrap().gwp_use_kgco2eq)
+            .collect();
+        assert_eq!(vec![2.0, 1.0], impacts);
+    }
+
+    #[test]
+    fn invalid_inventories_are_rejected() {
+        assert!(ColumnMapping::parse(&["name=Hostname".to_string()]).is_err());
+        assert!(ColumnMapping::parse(&["Hostname".to_string()]).is_err());
+        let default = ColumnMapping::default();
+        assert!(parse_csv_inventory(
+            "id,region\na,eu-west-3\n",
+            &default,
+            "eu-west-1",
+            &[],
+            false
+        )
+        .is_err());
+        let mapped = ColumnMapping::parse(&["load=CPU".to_string()]).unwrap();
+        assert!(
+            parse_csv_inventory("id,type\na,t3.micro\n", &mapped, "eu-west-1", &[], false).is_err()
+        );
+        assert!(parse_csv_inventory(
+            "id,type,load\na,t3.micro,150\n",
+            &default,
+            "eu-west-1",
+            &[],
+            false
+        )
+        .is_err());
+        let csv = parse_csv_inventory("ID,Type\na,t3.micro\n", &default, "eu-west-1", &[], false)
+            .unwrap();
+        assert_eq!(1, csv.inventory.resources.len());
+    }
+}
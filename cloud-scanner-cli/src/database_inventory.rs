@@ -0,0 +1,334 @@
+//! Inventories read from the databases of cloud asset tools, like the PostgreSQL service of Steampipe or the PostgreSQL and SQLite destinations of CloudQuery, so that teams already syncing their resources estimate impacts without granting describe permissions to the scanner.
+//!
+//! The inventory is the result of a query returning one row per resource, with the columns:
+//! - `id` and `region` (text, required)
+//! - `instance_type` (text) for instances, or `volume_type` (text) and `volume_size_gb` (bigint) for volumes
+//! - `account_id`, `state` (of instances), `attached_instance_id` (of volumes) and `tags` (text of a json object), optional
+//!
+//! The queries of the tables of Steampipe and CloudQuery are built in ([STEAMPIPE_QUERY] and [CLOUDQUERY_QUERY]). CPU loads are not part of the databases: instances are estimated with the default load of Boavizta API.
+use anyhow::{Context, Result};
+use rusqlite::{Connection, OpenFlags};
+
+use crate::aws_cloud_provider::ResourceSelection;
+use crate::model::{
+    CloudProvider, CloudResource, CloudResourceTag, Inventory, ResourceDetails, StorageAttachment,
+    StorageUsage,
+};
+use crate::tag_filter::TagFilter;
+use crate::tag_normalization;
+use crate::usage_location::UsageLocation;
+
+/// Query of the instances and volumes of the tables of the AWS plugin of Steampipe
+pub const STEAMPIPE_QUERY: &str = "\
+SELECT instance_id AS id, region, account_id, instance_type, instance_state AS state, \
+CAST(NULL AS TEXT) AS volume_type, CAST(NULL AS BIGINT) AS volume_size_gb, CAST(NULL AS TEXT) AS attached_instance_id, \
+CAST(tags AS TEXT) AS tags FROM aws_ec2_instance \
+UNION ALL \
+SELECT volume_id, region, account_id, NULL, NULL, volume_type, CAST(size AS BIGINT), attachments->0->>'InstanceId', \
+CAST(tags AS TEXT) FROM aws_ebs_volume";
+
+/// Query of the instances and volumes of the tables of the AWS plugin of CloudQuery (valid for its PostgreSQL and SQLite destinations)
+pub const CLOUDQUERY_QUERY: &str = "\
+SELECT instance_id AS id, region, account_id, instance_type, state->>'Name' AS state, \
+CAST(NULL AS TEXT) AS volume_type, CAST(NULL AS BIGINT) AS volume_size_gb, CAST(NULL AS TEXT) AS attached_instance_id, \
+CAST(tags AS TEXT) AS tags FROM aws_ec2_instances \
+UNION ALL \
+SELECT volume_id, region, account_id, NULL, NULL, volume_type, CAST(size AS BIGINT), attachments->0->>'InstanceId', \
+CAST(tags AS TEXT) FROM aws_ec2_ebs_volumes";
+
+/// A resource returned by the query of the inventory
+#[derive(Clone, Debug, Default)]
+struct ResourceRow {
+    id: String,
+    region: String,
+    account_id: Option<String>,
+    instance_type: Option<String>,
+    state: Option<String>,
+    volume_type: Option<String>,
+    volume_size_gb: Option<i64>,
+    attached_instance_id: Option<String>,
+    tags: Option<String>,
+}
+
+/// Returns the query of the inventory: the built-in query of `steampipe` or `cloudquery`, or the query itself
+pub fn inventory_query(query: &str) -> &str {
+    match query {
+        "steampipe" => STEAMPIPE_QUERY,
+        "cloudquery" => CLOUDQUERY_QUERY,
+        query => query,
+    }
+}
+
+/// Reads the instances (unless skipped by the selection, and in the selected states), and with `include_block_storage` the volumes returned by a query (see [inventory_query]) of a PostgreSQL database (connection string like `postgresql://steampipe@localhost:9193/steampipe`, connecting with the TLS of its `sslmode`, see [crate::postgres_tls]) or of a SQLite database (its path, optionally prefixed by `sqlite://`)
+pub async fn read_database_inventory(
+    database: &str,
+    query: &str,
+    tags: &[String],
+    include_block_storage: bool,
+    selection: &ResourceSelection,
+) -> Result<Inventory> {
+    let query = inventory_query(query);
+    let rows = if is_postgres(database) {
+        query_postgres(database, query).await?
+    } else {
+        query_sqlite(
+            database.strip_prefix("sqlite://").unwrap_or(database),
+            query,
+        )?
+    };
+    inventory_of_rows(rows, tags, include_block_storage, selection)
+}
+
+/// Returns true for the connection strings of PostgreSQL (urls or key=value settings), false for the paths of SQLite databases
+fn is_postgres(database: &str) -> bool {
+    database.starts_with("postgres://")
+        || database.starts_with("postgresql://")
+        || database.contains("host=")
+}
+
+async fn query_postgres(connection_string: &str, query: &str) -> Result<Vec<ResourceRow>> {
+    let client = crate::postgres_tls::connect(connection_string)
+        .await
+        .context("Cannot connect to the PostgreSQL inventory database")?;
+    let rows = client
+        .query(query, &[])
+        .await
+        .context("Cannot query the PostgreSQL inventory database")?;
+    rows.iter()
+        .map(|row| {
+            let has = |name: &str| row.columns().iter().any(|column| column.name() == name);
+            let text = |name: &str| -> Result<Option<String>> {
+                if !has(name) {
+                    return Ok(None);
+                }
+                row.try_get(name)
+                    .with_context(|| format!("Expected a text column {}", name))
+            };
+            let volume_size_gb = if has("volume_size_gb") {
+                row.try_get::<_, Option<i64>>("volume_size_gb")
+                    .or_else(|_| {
+                        row.try_get::<_, Option<i32>>("volume_size_gb")
+                            .map(|size| size.map(i64::from))
+                    })
+                    .context("Expected a bigint column volume_size_gb")?
+            } else {
+                None
+            };
+            resource_row(
+                text("id")?,
+                text("region")?,
+                ResourceRow {
+                    account_id: text("account_id")?,
+                    instance_type: text("instance_type")?,
+                    state: text("state")?,
+                    volume_type: text("volume_type")?,
+                    volume_size_gb,
+                    attached_instance_id: text("attached_instance_id")?,
+                    tags: text("tags")?,
+                    ..Default::default()
+                },
+            )
+        })
+        .collect()
+}
+
+fn query_sqlite(path: &str, query: &str) -> Result<Vec<ResourceRow>> {
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Cannot open the SQLite inventory database {}", path))?;
+    let mut statement = connection
+        .prepare(query)
+        .context("Cannot query the SQLite inventory database")?;
+    let names: Vec<String> = statement
+        .column_names()
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+    let mut rows = statement
+        .query([])
+        .context("Cannot query the SQLite inventory database")?;
+    let mut resources = Vec::new();
+    while let Some(row) = rows.next()? {
+        let has = |name: &str| names.iter().any(|column| column == name);
+        let text = |name: &str| -> Result<Option<String>> {
+            if !has(name) {
+                return Ok(None);
+            }
+            row.get(name)
+                .with_context(|| format!("Expected a text column {}", name))
+        };
+        let volume_size_gb = if has("volume_size_gb") {
+            row.get("volume_size_gb")
+                .context("Expected an integer column volume_size_gb")?
+        } else {
+            None
+        };
+        resources.push(resource_row(
+            text("id")?,
+            text("region")?,
+            ResourceRow {
+                account_id: text("account_id")?,
+                instance_type: text("instance_type")?,
+                state: text("state")?,
+                volume_type: text("volume_type")?,
+                volume_size_gb,
+                attached_instance_id: text("attached_instance_id")?,
+                tags: text("tags")?,
+                ..Default::default()
+            },
+        )?);
+    }
+    Ok(resources)
+}
+
+/// Returns a row of the required id and region
+fn resource_row(
+    id: Option<String>,
+    region: Option<String>,
+    row: ResourceRow,
+) -> Result<ResourceRow> {
+    let id = id.context("Expected an id column (not null) in the inventory query")?;
+    let region = region.with_context(|| format!("No region for {} in the inventory query", id))?;
+    Ok(ResourceRow { id, region, ..row })
+}
+
+fn inventory_of_rows(
+    rows: Vec<ResourceRow>,
+    tags: &[String],
+    include_block_storage: bool,
+    selection: &ResourceSelection,
+) -> Result<Inventory> {
+    let filter = TagFilter::parse_all(tags)?;
+    let mut resources = Vec::new();
+    for row in rows {
+        let resource_details = if let Some(instance_type) = row.instance_type {
+            if selection.skip_instances {
+                continue;
+            }
+            let state = row.state.unwrap_or_default();
+            if !selection.instance_states.is_empty() && !selection.instance_states.contains(&state)
+            {
+                debug!("Filtered instance {} (in state {})", row.id, state);
+                continue;
+            }
+            ResourceDetails::Instance {
+                instance_type,
+                usage: None,
+            }
+        } else if let Some(storage_type) = row.volume_type {
+            if !include_block_storage {
+                continue;
+            }
+            let Some(size_gb) = row.volume_size_gb else {
+                warn!("Ignoring volume {} (no size)", row.id);
+                continue;
+            };
+            ResourceDetails::BlockStorage {
+                storage_type,
+                usage: Some(StorageUsage {
+                    size_gb: size_gb as i32,
+                    usage_duration_seconds: 3600,
+                }),
+                attached_instances: row
+                    .attached_instance_id
+                    .map(|instance_id| vec![StorageAttachment { instance_id }]),
+            }
+        } else {
+            debug!(
+                "Ignoring {} (neither an instance type nor a volume type)",
+                row.id
+            );
+            continue;
+        };
+        let resource = CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: row.account_id,
+            location: UsageLocation::try_from(row.region.as_str())
+                .with_context(|| format!("Unsupported region of {}", row.id))?,
+            id: row.id,
+            resource_details,
+            tags: tag_normalization::normalize(row_tags(row.tags.as_deref())),
+            relationships: Vec::new(),
+        };
+        if filter.matches(&resource.tags) {
+            resources.push(resource);
+        } else {
+            debug!("Filtered {} (tags do not match)", resource.id);
+        }
+    }
+    Ok(Inventory {
+        resources,
+        execution_statistics: None,
+    })
+}
+
+/// Returns the tags of a row (the text of a json object of their keys to their values)
+fn row_tags(tags: Option<&str>) -> Vec<CloudResourceTag> {
+    let Some(Ok(serde_json::Value::Object(tags))) = tags.map(serde_json::from_str) else {
+        return Vec::new();
+    };
+    tags.iter()
+        .map(|(key, value)| CloudResourceTag {
+            key: key.clone(),
+            value: value.as_str().map(str::to_string),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn cloudquery_databases_are_inventoried() {
+        let path = std::env::temp_dir().join("cloud-scanner-cloudquery.sqlite");
+        let _ = std::fs::remove_file(&path);
+        let connection = Connection::open(&path).unwrap();
+        connection
+            .execute_batch(
+                r#"CREATE TABLE aws_ec2_instances (instance_id TEXT, region TEXT, account_id TEXT, instance_type TEXT, state TEXT, tags TEXT);
+                CREATE TABLE aws_ec2_ebs_volumes (volume_id TEXT, region TEXT, account_id TEXT, volume_type TEXT, size INTEGER, attachments TEXT, tags TEXT);
+                INSERT INTO aws_ec2_instances VALUES ('i-0123456789abcdef0', 'eu-west-3', '111111111111', 'm6g.xlarge', '{"Code": 16, "Name": "running"}', '{"team": "web"}');
+                INSERT INTO aws_ec2_instances VALUES ('i-0fedcba9876543210', 'eu-west-1', '111111111111', 't3.micro', '{"Code": 80, "Name": "stopped"}', '{}');
+                INSERT INTO aws_ec2_ebs_volumes VALUES ('vol-0123456789abcdef0', 'eu-west-3', '111111111111', 'gp3', 100, '[{"InstanceId": "i-0123456789abcdef0"}]', '{"team": "web"}');"#,
+            )
+            .unwrap();
+        drop(connection);
+        let database = format!("sqlite://{}", path.to_string_lossy());
+
+        let all = ResourceSelection::default();
+        let inventory = read_database_inventory(&database, "cloudquery", &[], false, &all)
+            .await
+            .unwrap();
+        let ids: Vec<&str> = inventory.resources.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(vec!["i-0123456789abcdef0", "i-0fedcba9876543210"], ids);
+        assert_eq!("eu-west-1", inventory.resources[1].location.aws_region);
+
+        let (running, _) = ResourceSelection::of_kinds(&[], &["running".to_string()]);
+        let tags = vec!["team=web".to_string()];
+        let inventory = read_database_inventory(&database, "cloudquery", &tags, true, &running)
+            .await
+            .unwrap();
+        let ids: Vec<&str> = inventory.resources.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(vec!["i-0123456789abcdef0", "vol-0123456789abcdef0"], ids);
+        assert_eq!(
+            Some("111111111111".to_string()),
+            inventory.resources[1].account_id
+        );
+        let ResourceDetails::BlockStorage {
+            attached_instances, ..
+        } = &inventory.resources[1].resource_details
+        else {
+            panic!("Expected a volume");
+        };
+        assert_eq!(
+            "i-0123456789abcdef0",
+            attached_instances.as_ref().unwrap()[0].instance_id
+        );
+
+        let custom = "SELECT instance_id AS id, instance_type FROM aws_ec2_instances";
+        assert!(read_database_inventory(&database, custom, &[], false, &all)
+            .await
+            .is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,352 @@
+//! A module to submit the impacts to the metrics API of Datadog (v2 series), for the users without Prometheus nor Datadog agent.
+//!
+//! Series are gauges tagged with the region, country and account of the impacts. When resource tags are mapped to Datadog tags, the series hold the impacts of the resources of each region and value of the mapped tags, instead of the summary of each region.
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+
+use crate::impact_provider::{CloudResourceWithImpacts, ImpactsSummary};
+
+/// Prefix of the names of the metrics
+pub const DEFAULT_PREFIX: &str = "boavizta";
+
+/// Site of the Datadog account, unless set by `DD_SITE`
+pub const DEFAULT_SITE: &str = "datadoghq.com";
+
+/// Type of the gauge series of the metrics API
+const GAUGE_TYPE: u8 = 3;
+
+///  Settings of the Datadog export
+#[derive(Clone, Debug)]
+pub struct DatadogConfig {
+    /// API key of the Datadog account, sent as `DD-API-KEY`
+    pub api_key: String,
+    /// Site of the Datadog account (like `datadoghq.com` or `datadoghq.eu`)
+    pub site: String,
+    /// Prefix of the names of the metrics (like `boavizta`, for `boavizta.gwp_use_kgco2eq`)
+    pub prefix: String,
+    /// Tags added to every series (like `env:prod`)
+    pub tags: Vec<String>,
+    /// Keys of the resource tags mapped to Datadog tags (like `team`)
+    pub tag_keys: Vec<String>,
+}
+
+/// Returns a tag in the format of Datadog: lower case, the characters that Datadog does not accept being replaced by `_`, at most 200 characters
+fn sanitize_tag(tag: &str) -> String {
+    tag.to_lowercase()
+        .chars()
+        .map(
+            |c| match c.is_alphanumeric() || matches!(c, '_' | '-' | ':' | '.' | '/') {
+                true => c,
+                false => '_',
+            },
+        )
+        .take(200)
+        .collect()
+}
+
+/// Number of resources and impacts of a series of tags
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Totals {
+    number_of_resources: [usize; 3],
+    impacts: [f64; 6],
+}
+
+impl Totals {
+    fn add(&mut self, resource: &CloudResourceWithImpacts) {
+        self.number_of_resources[0] += 1;
+        match &resource.impacts_values {
+            Some(impacts) => {
+                self.number_of_resources[1] += 1;
+                for (total, value) in self.impacts.iter_mut().zip([
+                    impacts.adp_manufacture_kgsbeq,
+                    impacts.adp_use_kgsbeq,
+                    impacts.pe_manufacture_megajoules,
+                    impacts.pe_use_megajoules,
+                    impacts.gwp_manufacture_kgco2eq,
+                    impacts.gwp_use_kgco2eq,
+                ]) {
+                    *total += value;
+                }
+            }
+            None => self.number_of_resources[2] += 1,
+        }
+    }
+
+    /// Returns the values of the totals, by name
+    fn values(&self) -> Vec<(&'static str, f64)> {
+        let [total, assessed, not_assessed] = self.number_of_resources;
+        let [adp_manufacture, adp_use, pe_manufacture, pe_use, gwp_manufacture, gwp_use] =
+            self.impacts;
+        vec![
+            ("number_of_resources_total", total as f64),
+            ("number_of_resources_assessed", assessed as f64),
+            ("number_of_resources_not_assessed", not_assessed as f64),
+            ("adp_manufacture_kgsbeq", adp_manufacture),
+            ("adp_use_kgsbeq", adp_use),
+            ("pe_manufacture_megajoules", pe_manufacture),
+            ("pe_use_megajoules", pe_use),
+            ("gwp_manufacture_kgco2eq", gwp_manufacture),
+            ("gwp_use_kgco2eq", gwp_use),
+        ]
+    }
+}
+
+/// Returns the tags of the location of the impacts
+fn location_tags(aws_region: &str, country: &str, account_id: Option<&str>) -> Vec<String> {
+    let mut tags = vec![
+        format!("awsregion:{}", aws_region),
+        format!("country:{}", country),
+    ];
+    if let Some(account_id) = account_id {
+        tags.push(format!("account_id:{}", account_id));
+    }
+    tags
+}
+
+/// Returns the totals of the summary of each region, or of the resources of each region and value of the mapped tags, by tags
+fn totals_by_tags(
+    summary: &ImpactsSummary,
+    resources_with_impacts: &[CloudResourceWithImpacts],
+    tag_keys: &[String],
+) -> BTreeMap<Vec<String>, Totals> {
+    let mut totals: BTreeMap<Vec<String>, Totals> = BTreeMap::new();
+    if tag_keys.is_empty() {
+        for region in summary.regions.iter() {
+            let tags = location_tags(
+                &region.aws_region,
+                &region.country,
+                region.account_id.as_deref(),
+            );
+            totals.insert(
+                tags,
+                Totals {
+                    number_of_resources: [
+                        region.number_of_resources_total,
+                        region.number_of_resources_assessed,
+                        region.number_of_resources_not_assessed,
+                    ],
+                    impacts: [
+                        region.adp_manufacture_kgsbeq,
+                        region.adp_use_kgsbeq,
+                        region.pe_manufacture_megajoules,
+                        region.pe_use_megajoules,
+                        region.gwp_manufacture_kgco2eq,
+                        region.gwp_use_kgco2eq,
+                    ],
+                },
+            );
+        }
+        return totals;
+    }
+    for resource in resources_with_impacts.iter() {
+        let cloud_resource = &resource.cloud_resource;
+        let mut tags = location_tags(
+            &cloud_resource.location.aws_region,
+            &cloud_resource.location.iso_country_code,
+            cloud_resource.account_id.as_deref(),
+        );
+        // Resources without a mapped tag have no Datadog tag for its key
+        for key in tag_keys.iter() {
+            if let Some(value) = cloud_resource
+                .tags
+                .iter()
+                .find(|tag| &tag.key == key)
+                .and_then(|tag| tag.value.as_ref())
+            {
+                tags.push(format!("{}:{}", key, value));
+            }
+        }
+        totals.entry(tags).or_default().add(resource);
+    }
+    totals
+}
+
+/// Returns the impacts as a submission of series to the metrics API of Datadog (json), at a timestamp in seconds
+pub fn get_series(
+    summary: &ImpactsSummary,
+    resources_with_impacts: &[CloudResourceWithImpacts],
+    config: &DatadogConfig,
+    timestamp: i64,
+) -> serde_json::Value {
+    let gauge = |name: &str, value: f64, tags: &[String]| {
+        let tags: Vec<String> = config
+            .tags
+            .iter()
+            .chain(tags.iter())
+            .map(|tag| sanitize_tag(tag))
+            .collect();
+        serde_json::json!({
+            "metric": format!("{}.{}", config.prefix, name),
+            "type": GAUGE_TYPE,
+            "points": [{ "timestamp": timestamp, "value": value }],
+            "tags": tags,
+        })
+    };
+    let mut series = Vec::new();
+    for (tags, totals) in totals_by_tags(summary, resources_with_impacts, &config.tag_keys) {
+        for (name, value) in totals.values() {
+            series.push(gauge(name, value, &tags));
+        }
+    }
+    series.push(gauge(
+        "duration_of_use_hours",
+        summary.duration_of_use_hours,
+        &[],
+    ));
+    // The SCI score is computed for the whole scan
+    if let Some(sci) = &summary.sci {
+        series.push(gauge(
+            "sci_kgco2eq_per_unit",
+            sci.sci_kgco2eq_per_unit,
+            &[format!("functional_unit:{}", sci.functional_unit)],
+        ));
+    }
+    serde_json::json!({ "series": series })
+}
+
+/// Submits series to the metrics API of Datadog
+pub async fn submit_to_datadog(config: &DatadogConfig, series: serde_json::Value) -> Result<()> {
+    let series_url = format!("https://api.{}/api/v2/series", config.site);
+    let count = series["series"].as_array().map_or(0, |series| series.len());
+    let response = reqwest::Client::new()
+        .post(&series_url)
+        .header("DD-API-KEY", &config.api_key)
+        .json(&series)
+        .send()
+        .await
+        .with_context(|| format!("Cannot reach Datadog at {}", series_url))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let message = response.text().await.unwrap_or_default();
+        anyhow::bail!(
+            "Submission of series to Datadog failed with status {}: {}",
+            status,
+            message
+        );
+    }
+    info!("Submitted {} series to Datadog {}", count, config.site);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{ImpactsValues, RegionSummary};
+    use crate::model::{
+        CloudProvider, CloudResource, CloudResourceTag, InstanceState, InstanceUsage,
+        ResourceDetails,
+    };
+    use crate::usage_location::UsageLocation;
+
+    fn config(tag_keys: &[&str]) -> DatadogConfig {
+        DatadogConfig {
+            api_key: "key".to_string(),
+            site: DEFAULT_SITE.to_string(),
+            prefix: DEFAULT_PREFIX.to_string(),
+            tags: vec!["env:Prod".to_string()],
+            tag_keys: tag_keys.iter().map(|key| key.to_string()).collect(),
+        }
+    }
+
+    fn instance(id: &str, team: Option<&str>, gwp_use: f64) -> CloudResourceWithImpacts {
+        CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: id.to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details: ResourceDetails::Instance {
+                    instance_type: "m6g.xlarge".to_string(),
+                    usage: Some(InstanceUsage {
+                        average_cpu_load: 50.0,
+                        usage_duration_seconds: 300,
+                        state: InstanceState::Running,
+                    }),
+                },
+                tags: team
+                    .map(|team| CloudResourceTag {
+                        key: "team".to_string(),
+                        value: Some(team.to_string()),
+                    })
+                    .into_iter()
+                    .collect(),
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues {
+                gwp_use_kgco2eq: gwp_use,
+                ..ImpactsValues::default()
+            }),
+            impacts_duration_hours: 1.0,
+            cost: None,
+        }
+    }
+
+    #[test]
+    fn impacts_are_submitted_as_series_tagged_with_the_mapped_tags() {
+        let resources = vec![
+            instance("i-1", Some("Web"), 1.0),
+            instance("i-2", Some("Web"), 2.0),
+            instance("i-3", None, 4.0),
+        ];
+        let summary = ImpactsSummary {
+            regions: vec![RegionSummary {
+                gwp_use_kgco2eq: 7.0,
+                number_of_resources_total: 3,
+                number_of_resources_assessed: 3,
+                ..RegionSummary::new(None, "eu-west-3".to_string(), "FRA".to_string())
+            }],
+            ..ImpactsSummary::new(
+                "eu-west-3".to_string(),
+                "FRA".to_string(),
+                &crate::model::EstimatedInventory {
+                    impacting_resources: resources.clone(),
+                    execution_statistics: None,
+                    errors: Vec::new(),
+                },
+                1.0,
+            )
+        };
+
+        let series = get_series(&summary, &resources, &config(&[]), 1700000000);
+        let series = series["series"].as_array().unwrap();
+        assert_eq!(10, series.len());
+        assert_eq!("boavizta.gwp_use_kgco2eq", series[8]["metric"]);
+        assert_eq!(7.0, series[8]["points"][0]["value"]);
+        assert_eq!(1700000000, series[8]["points"][0]["timestamp"]);
+        assert_eq!(
+            serde_json::json!(["env:prod", "awsregion:eu-west-3", "country:fra"]),
+            series[8]["tags"]
+        );
+
+        let series = get_series(&summary, &resources, &config(&["team"]), 1700000000);
+        let series = series["series"].as_array().unwrap();
+        assert_eq!(19, series.len());
+        let gwp_of = |tags: serde_json::Value| {
+            series
+                .iter()
+                .find(|series| {
+                    series["metric"] == "boavizta.gwp_use_kgco2eq" && series["tags"] == tags
+                })
+                .map(|series| series["points"][0]["value"].clone())
+        };
+        assert_eq!(
+            Some(serde_json::json!(3.0)),
+            gwp_of(serde_json::json!([
+                "env:prod",
+                "awsregion:eu-west-3",
+                "country:fra",
+                "team:web"
+            ]))
+        );
+        assert_eq!(
+            Some(serde_json::json!(4.0)),
+            gwp_of(serde_json::json!([
+                "env:prod",
+                "awsregion:eu-west-3",
+                "country:fra"
+            ]))
+        );
+        assert_eq!("team_a:x", sanitize_tag("Team A:x"));
+    }
+}
@@ -0,0 +1,275 @@
+//! Plan of the calls of a scan, displayed by `--dry-run` without performing them: to validate filters and the IAM permissions needed before running a scan.
+use anyhow::Result;
+
+use crate::aws_cloud_provider::{AwsAccount, ResourceSelection, MAX_METRIC_QUERIES};
+use crate::tag_filter::TagFilter;
+
+/// What a scan would scan
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DryRun {
+    /// Accounts scanned by assuming their roles (none for the account of the environment)
+    pub accounts: Vec<AwsAccount>,
+    /// Role assumed in the accounts of the AWS Organization (instead of `accounts`)
+    pub organization_role: Option<String>,
+    /// Scanned regions (none for all the regions enabled in the account, an empty region for the default region of the environment)
+    pub regions: Vec<String>,
+    pub filter_tags: Vec<String>,
+    pub include_block_storage: bool,
+    /// Kinds of resources (instances unless skipped) and states of instances
+    pub selection: ResourceSelection,
+    /// Boavizta API queried to estimate impacts (none for an inventory)
+    pub boavizta_api_url: Option<String>,
+}
+
+/// A call the scan would perform
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlannedCall {
+    /// IAM action of a cloud API call, or endpoint of Boavizta API
+    pub operation: String,
+    /// Number of calls, like `1 per instance`
+    pub count: String,
+}
+
+impl PlannedCall {
+    fn new(operation: &str, count: impl Into<String>) -> Self {
+        PlannedCall {
+            operation: operation.to_string(),
+            count: count.into(),
+        }
+    }
+}
+
+impl DryRun {
+    /// Returns the calls to cloud APIs the scan would perform (their IAM actions being the permissions it needs)
+    pub fn cloud_api_calls(&self) -> Vec<PlannedCall> {
+        let mut calls = Vec::new();
+        let accounts = if let Some(role_name) = &self.organization_role {
+            calls.push(PlannedCall::new(
+                "organizations:ListAccounts",
+                "1 per page of accounts of the organization",
+            ));
+            calls.push(PlannedCall::new(
+                "sts:AssumeRole",
+                format!("1 per active account (role {})", role_name),
+            ));
+            None
+        } else if self.accounts.is_empty() {
+            Some(1)
+        } else {
+            calls.push(PlannedCall::new(
+                "sts:AssumeRole",
+                format!("{} (1 per account)", self.accounts.len()),
+            ));
+            Some(self.accounts.len())
+        };
+        if self.regions.is_empty() {
+            calls.push(PlannedCall::new("ec2:DescribeRegions", "1"));
+        }
+        let scans = match (accounts, self.regions.len()) {
+            (Some(accounts), regions) if regions > 0 => {
+                format!("{} (1 per account and region)", accounts * regions)
+            }
+            _ => "1 per account and region".to_string(),
+        };
+        if !self.selection.skip_instances {
+            calls.push(PlannedCall::new("ec2:DescribeInstances", scans.clone()));
+            calls.push(PlannedCall::new(
+                "cloudwatch:GetMetricData",
+                format!("1 per {} instances", MAX_METRIC_QUERIES),
+            ));
+        }
+        if self.include_block_storage {
+            calls.push(PlannedCall::new("ec2:DescribeVolumes", scans));
+        }
+        calls
+    }
+
+    /// Returns the queries to Boavizta API the scan would perform
+    pub fn boavizta_queries(&self) -> Vec<PlannedCall> {
+        let mut queries = Vec::new();
+        if self.boavizta_api_url.is_some() {
+            queries.push(PlannedCall::new("GET /v1/utils/version", "1"));
+            if !self.selection.skip_instances {
+                queries.push(PlannedCall::new(
+                    "POST /v1/cloud/instance",
+                    "1 per instance (matching the filters)",
+                ));
+            }
+            if self.include_block_storage {
+                queries.push(PlannedCall::new(
+                    "POST /v1/component/ssd or /v1/component/hdd",
+                    "1 per volume (matching the filters)",
+                ));
+            }
+        }
+        queries
+    }
+
+    /// Returns the plan of the scan as text, failing when a tag filter is invalid
+    pub fn to_text(&self) -> Result<String> {
+        TagFilter::parse_all(&self.filter_tags)?;
+        let accounts = match &self.organization_role {
+            Some(role_name) => format!(
+                "active accounts of the AWS Organization (assuming role {})",
+                role_name
+            ),
+            None if self.accounts.is_empty() => "account of the environment".to_string(),
+            None => self
+                .accounts
+                .iter()
+                .map(|account| account.account_id.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        let regions = if self.regions.is_empty() {
+            "all the regions enabled in the account".to_string()
+        } else {
+            self.regions
+                .iter()
+                .map(|region| match region.as_str() {
+                    "" => "default region of the environment",
+                    region => region,
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let filters = if self.filter_tags.is_empty() {
+            "none (all resources)".to_string()
+        } else {
+            format!("{} (valid)", self.filter_tags.join(" AND "))
+        };
+        let kinds = match (self.selection.skip_instances, self.include_block_storage) {
+            (false, true) => "instances, block storage volumes",
+            (false, false) => "instances",
+            (true, true) => "block storage volumes",
+            (true, false) => "none",
+        };
+        let states = if self.selection.instance_states.is_empty() {
+            "all".to_string()
+        } else {
+            self.selection.instance_states.join(", ")
+        };
+
+        let mut text = String::from("Dry run: no call is performed.\n\n");
+        text.push_str(&format!("Accounts: {}\n", accounts));
+        text.push_str(&format!("Regions: {}\n", regions));
+        text.push_str(&format!("Tag filters: {}\n", filters));
+        text.push_str(&format!("Resource kinds: {}\n", kinds));
+        if !self.selection.skip_instances {
+            text.push_str(&format!("Instance states: {}\n", states));
+        }
+        text.push_str("\nCloud API calls (IAM permissions needed):\n");
+        text.push_str(&calls_as_text(&self.cloud_api_calls()));
+        if let Some(api_url) = &self.boavizta_api_url {
+            text.push_str(&format!("\nBoavizta API queries ({}):\n", api_url));
+            text.push_str(&calls_as_text(&self.boavizta_queries()));
+        }
+        Ok(text)
+    }
+}
+
+fn calls_as_text(calls: &[PlannedCall]) -> String {
+    let width = calls
+        .iter()
+        .map(|call| call.operation.len())
+        .max()
+        .unwrap_or(0);
+    calls
+        .iter()
+        .map(|call| format!("  {:width$}  {}\n", call.operation, call.count))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plan_counts_the_calls_of_each_account_and_region() {
+        let dry_run = DryRun {
+            accounts: vec![
+                AwsAccount::from_role_arn("arn:aws:iam::111111111111:role/cloud-scanner").unwrap(),
+                AwsAccount::from_role_arn("arn:aws:iam::222222222222:role/cloud-scanner").unwrap(),
+            ],
+            regions: vec!["eu-west-1".to_string(), "eu-west-3".to_string()],
+            filter_tags: vec!["env=prod".to_string()],
+            include_block_storage: true,
+            selection: ResourceSelection {
+                instance_states: vec!["running".to_string(), "stopped".to_string()],
+                ..Default::default()
+            },
+            boavizta_api_url: Some("https://api.boavizta.org".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![
+                PlannedCall::new("sts:AssumeRole", "2 (1 per account)"),
+                PlannedCall::new("ec2:DescribeInstances", "4 (1 per account and region)"),
+                PlannedCall::new("cloudwatch:GetMetricData", "1 per 500 instances"),
+                PlannedCall::new("ec2:DescribeVolumes", "4 (1 per account and region)"),
+            ],
+            dry_run.cloud_api_calls()
+        );
+        assert_eq!(3, dry_run.boavizta_queries().len());
+        let text = dry_run.to_text().unwrap();
+        assert!(
+            text.contains("Accounts: 111111111111, 222222222222"),
+            "{}",
+            text
+        );
+        assert!(text.contains("Tag filters: env=prod (valid)"), "{}", text);
+        assert!(
+            text.contains("Instance states: running, stopped"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn plan_of_an_inventory_of_all_regions() {
+        let dry_run = DryRun {
+            organization_role: Some("cloud-scanner".to_string()),
+            ..Default::default()
+        };
+        let operations: Vec<String> = dry_run
+            .cloud_api_calls()
+            .into_iter()
+            .map(|call| call.operation)
+            .collect();
+        assert_eq!(
+            vec![
+                "organizations:ListAccounts",
+                "sts:AssumeRole",
+                "ec2:DescribeRegions",
+                "ec2:DescribeInstances",
+                "cloudwatch:GetMetricData"
+            ],
+            operations
+        );
+        assert!(dry_run.boavizta_queries().is_empty());
+        assert!(!dry_run.to_text().unwrap().contains("Boavizta"));
+
+        let volumes_only = DryRun {
+            regions: vec!["eu-west-3".to_string()],
+            include_block_storage: true,
+            selection: ResourceSelection {
+                skip_instances: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![PlannedCall::new(
+                "ec2:DescribeVolumes",
+                "1 (1 per account and region)"
+            )],
+            volumes_only.cloud_api_calls()
+        );
+
+        let invalid = DryRun {
+            filter_tags: vec!["env=prod AND".to_string()],
+            ..Default::default()
+        };
+        assert!(invalid.to_text().is_err());
+    }
+}
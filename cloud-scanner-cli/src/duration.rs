@@ -0,0 +1,122 @@
+//! Durations of use given like `30d`, `720h` or `1y` (on the command line and in the configuration file), converted to hours.
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer};
+
+/// Hours of each unit of duration (a month being 730 hours, a year 365 days)
+const UNITS: [(&str, f32); 6] = [
+    ("m", 1.0 / 60.0),
+    ("h", 1.0),
+    ("d", 24.0),
+    ("w", 168.0),
+    ("mo", 730.0),
+    ("y", 8760.0),
+];
+
+/// Parses a duration like `30d`, `720h`, `1.5h` or `1y` into hours, a number without unit being hours
+pub fn parse_hours(duration: &str) -> Result<f32> {
+    let duration = duration.trim();
+    let split = duration
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(duration.len());
+    let (value, unit) = duration.split_at(split);
+    let value: f32 = value.parse().with_context(|| {
+        format!(
+            "Invalid duration '{}', expecting hours or like 30d or 1y",
+            duration
+        )
+    })?;
+    let hours_per_unit = if unit.is_empty() {
+        1.0
+    } else {
+        UNITS
+            .iter()
+            .find(|(symbol, _)| *symbol == unit)
+            .map(|(_, hours)| *hours)
+            .with_context(|| {
+                format!(
+                    "Invalid unit of duration '{}', expecting m, h, d, w, mo or y",
+                    duration
+                )
+            })?
+    };
+    check_hours(value * hours_per_unit)
+}
+
+/// Rejects durations that are not positive, whether given as text or as a number
+fn check_hours(hours: f32) -> Result<f32> {
+    if !hours.is_finite() || hours <= 0.0 {
+        anyhow::bail!("The duration must be longer than 0");
+    }
+    Ok(hours)
+}
+
+/// Duration of the configuration file: hours or text like `30d`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Hours {
+    Number(f32),
+    Text(String),
+}
+
+impl Hours {
+    fn into_hours(self) -> Result<f32> {
+        match self {
+            Hours::Number(hours) => check_hours(hours),
+            Hours::Text(duration) => parse_hours(&duration),
+        }
+    }
+}
+
+/// Deserializes a duration of the configuration file (hours, or text like `30d`) into hours
+pub fn deserialize_hours<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    Hours::deserialize(deserializer)?
+        .into_hours()
+        .map_err(serde::de::Error::custom)
+}
+
+/// Deserializes an optional duration of the configuration file into hours
+pub fn deserialize_optional_hours<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<f32>, D::Error> {
+    Option::<Hours>::deserialize(deserializer)?
+        .map(Hours::into_hours)
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_are_parsed_into_hours() {
+        assert_eq!(730.0, parse_hours("730").unwrap());
+        assert_eq!(1.5, parse_hours("1.5").unwrap());
+        assert_eq!(720.0, parse_hours("720h").unwrap());
+        assert_eq!(720.0, parse_hours("30d").unwrap());
+        assert_eq!(336.0, parse_hours("2w").unwrap());
+        assert_eq!(730.0, parse_hours("1mo").unwrap());
+        assert_eq!(8760.0, parse_hours("1y").unwrap());
+        assert_eq!(0.5, parse_hours("30m").unwrap());
+        for invalid in ["", "d", "1x", "0d", "-1h", "1..5h", "1 d"] {
+            assert!(parse_hours(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct Retention {
+        #[serde(deserialize_with = "deserialize_hours")]
+        keep_scans: f32,
+    }
+
+    #[test]
+    fn numbers_of_hours_must_be_positive() {
+        let retention: Retention = toml::from_str("keep_scans = 48").unwrap();
+        assert_eq!(48.0, retention.keep_scans);
+        let retention: Retention = toml::from_str("keep_scans = \"2d\"").unwrap();
+        assert_eq!(48.0, retention.keep_scans);
+        for invalid in ["keep_scans = 0", "keep_scans = -1", "keep_scans = -1.5"] {
+            assert!(toml::from_str::<Retention>(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! A module to send reports of scans by email (SMTP), for stakeholders who do not open dashboards.
+//!
+//! The email contains the report as Markdown (text part) and as HTML. SMTP settings are read from the [configuration file](crate::config_file).
+use anyhow::{Context, Result};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Deserialize;
+
+use crate::report::{self, Report};
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_starttls() -> bool {
+    true
+}
+
+fn default_use_duration_hours() -> f32 {
+    24.0
+}
+
+/// Settings to send reports by email
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct EmailConfig {
+    pub smtp_host: String,
+    #[serde(default = "default_smtp_port")]
+    pub smtp_port: u16,
+    pub smtp_username: Option<String>,
+    /// Prefer the `SMTP_PASSWORD` environment variable to keep the password out of the configuration file
+    pub smtp_password: Option<String>,
+    /// Upgrades the connection to TLS (STARTTLS), disable only for local relays
+    #[serde(default = "default_starttls")]
+    pub starttls: bool,
+    pub from: String,
+    pub to: Vec<String>,
+    /// Defaults to the title of the report
+    pub subject: Option<String>,
+    /// Sends reports periodically when running in server mode
+    pub schedule: Option<EmailSchedule>,
+}
+
+/// Periodic sending of reports in server mode
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct EmailSchedule {
+    pub interval_hours: u64,
+    pub aws_region: String,
+    #[serde(
+        default = "default_use_duration_hours",
+        deserialize_with = "crate::duration::deserialize_hours"
+    )]
+    pub use_duration_hours: f32,
+    #[serde(default)]
+    pub filter_tags: Vec<String>,
+    #[serde(default)]
+    pub include_block_storage: bool,
+}
+
+impl EmailConfig {
+    /// Returns the SMTP password from the `SMTP_PASSWORD` environment variable or from the configuration
+    fn password(&self) -> Option<String> {
+        std::env::var("SMTP_PASSWORD")
+            .ok()
+            .or_else(|| self.smtp_password.clone())
+    }
+}
+
+fn parse_mailbox(address: &str) -> Result<Mailbox> {
+    address
+        .parse()
+        .with_context(|| format!("Invalid email address {}", address))
+}
+
+/// Builds an email containing the report (as Markdown and HTML alternatives)
+pub fn build_message(config: &EmailConfig, report: &Report) -> Result<Message> {
+    if config.to.is_empty() {
+        anyhow::bail!("No recipient for the email report");
+    }
+    let mut builder = Message::builder()
+        .from(parse_mailbox(&config.from)?)
+        .subject(config.subject.clone().unwrap_or_else(|| report.title()));
+    for to in config.to.iter() {
+        builder = builder.to(parse_mailbox(to)?);
+    }
+    builder
+        .multipart(MultiPart::alternative_plain_html(
+            report::to_markdown(report),
+            report::to_html(report),
+        ))
+        .context("Cannot build email")
+}
+
+/// Builds an email with a plain text body (like alerts) to the recipients of the configuration
+pub fn build_text_message(config: &EmailConfig, subject: &str, body: &str) -> Result<Message> {
+    if config.to.is_empty() {
+        anyhow::bail!("No recipient for the email");
+    }
+    let mut builder = Message::builder()
+        .from(parse_mailbox(&config.from)?)
+        .subject(subject);
+    for to in config.to.iter() {
+        builder = builder.to(parse_mailbox(to)?);
+    }
+    builder.body(body.to_string()).context("Cannot build email")
+}
+
+/// Sends an email with the SMTP server of the configuration
+async fn send(config: &EmailConfig, message: Message) -> Result<()> {
+    let mut transport = if config.starttls {
+        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_host)
+            .with_context(|| format!("Cannot connect to SMTP server {}", config.smtp_host))?
+    } else {
+        AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.smtp_host)
+    }
+    .port(config.smtp_port);
+    if let Some(username) = &config.smtp_username {
+        transport = transport.credentials(Credentials::new(
+            username.to_string(),
+            config.password().unwrap_or_default(),
+        ));
+    }
+    transport
+        .build()
+        .send(message)
+        .await
+        .with_context(|| format!("Cannot send email with {}", config.smtp_host))?;
+    Ok(())
+}
+
+/// Sends the report by email
+pub async fn send_report(config: &EmailConfig, report: &Report) -> Result<()> {
+    send(config, build_message(config, report)?).await?;
+    info!("Report sent by email to {}", config.to.join(", "));
+    Ok(())
+}
+
+/// Sends a plain text email (like alerts)
+pub async fn send_text(config: &EmailConfig, subject: &str, body: &str) -> Result<()> {
+    send(config, build_text_message(config, subject, body)?).await?;
+    info!("Email sent to {}", config.to.join(", "));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::ImpactsSummary;
+    use crate::model::EstimatedInventory;
+    use crate::report::ReportMetadata;
+    use chrono::{TimeZone, Utc};
+
+    fn email_config() -> EmailConfig {
+        EmailConfig {
+            smtp_host: "smtp.example.com".to_string(),
+            smtp_port: 587,
+            smtp_username: None,
+            smtp_password: None,
+            starttls: true,
+            from: "Cloud scanner <cloud-scanner@example.com>".to_string(),
+            to: vec!["green-it@example.com".to_string()],
+            subject: None,
+            schedule: None,
+        }
+    }
+
+    #[test]
+    fn build_message_with_text_and_html() {
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let metadata = ReportMetadata {
+            generated_at: Utc.with_ymd_and_hms(2024, 4, 12, 10, 15, 0).unwrap(),
+            cloud_scanner_version: "2.0.5".to_string(),
+            aws_region: "eu-west-3".to_string(),
+            boavizta_api_url: "https://api.boavizta.org".to_string(),
+            filter_tags: Vec::new(),
+        };
+        let report = Report::new(metadata, &summary, &estimated_inventory);
+
+        let message = build_message(&email_config(), &report).unwrap();
+        let formatted = String::from_utf8(message.formatted()).unwrap();
+        assert!(formatted.contains("Subject: Cloud impacts report - eu-west-3"));
+        assert!(formatted.contains("To: green-it@example.com"));
+        assert!(formatted.contains("multipart/alternative"));
+        assert!(formatted.contains("Content-Type: text/html"));
+
+        let mut config = email_config();
+        config.to = vec!["not an address".to_string()];
+        assert!(build_message(&config, &report).is_err());
+        config.to = Vec::new();
+        assert!(build_message(&config, &report).is_err());
+    }
+}
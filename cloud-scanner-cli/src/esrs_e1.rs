@@ -0,0 +1,333 @@
+//! Exports of the history of scans aligned with the datapoints of ESRS E1 (climate change) of the CSRD (`report --standard esrs-e1`): gross GHG emissions by scope (E1-6) and energy consumption in MWh (E1-5), over a reporting period.
+//!
+//! The scans of a result store are aggregated over the period as in [crate::scan_aggregation] (the gaps of the history are not extrapolated). The emissions of each scan are mapped to scopes as in the GHG Protocol reports (see [crate::ghg_protocol]).
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::ghg_protocol::{build_ghg_protocol_report, location_factor, GhgScope};
+use crate::model::EstimatedInventory;
+use crate::scan_aggregation::{hours_between, CoveredScan};
+
+/// Gross Scope 3 emissions of a category
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EsrsScope3Category {
+    /// Category of the Scope 3 Standard, like `Category 1: Purchased goods and services`
+    pub category: String,
+    pub gross_emissions_tco2eq: f64,
+}
+
+/// Export of the history of scans aligned with the datapoints of ESRS E1
+#[derive(Clone, Debug, Serialize)]
+pub struct EsrsE1Export {
+    pub standard: String,
+    pub organization: Option<String>,
+    pub generated_at: DateTime<Utc>,
+    pub cloud_scanner_version: String,
+    pub reporting_period_start: DateTime<Utc>,
+    pub reporting_period_end: DateTime<Utc>,
+    /// Hours of the reporting period covered by scans (the others are gaps of the history)
+    pub covered_hours: f64,
+    pub reporting_period_hours: f64,
+    /// E1-5: total energy consumption related to own operations (electricity of the on-premises servers)
+    pub energy_consumption_own_operations_mwh: f64,
+    /// Electricity of the cloud services used (upstream, not part of own operations), disclosed for information
+    pub energy_consumption_cloud_services_mwh: f64,
+    /// E1-6: gross Scope 1 emissions (none for cloud and IT resources)
+    pub gross_scope1_tco2eq: f64,
+    /// E1-6: gross location-based Scope 2 emissions
+    pub gross_location_based_scope2_tco2eq: f64,
+    /// E1-6: gross market-based Scope 2 emissions
+    pub gross_market_based_scope2_tco2eq: f64,
+    /// E1-6: gross Scope 3 emissions
+    pub gross_scope3_tco2eq: f64,
+    pub scope3_categories: Vec<EsrsScope3Category>,
+    /// E1-6: total GHG emissions (location-based)
+    pub total_location_based_tco2eq: f64,
+    /// E1-6: total GHG emissions (market-based)
+    pub total_market_based_tco2eq: f64,
+    /// Market-based factors used (kgCO2eq/kWh), by region, country or `*`
+    pub market_factors: BTreeMap<String, f64>,
+    pub scans: Vec<CoveredScan>,
+    pub disclosures: Vec<String>,
+}
+
+/// Methodology disclosures of every export
+const DISCLOSURES: [&str; 4] = [
+    "Boundary: the resources of the scanned accounts, regions and filters (operational control approach); cloud services are reported in Scope 3, category 1, on-premises servers in Scope 2 (use) and Scope 3, category 2 (manufacture).",
+    "Emissions are estimated by Boavizta API from the type, configuration and average CPU load of the resources, and aggregated over the reporting period: each scan covers the time until the next scan, at most its duration of use, its emissions being prorated to the hours it covers.",
+    "Scope 3 emissions are location-based; the market-based factors only apply to Scope 2 emissions.",
+    "Energy consumption is the electricity of the resources (including the power usage effectiveness of the data centers), derived from their emissions of use and the carbon intensity of their grid given by Boavizta API.",
+];
+
+/// Returns the ESRS E1 export of the scans (with their estimated inventory) covering a reporting period, with the market-based factors (kgCO2eq/kWh) by region, country or `*`
+pub fn build_esrs_e1_export(
+    period_start: DateTime<Utc>,
+    period_end: DateTime<Utc>,
+    scans: &[(CoveredScan, EstimatedInventory)],
+    market_factors: &BTreeMap<String, f64>,
+    organization: Option<&str>,
+) -> EsrsE1Export {
+    let mut scope2_location_based_kgco2eq = 0.0;
+    let mut scope2_market_based_kgco2eq = 0.0;
+    let mut scope3_categories: BTreeMap<String, f64> = BTreeMap::new();
+    let mut own_operations_kwh = 0.0;
+    let mut cloud_services_kwh = 0.0;
+    let mut scans_with_unknown_electricity = 0;
+    let mut scans_with_errors = 0;
+    for (scan, estimated_inventory) in scans.iter() {
+        if scan.duration_of_use_hours <= 0.0 {
+            continue;
+        }
+        let share = scan.covered_hours() / scan.duration_of_use_hours;
+        let report = build_ghg_protocol_report(None, estimated_inventory, market_factors, None);
+        scope2_location_based_kgco2eq += report.scope2_location_based_kgco2eq * share;
+        scope2_market_based_kgco2eq += report.scope2_market_based_kgco2eq * share;
+        for category in report.categories.iter() {
+            let electricity_kwh = category.electricity_kwh.unwrap_or_default() * share;
+            match category.scope {
+                GhgScope::Scope2 => own_operations_kwh += electricity_kwh,
+                GhgScope::Scope3 => {
+                    cloud_services_kwh += electricity_kwh;
+                    *scope3_categories
+                        .entry(category.category.clone())
+                        .or_default() += category.location_based_kgco2eq * share;
+                }
+            }
+        }
+        if estimated_inventory
+            .impacting_resources
+            .iter()
+            .any(|resource| {
+                resource.impacts_values.is_some() && location_factor(resource).is_none()
+            })
+        {
+            scans_with_unknown_electricity += 1;
+        }
+        if !estimated_inventory.errors.is_empty() {
+            scans_with_errors += 1;
+        }
+    }
+
+    let reporting_period_hours = hours_between(period_start, period_end);
+    let covered_hours: f64 = scans.iter().map(|(scan, _)| scan.covered_hours()).sum();
+    let mut disclosures: Vec<String> = DISCLOSURES.iter().map(|d| d.to_string()).collect();
+    if covered_hours < reporting_period_hours {
+        disclosures.push(format!(
+            "{:.1} hours of the reporting period ({:.1} hours) are not covered by scans: their emissions are not included.",
+            reporting_period_hours - covered_hours,
+            reporting_period_hours
+        ));
+    }
+    if scans_with_unknown_electricity > 0 {
+        disclosures.push(format!(
+            "The electricity of some resources of {} scans is unknown (scans without verbose impacts), the energy consumption is underestimated; the market-based Scope 2 emissions of these resources are their location-based emissions.",
+            scans_with_unknown_electricity
+        ));
+    }
+    if scans_with_errors > 0 {
+        disclosures.push(format!(
+            "{} scans continued past errors (regions or resources that could not be listed or estimated), which are excluded from the totals.",
+            scans_with_errors
+        ));
+    }
+
+    let gross_location_based_scope2_tco2eq = scope2_location_based_kgco2eq / 1000.0;
+    let gross_market_based_scope2_tco2eq = scope2_market_based_kgco2eq / 1000.0;
+    let scope3_categories: Vec<EsrsScope3Category> = scope3_categories
+        .into_iter()
+        .map(|(category, kgco2eq)| EsrsScope3Category {
+            category,
+            gross_emissions_tco2eq: kgco2eq / 1000.0,
+        })
+        .collect();
+    let gross_scope3_tco2eq = scope3_categories
+        .iter()
+        .map(|category| category.gross_emissions_tco2eq)
+        .fold(0.0, |total, value| total + value);
+    EsrsE1Export {
+        standard: "ESRS E1".to_string(),
+        organization: organization.map(str::to_string),
+        generated_at: Utc::now(),
+        cloud_scanner_version: crate::get_version(),
+        reporting_period_start: period_start,
+        reporting_period_end: period_end,
+        covered_hours,
+        reporting_period_hours,
+        energy_consumption_own_operations_mwh: own_operations_kwh / 1000.0,
+        energy_consumption_cloud_services_mwh: cloud_services_kwh / 1000.0,
+        gross_scope1_tco2eq: 0.0,
+        gross_location_based_scope2_tco2eq,
+        gross_market_based_scope2_tco2eq,
+        gross_scope3_tco2eq,
+        scope3_categories,
+        total_location_based_tco2eq: gross_location_based_scope2_tco2eq + gross_scope3_tco2eq,
+        total_market_based_tco2eq: gross_market_based_scope2_tco2eq + gross_scope3_tco2eq,
+        market_factors: market_factors.clone(),
+        scans: scans.iter().map(|(scan, _)| scan.clone()).collect(),
+        disclosures,
+    }
+}
+
+impl EsrsE1Export {
+    /// Returns the export as a Markdown document
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        md.push_str("# Climate change datapoints of cloud and IT resources (ESRS E1)\n\n");
+        if let Some(organization) = &self.organization {
+            md.push_str(&format!("- Organization: {}\n", organization));
+        }
+        md.push_str(&format!(
+            "- Reporting period: {} to {} ({:.1} of {:.1} hours covered by {} scans)\n",
+            self.reporting_period_start.to_rfc3339(),
+            self.reporting_period_end.to_rfc3339(),
+            self.covered_hours,
+            self.reporting_period_hours,
+            self.scans.len()
+        ));
+        md.push_str(&format!(
+            "- Generated by cloud-scanner {} on {}\n\n",
+            self.cloud_scanner_version,
+            self.generated_at.to_rfc3339()
+        ));
+
+        md.push_str("## E1-5 Energy consumption\n\n");
+        md.push_str("| Datapoint | MWh |\n|---|---:|\n");
+        md.push_str(&format!(
+            "| Total energy consumption related to own operations | {:.3} |\n",
+            self.energy_consumption_own_operations_mwh
+        ));
+        md.push_str(&format!(
+            "| Electricity of the cloud services used (for information) | {:.3} |\n\n",
+            self.energy_consumption_cloud_services_mwh
+        ));
+
+        md.push_str("## E1-6 Gross Scopes 1, 2, 3 and total GHG emissions\n\n");
+        md.push_str("| Datapoint | tCO2eq |\n|---|---:|\n");
+        md.push_str(&format!(
+            "| Gross Scope 1 GHG emissions | {:.3} |\n",
+            self.gross_scope1_tco2eq
+        ));
+        md.push_str(&format!(
+            "| Gross location-based Scope 2 GHG emissions | {:.3} |\n",
+            self.gross_location_based_scope2_tco2eq
+        ));
+        md.push_str(&format!(
+            "| Gross market-based Scope 2 GHG emissions | {:.3} |\n",
+            self.gross_market_based_scope2_tco2eq
+        ));
+        md.push_str(&format!(
+            "| Gross Scope 3 GHG emissions | {:.3} |\n",
+            self.gross_scope3_tco2eq
+        ));
+        for category in self.scope3_categories.iter() {
+            md.push_str(&format!(
+                "| - {} | {:.3} |\n",
+                category.category, category.gross_emissions_tco2eq
+            ));
+        }
+        md.push_str(&format!(
+            "| **Total GHG emissions (location-based)** | **{:.3}** |\n",
+            self.total_location_based_tco2eq
+        ));
+        md.push_str(&format!(
+            "| **Total GHG emissions (market-based)** | **{:.3}** |\n\n",
+            self.total_market_based_tco2eq
+        ));
+
+        if !self.market_factors.is_empty() {
+            md.push_str("## Market-based emission factors\n\n");
+            for (key, factor) in self.market_factors.iter() {
+                let key = if key == "*" { "Other regions" } else { key };
+                md.push_str(&format!("- {}: {} kgCO2eq/kWh\n", key, factor));
+            }
+            md.push('\n');
+        }
+
+        md.push_str("## Methodology and disclosures\n\n");
+        for disclosure in self.disclosures.iter() {
+            md.push_str(&format!("- {}\n", disclosure));
+        }
+        md
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+    use crate::model::{CloudProvider, CloudResource, ResourceDetails};
+    use crate::scan_aggregation::covered_scans;
+    use crate::usage_location::UsageLocation;
+
+    fn date(day: u32, hour: u32) -> DateTime<Utc> {
+        format!("2024-05-{:02}T{:02}:00:00Z", day, hour)
+            .parse()
+            .unwrap()
+    }
+
+    fn inventory(provider: CloudProvider, gwp_use: f64, gwp_factor: f64) -> EstimatedInventory {
+        EstimatedInventory {
+            impacting_resources: vec![CloudResourceWithImpacts {
+                cloud_resource: CloudResource {
+                    provider,
+                    id: "srv-1".to_string(),
+                    location: UsageLocation::try_from("eu-west-3").unwrap(),
+                    resource_details: ResourceDetails::Instance {
+                        instance_type: "t3.micro".to_string(),
+                        usage: None,
+                    },
+                    tags: Vec::new(),
+                    account_id: None,
+                    relationships: Vec::new(),
+                },
+                impacts_values: Some(ImpactsValues {
+                    gwp_manufacture_kgco2eq: 10.0,
+                    gwp_use_kgco2eq: gwp_use,
+                    raw_data: Some(
+                        serde_json::json!({"verbose": {"gwp_factor": {"value": gwp_factor, "source": "test"}}}),
+                    ),
+                    ..Default::default()
+                }),
+                impacts_duration_hours: 24.0,
+                cost: None,
+            }],
+            execution_statistics: None,
+            errors: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn emissions_and_energy_are_prorated_over_the_period() {
+        let scans = vec![(1, date(1, 0), 24.0), (2, date(2, 0), 24.0)];
+        let covered = covered_scans(&scans, date(1, 0), date(2, 12));
+        let scans: Vec<(CoveredScan, EstimatedInventory)> = covered
+            .into_iter()
+            .zip([
+                // 100 kWh a day at 0.05 kgCO2eq/kWh
+                inventory(CloudProvider::OnPremises, 5.0, 0.05),
+                inventory(CloudProvider::AWS, 2.0, 0.1),
+            ])
+            .collect();
+        let factors = BTreeMap::from([("*".to_string(), 0.01)]);
+        let export = build_esrs_e1_export(date(1, 0), date(3, 0), &scans, &factors, None);
+
+        assert_eq!(36.0, export.covered_hours);
+        assert!((export.energy_consumption_own_operations_mwh - 0.1).abs() < 1e-9);
+        // Half a day of 20 kWh
+        assert!((export.energy_consumption_cloud_services_mwh - 0.01).abs() < 1e-9);
+        assert!((export.gross_location_based_scope2_tco2eq - 0.005).abs() < 1e-9);
+        assert!((export.gross_market_based_scope2_tco2eq - 0.001).abs() < 1e-9);
+        // 10 kgCO2eq of capital goods, 6 kgCO2eq of purchased services
+        assert_eq!(2, export.scope3_categories.len());
+        assert!((export.gross_scope3_tco2eq - 0.016).abs() < 1e-9);
+        assert!((export.total_market_based_tco2eq - 0.017).abs() < 1e-9);
+        assert!(export
+            .disclosures
+            .iter()
+            .any(|disclosure| disclosure.starts_with("12.0 hours of the reporting period")));
+
+        let markdown = export.to_markdown();
+        assert!(markdown.contains("| Gross location-based Scope 2 GHG emissions | 0.005 |"));
+    }
+}
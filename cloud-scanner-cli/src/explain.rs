@@ -0,0 +1,353 @@
+//! Explanation of the impacts of a single resource (`explain` command): the specs detected by the inventory, the utilization used, the query of Boavizta API and its raw response, the allocation of the embedded impacts and the final values.
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt::Write;
+
+use crate::aws_cloud_provider::{AwsAccount, ResourceSelection};
+use crate::boavizta_api_v1::{
+    boa_impacts_to_cloud_resource_with_impacts, impacts_query, BoaviztaApiV1, ImpactsQuery,
+};
+use crate::impact_provider::ImpactsValues;
+use crate::model::{CloudResource, ResourceDetails};
+
+/// Parameters of the verbose response of Boavizta API used to allocate the embedded impacts to the duration of use
+const ALLOCATION_PARAMETERS: [(&str, &str); 5] = [
+    ("duration", "duration of use"),
+    ("hours_life_time", "lifetime of the hardware"),
+    ("use_time_ratio", "ratio of time in use"),
+    ("instance_per_server", "instances per server"),
+    ("units", "units"),
+];
+
+/// How the impacts of a resource were derived
+#[derive(Clone, Debug, Serialize)]
+pub struct Explanation {
+    /// The resource, as detected by the inventory (specs, utilization and tags)
+    pub resource: CloudResource,
+    pub usage_duration_hours: f32,
+    pub api_url: String,
+    /// The query of Boavizta API (verbose, to return the details of the estimation)
+    pub query: ImpactsQuery,
+    /// Raw (verbose) response of Boavizta API, none if the query failed
+    pub raw_response: Option<Value>,
+    /// Impacts of the resource converted from the response
+    pub impacts: Option<ImpactsValues>,
+}
+
+/// Finds a resource in the accounts and regions, and queries Boavizta API for its impacts over the duration of use (hours)
+pub async fn explain_resource(
+    resource_id: &str,
+    accounts: &[AwsAccount],
+    aws_regions: &[String],
+    api_url: &str,
+    usage_duration_hours: &f32,
+) -> Result<Explanation> {
+    // Only the kind of the resource is listed
+    let is_volume = resource_id.starts_with("vol-");
+    let selection = ResourceSelection {
+        skip_instances: is_volume,
+        ..Default::default()
+    };
+    let inventory =
+        crate::get_inventory_in_accounts(accounts, &[], aws_regions, is_volume, &selection).await;
+    crate::progress::finish();
+    let resource = inventory?
+        .resources
+        .into_iter()
+        .find(|resource| resource.id == resource_id)
+        .with_context(|| {
+            format!(
+                "Resource {} not found in the scanned accounts and regions",
+                resource_id
+            )
+        })?;
+    let query = impacts_query(&resource, usage_duration_hours, true)
+        .with_context(|| format!("Impacts of {} cannot be estimated", resource_id))?;
+    let raw_response = BoaviztaApiV1::new(api_url)
+        .get_raws_impacts(&resource, usage_duration_hours, true)
+        .await;
+    let impacts =
+        boa_impacts_to_cloud_resource_with_impacts(&resource, &raw_response, usage_duration_hours)
+            .impacts_values
+            // The raw data is already the raw response
+            .map(|impacts| ImpactsValues {
+                raw_data: None,
+                ..impacts
+            });
+    Ok(Explanation {
+        resource,
+        usage_duration_hours: *usage_duration_hours,
+        api_url: api_url.to_string(),
+        query,
+        raw_response,
+        impacts,
+    })
+}
+
+/// Returns a parameter of the verbose response of Boavizta API as text, like `35040 hours (ARCHETYPE)`, None if it is not a single value
+fn verbose_value(parameter: &Value) -> Option<String> {
+    let value = match &parameter["value"] {
+        Value::Number(number) => number.to_string(),
+        Value::String(text) => text.clone(),
+        Value::Bool(flag) => flag.to_string(),
+        _ => return None,
+    };
+    let mut text = value;
+    if let Some(unit) = parameter["unit"].as_str() {
+        text.push_str(&format!(" {}", unit));
+    }
+    if let Some(status) = parameter["status"].as_str() {
+        text.push_str(&format!(" ({})", status));
+    }
+    Some(text)
+}
+
+/// Returns the single value parameters of a part of the verbose response, like `  tdp: 150 W (COMPLETED)`
+fn parameters_as_text(parameters: &serde_json::Map<String, Value>, indent: &str) -> String {
+    parameters
+        .iter()
+        .filter_map(|(name, parameter)| {
+            verbose_value(parameter).map(|value| format!("{}{}: {}\n", indent, name, value))
+        })
+        .collect()
+}
+
+impl Explanation {
+    /// Returns the explanation as text, section by section
+    pub fn to_text(&self) -> Result<String> {
+        let resource = &self.resource;
+        let mut text = String::new();
+        writeln!(
+            text,
+            "Resource {} ({} {})",
+            resource.id,
+            resource.resource_details.kind(),
+            resource
+                .resource_details
+                .resource_type()
+                .unwrap_or_default()
+        )?;
+        if let Some(account_id) = &resource.account_id {
+            writeln!(text, "  Account: {}", account_id)?;
+        }
+        writeln!(
+            text,
+            "  Region: {} (usage location {})",
+            resource.location.aws_region, resource.location.iso_country_code
+        )?;
+        let tags: Vec<String> = resource
+            .tags
+            .iter()
+            .map(|tag| format!("{}={}", tag.key, tag.value.as_deref().unwrap_or_default()))
+            .collect();
+        writeln!(text, "  Tags: {}", tags.join(", "))?;
+
+        writeln!(text, "\nUtilization")?;
+        match &resource.resource_details {
+            ResourceDetails::Instance {
+                usage: Some(usage), ..
+            } => writeln!(
+                text,
+                "  Average cpu load: {:.2} % (last 10 minutes of CloudWatch), state {:?}",
+                usage.average_cpu_load, usage.state
+            )?,
+            ResourceDetails::Instance { usage: None, .. } => writeln!(
+                text,
+                "  Unknown cpu load (the default load of Boavizta API is used)"
+            )?,
+            ResourceDetails::BlockStorage {
+                usage,
+                attached_instances,
+                ..
+            } => {
+                if let Some(usage) = usage {
+                    writeln!(text, "  Size: {} GB", usage.size_gb)?;
+                }
+                let instances: Vec<&str> = attached_instances
+                    .iter()
+                    .flatten()
+                    .map(|attachment| attachment.instance_id.as_str())
+                    .collect();
+                writeln!(text, "  Attached to: {}", instances.join(", "))?;
+            }
+            ResourceDetails::ObjectStorage => {}
+            ResourceDetails::Server {
+                hardware, usage, ..
+            } => {
+                if let Some(hardware) = hardware {
+                    writeln!(text, "  Hardware: {:?}", hardware)?;
+                }
+                match usage {
+                    Some(usage) => writeln!(
+                        text,
+                        "  Average cpu load: {:.2} %, state {:?}",
+                        usage.average_cpu_load, usage.state
+                    )?,
+                    None => writeln!(
+                        text,
+                        "  Unknown cpu load (the default load of Boavizta API is used)"
+                    )?,
+                }
+            }
+        }
+        writeln!(
+            text,
+            "  Duration of use: {} hours",
+            self.usage_duration_hours
+        )?;
+
+        let parameters: Vec<String> = self
+            .query
+            .parameters
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        writeln!(
+            text,
+            "\nBoavizta API query\n  POST {}{}?{}\n{}",
+            self.api_url,
+            self.query.path,
+            parameters.join("&"),
+            indented(&serde_json::to_string_pretty(&self.query.body)?)
+        )?;
+
+        let Some(raw_response) = &self.raw_response else {
+            writeln!(text, "\nNo response of Boavizta API (see the logs)")?;
+            return Ok(text);
+        };
+        if let Some(verbose) = raw_response["verbose"].as_object() {
+            writeln!(text, "\nSpecs and parameters completed by Boavizta API")?;
+            text.push_str(&parameters_as_text(verbose, "  "));
+            // Components of an instance are named like CPU-1
+            for (component, details) in verbose.iter() {
+                if let Some(details) = details.as_object().filter(|d| d.contains_key("impacts")) {
+                    writeln!(text, "  {}", component)?;
+                    text.push_str(&parameters_as_text(details, "    "));
+                }
+            }
+
+            writeln!(text, "\nAllocation of the embedded impacts")?;
+            for (name, description) in ALLOCATION_PARAMETERS {
+                if let Some(value) = verbose.get(name).and_then(verbose_value) {
+                    writeln!(text, "  {} ({}): {}", description, name, value)?;
+                }
+            }
+            writeln!(
+                text,
+                "  Embedded impacts are allocated proportionally to the duration of use over the lifetime of the hardware"
+            )?;
+        }
+
+        writeln!(text, "\nImpacts over {} hours", self.usage_duration_hours)?;
+        match &self.impacts {
+            Some(impacts) => {
+                writeln!(
+                    text,
+                    "  GWP: {} kgCO2eq manufacture, {} kgCO2eq use",
+                    impacts.gwp_manufacture_kgco2eq, impacts.gwp_use_kgco2eq
+                )?;
+                writeln!(
+                    text,
+                    "  ADP: {} kgSbeq manufacture, {} kgSbeq use",
+                    impacts.adp_manufacture_kgsbeq, impacts.adp_use_kgsbeq
+                )?;
+                writeln!(
+                    text,
+                    "  PE: {} MJ manufacture, {} MJ use",
+                    impacts.pe_manufacture_megajoules, impacts.pe_use_megajoules
+                )?;
+                if matches!(
+                    resource.resource_details,
+                    ResourceDetails::BlockStorage { .. }
+                ) {
+                    writeln!(
+                        text,
+                        "  Impacts of the use of block storage are not counted (only embedded impacts)"
+                    )?;
+                }
+            }
+            None => writeln!(text, "  None")?,
+        }
+
+        writeln!(
+            text,
+            "\nRaw response of Boavizta API\n{}",
+            indented(&serde_json::to_string_pretty(raw_response)?)
+        )?;
+        Ok(text)
+    }
+}
+
+fn indented(text: &str) -> String {
+    text.lines()
+        .map(|line| format!("  {}", line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{CloudProvider, CloudResourceTag, InstanceState, InstanceUsage};
+    use crate::usage_location::UsageLocation;
+
+    const DEFAULT_RAW_IMPACTS_OF_M6GXLARGE_1HRS_FR_VERBOSE: &str =
+        include_str!("../test-data/DEFAULT_RAW_IMPACTS_OF_M6GXLARGE_1HRS_FR_VERBOSE.json");
+
+    #[test]
+    fn explanation_shows_each_step_of_the_estimation() {
+        let resource = CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: None,
+            id: "i-0abc123".to_string(),
+            location: UsageLocation::try_from("eu-west-3").unwrap(),
+            resource_details: ResourceDetails::Instance {
+                instance_type: "m6g.xlarge".to_string(),
+                usage: Some(InstanceUsage {
+                    average_cpu_load: 100.0,
+                    usage_duration_seconds: 300,
+                    state: InstanceState::Running,
+                }),
+            },
+            tags: vec![CloudResourceTag {
+                key: "env".to_string(),
+                value: Some("prod".to_string()),
+            }],
+            relationships: Vec::new(),
+        };
+        let raw_response: Value =
+            serde_json::from_str(DEFAULT_RAW_IMPACTS_OF_M6GXLARGE_1HRS_FR_VERBOSE).unwrap();
+        let impacts = boa_impacts_to_cloud_resource_with_impacts(
+            &resource,
+            &Some(raw_response.clone()),
+            &1.0,
+        )
+        .impacts_values;
+        let explanation = Explanation {
+            query: impacts_query(&resource, &1.0, true).unwrap(),
+            resource,
+            usage_duration_hours: 1.0,
+            api_url: "https://api.boavizta.org".to_string(),
+            raw_response: Some(raw_response),
+            impacts,
+        };
+        let text = explanation.to_text().unwrap();
+        for expected in [
+            "Resource i-0abc123 (Instance m6g.xlarge)",
+            "Tags: env=prod",
+            "Average cpu load: 100.00 %",
+            "POST https://api.boavizta.org/v1/cloud/instance?verbose=true&duration=1&criteria=gwp&criteria=adp&criteria=pe",
+            "\"instance_type\": \"m6g.xlarge\"",
+            "  CPU-1\n",
+            "    tdp: 150 W (COMPLETED)",
+            "gwp_factor: 0.098 kg CO2eq/kWh (COMPLETED)",
+            "lifetime of the hardware (hours_life_time): 35040 hours (ARCHETYPE)",
+            "instances per server (instance_per_server): 16 (ARCHETYPE)",
+            "GWP: 0.0016 kgCO2eq manufacture, 0.00184 kgCO2eq use",
+            "Raw response of Boavizta API",
+        ] {
+            assert!(text.contains(expected), "{} not in {}", expected, text);
+        }
+    }
+}
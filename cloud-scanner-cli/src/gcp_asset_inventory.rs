@@ -0,0 +1,288 @@
+//! Inventories of GCP Cloud Asset Inventory exports, so that GCP estates are estimated from existing asset dumps.
+//!
+//! Exports are the newline delimited json files of `gcloud asset export --content-type=resource` (one asset per line), or the json output of `gcloud asset list --content-type=resource --format=json` (an array of assets), gzipped or not:
+//!
+//! ```sh
+//! gcloud asset export --project my-project --content-type resource --asset-types compute.googleapis.com/Instance,compute.googleapis.com/Disk --output-path gs://my-bucket/assets.json
+//! ```
+//!
+//! Instances (`compute.googleapis.com/Instance`) are inventoried with their machine type and status, disks (`compute.googleapis.com/Disk`) with their size, type and the instances using them. Resources are identified by their path (like `projects/my-project/zones/europe-west1-b/instances/web-1`), their account is their project, their region is the region of their zone and their labels are their tags. CPU loads are not part of the exports: instances are estimated with the default load of Boavizta API.
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io::Read;
+
+use crate::aws_cloud_provider::ResourceSelection;
+use crate::model::{
+    CloudProvider, CloudResource, CloudResourceTag, Inventory, ResourceDetails,
+    ResourceRelationship, StorageAttachment, StorageUsage,
+};
+use crate::tag_filter::TagFilter;
+use crate::tag_normalization;
+use crate::usage_location::UsageLocation;
+
+const INSTANCE_TYPE: &str = "compute.googleapis.com/Instance";
+const DISK_TYPE: &str = "compute.googleapis.com/Disk";
+
+/// Returns the state of an instance (see [crate::aws_cloud_provider::INSTANCE_STATES]) of the status of a GCE instance (like `TERMINATED`, the status of stopped instances)
+fn instance_state(status: &str) -> &'static str {
+    match status {
+        "PROVISIONING" | "STAGING" => "pending",
+        "RUNNING" => "running",
+        "STOPPING" | "SUSPENDING" => "stopping",
+        "TERMINATED" | "SUSPENDED" => "stopped",
+        _ => "",
+    }
+}
+
+/// Returns the path of a resource of its asset name (`//compute.googleapis.com/projects/...`) or self link (`https://www.googleapis.com/compute/v1/projects/...`)
+fn resource_path(name: &str) -> &str {
+    name.find("projects/").map_or(name, |start| &name[start..])
+}
+
+/// Returns the last segment of a url (like the machine type of `.../machineTypes/n2-standard-4`)
+fn last_segment(url: &str) -> &str {
+    url.rsplit('/').next().unwrap_or(url)
+}
+
+/// Returns the region of a zone (like `europe-west1` of `europe-west1-b`)
+fn zone_region(zone: &str) -> &str {
+    match zone.rsplit_once('-') {
+        Some((region, suffix)) if suffix.len() == 1 => region,
+        _ => zone,
+    }
+}
+
+/// Returns the assets of an export: the lines of a newline delimited json file, or the items of a json array
+fn export_assets(content: &str) -> Result<Vec<Value>> {
+    if content.trim_start().starts_with('[') {
+        let assets: Vec<Value> = serde_json::from_str(content)
+            .context("Expected a json array of assets of Cloud Asset Inventory")?;
+        return Ok(assets);
+    }
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            serde_json::from_str(line).with_context(|| {
+                format!(
+                    "Expected an asset of Cloud Asset Inventory at line {}",
+                    number + 1
+                )
+            })
+        })
+        .collect()
+}
+
+/// Parses the instances (unless skipped by the selection, and in the selected states), and with `include_block_storage` the disks of a Cloud Asset Inventory export
+pub fn parse_asset_export(
+    content: &str,
+    tags: &[String],
+    include_block_storage: bool,
+    selection: &ResourceSelection,
+) -> Result<Inventory> {
+    let filter = TagFilter::parse_all(tags)?;
+    let mut resources = Vec::new();
+    for asset in export_assets(content)? {
+        let Some(name) = asset["name"].as_str() else {
+            continue;
+        };
+        let id = resource_path(name).to_string();
+        // Exports use snake case keys, gcloud asset list camel case keys
+        let asset_type = asset["asset_type"]
+            .as_str()
+            .or_else(|| asset["assetType"].as_str());
+        let data = &asset["resource"]["data"];
+        let resource_details = match asset_type {
+            Some(INSTANCE_TYPE) if !selection.skip_instances => {
+                let status = data["status"].as_str().unwrap_or_default();
+                if !selection.instance_states.is_empty()
+                    && !selection
+                        .instance_states
+                        .iter()
+                        .any(|s| s == instance_state(status))
+                {
+                    debug!("Filtered instance {} (in status {})", id, status);
+                    continue;
+                }
+                let Some(machine_type) = data["machineType"].as_str() else {
+                    warn!("Ignoring instance {} (no machine type)", id);
+                    continue;
+                };
+                ResourceDetails::Instance {
+                    instance_type: last_segment(machine_type).to_string(),
+                    usage: None,
+                }
+            }
+            Some(DISK_TYPE) if include_block_storage => {
+                // Sizes are int64 values, serialized as strings
+                let size_gb = data["sizeGb"]
+                    .as_str()
+                    .and_then(|size| size.parse::<i64>().ok())
+                    .or_else(|| data["sizeGb"].as_i64());
+                let Some(size_gb) = size_gb else {
+                    warn!("Ignoring disk {} (no size)", id);
+                    continue;
+                };
+                let attached_instances: Vec<StorageAttachment> = data["users"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .map(|user| StorageAttachment {
+                        instance_id: resource_path(user).to_string(),
+                    })
+                    .collect();
+                ResourceDetails::BlockStorage {
+                    storage_type: last_segment(data["type"].as_str().unwrap_or_default())
+                        .to_string(),
+                    usage: Some(StorageUsage {
+                        size_gb: size_gb as i32,
+                        usage_duration_seconds: 3600,
+                    }),
+                    attached_instances: (!attached_instances.is_empty())
+                        .then_some(attached_instances),
+                }
+            }
+            _ => continue,
+        };
+        let zone = asset["resource"]["location"]
+            .as_str()
+            .or_else(|| data["zone"].as_str().map(last_segment))
+            .unwrap_or_default();
+        let project = id
+            .strip_prefix("projects/")
+            .and_then(|path| path.split('/').next())
+            .map(str::to_string);
+        let tags = tag_normalization::normalize(labels(data));
+        let resource = CloudResource {
+            provider: CloudProvider::GCP,
+            account_id: project,
+            location: UsageLocation::try_from(zone_region(zone))
+                .with_context(|| format!("Unsupported region of {}", id))?,
+            id,
+            relationships: ResourceRelationship::derive(&resource_details, &tags),
+            resource_details,
+            tags,
+        };
+        if filter.matches(&resource.tags) {
+            resources.push(resource);
+        } else {
+            debug!("Filtered {} (tags do not match)", resource.id);
+        }
+    }
+    Ok(Inventory {
+        resources,
+        execution_statistics: None,
+    })
+}
+
+/// Reads Cloud Asset Inventory exports (gzipped when their name ends with `.gz`), the resources found in several exports being inventoried once, see [parse_asset_export]
+pub fn read_asset_exports(
+    paths: &[String],
+    tags: &[String],
+    include_block_storage: bool,
+    selection: &ResourceSelection,
+) -> Result<Inventory> {
+    let mut inventories = Vec::new();
+    for path in paths {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Cannot read asset export {}", path))?;
+        let mut content = String::new();
+        if path.ends_with(".gz") {
+            flate2::read::MultiGzDecoder::new(bytes.as_slice())
+                .read_to_string(&mut content)
+                .with_context(|| format!("Cannot decompress asset export {}", path))?;
+        } else {
+            content = String::from_utf8(bytes)
+                .with_context(|| format!("Cannot read asset export {}", path))?;
+        }
+        let inventory = parse_asset_export(&content, tags, include_block_storage, selection)
+            .with_context(|| format!("Cannot parse asset export {}", path))?;
+        inventories.push(inventory);
+    }
+    let mut seen = HashSet::new();
+    let mut inventory = Inventory::combine(inventories);
+    inventory
+        .resources
+        .retain(|resource| seen.insert(resource.id.clone()));
+    Ok(inventory)
+}
+
+/// Returns the labels of a resource (a map of their keys to their values)
+fn labels(data: &Value) -> Vec<CloudResourceTag> {
+    data["labels"]
+        .as_object()
+        .map(|labels| {
+            labels
+                .iter()
+                .map(|(key, value)| CloudResourceTag {
+                    key: key.clone(),
+                    value: value.as_str().map(str::to_string),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT: &str = r#"{"name": "//compute.googleapis.com/projects/shop/zones/europe-west1-b/instances/web-1", "asset_type": "compute.googleapis.com/Instance", "resource": {"version": "v1", "location": "europe-west1-b", "data": {"machineType": "https://www.googleapis.com/compute/v1/projects/shop/zones/europe-west1-b/machineTypes/n2-standard-4", "status": "RUNNING", "labels": {"team": "web"}}}}
+{"name": "//compute.googleapis.com/projects/shop/zones/us-central1-a/instances/batch-1", "asset_type": "compute.googleapis.com/Instance", "resource": {"version": "v1", "location": "us-central1-a", "data": {"machineType": "https://www.googleapis.com/compute/v1/projects/shop/zones/us-central1-a/machineTypes/e2-small", "status": "TERMINATED"}}}
+
+{"name": "//compute.googleapis.com/projects/shop/zones/europe-west1-b/disks/web-1", "asset_type": "compute.googleapis.com/Disk", "resource": {"version": "v1", "location": "europe-west1-b", "data": {"sizeGb": "50", "type": "https://www.googleapis.com/compute/v1/projects/shop/zones/europe-west1-b/diskTypes/pd-balanced", "users": ["https://www.googleapis.com/compute/v1/projects/shop/zones/europe-west1-b/instances/web-1"], "labels": {"team": "web"}}}}
+{"name": "//storage.googleapis.com/logs", "asset_type": "storage.googleapis.com/Bucket", "resource": {"version": "v1", "location": "eu", "data": {}}}
+"#;
+
+    #[test]
+    fn exports_are_inventoried() {
+        let all = ResourceSelection::default();
+        let inventory = parse_asset_export(EXPORT, &[], false, &all).unwrap();
+        let ids: Vec<&str> = inventory.resources.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(
+            vec![
+                "projects/shop/zones/europe-west1-b/instances/web-1",
+                "projects/shop/zones/us-central1-a/instances/batch-1"
+            ],
+            ids
+        );
+        let web = &inventory.resources[0];
+        assert_eq!(Some("shop".to_string()), web.account_id);
+        assert_eq!("europe-west1", web.location.aws_region);
+        assert_eq!("BEL", web.location.iso_country_code);
+        assert_eq!(
+            Some("n2-standard-4".to_string()),
+            web.resource_details.resource_type()
+        );
+
+        let (running, _) = ResourceSelection::of_kinds(&[], &["running".to_string()]);
+        let inventory =
+            parse_asset_export(EXPORT, &["team=web".to_string()], true, &running).unwrap();
+        assert_eq!(2, inventory.resources.len());
+        let disk = &inventory.resources[1];
+        assert_eq!(
+            Some("pd-balanced".to_string()),
+            disk.resource_details.resource_type()
+        );
+        assert_eq!(
+            vec![ResourceRelationship::AttachedTo {
+                instance_id: "projects/shop/zones/europe-west1-b/instances/web-1".to_string()
+            }],
+            disk.relationships
+        );
+    }
+
+    #[test]
+    fn assets_can_be_listed_as_a_json_array() {
+        let listed = r#"[{"name": "//compute.googleapis.com/projects/shop/zones/europe-west9-a/instances/web-2", "assetType": "compute.googleapis.com/Instance",
+                          "resource": {"location": "europe-west9-a", "data": {"machineType": "zones/europe-west9-a/machineTypes/n2-standard-2", "status": "RUNNING"}}}]"#;
+        let inventory =
+            parse_asset_export(listed, &[], false, &ResourceSelection::default()).unwrap();
+        assert_eq!(1, inventory.resources.len());
+        assert_eq!("FRA", inventory.resources[0].location.iso_country_code);
+        assert!(parse_asset_export("not json", &[], false, &ResourceSelection::default()).is_err());
+    }
+}
@@ -0,0 +1,508 @@
+//! Reports of saved scans following the GHG Protocol (`report --standard ghg-protocol`): the emissions of the resources are mapped to the scopes and categories of the Corporate Standard and of the Scope 3 Standard, with location-based and market-based totals and the methodology disclosures expected in a corporate inventory.
+//!
+//! - Cloud resources (AWS, Azure, GCP, OVH) are services bought from providers: their use and their manufacture are reported in Scope 3, category 1 (purchased goods and services).
+//! - On-premises servers are owned and powered by the organization: their use is reported in Scope 2 (purchased electricity) and their manufacture in Scope 3, category 2 (capital goods).
+//!
+//! Location-based emissions are the impacts estimated with the average carbon intensity of the grid of each region. Market-based emissions use the factors of the contractual instruments of the organization or of its providers (`--market-factor eu-west-3=0.012`, in kgCO2eq/kWh, by region, country or `*`), applied to the electricity of the resources (known for scans of verbose impacts): the location-based emissions are used (and disclosed) for the resources without factor or electricity.
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::impact_provider::CloudResourceWithImpacts;
+use crate::model::{CloudProvider, EstimatedInventory, ResourceDetails};
+use crate::result_envelope::ResultMetadata;
+
+/// Reporting standards of the report command
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportStandard {
+    GhgProtocol,
+    EsrsE1,
+}
+
+impl TryFrom<&str> for ReportStandard {
+    type Error = anyhow::Error;
+
+    fn try_from(standard: &str) -> Result<Self> {
+        match standard {
+            "ghg-protocol" => Ok(ReportStandard::GhgProtocol),
+            "esrs-e1" => Ok(ReportStandard::EsrsE1),
+            _ => anyhow::bail!(
+                "Unsupported reporting standard {} (expecting ghg-protocol or esrs-e1)",
+                standard
+            ),
+        }
+    }
+}
+
+/// Scopes of the GHG Protocol
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GhgScope {
+    Scope2,
+    Scope3,
+}
+
+/// Emissions of a category of a scope
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct GhgCategory {
+    pub scope: GhgScope,
+    /// Category of the scope, like `Purchased electricity` or `Category 1: Purchased goods and services`
+    pub category: String,
+    /// Activity of the resources, like `Use of cloud services`
+    pub activity: String,
+    pub number_of_resources: usize,
+    pub location_based_kgco2eq: f64,
+    pub market_based_kgco2eq: f64,
+    /// Electricity of the resources (only known for scans of verbose impacts), none for manufacture
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub electricity_kwh: Option<f64>,
+    /// Resources whose market-based emissions are their location-based emissions (no factor or no electricity)
+    pub number_of_resources_market_based_as_location_based: usize,
+}
+
+/// Report of a scan following the GHG Protocol
+#[derive(Clone, Debug, Serialize)]
+pub struct GhgProtocolReport {
+    pub standard: String,
+    pub organization: Option<String>,
+    pub generated_at: DateTime<Utc>,
+    pub cloud_scanner_version: String,
+    /// Date of the scan, when the scan has metadata
+    pub scan_timestamp: Option<DateTime<Utc>>,
+    /// Duration of use of the estimations (the reporting period)
+    pub reporting_period_hours: f64,
+    pub regions: Vec<String>,
+    /// Impact provider of the estimations, like `Boavizta API https://api.boavizta.org (1.3.0)`
+    pub impact_provider: String,
+    pub categories: Vec<GhgCategory>,
+    pub scope2_location_based_kgco2eq: f64,
+    pub scope2_market_based_kgco2eq: f64,
+    pub scope3_location_based_kgco2eq: f64,
+    pub scope3_market_based_kgco2eq: f64,
+    pub total_location_based_kgco2eq: f64,
+    pub total_market_based_kgco2eq: f64,
+    /// Market-based factors used (kgCO2eq/kWh), by region, country or `*`
+    pub market_factors: BTreeMap<String, f64>,
+    pub number_of_resources_not_assessed: usize,
+    pub disclosures: Vec<String>,
+}
+
+/// Methodology disclosures of every report
+const DISCLOSURES: [&str; 6] = [
+    "Boundary: the resources of the scanned accounts, regions and filters (operational control approach); the networking, support services and object storage of the providers are excluded.",
+    "Emissions are estimated by Boavizta API from the type, configuration and average CPU load of the resources, over the reporting period; they are expressed in kgCO2eq with the GWP100 of the embedded and use phases of the life cycle of the hardware.",
+    "The emissions of manufacture (embodied) of the hardware are allocated to the reporting period over the expected lifetime of the hardware, and are the same in the location-based and market-based totals.",
+    "Location-based emissions use the average carbon intensity of the electricity grid of the country of each region (sources of the factors in the verbose details of Boavizta API), including the power usage effectiveness of the data centers.",
+    "Cloud services are reported in Scope 3, category 1 (their use is Scope 2 of the providers); on-premises servers are reported in Scope 2 (use) and Scope 3, category 2 (manufacture).",
+    "Emissions are modelled estimates, not measurements: they are suitable for a screening inventory and for tracking trends, and carry the uncertainty of the models of Boavizta API.",
+];
+
+/// Parses market-based factors like `eu-west-3=0.012` or `*=0.3` (kgCO2eq/kWh)
+pub fn parse_market_factors(factors: &[String]) -> Result<BTreeMap<String, f64>> {
+    factors
+        .iter()
+        .map(|factor| {
+            let (key, value) = factor.split_once('=').with_context(|| {
+                format!(
+                    "Expected a market-based factor like eu-west-3=0.012, not {}",
+                    factor
+                )
+            })?;
+            let value: f64 = value
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid market-based factor {}", factor))?;
+            if value < 0.0 {
+                anyhow::bail!("Market-based factor {} is negative", factor);
+            }
+            Ok((key.trim().to_string(), value))
+        })
+        .collect()
+}
+
+/// Returns the carbon intensity of electricity (kgCO2eq/kWh) used to estimate a resource, read from the verbose details of Boavizta API
+pub(crate) fn location_factor(resource: &CloudResourceWithImpacts) -> Option<f64> {
+    let raw_data = resource.impacts_values.as_ref()?.raw_data.as_ref()?;
+    crate::metric_exporter::verbose_factor(raw_data, "gwp_factor")
+        .map(|(value, _)| value)
+        .filter(|value| *value > 0.0)
+}
+
+/// Accumulates the emissions of a category
+#[derive(Default)]
+struct CategoryTotals {
+    number_of_resources: usize,
+    location_based_kgco2eq: f64,
+    market_based_kgco2eq: f64,
+    electricity_kwh: Option<f64>,
+    market_based_as_location_based: usize,
+}
+
+impl CategoryTotals {
+    fn add(&mut self, location_based: f64, market_based: Option<f64>, electricity: Option<f64>) {
+        self.number_of_resources += 1;
+        self.location_based_kgco2eq += location_based;
+        match market_based {
+            Some(market_based) => self.market_based_kgco2eq += market_based,
+            None => {
+                self.market_based_kgco2eq += location_based;
+                self.market_based_as_location_based += 1;
+            }
+        }
+        if let Some(electricity) = electricity {
+            *self.electricity_kwh.get_or_insert(0.0) += electricity;
+        }
+    }
+}
+
+/// Returns the GHG Protocol report of a scan, with the market-based factors (kgCO2eq/kWh) by region, country or `*`
+pub fn build_ghg_protocol_report(
+    metadata: Option<&ResultMetadata>,
+    estimated_inventory: &EstimatedInventory,
+    market_factors: &BTreeMap<String, f64>,
+    organization: Option<&str>,
+) -> GhgProtocolReport {
+    // Scope, category, activity, and whether the category is the use of resources
+    type Key = (GhgScope, &'static str, &'static str, bool);
+    let mut totals: BTreeMap<Key, CategoryTotals> = BTreeMap::new();
+    let mut not_assessed = 0;
+    let mut regions: Vec<String> = Vec::new();
+    for resource in estimated_inventory.impacting_resources.iter() {
+        let location = &resource.cloud_resource.location;
+        if !regions.contains(&location.aws_region) {
+            regions.push(location.aws_region.clone());
+        }
+        let Some(impacts) = resource.impacts_values.as_ref() else {
+            not_assessed += 1;
+            continue;
+        };
+        let owned = matches!(resource.cloud_resource.provider, CloudProvider::OnPremises)
+            || matches!(
+                resource.cloud_resource.resource_details,
+                ResourceDetails::Server { .. }
+            );
+        let (use_key, manufacture_key): (Key, Key) = if owned {
+            (
+                (
+                    GhgScope::Scope2,
+                    "Purchased electricity",
+                    "Use of on-premises servers",
+                    true,
+                ),
+                (
+                    GhgScope::Scope3,
+                    "Category 2: Capital goods",
+                    "Manufacture of on-premises servers",
+                    false,
+                ),
+            )
+        } else {
+            (
+                (
+                    GhgScope::Scope3,
+                    "Category 1: Purchased goods and services",
+                    "Use of cloud services",
+                    true,
+                ),
+                (
+                    GhgScope::Scope3,
+                    "Category 1: Purchased goods and services",
+                    "Manufacture of the hardware of cloud services",
+                    false,
+                ),
+            )
+        };
+        let electricity = location_factor(resource).map(|factor| impacts.gwp_use_kgco2eq / factor);
+        let market_factor = market_factors
+            .get(&location.aws_region)
+            .or_else(|| market_factors.get(&location.iso_country_code))
+            .or_else(|| market_factors.get("*"));
+        let market_based = electricity
+            .zip(market_factor)
+            .map(|(electricity, factor)| electricity * factor);
+        totals
+            .entry(use_key)
+            .or_default()
+            .add(impacts.gwp_use_kgco2eq, market_based, electricity);
+        totals.entry(manufacture_key).or_default().add(
+            impacts.gwp_manufacture_kgco2eq,
+            Some(impacts.gwp_manufacture_kgco2eq),
+            None,
+        );
+    }
+    let categories: Vec<GhgCategory> = totals
+        .into_iter()
+        .map(
+            |((scope, category, activity, is_use), totals)| GhgCategory {
+                scope,
+                category: category.to_string(),
+                activity: activity.to_string(),
+                number_of_resources: totals.number_of_resources,
+                location_based_kgco2eq: totals.location_based_kgco2eq,
+                market_based_kgco2eq: totals.market_based_kgco2eq,
+                electricity_kwh: if is_use { totals.electricity_kwh } else { None },
+                number_of_resources_market_based_as_location_based: totals
+                    .market_based_as_location_based,
+            },
+        )
+        .collect();
+    let sum = |scope: GhgScope, value: fn(&GhgCategory) -> f64| -> f64 {
+        categories
+            .iter()
+            .filter(|category| category.scope == scope)
+            .map(value)
+            // Scopes without resources are 0, not -0
+            .fold(0.0, |total, value| total + value)
+    };
+    let scope2_location_based_kgco2eq = sum(GhgScope::Scope2, |c| c.location_based_kgco2eq);
+    let scope2_market_based_kgco2eq = sum(GhgScope::Scope2, |c| c.market_based_kgco2eq);
+    let scope3_location_based_kgco2eq = sum(GhgScope::Scope3, |c| c.location_based_kgco2eq);
+    let scope3_market_based_kgco2eq = sum(GhgScope::Scope3, |c| c.market_based_kgco2eq);
+
+    let mut disclosures: Vec<String> = DISCLOSURES.iter().map(|d| d.to_string()).collect();
+    let as_location_based: usize = categories
+        .iter()
+        .map(|c| c.number_of_resources_market_based_as_location_based)
+        .sum();
+    if as_location_based > 0 {
+        disclosures.push(format!(
+            "Market-based emissions of {} resources are their location-based emissions: no contractual factor was provided for their region, or their electricity is unknown (scan with verbose impacts to know it).",
+            as_location_based
+        ));
+    }
+    if not_assessed > 0 {
+        disclosures.push(format!(
+            "{} resources of an unknown type were not assessed and are excluded from the totals.",
+            not_assessed
+        ));
+    }
+    if !estimated_inventory.errors.is_empty() {
+        disclosures.push(format!(
+            "The scan continued past {} errors (regions or resources that could not be listed or estimated), which are excluded from the totals.",
+            estimated_inventory.errors.len()
+        ));
+    }
+    let impact_provider = metadata
+        .and_then(|metadata| metadata.impact_provider.as_ref())
+        .map(|provider| match &provider.version {
+            Some(version) => format!("{} {} ({})", provider.name, provider.url, version),
+            None => format!("{} {}", provider.name, provider.url),
+        })
+        .unwrap_or_else(|| "Boavizta API".to_string());
+    let reporting_period_hours = metadata
+        .and_then(|metadata| metadata.parameters.use_duration_hours)
+        .or_else(|| {
+            estimated_inventory
+                .impacting_resources
+                .first()
+                .map(|resource| resource.impacts_duration_hours)
+        })
+        .unwrap_or_default() as f64;
+    GhgProtocolReport {
+        standard: "GHG Protocol".to_string(),
+        organization: organization.map(str::to_string),
+        generated_at: Utc::now(),
+        cloud_scanner_version: crate::get_version(),
+        scan_timestamp: metadata.map(|metadata| metadata.scan_timestamp),
+        reporting_period_hours,
+        regions,
+        impact_provider,
+        categories,
+        scope2_location_based_kgco2eq,
+        scope2_market_based_kgco2eq,
+        scope3_location_based_kgco2eq,
+        scope3_market_based_kgco2eq,
+        total_location_based_kgco2eq: scope2_location_based_kgco2eq + scope3_location_based_kgco2eq,
+        total_market_based_kgco2eq: scope2_market_based_kgco2eq + scope3_market_based_kgco2eq,
+        market_factors: market_factors.clone(),
+        number_of_resources_not_assessed: not_assessed,
+        disclosures,
+    }
+}
+
+impl GhgProtocolReport {
+    /// Returns the report as a Markdown document
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        md.push_str("# Greenhouse gas emissions of cloud and IT resources (GHG Protocol)\n\n");
+        if let Some(organization) = &self.organization {
+            md.push_str(&format!("- Organization: {}\n", organization));
+        }
+        md.push_str(&format!(
+            "- Reporting period: {} hours{}\n",
+            self.reporting_period_hours,
+            self.scan_timestamp
+                .map(|timestamp| format!(" (scan of {})", timestamp.to_rfc3339()))
+                .unwrap_or_default()
+        ));
+        md.push_str(&format!("- Regions: {}\n", self.regions.join(", ")));
+        md.push_str(&format!("- Impact provider: {}\n", self.impact_provider));
+        md.push_str(&format!(
+            "- Generated by cloud-scanner {} on {}\n\n",
+            self.cloud_scanner_version,
+            self.generated_at.to_rfc3339()
+        ));
+
+        md.push_str("## Summary (kgCO2eq)\n\n");
+        md.push_str("| | Location-based | Market-based |\n|---|---:|---:|\n");
+        md.push_str(&format!(
+            "| Scope 2 | {:.3} | {:.3} |\n",
+            self.scope2_location_based_kgco2eq, self.scope2_market_based_kgco2eq
+        ));
+        md.push_str(&format!(
+            "| Scope 3 | {:.3} | {:.3} |\n",
+            self.scope3_location_based_kgco2eq, self.scope3_market_based_kgco2eq
+        ));
+        md.push_str(&format!(
+            "| **Total** | **{:.3}** | **{:.3}** |\n\n",
+            self.total_location_based_kgco2eq, self.total_market_based_kgco2eq
+        ));
+
+        md.push_str("## Emissions by scope and category (kgCO2eq)\n\n");
+        md.push_str("| Scope | Category | Activity | Resources | Location-based | Market-based | Electricity (kWh) |\n");
+        md.push_str("|---|---|---|---:|---:|---:|---:|\n");
+        for category in self.categories.iter() {
+            md.push_str(&format!(
+                "| {} | {} | {} | {} | {:.3} | {:.3} | {} |\n",
+                match category.scope {
+                    GhgScope::Scope2 => "Scope 2",
+                    GhgScope::Scope3 => "Scope 3",
+                },
+                category.category,
+                category.activity,
+                category.number_of_resources,
+                category.location_based_kgco2eq,
+                category.market_based_kgco2eq,
+                category
+                    .electricity_kwh
+                    .map(|kwh| format!("{:.3}", kwh))
+                    .unwrap_or_else(|| "-".to_string())
+            ));
+        }
+        md.push('\n');
+
+        md.push_str("## Market-based emission factors\n\n");
+        if self.market_factors.is_empty() {
+            md.push_str("No contractual instrument (supplier-specific factor, energy attribute certificate or residual mix) was provided: the market-based emissions are the location-based emissions.\n\n");
+        } else {
+            for (key, factor) in self.market_factors.iter() {
+                let key = if key == "*" { "Other regions" } else { key };
+                md.push_str(&format!("- {}: {} kgCO2eq/kWh\n", key, factor));
+            }
+            md.push('\n');
+        }
+
+        md.push_str("## Methodology and disclosures\n\n");
+        for disclosure in self.disclosures.iter() {
+            md.push_str(&format!("- {}\n", disclosure));
+        }
+        md
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::ImpactsValues;
+    use crate::model::CloudResource;
+    use crate::usage_location::UsageLocation;
+
+    fn resource(
+        id: &str,
+        provider: CloudProvider,
+        gwp: Option<(f64, f64)>,
+        gwp_factor: Option<f64>,
+    ) -> CloudResourceWithImpacts {
+        CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider,
+                id: id.to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details: ResourceDetails::Instance {
+                    instance_type: "t3.micro".to_string(),
+                    usage: None,
+                },
+                tags: Vec::new(),
+                account_id: None,
+                relationships: Vec::new(),
+            },
+            impacts_values: gwp.map(|(manufacture, usage)| ImpactsValues {
+                gwp_manufacture_kgco2eq: manufacture,
+                gwp_use_kgco2eq: usage,
+                raw_data: gwp_factor.map(|factor| {
+                    serde_json::json!({"verbose": {"gwp_factor": {"value": factor, "source": "test"}}})
+                }),
+                ..Default::default()
+            }),
+            impacts_duration_hours: 730.0,
+            cost: None,
+        }
+    }
+
+    #[test]
+    fn resources_are_mapped_to_scopes_and_categories() {
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![
+                resource("i-1", CloudProvider::AWS, Some((2.0, 1.0)), Some(0.1)),
+                resource("i-2", CloudProvider::AWS, Some((2.0, 3.0)), None),
+                resource("i-3", CloudProvider::AWS, None, None),
+                resource(
+                    "srv-1",
+                    CloudProvider::OnPremises,
+                    Some((10.0, 5.0)),
+                    Some(0.05),
+                ),
+            ],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let factors = parse_market_factors(&["FRA=0.01".to_string()]).unwrap();
+        let report = build_ghg_protocol_report(None, &estimated_inventory, &factors, Some("Shop"));
+        assert_eq!(4, report.categories.len());
+
+        let scope2 = &report.categories[0];
+        assert_eq!(
+            (GhgScope::Scope2, 1),
+            (scope2.scope, scope2.number_of_resources)
+        );
+        // 5 kgCO2eq at 0.05 kgCO2eq/kWh are 100 kWh, 1 kgCO2eq at 0.01 kgCO2eq/kWh
+        assert_eq!(Some(100.0), scope2.electricity_kwh);
+        assert!((scope2.market_based_kgco2eq - 1.0).abs() < 1e-9);
+
+        let cloud_use = report
+            .categories
+            .iter()
+            .find(|c| c.activity == "Use of cloud services")
+            .unwrap();
+        assert_eq!(2, cloud_use.number_of_resources);
+        assert_eq!(4.0, cloud_use.location_based_kgco2eq);
+        // i-1: 10 kWh at 0.01, i-2: no electricity, its location-based emissions
+        assert!((cloud_use.market_based_kgco2eq - 3.1).abs() < 1e-9);
+        assert_eq!(
+            1,
+            cloud_use.number_of_resources_market_based_as_location_based
+        );
+
+        assert_eq!(5.0, report.scope2_location_based_kgco2eq);
+        assert_eq!(18.0, report.scope3_location_based_kgco2eq);
+        assert!((report.scope3_market_based_kgco2eq - 17.1).abs() < 1e-9);
+        assert_eq!(23.0, report.total_location_based_kgco2eq);
+        assert_eq!(1, report.number_of_resources_not_assessed);
+
+        let markdown = report.to_markdown();
+        assert!(markdown.contains("- Organization: Shop"));
+        assert!(markdown.contains("| Scope 2 | 5.000 | 1.000 |"));
+        assert!(markdown.contains("1 resources of an unknown type were not assessed"));
+    }
+
+    #[test]
+    fn market_factors_are_parsed() {
+        let factors =
+            parse_market_factors(&["eu-west-3=0.012".to_string(), "* = 0.3".to_string()]).unwrap();
+        assert_eq!(Some(&0.3), factors.get("*"));
+        assert!(parse_market_factors(&["eu-west-3".to_string()]).is_err());
+        assert!(parse_market_factors(&["eu-west-3=-1".to_string()]).is_err());
+        assert!(ReportStandard::try_from("iso-14064").is_err());
+    }
+}
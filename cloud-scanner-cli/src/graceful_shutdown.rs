@@ -0,0 +1,120 @@
+//! Graceful shutdown of the standalone server: when it is asked to stop (like by the `SIGTERM` of a Kubernetes rolling update), the scans still running finish before the server exits.
+//!
+//! Scans started in the background (jobs and scheduled scans) are tracked with [InFlightScans]. On shutdown, the [DrainScans] fairing waits for them during the grace period of Rocket, while Rocket finishes the requests in flight.
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Orbit, Rocket};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Number of scans running in the background
+#[derive(Clone)]
+pub struct InFlightScans {
+    count: Arc<watch::Sender<usize>>,
+}
+
+impl Default for InFlightScans {
+    fn default() -> Self {
+        InFlightScans {
+            count: Arc::new(watch::Sender::new(0)),
+        }
+    }
+}
+
+/// A scan in flight, until dropped
+pub struct InFlightScan {
+    count: Arc<watch::Sender<usize>>,
+}
+
+impl Drop for InFlightScan {
+    fn drop(&mut self) {
+        self.count.send_modify(|count| *count -= 1);
+    }
+}
+
+impl InFlightScans {
+    /// Tracks a scan until the returned guard is dropped
+    pub fn start(&self) -> InFlightScan {
+        self.count.send_modify(|count| *count += 1);
+        InFlightScan {
+            count: self.count.clone(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        *self.count.borrow()
+    }
+
+    /// Waits until no scan is in flight, returns false when some are still running after the timeout
+    pub async fn wait_until_idle(&self, timeout: Duration) -> bool {
+        let mut count = self.count.subscribe();
+        let idle = tokio::time::timeout(timeout, count.wait_for(|count| *count == 0)).await;
+        idle.is_ok()
+    }
+}
+
+/// A fairing waiting for the scans in flight when the server shuts down
+pub struct DrainScans {
+    scans: InFlightScans,
+}
+
+impl DrainScans {
+    pub fn new(scans: InFlightScans) -> Self {
+        DrainScans { scans }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for DrainScans {
+    fn info(&self) -> Info {
+        Info {
+            name: "Drain scans",
+            kind: Kind::Shutdown,
+        }
+    }
+
+    async fn on_shutdown(&self, rocket: &Rocket<Orbit>) {
+        let count = self.scans.count();
+        if count == 0 {
+            return;
+        }
+        let grace = Duration::from_secs(rocket.config().shutdown.grace.into());
+        info!(
+            "Waiting up to {:?} for {} scans in flight to finish",
+            grace, count
+        );
+        if self.scans.wait_until_idle(grace).await {
+            info!("Scans in flight finished");
+        } else {
+            warn!(
+                "Stopping with {} scans still running after {:?}",
+                self.scans.count(),
+                grace
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waits_for_scans_in_flight() {
+        let scans = InFlightScans::default();
+        assert!(scans.wait_until_idle(Duration::ZERO).await);
+
+        let scan = scans.start();
+        let other_scan = scans.start();
+        assert_eq!(2, scans.count());
+        drop(other_scan);
+        assert!(!scans.wait_until_idle(Duration::from_millis(10)).await);
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            drop(scan);
+        });
+        assert!(scans.wait_until_idle(Duration::from_secs(5)).await);
+        assert_eq!(0, scans.count());
+    }
+}
@@ -0,0 +1,426 @@
+//! A module to generate a Grafana dashboard that displays the metrics produced by the [metric_exporter](crate::metric_exporter) module.
+//!
+//! The dashboard queries a Prometheus datasource. By default the datasource is declared as an input (`DS_PROMETHEUS`), so that Grafana asks for it when the dashboard is imported.
+use anyhow::Result;
+use serde_json::{json, Value};
+
+/// Name of the datasource input of the dashboard (when no datasource uid is provided)
+const DATASOURCE_INPUT: &str = "DS_PROMETHEUS";
+
+/// Summary metrics displayed as stats (metric name, title, unit)
+const SUMMARY_STATS: [(&str, &str, &str); 8] = [
+    (
+        "boavizta_number_of_resources_total",
+        "Resources detected",
+        "none",
+    ),
+    (
+        "boavizta_number_of_resources_assessed",
+        "Resources assessed",
+        "none",
+    ),
+    (
+        "boavizta_gwp_manufacture_kgco2eq",
+        "GWP manufacture (kgCO2eq)",
+        "none",
+    ),
+    ("boavizta_gwp_use_kgco2eq", "GWP use (kgCO2eq)", "none"),
+    (
+        "boavizta_pe_manufacture_megajoules",
+        "Primary energy manufacture",
+        "decmjoule",
+    ),
+    (
+        "boavizta_pe_use_megajoules",
+        "Primary energy use",
+        "decmjoule",
+    ),
+    (
+        "boavizta_adp_manufacture_kgsbeq",
+        "ADP manufacture (kgSbeq)",
+        "none",
+    ),
+    ("boavizta_adp_use_kgsbeq", "ADP use (kgSbeq)", "none"),
+];
+
+/// Resource metrics displayed as time series by region (metric name, title, unit)
+const RESOURCE_TIMESERIES: [(&str, &str, &str); 6] = [
+    (
+        "boavizta_resource_gwp_embodied_kgco2eq",
+        "GWP embodied by region (kgCO2eq)",
+        "none",
+    ),
+    (
+        "boavizta_resource_gwp_use_kgco2eq",
+        "GWP use by region (kgCO2eq)",
+        "none",
+    ),
+    (
+        "boavizta_resource_pe_embodied_megajoules",
+        "Primary energy embodied by region",
+        "decmjoule",
+    ),
+    (
+        "boavizta_resource_pe_use_megajoules",
+        "Primary energy use by region",
+        "decmjoule",
+    ),
+    (
+        "boavizta_resource_adp_embodied_kgsbeq",
+        "ADP embodied by region (kgSbeq)",
+        "none",
+    ),
+    (
+        "boavizta_resource_adp_use_kgsbeq",
+        "ADP use by region (kgSbeq)",
+        "none",
+    ),
+];
+
+/// Returns a Grafana dashboard (as json) displaying cloud scanner metrics.
+///
+/// If `datasource_uid` is provided, panels use this Prometheus datasource directly (suitable for provisioning), otherwise the datasource is an input of the dashboard (suitable for manual import).
+pub fn get_dashboard(datasource_uid: Option<&str>) -> Result<String> {
+    let dashboard = build_dashboard(datasource_uid);
+    Ok(serde_json::to_string_pretty(&dashboard)?)
+}
+
+fn build_dashboard(datasource_uid: Option<&str>) -> Value {
+    let uid = match datasource_uid {
+        Some(uid) => uid.to_string(),
+        None => format!("${{{}}}", DATASOURCE_INPUT),
+    };
+    let datasource = json!({ "type": "prometheus", "uid": uid });
+
+    let mut panels: Vec<Value> = Vec::new();
+    let mut id = 1;
+
+    panels.push(row_panel(id, "Summary", 0));
+    id += 1;
+    for (i, (metric, title, unit)) in SUMMARY_STATS.iter().enumerate() {
+        let expr = format!("sum({}{{awsregion=~\"$awsregion\"}})", metric);
+        let grid_pos = json!({ "h": 4, "w": 6, "x": (i % 4) * 6, "y": 1 + (i / 4) * 4 });
+        panels.push(stat_panel(id, title, &expr, unit, grid_pos, &datasource));
+        id += 1;
+    }
+
+    panels.push(row_panel(id, "Impacts by region", 9));
+    id += 1;
+    for (i, (metric, title, unit)) in RESOURCE_TIMESERIES.iter().enumerate() {
+        let expr = format!(
+            "sum by(awsregion) ({}{{awsregion=~\"$awsregion\"}})",
+            metric
+        );
+        let grid_pos = json!({ "h": 8, "w": 12, "x": (i % 2) * 12, "y": 10 + (i / 2) * 8 });
+        panels.push(timeseries_panel(
+            id,
+            title,
+            &expr,
+            "{{awsregion}}",
+            unit,
+            grid_pos,
+            &datasource,
+        ));
+        id += 1;
+    }
+
+    panels.push(row_panel(id, "Resources", 34));
+    id += 1;
+    panels.push(bargauge_panel(
+        id,
+        "Top 10 resources by GWP use (kgCO2eq)",
+        "topk(10, boavizta_resource_gwp_use_kgco2eq{awsregion=~\"$awsregion\"})",
+        "{{resource_id}} ({{resource_type}})",
+        json!({ "h": 10, "w": 12, "x": 0, "y": 35 }),
+        &datasource,
+    ));
+    id += 1;
+    panels.push(bargauge_panel(
+        id,
+        "Top 10 resources by GWP embodied (kgCO2eq)",
+        "topk(10, boavizta_resource_gwp_embodied_kgco2eq{awsregion=~\"$awsregion\"})",
+        "{{resource_id}} ({{resource_type}})",
+        json!({ "h": 10, "w": 12, "x": 12, "y": 35 }),
+        &datasource,
+    ));
+    id += 1;
+    panels.push(timeseries_panel(
+        id,
+        "Resources by type and state",
+        "count by(resource_type, resource_state) (boavizta_resource_duration_of_use_hours{awsregion=~\"$awsregion\"})",
+        "{{resource_type}} {{resource_state}}",
+        "none",
+        json!({ "h": 8, "w": 8, "x": 0, "y": 45 }),
+        &datasource,
+    ));
+    id += 1;
+    panels.push(timeseries_panel(
+        id,
+        "Average CPU load of instances",
+        "boavizta_resource_cpu_load{awsregion=~\"$awsregion\"}",
+        "{{resource_id}}",
+        "percent",
+        json!({ "h": 8, "w": 8, "x": 8, "y": 45 }),
+        &datasource,
+    ));
+    id += 1;
+    panels.push(timeseries_panel(
+        id,
+        "Block storage size by region",
+        "sum by(awsregion) (boavizta_storage_size_gb{awsregion=~\"$awsregion\"})",
+        "{{awsregion}}",
+        "decgbytes",
+        json!({ "h": 8, "w": 8, "x": 16, "y": 45 }),
+        &datasource,
+    ));
+
+    let mut dashboard = json!({
+        "annotations": { "list": [] },
+        "description": "Environmental impacts of cloud resources, estimated by cloud scanner with Boavizta API",
+        "editable": true,
+        "graphTooltip": 1,
+        "panels": panels,
+        "refresh": "5m",
+        "schemaVersion": 39,
+        "tags": ["cloud-scanner", "boavizta"],
+        "templating": {
+            "list": [{
+                "name": "awsregion",
+                "label": "AWS region",
+                "type": "query",
+                "datasource": datasource,
+                "query": {
+                    "query": "label_values(boavizta_resource_duration_of_use_hours, awsregion)",
+                    "refId": "awsregion"
+                },
+                "definition": "label_values(boavizta_resource_duration_of_use_hours, awsregion)",
+                "includeAll": true,
+                "multi": true,
+                "current": { "selected": true, "text": ["All"], "value": ["$__all"] },
+                "refresh": 2,
+                "sort": 1
+            }]
+        },
+        "time": { "from": "now-24h", "to": "now" },
+        "timezone": "",
+        "title": "Cloud scanner - cloud impacts",
+        "uid": "cloud-scanner-impacts",
+        "version": 1
+    });
+
+    if datasource_uid.is_none() {
+        dashboard["__inputs"] = json!([{
+            "name": DATASOURCE_INPUT,
+            "label": "Prometheus",
+            "description": "Prometheus datasource scraping cloud scanner metrics",
+            "type": "datasource",
+            "pluginId": "prometheus",
+            "pluginName": "Prometheus"
+        }]);
+    }
+    dashboard
+}
+
+fn row_panel(id: usize, title: &str, y: usize) -> Value {
+    json!({
+        "id": id,
+        "type": "row",
+        "title": title,
+        "collapsed": false,
+        "gridPos": { "h": 1, "w": 24, "x": 0, "y": y },
+        "panels": []
+    })
+}
+
+fn target(expr: &str, legend: &str, datasource: &Value) -> Value {
+    json!({
+        "datasource": datasource,
+        "expr": expr,
+        "legendFormat": legend,
+        "refId": "A"
+    })
+}
+
+fn stat_panel(
+    id: usize,
+    title: &str,
+    expr: &str,
+    unit: &str,
+    grid_pos: Value,
+    datasource: &Value,
+) -> Value {
+    json!({
+        "id": id,
+        "type": "stat",
+        "title": title,
+        "datasource": datasource,
+        "gridPos": grid_pos,
+        "fieldConfig": { "defaults": { "unit": unit, "decimals": 2 }, "overrides": [] },
+        "options": {
+            "colorMode": "value",
+            "graphMode": "area",
+            "reduceOptions": { "calcs": ["lastNotNull"], "fields": "", "values": false }
+        },
+        "targets": [target(expr, "", datasource)]
+    })
+}
+
+fn timeseries_panel(
+    id: usize,
+    title: &str,
+    expr: &str,
+    legend: &str,
+    unit: &str,
+    grid_pos: Value,
+    datasource: &Value,
+) -> Value {
+    json!({
+        "id": id,
+        "type": "timeseries",
+        "title": title,
+        "datasource": datasource,
+        "gridPos": grid_pos,
+        "fieldConfig": { "defaults": { "unit": unit }, "overrides": [] },
+        "options": {
+            "legend": { "displayMode": "list", "placement": "bottom", "showLegend": true },
+            "tooltip": { "mode": "multi", "sort": "desc" }
+        },
+        "targets": [target(expr, legend, datasource)]
+    })
+}
+
+fn bargauge_panel(
+    id: usize,
+    title: &str,
+    expr: &str,
+    legend: &str,
+    grid_pos: Value,
+    datasource: &Value,
+) -> Value {
+    let mut query = target(expr, legend, datasource);
+    query["instant"] = json!(true);
+    json!({
+        "id": id,
+        "type": "bargauge",
+        "title": title,
+        "datasource": datasource,
+        "gridPos": grid_pos,
+        "fieldConfig": { "defaults": { "unit": "none", "decimals": 4 }, "overrides": [] },
+        "options": {
+            "displayMode": "gradient",
+            "orientation": "horizontal",
+            "reduceOptions": { "calcs": ["lastNotNull"], "fields": "", "values": false }
+        },
+        "targets": [query]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{CloudResourceWithImpacts, ImpactsSummary, ImpactsValues};
+    use crate::metric_exporter::get_all_metrics;
+    use crate::model::{
+        CloudProvider, CloudResource, EstimatedInventory, InstanceState, InstanceUsage,
+        ResourceDetails, StorageUsage,
+    };
+    use crate::usage_location::UsageLocation;
+
+    /// Returns the expressions of all queries of the dashboard
+    fn all_expressions(dashboard: &Value) -> Vec<String> {
+        dashboard["panels"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|panel| panel["targets"].as_array())
+            .flatten()
+            .map(|target| target["expr"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    fn resource(id: &str, resource_details: ResourceDetails) -> CloudResourceWithImpacts {
+        CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: id.to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details,
+                tags: Vec::new(),
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues::default()),
+            impacts_duration_hours: 1.0,
+            cost: None,
+        }
+    }
+
+    #[test]
+    fn dashboard_only_uses_exported_metrics() {
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![
+                resource(
+                    "inst-1",
+                    ResourceDetails::Instance {
+                        instance_type: "m6g.xlarge".to_string(),
+                        usage: Some(InstanceUsage {
+                            average_cpu_load: 42.0,
+                            usage_duration_seconds: 3600,
+                            state: InstanceState::Running,
+                        }),
+                    },
+                ),
+                resource(
+                    "vol-1",
+                    ResourceDetails::BlockStorage {
+                        storage_type: "gp2".to_string(),
+                        usage: Some(StorageUsage {
+                            size_gb: 42,
+                            usage_duration_seconds: 3600,
+                        }),
+                        attached_instances: None,
+                    },
+                ),
+            ],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let metrics = get_all_metrics(&summary, &estimated_inventory).unwrap();
+
+        let dashboard = build_dashboard(None);
+        for expr in all_expressions(&dashboard) {
+            let metric = expr
+                .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .find(|word| word.starts_with("boavizta_"))
+                .unwrap();
+            assert!(
+                metrics.contains(&format!("# TYPE {} gauge", metric)),
+                "Metric {} is not exported",
+                metric
+            );
+        }
+    }
+
+    #[test]
+    fn datasource_is_an_input_unless_an_uid_is_provided() {
+        let dashboard = build_dashboard(None);
+        assert_eq!(DATASOURCE_INPUT, dashboard["__inputs"][0]["name"]);
+        assert_eq!(
+            "${DS_PROMETHEUS}",
+            dashboard["panels"][1]["datasource"]["uid"]
+        );
+
+        let dashboard = build_dashboard(Some("prometheus-uid"));
+        assert!(dashboard.get("__inputs").is_none());
+        assert_eq!(
+            "prometheus-uid",
+            dashboard["panels"][1]["datasource"]["uid"]
+        );
+    }
+}
@@ -0,0 +1,467 @@
+//! An optional GraphQL endpoint over the results of a scan, so that UI developers can query the fields and aggregations they need (groups of a tag, top emitters) in one request.
+//!
+//! `POST /graphql` runs queries (authenticated like the REST API), `GET /graphql` serves the GraphiQL IDE.
+use async_graphql::http::GraphiQLSource;
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject};
+use async_graphql_rocket::{GraphQLRequest, GraphQLResponse};
+use rocket::response::content::RawHtml;
+use rocket::State;
+
+use crate::access_log::RequestId;
+use crate::aws_cloud_provider::AwsAccount;
+use crate::impact_provider::{CloudResourceWithImpacts, GroupSummary, ImpactsValues};
+use crate::model::EstimatedInventory;
+use crate::rate_limit::RateLimited;
+use crate::response_cache::{cache_key, ResponseCache};
+use crate::server_auth::Authenticated;
+use crate::tenants::TenantConfig;
+use crate::top_emitters::{top_emitters, RankingCriterion};
+use crate::ImpactsSummary;
+
+pub type CloudScannerSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+/// What the resolvers need to scan (shared by the queries)
+struct ScanContext {
+    boavizta_url: String,
+    accounts: Vec<AwsAccount>,
+    cache: ResponseCache<EstimatedInventory>,
+}
+
+/// Impacts over the duration of use
+#[derive(Clone, Debug, SimpleObject, PartialEq)]
+pub struct Impacts {
+    pub adp_manufacture_kgsbeq: f64,
+    pub adp_use_kgsbeq: f64,
+    pub pe_manufacture_megajoules: f64,
+    pub pe_use_megajoules: f64,
+    pub gwp_manufacture_kgco2eq: f64,
+    pub gwp_use_kgco2eq: f64,
+}
+
+impl From<&ImpactsValues> for Impacts {
+    fn from(impacts: &ImpactsValues) -> Self {
+        Impacts {
+            adp_manufacture_kgsbeq: impacts.adp_manufacture_kgsbeq,
+            adp_use_kgsbeq: impacts.adp_use_kgsbeq,
+            pe_manufacture_megajoules: impacts.pe_manufacture_megajoules,
+            pe_use_megajoules: impacts.pe_use_megajoules,
+            gwp_manufacture_kgco2eq: impacts.gwp_manufacture_kgco2eq,
+            gwp_use_kgco2eq: impacts.gwp_use_kgco2eq,
+        }
+    }
+}
+
+#[derive(Clone, Debug, SimpleObject, PartialEq)]
+pub struct Tag {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+/// A resource with its impacts (none when they cannot be estimated)
+#[derive(Clone, Debug, SimpleObject, PartialEq)]
+pub struct Resource {
+    pub id: String,
+    /// Kind of resource (like Instance or BlockStorage)
+    pub kind: String,
+    pub resource_type: Option<String>,
+    pub aws_region: String,
+    pub tags: Vec<Tag>,
+    pub impacts: Option<Impacts>,
+}
+
+impl From<&CloudResourceWithImpacts> for Resource {
+    fn from(resource: &CloudResourceWithImpacts) -> Self {
+        let cloud_resource = &resource.cloud_resource;
+        Resource {
+            id: cloud_resource.id.clone(),
+            kind: cloud_resource.resource_details.kind().to_string(),
+            resource_type: cloud_resource.resource_details.resource_type(),
+            aws_region: cloud_resource.location.aws_region.clone(),
+            tags: cloud_resource
+                .tags
+                .iter()
+                .map(|tag| Tag {
+                    key: tag.key.clone(),
+                    value: tag.value.clone(),
+                })
+                .collect(),
+            impacts: resource.impacts_values.as_ref().map(Into::into),
+        }
+    }
+}
+
+/// Aggregated impacts of the resources of the scan
+#[derive(Clone, Debug, SimpleObject, PartialEq)]
+pub struct Summary {
+    pub country: String,
+    pub duration_of_use_hours: f64,
+    pub number_of_resources_total: usize,
+    pub number_of_resources_assessed: usize,
+    pub number_of_resources_not_assessed: usize,
+    pub impacts: Impacts,
+}
+
+impl From<&ImpactsSummary> for Summary {
+    fn from(summary: &ImpactsSummary) -> Self {
+        Summary {
+            country: summary.countries(),
+            duration_of_use_hours: summary.duration_of_use_hours,
+            number_of_resources_total: summary.number_of_resources_total,
+            number_of_resources_assessed: summary.number_of_resources_assessed,
+            number_of_resources_not_assessed: summary.number_of_resources_not_assessed,
+            impacts: Impacts {
+                adp_manufacture_kgsbeq: summary.adp_manufacture_kgsbeq,
+                adp_use_kgsbeq: summary.adp_use_kgsbeq,
+                pe_manufacture_megajoules: summary.pe_manufacture_megajoules,
+                pe_use_megajoules: summary.pe_use_megajoules,
+                gwp_manufacture_kgco2eq: summary.gwp_manufacture_kgco2eq,
+                gwp_use_kgco2eq: summary.gwp_use_kgco2eq,
+            },
+        }
+    }
+}
+
+/// Aggregated impacts of the resources with a value of a tag
+#[derive(Clone, Debug, SimpleObject, PartialEq)]
+pub struct Group {
+    pub tag_key: String,
+    /// None groups the resources without the tag
+    pub tag_value: Option<String>,
+    pub number_of_resources_total: usize,
+    pub number_of_resources_assessed: usize,
+    pub impacts: Impacts,
+}
+
+impl From<&GroupSummary> for Group {
+    fn from(group: &GroupSummary) -> Self {
+        Group {
+            tag_key: group.tag_key.clone(),
+            tag_value: group.tag_value.clone(),
+            number_of_resources_total: group.number_of_resources_total,
+            number_of_resources_assessed: group.number_of_resources_assessed,
+            impacts: Impacts {
+                adp_manufacture_kgsbeq: group.adp_manufacture_kgsbeq,
+                adp_use_kgsbeq: group.adp_use_kgsbeq,
+                pe_manufacture_megajoules: group.pe_manufacture_megajoules,
+                pe_use_megajoules: group.pe_use_megajoules,
+                gwp_manufacture_kgco2eq: group.gwp_manufacture_kgco2eq,
+                gwp_use_kgco2eq: group.gwp_use_kgco2eq,
+            },
+        }
+    }
+}
+
+/// A resource of the top emitters
+#[derive(Clone, Debug, SimpleObject, PartialEq)]
+pub struct TopEmitter {
+    pub rank: usize,
+    pub resource_id: String,
+    pub resource_kind: String,
+    pub aws_region: String,
+    /// Value of the ranking criterion
+    pub value: f64,
+    pub unit: String,
+    /// Share of the value in the total of the assessed resources (between 0 and 1)
+    pub share: f64,
+}
+
+/// Returns the resources of a kind and with a tag (written as `key=value`, or `key` for any value), from the offset
+fn filter_resources(
+    estimated_inventory: &EstimatedInventory,
+    kind: Option<&str>,
+    tag: Option<&str>,
+    offset: usize,
+    limit: Option<usize>,
+) -> Vec<Resource> {
+    let tag = tag.map(|tag| match tag.split_once('=') {
+        Some((key, value)) => (key, Some(value)),
+        None => (tag, None),
+    });
+    estimated_inventory
+        .impacting_resources
+        .iter()
+        .filter(|r| kind.is_none_or(|kind| r.cloud_resource.resource_details.kind() == kind))
+        .filter(|r| {
+            tag.is_none_or(|(key, value)| {
+                r.cloud_resource.tags.iter().any(|t| {
+                    t.key == key && value.is_none_or(|value| t.value.as_deref() == Some(value))
+                })
+            })
+        })
+        .skip(offset)
+        .take(limit.unwrap_or(usize::MAX))
+        .map(Into::into)
+        .collect()
+}
+
+/// The results of the scan of a region
+pub struct Scan {
+    aws_region: String,
+    use_duration_hours: f32,
+    estimated_inventory: EstimatedInventory,
+}
+
+#[Object]
+impl Scan {
+    async fn aws_region(&self) -> &str {
+        &self.aws_region
+    }
+
+    async fn use_duration_hours(&self) -> f32 {
+        self.use_duration_hours
+    }
+
+    async fn summary(&self) -> async_graphql::Result<Summary> {
+        let summary = crate::build_summary(
+            &self.estimated_inventory,
+            &self.aws_region,
+            &self.use_duration_hours,
+        )?;
+        Ok((&summary).into())
+    }
+
+    /// Resources of the scan, optionally of a kind (like Instance) and with a tag (written as key=value, or key for any value)
+    async fn resources(
+        &self,
+        kind: Option<String>,
+        tag: Option<String>,
+        #[graphql(default)] offset: usize,
+        limit: Option<usize>,
+    ) -> Vec<Resource> {
+        filter_resources(
+            &self.estimated_inventory,
+            kind.as_deref(),
+            tag.as_deref(),
+            offset,
+            limit,
+        )
+    }
+
+    /// Impacts of the resources grouped by the values of a tag
+    async fn groups(&self, tag_key: String) -> async_graphql::Result<Vec<Group>> {
+        let summary = crate::build_summary(
+            &self.estimated_inventory,
+            &self.aws_region,
+            &self.use_duration_hours,
+        )?
+        .with_groups_by_tag(&tag_key, &self.estimated_inventory);
+        Ok(summary.groups.iter().map(Into::into).collect())
+    }
+
+    /// Resources with the highest value of a criterion (like gwp, gwp_use or pe)
+    async fn top_emitters(
+        &self,
+        #[graphql(default_with = "\"gwp\".to_string()")] criterion: String,
+        #[graphql(default = 10)] count: usize,
+    ) -> async_graphql::Result<Vec<TopEmitter>> {
+        let criterion: RankingCriterion = criterion.parse()?;
+        let top = top_emitters(&self.estimated_inventory, criterion, count);
+        Ok(top
+            .resources
+            .into_iter()
+            .map(|resource| TopEmitter {
+                rank: resource.rank,
+                resource_id: resource.resource_id,
+                resource_kind: resource.resource_kind,
+                aws_region: resource.aws_region,
+                value: resource.value,
+                unit: top.unit.clone(),
+                share: resource.share,
+            })
+            .collect())
+    }
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// Scans a region (or reuses a cached scan with the same parameters)
+    ///
+    /// Another account of the allow-list can be scanned by passing its accountId or roleArn.
+    // GraphQL passes each argument of the query as an argument
+    #[allow(clippy::too_many_arguments)]
+    async fn scan(
+        &self,
+        ctx: &Context<'_>,
+        aws_region: String,
+        #[graphql(default)] filter_tags: Vec<String>,
+        #[graphql(default_with = "1.0")] use_duration_hours: f32,
+        #[graphql(default)] include_block_storage: bool,
+        account_id: Option<String>,
+        role_arn: Option<String>,
+    ) -> async_graphql::Result<Scan> {
+        let scan_context = ctx.data::<ScanContext>()?;
+        let account = crate::tenants::select_account(
+            ctx.data_opt::<TenantConfig>(),
+            &scan_context.accounts,
+            &[&aws_region],
+            account_id.as_deref(),
+            role_arn.as_deref(),
+        )?;
+        let key = cache_key(
+            account,
+            &aws_region,
+            &filter_tags,
+            Some(use_duration_hours),
+            false,
+            include_block_storage,
+        );
+        let estimated_inventory = match scan_context.cache.get(&key) {
+            Some(estimated_inventory) => estimated_inventory,
+            None => {
+                let estimated_inventory = crate::estimate_impacts_in_account(
+                    account,
+                    &use_duration_hours,
+                    &filter_tags,
+                    &aws_region,
+                    &scan_context.boavizta_url,
+                    false,
+                    include_block_storage,
+                )
+                .await?;
+                // Partial results are not cached, the next request scans again
+                if estimated_inventory.errors.is_empty() {
+                    scan_context.cache.insert(key, estimated_inventory.clone());
+                }
+                estimated_inventory
+            }
+        };
+        Ok(Scan {
+            aws_region,
+            use_duration_hours,
+            estimated_inventory,
+        })
+    }
+}
+
+/// Returns the schema, scanning with the Boavizta API and the accounts of the server, and sharing the cache of impacts of the REST API
+pub fn schema(
+    boavizta_url: String,
+    accounts: Vec<AwsAccount>,
+    cache: ResponseCache<EstimatedInventory>,
+) -> CloudScannerSchema {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .data(ScanContext {
+            boavizta_url,
+            accounts,
+            cache,
+        })
+        .finish()
+}
+
+/// Runs a GraphQL query, with the tenant of the request
+#[rocket::post("/graphql", data = "<request>")]
+async fn graphql_query(
+    auth: Authenticated,
+    _rate: RateLimited,
+    request_id: RequestId,
+    schema: &State<CloudScannerSchema>,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    let request = match auth.tenant {
+        Some(tenant) => request.data(tenant),
+        None => request,
+    };
+    request_id.scope(request.execute(schema.inner())).await
+}
+
+/// Serves the GraphiQL IDE
+#[rocket::get("/graphql")]
+fn graphiql() -> RawHtml<String> {
+    RawHtml(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+/// Returns the routes of the GraphQL endpoint
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![graphql_query, graphiql]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{CloudProvider, CloudResource, CloudResourceTag, ResourceDetails};
+    use crate::usage_location::UsageLocation;
+
+    fn resource(id: &str, tags: &[(&str, &str)]) -> CloudResourceWithImpacts {
+        CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: id.to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details: ResourceDetails::Instance {
+                    instance_type: "m6g.xlarge".to_string(),
+                    usage: None,
+                },
+                tags: tags
+                    .iter()
+                    .map(|(key, value)| CloudResourceTag {
+                        key: key.to_string(),
+                        value: Some(value.to_string()),
+                    })
+                    .collect(),
+                relationships: Vec::new(),
+            },
+            impacts_values: None,
+            impacts_duration_hours: 1.0,
+            cost: None,
+        }
+    }
+
+    #[test]
+    fn resources_are_filtered_by_kind_and_tag() {
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![
+                resource("i-1", &[("Env", "prod")]),
+                resource("i-2", &[("Env", "dev")]),
+                resource("i-3", &[]),
+            ],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let ids = |resources: Vec<Resource>| -> Vec<String> {
+            resources.into_iter().map(|r| r.id).collect()
+        };
+        assert_eq!(
+            vec!["i-1"],
+            ids(filter_resources(
+                &estimated_inventory,
+                None,
+                Some("Env=prod"),
+                0,
+                None
+            ))
+        );
+        assert_eq!(
+            vec!["i-2"],
+            ids(filter_resources(
+                &estimated_inventory,
+                Some("Instance"),
+                Some("Env"),
+                1,
+                Some(5)
+            ))
+        );
+        assert!(
+            filter_resources(&estimated_inventory, Some("BlockStorage"), None, 0, None).is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn schema_exposes_scans_with_aggregations() {
+        let schema = schema(
+            "http://localhost:5000".to_string(),
+            Vec::new(),
+            ResponseCache::new(std::time::Duration::ZERO),
+        );
+        let sdl = schema.sdl();
+        assert!(sdl.contains("topEmitters(criterion: String! = \"gwp\", count: Int! = 10)"));
+        assert!(sdl.contains("groups(tagKey: String!): [Group!]!"));
+
+        let response = schema
+            .execute(r#"{ scan(awsRegion: "eu-west-3", accountId: "123456789012") { awsRegion } }"#)
+            .await;
+        assert_eq!(1, response.errors.len());
+    }
+}
@@ -0,0 +1,380 @@
+//! gRPC API of the standalone server (see `proto/cloud_scanner.proto`), for internal platforms that standardize on gRPC.
+//!
+//! It mirrors the inventory, impacts and scan job endpoints of the REST API, with resources streamed one by one. Clients authenticate with the same API keys or tokens, passed in the `authorization` (bearer) or `x-api-key` metadata.
+// tonic::Status is the error of every gRPC method
+#![allow(clippy::result_large_err)]
+use anyhow::Context;
+use rocket::futures::stream::{self, BoxStream, StreamExt};
+use std::net::SocketAddr;
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Response, Status};
+
+use crate::aws_cloud_provider::AwsAccount;
+use crate::graceful_shutdown::InFlightScans;
+use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+use crate::model::CloudResource;
+use crate::scan_jobs::{Job, JobStatus, JobStore, ScanRequest};
+use crate::server_auth::{self, Authenticator};
+use crate::tenants::TenantConfig;
+
+/// Code generated from the protobuf definitions
+pub mod proto {
+    tonic::include_proto!("cloud_scanner.v1");
+}
+
+use proto::cloud_scanner_server::{CloudScanner, CloudScannerServer};
+
+impl From<&CloudResource> for proto::Resource {
+    fn from(resource: &CloudResource) -> Self {
+        proto::Resource {
+            provider: format!("{:?}", resource.provider),
+            id: resource.id.clone(),
+            aws_region: resource.location.aws_region.clone(),
+            kind: resource.resource_details.kind().to_string(),
+            resource_type: resource.resource_details.resource_type(),
+            details_json: serde_json::to_string(&resource.resource_details).unwrap_or_default(),
+            tags: resource
+                .tags
+                .iter()
+                .map(|tag| proto::Tag {
+                    key: tag.key.clone(),
+                    value: tag.value.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&ImpactsValues> for proto::Impacts {
+    fn from(impacts: &ImpactsValues) -> Self {
+        proto::Impacts {
+            adp_manufacture_kgsbeq: impacts.adp_manufacture_kgsbeq,
+            adp_use_kgsbeq: impacts.adp_use_kgsbeq,
+            pe_manufacture_megajoules: impacts.pe_manufacture_megajoules,
+            pe_use_megajoules: impacts.pe_use_megajoules,
+            gwp_manufacture_kgco2eq: impacts.gwp_manufacture_kgco2eq,
+            gwp_use_kgco2eq: impacts.gwp_use_kgco2eq,
+        }
+    }
+}
+
+impl From<&CloudResourceWithImpacts> for proto::ResourceImpacts {
+    fn from(resource: &CloudResourceWithImpacts) -> Self {
+        proto::ResourceImpacts {
+            resource: Some((&resource.cloud_resource).into()),
+            impacts: resource.impacts_values.as_ref().map(Into::into),
+            impacts_duration_hours: resource.impacts_duration_hours,
+        }
+    }
+}
+
+impl From<&Job> for proto::Job {
+    fn from(job: &Job) -> Self {
+        let status = match job.status {
+            JobStatus::Pending => proto::JobStatus::Pending,
+            JobStatus::Running => proto::JobStatus::Running,
+            JobStatus::Completed => proto::JobStatus::Completed,
+            JobStatus::Failed => proto::JobStatus::Failed,
+        };
+        proto::Job {
+            id: job.id.clone(),
+            status: status.into(),
+            progress: job.progress.clone(),
+            resources_count: job.resources_count.map(|count| count as u64),
+            resources_estimated: job.resources_estimated.map(|count| count as u64),
+            submitted_at: job.submitted_at.to_rfc3339(),
+            started_at: job.started_at.map(|date| date.to_rfc3339()),
+            finished_at: job.finished_at.map(|date| date.to_rfc3339()),
+            error: job.error.clone(),
+        }
+    }
+}
+
+fn internal_error(e: anyhow::Error) -> Status {
+    error!("gRPC scan failed: {:#}", e);
+    Status::internal(format!("{:#}", e))
+}
+
+/// The gRPC service, sharing the jobs and the authentication of the REST API
+pub struct GrpcService {
+    boavizta_url: String,
+    accounts: Vec<AwsAccount>,
+    authenticator: Authenticator,
+    jobs: JobStore,
+    in_flight: InFlightScans,
+}
+
+impl GrpcService {
+    pub fn new(
+        boavizta_url: String,
+        accounts: Vec<AwsAccount>,
+        authenticator: Authenticator,
+        jobs: JobStore,
+        in_flight: InFlightScans,
+    ) -> Self {
+        GrpcService {
+            boavizta_url,
+            accounts,
+            authenticator,
+            jobs,
+            in_flight,
+        }
+    }
+
+    /// Returns the tenant of a request (none when authentication is disabled or for the keys of the server)
+    async fn authenticate(&self, metadata: &MetadataMap) -> Result<Option<TenantConfig>, Status> {
+        if !self.authenticator.is_enabled() {
+            return Ok(None);
+        }
+        let header = |name: &str| metadata.get(name).and_then(|value| value.to_str().ok());
+        let Some(token) = server_auth::request_token(header("authorization"), header("x-api-key"))
+        else {
+            return Err(Status::unauthenticated("Missing API key or bearer token"));
+        };
+        self.authenticator.authenticate(token).await.map_err(|e| {
+            warn!("Rejecting gRPC request: {:#}", e);
+            Status::unauthenticated(e.to_string())
+        })
+    }
+
+    /// Returns the account selected by a request, like [crate::standalone_server::selected_account]
+    fn scan_account(
+        &self,
+        tenant: Option<&TenantConfig>,
+        aws_region: &str,
+        account_id: Option<&str>,
+        role_arn: Option<&str>,
+    ) -> Result<Option<AwsAccount>, Status> {
+        crate::tenants::select_account(tenant, &self.accounts, &[aws_region], account_id, role_arn)
+            .map(|account| account.cloned())
+            .map_err(|e| {
+                warn!("Rejecting gRPC request: {:#}", e);
+                Status::permission_denied(e.to_string())
+            })
+    }
+}
+
+#[tonic::async_trait]
+impl CloudScanner for GrpcService {
+    type GetInventoryStream = BoxStream<'static, Result<proto::Resource, Status>>;
+    type GetImpactsStream = BoxStream<'static, Result<proto::ResourceImpacts, Status>>;
+    type WatchJobStream = BoxStream<'static, Result<proto::Job, Status>>;
+
+    async fn get_inventory(
+        &self,
+        request: Request<proto::InventoryRequest>,
+    ) -> Result<Response<Self::GetInventoryStream>, Status> {
+        let tenant = self.authenticate(request.metadata()).await?;
+        let request = request.into_inner();
+        let account = self.scan_account(
+            tenant.as_ref(),
+            &request.aws_region,
+            request.account_id.as_deref(),
+            request.role_arn.as_deref(),
+        )?;
+        let inventory = crate::get_inventory_in_account(
+            account.as_ref(),
+            &request.filter_tags,
+            &request.aws_region,
+            request.include_block_storage,
+        )
+        .await
+        .map_err(internal_error)?;
+        let resources: Vec<_> = inventory
+            .resources
+            .iter()
+            .map(|resource| Ok(resource.into()))
+            .collect();
+        Ok(Response::new(stream::iter(resources).boxed()))
+    }
+
+    async fn get_impacts(
+        &self,
+        request: Request<proto::ImpactsRequest>,
+    ) -> Result<Response<Self::GetImpactsStream>, Status> {
+        let tenant = self.authenticate(request.metadata()).await?;
+        let request = request.into_inner();
+        let account = self.scan_account(
+            tenant.as_ref(),
+            &request.aws_region,
+            request.account_id.as_deref(),
+            request.role_arn.as_deref(),
+        )?;
+        let estimated_inventory = crate::estimate_impacts_in_account(
+            account.as_ref(),
+            &request.use_duration_hours.unwrap_or(1.0),
+            &request.filter_tags,
+            &request.aws_region,
+            &self.boavizta_url,
+            request.verbose_output,
+            request.include_block_storage,
+        )
+        .await
+        .map_err(internal_error)?;
+        let resources: Vec<_> = estimated_inventory
+            .impacting_resources
+            .iter()
+            .map(|resource| Ok(resource.into()))
+            .collect();
+        Ok(Response::new(stream::iter(resources).boxed()))
+    }
+
+    async fn submit_scan(
+        &self,
+        request: Request<proto::ImpactsRequest>,
+    ) -> Result<Response<proto::Job>, Status> {
+        let tenant = self.authenticate(request.metadata()).await?;
+        let request = request.into_inner();
+        let account = self.scan_account(
+            tenant.as_ref(),
+            &request.aws_region,
+            request.account_id.as_deref(),
+            request.role_arn.as_deref(),
+        )?;
+        let scan_request = ScanRequest {
+            aws_region: request.aws_region,
+            filter_tags: request.filter_tags,
+            use_duration_hours: request.use_duration_hours.unwrap_or(1.0),
+            verbose_output: request.verbose_output,
+            include_block_storage: request.include_block_storage,
+            account_id: request.account_id,
+            role_arn: request.role_arn,
+            callback_url: None,
+        };
+        let job = self
+            .jobs
+            .submit(scan_request, tenant.map(|tenant| tenant.name));
+        warn!("Submitted scan job {} with gRPC", job.id);
+        let jobs = self.jobs.clone();
+        let id = job.id.clone();
+        let api_url = self.boavizta_url.clone();
+        let scan = self.in_flight.start();
+        tokio::spawn(async move {
+            let _scan = scan;
+            jobs.run(&id, &api_url, account.as_ref()).await
+        });
+        Ok(Response::new((&job).into()))
+    }
+
+    async fn watch_job(
+        &self,
+        request: Request<proto::JobRequest>,
+    ) -> Result<Response<Self::WatchJobStream>, Status> {
+        let tenant = self.authenticate(request.metadata()).await?;
+        let id = request.into_inner().id;
+        let tenant_name = tenant.as_ref().map(|tenant| tenant.name.as_str());
+        let changes = self
+            .jobs
+            .subscribe(&id)
+            .filter(|changes| changes.borrow().is_visible_to(tenant_name))
+            .ok_or_else(|| Status::not_found(format!("No job with id {}", id)))?;
+        // The state is the receiver of changes (none after the last job), and whether the current job is already sent
+        let jobs = stream::unfold((Some(changes), false), |(changes, sent)| async move {
+            let mut changes = changes?;
+            if sent && changes.changed().await.is_err() {
+                return None;
+            }
+            let job = changes.borrow_and_update().clone();
+            let finished = matches!(job.status, JobStatus::Completed | JobStatus::Failed);
+            let next = if finished { None } else { Some(changes) };
+            Some((Ok(proto::Job::from(&job)), (next, true)))
+        });
+        Ok(Response::new(jobs.boxed()))
+    }
+}
+
+/// Serves the gRPC API at the address (plain HTTP/2)
+pub async fn serve(service: GrpcService, address: SocketAddr) -> anyhow::Result<()> {
+    info!("Serving gRPC API on {}", address);
+    tonic::transport::Server::builder()
+        .add_service(CloudScannerServer::new(service))
+        .serve(address)
+        .await
+        .with_context(|| format!("Cannot serve gRPC API on {}", address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{CloudProvider, CloudResourceTag, ResourceDetails};
+    use crate::usage_location::UsageLocation;
+
+    #[test]
+    fn resources_are_converted_to_protobuf() {
+        let resource = CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: "i-1".to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details: ResourceDetails::Instance {
+                    instance_type: "m6g.xlarge".to_string(),
+                    usage: None,
+                },
+                tags: vec![CloudResourceTag {
+                    key: "Env".to_string(),
+                    value: Some("prod".to_string()),
+                }],
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues {
+                gwp_use_kgco2eq: 0.5,
+                ..Default::default()
+            }),
+            impacts_duration_hours: 1.0,
+            cost: None,
+        };
+        let converted = proto::ResourceImpacts::from(&resource);
+        let converted_resource = converted.resource.unwrap();
+        assert_eq!("AWS", converted_resource.provider);
+        assert_eq!("Instance", converted_resource.kind);
+        assert_eq!(
+            Some("m6g.xlarge".to_string()),
+            converted_resource.resource_type
+        );
+        assert_eq!("Env", converted_resource.tags[0].key);
+        assert_eq!(0.5, converted.impacts.unwrap().gwp_use_kgco2eq);
+    }
+
+    #[tokio::test]
+    async fn requests_are_authenticated_and_restricted_to_allowed_accounts() {
+        let authenticator = Authenticator::new(
+            &server_auth::AuthConfig {
+                api_keys: vec!["key-1".to_string()],
+                oidc: None,
+            },
+            &[],
+        )
+        .await
+        .unwrap();
+        let jobs = JobStore::default();
+        let service = GrpcService::new(
+            "http://localhost:5000".to_string(),
+            Vec::new(),
+            authenticator,
+            jobs.clone(),
+            InFlightScans::default(),
+        );
+        let mut metadata = MetadataMap::new();
+        assert_eq!(
+            tonic::Code::Unauthenticated,
+            service.authenticate(&metadata).await.unwrap_err().code()
+        );
+        metadata.insert("x-api-key", "key-1".parse().unwrap());
+        assert_eq!(None, service.authenticate(&metadata).await.unwrap());
+
+        let denied = service
+            .scan_account(None, "eu-west-3", Some("123456789012"), None)
+            .unwrap_err();
+        assert_eq!(tonic::Code::PermissionDenied, denied.code());
+
+        let job = jobs.submit(
+            serde_json::from_str(r#"{"aws_region": "eu-west-3"}"#).unwrap(),
+            Some("team-a".to_string()),
+        );
+        let mut request = Request::new(proto::JobRequest { id: job.id });
+        *request.metadata_mut() = metadata;
+        let hidden = service.watch_job(request).await.err().unwrap();
+        assert_eq!(tonic::Code::NotFound, hidden.code());
+    }
+}
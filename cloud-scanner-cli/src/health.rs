@@ -0,0 +1,125 @@
+//! Health (liveness) and readiness checks of the standalone server, for Kubernetes probes and uptime monitoring.
+//!
+//! Readiness checks that the dependencies of a scan are usable: the cloud credentials and the Boavizta API.
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::aws_cloud_provider::AwsCloudProvider;
+
+/// Maximum duration of a check, a dependency that does not answer in time is not ready
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Ok,
+    Failed,
+}
+
+/// The result of the check of a dependency
+#[derive(Clone, Debug, Serialize, JsonSchema, PartialEq)]
+pub struct DependencyCheck {
+    pub name: String,
+    pub status: CheckStatus,
+    /// Version of the dependency, or cause of the failure
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+    pub duration_ms: u128,
+}
+
+/// Status of the server, failed when any of its checks failed
+#[derive(Clone, Debug, Serialize, JsonSchema, PartialEq)]
+pub struct HealthReport {
+    pub status: CheckStatus,
+    pub version: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub checks: Vec<DependencyCheck>,
+}
+
+impl HealthReport {
+    fn new(checks: Vec<DependencyCheck>) -> Self {
+        let status = if checks.iter().all(|check| check.status == CheckStatus::Ok) {
+            CheckStatus::Ok
+        } else {
+            CheckStatus::Failed
+        };
+        HealthReport {
+            status,
+            version: crate::get_version(),
+            checks,
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == CheckStatus::Ok
+    }
+}
+
+/// Runs a check within [CHECK_TIMEOUT], the check returns optional details when it succeeds
+async fn run_check(
+    name: &str,
+    check: impl Future<Output = Result<Option<String>>>,
+) -> DependencyCheck {
+    let start = Instant::now();
+    let result = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow::anyhow!("No answer within {:?}", CHECK_TIMEOUT)),
+    };
+    let (status, details) = match result {
+        Ok(details) => (CheckStatus::Ok, details),
+        Err(e) => {
+            warn!("Readiness check of {} failed: {:#}", name, e);
+            (CheckStatus::Failed, Some(format!("{:#}", e)))
+        }
+    };
+    DependencyCheck {
+        name: name.to_string(),
+        status,
+        details,
+        duration_ms: start.elapsed().as_millis(),
+    }
+}
+
+/// Returns the liveness of the server (no dependency is checked)
+pub fn liveness() -> HealthReport {
+    HealthReport::new(Vec::new())
+}
+
+/// Checks the cloud credentials and the Boavizta API (concurrently)
+pub async fn readiness(api_url: &str) -> HealthReport {
+    let (aws, boavizta) = tokio::join!(
+        run_check("aws", async {
+            AwsCloudProvider::check_credentials().await?;
+            Ok(None)
+        }),
+        run_check("boavizta_api", async {
+            match crate::boavizta_api_v1::get_api_version(api_url).await {
+                Some(version) => Ok(Some(version)),
+                None => anyhow::bail!("Cannot reach Boavizta API at {}", api_url),
+            }
+        })
+    );
+    HealthReport::new(vec![aws, boavizta])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn report_fails_when_a_check_fails() {
+        let ok = run_check("ok", async { Ok(Some("1.0".to_string())) }).await;
+        assert_eq!(CheckStatus::Ok, ok.status);
+        assert_eq!(Some("1.0".to_string()), ok.details);
+        let failed = run_check("failed", async { anyhow::bail!("Unreachable") }).await;
+        assert_eq!(Some("Unreachable".to_string()), failed.details);
+
+        assert!(HealthReport::new(vec![ok.clone()]).is_ok());
+        let report = HealthReport::new(vec![ok, failed]);
+        assert_eq!(CheckStatus::Failed, report.status);
+        assert!(liveness().is_ok());
+    }
+}
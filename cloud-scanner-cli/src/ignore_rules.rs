@@ -0,0 +1,180 @@
+//! Rules of an ignore file (like `.cloudscannerignore`) excluding known exceptions, like sandbox resources, from the estimation of impacts.
+//!
+//! Each line of the file is a rule (blank lines and lines starting with `#` are skipped):
+//! - `i-0123456789abcdef0` or `vol-*`: the id of the resource matches a pattern, where `*` matches any characters and `?` one character
+//! - `name:sandbox-*`: the `Name` tag of the resource matches a pattern
+//! - `tag:env=sandbox OR team~lab-*`: the tags of the resource match a filter expression (see [crate::tag_filter])
+//!
+//! Resources matching any rule are excluded.
+use anyhow::{Context, Result};
+use std::path::Path;
+
+use crate::model::{CloudResource, Inventory};
+use crate::tag_filter::{glob_matches, TagFilter};
+
+/// Ignore file used when none is passed, if it exists in the working directory
+pub const DEFAULT_IGNORE_FILE: &str = ".cloudscannerignore";
+
+/// A rule of an ignore file
+#[derive(Clone, Debug, PartialEq)]
+pub enum IgnoreRule {
+    /// Pattern on the id of resources
+    Id(String),
+    /// Pattern on the `Name` tag of resources
+    Name(String),
+    /// Filter expression on the tags of resources
+    Tags(TagFilter),
+}
+
+/// The rules of an ignore file (none excludes no resource)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IgnoreRules {
+    pub rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreRules {
+    /// Parses the content of an ignore file
+    pub fn parse(content: &str) -> Result<IgnoreRules> {
+        let mut rules = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = if let Some(pattern) = line.strip_prefix("name:") {
+                IgnoreRule::Name(pattern.trim().to_string())
+            } else if let Some(expression) = line.strip_prefix("tag:") {
+                IgnoreRule::Tags(
+                    TagFilter::parse(expression.trim())
+                        .with_context(|| format!("Invalid rule at line {}", number + 1))?,
+                )
+            } else {
+                IgnoreRule::Id(line.to_string())
+            };
+            rules.push(rule);
+        }
+        Ok(IgnoreRules { rules })
+    }
+
+    /// Reads the rules of an ignore file
+    pub fn load(path: &str) -> Result<IgnoreRules> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read ignore file {}", path))?;
+        IgnoreRules::parse(&content).with_context(|| format!("Invalid ignore file {}", path))
+    }
+
+    /// Reads the rules of the ignore file passed, or of the default ignore file if it exists (no rules otherwise)
+    pub fn load_or_default(path: Option<&str>) -> Result<IgnoreRules> {
+        match path {
+            Some(path) => IgnoreRules::load(path),
+            None if Path::new(DEFAULT_IGNORE_FILE).exists() => {
+                info!("Using ignore file {}", DEFAULT_IGNORE_FILE);
+                IgnoreRules::load(DEFAULT_IGNORE_FILE)
+            }
+            None => Ok(IgnoreRules::default()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns true when a resource matches a rule
+    pub fn matches(&self, resource: &CloudResource) -> bool {
+        self.rules.iter().any(|rule| match rule {
+            IgnoreRule::Id(pattern) => glob_matches(pattern, &resource.id),
+            IgnoreRule::Name(pattern) => resource
+                .tags
+                .iter()
+                .find(|tag| tag.key == "Name")
+                .is_some_and(|tag| glob_matches(pattern, tag.value.as_deref().unwrap_or_default())),
+            IgnoreRule::Tags(filter) => filter.matches(&resource.tags),
+        })
+    }
+
+    /// Removes the resources matching a rule from an inventory, returning the remaining inventory and the number of excluded resources
+    pub fn exclude(&self, inventory: Inventory) -> (Inventory, usize) {
+        if self.is_empty() {
+            return (inventory, 0);
+        }
+        let total = inventory.resources.len();
+        let resources: Vec<CloudResource> = inventory
+            .resources
+            .into_iter()
+            .filter(|resource| {
+                let ignored = self.matches(resource);
+                if ignored {
+                    debug!("Excluding resource {} (ignore file)", resource.id);
+                }
+                !ignored
+            })
+            .collect();
+        let excluded = total - resources.len();
+        (
+            Inventory {
+                resources,
+                execution_statistics: inventory.execution_statistics,
+            },
+            excluded,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{CloudProvider, CloudResourceTag, ResourceDetails};
+    use crate::usage_location::UsageLocation;
+
+    fn resource(id: &str, tags: &[(&str, &str)]) -> CloudResource {
+        CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: None,
+            id: id.to_string(),
+            location: UsageLocation::try_from("eu-west-3").unwrap(),
+            resource_details: ResourceDetails::Instance {
+                instance_type: "m5.large".to_string(),
+                usage: None,
+            },
+            tags: tags
+                .iter()
+                .map(|(key, value)| CloudResourceTag {
+                    key: key.to_string(),
+                    value: Some(value.to_string()),
+                })
+                .collect(),
+            relationships: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn resources_matching_a_rule_are_excluded() {
+        let rules = IgnoreRules::parse(
+            "# Known exceptions\n\ni-0sandbox*\nname:lab-?\ntag:env=sandbox OR team~poc-*\n",
+        )
+        .unwrap();
+        assert_eq!(3, rules.rules.len());
+        let inventory = Inventory {
+            resources: vec![
+                resource("i-0sandbox1", &[]),
+                resource("i-1", &[("Name", "lab-1")]),
+                resource("i-2", &[("Name", "lab-10")]),
+                resource("i-3", &[("env", "sandbox")]),
+                resource("i-4", &[("env", "prod"), ("team", "poc-42")]),
+                resource("i-5", &[("env", "prod")]),
+            ],
+            execution_statistics: None,
+        };
+        let (inventory, excluded) = rules.exclude(inventory);
+        assert_eq!(4, excluded);
+        let ids: Vec<&str> = inventory.resources.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(vec!["i-2", "i-5"], ids);
+    }
+
+    #[test]
+    fn invalid_rules_are_reported_with_their_line() {
+        let error = IgnoreRules::parse("i-1\ntag:env=prod AND\n").unwrap_err();
+        assert!(format!("{:#}", error).contains("line 2"), "{:#}", error);
+        assert!(IgnoreRules::load_or_default(None).unwrap().is_empty());
+    }
+}
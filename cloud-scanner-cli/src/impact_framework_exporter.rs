@@ -0,0 +1,227 @@
+//! A module to format an estimated inventory as a [Green Software Foundation Impact Framework](https://if.greensoftware.foundation/) (IF) manifest.
+//!
+//! Each assessed resource becomes a component of the manifest tree, with a single observation (input) holding its usage and its carbon emissions (`carbon-embodied` and `carbon-operational`, in gCO2eq).
+//! A `sum` pipeline computes the total `carbon` of each component when the manifest is run with `if-run`, and the manifest can be extended with other IF plugins.
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::model::{EstimatedInventory, ResourceDetails};
+
+/// Name of the plugin that sums operational and embodied carbon
+const SUM_CARBON_PLUGIN: &str = "sum-carbon";
+
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    pub name: String,
+    pub description: String,
+    pub initialize: Initialize,
+    pub tree: Tree,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Initialize {
+    pub plugins: BTreeMap<String, Plugin>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Plugin {
+    pub method: String,
+    pub path: String,
+    pub config: PluginConfig,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PluginConfig {
+    pub input_parameters: Vec<String>,
+    pub output_parameter: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Tree {
+    pub children: BTreeMap<String, Component>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Component {
+    pub pipeline: Pipeline,
+    pub inputs: Vec<Observation>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Pipeline {
+    pub compute: Vec<String>,
+}
+
+/// An observation of a resource, using the parameter names of IF
+#[derive(Debug, Serialize)]
+pub struct Observation {
+    /// Start of the observation (RFC 3339)
+    pub timestamp: String,
+    /// Duration of the observation in seconds
+    pub duration: f64,
+    #[serde(rename = "cloud/vendor")]
+    pub cloud_vendor: String,
+    #[serde(rename = "cloud/region")]
+    pub cloud_region: String,
+    #[serde(
+        rename = "cloud/instance-type",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cloud_instance_type: Option<String>,
+    #[serde(rename = "cpu/utilization", skip_serializing_if = "Option::is_none")]
+    pub cpu_utilization: Option<f64>,
+    /// Embodied emissions (gCO2eq)
+    #[serde(rename = "carbon-embodied")]
+    pub carbon_embodied: f64,
+    /// Operational emissions (gCO2eq)
+    #[serde(rename = "carbon-operational")]
+    pub carbon_operational: f64,
+}
+
+/// Returns an Impact Framework manifest describing the resources assessed in an estimated inventory.
+///
+/// `timestamp` is the start of the observations. Resources without impacts are not included.
+pub fn build_manifest(
+    timestamp: DateTime<Utc>,
+    estimated_inventory: &EstimatedInventory,
+) -> Manifest {
+    let mut plugins = BTreeMap::new();
+    plugins.insert(
+        SUM_CARBON_PLUGIN.to_string(),
+        Plugin {
+            method: "Sum".to_string(),
+            path: "builtin".to_string(),
+            config: PluginConfig {
+                input_parameters: vec![
+                    "carbon-operational".to_string(),
+                    "carbon-embodied".to_string(),
+                ],
+                output_parameter: "carbon".to_string(),
+            },
+        },
+    );
+
+    let timestamp = timestamp.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let mut children = BTreeMap::new();
+    for resource in estimated_inventory.impacting_resources.iter() {
+        let Some(impacts) = &resource.impacts_values else {
+            continue;
+        };
+        let cloud_resource = &resource.cloud_resource;
+        let (cloud_instance_type, cpu_utilization) = match &cloud_resource.resource_details {
+            ResourceDetails::Instance {
+                instance_type,
+                usage,
+            } => (
+                Some(instance_type.clone()),
+                usage.as_ref().map(|u| u.average_cpu_load),
+            ),
+            _ => (None, None),
+        };
+        let observation = Observation {
+            timestamp: timestamp.clone(),
+            duration: f64::from(resource.impacts_duration_hours) * 3600.0,
+            cloud_vendor: "aws".to_string(),
+            cloud_region: cloud_resource.location.aws_region.clone(),
+            cloud_instance_type,
+            cpu_utilization,
+            carbon_embodied: impacts.gwp_manufacture_kgco2eq * 1000.0,
+            carbon_operational: impacts.gwp_use_kgco2eq * 1000.0,
+        };
+        children.insert(
+            cloud_resource.id.clone(),
+            Component {
+                pipeline: Pipeline {
+                    compute: vec![SUM_CARBON_PLUGIN.to_string()],
+                },
+                inputs: vec![observation],
+            },
+        );
+    }
+
+    Manifest {
+        name: "cloud-scanner".to_string(),
+        description: "Impacts of cloud resources estimated by cloud scanner with Boavizta API"
+            .to_string(),
+        initialize: Initialize { plugins },
+        tree: Tree { children },
+    }
+}
+
+/// Returns an Impact Framework manifest (yaml) describing the resources assessed in an estimated inventory
+pub fn get_manifest_yaml(
+    timestamp: DateTime<Utc>,
+    estimated_inventory: &EstimatedInventory,
+) -> Result<String> {
+    let manifest = build_manifest(timestamp, estimated_inventory);
+    serde_yaml::to_string(&manifest).context("Cannot format Impact Framework manifest")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+    use crate::model::{CloudProvider, CloudResource, InstanceState, InstanceUsage};
+    use crate::usage_location::UsageLocation;
+    use chrono::TimeZone;
+
+    #[test]
+    fn format_inventory_as_manifest() {
+        let instance = CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: None,
+            id: "inst-1".to_string(),
+            location: UsageLocation::try_from("eu-west-1").unwrap(),
+            resource_details: ResourceDetails::Instance {
+                instance_type: "m6g.xlarge".to_string(),
+                usage: Some(InstanceUsage {
+                    average_cpu_load: 42.0,
+                    usage_duration_seconds: 3600,
+                    state: InstanceState::Running,
+                }),
+            },
+            tags: Vec::new(),
+            relationships: Vec::new(),
+        };
+        let mut not_assessed = instance.clone();
+        not_assessed.id = "inst-2".to_string();
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![
+                CloudResourceWithImpacts {
+                    cloud_resource: instance,
+                    impacts_values: Some(ImpactsValues {
+                        gwp_manufacture_kgco2eq: 0.5,
+                        gwp_use_kgco2eq: 0.25,
+                        ..Default::default()
+                    }),
+                    impacts_duration_hours: 2.0,
+                    cost: None,
+                },
+                CloudResourceWithImpacts {
+                    cloud_resource: not_assessed,
+                    impacts_values: None,
+                    impacts_duration_hours: 2.0,
+                    cost: None,
+                },
+            ],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let timestamp = Utc.with_ymd_and_hms(2024, 4, 12, 10, 15, 0).unwrap();
+
+        let manifest = build_manifest(timestamp, &estimated_inventory);
+        assert_eq!(1, manifest.tree.children.len());
+        let observation = &manifest.tree.children["inst-1"].inputs[0];
+        assert_eq!(7200.0, observation.duration);
+        assert_eq!(500.0, observation.carbon_embodied);
+        assert_eq!(250.0, observation.carbon_operational);
+
+        let yaml = get_manifest_yaml(timestamp, &estimated_inventory).unwrap();
+        assert!(yaml.contains("input-parameters:"));
+        assert!(yaml.contains("cloud/instance-type: m6g.xlarge"));
+        assert!(yaml.contains("timestamp: 2024-04-12T10:15:00Z"));
+    }
+}
@@ -1,10 +1,14 @@
 //! A module to abstract the service used to retrieve impacts of cloud resources.
+use crate::allocation::AllocationRules;
+use crate::kubernetes_workloads::{KubernetesAllocation, KubernetesCluster};
 use crate::model::{CloudResource, EstimatedInventory, Inventory};
+use crate::pricing::ResourceCost;
+use crate::supported_types::SupportedTypes;
 use anyhow::Result;
 use async_trait::async_trait;
-use rocket_okapi::okapi::schemars;
-use rocket_okapi::okapi::schemars::JsonSchema;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 /// A ImpactProvider trait to implement for a specific impact API/Referential.
 #[async_trait]
@@ -17,6 +21,9 @@ pub trait ImpactProvider {
         usage_duration_hours: &f32,
         verbose: bool,
     ) -> Result<EstimatedInventory>;
+
+    /// Returns the types of resources (like instance types) known by the provider
+    async fn supported_types(&self) -> Result<SupportedTypes>;
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
@@ -26,6 +33,9 @@ pub struct CloudResourceWithImpacts {
     pub impacts_values: Option<ImpactsValues>,
     /// The duration for which impacts are calculated
     pub impacts_duration_hours: f32,
+    /// Approximate cost over the duration, only set when a price file is used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<ResourceCost>,
 }
 
 // TODO: shouldn't theses fields be optional ?
@@ -47,6 +57,9 @@ pub struct ImpactsSummary {
     pub number_of_resources_total: usize,
     pub number_of_resources_assessed: usize,
     pub number_of_resources_not_assessed: usize,
+    /// Number of resources excluded by the ignore file (not part of the total), only set when an ignore file is used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub number_of_resources_excluded: Option<usize>,
     pub duration_of_use_hours: f64,
     pub adp_manufacture_kgsbeq: f64,
     pub adp_use_kgsbeq: f64,
@@ -54,26 +67,277 @@ pub struct ImpactsSummary {
     pub pe_use_megajoules: f64,
     pub gwp_manufacture_kgco2eq: f64,
     pub gwp_use_kgco2eq: f64,
+    /// Sub-summaries per region (and account) of the resources
+    pub regions: Vec<RegionSummary>,
+    /// Software Carbon Intensity, only computed when a functional unit is provided
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sci: Option<SciScore>,
+    /// Sub-summaries per value of a tag, only computed when a grouping tag is provided
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<GroupSummary>,
+    /// Impacts of the resources rolled up to their parents (instance, auto scaling group or cluster), only computed when rolling up is requested
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parents: Vec<ParentSummary>,
+    /// Approximate cost of the resources, only computed when a price file is used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<CostSummary>,
+    /// Impacts of the nodes of a Kubernetes cluster allocated to its namespaces and workloads, only computed when the workloads of a cluster are provided
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kubernetes: Option<KubernetesAllocation>,
+}
+
+/// The approximate cost of the resources, compared to their emissions
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct CostSummary {
+    pub currency: String,
+    /// Cost of the resources with a price, over the duration of use
+    pub total_cost: f64,
+    pub number_of_resources_priced: usize,
+    /// GWP (manufacture and use) of the resources with a price, per unit of currency spent (none when the total cost is 0)
+    pub gwp_kgco2eq_per_currency_unit: Option<f64>,
+}
+
+/// The aggregated impacts of the resources of a region (and account)
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct RegionSummary {
+    /// Account of the resources, only known for multi-account scans
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_id: Option<String>,
     pub aws_region: String,
     pub country: String,
+    pub number_of_resources_total: usize,
+    pub number_of_resources_assessed: usize,
+    pub number_of_resources_not_assessed: usize,
+    pub adp_manufacture_kgsbeq: f64,
+    pub adp_use_kgsbeq: f64,
+    pub pe_manufacture_megajoules: f64,
+    pub pe_use_megajoules: f64,
+    pub gwp_manufacture_kgco2eq: f64,
+    pub gwp_use_kgco2eq: f64,
+}
+
+impl RegionSummary {
+    /// Returns the empty sub-summary of a region
+    pub fn new(account_id: Option<String>, aws_region: String, country: String) -> Self {
+        RegionSummary {
+            account_id,
+            aws_region,
+            country,
+            number_of_resources_total: 0,
+            number_of_resources_assessed: 0,
+            number_of_resources_not_assessed: 0,
+            adp_manufacture_kgsbeq: 0.0,
+            adp_use_kgsbeq: 0.0,
+            pe_manufacture_megajoules: 0.0,
+            pe_use_megajoules: 0.0,
+            gwp_manufacture_kgco2eq: 0.0,
+            gwp_use_kgco2eq: 0.0,
+        }
+    }
+
+    fn add(&mut self, resource: &CloudResourceWithImpacts) {
+        self.number_of_resources_total += 1;
+        if let Some(impacts) = &resource.impacts_values {
+            self.number_of_resources_assessed += 1;
+            self.adp_manufacture_kgsbeq += impacts.adp_manufacture_kgsbeq;
+            self.adp_use_kgsbeq += impacts.adp_use_kgsbeq;
+            self.pe_manufacture_megajoules += impacts.pe_manufacture_megajoules;
+            self.pe_use_megajoules += impacts.pe_use_megajoules;
+            self.gwp_manufacture_kgco2eq += impacts.gwp_manufacture_kgco2eq;
+            self.gwp_use_kgco2eq += impacts.gwp_use_kgco2eq;
+        } else {
+            self.number_of_resources_not_assessed += 1;
+        }
+    }
+
+    /// Adds the resources and impacts of another sub-summary of the same region
+    fn merge(&mut self, other: &RegionSummary) {
+        self.number_of_resources_total += other.number_of_resources_total;
+        self.number_of_resources_assessed += other.number_of_resources_assessed;
+        self.number_of_resources_not_assessed += other.number_of_resources_not_assessed;
+        self.adp_manufacture_kgsbeq += other.adp_manufacture_kgsbeq;
+        self.adp_use_kgsbeq += other.adp_use_kgsbeq;
+        self.pe_manufacture_megajoules += other.pe_manufacture_megajoules;
+        self.pe_use_megajoules += other.pe_use_megajoules;
+        self.gwp_manufacture_kgco2eq += other.gwp_manufacture_kgco2eq;
+        self.gwp_use_kgco2eq += other.gwp_use_kgco2eq;
+    }
+}
+
+/// The aggregated impacts of the resources sharing the same value of a tag
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct GroupSummary {
+    pub tag_key: String,
+    /// Value of the tag, `None` groups the resources that do not have the tag (or have no value for it)
+    pub tag_value: Option<String>,
+    pub number_of_resources_total: usize,
+    pub number_of_resources_assessed: usize,
+    /// Share of the resources of the group that were assessed (between 0 and 1)
+    pub assessed_share: f64,
+    pub adp_manufacture_kgsbeq: f64,
+    pub adp_use_kgsbeq: f64,
+    pub pe_manufacture_megajoules: f64,
+    pub pe_use_megajoules: f64,
+    pub gwp_manufacture_kgco2eq: f64,
+    pub gwp_use_kgco2eq: f64,
+}
+
+impl GroupSummary {
+    fn new(tag_key: &str, tag_value: Option<&str>) -> Self {
+        GroupSummary {
+            tag_key: tag_key.to_string(),
+            tag_value: tag_value.map(String::from),
+            number_of_resources_total: 0,
+            number_of_resources_assessed: 0,
+            assessed_share: 0.0,
+            adp_manufacture_kgsbeq: 0.0,
+            adp_use_kgsbeq: 0.0,
+            pe_manufacture_megajoules: 0.0,
+            pe_use_megajoules: 0.0,
+            gwp_manufacture_kgco2eq: 0.0,
+            gwp_use_kgco2eq: 0.0,
+        }
+    }
+
+    /// Adds a share (between 0 and 1) of the impacts of a resource, the resource counting in the group whatever its share
+    fn add(&mut self, resource: &CloudResourceWithImpacts, share: f64) {
+        self.number_of_resources_total += 1;
+        if let Some(impacts) = &resource.impacts_values {
+            self.number_of_resources_assessed += 1;
+            self.adp_manufacture_kgsbeq += impacts.adp_manufacture_kgsbeq * share;
+            self.adp_use_kgsbeq += impacts.adp_use_kgsbeq * share;
+            self.pe_manufacture_megajoules += impacts.pe_manufacture_megajoules * share;
+            self.pe_use_megajoules += impacts.pe_use_megajoules * share;
+            self.gwp_manufacture_kgco2eq += impacts.gwp_manufacture_kgco2eq * share;
+            self.gwp_use_kgco2eq += impacts.gwp_use_kgco2eq * share;
+        }
+        self.assessed_share =
+            self.number_of_resources_assessed as f64 / self.number_of_resources_total as f64;
+    }
+}
+
+/// The aggregated impacts of a parent and of the resources depending on it (directly or through other resources, like the volumes of the instances of an auto scaling group)
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct ParentSummary {
+    /// `Instance`, `AutoScalingGroup` or `Cluster`
+    pub parent_kind: String,
+    pub parent_id: String,
+    /// Number of resources rolled up, including the parent when it is a resource of the inventory
+    pub number_of_resources_total: usize,
+    pub number_of_resources_assessed: usize,
+    pub adp_manufacture_kgsbeq: f64,
+    pub adp_use_kgsbeq: f64,
+    pub pe_manufacture_megajoules: f64,
+    pub pe_use_megajoules: f64,
+    pub gwp_manufacture_kgco2eq: f64,
+    pub gwp_use_kgco2eq: f64,
+}
+
+impl ParentSummary {
+    fn new(parent_kind: &str, parent_id: &str) -> Self {
+        ParentSummary {
+            parent_kind: parent_kind.to_string(),
+            parent_id: parent_id.to_string(),
+            number_of_resources_total: 0,
+            number_of_resources_assessed: 0,
+            adp_manufacture_kgsbeq: 0.0,
+            adp_use_kgsbeq: 0.0,
+            pe_manufacture_megajoules: 0.0,
+            pe_use_megajoules: 0.0,
+            gwp_manufacture_kgco2eq: 0.0,
+            gwp_use_kgco2eq: 0.0,
+        }
+    }
+
+    fn add(&mut self, resource: &CloudResourceWithImpacts) {
+        self.number_of_resources_total += 1;
+        if let Some(impacts) = &resource.impacts_values {
+            self.number_of_resources_assessed += 1;
+            self.adp_manufacture_kgsbeq += impacts.adp_manufacture_kgsbeq;
+            self.adp_use_kgsbeq += impacts.adp_use_kgsbeq;
+            self.pe_manufacture_megajoules += impacts.pe_manufacture_megajoules;
+            self.pe_use_megajoules += impacts.pe_use_megajoules;
+            self.gwp_manufacture_kgco2eq += impacts.gwp_manufacture_kgco2eq;
+            self.gwp_use_kgco2eq += impacts.gwp_use_kgco2eq;
+        }
+    }
+}
+
+/// Returns the kind and id of the root parent of a resource, following the first relationship of each resource up to a parent that is not a resource of the inventory (like an auto scaling group), or the resource itself when it has no parent
+fn root_parent<'a>(
+    resource: &'a CloudResource,
+    resources_by_id: &HashMap<&str, &'a CloudResource>,
+) -> (String, String) {
+    let mut root = (
+        resource.resource_details.kind().to_string(),
+        resource.id.clone(),
+    );
+    let mut current = resource;
+    let mut visited = HashSet::from([resource.id.as_str()]);
+    while let Some(relationship) = current.parent_relationships().first() {
+        let (parent_kind, parent_id) = relationship.parent();
+        root = (parent_kind.to_string(), parent_id.to_string());
+        match resources_by_id.get(parent_id) {
+            // Stops on cycles (like instances tagged as members of each other)
+            Some(parent) if visited.insert(parent.id.as_str()) => current = parent,
+            _ => break,
+        }
+    }
+    root
+}
+
+/// Parses a grouping criteria given as `tag:<tag key>` and returns the tag key
+pub fn parse_group_by(group_by: &str) -> Result<String> {
+    match group_by.strip_prefix("tag:") {
+        Some(tag_key) if !tag_key.is_empty() => Ok(tag_key.to_string()),
+        _ => anyhow::bail!(
+            "Invalid grouping {} (expected tag:<tag key>, like tag:team)",
+            group_by
+        ),
+    }
+}
+
+/// A Software Carbon Intensity (SCI) score: emissions of the resources per functional unit
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct SciScore {
+    /// Name of the functional unit (like requests, users or builds)
+    pub functional_unit: String,
+    /// Number of functional units served during the duration of use
+    pub functional_unit_quantity: f64,
+    /// Total GWP (manufacture and use) divided by the number of functional units
+    pub sci_kgco2eq_per_unit: f64,
 }
 
 impl ImpactsSummary {
-    /// Returns a Summary of impacts for a list of Cloud Resources
+    /// Returns a Summary of impacts for a list of Cloud Resources.
+    ///
+    /// `aws_region` and `country` describe the scanned region: its sub-summary is always present (even when no resource is found), resources located in other regions get their own sub-summary.
     pub fn new(
         aws_region: String,
         country: String,
         resources_with_impacts: &EstimatedInventory,
         duration_of_use_hours: f64,
     ) -> Self {
-        let resources = resources_with_impacts.impacting_resources.clone();
+        Self::of_scanned_regions(
+            vec![RegionSummary::new(None, aws_region, country)],
+            resources_with_impacts,
+            duration_of_use_hours,
+        )
+    }
 
+    /// Returns a Summary of impacts for the Cloud Resources of several scanned regions (and accounts).
+    ///
+    /// The sub-summaries of the scanned regions are always present (even when no resource is found), resources located in other regions (or accounts) get their own sub-summary.
+    pub fn of_scanned_regions(
+        scanned_regions: Vec<RegionSummary>,
+        resources_with_impacts: &EstimatedInventory,
+        duration_of_use_hours: f64,
+    ) -> Self {
         let mut summary = ImpactsSummary {
-            number_of_resources_total: resources.len(),
+            number_of_resources_total: 0,
             number_of_resources_assessed: 0,
             number_of_resources_not_assessed: 0,
-            aws_region,
-            country,
+            number_of_resources_excluded: None,
             duration_of_use_hours,
             adp_manufacture_kgsbeq: 0.0,
             adp_use_kgsbeq: 0.0,
@@ -81,24 +345,612 @@ impl ImpactsSummary {
             pe_use_megajoules: 0.0,
             gwp_manufacture_kgco2eq: 0.0,
             gwp_use_kgco2eq: 0.0,
+            regions: Vec::new(),
+            sci: None,
+            groups: Vec::new(),
+            cost: None,
+            kubernetes: None,
+            parents: Vec::new(),
         };
 
-        for resource in resources {
-            // Only consider the instances for which we have impact data
-            if let Some(impacts) = resource.impacts_values {
-                summary.number_of_resources_assessed += 1;
-                summary.adp_manufacture_kgsbeq += impacts.adp_manufacture_kgsbeq;
-                summary.adp_use_kgsbeq += impacts.adp_use_kgsbeq;
-                summary.pe_manufacture_megajoules += impacts.pe_manufacture_megajoules;
-                summary.pe_use_megajoules += impacts.pe_use_megajoules;
-                summary.gwp_manufacture_kgco2eq += impacts.gwp_manufacture_kgco2eq;
-                summary.gwp_use_kgco2eq += impacts.gwp_use_kgco2eq;
-            } else {
-                // Resource was not counted due to no impact
-                debug!("Skipped counting resource: {:#?} while building summary because it has no impact data", resource);
-                summary.number_of_resources_not_assessed += 1;
-            }
+        let regions: BTreeMap<(Option<String>, String), RegionSummary> = scanned_regions
+            .into_iter()
+            .map(|region| {
+                (
+                    (region.account_id.clone(), region.aws_region.clone()),
+                    region,
+                )
+            })
+            .collect();
+        summary.regions = regions.into_values().collect();
+        for resource in resources_with_impacts.impacting_resources.iter() {
+            summary.add(resource);
         }
         summary
     }
+
+    /// Adds a resource with its impacts to the totals of the summary and to the sub-summary of its region (and account), so that the summary can be built while resources are estimated.
+    ///
+    /// The sub-summaries remain sorted by account and region.
+    pub fn add(&mut self, resource: &CloudResourceWithImpacts) {
+        self.number_of_resources_total += 1;
+        let account_id = &resource.cloud_resource.account_id;
+        let location = &resource.cloud_resource.location;
+        let position = self.regions.binary_search_by(|region| {
+            (&region.account_id, &region.aws_region).cmp(&(account_id, &location.aws_region))
+        });
+        match position {
+            Ok(index) => self.regions[index].add(resource),
+            Err(index) => {
+                let mut region = RegionSummary::new(
+                    account_id.clone(),
+                    location.aws_region.clone(),
+                    location.iso_country_code.clone(),
+                );
+                region.add(resource);
+                self.regions.insert(index, region);
+            }
+        }
+
+        // Only consider the instances for which we have impact data
+        if let Some(impacts) = &resource.impacts_values {
+            self.number_of_resources_assessed += 1;
+            self.adp_manufacture_kgsbeq += impacts.adp_manufacture_kgsbeq;
+            self.adp_use_kgsbeq += impacts.adp_use_kgsbeq;
+            self.pe_manufacture_megajoules += impacts.pe_manufacture_megajoules;
+            self.pe_use_megajoules += impacts.pe_use_megajoules;
+            self.gwp_manufacture_kgco2eq += impacts.gwp_manufacture_kgco2eq;
+            self.gwp_use_kgco2eq += impacts.gwp_use_kgco2eq;
+        } else {
+            // Resource was not counted due to no impact
+            debug!("Skipped counting resource: {:#?} while building summary because it has no impact data", resource);
+            self.number_of_resources_not_assessed += 1;
+        }
+    }
+
+    /// Combines the summaries of scans done apart (like the regions scanned by separate Lambda invocations), with the duration of use of the first one.
+    ///
+    /// Totals and sub-summaries of the same region (and account) are added. Sub-summaries by tag value, parents, cost and SCI are not combined.
+    pub fn combine(summaries: Vec<ImpactsSummary>) -> ImpactsSummary {
+        let duration_of_use_hours = summaries
+            .first()
+            .map(|s| s.duration_of_use_hours)
+            .unwrap_or_default();
+        let mut regions: BTreeMap<(Option<String>, String), RegionSummary> = BTreeMap::new();
+        for region in summaries.iter().flat_map(|s| s.regions.iter()) {
+            let key = (region.account_id.clone(), region.aws_region.clone());
+            match regions.get_mut(&key) {
+                Some(combined) => combined.merge(region),
+                None => {
+                    regions.insert(key, region.clone());
+                }
+            }
+        }
+        let total = |value: fn(&ImpactsSummary) -> f64| summaries.iter().map(value).sum::<f64>();
+        let excluded: Vec<usize> = summaries
+            .iter()
+            .filter_map(|s| s.number_of_resources_excluded)
+            .collect();
+        ImpactsSummary {
+            number_of_resources_total: summaries.iter().map(|s| s.number_of_resources_total).sum(),
+            number_of_resources_assessed: summaries
+                .iter()
+                .map(|s| s.number_of_resources_assessed)
+                .sum(),
+            number_of_resources_not_assessed: summaries
+                .iter()
+                .map(|s| s.number_of_resources_not_assessed)
+                .sum(),
+            number_of_resources_excluded: (!excluded.is_empty()).then(|| excluded.iter().sum()),
+            duration_of_use_hours,
+            adp_manufacture_kgsbeq: total(|s| s.adp_manufacture_kgsbeq),
+            adp_use_kgsbeq: total(|s| s.adp_use_kgsbeq),
+            pe_manufacture_megajoules: total(|s| s.pe_manufacture_megajoules),
+            pe_use_megajoules: total(|s| s.pe_use_megajoules),
+            gwp_manufacture_kgco2eq: total(|s| s.gwp_manufacture_kgco2eq),
+            gwp_use_kgco2eq: total(|s| s.gwp_use_kgco2eq),
+            regions: regions.into_values().collect(),
+            sci: None,
+            groups: Vec::new(),
+            cost: None,
+            kubernetes: None,
+            parents: Vec::new(),
+        }
+    }
+
+    /// Returns the summary of the resources of an account: the totals of its sub-summaries
+    pub fn of_account(&self, account_id: &str) -> ImpactsSummary {
+        let regions: Vec<RegionSummary> = self
+            .regions
+            .iter()
+            .filter(|region| region.account_id.as_deref() == Some(account_id))
+            .cloned()
+            .collect();
+        let total = |value: fn(&RegionSummary) -> f64| regions.iter().map(value).sum::<f64>();
+        ImpactsSummary {
+            number_of_resources_total: regions.iter().map(|r| r.number_of_resources_total).sum(),
+            number_of_resources_assessed: regions
+                .iter()
+                .map(|r| r.number_of_resources_assessed)
+                .sum(),
+            number_of_resources_not_assessed: regions
+                .iter()
+                .map(|r| r.number_of_resources_not_assessed)
+                .sum(),
+            number_of_resources_excluded: None,
+            duration_of_use_hours: self.duration_of_use_hours,
+            adp_manufacture_kgsbeq: total(|r| r.adp_manufacture_kgsbeq),
+            adp_use_kgsbeq: total(|r| r.adp_use_kgsbeq),
+            pe_manufacture_megajoules: total(|r| r.pe_manufacture_megajoules),
+            pe_use_megajoules: total(|r| r.pe_use_megajoules),
+            gwp_manufacture_kgco2eq: total(|r| r.gwp_manufacture_kgco2eq),
+            gwp_use_kgco2eq: total(|r| r.gwp_use_kgco2eq),
+            regions,
+            sci: None,
+            groups: Vec::new(),
+            cost: None,
+            kubernetes: None,
+            parents: Vec::new(),
+        }
+    }
+
+    /// Returns the regions of the summary, separated by commas (like `eu-west-1,us-east-1`)
+    pub fn aws_regions(&self) -> String {
+        let mut regions: Vec<&str> = self.regions.iter().map(|r| r.aws_region.as_str()).collect();
+        regions.sort();
+        regions.dedup();
+        regions.join(",")
+    }
+
+    /// Returns the countries of the regions of the summary, separated by commas
+    pub fn countries(&self) -> String {
+        let mut countries: Vec<&str> = self.regions.iter().map(|r| r.country.as_str()).collect();
+        countries.sort();
+        countries.dedup();
+        countries.join(",")
+    }
+
+    /// Reports the number of resources excluded by the ignore file
+    pub fn with_excluded_resources(mut self, number_of_resources_excluded: usize) -> Self {
+        self.number_of_resources_excluded = Some(number_of_resources_excluded);
+        self
+    }
+
+    /// Computes the SCI score of the summary for a number of functional units served during the duration of use
+    pub fn with_functional_unit(
+        mut self,
+        functional_unit: &str,
+        functional_unit_quantity: f64,
+    ) -> Result<Self> {
+        if functional_unit_quantity <= 0.0 || !functional_unit_quantity.is_finite() {
+            anyhow::bail!(
+                "Invalid quantity of functional units {} (should be a positive number)",
+                functional_unit_quantity
+            );
+        }
+        let total_gwp_kgco2eq = self.gwp_manufacture_kgco2eq + self.gwp_use_kgco2eq;
+        self.sci = Some(SciScore {
+            functional_unit: functional_unit.to_string(),
+            functional_unit_quantity,
+            sci_kgco2eq_per_unit: total_gwp_kgco2eq / functional_unit_quantity,
+        });
+        Ok(self)
+    }
+
+    /// Computes the total cost of the resources with a cost (see [crate::pricing]) and their emissions per unit of currency
+    pub fn with_costs(
+        mut self,
+        currency: &str,
+        resources_with_impacts: &EstimatedInventory,
+    ) -> Self {
+        let mut cost = CostSummary {
+            currency: currency.to_string(),
+            total_cost: 0.0,
+            number_of_resources_priced: 0,
+            gwp_kgco2eq_per_currency_unit: None,
+        };
+        let mut gwp_kgco2eq = 0.0;
+        for resource in resources_with_impacts.impacting_resources.iter() {
+            if let Some(resource_cost) = &resource.cost {
+                cost.total_cost += resource_cost.cost;
+                cost.number_of_resources_priced += 1;
+                if let Some(impacts) = &resource.impacts_values {
+                    gwp_kgco2eq += impacts.gwp_manufacture_kgco2eq + impacts.gwp_use_kgco2eq;
+                }
+            }
+        }
+        if cost.total_cost > 0.0 {
+            cost.gwp_kgco2eq_per_currency_unit = Some(gwp_kgco2eq / cost.total_cost);
+        }
+        self.cost = Some(cost);
+        self
+    }
+
+    /// Allocates the impacts of the nodes of a Kubernetes cluster (instances of the inventory) to its namespaces and workloads (see [crate::kubernetes_workloads])
+    pub fn with_kubernetes_workloads(
+        mut self,
+        cluster: &KubernetesCluster,
+        resources_with_impacts: &EstimatedInventory,
+    ) -> Self {
+        self.kubernetes = Some(cluster.allocate(resources_with_impacts));
+        self
+    }
+
+    /// Adds sub-summaries of the resources grouped by the values of a tag (sorted by tag value, resources without the tag come first)
+    pub fn with_groups_by_tag(
+        self,
+        tag_key: &str,
+        resources_with_impacts: &EstimatedInventory,
+    ) -> Self {
+        self.with_allocated_groups_by_tag(
+            tag_key,
+            resources_with_impacts,
+            &AllocationRules::default(),
+        )
+    }
+
+    /// Adds sub-summaries of the resources grouped by the values of a tag, the impacts of the resources matching allocation rules being split across the values of their rule (a shared resource counting in each of its groups)
+    pub fn with_allocated_groups_by_tag(
+        mut self,
+        tag_key: &str,
+        resources_with_impacts: &EstimatedInventory,
+        allocations: &AllocationRules,
+    ) -> Self {
+        let mut groups: BTreeMap<Option<String>, GroupSummary> = BTreeMap::new();
+        for resource in resources_with_impacts.impacting_resources.iter() {
+            for (tag_value, share) in allocations.shares(&resource.cloud_resource, tag_key) {
+                groups
+                    .entry(tag_value)
+                    .or_insert_with_key(|tag_value| {
+                        GroupSummary::new(tag_key, tag_value.as_deref())
+                    })
+                    .add(resource, share);
+            }
+        }
+        self.groups = groups.into_values().collect();
+        self
+    }
+
+    /// Rolls up the impacts of the resources to their parents (see [CloudResource::parent_relationships]): each resource counts in the summary of its root parent, the parents being sorted by decreasing GWP. Resources that have no parent and no dependent resources are not part of the summaries.
+    pub fn with_parents(mut self, resources_with_impacts: &EstimatedInventory) -> Self {
+        let resources_by_id: HashMap<&str, &CloudResource> = resources_with_impacts
+            .impacting_resources
+            .iter()
+            .map(|resource| {
+                (
+                    resource.cloud_resource.id.as_str(),
+                    &resource.cloud_resource,
+                )
+            })
+            .collect();
+        let mut parents: BTreeMap<(String, String), ParentSummary> = BTreeMap::new();
+        let mut has_dependents = HashSet::new();
+        for resource in resources_with_impacts.impacting_resources.iter() {
+            let root = root_parent(&resource.cloud_resource, &resources_by_id);
+            if root.1 != resource.cloud_resource.id {
+                has_dependents.insert(root.clone());
+            }
+            parents
+                .entry(root)
+                .or_insert_with_key(|(kind, id)| ParentSummary::new(kind, id))
+                .add(resource);
+        }
+        parents.retain(|root, _| has_dependents.contains(root));
+        let mut parents: Vec<ParentSummary> = parents.into_values().collect();
+        parents.sort_by(|a, b| {
+            (b.gwp_manufacture_kgco2eq + b.gwp_use_kgco2eq)
+                .total_cmp(&(a.gwp_manufacture_kgco2eq + a.gwp_use_kgco2eq))
+        });
+        self.parents = parents;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sci_is_total_gwp_per_functional_unit() {
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let mut summary = ImpactsSummary::new(
+            "eu-west-1".to_string(),
+            "IRL".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        assert_eq!(None, summary.sci);
+        summary.gwp_manufacture_kgco2eq = 1.0;
+        summary.gwp_use_kgco2eq = 3.0;
+
+        let summary = summary.with_functional_unit("requests", 1000.0).unwrap();
+        let sci = summary.sci.clone().unwrap();
+        assert_eq!("requests", sci.functional_unit);
+        assert_eq!(0.004, sci.sci_kgco2eq_per_unit);
+
+        assert!(summary
+            .clone()
+            .with_functional_unit("requests", 0.0)
+            .is_err());
+        assert!(summary.with_functional_unit("requests", -1.0).is_err());
+    }
+
+    #[test]
+    fn summarize_resources_by_tag_value() {
+        use crate::model::{CloudProvider, CloudResource, CloudResourceTag, ResourceDetails};
+        use crate::usage_location::UsageLocation;
+
+        let resource =
+            |id: &str, team: Option<&str>, gwp_use_kgco2eq: Option<f64>| CloudResourceWithImpacts {
+                cloud_resource: CloudResource {
+                    provider: CloudProvider::AWS,
+                    account_id: None,
+                    id: id.to_string(),
+                    location: UsageLocation::try_from("eu-west-1").unwrap(),
+                    resource_details: ResourceDetails::ObjectStorage,
+                    tags: team
+                        .map(|team| {
+                            vec![CloudResourceTag {
+                                key: "team".to_string(),
+                                value: Some(team.to_string()),
+                            }]
+                        })
+                        .unwrap_or_default(),
+                    relationships: Vec::new(),
+                },
+                impacts_values: gwp_use_kgco2eq.map(|gwp_use_kgco2eq| ImpactsValues {
+                    gwp_use_kgco2eq,
+                    ..Default::default()
+                }),
+                impacts_duration_hours: 1.0,
+                cost: None,
+            };
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![
+                resource("a", Some("web"), Some(1.0)),
+                resource("b", Some("data"), Some(2.0)),
+                resource("c", Some("web"), None),
+                resource("d", None, Some(4.0)),
+            ],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-1".to_string(),
+            "IRL".to_string(),
+            &estimated_inventory,
+            1.0,
+        )
+        .with_groups_by_tag("team", &estimated_inventory);
+
+        let values: Vec<Option<&str>> = summary
+            .groups
+            .iter()
+            .map(|g| g.tag_value.as_deref())
+            .collect();
+        assert_eq!(vec![None, Some("data"), Some("web")], values);
+        let web = &summary.groups[2];
+        assert_eq!(2, web.number_of_resources_total);
+        assert_eq!(1, web.number_of_resources_assessed);
+        assert_eq!(0.5, web.assessed_share);
+        assert_eq!(1.0, web.gwp_use_kgco2eq);
+
+        // The untagged resource is shared 75/25 by the web and data teams
+        let allocations =
+            AllocationRules::from_config(&[crate::allocation::AllocationRuleConfig {
+                resources: "team!~*".to_string(),
+                tag: "team".to_string(),
+                shares: [("web".to_string(), 75.0), ("data".to_string(), 25.0)].into(),
+            }])
+            .unwrap();
+        let summary =
+            summary.with_allocated_groups_by_tag("team", &estimated_inventory, &allocations);
+        let values: Vec<(Option<&str>, f64)> = summary
+            .groups
+            .iter()
+            .map(|g| (g.tag_value.as_deref(), g.gwp_use_kgco2eq))
+            .collect();
+        assert_eq!(vec![(Some("data"), 3.0), (Some("web"), 4.0)], values);
+        assert_eq!(3, summary.groups[1].number_of_resources_total);
+    }
+
+    #[test]
+    fn summarize_resources_by_region() {
+        use crate::model::{CloudProvider, CloudResource, ResourceDetails};
+        use crate::usage_location::UsageLocation;
+
+        let bucket = |region: &str| CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: format!("bucket-{}", region),
+                location: UsageLocation::try_from(region).unwrap(),
+                resource_details: ResourceDetails::ObjectStorage,
+                tags: Vec::new(),
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues {
+                gwp_use_kgco2eq: 1.0,
+                ..Default::default()
+            }),
+            impacts_duration_hours: 1.0,
+            cost: None,
+        };
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![bucket("us-east-1"), bucket("us-east-1")],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-1".to_string(),
+            "IRL".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+
+        // The scanned region is kept even if it has no resource
+        assert_eq!(2, summary.regions.len());
+        assert_eq!("eu-west-1", summary.regions[0].aws_region);
+        assert_eq!(0, summary.regions[0].number_of_resources_total);
+        assert_eq!("us-east-1", summary.regions[1].aws_region);
+        assert_eq!("USA", summary.regions[1].country);
+        assert_eq!(2, summary.regions[1].number_of_resources_assessed);
+        assert_eq!(2.0, summary.regions[1].gwp_use_kgco2eq);
+        assert_eq!(2.0, summary.gwp_use_kgco2eq);
+        assert_eq!("eu-west-1,us-east-1", summary.aws_regions());
+        assert_eq!("IRL,USA", summary.countries());
+    }
+
+    #[test]
+    fn combine_summaries_of_regions_scanned_apart() {
+        use crate::model::{CloudProvider, CloudResource, ResourceDetails};
+        use crate::usage_location::UsageLocation;
+
+        let bucket = |region: &str| CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: format!("bucket-{}", region),
+                location: UsageLocation::try_from(region).unwrap(),
+                resource_details: ResourceDetails::ObjectStorage,
+                tags: Vec::new(),
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues {
+                gwp_use_kgco2eq: 1.0,
+                ..Default::default()
+            }),
+            impacts_duration_hours: 1.0,
+            cost: None,
+        };
+        let summary_of = |region: &str, resources: Vec<CloudResourceWithImpacts>| {
+            let estimated_inventory = EstimatedInventory {
+                impacting_resources: resources,
+                execution_statistics: None,
+                errors: Vec::new(),
+            };
+            ImpactsSummary::new(
+                region.to_string(),
+                UsageLocation::try_from(region).unwrap().iso_country_code,
+                &estimated_inventory,
+                24.0,
+            )
+        };
+        let summary = ImpactsSummary::combine(vec![
+            summary_of("eu-west-1", vec![bucket("eu-west-1"), bucket("us-east-1")]),
+            summary_of("us-east-1", vec![bucket("us-east-1")]),
+            summary_of("eu-west-3", Vec::new()),
+        ]);
+
+        assert_eq!(3, summary.number_of_resources_total);
+        assert_eq!(3.0, summary.gwp_use_kgco2eq);
+        assert_eq!(24.0, summary.duration_of_use_hours);
+        assert_eq!("eu-west-1,eu-west-3,us-east-1", summary.aws_regions());
+        assert_eq!(2, summary.regions[2].number_of_resources_assessed);
+        assert_eq!(2.0, summary.regions[2].gwp_use_kgco2eq);
+        assert_eq!(0, summary.regions[1].number_of_resources_total);
+    }
+
+    #[test]
+    fn roll_up_resources_to_their_parents() {
+        use crate::model::{
+            CloudProvider, CloudResource, CloudResourceTag, ResourceDetails, StorageAttachment,
+        };
+        use crate::usage_location::UsageLocation;
+
+        let resource =
+            |id: &str, resource_details: ResourceDetails, tags: Vec<CloudResourceTag>| {
+                CloudResourceWithImpacts {
+                    cloud_resource: CloudResource {
+                        provider: CloudProvider::AWS,
+                        account_id: None,
+                        id: id.to_string(),
+                        location: UsageLocation::try_from("eu-west-1").unwrap(),
+                        resource_details,
+                        tags,
+                        relationships: Vec::new(),
+                    },
+                    impacts_values: Some(ImpactsValues {
+                        gwp_use_kgco2eq: 1.0,
+                        ..Default::default()
+                    }),
+                    impacts_duration_hours: 1.0,
+                    cost: None,
+                }
+            };
+        let instance = |id: &str, tags: Vec<CloudResourceTag>| {
+            resource(
+                id,
+                ResourceDetails::Instance {
+                    instance_type: "m6g.xlarge".to_string(),
+                    usage: None,
+                },
+                tags,
+            )
+        };
+        let volume = |id: &str, instance_id: &str| {
+            resource(
+                id,
+                ResourceDetails::BlockStorage {
+                    storage_type: "gp3".to_string(),
+                    usage: None,
+                    attached_instances: Some(vec![StorageAttachment {
+                        instance_id: instance_id.to_string(),
+                    }]),
+                },
+                Vec::new(),
+            )
+        };
+        let workers = vec![CloudResourceTag {
+            key: "aws:autoscaling:groupName".to_string(),
+            value: Some("workers".to_string()),
+        }];
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![
+                instance("i-1", workers.clone()),
+                volume("vol-1", "i-1"),
+                instance("i-2", workers),
+                instance("i-3", Vec::new()),
+                volume("vol-3", "i-3"),
+                instance("i-4", Vec::new()),
+            ],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-1".to_string(),
+            "IRL".to_string(),
+            &estimated_inventory,
+            1.0,
+        )
+        .with_parents(&estimated_inventory);
+
+        let parents: Vec<(&str, &str, usize)> = summary
+            .parents
+            .iter()
+            .map(|p| {
+                (
+                    p.parent_kind.as_str(),
+                    p.parent_id.as_str(),
+                    p.number_of_resources_total,
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![("AutoScalingGroup", "workers", 3), ("Instance", "i-3", 2)],
+            parents
+        );
+        assert_eq!(3.0, summary.parents[0].gwp_use_kgco2eq);
+    }
+
+    #[test]
+    fn group_by_expects_a_tag_key() {
+        assert_eq!("team", parse_group_by("tag:team").unwrap());
+        assert!(parse_group_by("team").is_err());
+        assert!(parse_group_by("tag:").is_err());
+    }
 }
@@ -0,0 +1,260 @@
+//! Incremental scans: the resources of the last scan are kept in a local state file with their utilization and impacts, so that repeated scans only query the utilization and impacts of new or changed resources.
+//!
+//! A resource is unchanged when its description (type, state, size, attachments, tags and region) is the same as in the last scan: its CPU load is reused instead of being queried from CloudWatch, and its impacts instead of being requested from Boavizta API.
+//! The state is replaced by the resources of each incremental scan (resources that disappeared are forgotten), and it is ignored when the scan uses another duration of use, Boavizta API or verbosity.
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::impact_provider::CloudResourceWithImpacts;
+use crate::model::{CloudResource, ResourceDetails};
+
+/// State file used when none is passed
+pub const DEFAULT_STATE_FILE: &str = ".cloud-scanner-state.json";
+
+/// Parameters of a scan that its impacts depend on
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StateParameters {
+    pub use_duration_hours: f32,
+    pub api_url: String,
+    pub verbose: bool,
+}
+
+/// The resources seen by a scan, with their utilization and impacts
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScanState {
+    pub parameters: StateParameters,
+    /// The resources by id
+    pub resources: BTreeMap<String, CloudResourceWithImpacts>,
+}
+
+/// Returns the description of a resource, without its CPU load and account (set after the inventory)
+fn description(resource: &CloudResource) -> serde_json::Value {
+    let mut resource = resource.clone();
+    resource.account_id = None;
+    if let ResourceDetails::Instance {
+        usage: Some(usage), ..
+    } = &mut resource.resource_details
+    {
+        usage.average_cpu_load = 0.0;
+    }
+    serde_json::to_value(resource).unwrap_or_default()
+}
+
+impl ScanState {
+    /// Returns an empty state
+    pub fn new(parameters: StateParameters) -> Self {
+        ScanState {
+            parameters,
+            resources: BTreeMap::new(),
+        }
+    }
+
+    /// Reads a state file, an empty state being returned when it does not exist or was written by a scan with other parameters
+    pub fn load(path: &str, parameters: &StateParameters) -> Result<ScanState> {
+        if !Path::new(path).exists() {
+            info!("No state file {}, scanning all resources", path);
+            return Ok(ScanState::new(parameters.clone()));
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read state file {}", path))?;
+        let state: ScanState = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid state file {}", path))?;
+        if &state.parameters != parameters {
+            info!(
+                "State file {} was written by a scan with other parameters, scanning all resources",
+                path
+            );
+            return Ok(ScanState::new(parameters.clone()));
+        }
+        Ok(state)
+    }
+
+    /// Writes the state file
+    pub fn save(&self, path: &str) -> Result<()> {
+        let content = serde_json::to_string(self)?;
+        std::fs::write(path, content).with_context(|| format!("Cannot write state file {}", path))
+    }
+
+    /// Returns the CPU load of an instance in the last scan, if its description did not change
+    pub fn previous_cpu_load(&self, resource: &CloudResource) -> Option<f64> {
+        let previous = self.resources.get(&resource.id)?;
+        if description(&previous.cloud_resource) != description(resource) {
+            return None;
+        }
+        match &previous.cloud_resource.resource_details {
+            ResourceDetails::Instance {
+                usage: Some(usage), ..
+            } => Some(usage.average_cpu_load),
+            _ => None,
+        }
+    }
+
+    /// Returns the impacts of a resource in the last scan, if the resource (and its utilization) did not change
+    pub fn previous_impacts(&self, resource: &CloudResource) -> Option<CloudResourceWithImpacts> {
+        let previous = self.resources.get(&resource.id)?;
+        let unchanged = serde_json::to_value(&previous.cloud_resource).ok()
+            == serde_json::to_value(resource).ok();
+        unchanged.then(|| previous.clone())
+    }
+
+    /// Adds a resource with its impacts to the state
+    pub fn record(&mut self, resource_with_impacts: &CloudResourceWithImpacts) {
+        self.resources.insert(
+            resource_with_impacts.cloud_resource.id.clone(),
+            resource_with_impacts.clone(),
+        );
+    }
+}
+
+/// The incremental scan of the process (if enabled): the state of the last scan, and the state of the current scan
+struct IncrementalScan {
+    path: String,
+    previous: ScanState,
+    current: ScanState,
+    reused: usize,
+}
+
+static INCREMENTAL: Mutex<Option<IncrementalScan>> = Mutex::new(None);
+
+/// Reuses the results of the last scan of the state file for the unchanged resources from now on
+pub fn enable(path: &str, parameters: StateParameters) -> Result<()> {
+    let previous = ScanState::load(path, &parameters)?;
+    *INCREMENTAL.lock().unwrap() = Some(IncrementalScan {
+        path: path.to_string(),
+        previous,
+        current: ScanState::new(parameters),
+        reused: 0,
+    });
+    Ok(())
+}
+
+/// Returns the CPU load of an unchanged instance in the last scan (None when incremental scans are not enabled)
+pub fn previous_cpu_load(resource: &CloudResource) -> Option<f64> {
+    INCREMENTAL
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|scan| scan.previous.previous_cpu_load(resource))
+}
+
+/// Returns the impacts of an unchanged resource in the last scan (None when incremental scans are not enabled)
+pub fn previous_impacts(resource: &CloudResource) -> Option<CloudResourceWithImpacts> {
+    let mut incremental = INCREMENTAL.lock().unwrap();
+    let scan = incremental.as_mut()?;
+    let previous = scan.previous.previous_impacts(resource);
+    if previous.is_some() {
+        scan.reused += 1;
+    }
+    previous
+}
+
+/// Adds resources with their impacts to the state of the current scan (if incremental scans are enabled)
+pub fn record(resources_with_impacts: &[CloudResourceWithImpacts]) {
+    if let Some(scan) = INCREMENTAL.lock().unwrap().as_mut() {
+        for resource_with_impacts in resources_with_impacts {
+            scan.current.record(resource_with_impacts);
+        }
+    }
+}
+
+/// Replaces the state file with the resources of the current scan (if incremental scans are enabled)
+pub fn save() -> Result<()> {
+    if let Some(scan) = INCREMENTAL.lock().unwrap().as_ref() {
+        scan.current.save(&scan.path)?;
+        info!(
+            "Saved {} resources to state file {} (results of {} unchanged resources reused)",
+            scan.current.resources.len(),
+            scan.path,
+            scan.reused
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::ImpactsValues;
+    use crate::model::{CloudProvider, InstanceState, InstanceUsage};
+    use crate::usage_location::UsageLocation;
+
+    fn instance(instance_type: &str, average_cpu_load: f64) -> CloudResource {
+        CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: None,
+            id: "i-1".to_string(),
+            location: UsageLocation::try_from("eu-west-3").unwrap(),
+            resource_details: ResourceDetails::Instance {
+                instance_type: instance_type.to_string(),
+                usage: Some(InstanceUsage {
+                    average_cpu_load,
+                    usage_duration_seconds: 300,
+                    state: InstanceState::Running,
+                }),
+            },
+            tags: Vec::new(),
+            relationships: Vec::new(),
+        }
+    }
+
+    fn parameters(use_duration_hours: f32) -> StateParameters {
+        StateParameters {
+            use_duration_hours,
+            api_url: "https://api.boavizta.org".to_string(),
+            verbose: false,
+        }
+    }
+
+    #[test]
+    fn results_of_unchanged_resources_are_reused() {
+        let mut state = ScanState::new(parameters(730.0));
+        state.record(&CloudResourceWithImpacts {
+            cloud_resource: instance("m6g.xlarge", 42.0),
+            impacts_values: Some(ImpactsValues {
+                gwp_use_kgco2eq: 1.5,
+                ..ImpactsValues::default()
+            }),
+            impacts_duration_hours: 730.0,
+            cost: None,
+        });
+
+        // The CPU load is read before querying CloudWatch
+        assert_eq!(
+            Some(42.0),
+            state.previous_cpu_load(&instance("m6g.xlarge", 0.0))
+        );
+        assert_eq!(None, state.previous_cpu_load(&instance("m6g.2xlarge", 0.0)));
+        let mut other = instance("m6g.xlarge", 0.0);
+        other.id = "i-2".to_string();
+        assert_eq!(None, state.previous_cpu_load(&other));
+
+        let previous = state
+            .previous_impacts(&instance("m6g.xlarge", 42.0))
+            .unwrap();
+        assert_eq!(1.5, previous.impacts_values.unwrap().gwp_use_kgco2eq);
+        assert!(state
+            .previous_impacts(&instance("m6g.xlarge", 10.0))
+            .is_none());
+
+        let path = std::env::temp_dir().join(format!("cloud-scanner-state-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert!(ScanState::load(path, &parameters(730.0))
+            .unwrap()
+            .resources
+            .is_empty());
+        state.save(path).unwrap();
+        let loaded = ScanState::load(path, &parameters(730.0)).unwrap();
+        assert!(loaded
+            .previous_impacts(&instance("m6g.xlarge", 42.0))
+            .is_some());
+        // Impacts depend on the duration of use
+        assert!(ScanState::load(path, &parameters(24.0))
+            .unwrap()
+            .resources
+            .is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,377 @@
+//!  A module to format the results of cloud-scanner into InfluxDB line protocol, and optionally push them to an InfluxDB v2 endpoint.
+use anyhow::{Context, Result};
+
+use crate::impact_provider::{CloudResourceWithImpacts, ImpactsSummary};
+use crate::model::{EstimatedInventory, InstanceState, ResourceDetails};
+
+/// Name of the measurement holding the summary of impacts
+const SUMMARY_MEASUREMENT: &str = "boavizta_summary";
+/// Name of the measurement holding the impacts of individual resources
+const RESOURCE_MEASUREMENT: &str = "boavizta_resource";
+
+///  Connection settings of an InfluxDB v2 endpoint
+#[derive(Clone, Debug)]
+pub struct InfluxDbConfig {
+    /// Base URL of the InfluxDB server (without trailing slash, e.g. http://localhost:8086)
+    pub url: String,
+    pub org: String,
+    pub bucket: String,
+    /// API token, sent as `Authorization: Token <token>`
+    pub token: String,
+}
+
+/// Escape measurement names (commas and spaces)
+fn escape_measurement(value: &str) -> String {
+    value.replace(',', "\\,").replace(' ', "\\ ")
+}
+
+/// Escape tag keys, tag values and field keys (commas, equal signs and spaces)
+fn escape_key(value: &str) -> String {
+    value
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+/// Build a single line of line protocol from a measurement, a list of tags and a list of (already formatted) fields.
+///
+/// Tags with an empty value are skipped because line protocol does not support them.
+fn build_line(
+    measurement: &str,
+    tags: &[(&str, String)],
+    fields: &[(&str, String)],
+    timestamp_ns: i64,
+) -> String {
+    let mut line = escape_measurement(measurement);
+    for (key, value) in tags.iter().filter(|(_, v)| !v.is_empty()) {
+        line.push(',');
+        line.push_str(&escape_key(key));
+        line.push('=');
+        line.push_str(&escape_key(value));
+    }
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| format!("{}={}", escape_key(key), value))
+        .collect();
+    line.push(' ');
+    line.push_str(&fields.join(","));
+    line.push(' ');
+    line.push_str(&timestamp_ns.to_string());
+    line
+}
+
+/// Format an integer field value
+fn int_field(value: usize) -> String {
+    format!("{}i", value)
+}
+
+/// Format a float field value (always with a decimal point to keep the field type stable)
+fn float_field(value: f64) -> String {
+    format!("{:?}", value)
+}
+
+/// Return an ImpactsSummary as lines of InfluxDB line protocol (one point per region sub-summary)
+pub fn get_summary_line_protocol(summary: &ImpactsSummary, timestamp_ns: i64) -> Vec<String> {
+    summary
+        .regions
+        .iter()
+        .map(|region| {
+            let mut tags = vec![
+                ("awsregion", region.aws_region.clone()),
+                ("country", region.country.clone()),
+            ];
+            if let Some(account_id) = &region.account_id {
+                tags.push(("account_id", account_id.clone()));
+            }
+            let mut fields = vec![
+                (
+                    "number_of_resources_total",
+                    int_field(region.number_of_resources_total),
+                ),
+                (
+                    "number_of_resources_assessed",
+                    int_field(region.number_of_resources_assessed),
+                ),
+                (
+                    "number_of_resources_not_assessed",
+                    int_field(region.number_of_resources_not_assessed),
+                ),
+                (
+                    "duration_of_use_hours",
+                    float_field(summary.duration_of_use_hours),
+                ),
+                (
+                    "adp_manufacture_kgsbeq",
+                    float_field(region.adp_manufacture_kgsbeq),
+                ),
+                ("adp_use_kgsbeq", float_field(region.adp_use_kgsbeq)),
+                (
+                    "pe_manufacture_megajoules",
+                    float_field(region.pe_manufacture_megajoules),
+                ),
+                ("pe_use_megajoules", float_field(region.pe_use_megajoules)),
+                (
+                    "gwp_manufacture_kgco2eq",
+                    float_field(region.gwp_manufacture_kgco2eq),
+                ),
+                ("gwp_use_kgco2eq", float_field(region.gwp_use_kgco2eq)),
+            ];
+            // The SCI score is computed for the whole scan, it is repeated on each point
+            if let Some(sci) = &summary.sci {
+                fields.push((
+                    "functional_unit_quantity",
+                    float_field(sci.functional_unit_quantity),
+                ));
+                fields.push((
+                    "sci_kgco2eq_per_unit",
+                    float_field(sci.sci_kgco2eq_per_unit),
+                ));
+            }
+            build_line(SUMMARY_MEASUREMENT, &tags, &fields, timestamp_ns)
+        })
+        .collect()
+}
+
+/// Return the impacts of a resource as a line of InfluxDB line protocol.
+///
+/// Resources without impacts are skipped (returns None).
+fn get_resource_line_protocol(
+    resource: &CloudResourceWithImpacts,
+    timestamp_ns: i64,
+) -> Option<String> {
+    let impacts = resource.impacts_values.as_ref()?;
+    let cloud_resource = &resource.cloud_resource;
+
+    let (resource_type, resource_state) = match &cloud_resource.resource_details {
+        ResourceDetails::Instance { usage, .. } | ResourceDetails::Server { usage, .. } => {
+            let state = match usage.as_ref().map(|u| &u.state) {
+                Some(InstanceState::Running) => "Running",
+                Some(InstanceState::Stopped) => "Stopped",
+                None => "Unknown",
+            };
+            (cloud_resource.resource_details.kind(), state)
+        }
+        ResourceDetails::BlockStorage { .. } => ("BlockStorage", "Unknown"),
+        ResourceDetails::ObjectStorage => ("ObjectStorage", "Unknown"),
+    };
+
+    let tags = [
+        ("awsregion", cloud_resource.location.aws_region.clone()),
+        ("country", cloud_resource.location.iso_country_code.clone()),
+        ("resource_type", resource_type.to_string()),
+        ("resource_id", cloud_resource.id.clone()),
+        ("resource_tags", cloud_resource.tags_as_metric_label_value()),
+        ("resource_state", resource_state.to_string()),
+    ];
+
+    let mut fields = vec![
+        (
+            "duration_of_use_hours",
+            float_field(resource.impacts_duration_hours.into()),
+        ),
+        (
+            "adp_manufacture_kgsbeq",
+            float_field(impacts.adp_manufacture_kgsbeq),
+        ),
+        ("adp_use_kgsbeq", float_field(impacts.adp_use_kgsbeq)),
+        (
+            "pe_manufacture_megajoules",
+            float_field(impacts.pe_manufacture_megajoules),
+        ),
+        ("pe_use_megajoules", float_field(impacts.pe_use_megajoules)),
+        (
+            "gwp_manufacture_kgco2eq",
+            float_field(impacts.gwp_manufacture_kgco2eq),
+        ),
+        ("gwp_use_kgco2eq", float_field(impacts.gwp_use_kgco2eq)),
+    ];
+
+    // Export CPU usage (for instances) and size (for storage)
+    match &cloud_resource.resource_details {
+        ResourceDetails::Instance {
+            usage: Some(instance_usage),
+            ..
+        } => fields.push(("cpu_load", float_field(instance_usage.average_cpu_load))),
+        ResourceDetails::BlockStorage {
+            usage: Some(storage_usage),
+            ..
+        } => fields.push(("storage_size_gb", format!("{}i", storage_usage.size_gb))),
+        _ => {}
+    }
+
+    Some(build_line(
+        RESOURCE_MEASUREMENT,
+        &tags,
+        &fields,
+        timestamp_ns,
+    ))
+}
+
+/// Return the summary and the impacts of each resource as InfluxDB line protocol (one line per point)
+pub fn get_all_line_protocol(
+    summary: &ImpactsSummary,
+    resources_with_impacts: &EstimatedInventory,
+    timestamp_ns: i64,
+) -> String {
+    let mut lines: Vec<String> = get_summary_line_protocol(summary, timestamp_ns);
+    for resource in resources_with_impacts.impacting_resources.iter() {
+        if let Some(line) = get_resource_line_protocol(resource, timestamp_ns) {
+            lines.push(line);
+        } else {
+            debug!(
+                "Skipped resource {} in line protocol output because it has no impact data",
+                resource.cloud_resource.id
+            );
+        }
+    }
+    let mut body = lines.join("\n");
+    body.push('\n');
+    body
+}
+
+/// Push line protocol data to an InfluxDB v2 endpoint (`/api/v2/write`) using token authentication.
+pub async fn push_to_influxdb(config: &InfluxDbConfig, line_protocol: String) -> Result<()> {
+    let write_url = format!("{}/api/v2/write", config.url);
+    let client = reqwest::Client::new();
+    let response = client
+        .post(&write_url)
+        .query(&[
+            ("org", config.org.as_str()),
+            ("bucket", config.bucket.as_str()),
+            ("precision", "ns"),
+        ])
+        .header("Authorization", format!("Token {}", config.token))
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(line_protocol)
+        .send()
+        .await
+        .with_context(|| format!("Cannot reach InfluxDB at {}", write_url))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let message = response.text().await.unwrap_or_default();
+        anyhow::bail!("InfluxDB write failed with status {}: {}", status, message);
+    }
+    info!("Pushed impacts to InfluxDB bucket {}", config.bucket);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{ImpactsValues, RegionSummary};
+    use crate::model::{CloudProvider, CloudResource, CloudResourceTag, InstanceUsage};
+    use crate::usage_location::UsageLocation;
+
+    #[test]
+    fn escape_special_characters_in_tags() {
+        assert_eq!("a\\,b\\=c\\ d", escape_key("a,b=c d"));
+        assert_eq!("my\\ measure\\,ment", escape_measurement("my measure,ment"));
+    }
+
+    #[test]
+    fn format_summary_as_line_protocol() {
+        let summary: ImpactsSummary = ImpactsSummary {
+            number_of_resources_total: 5,
+            number_of_resources_assessed: 2,
+            number_of_resources_not_assessed: 3,
+            number_of_resources_excluded: None,
+            duration_of_use_hours: 1.0,
+            adp_manufacture_kgsbeq: 0.1,
+            adp_use_kgsbeq: 0.2,
+            pe_manufacture_megajoules: 0.3,
+            pe_use_megajoules: 0.4,
+            gwp_manufacture_kgco2eq: 0.5,
+            gwp_use_kgco2eq: 0.6,
+            regions: vec![RegionSummary {
+                account_id: None,
+                aws_region: "eu-west-1".to_string(),
+                country: "IRL".to_string(),
+                number_of_resources_total: 5,
+                number_of_resources_assessed: 2,
+                number_of_resources_not_assessed: 3,
+                adp_manufacture_kgsbeq: 0.1,
+                adp_use_kgsbeq: 0.2,
+                pe_manufacture_megajoules: 0.3,
+                pe_use_megajoules: 0.4,
+                gwp_manufacture_kgco2eq: 0.5,
+                gwp_use_kgco2eq: 0.6,
+            }],
+            sci: None,
+            groups: Vec::new(),
+            cost: None,
+            kubernetes: None,
+            parents: Vec::new(),
+        };
+
+        let lines = get_summary_line_protocol(&summary, 1700000000000000000);
+        assert_eq!(vec!["boavizta_summary,awsregion=eu-west-1,country=IRL number_of_resources_total=5i,number_of_resources_assessed=2i,number_of_resources_not_assessed=3i,duration_of_use_hours=1.0,adp_manufacture_kgsbeq=0.1,adp_use_kgsbeq=0.2,pe_manufacture_megajoules=0.3,pe_use_megajoules=0.4,gwp_manufacture_kgco2eq=0.5,gwp_use_kgco2eq=0.6 1700000000000000000"], lines);
+    }
+
+    #[test]
+    fn format_resources_as_line_protocol() {
+        let cloud_resource: CloudResource = CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: None,
+            id: "inst-1".to_string(),
+            location: UsageLocation::try_from("eu-west-3").unwrap(),
+            resource_details: ResourceDetails::Instance {
+                instance_type: "m6g.xlarge".to_string(),
+                usage: Some(InstanceUsage {
+                    average_cpu_load: 100.0,
+                    usage_duration_seconds: 3600,
+                    state: InstanceState::Running,
+                }),
+            },
+            tags: vec![CloudResourceTag {
+                key: "Name".to_string(),
+                value: Some("my app".to_string()),
+            }],
+            relationships: Vec::new(),
+        };
+        let mut not_assessed = cloud_resource.clone();
+        not_assessed.id = "inst-2".to_string();
+
+        let estimated_inventory: EstimatedInventory = EstimatedInventory {
+            impacting_resources: vec![
+                CloudResourceWithImpacts {
+                    cloud_resource,
+                    impacts_values: Some(ImpactsValues {
+                        adp_manufacture_kgsbeq: 0.1,
+                        adp_use_kgsbeq: 0.2,
+                        pe_manufacture_megajoules: 0.3,
+                        pe_use_megajoules: 0.4,
+                        gwp_manufacture_kgco2eq: 0.5,
+                        gwp_use_kgco2eq: 0.6,
+                        raw_data: None,
+                    }),
+                    impacts_duration_hours: 1.0,
+                    cost: None,
+                },
+                CloudResourceWithImpacts {
+                    cloud_resource: not_assessed,
+                    impacts_values: None,
+                    impacts_duration_hours: 1.0,
+                    cost: None,
+                },
+            ],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+
+        let body = get_all_line_protocol(&summary, &estimated_inventory, 42);
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(
+            2,
+            lines.len(),
+            "Resources without impacts should be skipped"
+        );
+        assert_eq!("boavizta_resource,awsregion=eu-west-3,country=FRA,resource_type=Instance,resource_id=inst-1,resource_tags=Name:my\\ app;,resource_state=Running duration_of_use_hours=1.0,adp_manufacture_kgsbeq=0.1,adp_use_kgsbeq=0.2,pe_manufacture_megajoules=0.3,pe_use_megajoules=0.4,gwp_manufacture_kgco2eq=0.5,gwp_use_kgco2eq=0.6,cpu_load=100.0 42", lines[1]);
+    }
+}
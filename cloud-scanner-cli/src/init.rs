@@ -0,0 +1,277 @@
+//! Interactive generation of a configuration file (`init` command): the answers to a few questions (provider, regions, Boavizta API URL, output defaults) become a profile of the configuration file.
+use anyhow::{Context, Result};
+use std::io::{BufRead, Write};
+
+use crate::config_file::ConfigFile;
+use crate::usage_location::UsageLocation;
+
+/// Answers to the questions of `init`
+#[derive(Clone, Debug, PartialEq)]
+pub struct InitAnswers {
+    pub profile_name: String,
+    pub provider: String,
+    pub regions: Vec<String>,
+    pub boavizta_api_url: String,
+    /// Duration of use, in hours or like `30d`
+    pub use_duration: String,
+    pub summary_only: bool,
+    /// Destination of the results (stdout when not set)
+    pub output: Option<String>,
+}
+
+/// Asks a question, returning the answer or the default answer when the answer is empty
+fn ask(
+    input: &mut impl BufRead,
+    prompt: &mut impl Write,
+    question: &str,
+    default: &str,
+) -> Result<String> {
+    if default.is_empty() {
+        write!(prompt, "{}: ", question)?;
+    } else {
+        write!(prompt, "{} [{}]: ", question, default)?;
+    }
+    prompt.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    let answer = answer.trim();
+    Ok(match answer {
+        "" => default.to_string(),
+        answer => answer.to_string(),
+    })
+}
+
+/// Asks a question until the answer is valid
+fn ask_valid<T>(
+    input: &mut impl BufRead,
+    prompt: &mut impl Write,
+    question: &str,
+    default: &str,
+    validate: impl Fn(&str) -> Result<T>,
+) -> Result<T> {
+    // Bounded, so that a closed input does not loop forever
+    for _ in 0..3 {
+        let answer = ask(input, prompt, question, default)?;
+        match validate(&answer) {
+            Ok(value) => return Ok(value),
+            Err(e) => writeln!(prompt, "  {:#}", e)?,
+        }
+    }
+    anyhow::bail!("No valid answer to: {}", question)
+}
+
+fn parse_regions(answer: &str) -> Result<Vec<String>> {
+    let regions: Vec<String> = answer
+        .split(',')
+        .map(str::trim)
+        .filter(|region| !region.is_empty())
+        .map(str::to_string)
+        .collect();
+    if regions.is_empty() {
+        anyhow::bail!("Expecting at least one region");
+    }
+    for region in regions.iter() {
+        UsageLocation::try_from(region.as_str())?;
+    }
+    Ok(regions)
+}
+
+fn parse_yes_no(answer: &str) -> Result<bool> {
+    match answer.to_lowercase().as_str() {
+        "y" | "yes" => Ok(true),
+        "n" | "no" => Ok(false),
+        _ => anyhow::bail!("Expecting y or n"),
+    }
+}
+
+/// Asks the questions of `init`, the default region being the region of the environment (if any)
+pub fn ask_questions(
+    input: &mut impl BufRead,
+    prompt: &mut impl Write,
+    default_region: Option<&str>,
+) -> Result<InitAnswers> {
+    let profile_name = ask(input, prompt, "Name of the profile", "default")?;
+    let provider = ask_valid(
+        input,
+        prompt,
+        "Cloud provider",
+        "aws",
+        |answer| match answer {
+            "aws" => Ok(answer.to_string()),
+            _ => anyhow::bail!("Only aws is supported"),
+        },
+    )?;
+    let regions = ask_valid(
+        input,
+        prompt,
+        "AWS regions to scan (separated by commas)",
+        default_region.unwrap_or("eu-west-1"),
+        parse_regions,
+    )?;
+    let boavizta_api_url = ask_valid(
+        input,
+        prompt,
+        "Boavizta API URL",
+        "https://api.boavizta.org",
+        |answer| match answer.starts_with("http://") || answer.starts_with("https://") {
+            true => Ok(answer.trim_end_matches('/').to_string()),
+            false => anyhow::bail!("Expecting an http(s) URL"),
+        },
+    )?;
+    let use_duration = ask_valid(
+        input,
+        prompt,
+        "Duration of use of the estimations (hours, or like 30d)",
+        "730h",
+        |answer| crate::duration::parse_hours(answer).map(|_| answer.to_string()),
+    )?;
+    let summary_only = ask_valid(
+        input,
+        prompt,
+        "Only output the summary of the impacts (y/n)",
+        "n",
+        parse_yes_no,
+    )?;
+    let output = ask(
+        input,
+        prompt,
+        "Destination of the results (a file, s3://bucket/prefix/, empty for stdout)",
+        "",
+    )?;
+    Ok(InitAnswers {
+        profile_name,
+        provider,
+        regions,
+        boavizta_api_url,
+        use_duration,
+        summary_only,
+        output: Some(output).filter(|output| !output.is_empty()),
+    })
+}
+
+/// Quotes a TOML string
+fn quoted(text: &str) -> String {
+    toml::Value::String(text.to_string()).to_string()
+}
+
+/// Returns a TOML key, quoted unless it is a bare key (like `prod` or `team-a`)
+fn key(name: &str) -> String {
+    let is_bare = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    match is_bare {
+        true => name.to_string(),
+        false => quoted(name),
+    }
+}
+
+impl InitAnswers {
+    /// Returns the configuration file with a profile of the answers (validated by parsing it)
+    pub fn to_config_file(&self) -> Result<String> {
+        let mut config = String::from(
+            "# Configuration of cloud scanner, generated by `cloud-scanner-cli init`\n",
+        );
+        config.push_str(&format!(
+            "# Options of the commands, selected with `--profile {}`\n",
+            self.profile_name
+        ));
+        config.push_str(&format!("[profiles.{}]\n", key(&self.profile_name)));
+        config.push_str(&format!("provider = {}\n", quoted(&self.provider)));
+        match self.regions.as_slice() {
+            [region] => config.push_str(&format!("aws_region = {}\n", quoted(region))),
+            regions => config.push_str(&format!(
+                "regions = [{}]\n",
+                regions
+                    .iter()
+                    .map(|region| quoted(region))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+        config.push_str(&format!(
+            "boavizta_api_url = {}\n",
+            quoted(&self.boavizta_api_url)
+        ));
+        config.push_str(&format!(
+            "use_duration_hours = {}\n",
+            quoted(&self.use_duration)
+        ));
+        config.push_str(&format!("summary_only = {}\n", self.summary_only));
+        if let Some(output) = &self.output {
+            config.push_str(&format!("output = {}\n", quoted(output)));
+        }
+        ConfigFile::parse(&config)
+            .and_then(|parsed| parsed.profile(&self.profile_name).map(|_| ()))
+            .context("Invalid generated configuration")?;
+        Ok(config)
+    }
+}
+
+/// Fails when the configuration file exists, unless it can be overwritten (`force`)
+pub fn check_overwrite(path: &str, force: bool) -> Result<()> {
+    if !force && std::path::Path::new(path).exists() {
+        anyhow::bail!(
+            "The configuration file {} already exists (use --force to overwrite it)",
+            path
+        );
+    }
+    Ok(())
+}
+
+/// Writes the configuration file, refusing to overwrite an existing file unless `force`
+pub fn write_config_file(path: &str, content: &str, force: bool) -> Result<()> {
+    check_overwrite(path, force)?;
+    std::fs::write(path, content)
+        .with_context(|| format!("Cannot write the configuration file {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_become_a_valid_profile() {
+        let mut input =
+            "prod\n\neu-west-3, mars-1\neu-west-3,eu-west-1\n\n30d\nyes\nresults.json\n".as_bytes();
+        let mut prompt = Vec::new();
+        let answers = ask_questions(&mut input, &mut prompt, None).unwrap();
+        assert_eq!(
+            InitAnswers {
+                profile_name: "prod".to_string(),
+                provider: "aws".to_string(),
+                regions: vec!["eu-west-3".to_string(), "eu-west-1".to_string()],
+                boavizta_api_url: "https://api.boavizta.org".to_string(),
+                use_duration: "30d".to_string(),
+                summary_only: true,
+                output: Some("results.json".to_string()),
+            },
+            answers
+        );
+        let prompt = String::from_utf8(prompt).unwrap();
+        assert!(prompt.contains("Cloud provider [aws]: "), "{}", prompt);
+        assert!(prompt.contains("mars-1"), "{}", prompt);
+
+        let config = ConfigFile::parse(&answers.to_config_file().unwrap()).unwrap();
+        let profile = config.profile("prod").unwrap();
+        assert_eq!(vec!["eu-west-3", "eu-west-1"], profile.regions);
+        assert_eq!(Some(720.0), profile.use_duration_hours);
+        assert!(profile.summary_only);
+
+        // Default answers, until the end of the input
+        let answers =
+            ask_questions(&mut "".as_bytes(), &mut Vec::new(), Some("us-east-1")).unwrap();
+        assert_eq!(vec!["us-east-1"], answers.regions);
+        assert_eq!(None, answers.output);
+        assert!(answers
+            .to_config_file()
+            .unwrap()
+            .contains("aws_region = \"us-east-1\""));
+        assert!(ask_questions(
+            &mut "\nazure\nazure\nazure\n".as_bytes(),
+            &mut Vec::new(),
+            None
+        )
+        .is_err());
+    }
+}
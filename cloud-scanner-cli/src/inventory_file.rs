@@ -0,0 +1,443 @@
+//! Inventories saved by the `inventory` command, so that their impacts are estimated elsewhere: listing the resources needs credentials of the account, estimating their impacts only needs Boavizta API.
+//!
+//! Inventory files are an interchange format between tools (see the `inventory-file` JSON Schema): the resources are wrapped in the metadata envelope of results (see [crate::result_envelope]), whose `schema_version` is incremented on breaking changes only. Reading is forward compatible: unknown fields are ignored, and resources of kinds or providers unknown to this version are skipped with a warning instead of failing the whole file.
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+use crate::aws_cloud_provider::AwsAccount;
+use crate::model::{CloudResource, ExecutionStatistics, Inventory};
+use crate::result_envelope::{ResultMetadata, ScanParameters, SCHEMA_VERSION};
+
+/// Kinds of resources (the variants of [crate::model::ResourceDetails]) known to this version
+const KNOWN_KINDS: [&str; 4] = ["Instance", "BlockStorage", "ObjectStorage", "Server"];
+
+/// Providers of resources (the variants of [crate::model::CloudProvider]) known to this version
+const KNOWN_PROVIDERS: [&str; 5] = ["AWS", "OVH", "Azure", "GCP", "OnPremises"];
+
+/// An inventory file, its resources not being read yet
+struct InventoryContent {
+    metadata: Option<ResultMetadata>,
+    resources: Vec<Value>,
+    execution_statistics: Option<ExecutionStatistics>,
+}
+
+/// A resource of an inventory file
+enum ResourceReading {
+    Read(CloudResource),
+    /// A resource of a kind or provider unknown to this version (with the reason)
+    Skipped(String),
+    Invalid(String),
+}
+
+/// Splits an inventory (json) in its metadata and resources: the resources of the `inventory` command (with or without metadata envelope), or an inventory as returned by the server
+fn split_inventory(content: &str) -> Result<InventoryContent> {
+    let json: Value = serde_json::from_str(content)
+        .context("Expected the json output of the inventory command")?;
+    let (metadata, data) = match json {
+        Value::Object(mut object)
+            if object.contains_key("metadata") && object.contains_key("data") =>
+        {
+            let metadata: ResultMetadata =
+                serde_json::from_value(object.remove("metadata").unwrap_or_default())
+                    .context("Invalid metadata of the inventory")?;
+            (Some(metadata), object.remove("data").unwrap_or_default())
+        }
+        json => (None, json),
+    };
+    let (resources, execution_statistics) = match data {
+        Value::Array(resources) => (resources, None),
+        Value::Object(mut inventory) => match inventory.remove("resources") {
+            Some(Value::Array(resources)) => (
+                resources,
+                inventory
+                    .remove("executionStatistics")
+                    .and_then(|statistics| serde_json::from_value(statistics).ok()),
+            ),
+            _ => anyhow::bail!("Expected the json output of the inventory command (no resources)"),
+        },
+        _ => anyhow::bail!("Expected the json output of the inventory command (no resources)"),
+    };
+    Ok(InventoryContent {
+        metadata,
+        resources,
+        execution_statistics,
+    })
+}
+
+/// Reads the resource at `index` of an inventory
+fn read_resource(index: usize, resource: Value) -> ResourceReading {
+    let id = resource["id"].as_str().unwrap_or("without id").to_string();
+    let details = &resource["resource_details"];
+    // Unit variants are serialized as strings, the other variants as objects of a single key
+    let kind = details.as_str().or_else(|| {
+        details
+            .as_object()
+            .and_then(|d| d.keys().next())
+            .map(String::as_str)
+    });
+    let provider = resource["provider"].as_str();
+    match serde_json::from_value::<CloudResource>(resource.clone()) {
+        Ok(resource) => ResourceReading::Read(resource),
+        Err(_) if kind.is_some_and(|kind| !KNOWN_KINDS.contains(&kind)) => {
+            ResourceReading::Skipped(format!(
+                "Resource {} (at index {}) is of a kind unknown to this version ({})",
+                id,
+                index,
+                kind.unwrap_or_default()
+            ))
+        }
+        Err(_) if provider.is_some_and(|provider| !KNOWN_PROVIDERS.contains(&provider)) => {
+            ResourceReading::Skipped(format!(
+                "Resource {} (at index {}) is of a provider unknown to this version ({})",
+                id,
+                index,
+                provider.unwrap_or_default()
+            ))
+        }
+        Err(e) => ResourceReading::Invalid(format!(
+            "Invalid resource {} (at index {}): {}",
+            id, index, e
+        )),
+    }
+}
+
+/// Parses an inventory (json), with or without metadata envelope, the resources of unknown kinds or providers being skipped
+pub fn parse_inventory(content: &str) -> Result<(Option<ResultMetadata>, Inventory)> {
+    let content = split_inventory(content)?;
+    if let Some(metadata) = &content.metadata {
+        if metadata.schema_version > SCHEMA_VERSION {
+            warn!(
+                "Inventory uses schema version {} which is more recent than the version supported by this cloud scanner ({}), some fields may be ignored",
+                metadata.schema_version, SCHEMA_VERSION
+            );
+        }
+    }
+    let mut resources = Vec::new();
+    for (index, resource) in content.resources.into_iter().enumerate() {
+        match read_resource(index, resource) {
+            ResourceReading::Read(resource) => resources.push(resource),
+            ResourceReading::Skipped(reason) => warn!("{}, it is ignored", reason),
+            ResourceReading::Invalid(error) => anyhow::bail!(error),
+        }
+    }
+    Ok((
+        content.metadata,
+        Inventory {
+            resources,
+            execution_statistics: content.execution_statistics,
+        },
+    ))
+}
+
+/// Result of the validation of an inventory file
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct InventoryValidation {
+    /// Version of the schema of the file, not set for inventories written without metadata
+    pub schema_version: Option<u32>,
+    /// Number of resources read
+    pub number_of_resources: usize,
+    /// Problems that do not prevent the estimation, like the resources of unknown kinds (ignored)
+    pub warnings: Vec<String>,
+    /// Problems that prevent reading the inventory
+    pub errors: Vec<String>,
+}
+
+impl InventoryValidation {
+    /// Returns true when the inventory can be read
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Returns the validation as text: the schema version, the number of resources, then the errors and warnings
+    pub fn to_text(&self) -> String {
+        let mut text = match self.schema_version {
+            Some(version) => format!("Schema version: {}\n", version),
+            None => "Schema version: none (inventory without metadata)\n".to_string(),
+        };
+        text.push_str(&format!("Resources: {}\n", self.number_of_resources));
+        for error in self.errors.iter() {
+            text.push_str(&format!("Error: {}\n", error));
+        }
+        for warning in self.warnings.iter() {
+            text.push_str(&format!("Warning: {}\n", warning));
+        }
+        text.push_str(if self.is_valid() {
+            "Valid inventory\n"
+        } else {
+            "Invalid inventory\n"
+        });
+        text
+    }
+}
+
+/// Validates an inventory (json): its metadata, and each of its resources
+pub fn validate_inventory(content: &str) -> InventoryValidation {
+    let mut validation = InventoryValidation::default();
+    let content = match split_inventory(content) {
+        Ok(content) => content,
+        Err(e) => {
+            validation.errors.push(format!("{:#}", e));
+            return validation;
+        }
+    };
+    match &content.metadata {
+        Some(metadata) => {
+            validation.schema_version = Some(metadata.schema_version);
+            if metadata.schema_version > SCHEMA_VERSION {
+                validation.warnings.push(format!(
+                    "Schema version {} is more recent than the version supported by this cloud scanner ({}), some fields may be ignored",
+                    metadata.schema_version, SCHEMA_VERSION
+                ));
+            }
+        }
+        None => validation.warnings.push(
+            "No metadata: the regions and accounts of the estimation are the regions and accounts of the resources".to_string(),
+        ),
+    }
+    let mut ids = HashSet::new();
+    for (index, resource) in content.resources.into_iter().enumerate() {
+        match read_resource(index, resource) {
+            ResourceReading::Read(resource) => {
+                validation.number_of_resources += 1;
+                if !ids.insert(resource.id.clone()) {
+                    validation.warnings.push(format!(
+                        "Resource {} (at index {}) is listed several times",
+                        resource.id, index
+                    ));
+                }
+            }
+            ResourceReading::Skipped(reason) => validation
+                .warnings
+                .push(format!("{}, it is ignored", reason)),
+            ResourceReading::Invalid(error) => validation.errors.push(error),
+        }
+    }
+    validation
+}
+
+/// Reads an inventory from a json file (output of the `inventory` command)
+pub fn read_inventory(path: &str) -> Result<(Option<ResultMetadata>, Inventory)> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("Cannot read inventory {}", path))?;
+    parse_inventory(&content).with_context(|| format!("Cannot parse inventory {}", path))
+}
+
+/// Returns the regions of an inventory: the regions of its scan (including regions without resources), or the regions of its resources for inventories without metadata
+pub fn inventory_regions(metadata: Option<&ResultMetadata>, inventory: &Inventory) -> Vec<String> {
+    let mut regions: Vec<String> = match metadata {
+        Some(metadata) => metadata
+            .parameters
+            .aws_region
+            .split(',')
+            .filter(|region| !region.is_empty())
+            .map(str::to_string)
+            .collect(),
+        None => Vec::new(),
+    };
+    for resource in inventory.resources.iter() {
+        if !regions.contains(&resource.location.aws_region) {
+            regions.push(resource.location.aws_region.clone());
+        }
+    }
+    regions
+}
+
+/// Returns the accounts of the resources of an inventory (without role, the resources being already listed), none for an inventory of the account of the environment
+pub fn inventory_accounts(inventory: &Inventory) -> Vec<AwsAccount> {
+    let mut accounts: Vec<AwsAccount> = Vec::new();
+    for account_id in inventory
+        .resources
+        .iter()
+        .filter_map(|r| r.account_id.as_ref())
+    {
+        if !accounts.iter().any(|a| &a.account_id == account_id) {
+            accounts.push(AwsAccount {
+                account_id: account_id.clone(),
+                role_arn: String::new(),
+                external_id: None,
+            });
+        }
+    }
+    accounts
+}
+
+/// Merges inventories (like the inventories of several runs, accounts or providers) into a single inventory, ready for estimation.
+///
+/// A resource listed by several inventories is kept once, as listed by the last of them. The metadata is the metadata of a scan of the regions of all the inventories, at the time of the most recent of them, with their filters when they share them.
+pub fn merge_inventories(
+    inventories: Vec<(Option<ResultMetadata>, Inventory)>,
+) -> (ResultMetadata, Inventory) {
+    let mut regions: Vec<String> = Vec::new();
+    let mut resources: Vec<CloudResource> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let parameters: Vec<Option<&ScanParameters>> = inventories
+        .iter()
+        .map(|(metadata, _)| metadata.as_ref().map(|metadata| &metadata.parameters))
+        .collect();
+    let shared = |value: &dyn Fn(&ScanParameters) -> Vec<String>| -> Vec<String> {
+        let mut values = parameters.iter().map(|p| p.map(value));
+        match values.next().flatten() {
+            Some(first) if values.all(|v| v.as_ref() == Some(&first)) => first,
+            _ => Vec::new(),
+        }
+    };
+    let filter_tags = shared(&|p| p.filter_tags.clone());
+    let include_states = shared(&|p| p.include_states.clone());
+    let scan_timestamp = inventories
+        .iter()
+        .filter_map(|(metadata, _)| metadata.as_ref().map(|m| m.scan_timestamp))
+        .max()
+        .unwrap_or_else(chrono::Utc::now);
+    let mut include_block_storage = parameters.iter().flatten().any(|p| p.include_block_storage);
+
+    for (metadata, inventory) in inventories {
+        for region in inventory_regions(metadata.as_ref(), &inventory) {
+            if !regions.contains(&region) {
+                regions.push(region);
+            }
+        }
+        for resource in inventory.resources {
+            include_block_storage |= resource.resource_details.kind() == "BlockStorage";
+            match positions.get(&resource.id) {
+                Some(position) => {
+                    debug!("Resource {} is listed by several inventories", resource.id);
+                    resources[*position] = resource;
+                }
+                None => {
+                    positions.insert(resource.id.clone(), resources.len());
+                    resources.push(resource);
+                }
+            }
+        }
+    }
+    let metadata = ResultMetadata::new(
+        scan_timestamp,
+        ScanParameters {
+            aws_region: regions.join(","),
+            use_duration_hours: None,
+            filter_tags,
+            include_block_storage,
+            include_states,
+            verbose: false,
+        },
+        None,
+    );
+    (
+        metadata,
+        Inventory {
+            resources,
+            execution_statistics: None,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inventories_are_read_with_or_without_envelope() {
+        let resources = r#"[{"provider": "AWS", "id": "i-1", "location": {"aws_region": "eu-west-1", "iso_country_code": "IRL"}, "resource_details": {"Instance": {"instance_type": "m6g.xlarge", "usage": null}}, "tags": [], "account_id": "111111111111"}]"#;
+        let enveloped = format!(
+            r#"{{"metadata": {{"schema_version": 1, "cloud_scanner_version": "2.0.5", "provider": "aws", "scan_timestamp": "2024-04-12T10:15:00Z", "parameters": {{"aws_region": "eu-west-3,eu-west-1", "filter_tags": [], "include_block_storage": false}}}}, "data": {}}}"#,
+            resources
+        );
+        let (metadata, inventory) = parse_inventory(&enveloped).unwrap();
+        assert_eq!(1, inventory.resources.len());
+        assert_eq!(
+            vec!["eu-west-3", "eu-west-1"],
+            inventory_regions(metadata.as_ref(), &inventory)
+        );
+        assert_eq!("111111111111", inventory_accounts(&inventory)[0].account_id);
+
+        let (metadata, inventory) = parse_inventory(resources).unwrap();
+        assert!(metadata.is_none());
+        assert_eq!(vec!["eu-west-1"], inventory_regions(None, &inventory));
+
+        let (_, inventory) =
+            parse_inventory(r#"{"resources": [], "executionStatistics": null}"#).unwrap();
+        assert!(inventory_accounts(&inventory).is_empty());
+        assert!(parse_inventory(r#"{"instances": []}"#).is_err());
+    }
+
+    #[test]
+    fn resources_unknown_to_this_version_are_skipped() {
+        let inventory = r#"{"metadata": {"schema_version": 2, "cloud_scanner_version": "9.0.0", "provider": "aws", "scan_timestamp": "2024-04-12T10:15:00Z", "parameters": {"aws_region": "eu-west-1", "filter_tags": [], "include_block_storage": false}, "new_field": true}, "data": [
+            {"provider": "AWS", "id": "i-1", "location": {"aws_region": "eu-west-1", "iso_country_code": "IRL"}, "resource_details": {"Instance": {"instance_type": "m6g.xlarge", "usage": null}}, "tags": [], "new_field": 1},
+            {"provider": "AWS", "id": "fn-1", "location": {"aws_region": "eu-west-1", "iso_country_code": "IRL"}, "resource_details": {"Function": {"memory_mb": 512}}, "tags": []},
+            {"provider": "Scaleway", "id": "vm-1", "location": {"aws_region": "eu-west-1", "iso_country_code": "IRL"}, "resource_details": {"Instance": {"instance_type": "e2-medium", "usage": null}}, "tags": []},
+            {"provider": "AWS", "id": "i-1", "location": {"aws_region": "eu-west-1", "iso_country_code": "IRL"}, "resource_details": {"Instance": {"instance_type": "m6g.xlarge", "usage": null}}, "tags": []}
+        ]}"#;
+        let (metadata, parsed) = parse_inventory(inventory).unwrap();
+        assert_eq!(2, metadata.unwrap().schema_version);
+        assert_eq!(2, parsed.resources.len());
+
+        let validation = validate_inventory(inventory);
+        assert!(validation.is_valid());
+        assert_eq!(Some(2), validation.schema_version);
+        assert_eq!(2, validation.number_of_resources);
+        // A newer schema, a function, a resource of Scaleway and a duplicate
+        assert_eq!(4, validation.warnings.len(), "{:?}", validation.warnings);
+        assert!(validation.to_text().ends_with("Valid inventory\n"));
+
+        let invalid = r#"[{"provider": "AWS", "id": "i-1", "resource_details": {"Instance": {"instance_type": "m6g.xlarge"}}, "tags": []}]"#;
+        assert!(parse_inventory(invalid).is_err());
+        let validation = validate_inventory(invalid);
+        assert!(!validation.is_valid());
+        assert!(validation.errors[0].contains("i-1"));
+        assert!(!validate_inventory("{").is_valid());
+    }
+
+    #[test]
+    fn inventories_are_merged_once_per_resource() {
+        let resource = |id: &str, region: &str, instance_type: &str| {
+            format!(
+                r#"{{"provider": "AWS", "id": "{}", "location": {{"aws_region": "{}", "iso_country_code": "FRA"}}, "resource_details": {{"Instance": {{"instance_type": "{}", "usage": null}}}}, "tags": []}}"#,
+                id, region, instance_type
+            )
+        };
+        let first = format!(
+            r#"{{"metadata": {{"schema_version": 1, "cloud_scanner_version": "2.0.5", "provider": "aws", "scan_timestamp": "2024-04-12T10:15:00Z", "parameters": {{"aws_region": "eu-west-3,eu-central-1", "filter_tags": ["team=web"], "include_block_storage": false}}}}, "data": [{}, {}]}}"#,
+            resource("i-1", "eu-west-3", "t3.micro"),
+            resource("i-2", "eu-west-3", "t3.micro")
+        );
+        let second = format!(
+            r#"{{"metadata": {{"schema_version": 1, "cloud_scanner_version": "2.0.5", "provider": "aws", "scan_timestamp": "2024-04-13T10:15:00Z", "parameters": {{"aws_region": "eu-west-1", "filter_tags": ["team=web"], "include_block_storage": true}}}}, "data": [{}, {}]}}"#,
+            resource("i-2", "eu-west-1", "m6g.xlarge"),
+            resource("i-3", "eu-west-1", "c5.large")
+        );
+        let inventories = vec![
+            parse_inventory(&first).unwrap(),
+            parse_inventory(&second).unwrap(),
+        ];
+        let (metadata, inventory) = merge_inventories(inventories);
+        let ids: Vec<&str> = inventory.resources.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(vec!["i-1", "i-2", "i-3"], ids);
+        assert_eq!(
+            Some("m6g.xlarge".to_string()),
+            inventory.resources[1].resource_details.resource_type()
+        );
+        assert_eq!(
+            "eu-west-3,eu-central-1,eu-west-1",
+            metadata.parameters.aws_region
+        );
+        assert_eq!(vec!["team=web"], metadata.parameters.filter_tags);
+        assert!(metadata.parameters.include_block_storage);
+        assert_eq!(
+            "2024-04-13T10:15:00Z",
+            metadata
+                .scan_timestamp
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        );
+
+        let bare = format!("[{}]", resource("i-4", "us-east-1", "t3.micro"));
+        let (metadata, inventory) = merge_inventories(vec![
+            parse_inventory(&first).unwrap(),
+            parse_inventory(&bare).unwrap(),
+        ]);
+        assert_eq!(3, inventory.resources.len());
+        assert!(metadata.parameters.filter_tags.is_empty());
+    }
+}
@@ -0,0 +1,286 @@
+//! Callbacks of scan jobs: when a job submitted with a `callback_url` finishes, the server posts the job (with the summary of its impacts when it completed) to the URL.
+//!
+//! When a secret is configured, the body is signed with HMAC-SHA256 in the `X-Cloud-Scanner-Signature` header (`t=<unix timestamp>,sha256=<hex digest of "<timestamp>.<body>">`), so that receivers can check that the callback comes from the server and reject replayed callbacks.
+//!
+//! Callbacks are only posted with https to public addresses, so that the clients of the server cannot make it call its own network (server-side request forgery): the hosts resolving to loopback, private or link-local addresses (like the metadata endpoint of EC2) are rejected, unless allowed by the configuration (with http too). Each callback is posted to the address that was checked, without following redirects.
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use ipnet::IpNet;
+use reqwest::Url;
+use serde::Serialize;
+use sha2::Sha256;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use crate::impact_provider::ImpactsSummary;
+use crate::scan_jobs::{Job, JobResult, JobStore};
+
+/// Header of the signature of the body
+pub const SIGNATURE_HEADER: &str = "X-Cloud-Scanner-Signature";
+
+/// Number of attempts to post a callback, with a doubling delay between attempts
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Body posted to the callback URL
+#[derive(Clone, Debug, Serialize)]
+pub struct JobCallback {
+    pub job: Job,
+    /// Summary of the impacts, when the job completed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<ImpactsSummary>,
+}
+
+/// Returns true for the addresses of the network of the server: loopback, private, link-local, shared (carrier-grade NAT) and unspecified addresses
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || (ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_internal(IpAddr::V4(ip)),
+            None => {
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    // Unique local (fc00::/7) and link-local (fe80::/10) addresses
+                    || (ip.segments()[0] & 0xfe00) == 0xfc00
+                    || (ip.segments()[0] & 0xffc0) == 0xfe80
+            }
+        },
+    }
+}
+
+/// Returns true when a host (name or address) is in the allowed hosts (names, addresses or networks like `10.0.0.0/8`)
+fn is_allowed(host: &str, ip: Option<IpAddr>, allowed_hosts: &[String]) -> bool {
+    allowed_hosts.iter().any(|allowed| {
+        match (allowed.parse::<IpNet>(), allowed.parse::<IpAddr>(), ip) {
+            (Ok(network), _, Some(ip)) => network.contains(&ip),
+            (_, Ok(allowed), Some(ip)) => allowed == ip,
+            _ => allowed.eq_ignore_ascii_case(host),
+        }
+    })
+}
+
+/// Checks that a callback URL can be posted to (https to a public address, unless its host is allowed), returns the URL and the address of its host
+pub async fn resolve_callback_url(
+    url: &str,
+    allowed_hosts: &[String],
+) -> Result<(Url, SocketAddr)> {
+    let parsed = Url::parse(url).with_context(|| format!("Invalid callback URL {}", url))?;
+    // Without the brackets of IPv6 addresses
+    let host = parsed
+        .host_str()
+        .with_context(|| format!("Callback URL without host {}", url))?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    let host_allowed = is_allowed(&host, None, allowed_hosts);
+    match parsed.scheme() {
+        "https" => {}
+        "http" if host_allowed => {}
+        "http" => anyhow::bail!("Callback URL {} is not https", url),
+        scheme => anyhow::bail!("Unsupported scheme {} of callback URL", scheme),
+    }
+    let port = parsed.port_or_known_default().unwrap_or(443);
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), port))
+        .await
+        .with_context(|| format!("Cannot resolve the host of callback URL {}", url))?
+        .collect();
+    for address in &addresses {
+        if is_internal(address.ip())
+            && !host_allowed
+            && !is_allowed(&host, Some(address.ip()), allowed_hosts)
+        {
+            anyhow::bail!(
+                "Callback URL {} resolves to the internal address {}, which is not allowed",
+                url,
+                address.ip()
+            );
+        }
+    }
+    let address = *addresses
+        .first()
+        .with_context(|| format!("No address for the host of callback URL {}", url))?;
+    Ok((parsed, address))
+}
+
+/// Returns the value of the signature header of a body sent at a time (unix timestamp)
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(body);
+    format!(
+        "t={},sha256={}",
+        timestamp,
+        hex::encode(mac.finalize().into_bytes())
+    )
+}
+
+/// Returns the callback of a finished job, none when the job is forgotten
+pub fn job_callback(jobs: &JobStore, id: &str) -> Result<Option<JobCallback>> {
+    let Some(result) = jobs.result(id) else {
+        return Ok(None);
+    };
+    let (job, summary) = match result {
+        JobResult::Completed(estimated_inventory) => {
+            let Some(job) = jobs.get(id) else {
+                return Ok(None);
+            };
+            let summary = crate::build_summary(
+                &estimated_inventory,
+                &job.request.aws_region,
+                &job.request.use_duration_hours,
+            )?;
+            (job, Some(summary))
+        }
+        JobResult::NotAvailable(job) => (*job, None),
+    };
+    Ok(Some(JobCallback { job, summary }))
+}
+
+/// Posts a callback to its URL (signed when a secret is provided) if it is allowed, retries when the receiver fails
+pub async fn send(
+    url: &str,
+    secret: Option<&str>,
+    allowed_hosts: &[String],
+    callback: &JobCallback,
+) -> Result<()> {
+    let body = serde_json::to_vec(callback).context("Cannot serialize callback")?;
+    // The host is resolved once, so that it cannot resolve to another address once checked
+    let (url, address) = resolve_callback_url(url, allowed_hosts).await?;
+    let mut client = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none());
+    if let Some(domain) = url
+        .host_str()
+        .filter(|host| host.parse::<IpAddr>().is_err())
+    {
+        client = client.resolve(domain, address);
+    }
+    let client = client
+        .build()
+        .context("Cannot create the client of callbacks")?;
+    let mut delay = Duration::from_secs(1);
+    for attempt in 1..=MAX_ATTEMPTS {
+        let mut request = client
+            .post(url.clone())
+            .header("Content-Type", "application/json")
+            .body(body.clone());
+        if let Some(secret) = secret {
+            request = request.header(
+                SIGNATURE_HEADER,
+                sign(secret, chrono::Utc::now().timestamp(), &body),
+            );
+        }
+        match request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+        {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt < MAX_ATTEMPTS => {
+                warn!(
+                    "Callback of job {} failed (attempt {}): {}",
+                    callback.job.id, attempt, e
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Cannot post callback to {}", url));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Posts the callback of a finished job, failures are logged
+pub async fn notify(
+    jobs: &JobStore,
+    id: &str,
+    url: &str,
+    secret: Option<&str>,
+    allowed_hosts: &[String],
+) {
+    let result = match job_callback(jobs, id) {
+        Ok(Some(callback)) => send(url, secret, allowed_hosts, &callback).await,
+        Ok(None) => return,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => info!("Callback of job {} posted", id),
+        Err(e) => error!("Callback of job {} failed: {:#}", id, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bodies_are_signed_with_hmac_sha256_and_their_timestamp() {
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"Jefe").unwrap();
+        mac.update(b"1717171717.what do ya want for nothing?");
+        assert_eq!(
+            format!(
+                "t=1717171717,sha256={}",
+                hex::encode(mac.finalize().into_bytes())
+            ),
+            sign("Jefe", 1717171717, b"what do ya want for nothing?")
+        );
+        assert_ne!(
+            sign("Jefe", 1717171717, b"body"),
+            sign("Jefe", 1717171718, b"body")
+        );
+    }
+
+    #[tokio::test]
+    async fn callback_urls_are_https_to_public_addresses() {
+        let resolve = |url: &'static str, allowed: &'static [&'static str]| async move {
+            let allowed: Vec<String> = allowed.iter().map(|host| host.to_string()).collect();
+            resolve_callback_url(url, &allowed).await
+        };
+        let (url, address) = resolve("https://93.184.215.14:8443/hooks/scan", &[])
+            .await
+            .unwrap();
+        assert_eq!("/hooks/scan", url.path());
+        assert_eq!("93.184.215.14:8443", address.to_string());
+        assert!(resolve("http://93.184.215.14/hooks/scan", &[])
+            .await
+            .is_err());
+        assert!(resolve("file:///etc/passwd", &[]).await.is_err());
+        assert!(resolve("not a url", &[]).await.is_err());
+
+        // Internal addresses, including the hosts resolving to them
+        for internal in [
+            "https://127.0.0.1/",
+            "https://localhost:9000/",
+            "https://10.1.2.3/",
+            "https://172.16.0.1/",
+            "https://192.168.1.1/",
+            "https://169.254.169.254/latest/meta-data/",
+            "https://100.64.0.1/",
+            "https://0.0.0.0/",
+            "https://[::1]/",
+            "https://[fd00::1]/",
+            "https://[fe80::1]/",
+            "https://[::ffff:10.0.0.1]/",
+        ] {
+            let error = resolve(internal, &[]).await.unwrap_err();
+            assert!(error.to_string().contains("not allowed"), "{}", internal);
+        }
+
+        // Unless allowed, by name, address or network (http included)
+        assert!(resolve("http://localhost:9000/", &["localhost"])
+            .await
+            .is_ok());
+        assert!(resolve("https://10.1.2.3/", &["10.0.0.0/8"]).await.is_ok());
+        assert!(resolve("https://[fd00::1]/", &["fd00::1"]).await.is_ok());
+        assert!(resolve("https://10.1.2.3/", &["192.168.0.0/16"])
+            .await
+            .is_err());
+    }
+}
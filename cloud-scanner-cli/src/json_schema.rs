@@ -0,0 +1,93 @@
+//! A module to export the JSON Schemas of the results of cloud scanner, so that consumers can validate them or generate code from them.
+use anyhow::{Context, Result};
+use schemars::schema::RootSchema;
+use schemars::schema_for;
+use serde_json::{Map, Value};
+
+use crate::impact_provider::ImpactsSummary;
+use crate::model::{CloudResource, EstimatedInventory, Inventory};
+use crate::result_envelope::{ResultEnvelope, ResultMetadata};
+use crate::scan_diff::ScanDiff;
+use crate::top_emitters::TopEmitters;
+
+/// Names of the types that have a schema, with a description
+pub const SCHEMA_TYPES: [(&str, &str); 7] = [
+    (
+        "estimated-inventory",
+        "Resources and their impacts (json output of the estimate command and of the /impacts route of the server)",
+    ),
+    (
+        "impacts-summary",
+        "Summary of impacts (json output of the estimate command with --summary-only)",
+    ),
+    (
+        "inventory",
+        "Resources without impacts (json output of the inventory command and of the /inventory route of the server)",
+    ),
+    (
+        "inventory-file",
+        "Inventory file: resources in the metadata envelope (json output of the inventory and inventory merge commands, read by estimate --inventory-file)",
+    ),
+    (
+        "result-metadata",
+        "Metadata of the envelope wrapping the json results of the estimate and inventory commands (results are in the data field)",
+    ),
+    ("scan-diff", "Differences between two scans (diff command with --as-json)"),
+    ("top-emitters", "Resources with the highest impacts (estimate command with --top and --top-as-json)"),
+];
+
+fn root_schema(type_name: &str) -> Result<RootSchema> {
+    let schema = match type_name {
+        "estimated-inventory" => schema_for!(EstimatedInventory),
+        "impacts-summary" => schema_for!(ImpactsSummary),
+        "inventory" => schema_for!(Inventory),
+        "inventory-file" => schema_for!(ResultEnvelope<Vec<CloudResource>>),
+        "result-metadata" => schema_for!(ResultMetadata),
+        "scan-diff" => schema_for!(ScanDiff),
+        "top-emitters" => schema_for!(TopEmitters),
+        _ => {
+            let names: Vec<&str> = SCHEMA_TYPES.iter().map(|(name, _)| *name).collect();
+            anyhow::bail!(
+                "Unknown type {} (expected one of {})",
+                type_name,
+                names.join(", ")
+            )
+        }
+    };
+    Ok(schema)
+}
+
+/// Returns the JSON Schema of a type (pretty printed)
+pub fn get_schema(type_name: &str) -> Result<String> {
+    let schema = root_schema(type_name)?;
+    serde_json::to_string_pretty(&schema).context("Cannot format JSON Schema")
+}
+
+/// Returns the JSON Schemas of all the types, as a json object indexed by type name
+pub fn get_all_schemas() -> Result<String> {
+    let mut schemas = Map::new();
+    for (type_name, _) in SCHEMA_TYPES {
+        let schema = serde_json::to_value(root_schema(type_name)?)?;
+        schemas.insert(type_name.to_string(), schema);
+    }
+    serde_json::to_string_pretty(&Value::Object(schemas)).context("Cannot format JSON Schemas")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_type_has_a_schema() {
+        for (type_name, _) in SCHEMA_TYPES {
+            let schema: Value = serde_json::from_str(&get_schema(type_name).unwrap()).unwrap();
+            assert!(schema["title"].is_string(), "No title for {}", type_name);
+        }
+        let schema: Value = serde_json::from_str(&get_schema("impacts-summary").unwrap()).unwrap();
+        assert!(schema["properties"]["regions"].is_object());
+
+        let all: Value = serde_json::from_str(&get_all_schemas().unwrap()).unwrap();
+        assert_eq!(SCHEMA_TYPES.len(), all.as_object().unwrap().len());
+        assert!(get_schema("unknown").is_err());
+    }
+}
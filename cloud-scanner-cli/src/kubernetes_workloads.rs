@@ -0,0 +1,430 @@
+//! Workloads of a Kubernetes cluster (the pods of its Deployments, StatefulSets, DaemonSets..., as listed by `kubectl get nodes,pods --all-namespaces -o json`), so that the impacts of its nodes are allocated to its namespaces and workloads.
+//!
+//! The nodes are matched to the instances of the scan by their provider id (like `aws:///eu-west-3a/i-0123456789abcdef0`). The impacts of a node are allocated to the pods running on it in proportion to their requests: the share of a pod is the average of its share of the allocatable CPU and of its share of the allocatable memory of the node. The impacts of the capacity requested by no pod are unallocated.
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+use crate::model::EstimatedInventory;
+
+/// CPU (in millicores) and memory (in bytes) requested by a pod, or allocatable on a node
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Requests {
+    cpu_millis: f64,
+    memory_bytes: f64,
+}
+
+impl Requests {
+    fn of(resources: &Value) -> Requests {
+        Requests {
+            cpu_millis: resources["cpu"]
+                .as_str()
+                .and_then(parse_quantity)
+                .map(|cores| cores * 1000.0)
+                .unwrap_or_default(),
+            memory_bytes: resources["memory"]
+                .as_str()
+                .and_then(parse_quantity)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Returns the share of the allocatable resources of a node requested
+    fn share_of(&self, allocatable: &Requests) -> f64 {
+        let share = |requested: f64, allocatable: f64| match allocatable > 0.0 {
+            true => requested / allocatable,
+            false => 0.0,
+        };
+        (share(self.cpu_millis, allocatable.cpu_millis)
+            + share(self.memory_bytes, allocatable.memory_bytes))
+            / 2.0
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    name: String,
+    /// The EC2 instance of the node (from its provider id)
+    instance_id: Option<String>,
+    allocatable: Requests,
+}
+
+#[derive(Clone, Debug)]
+struct Pod {
+    namespace: String,
+    workload_kind: String,
+    workload_name: String,
+    node_name: String,
+    requests: Requests,
+}
+
+/// The nodes and the pods of a Kubernetes cluster
+#[derive(Clone, Debug, Default)]
+pub struct KubernetesCluster {
+    nodes: Vec<Node>,
+    pods: Vec<Pod>,
+}
+
+impl KubernetesCluster {
+    /// Parses the nodes and pods of a cluster (json output of `kubectl get nodes,pods --all-namespaces -o json`), pods that are not scheduled or that ended being ignored
+    pub fn parse(content: &str) -> Result<KubernetesCluster> {
+        let json: Value = serde_json::from_str(content)
+            .context("Expected the json output of kubectl get nodes,pods -o json")?;
+        let items = json["items"]
+            .as_array()
+            .context("Expected the json output of kubectl get nodes,pods -o json (no items)")?;
+        let mut cluster = KubernetesCluster::default();
+        for item in items {
+            match item["kind"].as_str() {
+                Some("Node") => cluster.nodes.push(Node {
+                    name: item["metadata"]["name"].as_str().unwrap_or_default().into(),
+                    instance_id: item["spec"]["providerID"]
+                        .as_str()
+                        .and_then(|id| id.rsplit('/').next())
+                        .filter(|id| id.starts_with("i-"))
+                        .map(str::to_string),
+                    allocatable: Requests::of(&item["status"]["allocatable"]),
+                }),
+                Some("Pod") => {
+                    let phase = item["status"]["phase"].as_str();
+                    let Some(node_name) = item["spec"]["nodeName"].as_str() else {
+                        continue;
+                    };
+                    if matches!(phase, Some("Succeeded") | Some("Failed")) {
+                        continue;
+                    }
+                    let (workload_kind, workload_name) = workload_of(item);
+                    let mut requests = Requests::default();
+                    for container in item["spec"]["containers"].as_array().into_iter().flatten() {
+                        let container_requests = Requests::of(&container["resources"]["requests"]);
+                        requests.cpu_millis += container_requests.cpu_millis;
+                        requests.memory_bytes += container_requests.memory_bytes;
+                    }
+                    cluster.pods.push(Pod {
+                        namespace: item["metadata"]["namespace"]
+                            .as_str()
+                            .unwrap_or("default")
+                            .into(),
+                        workload_kind,
+                        workload_name,
+                        node_name: node_name.to_string(),
+                        requests,
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(cluster)
+    }
+
+    /// Reads the nodes and pods of a cluster from a json file, see [KubernetesCluster::parse]
+    pub fn read(path: &str) -> Result<KubernetesCluster> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read Kubernetes workloads {}", path))?;
+        Self::parse(&content).with_context(|| format!("Cannot parse Kubernetes workloads {}", path))
+    }
+
+    /// Allocates the impacts of the nodes (instances of the estimated inventory) to the namespaces and workloads of their pods
+    pub fn allocate(&self, estimated_inventory: &EstimatedInventory) -> KubernetesAllocation {
+        let instances: HashMap<&str, &CloudResourceWithImpacts> = estimated_inventory
+            .impacting_resources
+            .iter()
+            .map(|resource| (resource.cloud_resource.id.as_str(), resource))
+            .collect();
+        let mut allocation = KubernetesAllocation::default();
+        let mut namespaces: BTreeMap<&str, NamespaceImpacts> = BTreeMap::new();
+        let mut workloads: BTreeMap<(&str, &str, &str), WorkloadImpacts> = BTreeMap::new();
+        for node in self.nodes.iter() {
+            let impacts = node
+                .instance_id
+                .as_deref()
+                .and_then(|id| instances.get(id))
+                .and_then(|instance| instance.impacts_values.as_ref());
+            let Some(impacts) = impacts else {
+                debug!("No impacts to allocate for node {}", node.name);
+                continue;
+            };
+            allocation.number_of_nodes += 1;
+            let pods: Vec<&Pod> = self
+                .pods
+                .iter()
+                .filter(|pod| pod.node_name == node.name)
+                .collect();
+            // Overcommitted nodes are allocated entirely, in proportion to the requests
+            let requested: f64 = pods
+                .iter()
+                .map(|pod| pod.requests.share_of(&node.allocatable))
+                .sum();
+            let scale = if requested > 1.0 {
+                1.0 / requested
+            } else {
+                1.0
+            };
+            for pod in pods {
+                let share = pod.requests.share_of(&node.allocatable) * scale;
+                namespaces
+                    .entry(&pod.namespace)
+                    .or_insert_with(|| NamespaceImpacts::new(&pod.namespace))
+                    .add(impacts, share);
+                workloads
+                    .entry((&pod.namespace, &pod.workload_kind, &pod.workload_name))
+                    .or_insert_with(|| WorkloadImpacts::new(pod))
+                    .add(pod, impacts, share);
+            }
+            allocation
+                .unallocated
+                .add(impacts, (1.0 - requested * scale).max(0.0));
+        }
+        allocation.namespaces = namespaces.into_values().collect();
+        allocation.workloads = workloads.into_values().collect();
+        allocation
+    }
+}
+
+/// Returns the kind and name of the workload of a pod (its controller, the Deployment of its ReplicaSet), or the pod itself when it has no controller
+fn workload_of(pod: &Value) -> (String, String) {
+    let metadata = &pod["metadata"];
+    let owners = metadata["ownerReferences"].as_array();
+    let owner = owners.and_then(|owners| {
+        owners
+            .iter()
+            .find(|owner| owner["controller"].as_bool() == Some(true))
+            .or_else(|| owners.first())
+    });
+    let Some(owner) = owner else {
+        let name = metadata["name"].as_str().unwrap_or_default();
+        return ("Pod".to_string(), name.to_string());
+    };
+    let kind = owner["kind"].as_str().unwrap_or_default();
+    let name = owner["name"].as_str().unwrap_or_default();
+    // Deployments name their ReplicaSets after the hash of the template of their pods
+    let deployment = metadata["labels"]["pod-template-hash"]
+        .as_str()
+        .and_then(|hash| name.strip_suffix(hash))
+        .and_then(|name| name.strip_suffix('-'))
+        .filter(|_| kind == "ReplicaSet");
+    match deployment {
+        Some(deployment) => ("Deployment".to_string(), deployment.to_string()),
+        None => (kind.to_string(), name.to_string()),
+    }
+}
+
+/// Parses a Kubernetes quantity, like `250m`, `2`, `512Mi` or `1G`
+fn parse_quantity(quantity: &str) -> Option<f64> {
+    const SUFFIXES: [(&str, f64); 15] = [
+        ("Ki", 1024.0),
+        ("Mi", 1048576.0),
+        ("Gi", 1073741824.0),
+        ("Ti", 1099511627776.0),
+        ("Pi", 1125899906842624.0),
+        ("Ei", 1152921504606846976.0),
+        ("n", 1e-9),
+        ("u", 1e-6),
+        ("m", 1e-3),
+        ("k", 1e3),
+        ("M", 1e6),
+        ("G", 1e9),
+        ("T", 1e12),
+        ("P", 1e15),
+        ("E", 1e18),
+    ];
+    for (suffix, multiplier) in SUFFIXES {
+        if let Some(number) = quantity.strip_suffix(suffix) {
+            return number.parse::<f64>().ok().map(|n| n * multiplier);
+        }
+    }
+    quantity.parse().ok()
+}
+
+/// Impacts allocated to pods (or unallocated)
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct AllocatedImpacts {
+    pub adp_manufacture_kgsbeq: f64,
+    pub adp_use_kgsbeq: f64,
+    pub pe_manufacture_megajoules: f64,
+    pub pe_use_megajoules: f64,
+    pub gwp_manufacture_kgco2eq: f64,
+    pub gwp_use_kgco2eq: f64,
+}
+
+impl AllocatedImpacts {
+    fn add(&mut self, impacts: &ImpactsValues, share: f64) {
+        self.adp_manufacture_kgsbeq += impacts.adp_manufacture_kgsbeq * share;
+        self.adp_use_kgsbeq += impacts.adp_use_kgsbeq * share;
+        self.pe_manufacture_megajoules += impacts.pe_manufacture_megajoules * share;
+        self.pe_use_megajoules += impacts.pe_use_megajoules * share;
+        self.gwp_manufacture_kgco2eq += impacts.gwp_manufacture_kgco2eq * share;
+        self.gwp_use_kgco2eq += impacts.gwp_use_kgco2eq * share;
+    }
+}
+
+/// The impacts of the nodes allocated to the pods of a namespace
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct NamespaceImpacts {
+    pub namespace: String,
+    pub number_of_pods: usize,
+    #[serde(flatten)]
+    pub impacts: AllocatedImpacts,
+}
+
+impl NamespaceImpacts {
+    fn new(namespace: &str) -> Self {
+        NamespaceImpacts {
+            namespace: namespace.to_string(),
+            number_of_pods: 0,
+            impacts: AllocatedImpacts::default(),
+        }
+    }
+
+    fn add(&mut self, impacts: &ImpactsValues, share: f64) {
+        self.number_of_pods += 1;
+        self.impacts.add(impacts, share);
+    }
+}
+
+/// The impacts of the nodes allocated to the pods of a workload
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct WorkloadImpacts {
+    pub namespace: String,
+    /// Kind of the workload (like Deployment, StatefulSet or DaemonSet, Pod for pods without controller)
+    pub kind: String,
+    pub name: String,
+    pub number_of_pods: usize,
+    /// CPU requested by the pods of the workload, in millicores
+    pub cpu_request_millis: f64,
+    /// Memory requested by the pods of the workload, in bytes
+    pub memory_request_bytes: f64,
+    #[serde(flatten)]
+    pub impacts: AllocatedImpacts,
+}
+
+impl WorkloadImpacts {
+    fn new(pod: &Pod) -> Self {
+        WorkloadImpacts {
+            namespace: pod.namespace.clone(),
+            kind: pod.workload_kind.clone(),
+            name: pod.workload_name.clone(),
+            number_of_pods: 0,
+            cpu_request_millis: 0.0,
+            memory_request_bytes: 0.0,
+            impacts: AllocatedImpacts::default(),
+        }
+    }
+
+    fn add(&mut self, pod: &Pod, impacts: &ImpactsValues, share: f64) {
+        self.number_of_pods += 1;
+        self.cpu_request_millis += pod.requests.cpu_millis;
+        self.memory_request_bytes += pod.requests.memory_bytes;
+        self.impacts.add(impacts, share);
+    }
+}
+
+/// The impacts of the nodes of a cluster allocated to its namespaces and workloads
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct KubernetesAllocation {
+    /// Number of nodes matched to instances with impacts (the other nodes are not allocated)
+    pub number_of_nodes: usize,
+    /// Sub-summaries per namespace, sorted by namespace
+    pub namespaces: Vec<NamespaceImpacts>,
+    /// Sub-summaries per workload, sorted by namespace, kind and name
+    pub workloads: Vec<WorkloadImpacts>,
+    /// Impacts of the capacity of the nodes requested by no pod
+    pub unallocated: AllocatedImpacts,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{CloudProvider, CloudResource, ResourceDetails};
+    use crate::usage_location::UsageLocation;
+
+    const CLUSTER: &str = r#"{"apiVersion": "v1", "kind": "List", "items": [
+        {"kind": "Node", "metadata": {"name": "ip-10-0-1-10.eu-west-3.compute.internal"},
+         "spec": {"providerID": "aws:///eu-west-3a/i-0123456789abcdef0"},
+         "status": {"allocatable": {"cpu": "4", "memory": "16Gi"}}},
+        {"kind": "Node", "metadata": {"name": "on-premises"}, "spec": {}, "status": {"allocatable": {"cpu": "8", "memory": "32Gi"}}},
+        {"kind": "Pod", "metadata": {"name": "web-7d9f8b6c5-x2k4p", "namespace": "shop", "labels": {"pod-template-hash": "7d9f8b6c5"},
+                                     "ownerReferences": [{"kind": "ReplicaSet", "name": "web-7d9f8b6c5", "controller": true}]},
+         "spec": {"nodeName": "ip-10-0-1-10.eu-west-3.compute.internal",
+                  "containers": [{"resources": {"requests": {"cpu": "1", "memory": "4Gi"}}}, {"resources": {}}]},
+         "status": {"phase": "Running"}},
+        {"kind": "Pod", "metadata": {"name": "db-0", "namespace": "shop", "ownerReferences": [{"kind": "StatefulSet", "name": "db", "controller": true}]},
+         "spec": {"nodeName": "ip-10-0-1-10.eu-west-3.compute.internal", "containers": [{"resources": {"requests": {"cpu": "1000m", "memory": "4096Mi"}}}]},
+         "status": {"phase": "Running"}},
+        {"kind": "Pod", "metadata": {"name": "migration-abcde", "namespace": "shop", "ownerReferences": [{"kind": "Job", "name": "migration", "controller": true}]},
+         "spec": {"nodeName": "ip-10-0-1-10.eu-west-3.compute.internal", "containers": [{"resources": {"requests": {"cpu": "4"}}}]},
+         "status": {"phase": "Succeeded"}},
+        {"kind": "Pod", "metadata": {"name": "pending", "namespace": "shop"}, "spec": {"containers": []}, "status": {"phase": "Pending"}}
+    ]}"#;
+
+    fn node_with_impacts(gwp_use_kgco2eq: f64) -> EstimatedInventory {
+        EstimatedInventory {
+            impacting_resources: vec![CloudResourceWithImpacts {
+                cloud_resource: CloudResource {
+                    provider: CloudProvider::AWS,
+                    account_id: None,
+                    id: "i-0123456789abcdef0".to_string(),
+                    location: UsageLocation::try_from("eu-west-3").unwrap(),
+                    resource_details: ResourceDetails::Instance {
+                        instance_type: "m6g.xlarge".to_string(),
+                        usage: None,
+                    },
+                    tags: Vec::new(),
+                    relationships: Vec::new(),
+                },
+                impacts_values: Some(ImpactsValues {
+                    gwp_use_kgco2eq,
+                    ..Default::default()
+                }),
+                impacts_duration_hours: 1.0,
+                cost: None,
+            }],
+            execution_statistics: None,
+            errors: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn node_impacts_are_allocated_to_workloads_by_requests() {
+        let cluster = KubernetesCluster::parse(CLUSTER).unwrap();
+        assert_eq!(2, cluster.nodes.len());
+        assert_eq!(2, cluster.pods.len());
+
+        let allocation = cluster.allocate(&node_with_impacts(100.0));
+        assert_eq!(1, allocation.number_of_nodes);
+        assert_eq!(1, allocation.namespaces.len());
+        assert_eq!(2, allocation.namespaces[0].number_of_pods);
+        // Each pod requests a quarter of the CPU and of the memory of the node
+        assert_eq!(50.0, allocation.namespaces[0].impacts.gwp_use_kgco2eq);
+        assert_eq!(50.0, allocation.unallocated.gwp_use_kgco2eq);
+
+        let workloads: Vec<(&str, &str)> = allocation
+            .workloads
+            .iter()
+            .map(|w| (w.kind.as_str(), w.name.as_str()))
+            .collect();
+        assert_eq!(
+            vec![("Deployment", "web"), ("StatefulSet", "db")],
+            workloads
+        );
+        assert_eq!(1000.0, allocation.workloads[0].cpu_request_millis);
+        assert_eq!(25.0, allocation.workloads[1].impacts.gwp_use_kgco2eq);
+
+        let json = serde_json::to_value(&allocation.namespaces[0]).unwrap();
+        assert_eq!(50.0, json["gwp_use_kgco2eq"]);
+        assert!(KubernetesCluster::parse(r#"{"kind": "Pod"}"#).is_err());
+    }
+
+    #[test]
+    fn quantities_are_parsed() {
+        assert_eq!(Some(0.25), parse_quantity("250m"));
+        assert_eq!(Some(2.0), parse_quantity("2"));
+        assert_eq!(Some(536870912.0), parse_quantity("512Mi"));
+        assert_eq!(Some(1e9), parse_quantity("1G"));
+        assert_eq!(Some(1e9), parse_quantity("1e9"));
+        assert_eq!(None, parse_quantity("lots"));
+    }
+}
@@ -4,36 +4,173 @@
 //!
 //! It performs inventory of resources of the account and combines it with Boavizta API to return impact data.
 //!
+//! To embed scans in other Rust tools, build a [scanner::Scanner] (source, regions, filters, impact provider and exporters) and call its `scan` method for typed results.
+//!
 
 use crate::model::{EstimatedInventory, ExecutionStatistics};
+use crate::result_envelope::{
+    ImpactProviderMetadata, ResultEnvelope, ResultMetadata, ScanParameters,
+};
 use crate::usage_location::*;
 use aws_cloud_provider::*;
 use boavizta_api_v1::*;
 use cloud_provider::*;
+use ignore_rules::IgnoreRules;
 use impact_provider::ImpactProvider;
-use impact_provider::ImpactsSummary;
+use impact_provider::{CloudResourceWithImpacts, ImpactsSummary, RegionSummary};
+use influxdb_exporter::InfluxDbConfig;
 use metric_exporter::*;
+#[cfg(feature = "stores")]
+use result_store::ResultStore;
+use scan_errors::{ScanError, ScanPhase};
 
+#[cfg(feature = "server")]
 #[macro_use]
 extern crate rocket;
 
 #[macro_use]
 extern crate log;
+use chrono::{DateTime, Utc};
+use futures::FutureExt;
 use model::Inventory;
 use pkg_version::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
+use tracing::Instrument;
+pub mod access_log;
+#[cfg(feature = "reports")]
+pub mod alerting;
+pub mod allocation;
+pub mod anonymization;
+pub mod ansible_inventory;
+#[cfg(feature = "server")]
+pub mod api_v2;
 pub mod aws_cloud_provider;
+pub mod aws_config_snapshot;
+pub mod aws_settings;
+pub mod azure_resource_graph;
+pub mod badge;
+pub mod baselines;
+pub mod bigquery_exporter;
 pub mod boavizta_api_v1;
+pub mod carbon_budgets;
+pub mod checkpoint;
+pub mod ci_gate;
 pub mod cloud_provider;
+pub mod cloudformation_inventory;
+pub mod cloudwatch_exporter;
+#[cfg(feature = "server")]
+pub mod compression;
+pub mod concurrency;
+#[cfg(all(feature = "server", feature = "reports"))]
+pub mod config_file;
+#[cfg(feature = "server")]
+pub mod cors;
+pub mod cost_and_usage_report;
+pub mod csv_exporter;
+pub mod csv_inventory;
+#[cfg(feature = "stores")]
+pub mod database_inventory;
+pub mod datadog_exporter;
+pub mod dry_run;
+pub mod duration;
+#[cfg(feature = "reports")]
+pub mod email_sender;
+pub mod esrs_e1;
+pub mod explain;
+pub mod gcp_asset_inventory;
+pub mod ghg_protocol;
+#[cfg(feature = "server")]
+pub mod graceful_shutdown;
+pub mod grafana_dashboard;
+#[cfg(feature = "server")]
+pub mod graphql_api;
+#[cfg(feature = "server")]
+pub mod grpc_server;
+pub mod health;
+pub mod ignore_rules;
+pub mod impact_framework_exporter;
 pub mod impact_provider;
+pub mod incremental;
+pub mod influxdb_exporter;
+#[cfg(all(feature = "server", feature = "reports"))]
+pub mod init;
+pub mod inventory_file;
+pub mod job_callbacks;
+pub mod json_schema;
+pub mod kubernetes_workloads;
 pub mod metric_exporter;
 pub mod model;
+pub mod netbox_inventory;
+pub mod notifier;
+pub mod output_exporter;
+#[cfg(feature = "reports")]
+pub mod pdf_report;
+pub mod plugins;
+#[cfg(feature = "stores")]
+pub mod postgres_exporter;
+#[cfg(feature = "stores")]
+pub mod postgres_tls;
+pub mod pricing;
+pub mod progress;
+pub mod pulumi_inventory;
+#[cfg(feature = "server")]
+pub mod rate_limit;
+pub mod remote_write;
+pub mod report;
+pub mod response_cache;
+pub mod response_page;
+#[cfg(feature = "server")]
+pub mod response_stream;
+pub mod result_envelope;
+pub mod result_exporter;
+pub mod result_signing;
+#[cfg(feature = "stores")]
+pub mod result_store;
+pub mod result_stream;
+pub mod retention;
+pub mod s3_exporter;
+pub mod scan_aggregation;
+pub mod scan_diff;
+pub mod scan_errors;
+pub mod scan_hooks;
+pub mod scan_jobs;
+pub mod scan_tracing;
+pub mod scanner;
+#[cfg(feature = "server")]
+pub mod scheduler;
+#[cfg(feature = "server")]
+pub mod server_auth;
+pub mod server_telemetry;
+#[cfg(feature = "shared-cache")]
+pub mod shared_cache;
+pub mod showback;
+#[cfg(feature = "server")]
 pub mod standalone_server;
+pub mod statsd_exporter;
+pub mod summary_table;
+pub mod supported_types;
+pub mod synthetic_inventory;
+pub mod tag_filter;
+pub mod tag_normalization;
+pub mod template_exporter;
+pub mod tenants;
+pub mod terraform_inventory;
+pub mod top_emitters;
+pub mod trend;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod units;
 pub mod usage_location;
+pub mod utilization_cache;
+pub mod watch;
+#[cfg(feature = "server")]
+pub mod web_dashboard;
 
 use anyhow::{Context, Result};
 
-async fn estimate_impacts(
+/// Performs the inventory of resources and returns them with their estimated impacts
+pub async fn estimate_impacts(
     use_duration_hours: &f32,
     tags: &[String],
     aws_region: &str,
@@ -41,19 +178,1136 @@ async fn estimate_impacts(
     verbose: bool,
     include_block_storage: bool,
 ) -> Result<EstimatedInventory> {
-    let aws_provider: AwsCloudProvider = AwsCloudProvider::new(aws_region).await;
-    let inventory: Inventory = aws_provider
-        .list_resources(tags, include_block_storage)
-        .await
-        .context("Cannot perform resources inventory")?;
+    estimate_impacts_in_account(
+        None,
+        use_duration_hours,
+        tags,
+        aws_region,
+        api_url,
+        verbose,
+        include_block_storage,
+    )
+    .await
+}
+
+/// Performs the inventory of the resources of several regions (concurrently, see [concurrency::region_concurrency]), and returns them with their estimated impacts
+pub async fn estimate_impacts_in_regions(
+    use_duration_hours: &f32,
+    tags: &[String],
+    aws_regions: &[String],
+    api_url: &str,
+    verbose: bool,
+    include_block_storage: bool,
+) -> Result<EstimatedInventory> {
+    if let [aws_region] = aws_regions {
+        return estimate_impacts(
+            use_duration_hours,
+            tags,
+            aws_region,
+            api_url,
+            verbose,
+            include_block_storage,
+        )
+        .await;
+    }
+    // The regions of the account of the environment, continuing past the regions whose inventory fails
+    estimate_impacts_in_accounts(
+        &[],
+        use_duration_hours,
+        tags,
+        aws_regions,
+        api_url,
+        verbose,
+        include_block_storage,
+    )
+    .await
+}
+
+/// Performs the inventory of the resources of several accounts (by assuming their roles) and regions concurrently, and returns them with their estimated impacts and account ids.
+///
+/// Without accounts, scans the account of the environment.
+pub async fn estimate_impacts_in_accounts(
+    accounts: &[AwsAccount],
+    use_duration_hours: &f32,
+    tags: &[String],
+    aws_regions: &[String],
+    api_url: &str,
+    verbose: bool,
+    include_block_storage: bool,
+) -> Result<EstimatedInventory> {
+    let (estimated_inventory, _) = estimate_impacts_in_accounts_excluding(
+        accounts,
+        use_duration_hours,
+        tags,
+        aws_regions,
+        api_url,
+        verbose,
+        include_block_storage,
+        &ResourceSelection::default(),
+        &IgnoreRules::default(),
+    )
+    .await?;
+    Ok(estimated_inventory)
+}
+
+/// Performs the inventory of the resources of several accounts and regions concurrently (like [estimate_impacts_in_accounts]), and returns the resources not matching the ignore rules with their estimated impacts, and the number of excluded resources.
+///
+/// Only the selected resources are listed (see [ResourceSelection]).
+#[allow(clippy::too_many_arguments)]
+pub async fn estimate_impacts_in_accounts_excluding(
+    accounts: &[AwsAccount],
+    use_duration_hours: &f32,
+    tags: &[String],
+    aws_regions: &[String],
+    api_url: &str,
+    verbose: bool,
+    include_block_storage: bool,
+    selection: &ResourceSelection,
+    ignore_rules: &IgnoreRules,
+) -> Result<(EstimatedInventory, usize)> {
+    scan_accounts_excluding(
+        accounts,
+        use_duration_hours,
+        tags,
+        aws_regions,
+        api_url,
+        verbose,
+        include_block_storage,
+        selection,
+        ignore_rules,
+        None,
+        None,
+    )
+    .await
+}
+
+/// Receives the resources with their impacts of a streamed scan, page by page (see [stream_impacts_in_accounts_excluding])
+pub type PageSink<'a> = dyn Fn(&[CloudResourceWithImpacts]) -> Result<()> + Sync + 'a;
+
+/// Is told of each account of a streamed scan as soon as all its regions are scanned (see [stream_impacts_in_accounts_excluding])
+pub type AccountSink<'a> = dyn Fn(&AwsAccount) -> Result<()> + Sync + 'a;
+
+/// Performs the inventory of the resources of several accounts and regions concurrently (like [estimate_impacts_in_accounts_excluding]), and hands the resources not matching the ignore rules with their estimated impacts to `on_page` as soon as each page is estimated, instead of keeping them in memory.
+///
+/// Each scanned account is handed to `on_account` as soon as all its regions are scanned, without waiting for the other accounts.
+///
+/// Returns the execution statistics of the scan, the errors it continued past (see [scan_errors]) and the number of excluded resources. The scan fails when `on_page` or `on_account` fail.
+#[allow(clippy::too_many_arguments)]
+pub async fn stream_impacts_in_accounts_excluding(
+    accounts: &[AwsAccount],
+    use_duration_hours: &f32,
+    tags: &[String],
+    aws_regions: &[String],
+    api_url: &str,
+    verbose: bool,
+    include_block_storage: bool,
+    selection: &ResourceSelection,
+    ignore_rules: &IgnoreRules,
+    on_page: &PageSink<'_>,
+    on_account: &AccountSink<'_>,
+) -> Result<(Option<ExecutionStatistics>, Vec<ScanError>, usize)> {
+    let (estimated_inventory, excluded) = scan_accounts_excluding(
+        accounts,
+        use_duration_hours,
+        tags,
+        aws_regions,
+        api_url,
+        verbose,
+        include_block_storage,
+        selection,
+        ignore_rules,
+        Some(on_page),
+        Some(on_account),
+    )
+    .await?;
+    Ok((
+        estimated_inventory.execution_statistics,
+        estimated_inventory.errors,
+        excluded,
+    ))
+}
+
+/// Scans several accounts and regions concurrently, the resources with their impacts being returned, or handed to the sink (if any), and each account being handed to `on_account` (if any) when all its regions are scanned
+#[allow(clippy::too_many_arguments)]
+async fn scan_accounts_excluding(
+    accounts: &[AwsAccount],
+    use_duration_hours: &f32,
+    tags: &[String],
+    aws_regions: &[String],
+    api_url: &str,
+    verbose: bool,
+    include_block_storage: bool,
+    selection: &ResourceSelection,
+    ignore_rules: &IgnoreRules,
+    sink: Option<&PageSink<'_>>,
+    on_account: Option<&AccountSink<'_>>,
+) -> Result<(EstimatedInventory, usize)> {
+    let accounts: Vec<Option<&AwsAccount>> = if accounts.is_empty() {
+        vec![None]
+    } else {
+        accounts.iter().map(Some).collect()
+    };
+    let scanned_regions = accounts.len() * aws_regions.len();
+    // A single region may be the default region of the environment (empty)
+    if scanned_regions > 1 {
+        check_regions(aws_regions)?;
+    }
+    // Regions of each account not scanned yet, so that an account is reported as soon as its last region is scanned
+    let remaining_regions: Vec<AtomicUsize> = accounts
+        .iter()
+        .map(|_| AtomicUsize::new(aws_regions.len()))
+        .collect();
+    let started = Instant::now();
+    let scans = accounts.into_iter().zip(remaining_regions.iter()).flat_map(
+        |(account, remaining_regions)| {
+            aws_regions.iter().map(move |region| async move {
+                let scan = scan_region_excluding(
+                    account,
+                    use_duration_hours,
+                    tags,
+                    region,
+                    api_url,
+                    verbose,
+                    include_block_storage,
+                    selection,
+                    ignore_rules,
+                    sink,
+                )
+                .await?;
+                if let Some(account) = account {
+                    if remaining_regions.fetch_sub(1, Ordering::Relaxed) == 1 {
+                        info!(
+                            "Scanned account {} in {:?}",
+                            account.account_id,
+                            started.elapsed()
+                        );
+                        if let Some(on_account) = on_account {
+                            on_account(account)?;
+                        }
+                    }
+                }
+                Ok::<_, anyhow::Error>(scan)
+            })
+        },
+    );
+    let scans = aws_settings::with_api_budget(concurrency::scan_regions(scans))
+        .instrument(tracing::info_span!("scan"))
+        .await?;
+    let excluded = scans.iter().map(|(_, excluded)| excluded).sum();
+    let estimated_inventories = scans
+        .into_iter()
+        .map(|(estimated_inventory, _)| estimated_inventory)
+        .collect();
+    let estimated_inventory = scan_errors::check_inventories(
+        EstimatedInventory::combine(estimated_inventories),
+        scanned_regions,
+    )?;
+    Ok((estimated_inventory, excluded))
+}
+
+/// Scans a region of an account, unless it was scanned before the interruption of a checkpointed scan
+#[allow(clippy::too_many_arguments)]
+async fn scan_region_excluding(
+    account: Option<&AwsAccount>,
+    use_duration_hours: &f32,
+    tags: &[String],
+    aws_region: &str,
+    api_url: &str,
+    verbose: bool,
+    include_block_storage: bool,
+    selection: &ResourceSelection,
+    ignore_rules: &IgnoreRules,
+    sink: Option<&PageSink<'_>>,
+) -> Result<(EstimatedInventory, usize)> {
+    if let Some(scanned) = checkpoint::scanned_region(account, aws_region) {
+        info!("Reusing the checkpointed results of region {}", aws_region);
+        let mut estimated_inventory = scanned.estimated_inventory;
+        if let Some(sink) = sink {
+            sink(&estimated_inventory.impacting_resources)?;
+            estimated_inventory.impacting_resources.clear();
+        }
+        return Ok((estimated_inventory, scanned.excluded));
+    }
+    let (estimated_inventory, excluded) = estimate_impacts_in_account_excluding(
+        account,
+        use_duration_hours,
+        tags,
+        aws_region,
+        api_url,
+        verbose,
+        include_block_storage,
+        selection,
+        ignore_rules,
+        sink,
+    )
+    .await?;
+    // Regions whose inventory failed are scanned again when resuming
+    if !estimated_inventory
+        .errors
+        .iter()
+        .any(|error| error.phase == ScanPhase::Inventory)
+    {
+        checkpoint::record_scanned_region(account, aws_region, &estimated_inventory, excluded);
+    }
+    Ok((estimated_inventory, excluded))
+}
+
+/// Fails when the usage location of a region is unknown, before scanning any region
+fn check_regions(aws_regions: &[String]) -> Result<()> {
+    for region in aws_regions {
+        UsageLocation::try_from(region.as_str())
+            .with_context(|| format!("Cannot scan region {}", region))?;
+    }
+    Ok(())
+}
 
+/// Performs the inventory of the resources of an account (by assuming its role), and returns them with their estimated impacts
+pub async fn estimate_impacts_in_account(
+    account: Option<&AwsAccount>,
+    use_duration_hours: &f32,
+    tags: &[String],
+    aws_region: &str,
+    api_url: &str,
+    verbose: bool,
+    include_block_storage: bool,
+) -> Result<EstimatedInventory> {
+    let (estimated_inventory, _) = estimate_impacts_in_account_excluding(
+        account,
+        use_duration_hours,
+        tags,
+        aws_region,
+        api_url,
+        verbose,
+        include_block_storage,
+        &ResourceSelection::default(),
+        &IgnoreRules::default(),
+        None,
+    )
+    .await?;
+    scan_errors::check_inventories(estimated_inventory, 1)
+}
+
+/// Performs the inventory of the selected resources of an account, and returns the resources not matching the ignore rules with their estimated impacts (or hands them to the sink, if any), and the number of excluded resources.
+///
+/// The scan continues past the errors of the inventory (keeping the resources listed before the error), of the CPU loads and of the impacts of resources: they are returned in the `errors` of the estimated inventory (see [scan_errors]). It fails when the sink fails.
+#[allow(clippy::too_many_arguments)]
+async fn estimate_impacts_in_account_excluding(
+    account: Option<&AwsAccount>,
+    use_duration_hours: &f32,
+    tags: &[String],
+    aws_region: &str,
+    api_url: &str,
+    verbose: bool,
+    include_block_storage: bool,
+    selection: &ResourceSelection,
+    ignore_rules: &IgnoreRules,
+    sink: Option<&PageSink<'_>>,
+) -> Result<(EstimatedInventory, usize)> {
+    let started = std::time::Instant::now();
+    let span = tracing::info_span!(
+        "scan_region",
+        cloud.region = %aws_region,
+        cloud.account.id = tracing::field::Empty,
+    );
+    if let Some(account) = account {
+        span.record("cloud.account.id", account.account_id.as_str());
+    }
+    let scan = async {
+        let aws_provider: AwsCloudProvider = AwsCloudProvider::new_in_account(aws_region, account)
+            .await
+            .with_selection(selection);
+        let api: BoaviztaApiV1 = BoaviztaApiV1::new(api_url);
+        let progress_label = aws_provider.progress_label();
+
+        // The impacts of a page of the inventory are estimated while the next pages are listed, instead of waiting for the whole inventory
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+        let listing = async {
+            let listed = aws_provider
+                .list_resource_pages(tags, include_block_storage, sender)
+                .await
+                .context("Cannot perform resources inventory");
+            (listed, started.elapsed())
+        };
+        let estimation = async {
+            let mut impacting_resources = Vec::new();
+            let mut excluded = 0;
+            let mut impact_estimation_duration = Duration::from_millis(0);
+            while let Some(resources) = receiver.recv().await {
+                let page = Inventory {
+                    resources,
+                    execution_statistics: None,
+                };
+                let (page, page_excluded) = ignore_rules.exclude(with_account_id(page, account));
+                excluded += page_excluded;
+                // Unchanged resources of incremental scans keep the impacts of the last scan, and those of resumed scans the impacts estimated before the interruption
+                let previous: Vec<_> = page
+                    .resources
+                    .iter()
+                    .map(|resource| {
+                        incremental::previous_impacts(resource)
+                            .or_else(|| checkpoint::estimated_impacts(resource))
+                    })
+                    .collect();
+                let changed: Vec<_> = page
+                    .resources
+                    .into_iter()
+                    .zip(previous.iter())
+                    .filter(|(_, previous)| previous.is_none())
+                    .map(|(resource, _)| resource)
+                    .collect();
+                let estimated = impacting_resources.len() + previous.len() - changed.len();
+                let page_started = Instant::now();
+                let mut estimations = api
+                    .get_resources_with_impacts(
+                        &changed,
+                        use_duration_hours,
+                        verbose,
+                        |done, total| {
+                            progress::report(
+                                progress_label,
+                                progress::Phase::ImpactEstimation,
+                                estimated + done,
+                                estimated + total,
+                            )
+                        },
+                    )
+                    .await
+                    .into_iter();
+                impact_estimation_duration += page_started.elapsed();
+                let mut page_with_impacts: Vec<_> = previous
+                    .into_iter()
+                    .filter_map(|previous| previous.or_else(|| estimations.next()))
+                    .collect();
+                incremental::record(&page_with_impacts);
+                checkpoint::record(&page_with_impacts);
+                // Streamed pages are not kept
+                match sink {
+                    Some(sink) => sink(&page_with_impacts)?,
+                    None => impacting_resources.append(&mut page_with_impacts),
+                }
+            }
+            Ok::<_, anyhow::Error>((impacting_resources, excluded, impact_estimation_duration))
+        };
+        let ((listed, inventory_duration), estimation) = tokio::join!(listing, estimation);
+        let (impacting_resources, excluded, impact_estimation_duration) = estimation?;
+        let mut errors = Vec::new();
+        if let Err(e) = listed {
+            warn!("{:#}", e);
+            errors.push(ScanError::of_region(
+                ScanPhase::Inventory,
+                account.map_or("", |account| account.account_id.as_str()),
+                aws_region,
+                format!("{:#}", e),
+            ));
+        }
+        server_telemetry::record_scan_stage(
+            server_telemetry::ScanStage::ImpactEstimation,
+            None,
+            impact_estimation_duration,
+        );
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources,
+            execution_statistics: Some(ExecutionStatistics {
+                inventory_duration,
+                impact_estimation_duration,
+                total_duration: started.elapsed(),
+            }),
+            errors,
+        };
+        Ok::<_, anyhow::Error>((estimated_inventory, excluded))
+    };
+    let scan = async {
+        let (scan, recorded) = scan_errors::collect(scan).await;
+        scan.map(|(mut estimated_inventory, excluded)| {
+            estimated_inventory.errors.extend(recorded);
+            (estimated_inventory, excluded)
+        })
+    };
+    // Boxed, so that the type of the futures of the scans of several regions remains shallow enough for the compiler
+    let estimated_inventory = aws_settings::with_api_budget(scan)
+        .instrument(span)
+        .boxed()
+        .await;
+    server_telemetry::record_scan(
+        server_telemetry::ScanKind::Impacts,
+        started.elapsed(),
+        estimated_inventory.is_ok(),
+    );
+    estimated_inventory
+}
+
+/// Returns the impacts of an inventory produced elsewhere (like by another instance of cloud-scanner), without listing cloud resources
+pub async fn estimate_inventory_impacts(
+    inventory: Inventory,
+    use_duration_hours: &f32,
+    api_url: &str,
+    verbose: bool,
+) -> Result<EstimatedInventory> {
     let api: BoaviztaApiV1 = BoaviztaApiV1::new(api_url);
-    let estimated_inventory = api
-        .get_impacts(inventory, use_duration_hours, verbose)
+    api.get_impacts(inventory, use_duration_hours, verbose)
         .await
-        .context("Failure while retrieving impacts")?;
+        .context("Failure while retrieving impacts")
+}
 
-    Ok(estimated_inventory)
+/// Returns the impacts of a hypothetical instance of a type, in a region and at an average cpu load (in percent, the default load of Boavizta API otherwise), without cloud credentials: to compare options before anything is deployed
+pub async fn estimate_instance_type(
+    instance_type: &str,
+    aws_region: &str,
+    use_duration_hours: &f32,
+    average_cpu_load: Option<f64>,
+    api_url: &str,
+    verbose: bool,
+) -> Result<impact_provider::CloudResourceWithImpacts> {
+    if let Some(load) = average_cpu_load {
+        if !(0.0..=100.0).contains(&load) {
+            anyhow::bail!("The cpu load must be between 0 and 100 (%), not {}", load);
+        }
+    }
+    let resource = model::CloudResource {
+        provider: model::CloudProvider::AWS,
+        account_id: None,
+        id: instance_type.to_string(),
+        location: UsageLocation::try_from(aws_region)?,
+        resource_details: model::ResourceDetails::Instance {
+            instance_type: instance_type.to_string(),
+            usage: average_cpu_load.map(|average_cpu_load| model::InstanceUsage {
+                average_cpu_load,
+                usage_duration_seconds: (use_duration_hours * 3600.0) as u32,
+                state: model::InstanceState::Running,
+            }),
+        },
+        tags: Vec::new(),
+        relationships: Vec::new(),
+    };
+    let inventory = Inventory {
+        resources: vec![resource],
+        execution_statistics: None,
+    };
+    estimate_inventory_impacts(inventory, use_duration_hours, api_url, verbose)
+        .await?
+        .impacting_resources
+        .into_iter()
+        .find(|resource| resource.impacts_values.is_some())
+        .with_context(|| {
+            format!(
+                "Cannot estimate the impacts of instance type {} (run with -v to show the error of Boavizta API)",
+                instance_type
+            )
+        })
+}
+
+/// Returns the summary of the impacts of an estimated inventory
+pub fn build_summary(
+    estimated_inventory: &EstimatedInventory,
+    aws_region: &str,
+    use_duration_hours: &f32,
+) -> Result<ImpactsSummary> {
+    let usage_location: UsageLocation = UsageLocation::try_from(aws_region)?;
+    let summary: ImpactsSummary = ImpactsSummary::new(
+        String::from(aws_region),
+        usage_location.iso_country_code,
+        estimated_inventory,
+        (*use_duration_hours).into(),
+    );
+    debug!("Summary: {:#?}", summary);
+    Ok(summary)
+}
+
+/// Returns the summary of the impacts of an estimated inventory of several regions, with a sub-summary for each region (even when no resource is found)
+pub fn build_summary_of_regions(
+    estimated_inventory: &EstimatedInventory,
+    aws_regions: &[String],
+    use_duration_hours: &f32,
+) -> Result<ImpactsSummary> {
+    build_summary_of_accounts(estimated_inventory, &[], aws_regions, use_duration_hours)
+}
+
+/// Returns the summary of the impacts of an estimated inventory of several accounts (none for the account of the environment) and regions, with a sub-summary for each account and region (even when no resource is found)
+pub fn build_summary_of_accounts(
+    estimated_inventory: &EstimatedInventory,
+    accounts: &[AwsAccount],
+    aws_regions: &[String],
+    use_duration_hours: &f32,
+) -> Result<ImpactsSummary> {
+    if aws_regions.is_empty() {
+        anyhow::bail!("No region to summarize");
+    }
+    let account_ids: Vec<Option<String>> = if accounts.is_empty() {
+        vec![None]
+    } else {
+        accounts
+            .iter()
+            .map(|a| Some(a.account_id.clone()))
+            .collect()
+    };
+    let mut scanned_regions = Vec::new();
+    for account_id in account_ids {
+        for region in aws_regions {
+            // Regions that are not cloud regions (like the sites of data centers) are located by their resources
+            let located_resource = estimated_inventory
+                .impacting_resources
+                .iter()
+                .find(|resource| resource.cloud_resource.location.aws_region == *region);
+            let iso_country_code = match located_resource {
+                Some(resource) => resource.cloud_resource.location.iso_country_code.clone(),
+                None => UsageLocation::try_from(region.as_str())?.iso_country_code,
+            };
+            scanned_regions.push(RegionSummary::new(
+                account_id.clone(),
+                region.clone(),
+                iso_country_code,
+            ));
+        }
+    }
+    let summary = ImpactsSummary::of_scanned_regions(
+        scanned_regions,
+        estimated_inventory,
+        (*use_duration_hours).into(),
+    );
+    debug!("Summary: {:#?}", summary);
+    Ok(summary)
+}
+
+/// Returns the metadata of a scan started at `scan_timestamp`.
+///
+/// When an API URL is provided, the version of Boavizta API is requested to describe the impact provider.
+pub async fn scan_metadata(
+    scan_timestamp: DateTime<Utc>,
+    parameters: ScanParameters,
+    api_url: Option<&str>,
+) -> ResultMetadata {
+    let impact_provider = match api_url {
+        Some(api_url) => Some(ImpactProviderMetadata {
+            name: "Boavizta API".to_string(),
+            url: api_url.to_string(),
+            version: boavizta_api_v1::get_api_version(api_url).await,
+        }),
+        None => None,
+    };
+    ResultMetadata::new(scan_timestamp, parameters, impact_provider)
+}
+
+/// Formats an estimated inventory (or only its summary) as json string, wrapped in a metadata envelope
+pub fn impacts_to_json_string(
+    metadata: &ResultMetadata,
+    estimated_inventory: &EstimatedInventory,
+    summary: &ImpactsSummary,
+    summary_only: bool,
+) -> Result<String> {
+    if summary_only {
+        return ResultEnvelope {
+            metadata: metadata.clone(),
+            data: summary,
+        }
+        .to_json();
+    }
+    ResultEnvelope {
+        metadata: metadata.clone(),
+        data: estimated_inventory,
+    }
+    .to_json()
+}
+
+/// Formats an estimated inventory as metrics (summary and one series per resource)
+pub fn impacts_to_metrics(
+    estimated_inventory: &EstimatedInventory,
+    summary: &ImpactsSummary,
+) -> Result<String> {
+    impacts_to_metrics_of_tenant(estimated_inventory, summary, None, &Default::default())
+}
+
+/// Formats an estimated inventory as Prometheus metrics, labelled with the tenant of the scan (if any), with the options of the metrics of resources (impact metrics, tags mapped to labels)
+pub fn impacts_to_metrics_of_tenant(
+    estimated_inventory: &EstimatedInventory,
+    summary: &ImpactsSummary,
+    tenant: Option<&str>,
+    options: &metric_exporter::MetricOptions,
+) -> Result<String> {
+    let labels: Vec<(String, String)> = tenant
+        .map(|tenant| ("tenant".to_string(), tenant.to_string()))
+        .into_iter()
+        .collect();
+    let all_metrics = get_all_metrics_with_labels(summary, estimated_inventory, &labels, options)
+        .with_context(|| {
+        format!(
+            "Unable to get resource impacts as metrics for region {}",
+            summary.aws_regions()
+        )
+    })?;
+    Ok(all_metrics)
+}
+
+/// Formats an inventory as Prometheus metrics (number of resources by kind, type and state), labelled with the tenant of the scan (if any), without estimating impacts
+pub fn inventory_to_metrics_of_tenant(
+    inventory: &Inventory,
+    tenant: Option<&str>,
+    options: &metric_exporter::MetricOptions,
+) -> Result<String> {
+    let labels: Vec<(String, String)> = tenant
+        .map(|tenant| ("tenant".to_string(), tenant.to_string()))
+        .into_iter()
+        .collect();
+    metric_exporter::get_inventory_metrics(inventory, &labels, options)
+        .context("Unable to get the inventory as metrics")
+}
+
+/// Formats an estimated inventory as InfluxDB line protocol (summary and one point per resource)
+pub fn impacts_to_line_protocol(
+    estimated_inventory: &EstimatedInventory,
+    summary: &ImpactsSummary,
+) -> Result<String> {
+    let timestamp_ns = chrono::Utc::now()
+        .timestamp_nanos_opt()
+        .context("Cannot compute scan timestamp")?;
+    Ok(influxdb_exporter::get_all_line_protocol(
+        summary,
+        estimated_inventory,
+        timestamp_ns,
+    ))
+}
+
+/// Formats the `count` resources with the highest impacts (according to `criterion`) as a text table, or as json
+pub fn impacts_to_top_emitters(
+    estimated_inventory: &EstimatedInventory,
+    criterion: top_emitters::RankingCriterion,
+    count: usize,
+    as_json: bool,
+    units: &units::OutputUnits,
+) -> Result<String> {
+    let top = top_emitters::top_emitters(estimated_inventory, criterion, count).with_units(units);
+    if as_json {
+        return Ok(serde_json::to_string(&top)?);
+    }
+    Ok(top_emitters::to_table(&top))
+}
+
+/// Formats the resources of an estimated inventory as CSV (one row per resource)
+pub fn impacts_to_csv(estimated_inventory: &EstimatedInventory) -> Result<String> {
+    csv_exporter::get_resources_csv(estimated_inventory)
+}
+
+/// Returns a report of a scan (metadata, summary, top resources and methodology notes), to be rendered as HTML or PDF
+pub fn build_report(
+    estimated_inventory: &EstimatedInventory,
+    summary: &ImpactsSummary,
+    api_url: &str,
+    tags: &[String],
+) -> report::Report {
+    let metadata = report::ReportMetadata {
+        generated_at: chrono::Utc::now(),
+        cloud_scanner_version: get_version(),
+        aws_region: summary.aws_regions(),
+        boavizta_api_url: api_url.to_string(),
+        filter_tags: tags.to_vec(),
+    };
+    report::Report::new(metadata, summary, estimated_inventory)
+}
+
+/// Sends a report by email, with the SMTP settings of the configuration file
+#[cfg(feature = "reports")]
+pub async fn send_report_by_email(
+    email_config: &email_sender::EmailConfig,
+    report: &report::Report,
+) -> Result<()> {
+    email_sender::send_report(email_config, report).await
+}
+
+/// Scans and sends a report by email every `interval_hours` of the schedule (in the background, for server mode).
+///
+/// Failures are logged and do not stop the schedule.
+#[cfg(feature = "reports")]
+pub fn spawn_scheduled_email_reports(
+    email_config: email_sender::EmailConfig,
+    schedule: email_sender::EmailSchedule,
+    api_url: String,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let interval = Duration::from_secs(schedule.interval_hours.max(1) * 3600);
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Err(e) = send_scheduled_report(&email_config, &schedule, &api_url).await {
+                error!("Cannot send scheduled email report: {:?}", e);
+            }
+        }
+    })
+}
+
+#[cfg(feature = "reports")]
+async fn send_scheduled_report(
+    email_config: &email_sender::EmailConfig,
+    schedule: &email_sender::EmailSchedule,
+    api_url: &str,
+) -> Result<()> {
+    let estimated_inventory = estimate_impacts(
+        &schedule.use_duration_hours,
+        &schedule.filter_tags,
+        &schedule.aws_region,
+        api_url,
+        false,
+        schedule.include_block_storage,
+    )
+    .await?;
+    let summary = build_summary(
+        &estimated_inventory,
+        &schedule.aws_region,
+        &schedule.use_duration_hours,
+    )?;
+    let report = build_report(
+        &estimated_inventory,
+        &summary,
+        api_url,
+        &schedule.filter_tags,
+    );
+    email_sender::send_report(email_config, &report).await
+}
+
+/// Posts the summary of a scan to the Slack or Teams webhooks of the configuration file, returns the thresholds breached
+pub async fn notify_webhooks(
+    notification_config: &notifier::NotificationConfig,
+    summary: &ImpactsSummary,
+    estimated_inventory: &EstimatedInventory,
+) -> Result<Vec<notifier::ThresholdBreach>> {
+    let breaches = notifier::notify(notification_config, summary, estimated_inventory).await?;
+    for breach in breaches.iter() {
+        warn!("{}", breach);
+    }
+    Ok(breaches)
+}
+
+/// Writes results to the destination selected by the scheme of an output URI (standard output when no URI is provided), returns the location of the results.
+///
+/// The extension (like `json` or `csv`) describes the format of the results. See [output_exporter] for supported destinations.
+pub async fn write_results(
+    output_uri: Option<&str>,
+    aws_region: &str,
+    results: impl Into<Vec<u8>>,
+    extension: &str,
+) -> Result<String> {
+    let exporter = output_exporter::exporter_for_uri(output_uri, aws_region)?;
+    exporter.export(results.into(), extension).await
+}
+
+/// Saves an estimated inventory and its summary in the result store located at `store_path`, returns the id of the stored scan
+#[cfg(feature = "stores")]
+pub fn store_impacts(
+    store_path: &str,
+    estimated_inventory: &EstimatedInventory,
+    summary: &ImpactsSummary,
+) -> Result<i64> {
+    let mut store = ResultStore::open(store_path)?;
+    let scan_id = store.save_scan(chrono::Utc::now(), summary, estimated_inventory)?;
+    info!("Scan saved with id {} in store {}", scan_id, store_path);
+    Ok(scan_id)
+}
+
+/// Pushes the metrics of the scans of the result store located at `store_path` (done between two dates, if any) to a remote-write receiver, each at the time of its scan, oldest first. Returns the number of scans pushed.
+#[cfg(feature = "stores")]
+pub async fn backfill_remote_write(
+    store_path: &str,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    remote_write_config: &remote_write::RemoteWriteConfig,
+    options: &metric_exporter::MetricOptions,
+) -> Result<usize> {
+    let store = ResultStore::open(store_path)?;
+    let scans = store.list_scans_between(since, until)?;
+    for scan in scans.iter() {
+        let estimated_inventory = store
+            .get_scan_inventory(scan.id)?
+            .with_context(|| format!("Scan {} has no inventory", scan.id))?;
+        let metrics =
+            impacts_to_metrics_of_tenant(&estimated_inventory, &scan.summary, None, options)?;
+        remote_write::push_to_remote_write(
+            remote_write_config,
+            &metrics,
+            scan.timestamp.timestamp_millis(),
+        )
+        .await
+        .with_context(|| format!("Cannot backfill scan {} of {}", scan.id, scan.timestamp))?;
+    }
+    Ok(scans.len())
+}
+
+/// Returns the scans of the result store located at `store_path` covering a period (from the first scan until the end of use of the last one when unbounded), with their estimated inventory
+#[cfg(feature = "stores")]
+pub fn covered_scans_of_store(
+    store_path: &str,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+) -> Result<scan_aggregation::ScansOfPeriod> {
+    let store = ResultStore::open(store_path)?;
+    // Scans done before the period may cover its beginning
+    let scans: Vec<(i64, DateTime<Utc>, f64)> = store
+        .list_scans_between(None, until)?
+        .iter()
+        .map(|scan| (scan.id, scan.timestamp, scan.summary.duration_of_use_hours))
+        .collect();
+    let (Some(first), Some(last)) = (scans.first(), scans.last()) else {
+        anyhow::bail!("No scan in the result store {}", store_path);
+    };
+    let start = since.unwrap_or(first.1);
+    let end = until.unwrap_or(last.1 + chrono::Duration::seconds((last.2 * 3600.0) as i64));
+    let mut covered_scans = Vec::new();
+    for scan in scan_aggregation::covered_scans(&scans, start, end) {
+        let estimated_inventory = store
+            .get_scan_inventory(scan.scan_id)?
+            .with_context(|| format!("Scan {} has no inventory", scan.scan_id))?;
+        covered_scans.push((scan, estimated_inventory));
+    }
+    Ok(scan_aggregation::ScansOfPeriod {
+        start,
+        end,
+        scans: covered_scans,
+    })
+}
+
+/// Returns the ESRS E1 export of the scans of the result store located at `store_path` over a reporting period, from the first scan until the end of use of the last one when unbounded
+#[cfg(feature = "stores")]
+pub fn esrs_e1_export_of_store(
+    store_path: &str,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    market_factors: &std::collections::BTreeMap<String, f64>,
+    organization: Option<&str>,
+) -> Result<esrs_e1::EsrsE1Export> {
+    let scans = covered_scans_of_store(store_path, since, until)?;
+    Ok(esrs_e1::build_esrs_e1_export(
+        scans.start,
+        scans.end,
+        &scans.scans,
+        market_factors,
+        organization,
+    ))
+}
+
+/// Returns the impacts of the scans of the result store located at `store_path` aggregated by period (daily, weekly or monthly) per account, region and value of the tag `tag_key` (allocating the shared resources by the allocation rules), from the first scan until the end of use of the last one when unbounded
+#[cfg(feature = "stores")]
+pub fn aggregate_store(
+    store_path: &str,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    period: scan_aggregation::AggregationPeriod,
+    tag_key: Option<&str>,
+    allocations: &allocation::AllocationRules,
+    extrapolate: bool,
+) -> Result<Vec<scan_aggregation::AggregatedImpacts>> {
+    // The periods purged by the retention policy are read from the aggregates of the store
+    let store = ResultStore::open(store_path)?;
+    let mut rows = Vec::new();
+    let mut since = since;
+    if let Some(aggregated_until) = store.aggregated_until(period.name())? {
+        if since.is_none_or(|since| since < aggregated_until) {
+            rows = store
+                .list_aggregates(period.name(), tag_key)?
+                .into_iter()
+                .filter(|row| {
+                    since.is_none_or(|since| row.period_end > since)
+                        && until.is_none_or(|until| row.period_start < until)
+                })
+                .map(|row| if extrapolate { row.extrapolated() } else { row })
+                .collect();
+            since = Some(aggregated_until);
+        }
+        if until.is_some_and(|until| until <= aggregated_until) {
+            return Ok(rows);
+        }
+    }
+    let scans = covered_scans_of_store(store_path, since, until)?;
+    rows.extend(scan_aggregation::aggregate_scans(
+        &scans.scans,
+        scans.start,
+        scans.end,
+        period,
+        tag_key,
+        allocations,
+        extrapolate,
+    ));
+    Ok(rows)
+}
+
+/// Applies a retention policy to the result store located at `store_path`: the scans older than `keep_scans` are aggregated (per account, region and value of the tag of the policy, allocating the shared resources by the allocation rules) then purged, and the aggregates older than `keep_aggregates` are purged
+#[cfg(feature = "stores")]
+pub fn purge_store(
+    store_path: &str,
+    retention: &retention::RetentionConfig,
+    allocations: &allocation::AllocationRules,
+    now: DateTime<Utc>,
+) -> Result<retention::PurgeOutcome> {
+    let mut store = ResultStore::open(store_path)?;
+    let mut outcome = retention::PurgeOutcome::default();
+    let cutoff = retention.scans_cutoff(now)?;
+    let scans: Vec<(i64, DateTime<Utc>, f64)> = store
+        .list_scans()?
+        .iter()
+        .map(|scan| (scan.id, scan.timestamp, scan.summary.duration_of_use_hours))
+        .collect();
+    let purged_scans = retention::scans_to_purge(
+        &scans
+            .iter()
+            .map(|(id, timestamp, _)| (*id, *timestamp))
+            .collect::<Vec<_>>(),
+        cutoff,
+    );
+    if let (Some(period), Some(first)) = (retention.aggregation_period()?, scans.first()) {
+        let start = store
+            .aggregated_until(period.name())?
+            .unwrap_or(period.start_of(first.1));
+        if start < cutoff {
+            let mut covered_scans = Vec::new();
+            for scan in scan_aggregation::covered_scans(&scans, start, cutoff) {
+                let estimated_inventory = store
+                    .get_scan_inventory(scan.scan_id)?
+                    .with_context(|| format!("Scan {} has no inventory", scan.scan_id))?;
+                covered_scans.push((scan, estimated_inventory));
+            }
+            let rows = scan_aggregation::aggregate_scans(
+                &covered_scans,
+                start,
+                cutoff,
+                period,
+                retention.aggregate_by_tag.as_deref(),
+                allocations,
+                false,
+            );
+            // Saved before purging the scans, so that an interrupted purge loses nothing
+            store.save_aggregates(
+                period.name(),
+                retention.aggregate_by_tag.as_deref(),
+                &rows,
+                cutoff,
+            )?;
+            outcome.added_aggregates = rows.len();
+            outcome.aggregated_until = Some(cutoff);
+        }
+    }
+    outcome.purged_scans = store.delete_scans(&purged_scans)?;
+    if let Some(aggregates_cutoff) = retention.aggregates_cutoff(now) {
+        outcome.purged_aggregates = store.delete_aggregates_before(aggregates_cutoff)?;
+    }
+    if !outcome.is_empty() {
+        info!("Result store {} purged: {}", store_path, outcome.to_text());
+    }
+    Ok(outcome)
+}
+
+/// Applies a retention policy to the result store located at `store_path` at startup, then every `purge_interval_hours` of the policy (in the background, for server mode).
+///
+/// Failures are logged and do not stop the purges.
+#[cfg(feature = "stores")]
+pub fn spawn_store_purge(
+    store_path: String,
+    retention: retention::RetentionConfig,
+    allocations: allocation::AllocationRules,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let interval = Duration::from_secs(retention.purge_interval_hours.max(1) * 3600);
+        loop {
+            if let Err(e) = purge_store(&store_path, &retention, &allocations, Utc::now()) {
+                error!("Cannot purge result store {}: {:?}", store_path, e);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    })
+}
+
+/// Returns the status of carbon budgets at a date, from the scans of the result store located at `store_path` over their current periods
+#[cfg(feature = "stores")]
+pub fn budget_statuses_of_store(
+    store_path: &str,
+    budgets: &[carbon_budgets::BudgetConfig],
+    allocations: &allocation::AllocationRules,
+    now: DateTime<Utc>,
+) -> Result<Vec<carbon_budgets::BudgetStatus>> {
+    let mut since = now;
+    for budget in budgets.iter() {
+        since = since.min(budget.period_start(now)?);
+    }
+    let scans = covered_scans_of_store(store_path, Some(since), Some(now))?;
+    carbon_budgets::budget_statuses(budgets, allocations, &scans.scans, now)
+}
+
+/// Returns the showback report of the teams (values of the tag `tag_key`) over the last period complete at a date, from the scans of the result store located at `store_path`
+#[cfg(feature = "stores")]
+pub fn showback_report_of_store(
+    store_path: &str,
+    tag_key: &str,
+    allocations: &allocation::AllocationRules,
+    period: scan_aggregation::AggregationPeriod,
+    at: DateTime<Utc>,
+) -> Result<showback::ShowbackReport> {
+    let (previous_period_start, _, period_end) = showback::report_periods(period, at);
+    let scans = covered_scans_of_store(store_path, Some(previous_period_start), Some(period_end))?;
+    Ok(showback::build_showback_report(
+        &scans.scans,
+        tag_key,
+        allocations,
+        period,
+        at,
+    ))
+}
+
+/// Returns the trends of the criteria over a window ending at a date, per value of the tag `tag_key` (if any), from the scans of the result store located at `store_path`
+#[cfg(feature = "stores")]
+pub fn trend_report_of_store(
+    store_path: &str,
+    window_hours: f32,
+    until: DateTime<Utc>,
+    criteria: &[top_emitters::RankingCriterion],
+    tag_key: Option<&str>,
+    allocations: &allocation::AllocationRules,
+) -> Result<trend::TrendReport> {
+    let since = until - chrono::Duration::seconds((window_hours as f64 * 3600.0) as i64);
+    let scans = covered_scans_of_store(store_path, Some(since), Some(until))?;
+    Ok(trend::build_trend_report(
+        &scans.scans,
+        since,
+        until,
+        criteria,
+        tag_key,
+        allocations,
+    ))
+}
+
+/// Exports an estimated inventory and its summary to PostgreSQL tables, returns the id of the summary row
+#[cfg(feature = "stores")]
+pub async fn export_impacts_to_postgres(
+    postgres_config: &postgres_exporter::PostgresConfig,
+    estimated_inventory: &EstimatedInventory,
+    summary: &ImpactsSummary,
+) -> Result<i64> {
+    postgres_exporter::export_to_postgres(
+        postgres_config,
+        chrono::Utc::now(),
+        summary,
+        estimated_inventory,
+    )
+    .await
+}
+
+/// Formats the assessed resources of an estimated inventory as a Green Software Foundation Impact Framework manifest (yaml)
+pub fn impacts_to_impact_framework_manifest(
+    estimated_inventory: &EstimatedInventory,
+    use_duration_hours: &f32,
+) -> Result<String> {
+    // Observations start at the beginning of the usage period
+    let duration = chrono::Duration::seconds((*use_duration_hours * 3600.0) as i64);
+    let start = chrono::Utc::now() - duration;
+    impact_framework_exporter::get_manifest_yaml(start, estimated_inventory)
+}
+
+/// Sends the resources of an estimated inventory and their impacts to a BigQuery table (streaming insert)
+pub async fn export_impacts_to_bigquery(
+    bigquery_config: &bigquery_exporter::BigQueryConfig,
+    estimated_inventory: &EstimatedInventory,
+) -> Result<()> {
+    let rows = bigquery_exporter::get_bigquery_rows(chrono::Utc::now(), estimated_inventory);
+    bigquery_exporter::insert_rows(bigquery_config, &rows).await
+}
+
+/// Formats the resources of an estimated inventory as newline delimited json rows, ready to be loaded into BigQuery
+pub fn impacts_to_bigquery_ndjson(estimated_inventory: &EstimatedInventory) -> Result<String> {
+    let rows = bigquery_exporter::get_bigquery_rows(chrono::Utc::now(), estimated_inventory);
+    bigquery_exporter::rows_to_ndjson(&rows)
 }
 
 /// Returns default impacts as json string
@@ -66,6 +1320,76 @@ pub async fn get_impacts_as_json_string(
     include_block_storage: bool,
     summary_only: bool,
 ) -> Result<String> {
+    let (impacts, _) = get_impacts_as_json_string_with_summary(
+        use_duration_hours,
+        tags,
+        aws_region,
+        api_url,
+        verbose,
+        include_block_storage,
+        summary_only,
+    )
+    .await?;
+    Ok(impacts)
+}
+
+/// Returns the impacts as a json String (like [get_impacts_as_json_string]), with the summary of the impacts
+pub async fn get_impacts_as_json_string_with_summary(
+    use_duration_hours: &f32,
+    tags: &[String],
+    aws_region: &str,
+    api_url: &str,
+    verbose: bool,
+    include_block_storage: bool,
+    summary_only: bool,
+) -> Result<(String, ImpactsSummary)> {
+    let (metadata, inventory_with_impacts, summary) = estimate_impacts_with_metadata(
+        use_duration_hours,
+        tags,
+        aws_region,
+        api_url,
+        verbose,
+        include_block_storage,
+    )
+    .await?;
+    let impacts =
+        impacts_to_json_string(&metadata, &inventory_with_impacts, &summary, summary_only)?;
+    Ok((impacts, summary))
+}
+
+/// Returns the impacts and their summary as two json Strings (both with the metadata of the scan, to write them apart), with the summary of the impacts
+pub async fn get_impacts_and_summary_as_json_strings(
+    use_duration_hours: &f32,
+    tags: &[String],
+    aws_region: &str,
+    api_url: &str,
+    verbose: bool,
+    include_block_storage: bool,
+) -> Result<(String, String, ImpactsSummary)> {
+    let (metadata, inventory_with_impacts, summary) = estimate_impacts_with_metadata(
+        use_duration_hours,
+        tags,
+        aws_region,
+        api_url,
+        verbose,
+        include_block_storage,
+    )
+    .await?;
+    let impacts = impacts_to_json_string(&metadata, &inventory_with_impacts, &summary, false)?;
+    let summary_json = impacts_to_json_string(&metadata, &inventory_with_impacts, &summary, true)?;
+    Ok((impacts, summary_json, summary))
+}
+
+/// Estimates the impacts of a standard scan, with its summary and metadata
+async fn estimate_impacts_with_metadata(
+    use_duration_hours: &f32,
+    tags: &[String],
+    aws_region: &str,
+    api_url: &str,
+    verbose: bool,
+    include_block_storage: bool,
+) -> Result<(ResultMetadata, EstimatedInventory, ImpactsSummary)> {
+    let scan_timestamp = Utc::now();
     let inventory_with_impacts = estimate_impacts(
         use_duration_hours,
         tags,
@@ -77,19 +1401,17 @@ pub async fn get_impacts_as_json_string(
     .await
     .context("Cannot perform standard scan")?;
 
-    if summary_only {
-        let usage_location: UsageLocation = UsageLocation::try_from(aws_region)?;
-        let summary: ImpactsSummary = ImpactsSummary::new(
-            String::from(aws_region),
-            usage_location.iso_country_code,
-            &inventory_with_impacts.clone(),
-            (*use_duration_hours).into(),
-        );
-
-        return Ok(serde_json::to_string(&summary)?);
-    }
-
-    Ok(serde_json::to_string(&inventory_with_impacts)?)
+    let summary = build_summary(&inventory_with_impacts, aws_region, use_duration_hours)?;
+    let parameters = ScanParameters {
+        aws_region: aws_region.to_string(),
+        use_duration_hours: Some(*use_duration_hours),
+        filter_tags: tags.to_vec(),
+        include_block_storage,
+        include_states: Vec::new(),
+        verbose,
+    };
+    let metadata = scan_metadata(scan_timestamp, parameters, Some(api_url)).await;
+    Ok((metadata, inventory_with_impacts, summary))
 }
 
 /// Returns  impacts as metrics
@@ -100,6 +1422,7 @@ pub async fn get_impacts_as_metrics(
     api_url: &str,
     include_storage: bool,
 ) -> Result<String> {
+    let scan_timestamp = Utc::now();
     let resources_with_impacts = estimate_impacts(
         use_duration_hours,
         tags,
@@ -111,23 +1434,47 @@ pub async fn get_impacts_as_metrics(
     .await
     .context("Cannot perform standard scan")?;
 
-    let usage_location: UsageLocation = UsageLocation::try_from(aws_region)?;
-    let summary: ImpactsSummary = ImpactsSummary::new(
-        String::from(aws_region),
-        usage_location.iso_country_code,
+    let summary = build_summary(&resources_with_impacts, aws_region, use_duration_hours)?;
+    let parameters = ScanParameters {
+        aws_region: aws_region.to_string(),
+        use_duration_hours: Some(*use_duration_hours),
+        filter_tags: tags.to_vec(),
+        include_block_storage: include_storage,
+        include_states: Vec::new(),
+        verbose: false,
+    };
+    impacts_to_metrics_of_tenant(
         &resources_with_impacts,
-        (*use_duration_hours).into(),
-    );
-    debug!("Summary: {:#?}", summary);
+        &summary,
+        None,
+        &MetricOptions {
+            scan_info: Some(scan_metadata(scan_timestamp, parameters, Some(api_url)).await),
+            ..Default::default()
+        },
+    )
+}
 
-    let all_metrics = get_all_metrics(&summary, resources_with_impacts).with_context(|| {
-        format!(
-            "Unable to get resource impacts as metrics for region {}",
-            aws_region
-        )
-    })?;
+/// Returns impacts as InfluxDB line protocol (summary and one point per resource)
+pub async fn get_impacts_as_line_protocol(
+    use_duration_hours: &f32,
+    tags: &[String],
+    aws_region: &str,
+    api_url: &str,
+    include_storage: bool,
+) -> Result<String> {
+    let resources_with_impacts = estimate_impacts(
+        use_duration_hours,
+        tags,
+        aws_region,
+        api_url,
+        false,
+        include_storage,
+    )
+    .await
+    .context("Cannot perform standard scan")?;
 
-    Ok(all_metrics)
+    let summary = build_summary(&resources_with_impacts, aws_region, use_duration_hours)?;
+    impacts_to_line_protocol(&resources_with_impacts, &summary)
 }
 
 /// Prints  impacts to standard output in json format
@@ -174,25 +1521,115 @@ pub async fn print_default_impacts_as_metrics(
     Ok(())
 }
 
-/// Returns the inventory of cloud resources a as json String
+/// Prints impacts to standard output as InfluxDB line protocol
+pub async fn print_default_impacts_as_line_protocol(
+    use_duration_hours: &f32,
+    tags: &[String],
+    aws_region: &str,
+    api_url: &str,
+    include_block_storage: bool,
+) -> Result<()> {
+    let lines = get_impacts_as_line_protocol(
+        use_duration_hours,
+        tags,
+        aws_region,
+        api_url,
+        include_block_storage,
+    )
+    .await?;
+    print!("{}", lines);
+    Ok(())
+}
+
+/// Pushes impacts to an InfluxDB v2 endpoint as line protocol
+pub async fn push_default_impacts_to_influxdb(
+    use_duration_hours: &f32,
+    tags: &[String],
+    aws_region: &str,
+    api_url: &str,
+    include_block_storage: bool,
+    influxdb_config: &InfluxDbConfig,
+) -> Result<()> {
+    let lines = get_impacts_as_line_protocol(
+        use_duration_hours,
+        tags,
+        aws_region,
+        api_url,
+        include_block_storage,
+    )
+    .await?;
+    influxdb_exporter::push_to_influxdb(influxdb_config, lines).await
+}
+
+/// Returns the inventory of cloud resources as a json String (wrapped in a metadata envelope)
 pub async fn get_inventory_as_json(
     tags: &[String],
     aws_region: &str,
     include_block_storage: bool,
 ) -> Result<String> {
+    get_inventory_of_regions_as_json(tags, &[aws_region.to_string()], include_block_storage).await
+}
+
+/// Returns the combined inventory of the cloud resources of several regions as a json String (wrapped in a metadata envelope)
+pub async fn get_inventory_of_regions_as_json(
+    tags: &[String],
+    aws_regions: &[String],
+    include_block_storage: bool,
+) -> Result<String> {
+    get_inventory_of_accounts_as_json(
+        &[],
+        tags,
+        aws_regions,
+        include_block_storage,
+        &ResourceSelection::default(),
+        None,
+    )
+    .await
+}
+
+/// Returns the combined inventory of the selected cloud resources of several accounts and regions as a json String (wrapped in a metadata envelope), anonymized with an anonymizer
+pub async fn get_inventory_of_accounts_as_json(
+    accounts: &[AwsAccount],
+    tags: &[String],
+    aws_regions: &[String],
+    include_block_storage: bool,
+    selection: &ResourceSelection,
+    anonymizer: Option<&anonymization::Anonymizer>,
+) -> Result<String> {
+    let scan_timestamp = Utc::now();
     let start = Instant::now();
-    let aws_inventory: AwsCloudProvider = AwsCloudProvider::new(aws_region).await;
-    let inventory: Inventory = aws_inventory
-        .list_resources(tags, include_block_storage)
-        .await
-        .context("Cannot perform inventory.")?;
+    let inventory = get_inventory_in_accounts(
+        accounts,
+        tags,
+        aws_regions,
+        include_block_storage,
+        selection,
+    )
+    .await?;
     let stats = ExecutionStatistics {
         inventory_duration: start.elapsed(),
         impact_estimation_duration: Duration::from_millis(0),
         total_duration: start.elapsed(),
     };
     warn!("{:?}", stats);
-    serde_json::to_string(&inventory.resources).context("Cannot format inventory as json")
+    let inventory = match anonymizer {
+        Some(anonymizer) => anonymizer.anonymize_inventory(inventory),
+        None => inventory,
+    };
+    let parameters = ScanParameters {
+        aws_region: aws_regions.join(","),
+        use_duration_hours: None,
+        filter_tags: tags.to_vec(),
+        include_block_storage,
+        include_states: selection.instance_states.clone(),
+        verbose: false,
+    };
+    ResultEnvelope {
+        metadata: scan_metadata(scan_timestamp, parameters, None).await,
+        data: inventory.resources,
+    }
+    .to_json()
+    .context("Cannot format inventory as json")
 }
 
 /// Returns the inventory of cloud resources
@@ -201,15 +1638,109 @@ pub async fn get_inventory(
     aws_region: &str,
     include_block_storage: bool,
 ) -> Result<Inventory> {
-    let aws_inventory: AwsCloudProvider = AwsCloudProvider::new(aws_region).await;
-    let inventory: Inventory = aws_inventory
-        .list_resources(tags, include_block_storage)
+    get_inventory_in_account(None, tags, aws_region, include_block_storage).await
+}
+
+/// Returns the combined inventory of the cloud resources of several regions (listed concurrently)
+pub async fn get_inventory_in_regions(
+    tags: &[String],
+    aws_regions: &[String],
+    include_block_storage: bool,
+) -> Result<Inventory> {
+    if let [aws_region] = aws_regions {
+        return get_inventory(tags, aws_region, include_block_storage).await;
+    }
+    check_regions(aws_regions)?;
+    let inventories = aws_settings::with_api_budget(concurrency::scan_regions(
+        aws_regions
+            .iter()
+            .map(|region| get_inventory(tags, region, include_block_storage)),
+    ))
+    .await?;
+    Ok(Inventory::combine(inventories))
+}
+
+/// Returns the combined inventory of the cloud resources of several accounts (by assuming their roles) and regions, listed concurrently.
+///
+/// Without accounts, lists the resources of the account of the environment. Only the selected resources are listed.
+pub async fn get_inventory_in_accounts(
+    accounts: &[AwsAccount],
+    tags: &[String],
+    aws_regions: &[String],
+    include_block_storage: bool,
+    selection: &ResourceSelection,
+) -> Result<Inventory> {
+    let accounts: Vec<Option<&AwsAccount>> = if accounts.is_empty() {
+        vec![None]
+    } else {
+        accounts.iter().map(Some).collect()
+    };
+    // A single region may be the default region of the environment (empty)
+    if accounts.len() * aws_regions.len() > 1 {
+        check_regions(aws_regions)?;
+    }
+    let inventories = accounts.into_iter().flat_map(|account| {
+        aws_regions.iter().map(move |region| {
+            list_inventory_in_account(account, tags, region, include_block_storage, selection)
+        })
+    });
+    let inventories = aws_settings::with_api_budget(concurrency::scan_regions(inventories)).await?;
+    Ok(Inventory::combine(inventories))
+}
+
+/// Sets the account id of the resources of an account (resources of the account of the environment have none)
+fn with_account_id(mut inventory: Inventory, account: Option<&AwsAccount>) -> Inventory {
+    if let Some(account) = account {
+        for resource in inventory.resources.iter_mut() {
+            resource.account_id = Some(account.account_id.clone());
+        }
+    }
+    inventory
+}
+
+/// Returns the inventory of the cloud resources of an account (by assuming its role)
+pub async fn get_inventory_in_account(
+    account: Option<&AwsAccount>,
+    tags: &[String],
+    aws_region: &str,
+    include_block_storage: bool,
+) -> Result<Inventory> {
+    list_inventory_in_account(
+        account,
+        tags,
+        aws_region,
+        include_block_storage,
+        &ResourceSelection::default(),
+    )
+    .await
+}
+
+/// Returns the inventory of the selected cloud resources of an account
+async fn list_inventory_in_account(
+    account: Option<&AwsAccount>,
+    tags: &[String],
+    aws_region: &str,
+    include_block_storage: bool,
+    selection: &ResourceSelection,
+) -> Result<Inventory> {
+    let started = std::time::Instant::now();
+    let aws_inventory: AwsCloudProvider = AwsCloudProvider::new_in_account(aws_region, account)
         .await
-        .context("Cannot perform inventory.")?;
-    Ok(inventory)
+        .with_selection(selection);
+    let inventory =
+        aws_settings::with_api_budget(aws_inventory.list_resources(tags, include_block_storage))
+            .await
+            .context("Cannot perform inventory.")
+            .map(|inventory| with_account_id(inventory, account));
+    server_telemetry::record_scan(
+        server_telemetry::ScanKind::Inventory,
+        started.elapsed(),
+        inventory.is_ok(),
+    );
+    inventory
 }
 
-/// List instances and metadata to standard output
+/// List instances and metadata to standard output (json wrapped in a metadata envelope)
 pub async fn show_inventory(
     tags: &[String],
     aws_region: &str,
@@ -221,10 +1752,60 @@ pub async fn show_inventory(
     Ok(())
 }
 
+/// Compares two scans (json files produced by the `estimate` command), returns the differences as text or as json
+pub fn diff_scan_files(old_path: &str, new_path: &str, as_json: bool) -> Result<String> {
+    let old = scan_diff::read_scan(old_path)?;
+    let new = scan_diff::read_scan(new_path)?;
+    let diff = scan_diff::diff_scans(&old, &new);
+    if as_json {
+        return Ok(serde_json::to_string(&diff)?);
+    }
+    Ok(scan_diff::to_text(&diff))
+}
+
+/// Returns the JSON Schema of a result type, or the schemas of all types (indexed by type name) when no type is given
+pub fn get_json_schema(type_name: Option<&str>) -> Result<String> {
+    match type_name {
+        Some(type_name) => json_schema::get_schema(type_name),
+        None => json_schema::get_all_schemas(),
+    }
+}
+
+/// Returns a Grafana dashboard (json) displaying the metrics of cloud scanner, optionally bound to the uid of a Prometheus datasource
+pub fn get_grafana_dashboard(datasource_uid: Option<&str>) -> Result<String> {
+    grafana_dashboard::get_dashboard(datasource_uid)
+}
+
 /// Starts a server that exposes metrics http like <http://localhost:8000/metrics?aws-region=eu-west-1>
-pub async fn serve_metrics(api_url: &str) -> Result<()> {
+#[cfg(all(feature = "server", feature = "reports"))]
+pub async fn serve_metrics(
+    api_url: &str,
+    settings: config_file::ServerSettings,
+    metric_options: metric_exporter::MetricOptions,
+) -> Result<()> {
     let config = standalone_server::Config {
         boavizta_url: api_url.to_string(),
+        address: settings.address,
+        port: settings.port,
+        auth: settings.auth,
+        tls: settings.tls,
+        scheduled_scans: settings.scheduled_scans,
+        store: settings.store,
+        cache_ttl: Duration::from_secs(settings.cache_ttl_minutes * 60),
+        accounts: settings.accounts,
+        rate_limit: settings.rate_limit,
+        cors: settings.cors,
+        grpc_port: settings.grpc_port,
+        callback_secret: settings.callback_secret,
+        callback_allowed_hosts: settings.callback_allowed_hosts,
+        tenants: settings.tenants,
+        graphql: settings.graphql,
+        access_log: settings.access_log.unwrap_or(true),
+        workers: settings.workers,
+        request_timeout: settings.request_timeout_seconds.map(Duration::from_secs),
+        shutdown_grace: settings.shutdown_grace_seconds.map(Duration::from_secs),
+        compression: settings.compression,
+        metric_options,
     };
     warn!("Starting server.");
     standalone_server::run(config).await?;
@@ -247,6 +1828,7 @@ async fn summary_has_to_contain_a_usage_duration() {
     let resources_with_impacts: EstimatedInventory = EstimatedInventory {
         impacting_resources: resources,
         execution_statistics: None,
+        errors: Vec::new(),
     };
 
     let usage_duration_hours = 1.5;
@@ -263,3 +1845,143 @@ async fn summary_has_to_contain_a_usage_duration() {
         "Duration of summary should match"
     );
 }
+
+#[test]
+fn summary_of_regions_has_a_sub_summary_per_region() {
+    let resources_with_impacts: EstimatedInventory = EstimatedInventory {
+        impacting_resources: Vec::new(),
+        execution_statistics: None,
+        errors: Vec::new(),
+    };
+    let regions = vec!["us-east-1".to_string(), "eu-west-1".to_string()];
+
+    let summary = build_summary_of_regions(&resources_with_impacts, &regions, &1.0).unwrap();
+
+    assert_eq!("eu-west-1,us-east-1", summary.aws_regions());
+    assert_eq!("IRL", summary.regions[0].country);
+    assert!(check_regions(&regions).is_ok());
+    assert!(check_regions(&["mars-north-1".to_string()]).is_err());
+}
+
+#[test]
+fn summary_of_accounts_has_a_sub_summary_per_account_and_region() {
+    let resources_with_impacts: EstimatedInventory = EstimatedInventory {
+        impacting_resources: Vec::new(),
+        execution_statistics: None,
+        errors: Vec::new(),
+    };
+    let accounts: Vec<AwsAccount> = ["111111111111", "222222222222"]
+        .iter()
+        .map(|id| {
+            AwsAccount::from_role_arn(&format!("arn:aws:iam::{}:role/cloud-scanner", id)).unwrap()
+        })
+        .collect();
+    let regions = vec!["eu-west-1".to_string(), "eu-west-3".to_string()];
+
+    let summary =
+        build_summary_of_accounts(&resources_with_impacts, &accounts, &regions, &1.0).unwrap();
+
+    assert_eq!(4, summary.regions.len());
+    assert_eq!(
+        Some("222222222222"),
+        summary.regions[3].account_id.as_deref()
+    );
+    assert_eq!("eu-west-3", summary.regions[3].aws_region);
+    assert_eq!("eu-west-1,eu-west-3", summary.aws_regions());
+    assert!(build_summary_of_accounts(&resources_with_impacts, &accounts, &[], &1.0).is_err());
+}
+
+#[cfg(feature = "stores")]
+#[test]
+fn purged_scans_are_read_from_the_aggregates_of_the_store() {
+    use crate::impact_provider::ImpactsValues;
+    use crate::model::{CloudProvider, CloudResource, ResourceDetails};
+
+    // A daily scan from the 1st of March to the 31st of May, a resource emitting 10 kgCO2eq a day
+    let inventory = EstimatedInventory {
+        impacting_resources: vec![CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                id: "i-1".to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details: ResourceDetails::Instance {
+                    instance_type: "t3.micro".to_string(),
+                    usage: None,
+                },
+                tags: Vec::new(),
+                account_id: None,
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues {
+                gwp_manufacture_kgco2eq: 4.0,
+                gwp_use_kgco2eq: 6.0,
+                ..Default::default()
+            }),
+            impacts_duration_hours: 24.0,
+            cost: None,
+        }],
+        execution_statistics: None,
+        errors: Vec::new(),
+    };
+    let summary = ImpactsSummary::new("eu-west-3".into(), "FRA".into(), &inventory, 24.0);
+    let path = std::env::temp_dir().join(format!(
+        "cloud-scanner-retention-test-{}.sqlite",
+        std::process::id()
+    ));
+    let path = path.to_str().unwrap();
+    let first_day: DateTime<Utc> = "2024-03-01T00:00:00Z".parse().unwrap();
+    {
+        let mut store = ResultStore::open(path).unwrap();
+        for day in 0..92 {
+            store
+                .save_scan(
+                    first_day + chrono::Duration::days(day),
+                    &summary,
+                    &inventory,
+                )
+                .unwrap();
+        }
+    }
+    let monthly = |store_path: &str| {
+        aggregate_store(
+            store_path,
+            None,
+            None,
+            scan_aggregation::AggregationPeriod::Monthly,
+            None,
+            &allocation::AllocationRules::default(),
+            false,
+        )
+        .unwrap()
+        .iter()
+        .map(|row| row.gwp_manufacture_kgco2eq + row.gwp_use_kgco2eq)
+        .collect::<Vec<f64>>()
+    };
+    let before_purge = monthly(path);
+    assert_eq!(vec![310.0, 300.0, 310.0], before_purge);
+
+    // 30 days of scans kept on the 31st of May: March and April are aggregated
+    let retention: retention::RetentionConfig = toml::from_str("keep_scans = \"30d\"").unwrap();
+    let now: DateTime<Utc> = "2024-05-31T12:00:00Z".parse().unwrap();
+    let outcome = purge_store(
+        path,
+        &retention,
+        &allocation::AllocationRules::default(),
+        now,
+    )
+    .unwrap();
+    assert_eq!(2, outcome.added_aggregates);
+    // The scan of the 30th of April covers the 1st of May
+    assert_eq!(60, outcome.purged_scans);
+    assert_eq!(before_purge, monthly(path));
+    // Nothing more to purge until June
+    assert!(purge_store(
+        path,
+        &retention,
+        &allocation::AllocationRules::default(),
+        now
+    )
+    .unwrap()
+    .is_empty());
+    std::fs::remove_file(path).unwrap();
+}
@@ -1,15 +1,21 @@
 //!  A module to format the results of cloud-scanner into OpenMetrics (Prometheus format) metrics
 use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::atomic::AtomicU64;
 
-use crate::impact_provider::CloudResourceWithImpacts;
+use crate::impact_provider::{CloudResourceWithImpacts, GroupSummary, ImpactsValues};
 use prometheus_client::encoding::text::encode;
 use prometheus_client::encoding::{EncodeLabelSet, EncodeLabelValue};
+use prometheus_client::metrics::exemplar::HistogramWithExemplars;
 use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::family::MetricConstructor;
 use prometheus_client::metrics::gauge::*;
+use prometheus_client::metrics::histogram::exponential_buckets;
 use prometheus_client::registry::Registry;
 
-use crate::model::{EstimatedInventory, InstanceState, ResourceDetails};
+use crate::model::{EstimatedInventory, InstanceState, Inventory, ResourceDetails};
+use crate::result_envelope::ResultMetadata;
 use crate::ImpactsSummary;
 
 // Define a type representing a metric label set, i.e. a key value pair.
@@ -19,6 +25,71 @@ pub struct SummaryLabels {
     pub country: String,
 }
 #[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+pub struct SciLabels {
+    pub awsregion: String,
+    pub country: String,
+    pub functional_unit: String,
+}
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+pub struct GroupLabels {
+    pub awsregion: String,
+    pub country: String,
+    pub tag_key: String,
+    /// Empty for the resources that do not have the tag
+    pub tag_value: String,
+}
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+pub struct KindLabels {
+    pub awsregion: String,
+    pub country: String,
+    pub resource_kind: ResourceType,
+    /// Family of the instance type (like `g4dn` for `g4dn.xlarge`), empty for other resources
+    pub instance_family: String,
+}
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+pub struct InventoryLabels {
+    pub awsregion: String,
+    pub country: String,
+    pub resource_kind: ResourceType,
+    /// Instance type or storage type, empty for other resources
+    pub resource_type: String,
+    pub resource_state: ResourceState,
+}
+/// Labels of the metadata of a scan: how its numbers were produced
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+pub struct ScanInfoLabels {
+    pub cloud_scanner_version: String,
+    pub provider: String,
+    /// Empty for inventories (no impacts)
+    pub impact_provider: String,
+    /// Empty when the provider does not report it
+    pub impact_provider_version: String,
+    /// Scanned regions, separated by commas
+    pub regions: String,
+    /// Empty for inventories
+    pub use_duration_hours: String,
+    /// Tag filters, separated by commas
+    pub filter_tags: String,
+    pub include_block_storage: String,
+    /// States of the inventoried instances, separated by commas (empty for all states)
+    pub include_states: String,
+}
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+pub struct RegionFactorLabels {
+    pub awsregion: String,
+    pub country: String,
+    /// Source of the factor, as reported by Boavizta API
+    pub source: String,
+}
+#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord, EncodeLabelSet, Debug)]
+pub struct TagAggregateLabels {
+    pub awsregion: String,
+    pub country: String,
+    pub tag_key: String,
+    /// Empty for the resources that do not have the tag
+    pub tag_value: String,
+}
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
 pub struct ResourceLabels {
     pub awsregion: String,
     pub country: String,
@@ -26,6 +97,44 @@ pub struct ResourceLabels {
     pub resource_id: String,
     pub resource_tags: String,
     pub resource_state: ResourceState,
+    /// Tags mapped to labels (`[metrics] tag_labels` of the configuration file)
+    #[prometheus(flatten)]
+    pub tag_labels: Vec<(String, String)>,
+}
+
+/// Labels of the opt-in impact metrics of resources, to slice impacts by resource without the tags and state of `ResourceLabels`
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+pub struct ResourceImpactLabels {
+    pub awsregion: String,
+    pub resource_id: String,
+    pub resource_kind: ResourceType,
+    /// Empty for the resources that are not instances
+    pub instance_type: String,
+    pub phase: ImpactPhase,
+    /// Tags mapped to labels (`[metrics] tag_labels` of the configuration file)
+    #[prometheus(flatten)]
+    pub tag_labels: Vec<(String, String)>,
+}
+
+/// Labels of the histograms of the impacts of resources
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+pub struct HistogramLabels {
+    pub awsregion: String,
+    pub country: String,
+    pub resource_kind: ResourceType,
+}
+
+/// Labels of the exemplars of the histograms: the resource with the highest impacts of a bucket
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+pub struct ExemplarLabels {
+    pub resource_id: String,
+}
+
+/// Phase of the life cycle of a resource
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum ImpactPhase {
+    Manufacture,
+    Use,
 }
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
@@ -33,6 +142,7 @@ pub enum ResourceType {
     BlockStorage,
     Instance,
     ObjectStorage,
+    Server,
 }
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
@@ -42,17 +152,320 @@ pub enum ResourceState {
     Unknown,
 }
 
-fn build_resource_labels(resource: &CloudResourceWithImpacts) -> ResourceLabels {
-    let resource_type = match resource.clone().cloud_resource.resource_details {
+/// Labels of the metrics, which tags cannot be mapped to
+const RESERVED_LABELS: [&str; 12] = [
+    "awsregion",
+    "country",
+    "functional_unit",
+    "instance_type",
+    "phase",
+    "resource_id",
+    "resource_kind",
+    "resource_state",
+    "resource_tags",
+    "resource_type",
+    "tag_key",
+    "tag_value",
+];
+
+/// Prefix of the names of the metrics of impacts
+pub const DEFAULT_NAMESPACE: &str = "boavizta";
+
+/// Prefix of the name of the metric of the metadata of scans, whatever the namespace
+const SCAN_INFO_NAMESPACE: &str = "cloud_scanner";
+
+/// Settings of the metrics (`[metrics]` of the configuration file)
+///
+/// ```toml
+/// [metrics]
+/// # Prefix of the names of the metrics (boavizta by default)
+/// namespace = "greenops"
+/// # Labels added to every metric, to tell deployments apart in a shared Prometheus
+/// constant_labels = { env = "prod", org = "acme", account = "123456789012" }
+/// # Tags of the resources added as labels of the metrics of resources (tag = label)
+/// tag_labels = { Team = "team", Service = "service" }
+/// # Tags whose values aggregate the impacts of the resources (one series per region and tag value)
+/// aggregate_by_tags = ["team"]
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Prefix of the names of the metrics, instead of `boavizta`
+    pub namespace: Option<String>,
+    /// Labels with the same value on every metric, by name
+    #[serde(default)]
+    pub constant_labels: BTreeMap<String, String>,
+    /// Allow-list of the tags mapped to labels, by tag key: other tags are only part of `resource_tags`, to control the cardinality of the metrics
+    #[serde(default)]
+    pub tag_labels: BTreeMap<String, String>,
+    /// Tag keys whose values aggregate the impacts of the resources
+    #[serde(default)]
+    pub aggregate_by_tags: Vec<String>,
+}
+
+/// A tag of the resources exported as a label
+#[derive(Clone, Debug, PartialEq)]
+pub struct TagLabel {
+    pub tag_key: String,
+    /// Name of the label (a valid Prometheus label name)
+    pub label: String,
+}
+
+/// Returns a valid Prometheus label name: characters other than letters, digits and `_` are replaced by `_`, names starting with a digit are prefixed with `_`
+pub fn sanitize_label_name(name: &str) -> String {
+    let mut label: String = name
+        .trim()
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    if label.starts_with(|c: char| c.is_ascii_digit()) {
+        label.insert(0, '_');
+    }
+    label
+}
+
+impl MetricsConfig {
+    /// Returns the tags mapped to labels, with sanitized label names, failing on empty, reserved or duplicated labels
+    pub fn tag_labels(&self) -> Result<Vec<TagLabel>> {
+        let mut tag_labels: Vec<TagLabel> = Vec::new();
+        for (tag_key, label) in self.tag_labels.iter() {
+            let label = sanitize_label_name(label);
+            if label.is_empty() || label.starts_with("__") {
+                anyhow::bail!("Invalid label '{}' for tag {}", label, tag_key);
+            }
+            if RESERVED_LABELS.contains(&label.as_str()) || label == "tenant" {
+                anyhow::bail!("Label {} of tag {} is reserved", label, tag_key);
+            }
+            if let Some(other) = tag_labels.iter().find(|other| other.label == label) {
+                anyhow::bail!(
+                    "Tags {} and {} are mapped to the same label {}",
+                    other.tag_key,
+                    tag_key,
+                    label
+                );
+            }
+            tag_labels.push(TagLabel {
+                tag_key: tag_key.clone(),
+                label,
+            });
+        }
+        Ok(tag_labels)
+    }
+
+    /// Returns the options of the metrics of these settings, failing on an invalid namespace or invalid labels
+    pub fn options(&self) -> Result<MetricOptions> {
+        let namespace = match &self.namespace {
+            Some(namespace) => {
+                let is_valid = namespace.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                    && namespace
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_');
+                if !is_valid {
+                    anyhow::bail!(
+                        "Invalid namespace '{}' (expecting letters, digits and '_', like boavizta)",
+                        namespace
+                    );
+                }
+                Some(namespace.clone())
+            }
+            None => None,
+        };
+        let tag_labels = self.tag_labels()?;
+        let mut constant_labels: Vec<(String, String)> = Vec::new();
+        for (name, value) in self.constant_labels.iter() {
+            if sanitize_label_name(name) != *name || name.is_empty() || name.starts_with("__") {
+                anyhow::bail!("Invalid name of constant label '{}'", name);
+            }
+            if RESERVED_LABELS.contains(&name.as_str())
+                || name == "tenant"
+                || tag_labels.iter().any(|tag_label| tag_label.label == *name)
+            {
+                anyhow::bail!("Constant label {} is already a label of the metrics", name);
+            }
+            constant_labels.push((name.clone(), value.clone()));
+        }
+        if self
+            .aggregate_by_tags
+            .iter()
+            .any(|tag_key| tag_key.is_empty())
+        {
+            anyhow::bail!("Invalid empty tag key of aggregate_by_tags");
+        }
+        Ok(MetricOptions {
+            namespace,
+            constant_labels,
+            tag_labels,
+            aggregate_by_tags: self.aggregate_by_tags.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Options of the metrics of resources
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MetricOptions {
+    /// Adds the impact metrics of resources (labelled with their id, kind and instance type)
+    pub resource_impacts: bool,
+    /// Tags added as labels of the metrics of resources
+    pub tag_labels: Vec<TagLabel>,
+    /// Adds the histograms of the impacts of resources
+    pub histograms: bool,
+    /// Attaches exemplars (the resource with the highest impacts) to the buckets of the histograms, for OpenMetrics scrapers
+    pub exemplars: bool,
+    /// Prefix of the names of the metrics (`boavizta` when not set)
+    pub namespace: Option<String>,
+    /// Labels added to every metric
+    pub constant_labels: Vec<(String, String)>,
+    /// Adds the impacts of the resources aggregated by the values of these tags
+    pub aggregate_by_tags: Vec<String>,
+    /// Metadata of the scan, added as the `cloud_scanner_scan_info` metric
+    pub scan_info: Option<ResultMetadata>,
+}
+
+/// Returns the labels of the tags of a resource (empty values for the tags it does not have)
+fn tag_label_values(
+    resource: &CloudResourceWithImpacts,
+    tag_labels: &[TagLabel],
+) -> Vec<(String, String)> {
+    tag_labels
+        .iter()
+        .map(|tag_label| {
+            let value = resource
+                .cloud_resource
+                .tags
+                .iter()
+                .find(|tag| tag.key == tag_label.tag_key)
+                .and_then(|tag| tag.value.clone())
+                .unwrap_or_default();
+            (tag_label.label.clone(), value)
+        })
+        .collect()
+}
+
+/// An impact criterion, exported as one metric family per phase (manufacture and use) for the summary, the groups and the resources
+pub struct ImpactCriterion {
+    /// Name of the criterion in the names of the metrics (like `gwp`)
+    pub name: &'static str,
+    /// Unit in the names of the metrics (like `kgco2eq`)
+    pub unit: &'static str,
+    pub manufacture_help: &'static str,
+    /// Description of the embodied impacts of resources
+    pub embodied_help: &'static str,
+    pub use_help: &'static str,
+    /// Name of the criterion in descriptions (like `Global Warming Potential`)
+    pub title: &'static str,
+    /// Impacts of manufacture and use
+    pub values: fn(&ImpactsValues) -> (f64, f64),
+    /// Upper bound of the first bucket of the histogram of the impacts of resources
+    pub histogram_start: f64,
+}
+
+/// Criteria of the impacts, each one getting its metric families (adding a criterion adds its families everywhere)
+pub const IMPACT_CRITERIA: [ImpactCriterion; 3] = [
+    ImpactCriterion {
+        name: "pe",
+        unit: "megajoules",
+        manufacture_help: "Energy consumed for manufacture",
+        embodied_help: "Energy consumed for manufacture",
+        use_help: "Energy consumed during use",
+        title: "Primary energy",
+        values: |i| (i.pe_manufacture_megajoules, i.pe_use_megajoules),
+        histogram_start: 0.01,
+    },
+    ImpactCriterion {
+        name: "adp",
+        unit: "kgsbeq",
+        manufacture_help: "Abiotic resources depletion potential of manufacture",
+        embodied_help: "Abiotic resources depletion potential of embodied impacts",
+        use_help: "Abiotic resources depletion potential of use",
+        title: "Abiotic resources depletion potential",
+        values: |i| (i.adp_manufacture_kgsbeq, i.adp_use_kgsbeq),
+        histogram_start: 0.000_000_001,
+    },
+    ImpactCriterion {
+        name: "gwp",
+        unit: "kgco2eq",
+        manufacture_help: "Global Warming Potential of manufacture",
+        embodied_help: "Global Warming Potential of embodied impacts",
+        use_help: "Global Warming Potential of use",
+        title: "Global Warming Potential",
+        values: |i| (i.gwp_manufacture_kgco2eq, i.gwp_use_kgco2eq),
+        histogram_start: 0.001,
+    },
+];
+
+type ImpactFamily<L> = Family<L, Gauge<f64, AtomicU64>>;
+
+/// Families of the impacts of manufacture and use of each criterion
+type ImpactFamilies<L> = Vec<(&'static ImpactCriterion, ImpactFamily<L>, ImpactFamily<L>)>;
+
+/// Joins the parts of the name of a metric (an empty prefix being skipped)
+fn metric_name(parts: &[&str]) -> String {
+    parts
+        .iter()
+        .filter(|part| !part.is_empty())
+        .copied()
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Registers the families of the impacts of manufacture and use of each criterion, named like `<prefix>_gwp_<manufacture>_kgco2eq` and `<prefix>_gwp_use_kgco2eq`
+fn register_impact_families<L>(
+    registry: &mut Registry,
+    prefix: &str,
+    manufacture: &str,
+    help: impl Fn(&ImpactCriterion, ImpactPhase) -> String,
+) -> ImpactFamilies<L>
+where
+    L: Clone + std::hash::Hash + Eq + EncodeLabelSet + std::fmt::Debug + Send + Sync + 'static,
+{
+    IMPACT_CRITERIA
+        .iter()
+        .map(|criterion| {
+            let manufacture_family = ImpactFamily::<L>::default();
+            registry.register(
+                metric_name(&[prefix, criterion.name, manufacture, criterion.unit]),
+                help(criterion, ImpactPhase::Manufacture),
+                manufacture_family.clone(),
+            );
+            let use_family = ImpactFamily::<L>::default();
+            registry.register(
+                metric_name(&[prefix, criterion.name, "use", criterion.unit]),
+                help(criterion, ImpactPhase::Use),
+                use_family.clone(),
+            );
+            (criterion, manufacture_family, use_family)
+        })
+        .collect()
+}
+
+fn resource_type(resource_details: &ResourceDetails) -> ResourceType {
+    match resource_details {
         ResourceDetails::Instance { .. } => ResourceType::Instance,
         ResourceDetails::BlockStorage { .. } => ResourceType::BlockStorage,
         ResourceDetails::ObjectStorage => ResourceType::ObjectStorage,
-    };
-    let resource_state = match resource.clone().cloud_resource.resource_details {
+        ResourceDetails::Server { .. } => ResourceType::Server,
+    }
+}
+
+fn build_resource_labels(
+    resource: &CloudResourceWithImpacts,
+    tag_labels: &[TagLabel],
+) -> ResourceLabels {
+    let resource_type = resource_type(&resource.cloud_resource.resource_details);
+    let resource_state = match &resource.cloud_resource.resource_details {
         ResourceDetails::Instance {
-            instance_type: _,
-            usage,
-        } => match usage.unwrap().state {
+            usage: Some(usage), ..
+        } => match usage.state {
+            InstanceState::Running => ResourceState::Running,
+            InstanceState::Stopped => ResourceState::Stopped,
+        },
+        ResourceDetails::Server {
+            usage: Some(usage), ..
+        } => match usage.state {
             InstanceState::Running => ResourceState::Running,
             InstanceState::Stopped => ResourceState::Stopped,
         },
@@ -66,104 +479,64 @@ fn build_resource_labels(resource: &CloudResourceWithImpacts) -> ResourceLabels
         resource_id: resource.cloud_resource.id.clone(),
         resource_tags: resource.cloud_resource.tags_as_metric_label_value(),
         resource_state,
+        tag_labels: tag_label_values(resource, tag_labels),
     }
 }
 
 pub fn register_resource_metrics(
     registry: &mut Registry,
-    resources_with_impacts: Vec<CloudResourceWithImpacts>,
+    resources_with_impacts: &[CloudResourceWithImpacts],
+    tag_labels: &[TagLabel],
 ) {
     // Register metrics
     let boavizta_resource_duration_of_use_hours =
         Family::<ResourceLabels, Gauge<f64, AtomicU64>>::default();
     registry.register(
-        "boavizta_resource_duration_of_use_hours",
+        "resource_duration_of_use_hours",
         "Use duration considered to estimate impacts",
         boavizta_resource_duration_of_use_hours.clone(),
     );
-    let boavizta_resource_pe_embodied_megajoules =
-        Family::<ResourceLabels, Gauge<f64, AtomicU64>>::default();
-    registry.register(
-        "boavizta_resource_pe_embodied_megajoules",
-        "Energy consumed for manufacture",
-        boavizta_resource_pe_embodied_megajoules.clone(),
-    );
-    let boavizta_resource_pe_use_megajoules =
-        Family::<ResourceLabels, Gauge<f64, AtomicU64>>::default();
-    registry.register(
-        "boavizta_resource_pe_use_megajoules",
-        "Energy consumed during use",
-        boavizta_resource_pe_use_megajoules.clone(),
-    );
-    let boavizta_resource_adp_embodied_kgsbeq =
-        Family::<ResourceLabels, Gauge<f64, AtomicU64>>::default();
-    registry.register(
-        "boavizta_resource_adp_embodied_kgsbeq",
-        "Abiotic resources depletion potential of embodied impacts",
-        boavizta_resource_adp_embodied_kgsbeq.clone(),
-    );
-    let boavizta_resource_adp_use_kgsbeq =
-        Family::<ResourceLabels, Gauge<f64, AtomicU64>>::default();
-    registry.register(
-        "boavizta_resource_adp_use_kgsbeq",
-        "Abiotic resources depletion potential of use",
-        boavizta_resource_adp_use_kgsbeq.clone(),
-    );
-    let boavizta_resource_gwp_embodied_kgco2eq =
-        Family::<ResourceLabels, Gauge<f64, AtomicU64>>::default();
-    registry.register(
-        "boavizta_resource_gwp_embodied_kgco2eq",
-        "Global Warming Potential of embodied impacts",
-        boavizta_resource_gwp_embodied_kgco2eq.clone(),
-    );
-    let boavizta_resource_gwp_use_kgco2eq =
-        Family::<ResourceLabels, Gauge<f64, AtomicU64>>::default();
-    registry.register(
-        "boavizta_resource_gwp_use_kgco2eq",
-        "Global Warming Potential of use",
-        boavizta_resource_gwp_use_kgco2eq.clone(),
+    let impact_families = register_impact_families::<ResourceLabels>(
+        registry,
+        "resource",
+        "embodied",
+        |criterion, phase| match phase {
+            ImpactPhase::Manufacture => criterion.embodied_help.to_string(),
+            ImpactPhase::Use => criterion.use_help.to_string(),
+        },
     );
 
     let boavizta_resource_cpu_load = Family::<ResourceLabels, Gauge<f64, AtomicU64>>::default();
     registry.register(
-        "boavizta_resource_cpu_load",
+        "resource_cpu_load",
         "CPU load of instance",
         boavizta_resource_cpu_load.clone(),
     );
 
     let boavizta_storage_size_gb = Family::<ResourceLabels, Gauge>::default();
     registry.register(
-        "boavizta_storage_size_gb",
+        "storage_size_gb",
         "Storage size in GB",
         boavizta_storage_size_gb.clone(),
     );
 
     // Fill up metrics values
     for resource in resources_with_impacts.iter() {
-        let resource_labels = build_resource_labels(resource);
-        let impacts = resource.impacts_values.as_ref().unwrap();
+        let resource_labels = build_resource_labels(resource, tag_labels);
 
         boavizta_resource_duration_of_use_hours
             .get_or_create(&resource_labels)
             .set(resource.impacts_duration_hours.into());
-        boavizta_resource_pe_use_megajoules
-            .get_or_create(&resource_labels)
-            .set(impacts.pe_use_megajoules);
-        boavizta_resource_pe_embodied_megajoules
-            .get_or_create(&resource_labels)
-            .set(impacts.pe_manufacture_megajoules);
-        boavizta_resource_adp_use_kgsbeq
-            .get_or_create(&resource_labels)
-            .set(impacts.adp_use_kgsbeq);
-        boavizta_resource_adp_embodied_kgsbeq
-            .get_or_create(&resource_labels)
-            .set(impacts.adp_manufacture_kgsbeq);
-        boavizta_resource_gwp_use_kgco2eq
-            .get_or_create(&resource_labels)
-            .set(impacts.gwp_use_kgco2eq);
-        boavizta_resource_gwp_embodied_kgco2eq
-            .get_or_create(&resource_labels)
-            .set(impacts.gwp_manufacture_kgco2eq);
+        // Resources not assessed have no impacts (they are counted by the metrics by kind)
+        if let Some(impacts) = resource.impacts_values.as_ref() {
+            for (criterion, manufacture, usage) in impact_families.iter() {
+                let (manufacture_value, use_value) = (criterion.values)(impacts);
+                manufacture
+                    .get_or_create(&resource_labels)
+                    .set(manufacture_value);
+                usage.get_or_create(&resource_labels).set(use_value);
+            }
+        }
 
         // Export CPU usage metrics (for instances) and size metrics (for storage)
         match &resource.cloud_resource.resource_details {
@@ -189,12 +562,317 @@ pub fn register_resource_metrics(
         }
     }
 }
+/// Registers the opt-in impact metrics of resources, one series per resource and phase, labelled with the id, kind and instance type of the resource
+pub fn register_resource_impact_metrics(
+    registry: &mut Registry,
+    resources_with_impacts: &[CloudResourceWithImpacts],
+    tag_labels: &[TagLabel],
+) {
+    let families: Vec<ImpactFamily<ResourceImpactLabels>> = IMPACT_CRITERIA
+        .iter()
+        .map(|criterion| {
+            let family = ImpactFamily::<ResourceImpactLabels>::default();
+            registry.register(
+                format!("resource_impact_{}_{}", criterion.name, criterion.unit),
+                format!("{} of the resource, per phase", criterion.title),
+                family.clone(),
+            );
+            family
+        })
+        .collect();
+    for resource in resources_with_impacts.iter() {
+        // Resources not assessed have no impacts
+        let Some(impacts) = resource.impacts_values.as_ref() else {
+            continue;
+        };
+        let details = &resource.cloud_resource.resource_details;
+        // The labels of a resource are built once, for all its series
+        let mut labels = ResourceImpactLabels {
+            awsregion: resource.cloud_resource.location.aws_region.clone(),
+            resource_id: resource.cloud_resource.id.clone(),
+            resource_kind: resource_type(details),
+            instance_type: match details {
+                ResourceDetails::Instance { instance_type, .. } => instance_type.clone(),
+                _ => String::new(),
+            },
+            phase: ImpactPhase::Manufacture,
+            tag_labels: tag_label_values(resource, tag_labels),
+        };
+        for (criterion, family) in IMPACT_CRITERIA.iter().zip(families.iter()) {
+            let (manufacture, usage) = (criterion.values)(impacts);
+            for (phase, value) in [
+                (ImpactPhase::Manufacture, manufacture),
+                (ImpactPhase::Use, usage),
+            ] {
+                labels.phase = phase;
+                family.get_or_create(&labels).set(value);
+            }
+        }
+    }
+}
+
+/// Number of buckets of the histograms, each bucket being twice as large as the previous one (6 to 7 orders of magnitude)
+const HISTOGRAM_BUCKETS: u16 = 24;
+
+/// Registers the number of assessed and not assessed resources of each kind (and family of instance type), to notice the resources whose impacts are not estimated
+pub fn register_kind_metrics(
+    registry: &mut Registry,
+    resources_with_impacts: &[CloudResourceWithImpacts],
+) {
+    let assessed = Family::<KindLabels, Gauge>::default();
+    registry.register(
+        "kind_number_of_resources_assessed",
+        "Number of resources of the kind that were considered in the estimation of impacts",
+        assessed.clone(),
+    );
+    let not_assessed = Family::<KindLabels, Gauge>::default();
+    registry.register(
+        "kind_number_of_resources_not_assessed",
+        "Number of resources of the kind whose impacts could not be estimated",
+        not_assessed.clone(),
+    );
+    for resource in resources_with_impacts.iter() {
+        let details = &resource.cloud_resource.resource_details;
+        let labels = KindLabels {
+            awsregion: resource.cloud_resource.location.aws_region.clone(),
+            country: resource.cloud_resource.location.iso_country_code.clone(),
+            resource_kind: resource_type(details),
+            instance_family: match details {
+                ResourceDetails::Instance { instance_type, .. } => instance_type
+                    .split('.')
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+                _ => String::new(),
+            },
+        };
+        // Both series exist for each kind, so that a kind with no assessed resource reports 0
+        let assessed = assessed.get_or_create(&labels);
+        let not_assessed = not_assessed.get_or_create(&labels);
+        match resource.impacts_values {
+            Some(_) => assessed.inc(),
+            None => not_assessed.inc(),
+        };
+    }
+}
+
+/// Returns the value and source of a factor of the verbose details of Boavizta API (like `gwp_factor`)
+pub(crate) fn verbose_factor(raw_data: &serde_json::Value, name: &str) -> Option<(f64, String)> {
+    let factor = &raw_data["verbose"][name];
+    let value = factor["value"].as_f64()?;
+    let source = factor["source"].as_str().unwrap_or_default().to_string();
+    Some((value, source))
+}
+
+/// Registers the carbon intensity of electricity and the PUE used to estimate the impacts of use in each region, read from the verbose details of Boavizta API (so only for scans of verbose impacts)
+pub fn register_region_factor_metrics(
+    registry: &mut Registry,
+    resources_with_impacts: &[CloudResourceWithImpacts],
+) {
+    let carbon_intensity = Family::<RegionFactorLabels, Gauge<f64, AtomicU64>>::default();
+    let pue = Family::<RegionFactorLabels, Gauge<f64, AtomicU64>>::default();
+    let mut has_carbon_intensity = false;
+    let mut has_pue = false;
+    let mut regions = BTreeSet::new();
+    for resource in resources_with_impacts.iter() {
+        let location = &resource.cloud_resource.location;
+        let Some(raw_data) = resource
+            .impacts_values
+            .as_ref()
+            .and_then(|impacts| impacts.raw_data.as_ref())
+        else {
+            continue;
+        };
+        // The factors are the same for every resource of a region
+        if regions.contains(&location.aws_region) {
+            continue;
+        }
+        let labels = |source: String| RegionFactorLabels {
+            awsregion: location.aws_region.clone(),
+            country: location.iso_country_code.clone(),
+            source,
+        };
+        if let Some((value, source)) = verbose_factor(raw_data, "gwp_factor") {
+            carbon_intensity.get_or_create(&labels(source)).set(value);
+            has_carbon_intensity = true;
+            regions.insert(location.aws_region.clone());
+        }
+        if let Some((value, source)) = verbose_factor(raw_data, "pue") {
+            pue.get_or_create(&labels(source)).set(value);
+            has_pue = true;
+        }
+    }
+    if has_carbon_intensity {
+        registry.register(
+            "region_carbon_intensity_kgco2eq_per_kwh",
+            "Carbon intensity of the electricity of the region, used to estimate the impacts of use",
+            carbon_intensity,
+        );
+    }
+    if has_pue {
+        registry.register(
+            "region_pue",
+            "Power usage effectiveness of the data centers of the region, used to estimate the impacts of use",
+            pue,
+        );
+    }
+}
+
+/// Registers the impacts of the resources aggregated by the values of tags: one series per region and value of each tag, whatever the number of resources
+pub fn register_tag_aggregate_metrics(
+    registry: &mut Registry,
+    resources_with_impacts: &[CloudResourceWithImpacts],
+    tag_keys: &[String],
+) {
+    if tag_keys.is_empty() {
+        return;
+    }
+    let tag_keys: BTreeSet<&str> = tag_keys.iter().map(String::as_str).collect();
+    // Number of resources (total and assessed) and impacts of manufacture and use of each criterion
+    type Aggregate = (usize, usize, [(f64, f64); IMPACT_CRITERIA.len()]);
+    // Aggregates by region, country, tag key and tag value (borrowed from the resources, the labels are only built once per aggregate)
+    let mut aggregates: BTreeMap<(&str, &str, &str, &str), Aggregate> = BTreeMap::new();
+    for tag_key in tag_keys {
+        for resource in resources_with_impacts.iter() {
+            let location = &resource.cloud_resource.location;
+            let tag_value = resource
+                .cloud_resource
+                .tags
+                .iter()
+                .find(|tag| tag.key == tag_key)
+                .and_then(|tag| tag.value.as_deref())
+                .unwrap_or_default();
+            let aggregate = aggregates
+                .entry((
+                    &location.aws_region,
+                    &location.iso_country_code,
+                    tag_key,
+                    tag_value,
+                ))
+                .or_insert((0, 0, [(0.0, 0.0); IMPACT_CRITERIA.len()]));
+            aggregate.0 += 1;
+            if let Some(impacts) = resource.impacts_values.as_ref() {
+                aggregate.1 += 1;
+                for (criterion, total) in IMPACT_CRITERIA.iter().zip(aggregate.2.iter_mut()) {
+                    let (manufacture, usage) = (criterion.values)(impacts);
+                    total.0 += manufacture;
+                    total.1 += usage;
+                }
+            }
+        }
+    }
+
+    let number_of_resources_total = Family::<TagAggregateLabels, Gauge>::default();
+    registry.register(
+        "tag_number_of_resources_total",
+        "Number of resources with the value of the tag detected during the inventory",
+        number_of_resources_total.clone(),
+    );
+    let number_of_resources_assessed = Family::<TagAggregateLabels, Gauge>::default();
+    registry.register(
+        "tag_number_of_resources_assessed",
+        "Number of resources with the value of the tag that were considered in the estimation of impacts",
+        number_of_resources_assessed.clone(),
+    );
+    let impact_families = register_impact_families::<TagAggregateLabels>(
+        registry,
+        "tag",
+        "manufacture",
+        |criterion, phase| {
+            let help = match phase {
+                ImpactPhase::Manufacture => criterion.manufacture_help,
+                ImpactPhase::Use => criterion.use_help,
+            };
+            format!("{} of the resources with the value of the tag", help)
+        },
+    );
+    for ((awsregion, country, tag_key, tag_value), (total, assessed, impacts)) in aggregates {
+        let labels = TagAggregateLabels {
+            awsregion: awsregion.to_string(),
+            country: country.to_string(),
+            tag_key: tag_key.to_string(),
+            tag_value: tag_value.to_string(),
+        };
+        number_of_resources_total
+            .get_or_create(&labels)
+            .set(total as i64);
+        number_of_resources_assessed
+            .get_or_create(&labels)
+            .set(assessed as i64);
+        for ((_, manufacture, usage), (manufacture_value, use_value)) in
+            impact_families.iter().zip(impacts.iter())
+        {
+            manufacture.get_or_create(&labels).set(*manufacture_value);
+            usage.get_or_create(&labels).set(*use_value);
+        }
+    }
+}
+
+/// Buckets of the histogram of a criterion
+#[derive(Clone, Debug)]
+struct HistogramBuckets {
+    start: f64,
+}
+
+impl MetricConstructor<HistogramWithExemplars<ExemplarLabels>> for HistogramBuckets {
+    fn new_metric(&self) -> HistogramWithExemplars<ExemplarLabels> {
+        HistogramWithExemplars::new(exponential_buckets(self.start, 2.0, HISTOGRAM_BUCKETS))
+    }
+}
+
+/// Registers the histograms of the impacts (manufacture and use) of resources, per region and kind of resource, showing whether the impacts are concentrated on a few resources.
 ///
+/// With exemplars, each bucket links to the resource with the highest impacts among the resources it counts, to jump from a dashboard to the responsible resource.
+pub fn register_impact_histograms(
+    registry: &mut Registry,
+    resources_with_impacts: &[CloudResourceWithImpacts],
+    exemplars: bool,
+) {
+    for criterion in IMPACT_CRITERIA.iter() {
+        let family = Family::new_with_constructor(HistogramBuckets {
+            start: criterion.histogram_start,
+        });
+        registry.register(
+            format!(
+                "resource_{}_distribution_{}",
+                criterion.name, criterion.unit
+            ),
+            format!(
+                "Distribution of the {} of the resources (manufacture and use)",
+                criterion.title
+            ),
+            family.clone(),
+        );
+        let mut observations: Vec<(&CloudResourceWithImpacts, f64)> = resources_with_impacts
+            .iter()
+            // Resources not assessed have no impacts
+            .filter_map(|resource| {
+                let (manufacture, usage) = (criterion.values)(resource.impacts_values.as_ref()?);
+                Some((resource, manufacture + usage))
+            })
+            .collect();
+        // The exemplar of a bucket is its last observation
+        observations.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        for (resource, value) in observations {
+            let labels = HistogramLabels {
+                awsregion: resource.cloud_resource.location.aws_region.clone(),
+                country: resource.cloud_resource.location.iso_country_code.clone(),
+                resource_kind: resource_type(&resource.cloud_resource.resource_details),
+            };
+            let exemplar = exemplars.then(|| ExemplarLabels {
+                resource_id: resource.cloud_resource.id.clone(),
+            });
+            family.get_or_create(&labels).observe(value, exemplar);
+        }
+    }
+}
+
+/// Return the impacts of resources as metrics in the prometheus format
 pub fn get_resources_metrics(
-    resources_with_impacts: Vec<CloudResourceWithImpacts>,
+    resources_with_impacts: &[CloudResourceWithImpacts],
 ) -> Result<String> {
-    let mut registry = <Registry>::default();
-    register_resource_metrics(&mut registry, resources_with_impacts);
+    let mut registry = Registry::with_prefix(DEFAULT_NAMESPACE);
+    register_resource_metrics(&mut registry, resources_with_impacts, &[]);
     let mut buffer = String::new();
     encode(&mut buffer, &registry).context("Fails to encode resources impacts into metrics")?;
     let metrics = buffer;
@@ -204,7 +882,7 @@ pub fn get_resources_metrics(
 
 /// Return an ImpactsSummary as metrics in the prometheus format
 pub fn get_summary_metrics(summary: &ImpactsSummary) -> Result<String> {
-    let mut registry = <Registry>::default();
+    let mut registry = Registry::with_prefix(DEFAULT_NAMESPACE);
     register_summary_metrics(&mut registry, summary);
     let mut buffer = String::new();
     encode(&mut buffer, &registry).context("Fails to encode impacts summary into metrics")?;
@@ -214,17 +892,155 @@ pub fn get_summary_metrics(summary: &ImpactsSummary) -> Result<String> {
 
 pub fn get_all_metrics(
     summary: &ImpactsSummary,
-    resources_with_impacts: EstimatedInventory,
+    resources_with_impacts: &EstimatedInventory,
 ) -> Result<String> {
-    let mut registry = <Registry>::default();
-    register_summary_metrics(&mut registry, summary);
-    register_resource_metrics(&mut registry, resources_with_impacts.impacting_resources);
+    get_all_metrics_with_labels(
+        summary,
+        resources_with_impacts,
+        &[],
+        &MetricOptions::default(),
+    )
+}
 
+/// Returns a registry of the namespace of the options, with constant labels added to every metric (the constant labels of the options, then `constant_labels`)
+fn registry_with_labels(constant_labels: &[(String, String)], options: &MetricOptions) -> Registry {
+    Registry::with_prefix_and_labels(
+        options.namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE),
+        options
+            .constant_labels
+            .iter()
+            .chain(constant_labels.iter())
+            .map(|(name, value)| (name.clone().into(), value.clone().into())),
+    )
+}
+
+/// Encodes the metrics of a registry, after the `cloud_scanner_scan_info` metric (always 1) of the metadata of the scan of the options (if any), with the same constant labels
+fn encode_with_scan_info(
+    registry: &Registry,
+    constant_labels: &[(String, String)],
+    options: &MetricOptions,
+) -> Result<String> {
     let mut buffer = String::new();
-    encode(&mut buffer, &registry).context("Fails to encode impacts into metrics")?;
-    let metrics = buffer;
+    if let Some(metadata) = &options.scan_info {
+        let mut info_registry = Registry::with_prefix_and_labels(
+            SCAN_INFO_NAMESPACE,
+            options
+                .constant_labels
+                .iter()
+                .chain(constant_labels.iter())
+                .map(|(name, value)| (name.clone().into(), value.clone().into())),
+        );
+        let scan_info = Family::<ScanInfoLabels, Gauge>::default();
+        info_registry.register(
+            "scan_info",
+            "Metadata of the scan that produced the metrics (versions, provider and parameters), always 1",
+            scan_info.clone(),
+        );
+        let impact_provider = metadata.impact_provider.as_ref();
+        let parameters = &metadata.parameters;
+        let labels = ScanInfoLabels {
+            cloud_scanner_version: metadata.cloud_scanner_version.clone(),
+            provider: metadata.provider.clone(),
+            impact_provider: impact_provider
+                .map(|provider| provider.name.clone())
+                .unwrap_or_default(),
+            impact_provider_version: impact_provider
+                .and_then(|provider| provider.version.clone())
+                .unwrap_or_default(),
+            regions: parameters.aws_region.clone(),
+            use_duration_hours: parameters
+                .use_duration_hours
+                .map(|hours| hours.to_string())
+                .unwrap_or_default(),
+            filter_tags: parameters.filter_tags.join(","),
+            include_block_storage: parameters.include_block_storage.to_string(),
+            include_states: parameters.include_states.join(","),
+        };
+        scan_info.get_or_create(&labels).set(1);
+        encode(&mut buffer, &info_registry).context("Fails to encode the scan info")?;
+        // The metrics of the registry end the exposition
+        buffer.truncate(buffer.trim_end_matches("# EOF\n").len());
+    }
+    encode(&mut buffer, registry).context("Fails to encode metrics")?;
+    Ok(buffer)
+}
 
-    Ok(metrics)
+/// Return the inventory as metrics in the prometheus format: the number of resources of each kind, type and state per region, without estimating impacts (much cheaper than a scan of impacts, so it can be run more often)
+pub fn get_inventory_metrics(
+    inventory: &Inventory,
+    constant_labels: &[(String, String)],
+    options: &MetricOptions,
+) -> Result<String> {
+    let mut registry = registry_with_labels(constant_labels, options);
+    let number_of_resources = Family::<InventoryLabels, Gauge>::default();
+    registry.register(
+        "inventory_number_of_resources",
+        "Number of resources of the inventory, by kind, type and state",
+        number_of_resources.clone(),
+    );
+    for resource in inventory.resources.iter() {
+        let details = &resource.resource_details;
+        let resource_state = match details {
+            ResourceDetails::Instance {
+                usage: Some(usage), ..
+            } => match usage.state {
+                InstanceState::Running => ResourceState::Running,
+                InstanceState::Stopped => ResourceState::Stopped,
+            },
+            _ => ResourceState::Unknown,
+        };
+        let labels = InventoryLabels {
+            awsregion: resource.location.aws_region.clone(),
+            country: resource.location.iso_country_code.clone(),
+            resource_kind: resource_type(details),
+            resource_type: details.resource_type().unwrap_or_default(),
+            resource_state,
+        };
+        number_of_resources.get_or_create(&labels).inc();
+    }
+
+    encode_with_scan_info(&registry, constant_labels, options)
+        .context("Fails to encode inventory into metrics")
+}
+
+/// Return the summary and the impacts of resources as metrics in the prometheus format, with constant labels added to every metric (like the tenant of the scan, after the constant labels of the options), and the options of the metrics
+pub fn get_all_metrics_with_labels(
+    summary: &ImpactsSummary,
+    resources_with_impacts: &EstimatedInventory,
+    constant_labels: &[(String, String)],
+    options: &MetricOptions,
+) -> Result<String> {
+    let mut registry = registry_with_labels(constant_labels, options);
+    register_summary_metrics(&mut registry, summary);
+    if options.histograms {
+        register_impact_histograms(
+            &mut registry,
+            &resources_with_impacts.impacting_resources,
+            options.exemplars,
+        );
+    }
+    register_kind_metrics(&mut registry, &resources_with_impacts.impacting_resources);
+    register_region_factor_metrics(&mut registry, &resources_with_impacts.impacting_resources);
+    register_tag_aggregate_metrics(
+        &mut registry,
+        &resources_with_impacts.impacting_resources,
+        &options.aggregate_by_tags,
+    );
+    if options.resource_impacts {
+        register_resource_impact_metrics(
+            &mut registry,
+            &resources_with_impacts.impacting_resources,
+            &options.tag_labels,
+        );
+    }
+    register_resource_metrics(
+        &mut registry,
+        &resources_with_impacts.impacting_resources,
+        &options.tag_labels,
+    );
+
+    encode_with_scan_info(&registry, constant_labels, options)
+        .context("Fails to encode impacts into metrics")
 }
 
 fn register_summary_metrics(registry: &mut Registry, summary: &ImpactsSummary) {
@@ -232,7 +1048,7 @@ fn register_summary_metrics(registry: &mut Registry, summary: &ImpactsSummary) {
     // Register the metric family with the registry.
     registry.register(
         // With the metric name.
-        "boavizta_number_of_resources_total",
+        "number_of_resources_total",
         // And the metric help text.
         "Number of resources detected during the inventory",
         boavizta_number_of_resources_total.clone(),
@@ -242,7 +1058,7 @@ fn register_summary_metrics(registry: &mut Registry, summary: &ImpactsSummary) {
     // Register the metric family with the registry.
     registry.register(
         // With the metric name.
-        "boavizta_number_of_resources_assessed",
+        "number_of_resources_assessed",
         // And the metric help text.
         "Number of resources that were considered in the estimation of impacts",
         boavizta_number_of_resources_assessed.clone(),
@@ -252,120 +1068,237 @@ fn register_summary_metrics(registry: &mut Registry, summary: &ImpactsSummary) {
     // Register the metric family with the registry.
     registry.register(
         // With the metric name.
-        "boavizta_duration_of_use_hours",
+        "duration_of_use_hours",
         // And the metric help text.
         "Use duration considered to estimate impacts",
         boavizta_duration_of_use_hours.clone(),
     );
 
-    let boavizta_pe_manufacture_megajoules =
-        Family::<SummaryLabels, Gauge<f64, AtomicU64>>::default();
-    // Register the metric family with the registry.
-    registry.register(
-        // With the metric name.
-        "boavizta_pe_manufacture_megajoules",
-        // And the metric help text.
-        "Energy consumed for manufacture",
-        boavizta_pe_manufacture_megajoules.clone(),
+    let impact_families = register_impact_families::<SummaryLabels>(
+        registry,
+        "",
+        "manufacture",
+        |criterion, phase| match phase {
+            ImpactPhase::Manufacture => criterion.manufacture_help.to_string(),
+            ImpactPhase::Use => criterion.use_help.to_string(),
+        },
     );
 
-    let boavizta_pe_use_megajoules = Family::<SummaryLabels, Gauge<f64, AtomicU64>>::default();
-    // Register the metric family with the registry.
-    registry.register(
-        // With the metric name.
-        "boavizta_pe_use_megajoules",
-        // And the metric help text.
-        "Energy consumed during use",
-        boavizta_pe_use_megajoules.clone(),
-    );
+    // One series per region, the sub-summaries of several accounts of the same region are added up
+    for region in summary.regions.iter() {
+        let summary_labels: SummaryLabels = SummaryLabels {
+            awsregion: region.aws_region.to_string(),
+            country: region.country.to_string(),
+        };
 
-    let boavizta_adp_manufacture_kgsbeq = Family::<SummaryLabels, Gauge<f64, AtomicU64>>::default();
-    // Register the metric family with the registry.
-    registry.register(
-        // With the metric name.
-        "boavizta_adp_manufacture_kgsbeq",
-        // And the metric help text.
-        "Abiotic resources depletion potential of manufacture",
-        boavizta_adp_manufacture_kgsbeq.clone(),
-    );
+        // Set the values
+        boavizta_number_of_resources_total
+            .get_or_create(&summary_labels)
+            .inc_by(region.number_of_resources_total as i64);
+        boavizta_number_of_resources_assessed
+            .get_or_create(&summary_labels)
+            .inc_by(region.number_of_resources_assessed as i64);
 
-    let boavizta_adp_use_kgsbeq = Family::<SummaryLabels, Gauge<f64, AtomicU64>>::default();
-    // Register the metric family with the registry.
-    registry.register(
-        // With the metric name.
-        "boavizta_adp_use_kgsbeq",
-        // And the metric help text.
-        "Abiotic resources depletion potential of use",
-        boavizta_adp_use_kgsbeq.clone(),
-    );
+        boavizta_duration_of_use_hours
+            .get_or_create(&summary_labels)
+            .set(summary.duration_of_use_hours);
 
-    let boavizta_gwp_manufacture_kgco2eq =
-        Family::<SummaryLabels, Gauge<f64, AtomicU64>>::default();
-    // Register the metric family with the registry.
-    registry.register(
-        // With the metric name.
-        "boavizta_gwp_manufacture_kgco2eq",
-        // And the metric help text.
-        "Global Warming Potential of manufacture",
-        boavizta_gwp_manufacture_kgco2eq.clone(),
-    );
+        let impacts = ImpactsValues {
+            adp_manufacture_kgsbeq: region.adp_manufacture_kgsbeq,
+            adp_use_kgsbeq: region.adp_use_kgsbeq,
+            pe_manufacture_megajoules: region.pe_manufacture_megajoules,
+            pe_use_megajoules: region.pe_use_megajoules,
+            gwp_manufacture_kgco2eq: region.gwp_manufacture_kgco2eq,
+            gwp_use_kgco2eq: region.gwp_use_kgco2eq,
+            raw_data: None,
+        };
+        for (criterion, manufacture, usage) in impact_families.iter() {
+            let (manufacture_value, use_value) = (criterion.values)(&impacts);
+            manufacture
+                .get_or_create(&summary_labels)
+                .inc_by(manufacture_value);
+            usage.get_or_create(&summary_labels).inc_by(use_value);
+        }
+    }
 
-    let boavizta_gwp_use_kgco2eq = Family::<SummaryLabels, Gauge<f64, AtomicU64>>::default();
-    // Register the metric family with the registry.
-    registry.register(
-        // With the metric name.
-        "boavizta_gwp_use_kgco2eq",
-        // And the metric help text.
-        "Global Warming Potential of use",
-        boavizta_gwp_use_kgco2eq.clone(),
-    );
+    // The SCI score only exists when a functional unit was provided
+    if let Some(sci) = &summary.sci {
+        let boavizta_sci_kgco2eq_per_functional_unit =
+            Family::<SciLabels, Gauge<f64, AtomicU64>>::default();
+        registry.register(
+            "sci_kgco2eq_per_functional_unit",
+            "Software Carbon Intensity (GWP of manufacture and use per functional unit)",
+            boavizta_sci_kgco2eq_per_functional_unit.clone(),
+        );
+        // The score is computed for all the regions of the scan
+        let sci_labels = SciLabels {
+            awsregion: summary.aws_regions(),
+            country: summary.countries(),
+            functional_unit: sci.functional_unit.clone(),
+        };
+        boavizta_sci_kgco2eq_per_functional_unit
+            .get_or_create(&sci_labels)
+            .set(sci.sci_kgco2eq_per_unit);
+    }
 
-    let summary_labels: SummaryLabels = SummaryLabels {
-        awsregion: summary.aws_region.to_string(),
-        country: summary.country.to_string(),
-    };
+    if !summary.groups.is_empty() {
+        register_group_metrics(registry, summary);
+    }
+}
 
-    // Set the values
-    boavizta_number_of_resources_total
-        .get_or_create(&summary_labels)
-        .set(summary.number_of_resources_total as i64);
-    boavizta_number_of_resources_assessed
-        .get_or_create(&summary_labels)
-        .set(summary.number_of_resources_assessed as i64);
+/// Name, help and value of a metric of the sub-summaries
+type GroupMetric = (&'static str, &'static str, fn(&GroupSummary) -> f64);
 
-    boavizta_duration_of_use_hours
-        .get_or_create(&summary_labels)
-        .set(summary.duration_of_use_hours);
+/// Registers the sub-summaries of the resources grouped by tag value
+fn register_group_metrics(registry: &mut Registry, summary: &ImpactsSummary) {
+    let group_metrics: [GroupMetric; 3] = [
+        (
+            "group_number_of_resources_total",
+            "Number of resources of the group detected during the inventory",
+            |g| g.number_of_resources_total as f64,
+        ),
+        (
+            "group_number_of_resources_assessed",
+            "Number of resources of the group that were considered in the estimation of impacts",
+            |g| g.number_of_resources_assessed as f64,
+        ),
+        (
+            "group_assessed_share",
+            "Share of the resources of the group that were assessed (between 0 and 1)",
+            |g| g.assessed_share,
+        ),
+    ];
 
-    boavizta_pe_manufacture_megajoules
-        .get_or_create(&summary_labels)
-        .set(summary.pe_manufacture_megajoules);
+    for (name, help, value) in group_metrics {
+        let family = Family::<GroupLabels, Gauge<f64, AtomicU64>>::default();
+        registry.register(name, help, family.clone());
+        for group in summary.groups.iter() {
+            let group_labels = GroupLabels {
+                awsregion: summary.aws_regions(),
+                country: summary.countries(),
+                tag_key: group.tag_key.clone(),
+                tag_value: group.tag_value.clone().unwrap_or_default(),
+            };
+            family.get_or_create(&group_labels).set(value(group));
+        }
+    }
 
-    boavizta_pe_use_megajoules
-        .get_or_create(&summary_labels)
-        .set(summary.pe_use_megajoules);
+    let impact_families = register_impact_families::<GroupLabels>(
+        registry,
+        "group",
+        "manufacture",
+        |criterion, phase| {
+            let help = match phase {
+                ImpactPhase::Manufacture => criterion.manufacture_help,
+                ImpactPhase::Use => criterion.use_help,
+            };
+            format!("{} of the resources of the group", help)
+        },
+    );
+    for group in summary.groups.iter() {
+        let group_labels = GroupLabels {
+            awsregion: summary.aws_regions(),
+            country: summary.countries(),
+            tag_key: group.tag_key.clone(),
+            tag_value: group.tag_value.clone().unwrap_or_default(),
+        };
+        let impacts = ImpactsValues {
+            adp_manufacture_kgsbeq: group.adp_manufacture_kgsbeq,
+            adp_use_kgsbeq: group.adp_use_kgsbeq,
+            pe_manufacture_megajoules: group.pe_manufacture_megajoules,
+            pe_use_megajoules: group.pe_use_megajoules,
+            gwp_manufacture_kgco2eq: group.gwp_manufacture_kgco2eq,
+            gwp_use_kgco2eq: group.gwp_use_kgco2eq,
+            raw_data: None,
+        };
+        for (criterion, manufacture, usage) in impact_families.iter() {
+            let (manufacture_value, use_value) = (criterion.values)(&impacts);
+            manufacture
+                .get_or_create(&group_labels)
+                .set(manufacture_value);
+            usage.get_or_create(&group_labels).set(use_value);
+        }
+    }
+}
 
-    boavizta_adp_manufacture_kgsbeq
-        .get_or_create(&summary_labels)
-        .set(summary.adp_manufacture_kgsbeq);
+/// Pushes metrics to a Prometheus Pushgateway (e.g. http://localhost:9091), replacing the metrics of the `cloud_scanner` job
+pub async fn push_to_pushgateway(pushgateway_url: &str, metrics: String) -> Result<()> {
+    let push_url = format!(
+        "{}/metrics/job/cloud_scanner",
+        pushgateway_url.trim_end_matches('/')
+    );
+    let response = reqwest::Client::new()
+        .put(&push_url)
+        .header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+        .body(metrics)
+        .send()
+        .await
+        .with_context(|| format!("Cannot reach Pushgateway at {}", push_url))?;
 
-    boavizta_adp_use_kgsbeq
-        .get_or_create(&summary_labels)
-        .set(summary.adp_use_kgsbeq);
+    let status = response.status();
+    if !status.is_success() {
+        let message = response.text().await.unwrap_or_default();
+        anyhow::bail!(
+            "Pushgateway push failed with status {}: {}",
+            status,
+            message
+        );
+    }
+    info!("Pushed metrics to Pushgateway {}", pushgateway_url);
+    Ok(())
+}
 
-    boavizta_gwp_manufacture_kgco2eq
-        .get_or_create(&summary_labels)
-        .set(summary.gwp_manufacture_kgco2eq);
+/// Writes metrics to a `.prom` file for the textfile collector of node_exporter.
+///
+/// The file is replaced atomically (written aside in the same directory, then renamed), so that the collector never reads a partial file.
+pub fn write_textfile(path: &str, metrics: &str) -> Result<()> {
+    let path = std::path::Path::new(path);
+    if path.extension().is_none_or(|extension| extension != "prom") {
+        anyhow::bail!(
+            "Invalid textfile {} (the textfile collector only reads files ending with .prom)",
+            path.display()
+        );
+    }
+    // Not ending with .prom, so that the collector ignores it
+    let temporary = path.with_extension(format!("prom.{}.tmp", std::process::id()));
+    std::fs::write(&temporary, metrics)
+        .with_context(|| format!("Cannot write metrics to {}", temporary.display()))?;
+    std::fs::rename(&temporary, path).with_context(|| {
+        let _ = std::fs::remove_file(&temporary);
+        format!("Cannot replace the textfile {}", path.display())
+    })?;
+    info!("Wrote metrics to textfile {}", path.display());
+    Ok(())
+}
 
-    boavizta_gwp_use_kgco2eq
-        .get_or_create(&summary_labels)
-        .set(summary.gwp_use_kgco2eq);
+/// Attaches a timestamp to every sample of metrics in the OpenMetrics format (in seconds, with milliseconds), like the time of the scan that produced them.
+///
+/// Prometheus then records the samples at this time instead of the time of the scrape. The textfile collector and the Pushgateway reject samples with a timestamp.
+pub fn with_timestamp(metrics: &str, timestamp: chrono::DateTime<chrono::Utc>) -> String {
+    let timestamp = format!(
+        "{}.{:03}",
+        timestamp.timestamp(),
+        timestamp.timestamp_subsec_millis()
+    );
+    metrics
+        .lines()
+        .map(|line| match line.is_empty() || line.starts_with('#') {
+            true => format!("{}\n", line),
+            // The timestamp of the sample comes before its exemplar
+            false => match line.split_once(" # {") {
+                Some((sample, exemplar)) => {
+                    format!("{} {} # {{{}\n", sample, timestamp, exemplar)
+                }
+                None => format!("{} {}\n", line, timestamp),
+            },
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::impact_provider::ImpactsValues;
+    use crate::impact_provider::{ImpactsValues, RegionSummary};
     use crate::model::{
         CloudProvider, CloudResource, CloudResourceTag, InstanceUsage, StorageUsage,
     };
@@ -377,6 +1310,7 @@ mod tests {
             number_of_resources_total: 5,
             number_of_resources_assessed: 2,
             number_of_resources_not_assessed: 3,
+            number_of_resources_excluded: None,
             duration_of_use_hours: 1.0,
             adp_manufacture_kgsbeq: 0.1,
             adp_use_kgsbeq: 0.2,
@@ -384,8 +1318,25 @@ mod tests {
             pe_use_megajoules: 0.4,
             gwp_manufacture_kgco2eq: 0.5,
             gwp_use_kgco2eq: 0.6,
-            aws_region: "eu-west-1".to_string(),
-            country: "IRL".to_string(),
+            regions: vec![RegionSummary {
+                account_id: None,
+                aws_region: "eu-west-1".to_string(),
+                country: "IRL".to_string(),
+                number_of_resources_total: 5,
+                number_of_resources_assessed: 2,
+                number_of_resources_not_assessed: 3,
+                adp_manufacture_kgsbeq: 0.1,
+                adp_use_kgsbeq: 0.2,
+                pe_manufacture_megajoules: 0.3,
+                pe_use_megajoules: 0.4,
+                gwp_manufacture_kgco2eq: 0.5,
+                gwp_use_kgco2eq: 0.6,
+            }],
+            sci: None,
+            groups: Vec::new(),
+            cost: None,
+            kubernetes: None,
+            parents: Vec::new(),
         };
 
         let metrics = get_summary_metrics(&summary).unwrap();
@@ -424,6 +1375,701 @@ boavizta_gwp_use_kgco2eq{awsregion="eu-west-1",country="IRL"} 0.6
 
         assert_eq!(expected, metrics);
     }
+
+    #[test]
+    fn sci_score_is_exported_when_available() {
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-1".to_string(),
+            "IRL".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let metrics = get_summary_metrics(&summary).unwrap();
+        assert!(!metrics.contains("boavizta_sci_kgco2eq_per_functional_unit"));
+
+        let summary = summary.with_functional_unit("requests", 10.0).unwrap();
+        let metrics = get_summary_metrics(&summary).unwrap();
+        assert!(metrics.contains("boavizta_sci_kgco2eq_per_functional_unit{awsregion=\"eu-west-1\",country=\"IRL\",functional_unit=\"requests\"} 0.0"));
+    }
+
+    #[test]
+    fn constant_labels_are_added_to_every_metric() {
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-1".to_string(),
+            "IRL".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let labels = [("tenant".to_string(), "team-a".to_string())];
+        let metrics = get_all_metrics_with_labels(
+            &summary,
+            &estimated_inventory,
+            &labels,
+            &Default::default(),
+        )
+        .unwrap();
+        assert!(metrics.contains(
+            "boavizta_number_of_resources_total{tenant=\"team-a\",awsregion=\"eu-west-1\",country=\"IRL\"} 0"
+        ));
+        assert!(metrics
+            .lines()
+            .filter(|line| line.starts_with("boavizta_"))
+            .all(|line| line.contains("tenant=\"team-a\"")));
+    }
+
+    #[test]
+    fn group_summaries_are_exported_with_tag_labels() {
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![CloudResourceWithImpacts {
+                cloud_resource: CloudResource {
+                    provider: CloudProvider::AWS,
+                    account_id: None,
+                    id: "bucket-1".to_string(),
+                    location: UsageLocation::try_from("eu-west-1").unwrap(),
+                    resource_details: ResourceDetails::ObjectStorage,
+                    tags: vec![CloudResourceTag {
+                        key: "team".to_string(),
+                        value: Some("web".to_string()),
+                    }],
+                    relationships: Vec::new(),
+                },
+                impacts_values: Some(ImpactsValues {
+                    gwp_use_kgco2eq: 0.5,
+                    ..Default::default()
+                }),
+                impacts_duration_hours: 1.0,
+                cost: None,
+            }],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-1".to_string(),
+            "IRL".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let metrics = get_summary_metrics(&summary).unwrap();
+        assert!(!metrics.contains("boavizta_group_"));
+
+        let summary = summary.with_groups_by_tag("team", &estimated_inventory);
+        let metrics = get_summary_metrics(&summary).unwrap();
+        assert!(metrics.contains("boavizta_group_gwp_use_kgco2eq{awsregion=\"eu-west-1\",country=\"IRL\",tag_key=\"team\",tag_value=\"web\"} 0.5"));
+        assert!(metrics.contains("boavizta_group_assessed_share{awsregion=\"eu-west-1\",country=\"IRL\",tag_key=\"team\",tag_value=\"web\"} 1.0"));
+    }
+
+    #[test]
+    fn resource_impacts_are_exported_when_requested() {
+        let instance = CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: "i-1".to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details: ResourceDetails::Instance {
+                    instance_type: "m6g.xlarge".to_string(),
+                    usage: Some(InstanceUsage {
+                        average_cpu_load: 50.0,
+                        usage_duration_seconds: 3600,
+                        state: InstanceState::Running,
+                    }),
+                },
+                tags: Vec::new(),
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues {
+                gwp_manufacture_kgco2eq: 0.5,
+                gwp_use_kgco2eq: 0.25,
+                ..Default::default()
+            }),
+            impacts_duration_hours: 1.0,
+            cost: None,
+        };
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![instance],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let metrics = get_all_metrics(&summary, &estimated_inventory).unwrap();
+        assert!(!metrics.contains("boavizta_resource_impact_"));
+
+        let options = MetricOptions {
+            resource_impacts: true,
+            ..Default::default()
+        };
+        let metrics =
+            get_all_metrics_with_labels(&summary, &estimated_inventory, &[], &options).unwrap();
+        assert!(metrics.contains("boavizta_resource_impact_gwp_kgco2eq{awsregion=\"eu-west-3\",resource_id=\"i-1\",resource_kind=\"Instance\",instance_type=\"m6g.xlarge\",phase=\"Manufacture\"} 0.5"), "{}", metrics);
+        assert!(metrics.contains("boavizta_resource_impact_gwp_kgco2eq{awsregion=\"eu-west-3\",resource_id=\"i-1\",resource_kind=\"Instance\",instance_type=\"m6g.xlarge\",phase=\"Use\"} 0.25"));
+        // Each criterion gets its families
+        for criterion in IMPACT_CRITERIA.iter() {
+            for family in [
+                format!("boavizta_{}_manufacture_{}", criterion.name, criterion.unit),
+                format!("boavizta_{}_use_{}", criterion.name, criterion.unit),
+                format!(
+                    "boavizta_resource_{}_embodied_{}",
+                    criterion.name, criterion.unit
+                ),
+                format!(
+                    "boavizta_resource_impact_{}_{}",
+                    criterion.name, criterion.unit
+                ),
+            ] {
+                assert!(
+                    metrics.contains(&format!("# TYPE {} gauge", family)),
+                    "{}",
+                    family
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn instances_without_usage_have_an_unknown_state() {
+        // Like the instances of a terraform plan
+        let instance = CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: "aws_instance.web".to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details: ResourceDetails::Instance {
+                    instance_type: "m6g.xlarge".to_string(),
+                    usage: None,
+                },
+                tags: Vec::new(),
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues {
+                gwp_use_kgco2eq: 0.25,
+                ..Default::default()
+            }),
+            impacts_duration_hours: 1.0,
+            cost: None,
+        };
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![instance],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let metrics = get_all_metrics(&summary, &estimated_inventory).unwrap();
+        assert!(metrics.contains("boavizta_resource_gwp_use_kgco2eq{awsregion=\"eu-west-3\",country=\"FRA\",resource_type=\"Instance\",resource_id=\"aws_instance.web\",resource_tags=\"\",resource_state=\"Unknown\"} 0.25"), "{}", metrics);
+        assert!(!metrics.contains("boavizta_resource_cpu_load{"));
+    }
+
+    #[test]
+    fn impacts_of_resources_are_distributed_in_histograms() {
+        let bucket = |id: &str, gwp_use_kgco2eq: f64| CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: id.to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details: ResourceDetails::ObjectStorage,
+                tags: Vec::new(),
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues {
+                gwp_use_kgco2eq,
+                ..Default::default()
+            }),
+            impacts_duration_hours: 1.0,
+            cost: None,
+        };
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![
+                bucket("b-1", 0.0015),
+                bucket("b-2", 0.003),
+                bucket("b-3", 0.75),
+            ],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let metrics = get_all_metrics(&summary, &estimated_inventory).unwrap();
+        assert!(!metrics.contains("_distribution_"));
+
+        let options = MetricOptions {
+            histograms: true,
+            ..Default::default()
+        };
+        let metrics =
+            get_all_metrics_with_labels(&summary, &estimated_inventory, &[], &options).unwrap();
+        assert!(metrics.contains("# TYPE boavizta_resource_gwp_distribution_kgco2eq histogram"));
+        assert!(metrics.contains(r#"boavizta_resource_gwp_distribution_kgco2eq_count{awsregion="eu-west-3",country="FRA",resource_kind="ObjectStorage"} 3"#), "{}", metrics);
+        assert!(metrics.contains(r#"boavizta_resource_gwp_distribution_kgco2eq_bucket{le="0.002",awsregion="eu-west-3",country="FRA",resource_kind="ObjectStorage"} 1"#), "{}", metrics);
+        assert!(metrics.contains(r#"boavizta_resource_gwp_distribution_kgco2eq_bucket{le="0.512",awsregion="eu-west-3",country="FRA",resource_kind="ObjectStorage"} 2"#));
+        assert!(metrics.contains(r#"boavizta_resource_gwp_distribution_kgco2eq_bucket{le="1.024",awsregion="eu-west-3",country="FRA",resource_kind="ObjectStorage"} 3"#));
+        assert!(metrics.contains("boavizta_resource_pe_distribution_megajoules_count{"));
+        assert!(!metrics.contains(" # {"));
+
+        let options = MetricOptions {
+            histograms: true,
+            exemplars: true,
+            ..Default::default()
+        };
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![
+                bucket("b-1", 0.0015),
+                bucket("b-2", 0.30),
+                bucket("b-3", 0.25),
+            ],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let metrics =
+            get_all_metrics_with_labels(&summary, &estimated_inventory, &[], &options).unwrap();
+        assert!(metrics.contains(r#"boavizta_resource_gwp_distribution_kgco2eq_bucket{le="0.002",awsregion="eu-west-3",country="FRA",resource_kind="ObjectStorage"} 1 # {resource_id="b-1"} 0.0015"#), "{}", metrics);
+        // The resource with the highest impacts of the bucket
+        assert!(metrics.contains(r#"boavizta_resource_gwp_distribution_kgco2eq_bucket{le="0.512",awsregion="eu-west-3",country="FRA",resource_kind="ObjectStorage"} 3 # {resource_id="b-2"} 0.3"#), "{}", metrics);
+    }
+
+    #[test]
+    fn impacts_are_aggregated_by_tag_value() {
+        let resource =
+            |id: &str, team: Option<&str>, gwp_use: Option<f64>| CloudResourceWithImpacts {
+                cloud_resource: CloudResource {
+                    provider: CloudProvider::AWS,
+                    account_id: None,
+                    id: id.to_string(),
+                    location: UsageLocation::try_from("eu-west-3").unwrap(),
+                    resource_details: ResourceDetails::ObjectStorage,
+                    tags: team
+                        .map(|team| CloudResourceTag {
+                            key: "team".to_string(),
+                            value: Some(team.to_string()),
+                        })
+                        .into_iter()
+                        .collect(),
+                    relationships: Vec::new(),
+                },
+                impacts_values: gwp_use.map(|gwp_use_kgco2eq| ImpactsValues {
+                    gwp_use_kgco2eq,
+                    ..Default::default()
+                }),
+                impacts_duration_hours: 1.0,
+                cost: None,
+            };
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![
+                resource("bucket-1", Some("web"), Some(1.0)),
+                resource("bucket-2", Some("web"), Some(2.5)),
+                resource("bucket-3", None, Some(0.5)),
+            ],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let config: MetricsConfig = toml::from_str(r#"aggregate_by_tags = ["team"]"#).unwrap();
+        let options = MetricOptions {
+            aggregate_by_tags: [
+                config.options().unwrap().aggregate_by_tags,
+                vec!["team".to_string()],
+            ]
+            .concat(),
+            ..Default::default()
+        };
+        let metrics =
+            get_all_metrics_with_labels(&summary, &estimated_inventory, &[], &options).unwrap();
+        assert!(metrics.contains(r#"boavizta_tag_gwp_use_kgco2eq{awsregion="eu-west-3",country="FRA",tag_key="team",tag_value="web"} 3.5"#), "{}", metrics);
+        assert!(metrics.contains(r#"boavizta_tag_number_of_resources_total{awsregion="eu-west-3",country="FRA",tag_key="team",tag_value="web"} 2"#), "{}", metrics);
+        assert!(metrics.contains(r#"boavizta_tag_number_of_resources_assessed{awsregion="eu-west-3",country="FRA",tag_key="team",tag_value=""} 1"#), "{}", metrics);
+        assert!(metrics.contains("# HELP boavizta_tag_gwp_use_kgco2eq Global Warming Potential of use of the resources with the value of the tag."));
+
+        let config: MetricsConfig = toml::from_str(r#"aggregate_by_tags = [""]"#).unwrap();
+        assert!(config.options().is_err());
+    }
+
+    #[test]
+    fn resources_not_assessed_are_counted_by_kind() {
+        let instance = |id: &str, instance_type: &str, impacts: Option<ImpactsValues>| {
+            CloudResourceWithImpacts {
+                cloud_resource: CloudResource {
+                    provider: CloudProvider::AWS,
+                    account_id: None,
+                    id: id.to_string(),
+                    location: UsageLocation::try_from("eu-west-3").unwrap(),
+                    resource_details: ResourceDetails::Instance {
+                        instance_type: instance_type.to_string(),
+                        usage: Some(InstanceUsage {
+                            average_cpu_load: 0.0,
+                            usage_duration_seconds: 300,
+                            state: InstanceState::Running,
+                        }),
+                    },
+                    tags: Vec::new(),
+                    relationships: Vec::new(),
+                },
+                impacts_values: impacts,
+                impacts_duration_hours: 1.0,
+                cost: None,
+            }
+        };
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![
+                instance("i-1", "m6g.xlarge", Some(ImpactsValues::default())),
+                instance("i-2", "g4dn.xlarge", None),
+                instance("i-3", "g4dn.2xlarge", None),
+            ],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let metrics = get_all_metrics(&summary, &estimated_inventory).unwrap();
+        assert!(metrics.contains(r#"boavizta_kind_number_of_resources_not_assessed{awsregion="eu-west-3",country="FRA",resource_kind="Instance",instance_family="g4dn"} 2"#), "{}", metrics);
+        assert!(metrics.contains(r#"boavizta_kind_number_of_resources_assessed{awsregion="eu-west-3",country="FRA",resource_kind="Instance",instance_family="g4dn"} 0"#));
+        assert!(metrics.contains(r#"boavizta_kind_number_of_resources_assessed{awsregion="eu-west-3",country="FRA",resource_kind="Instance",instance_family="m6g"} 1"#));
+        // Resources not assessed have no impact series
+        assert!(metrics.contains(r#"resource_id="i-2""#));
+        assert!(!metrics.contains(r#"boavizta_resource_gwp_use_kgco2eq{awsregion="eu-west-3",country="FRA",resource_type="Instance",resource_id="i-2""#));
+    }
+
+    #[test]
+    fn factors_of_the_regions_are_exported_from_verbose_impacts() {
+        let raw_data: serde_json::Value = serde_json::from_str(include_str!(
+            "../test-data/DEFAULT_RAW_IMPACTS_OF_M6GXLARGE_1HRS_FR_VERBOSE.json"
+        ))
+        .unwrap();
+        let instance = |id: &str, raw_data: Option<serde_json::Value>| CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: id.to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details: ResourceDetails::Instance {
+                    instance_type: "m6g.xlarge".to_string(),
+                    usage: Some(InstanceUsage {
+                        average_cpu_load: 100.0,
+                        usage_duration_seconds: 300,
+                        state: InstanceState::Running,
+                    }),
+                },
+                tags: Vec::new(),
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues {
+                raw_data,
+                ..ImpactsValues::default()
+            }),
+            impacts_duration_hours: 1.0,
+            cost: None,
+        };
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![
+                instance("i-1", Some(raw_data.clone())),
+                instance("i-2", Some(raw_data)),
+            ],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let metrics = get_all_metrics(&summary, &estimated_inventory).unwrap();
+        assert!(metrics.contains(r#"boavizta_region_carbon_intensity_kgco2eq_per_kwh{awsregion="eu-west-3",country="FRA",source="https://www.sciencedirect.com/science/article/pii/S0306261921012149"} 0.098"#), "{}", metrics);
+        assert_eq!(
+            1,
+            metrics
+                .matches("boavizta_region_carbon_intensity_kgco2eq_per_kwh{")
+                .count()
+        );
+        // Not reported by Boavizta API for this instance
+        assert!(!metrics.contains("boavizta_region_pue"));
+
+        // Impacts that are not verbose
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![instance("i-1", None)],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let metrics = get_all_metrics(&summary, &estimated_inventory).unwrap();
+        assert!(!metrics.contains("region_carbon_intensity"));
+    }
+
+    #[test]
+    fn inventory_is_counted_by_type_and_state_without_impacts() {
+        let instance = |id: &str, instance_type: &str, state: InstanceState| CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: None,
+            id: id.to_string(),
+            location: UsageLocation::try_from("eu-west-3").unwrap(),
+            resource_details: ResourceDetails::Instance {
+                instance_type: instance_type.to_string(),
+                usage: Some(InstanceUsage {
+                    average_cpu_load: 0.0,
+                    usage_duration_seconds: 300,
+                    state,
+                }),
+            },
+            tags: Vec::new(),
+            relationships: Vec::new(),
+        };
+        let inventory = Inventory {
+            resources: vec![
+                instance("i-1", "m6g.xlarge", InstanceState::Running),
+                instance("i-2", "m6g.xlarge", InstanceState::Running),
+                instance("i-3", "m6g.xlarge", InstanceState::Stopped),
+                instance("i-4", "t2.micro", InstanceState::Running),
+            ],
+            execution_statistics: None,
+        };
+        let metrics = get_inventory_metrics(
+            &inventory,
+            &[("tenant".to_string(), "team-a".to_string())],
+            &MetricOptions::default(),
+        )
+        .unwrap();
+        assert!(metrics.contains(r#"boavizta_inventory_number_of_resources{tenant="team-a",awsregion="eu-west-3",country="FRA",resource_kind="Instance",resource_type="m6g.xlarge",resource_state="Running"} 2"#), "{}", metrics);
+        assert!(metrics.contains(r#"resource_type="m6g.xlarge",resource_state="Stopped"} 1"#));
+        assert!(metrics.contains(r#"resource_type="t2.micro",resource_state="Running"} 1"#));
+        assert!(!metrics.contains("gwp"));
+    }
+
+    #[test]
+    fn metadata_of_the_scan_is_exported_as_scan_info() {
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: Vec::new(),
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let mut metadata = ResultMetadata::new(
+            chrono::Utc::now(),
+            crate::result_envelope::ScanParameters {
+                aws_region: "eu-west-3,eu-west-1".to_string(),
+                use_duration_hours: Some(730.0),
+                filter_tags: vec!["env=prod".to_string(), "team=web".to_string()],
+                include_block_storage: true,
+                include_states: Vec::new(),
+                verbose: false,
+            },
+            Some(crate::result_envelope::ImpactProviderMetadata {
+                name: "Boavizta API".to_string(),
+                url: "https://api.boavizta.org".to_string(),
+                version: Some("1.3.3".to_string()),
+            }),
+        );
+        metadata.cloud_scanner_version = "4.0.0".to_string();
+        let options = MetricOptions {
+            namespace: Some("greenops".to_string()),
+            constant_labels: vec![("env".to_string(), "prod".to_string())],
+            scan_info: Some(metadata),
+            ..MetricOptions::default()
+        };
+        let metrics =
+            get_all_metrics_with_labels(&summary, &estimated_inventory, &[], &options).unwrap();
+        assert!(metrics.contains(r#"cloud_scanner_scan_info{env="prod",cloud_scanner_version="4.0.0",provider="aws",impact_provider="Boavizta API",impact_provider_version="1.3.3",regions="eu-west-3,eu-west-1",use_duration_hours="730",filter_tags="env=prod,team=web",include_block_storage="true",include_states=""} 1"#), "{}", metrics);
+        assert!(metrics.contains(r#"greenops_number_of_resources_total{env="prod""#));
+        // A single exposition
+        assert_eq!(1, metrics.matches("# EOF").count());
+        assert!(metrics.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn metrics_are_written_atomically_to_a_textfile() {
+        let dir =
+            std::env::temp_dir().join(format!("cloud-scanner-textfile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cloud_scanner.prom");
+        let path = path.to_str().unwrap();
+        write_textfile(path, "boavizta_number_of_resources_total 1\n").unwrap();
+        write_textfile(path, "boavizta_number_of_resources_total 2\n").unwrap();
+        assert_eq!(
+            "boavizta_number_of_resources_total 2\n",
+            std::fs::read_to_string(path).unwrap()
+        );
+        // Only the textfile is left in the directory
+        assert_eq!(1, std::fs::read_dir(&dir).unwrap().count());
+        assert!(write_textfile(dir.join("metrics.txt").to_str().unwrap(), "").is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn samples_are_timestamped_with_the_time_of_the_scan() {
+        let metrics = r#"# HELP boavizta_number_of_resources_total Number of resources detected during the inventory.
+# TYPE boavizta_number_of_resources_total gauge
+boavizta_number_of_resources_total{awsregion="eu-west-3",country="FRA"} 5
+boavizta_resource_gwp_distribution_kgco2eq_bucket{le="0.002"} 1 # {resource_id="b-1"} 0.0015
+# EOF
+"#;
+        let timestamp = chrono::DateTime::parse_from_rfc3339("2023-11-14T22:13:20.250Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        assert_eq!(
+            r#"# HELP boavizta_number_of_resources_total Number of resources detected during the inventory.
+# TYPE boavizta_number_of_resources_total gauge
+boavizta_number_of_resources_total{awsregion="eu-west-3",country="FRA"} 5 1700000000.250
+boavizta_resource_gwp_distribution_kgco2eq_bucket{le="0.002"} 1 1700000000.250 # {resource_id="b-1"} 0.0015
+# EOF
+"#,
+            with_timestamp(metrics, timestamp)
+        );
+    }
+
+    #[test]
+    fn tags_of_the_allow_list_are_mapped_to_labels() {
+        let config: MetricsConfig =
+            toml::from_str(r#"tag_labels = { Team = "team", "cost-center" = "1cost-center" }"#)
+                .unwrap();
+        let tag_labels = config.tag_labels().unwrap();
+        assert_eq!(
+            vec![
+                TagLabel {
+                    tag_key: "Team".to_string(),
+                    label: "team".to_string()
+                },
+                TagLabel {
+                    tag_key: "cost-center".to_string(),
+                    label: "_1cost_center".to_string()
+                },
+            ],
+            tag_labels
+        );
+        for invalid in [
+            r#"tag_labels = { Name = "resource_id" }"#,
+            r#"tag_labels = { Team = "team", team = "team" }"#,
+            r#"tag_labels = { Team = "__team" }"#,
+            r#"tag_labels = { Team = "" }"#,
+        ] {
+            let config: MetricsConfig = toml::from_str(invalid).unwrap();
+            assert!(config.tag_labels().is_err(), "{}", invalid);
+        }
+
+        let bucket = CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: "bucket-1".to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details: ResourceDetails::ObjectStorage,
+                tags: vec![
+                    CloudResourceTag {
+                        key: "Team".to_string(),
+                        value: Some("web".to_string()),
+                    },
+                    CloudResourceTag {
+                        key: "Env".to_string(),
+                        value: Some("prod".to_string()),
+                    },
+                ],
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues::default()),
+            impacts_duration_hours: 1.0,
+            cost: None,
+        };
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![bucket],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let options = MetricOptions {
+            resource_impacts: true,
+            tag_labels,
+            ..Default::default()
+        };
+        let metrics =
+            get_all_metrics_with_labels(&summary, &estimated_inventory, &[], &options).unwrap();
+        assert!(metrics.contains(r#"boavizta_resource_gwp_use_kgco2eq{awsregion="eu-west-3",country="FRA",resource_type="ObjectStorage",resource_id="bucket-1",resource_tags="Team:web;Env:prod;",resource_state="Unknown",team="web",_1cost_center=""} 0.0"#), "{}", metrics);
+        assert!(metrics.contains(r#"boavizta_resource_impact_gwp_kgco2eq{awsregion="eu-west-3",resource_id="bucket-1",resource_kind="ObjectStorage",instance_type="",phase="Use",team="web",_1cost_center=""} 0.0"#), "{}", metrics);
+        assert!(!metrics.contains("env="));
+    }
+
+    #[test]
+    fn metrics_are_named_with_the_namespace_and_get_the_constant_labels() {
+        let config: MetricsConfig = toml::from_str(
+            r#"namespace = "greenops"
+constant_labels = { env = "prod", org = "acme" }"#,
+        )
+        .unwrap();
+        let options = config.options().unwrap();
+        assert_eq!(Some("greenops".to_string()), options.namespace);
+        for invalid in [
+            r#"namespace = "green-ops""#,
+            r#"namespace = "1greenops""#,
+            r#"constant_labels = { awsregion = "eu-west-3" }"#,
+            r#"constant_labels = { tenant = "a" }"#,
+            r#"constant_labels = { "cost-center" = "a" }"#,
+            r#"constant_labels = { team = "a" }
+tag_labels = { Team = "team" }"#,
+        ] {
+            let config: MetricsConfig = toml::from_str(invalid).unwrap();
+            assert!(config.options().is_err(), "{}", invalid);
+        }
+
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: Vec::new(),
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let tenant = [("tenant".to_string(), "team-a".to_string())];
+        let metrics =
+            get_all_metrics_with_labels(&summary, &estimated_inventory, &tenant, &options).unwrap();
+        assert!(metrics.contains(r#"greenops_number_of_resources_total{env="prod",org="acme",tenant="team-a",awsregion="eu-west-3",country="FRA"} 0"#), "{}", metrics);
+        assert!(
+            metrics.contains("# HELP greenops_gwp_use_kgco2eq "),
+            "{}",
+            metrics
+        );
+        assert!(!metrics.contains("boavizta_"));
+    }
     #[tokio::test]
     async fn test_get_all_metrics_for_instance() {
         let tag1 = CloudResourceTag {
@@ -437,6 +2083,7 @@ boavizta_gwp_use_kgco2eq{awsregion="eu-west-1",country="IRL"} 0.6
 
         let cloud_resource: CloudResource = CloudResource {
             provider: CloudProvider::AWS,
+            account_id: None,
             id: "inst-1".to_string(),
             location: UsageLocation::try_from("eu-west-3").unwrap(),
             resource_details: ResourceDetails::Instance {
@@ -448,6 +2095,7 @@ boavizta_gwp_use_kgco2eq{awsregion="eu-west-1",country="IRL"} 0.6
                 }),
             },
             tags: vec![tag1, tag2],
+            relationships: Vec::new(),
         };
 
         let cloud_resource_with_impacts = CloudResourceWithImpacts {
@@ -462,11 +2110,13 @@ boavizta_gwp_use_kgco2eq{awsregion="eu-west-1",country="IRL"} 0.6
                 raw_data: None,
             }),
             impacts_duration_hours: 1.0,
+            cost: None,
         };
 
         let estimated_inventory: EstimatedInventory = EstimatedInventory {
             impacting_resources: vec![cloud_resource_with_impacts],
             execution_statistics: None,
+            errors: Vec::new(),
         };
 
         let summary = ImpactsSummary::new(
@@ -475,7 +2125,7 @@ boavizta_gwp_use_kgco2eq{awsregion="eu-west-1",country="IRL"} 0.6
             &estimated_inventory,
             1.0,
         );
-        let metrics = get_all_metrics(&summary, estimated_inventory).unwrap();
+        let metrics = get_all_metrics(&summary, &estimated_inventory).unwrap();
 
         println!("{}", metrics);
 
@@ -506,6 +2156,12 @@ boavizta_gwp_manufacture_kgco2eq{awsregion="eu-west-3",country="FRA"} 0.5
 # HELP boavizta_gwp_use_kgco2eq Global Warming Potential of use.
 # TYPE boavizta_gwp_use_kgco2eq gauge
 boavizta_gwp_use_kgco2eq{awsregion="eu-west-3",country="FRA"} 0.6
+# HELP boavizta_kind_number_of_resources_assessed Number of resources of the kind that were considered in the estimation of impacts.
+# TYPE boavizta_kind_number_of_resources_assessed gauge
+boavizta_kind_number_of_resources_assessed{awsregion="eu-west-3",country="FRA",resource_kind="Instance",instance_family="m6g"} 1
+# HELP boavizta_kind_number_of_resources_not_assessed Number of resources of the kind whose impacts could not be estimated.
+# TYPE boavizta_kind_number_of_resources_not_assessed gauge
+boavizta_kind_number_of_resources_not_assessed{awsregion="eu-west-3",country="FRA",resource_kind="Instance",instance_family="m6g"} 0
 # HELP boavizta_resource_duration_of_use_hours Use duration considered to estimate impacts.
 # TYPE boavizta_resource_duration_of_use_hours gauge
 boavizta_resource_duration_of_use_hours{awsregion="eu-west-3",country="FRA",resource_type="Instance",resource_id="inst-1",resource_tags="tag_key_1:tag_value_1;tag_key_2:tag_value_2;",resource_state="Running"} 1.0
@@ -551,6 +2207,7 @@ boavizta_resource_cpu_load{awsregion="eu-west-3",country="FRA",resource_type="In
 
         let cloud_resource: CloudResource = CloudResource {
             provider: CloudProvider::AWS,
+            account_id: None,
             id: "inst-1".to_string(),
             location: UsageLocation::try_from("eu-west-3").unwrap(),
             resource_details: ResourceDetails::BlockStorage {
@@ -562,6 +2219,7 @@ boavizta_resource_cpu_load{awsregion="eu-west-3",country="FRA",resource_type="In
                 attached_instances: None,
             },
             tags: vec![tag1, tag2],
+            relationships: Vec::new(),
         };
 
         let cloud_resource_with_impacts = CloudResourceWithImpacts {
@@ -576,11 +2234,13 @@ boavizta_resource_cpu_load{awsregion="eu-west-3",country="FRA",resource_type="In
                 raw_data: None,
             }),
             impacts_duration_hours: 1.0,
+            cost: None,
         };
 
         let estimated_inventory: EstimatedInventory = EstimatedInventory {
             impacting_resources: vec![cloud_resource_with_impacts],
             execution_statistics: None,
+            errors: Vec::new(),
         };
 
         let summary = ImpactsSummary::new(
@@ -590,7 +2250,7 @@ boavizta_resource_cpu_load{awsregion="eu-west-3",country="FRA",resource_type="In
             1.0,
         );
 
-        let metrics = get_all_metrics(&summary, estimated_inventory).unwrap();
+        let metrics = get_all_metrics(&summary, &estimated_inventory).unwrap();
 
         println!("{}", metrics);
 
@@ -621,6 +2281,12 @@ boavizta_gwp_manufacture_kgco2eq{awsregion="eu-west-3",country="FRA"} 0.5
 # HELP boavizta_gwp_use_kgco2eq Global Warming Potential of use.
 # TYPE boavizta_gwp_use_kgco2eq gauge
 boavizta_gwp_use_kgco2eq{awsregion="eu-west-3",country="FRA"} 0.6
+# HELP boavizta_kind_number_of_resources_assessed Number of resources of the kind that were considered in the estimation of impacts.
+# TYPE boavizta_kind_number_of_resources_assessed gauge
+boavizta_kind_number_of_resources_assessed{awsregion="eu-west-3",country="FRA",resource_kind="BlockStorage",instance_family=""} 1
+# HELP boavizta_kind_number_of_resources_not_assessed Number of resources of the kind whose impacts could not be estimated.
+# TYPE boavizta_kind_number_of_resources_not_assessed gauge
+boavizta_kind_number_of_resources_not_assessed{awsregion="eu-west-3",country="FRA",resource_kind="BlockStorage",instance_family=""} 0
 # HELP boavizta_resource_duration_of_use_hours Use duration considered to estimate impacts.
 # TYPE boavizta_resource_duration_of_use_hours gauge
 boavizta_resource_duration_of_use_hours{awsregion="eu-west-3",country="FRA",resource_type="BlockStorage",resource_id="inst-1",resource_tags="tag_key_1:tag_value_1;tag_key_2:tag_value_2;",resource_state="Unknown"} 1.0
@@ -1,12 +1,13 @@
 //!  Business Entities of cloud Scanner
-use rocket_okapi::okapi::schemars;
-use rocket_okapi::okapi::schemars::JsonSchema;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt;
 use std::time::Duration;
 
 use crate::impact_provider::CloudResourceWithImpacts;
+use crate::scan_errors::ScanError;
+use crate::tag_filter::TagFilter;
 use crate::usage_location::UsageLocation;
 
 ///  Statistics about program execution
@@ -31,22 +32,172 @@ pub struct Inventory {
     pub execution_statistics: Option<ExecutionStatistics>,
 }
 
+impl Inventory {
+    /// Combines the inventories of regions scanned concurrently (with the statistics of the slowest region)
+    pub fn combine(inventories: Vec<Inventory>) -> Inventory {
+        let execution_statistics =
+            slowest(inventories.iter().map(|i| i.execution_statistics.as_ref()));
+        Inventory {
+            resources: inventories.into_iter().flat_map(|i| i.resources).collect(),
+            execution_statistics,
+        }
+    }
+}
+
 /// An estimated inventory: impacting resources with their estimated impacts
 #[derive(Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct EstimatedInventory {
     pub impacting_resources: Vec<CloudResourceWithImpacts>,
     pub execution_statistics: Option<ExecutionStatistics>,
+    /// Errors the scan continued past (see [crate::scan_errors])
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<ScanError>,
+}
+
+impl EstimatedInventory {
+    /// Combines the estimated inventories of regions scanned concurrently (with the statistics of the slowest region, and the errors of all regions)
+    pub fn combine(inventories: Vec<EstimatedInventory>) -> EstimatedInventory {
+        let execution_statistics =
+            slowest(inventories.iter().map(|i| i.execution_statistics.as_ref()));
+        let mut impacting_resources = Vec::new();
+        let mut errors = Vec::new();
+        for inventory in inventories {
+            impacting_resources.extend(inventory.impacting_resources);
+            errors.extend(inventory.errors);
+        }
+        EstimatedInventory {
+            impacting_resources,
+            execution_statistics,
+            errors,
+        }
+    }
+
+    /// Prorates the impacts of resources estimated for `estimated_hours` to their own hours of use (by id), like the hours for which they were billed
+    pub fn prorate(
+        mut self,
+        usage_hours: &HashMap<String, f32>,
+        estimated_hours: f32,
+    ) -> EstimatedInventory {
+        if estimated_hours <= 0.0 {
+            return self;
+        }
+        for resource in self.impacting_resources.iter_mut() {
+            let Some(hours) = usage_hours.get(&resource.cloud_resource.id) else {
+                continue;
+            };
+            let ratio = (hours / estimated_hours) as f64;
+            if let Some(impacts) = resource.impacts_values.as_mut() {
+                impacts.adp_manufacture_kgsbeq *= ratio;
+                impacts.adp_use_kgsbeq *= ratio;
+                impacts.pe_manufacture_megajoules *= ratio;
+                impacts.pe_use_megajoules *= ratio;
+                impacts.gwp_manufacture_kgco2eq *= ratio;
+                impacts.gwp_use_kgco2eq *= ratio;
+            }
+            resource.impacts_duration_hours = *hours;
+        }
+        self
+    }
+}
+
+/// Returns the longest durations among the statistics of concurrent scans
+fn slowest<'a>(
+    statistics: impl Iterator<Item = Option<&'a ExecutionStatistics>>,
+) -> Option<ExecutionStatistics> {
+    statistics.flatten().fold(None, |slowest, stats| {
+        Some(match slowest {
+            None => stats.clone(),
+            Some(slowest) => ExecutionStatistics {
+                inventory_duration: slowest.inventory_duration.max(stats.inventory_duration),
+                impact_estimation_duration: slowest
+                    .impact_estimation_duration
+                    .max(stats.impact_estimation_duration),
+                total_duration: slowest.total_duration.max(stats.total_duration),
+            },
+        })
+    })
 }
 
 ///  A cloud resource (could be an instance, function or any other resource)
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct CloudResource {
     pub provider: CloudProvider,
+    /// Account of the resource, only set when scanned by assuming the role of an account
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_id: Option<String>,
     pub id: String,
     pub location: UsageLocation,
     pub resource_details: ResourceDetails,
     pub tags: Vec<CloudResourceTag>,
+    /// Resources this resource depends on (the instance of a volume, the auto scaling group or cluster of an instance)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub relationships: Vec<ResourceRelationship>,
+}
+
+/// A relationship of a resource to a parent resource, whose impacts include the impacts of the resource when rolled up
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum ResourceRelationship {
+    /// A volume attached to an instance
+    AttachedTo { instance_id: String },
+    /// An instance member of an auto scaling group
+    MemberOfAutoScalingGroup { name: String },
+    /// An instance member of a cluster (like the nodes of an EKS cluster)
+    MemberOfCluster { name: String },
+}
+
+impl ResourceRelationship {
+    /// Returns the kind (`Instance`, `AutoScalingGroup` or `Cluster`) and the id of the parent
+    pub fn parent(&self) -> (&'static str, &str) {
+        match self {
+            ResourceRelationship::AttachedTo { instance_id } => ("Instance", instance_id),
+            ResourceRelationship::MemberOfAutoScalingGroup { name } => ("AutoScalingGroup", name),
+            ResourceRelationship::MemberOfCluster { name } => ("Cluster", name),
+        }
+    }
+
+    /// Derives the relationships of a resource from its attachments and tags (`aws:autoscaling:groupName`, `eks:cluster-name`, `aws:eks:cluster-name` or `kubernetes.io/cluster/<name>`), the clusters first
+    pub fn derive(
+        resource_details: &ResourceDetails,
+        tags: &[CloudResourceTag],
+    ) -> Vec<ResourceRelationship> {
+        let mut relationships = Vec::new();
+        for tag in tags {
+            let value = tag.value.clone().unwrap_or_default();
+            let relationship = match tag.key.as_str() {
+                "eks:cluster-name" | "aws:eks:cluster-name" if !value.is_empty() => {
+                    ResourceRelationship::MemberOfCluster { name: value }
+                }
+                key if key.starts_with("kubernetes.io/cluster/") => {
+                    ResourceRelationship::MemberOfCluster {
+                        name: key.trim_start_matches("kubernetes.io/cluster/").to_string(),
+                    }
+                }
+                "aws:autoscaling:groupName" if !value.is_empty() => {
+                    ResourceRelationship::MemberOfAutoScalingGroup { name: value }
+                }
+                _ => continue,
+            };
+            if !relationships.contains(&relationship) {
+                relationships.push(relationship);
+            }
+        }
+        relationships.sort_by_key(|relationship| {
+            !matches!(relationship, ResourceRelationship::MemberOfCluster { .. })
+        });
+        if let ResourceDetails::BlockStorage {
+            attached_instances: Some(attached_instances),
+            ..
+        } = resource_details
+        {
+            relationships.extend(attached_instances.iter().map(|attachment| {
+                ResourceRelationship::AttachedTo {
+                    instance_id: attachment.instance_id.clone(),
+                }
+            }));
+        }
+        relationships
+    }
 }
 
 impl fmt::Display for CloudResource {
@@ -56,14 +207,22 @@ impl fmt::Display for CloudResource {
 }
 
 impl CloudResource {
+    /// Returns the relationships of the resource, or the relationships derived from its attachments and tags when it has none (like the resources of inventories written by previous versions)
+    pub fn parent_relationships(&self) -> Vec<ResourceRelationship> {
+        if self.relationships.is_empty() {
+            ResourceRelationship::derive(&self.resource_details, &self.tags)
+        } else {
+            self.relationships.clone()
+        }
+    }
+
     /// Convert tags into a format supported by prometheus metrics label (like `tag_key_1:tag_value_1;tag_key_2:tag_value_2;`)
     pub fn tags_as_metric_label_value(&self) -> String {
-        let mut res = "".to_string();
+        let mut res = String::new();
         for tag in self.tags.iter() {
-            let val = tag.value.clone().unwrap_or("".parse().unwrap());
             res.push_str(&tag.key);
             res.push(':');
-            res.push_str(&val);
+            res.push_str(tag.value.as_deref().unwrap_or_default());
             res.push(';');
         }
         res
@@ -74,8 +233,15 @@ impl CloudResource {
 pub enum CloudProvider {
     AWS,
     OVH,
+    Azure,
+    GCP,
+    /// Servers of data centers, like the devices and virtual machines of NetBox
+    OnPremises,
 }
 
+/// Type of the volumes whose type is not set, in inventories of plans, templates or files (the default type of EC2)
+pub const DEFAULT_VOLUME_TYPE: &str = "gp2";
+
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub enum ResourceDetails {
     Instance {
@@ -88,6 +254,36 @@ pub enum ResourceDetails {
         attached_instances: Option<Vec<StorageAttachment>>,
     },
     ObjectStorage,
+    /// A physical or virtual server of a data center (like the devices and virtual machines of a CMDB), estimated with its hardware
+    Server {
+        /// Model of the server (like the device type of a physical server)
+        model: String,
+        /// Hardware of the server, the hardware of the default server archetype of Boavizta API otherwise
+        hardware: Option<ServerHardware>,
+        usage: Option<InstanceUsage>,
+    },
+}
+
+impl ResourceDetails {
+    /// Returns the kind of resource (like `Instance` or `BlockStorage`)
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ResourceDetails::Instance { .. } => "Instance",
+            ResourceDetails::BlockStorage { .. } => "BlockStorage",
+            ResourceDetails::ObjectStorage => "ObjectStorage",
+            ResourceDetails::Server { .. } => "Server",
+        }
+    }
+
+    /// Returns the vendor specific type of the resource (instance type, storage type or model of server), if any
+    pub fn resource_type(&self) -> Option<String> {
+        match self {
+            ResourceDetails::Instance { instance_type, .. } => Some(instance_type.clone()),
+            ResourceDetails::BlockStorage { storage_type, .. } => Some(storage_type.clone()),
+            ResourceDetails::ObjectStorage => None,
+            ResourceDetails::Server { model, .. } => Some(model.clone()),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
@@ -110,6 +306,54 @@ pub struct StorageUsage {
     pub usage_duration_seconds: u32,
 }
 
+/// Hardware of a server, the missing parts being completed by Boavizta API
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ServerHardware {
+    /// Number of CPUs
+    pub cpu_units: Option<i32>,
+    /// Number of cores of each CPU
+    pub cpu_cores: Option<i32>,
+    /// Memory (GB)
+    pub memory_gb: Option<i32>,
+    /// Storage (GB), estimated as SSD
+    pub storage_gb: Option<i32>,
+}
+
+impl ServerHardware {
+    /// Returns the hardware of the fields `cpu_units`, `cpu_cores`, `memory_gb` and `storage_gb` of a json object (like the custom fields of a NetBox device), None if it has none of them
+    pub fn from_fields(fields: &serde_json::Value) -> Option<ServerHardware> {
+        let hardware = ServerHardware {
+            cpu_units: json_integer(&fields["cpu_units"]),
+            cpu_cores: json_integer(&fields["cpu_cores"]),
+            memory_gb: json_integer(&fields["memory_gb"]),
+            storage_gb: json_integer(&fields["storage_gb"]),
+        };
+        (hardware != ServerHardware::default()).then_some(hardware)
+    }
+
+    /// Returns the hardware completed by the parts of another hardware that it misses
+    pub fn or(self, other: ServerHardware) -> ServerHardware {
+        ServerHardware {
+            cpu_units: self.cpu_units.or(other.cpu_units),
+            cpu_cores: self.cpu_cores.or(other.cpu_cores),
+            memory_gb: self.memory_gb.or(other.memory_gb),
+            storage_gb: self.storage_gb.or(other.storage_gb),
+        }
+    }
+}
+
+/// Returns a json number, or a number serialized as a string (like decimals)
+pub(crate) fn json_number(value: &serde_json::Value) -> Option<f64> {
+    value
+        .as_f64()
+        .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok()))
+}
+
+/// Returns an integer of a json number or of a number serialized as a string, rounded up
+pub(crate) fn json_integer(value: &serde_json::Value) -> Option<i32> {
+    json_number(value).map(|number| number.ceil() as i32)
+}
+
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct StorageAttachment {
     pub instance_id: String,
@@ -146,25 +390,18 @@ impl TryFrom<String> for CloudResourceTag {
 }
 
 impl CloudResource {
-    /// Returns true it _all_ the tags passed in argument are defined and have the same values on the cloud resource
-    fn has_matching_tagmap(&self, tags: &HashMap<String, CloudResourceTag>) -> bool {
-        tags.iter().all(|(filter_key, filter_tag)| {
-            let tag_map: HashMap<String, Option<String>> = vec_to_map(self.tags.clone());
-            tag_map.get(filter_key) == Some(&filter_tag.value)
-        })
-    }
-
+    /// Returns true if the tags of the resource match _all_ the filter expressions (see [crate::tag_filter]), invalid filters are skipped
     pub fn has_matching_tags(&self, filter_tags: &[String]) -> bool {
-        let mut filter = HashMap::new();
-        filter_tags.iter().for_each(|f| {
-            let res = CloudResourceTag::try_from(f.to_owned());
-            if let Ok(crt) = res {
-                filter.insert(crt.key.clone(), crt);
-            } else {
-                error!("Skipped filter");
-            }
-        });
-        self.has_matching_tagmap(&filter)
+        filter_tags
+            .iter()
+            .filter_map(|f| match TagFilter::parse(f) {
+                Ok(filter) => Some(filter),
+                Err(e) => {
+                    error!("Skipped filter: {:#}", e);
+                    None
+                }
+            })
+            .all(|filter| filter.matches(&self.tags))
     }
 }
 
@@ -178,14 +415,16 @@ pub fn vec_to_map(tagv: Vec<CloudResourceTag>) -> HashMap<String, Option<String>
 
 #[cfg(test)]
 mod tests {
-    use crate::model::{CloudProvider, CloudResource, CloudResourceTag, ResourceDetails};
+    use crate::model::{
+        CloudProvider, CloudResource, CloudResourceTag, ResourceDetails, ResourceRelationship,
+    };
     use crate::usage_location::UsageLocation;
-    use std::collections::HashMap;
 
     #[test]
     pub fn a_cloud_resource_can_be_displayed() {
         let instance1: CloudResource = CloudResource {
             provider: CloudProvider::AWS,
+            account_id: None,
             id: "inst-1".to_string(),
             location: UsageLocation::try_from("eu-west-1").unwrap(),
             resource_details: ResourceDetails::Instance {
@@ -193,9 +432,10 @@ mod tests {
                 usage: None,
             },
             tags: Vec::new(),
+            relationships: Vec::new(),
         };
 
-        assert_eq!("CloudResource { provider: AWS, id: \"inst-1\", location: UsageLocation { aws_region: \"eu-west-1\", iso_country_code: \"IRL\" }, resource_details: Instance { instance_type: \"t2.fictive\", usage: None }, tags: [] }", format!("{:?}", instance1));
+        assert_eq!("CloudResource { provider: AWS, account_id: None, id: \"inst-1\", location: UsageLocation { aws_region: \"eu-west-1\", iso_country_code: \"IRL\" }, resource_details: Instance { instance_type: \"t2.fictive\", usage: None }, tags: [], relationships: [] }", format!("{:?}", instance1));
     }
 
     #[test]
@@ -219,24 +459,47 @@ mod tests {
     }
 
     #[test]
-    pub fn match_tags() {
-        let mut filtertags = HashMap::new();
-        filtertags.insert(
-            "Name".to_string(),
-            CloudResourceTag {
-                key: "Name".to_string(),
-                value: Some("App1".to_string()),
-            },
+    pub fn relationships_are_derived_from_tags() {
+        let tag = |key: &str, value: &str| CloudResourceTag {
+            key: key.to_string(),
+            value: Some(value.to_string()),
+        };
+        let details = ResourceDetails::Instance {
+            instance_type: "t2.fictive".to_string(),
+            usage: None,
+        };
+        let relationships = ResourceRelationship::derive(
+            &details,
+            &[
+                tag("aws:autoscaling:groupName", "workers"),
+                tag("kubernetes.io/cluster/prod", "owned"),
+                tag("eks:cluster-name", "prod"),
+            ],
+        );
+        assert_eq!(
+            vec![
+                ResourceRelationship::MemberOfCluster {
+                    name: "prod".to_string()
+                },
+                ResourceRelationship::MemberOfAutoScalingGroup {
+                    name: "workers".to_string()
+                },
+            ],
+            relationships
         );
+        assert!(ResourceRelationship::derive(&details, &[tag("team", "web")]).is_empty());
+    }
 
-        let mut instance1tags: Vec<CloudResourceTag> = Vec::new();
-        instance1tags.push(CloudResourceTag {
+    #[test]
+    pub fn match_tags() {
+        let instance1tags: Vec<CloudResourceTag> = vec![CloudResourceTag {
             key: "Name".to_string(),
             value: Some("App1".to_string()),
-        });
+        }];
 
         let instance1: CloudResource = CloudResource {
             provider: CloudProvider::AWS,
+            account_id: None,
             id: "inst-1".to_string(),
             location: UsageLocation::try_from("eu-west-1").unwrap(),
             resource_details: ResourceDetails::Instance {
@@ -244,83 +507,46 @@ mod tests {
                 usage: None,
             },
             tags: instance1tags,
+            relationships: Vec::new(),
         };
+        let filter =
+            |filters: &[&str]| -> Vec<String> { filters.iter().map(|f| f.to_string()).collect() };
 
-        assert_eq!(
-            true,
-            instance1.has_matching_tagmap(&filtertags),
+        assert!(
+            instance1.has_matching_tags(&filter(&["Name=App1"])),
             "Tags should match"
         );
 
-        let mut other_name_tag = filtertags.clone();
         // Changing the content of Name tag
-        other_name_tag.insert(
-            "Name".to_string(),
-            CloudResourceTag {
-                key: "Name".to_string(),
-                value: Some("OtherApp".to_string()),
-            },
-        );
-        assert_eq!(
-            false,
-            instance1.has_matching_tagmap(&other_name_tag),
+        assert!(
+            !instance1.has_matching_tags(&filter(&["Name=OtherApp"])),
             "Tags should not match"
         );
 
-        let mut more_tags = filtertags.clone();
         // Adding an extra tag that is not on the instance
-        more_tags.insert(
-            "Env".to_string(),
-            CloudResourceTag {
-                key: "Env".to_string(),
-                value: Some("PROD".to_string()),
-            },
-        );
-        assert_eq!(
-            false,
-            instance1.has_matching_tagmap(&more_tags),
+        assert!(
+            !instance1.has_matching_tags(&filter(&["Name=App1", "Env=PROD"])),
             "Tags should not match"
         );
 
-        let mut tag_without_val = filtertags.clone();
-        // Adding an extra tag that is not on the instance
-        tag_without_val.insert(
-            "Name".to_string(),
-            CloudResourceTag {
-                key: "Name".to_string(),
-                value: None,
-            },
-        );
-        assert_eq!(
-            false,
-            instance1.has_matching_tagmap(&tag_without_val),
+        assert!(
+            !instance1.has_matching_tags(&filter(&["Name"])),
             "Tag without a value should not match"
         );
 
         // Trying an empty filter
-        let empty_filter = HashMap::new();
-        assert_eq!(
-            true,
-            instance1.has_matching_tagmap(&empty_filter),
-            "Tags should match"
-        );
+        assert!(instance1.has_matching_tags(&[]), "Tags should match");
 
         // When the name of tag used to filter is an empty string....
-        let mut empty_tag_name_in_filter = HashMap::new();
-
-        let empty_key: String = "".to_string();
-        empty_tag_name_in_filter.insert(
-            empty_key.clone(),
-            CloudResourceTag {
-                key: empty_key,
-                value: Some("whatever".to_string()),
-            },
-        );
-        assert_eq!(
-            true,
-            instance1.has_matching_tagmap(&empty_filter),
+        assert!(
+            instance1.has_matching_tags(&filter(&["=whatever"])),
             "Tags should match (i.e. we should ignore this invalid filter"
         );
+
+        assert!(
+            instance1.has_matching_tags(&filter(&["Name~App* AND NOT Env=PROD"])),
+            "Expressions should match"
+        );
     }
     #[test]
     pub fn format_tags_as_metric_label() {
@@ -335,6 +561,7 @@ mod tests {
 
         let cr = CloudResource {
             provider: CloudProvider::AWS,
+            account_id: None,
             id: "123".to_string(),
             location: UsageLocation {
                 aws_region: "eu-west-3".to_string(),
@@ -342,6 +569,7 @@ mod tests {
             },
             resource_details: ResourceDetails::ObjectStorage,
             tags: vec![tag1, tag2],
+            relationships: Vec::new(),
         };
 
         let tag_label_value = cr.tags_as_metric_label_value();
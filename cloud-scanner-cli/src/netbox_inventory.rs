@@ -0,0 +1,377 @@
+//! Inventories of NetBox, so that the servers of data centers tracked in a CMDB are estimated with the same pipeline and appear in the same reports as cloud resources.
+//!
+//! Devices (`/api/dcim/devices/`) and virtual machines (`/api/virtualization/virtual-machines/`) are listed with the REST API of NetBox and inventoried as servers ([ResourceDetails::Server]):
+//! - devices with the model of their device type and the hardware of their custom fields `cpu_units`, `cpu_cores`, `memory_gb` and `storage_gb` (Boavizta API completing the missing fields with its default server)
+//! - virtual machines with their `vcpus`, `memory` and `disk` (MB, like NetBox 4.1 and later)
+//!
+//! Resources are identified like `device-42` or `vm-7` (their NetBox ids), their name is their `Name` tag and their account is their tenant. Their location is their site (its slug being their region), in the country of the `country` custom field of the site (ISO 3166 code, like FRA or FR), or the default country otherwise. Virtual machines are members of their cluster (see [ResourceRelationship::MemberOfCluster]). CPU loads are not part of NetBox: servers are estimated with the default load of Boavizta API.
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::aws_cloud_provider::ResourceSelection;
+use crate::model::{
+    json_integer, CloudProvider, CloudResource, CloudResourceTag, Inventory, ResourceDetails,
+    ResourceRelationship, ServerHardware,
+};
+use crate::tag_filter::TagFilter;
+use crate::tag_normalization;
+use crate::usage_location::UsageLocation;
+
+/// The objects of NetBox that can be inventoried
+pub const NETBOX_OBJECTS: [&str; 2] = ["devices", "virtual-machines"];
+
+/// Number of objects of each page of the REST API (the default maximum of NetBox)
+const PAGE_SIZE: usize = 1000;
+
+/// Region of the resources without site
+const UNKNOWN_SITE: &str = "unknown";
+
+/// Model of the virtual machines
+const VIRTUAL_MACHINE_MODEL: &str = "virtual-machine";
+
+/// Returns the state of an instance (see [crate::aws_cloud_provider::INSTANCE_STATES]) of the status of a device or virtual machine (like `offline`)
+fn instance_state(status: &str) -> &'static str {
+    match status {
+        "planned" | "staged" => "pending",
+        "active" => "running",
+        "decommissioning" => "stopping",
+        "offline" | "failed" | "inventory" => "stopped",
+        _ => "",
+    }
+}
+
+/// Returns all the objects of an endpoint of the REST API (like `/api/dcim/devices/`), following its pages
+async fn get_all(
+    client: &reqwest::Client,
+    url: &str,
+    path: &str,
+    token: &str,
+) -> Result<Vec<Value>> {
+    let mut objects = Vec::new();
+    let mut next = Some(format!(
+        "{}{}?limit={}",
+        url.trim_end_matches('/'),
+        path,
+        PAGE_SIZE
+    ));
+    while let Some(page_url) = next {
+        let page: Value = client
+            .get(&page_url)
+            .header("Authorization", format!("Token {}", token))
+            .header("Accept", "application/json")
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Cannot query NetBox ({})", page_url))?
+            .json()
+            .await
+            .with_context(|| format!("Unexpected response of NetBox ({})", page_url))?;
+        objects.extend(page["results"].as_array().cloned().unwrap_or_default());
+        next = page["next"].as_str().map(str::to_string);
+    }
+    Ok(objects)
+}
+
+/// Lists the objects of NetBox (see [NETBOX_OBJECTS]) and returns their inventory, see [parse_netbox_objects]
+pub async fn read_netbox_inventory(
+    url: &str,
+    token: &str,
+    objects: &[String],
+    default_country: Option<&str>,
+    tags: &[String],
+    selection: &ResourceSelection,
+) -> Result<Inventory> {
+    let client = reqwest::Client::new();
+    let sites = get_all(&client, url, "/api/dcim/sites/", token).await?;
+    let selects = |object: &str| objects.is_empty() || objects.iter().any(|o| o == object);
+    let devices = match selects("devices") {
+        true => get_all(&client, url, "/api/dcim/devices/", token).await?,
+        false => Vec::new(),
+    };
+    let virtual_machines = match selects("virtual-machines") {
+        true => get_all(&client, url, "/api/virtualization/virtual-machines/", token).await?,
+        false => Vec::new(),
+    };
+    parse_netbox_objects(
+        &sites,
+        &devices,
+        &virtual_machines,
+        default_country,
+        tags,
+        selection,
+    )
+}
+
+/// Returns the location of the sites by id (unknown when neither the site nor the default have a country)
+fn site_locations(
+    sites: &[Value],
+    default_country: Option<&str>,
+) -> Result<HashMap<i64, Option<UsageLocation>>> {
+    let mut locations = HashMap::new();
+    for site in sites {
+        let Some(id) = site["id"].as_i64() else {
+            continue;
+        };
+        let slug = site["slug"].as_str().unwrap_or(UNKNOWN_SITE);
+        let country_code = match site["custom_fields"]["country"].as_str() {
+            Some(code) if !code.is_empty() => Some(code),
+            _ => default_country,
+        };
+        let location = country_code
+            .map(|code| UsageLocation::of_country(slug, code))
+            .transpose()
+            .with_context(|| format!("Invalid country of site {}", slug))?;
+        locations.insert(id, location);
+    }
+    Ok(locations)
+}
+
+/// Returns the slug of a nested object (like the role of a device), or its name
+fn slug(object: &Value) -> Option<&str> {
+    object["slug"].as_str().or_else(|| object["name"].as_str())
+}
+
+/// Returns the hardware of a virtual machine (a CPU of its vCPUs, and its memory and disk in GB)
+fn virtual_machine_hardware(virtual_machine: &Value) -> ServerHardware {
+    let gb = |mb: i32| (mb + 1023) / 1024;
+    let cpu_cores = json_integer(&virtual_machine["vcpus"]);
+    ServerHardware {
+        cpu_units: cpu_cores.map(|_| 1),
+        cpu_cores,
+        memory_gb: json_integer(&virtual_machine["memory"]).map(gb),
+        storage_gb: json_integer(&virtual_machine["disk"]).map(gb),
+    }
+}
+
+/// Returns the tags of a device or virtual machine: its name (`Name`), its NetBox tags (without values) and its role, platform, site and cluster (`netbox:role`, `netbox:platform`, `netbox:site` and `netbox:cluster`)
+fn object_tags(object: &Value) -> Vec<CloudResourceTag> {
+    let mut tags = Vec::new();
+    if let Some(name) = object["name"].as_str() {
+        tags.push(CloudResourceTag {
+            key: "Name".to_string(),
+            value: Some(name.to_string()),
+        });
+    }
+    for tag in object["tags"].as_array().into_iter().flatten() {
+        if let Some(key) = slug(tag) {
+            tags.push(CloudResourceTag {
+                key: key.to_string(),
+                value: None,
+            });
+        }
+    }
+    // Devices have a device_role before NetBox 3.6
+    let role = match object["role"].is_object() {
+        true => &object["role"],
+        false => &object["device_role"],
+    };
+    for (key, nested) in [
+        ("netbox:role", role),
+        ("netbox:platform", &object["platform"]),
+        ("netbox:site", &object["site"]),
+        ("netbox:cluster", &object["cluster"]),
+    ] {
+        if let Some(value) = slug(nested) {
+            tags.push(CloudResourceTag {
+                key: key.to_string(),
+                value: Some(value.to_string()),
+            });
+        }
+    }
+    tags
+}
+
+/// Parses the devices and virtual machines (unless instances are skipped by the selection, and in the selected states) listed by NetBox, located by their site (see [site_locations]), as on-premises servers
+pub fn parse_netbox_objects(
+    sites: &[Value],
+    devices: &[Value],
+    virtual_machines: &[Value],
+    default_country: Option<&str>,
+    tags: &[String],
+    selection: &ResourceSelection,
+) -> Result<Inventory> {
+    let mut resources = Vec::new();
+    if selection.skip_instances {
+        return Ok(Inventory {
+            resources,
+            execution_statistics: None,
+        });
+    }
+    let filter = TagFilter::parse_all(tags)?;
+    let locations = site_locations(sites, default_country)?;
+    let default_location = default_country
+        .map(|code| UsageLocation::of_country(UNKNOWN_SITE, code))
+        .transpose()?;
+    let objects = devices
+        .iter()
+        .map(|device| ("device", device))
+        .chain(virtual_machines.iter().map(|vm| ("vm", vm)));
+    for (prefix, object) in objects {
+        let Some(netbox_id) = object["id"].as_i64() else {
+            continue;
+        };
+        let id = format!("{}-{}", prefix, netbox_id);
+        let status = object["status"]["value"].as_str().unwrap_or_default();
+        if !selection.instance_states.is_empty()
+            && !selection
+                .instance_states
+                .iter()
+                .any(|s| s == instance_state(status))
+        {
+            debug!("Filtered {} (in status {})", id, status);
+            continue;
+        }
+        let resource_details = match prefix {
+            "device" => ResourceDetails::Server {
+                model: object["device_type"]["model"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                hardware: ServerHardware::from_fields(&object["custom_fields"]),
+                usage: None,
+            },
+            _ => ResourceDetails::Server {
+                model: VIRTUAL_MACHINE_MODEL.to_string(),
+                hardware: Some(virtual_machine_hardware(object)),
+                usage: None,
+            },
+        };
+        let location = match object["site"]["id"].as_i64() {
+            Some(site) => locations.get(&site).cloned().flatten(),
+            None => default_location.clone(),
+        };
+        let location = location.with_context(|| {
+            format!(
+                "Unknown country of {} (set the country custom field of its site, or a default country)",
+                id
+            )
+        })?;
+        let relationships = object["cluster"]["name"]
+            .as_str()
+            .map(|cluster| ResourceRelationship::MemberOfCluster {
+                name: cluster.to_string(),
+            })
+            .into_iter()
+            .collect();
+        let resource = CloudResource {
+            provider: CloudProvider::OnPremises,
+            account_id: slug(&object["tenant"]).map(str::to_string),
+            id,
+            location,
+            resource_details,
+            tags: tag_normalization::normalize(object_tags(object)),
+            relationships,
+        };
+        if filter.matches(&resource.tags) {
+            resources.push(resource);
+        } else {
+            debug!("Filtered {} (tags do not match)", resource.id);
+        }
+    }
+    Ok(Inventory {
+        resources,
+        execution_statistics: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn devices_and_virtual_machines_are_inventoried() {
+        let sites: Vec<Value> = serde_json::from_str(
+            r#"[{"id": 1, "slug": "paris-1", "custom_fields": {"country": "FR"}},
+                {"id": 2, "slug": "lab", "custom_fields": {}}]"#,
+        )
+        .unwrap();
+        let devices: Vec<Value> = serde_json::from_str(
+            r#"[{"id": 42, "name": "db-1", "status": {"value": "active"}, "site": {"id": 1, "slug": "paris-1"},
+                 "device_type": {"model": "PowerEdge R640"}, "role": {"slug": "database"}, "tenant": {"slug": "billing"},
+                 "tags": [{"name": "Production", "slug": "production"}],
+                 "custom_fields": {"cpu_units": 2, "cpu_cores": 16, "memory_gb": 256}},
+                {"id": 43, "name": "spare-1", "status": {"value": "inventory"}, "site": {"id": 2, "slug": "lab"},
+                 "device_type": {"model": "PowerEdge R640"}, "custom_fields": {}}]"#,
+        )
+        .unwrap();
+        let virtual_machines: Vec<Value> = serde_json::from_str(
+            r#"[{"id": 7, "name": "web-1", "status": {"value": "active"}, "site": null, "cluster": {"name": "vmware-1"},
+                 "vcpus": "4.00", "memory": 16384, "disk": 102400, "tags": []}]"#,
+        )
+        .unwrap();
+
+        let inventory = parse_netbox_objects(
+            &sites,
+            &devices,
+            &virtual_machines,
+            Some("DEU"),
+            &[],
+            &ResourceSelection::default(),
+        )
+        .unwrap();
+        let ids: Vec<&str> = inventory.resources.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(vec!["device-42", "device-43", "vm-7"], ids);
+
+        let db = &inventory.resources[0];
+        assert_eq!(Some("billing".to_string()), db.account_id);
+        assert_eq!("paris-1", db.location.aws_region);
+        assert_eq!("FRA", db.location.iso_country_code);
+        assert_eq!(
+            Some("PowerEdge R640".to_string()),
+            db.resource_details.resource_type()
+        );
+        assert!(db.tags.contains(&CloudResourceTag {
+            key: "netbox:role".to_string(),
+            value: Some("database".to_string())
+        }));
+        let ResourceDetails::Server { hardware, .. } = &inventory.resources[1].resource_details
+        else {
+            panic!("Expected a server");
+        };
+        assert_eq!(&None, hardware);
+        assert_eq!("DEU", inventory.resources[1].location.iso_country_code);
+
+        let web = &inventory.resources[2];
+        let ResourceDetails::Server { hardware, .. } = &web.resource_details else {
+            panic!("Expected a server");
+        };
+        assert_eq!(
+            &Some(ServerHardware {
+                cpu_units: Some(1),
+                cpu_cores: Some(4),
+                memory_gb: Some(16),
+                storage_gb: Some(100),
+            }),
+            hardware
+        );
+        assert_eq!(
+            vec![ResourceRelationship::MemberOfCluster {
+                name: "vmware-1".to_string()
+            }],
+            web.relationships
+        );
+
+        let (running, _) = ResourceSelection::of_kinds(&[], &["running".to_string()]);
+        let inventory = parse_netbox_objects(
+            &sites,
+            &devices,
+            &[],
+            None,
+            &["production".to_string()],
+            &running,
+        )
+        .unwrap();
+        assert_eq!(1, inventory.resources.len());
+        // Without a default country, the resources of sites without country cannot be located
+        assert!(parse_netbox_objects(
+            &sites,
+            &devices,
+            &[],
+            None,
+            &[],
+            &ResourceSelection::default()
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! A module to post the summary of a scan to Slack or Microsoft Teams incoming webhooks, with alerts when impacts exceed thresholds.
+//!
+//! The message is rendered with a [Tera](https://keats.github.io/tera/docs/) template receiving the same variables as [template_exporter](crate::template_exporter), plus `alerts` (the list of thresholds breached, as text).
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt;
+
+use crate::impact_provider::ImpactsSummary;
+use crate::model::EstimatedInventory;
+use crate::template_exporter;
+
+/// Message used when no template is configured
+pub const DEFAULT_TEMPLATE: &str = "Cloud impacts - {{ aws_region }} ({{ summary.duration_of_use_hours }} hours of use)
+{{ summary.number_of_resources_assessed }} resources assessed ({{ summary.number_of_resources_not_assessed }} not assessed)
+GWP: {{ summary.gwp_manufacture_kgco2eq + summary.gwp_use_kgco2eq | round(precision=3) }} kgCO2eq (manufacture {{ summary.gwp_manufacture_kgco2eq | round(precision=3) }}, use {{ summary.gwp_use_kgco2eq | round(precision=3) }})
+{% for alert in alerts %}
+:warning: {{ alert }}{% endfor %}";
+
+/// The service receiving the notifications
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookKind {
+    Slack,
+    Teams,
+}
+
+/// An incoming webhook
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    pub kind: WebhookKind,
+    pub url: String,
+}
+
+/// Impacts (manufacture and use, over the duration of the scan) above which an alert is added to the notification
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Thresholds {
+    pub max_gwp_kgco2eq: Option<f64>,
+    pub max_pe_megajoules: Option<f64>,
+    pub max_adp_kgsbeq: Option<f64>,
+}
+
+/// Settings of notifications
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NotificationConfig {
+    pub webhooks: Vec<Webhook>,
+    #[serde(default)]
+    pub thresholds: Thresholds,
+    /// Tera template of the message (defaults to [DEFAULT_TEMPLATE])
+    pub template: Option<String>,
+    /// Notifies only when a threshold is breached
+    #[serde(default)]
+    pub only_on_breach: bool,
+}
+
+/// A threshold exceeded by the impacts of a scan
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct ThresholdBreach {
+    pub impact: String,
+    pub unit: String,
+    pub value: f64,
+    pub threshold: f64,
+}
+
+impl fmt::Display for ThresholdBreach {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of {:.3} {} exceeds the threshold of {} {}",
+            self.impact, self.value, self.unit, self.threshold, self.unit
+        )
+    }
+}
+
+/// Returns the thresholds exceeded by the impacts of the summary
+pub fn check_thresholds(thresholds: &Thresholds, summary: &ImpactsSummary) -> Vec<ThresholdBreach> {
+    let impacts = [
+        (
+            "GWP",
+            "kgCO2eq",
+            thresholds.max_gwp_kgco2eq,
+            summary.gwp_manufacture_kgco2eq + summary.gwp_use_kgco2eq,
+        ),
+        (
+            "Primary energy",
+            "MJ",
+            thresholds.max_pe_megajoules,
+            summary.pe_manufacture_megajoules + summary.pe_use_megajoules,
+        ),
+        (
+            "ADP",
+            "kgSbeq",
+            thresholds.max_adp_kgsbeq,
+            summary.adp_manufacture_kgsbeq + summary.adp_use_kgsbeq,
+        ),
+    ];
+    impacts
+        .into_iter()
+        .filter_map(|(impact, unit, threshold, value)| {
+            let threshold = threshold?;
+            (value > threshold).then(|| ThresholdBreach {
+                impact: impact.to_string(),
+                unit: unit.to_string(),
+                value,
+                threshold,
+            })
+        })
+        .collect()
+}
+
+/// Renders the message of the notification
+pub fn render_message(
+    config: &NotificationConfig,
+    timestamp: DateTime<Utc>,
+    summary: &ImpactsSummary,
+    estimated_inventory: &EstimatedInventory,
+    breaches: &[ThresholdBreach],
+) -> Result<String> {
+    let mut context = template_exporter::scan_context(timestamp, summary, estimated_inventory);
+    let alerts: Vec<String> = breaches.iter().map(|b| b.to_string()).collect();
+    context.insert("alerts", &alerts);
+    let template = config.template.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+    template_exporter::render_context(template, &context)
+        .context("Cannot render notification template")
+}
+
+/// Returns the json payload expected by the webhook
+pub fn webhook_payload(kind: WebhookKind, message: &str, alert: bool) -> serde_json::Value {
+    match kind {
+        WebhookKind::Slack => json!({ "text": message }),
+        // Teams connectors display the text as Markdown, where single line breaks are ignored
+        WebhookKind::Teams => json!({
+            "@type": "MessageCard",
+            "@context": "https://schema.org/extensions",
+            "summary": message.lines().next().unwrap_or_default(),
+            "themeColor": if alert { "D70000" } else { "4E9A06" },
+            "text": message.replace('\n', "\n\n"),
+        }),
+    }
+}
+
+/// Posts the summary of a scan to the webhooks of the configuration, returns the thresholds breached.
+///
+/// Nothing is posted when `only_on_breach` is set and no threshold is breached.
+pub async fn notify(
+    config: &NotificationConfig,
+    summary: &ImpactsSummary,
+    estimated_inventory: &EstimatedInventory,
+) -> Result<Vec<ThresholdBreach>> {
+    let breaches = check_thresholds(&config.thresholds, summary);
+    if config.only_on_breach && breaches.is_empty() {
+        info!("No threshold breached, skipping notifications");
+        return Ok(breaches);
+    }
+    let message = render_message(config, Utc::now(), summary, estimated_inventory, &breaches)?;
+    let client = reqwest::Client::new();
+    for webhook in config.webhooks.iter() {
+        let payload = webhook_payload(webhook.kind, &message, !breaches.is_empty());
+        client
+            .post(&webhook.url)
+            .json(&payload)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Cannot post notification to {:?} webhook", webhook.kind))?;
+        info!("Notification posted to {:?} webhook", webhook.kind);
+    }
+    Ok(breaches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn summary() -> ImpactsSummary {
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let mut summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            24.0,
+        );
+        summary.gwp_manufacture_kgco2eq = 1.5;
+        summary.gwp_use_kgco2eq = 0.25;
+        summary.pe_use_megajoules = 10.0;
+        summary
+    }
+
+    #[test]
+    fn detect_threshold_breaches() {
+        let thresholds = Thresholds {
+            max_gwp_kgco2eq: Some(1.0),
+            max_pe_megajoules: Some(20.0),
+            max_adp_kgsbeq: None,
+        };
+        let breaches = check_thresholds(&thresholds, &summary());
+        assert_eq!(1, breaches.len());
+        assert_eq!(
+            "GWP of 1.750 kgCO2eq exceeds the threshold of 1 kgCO2eq",
+            breaches[0].to_string()
+        );
+        assert!(check_thresholds(&Thresholds::default(), &summary()).is_empty());
+    }
+
+    #[test]
+    fn render_default_message_with_alerts() {
+        let config = NotificationConfig {
+            webhooks: vec![],
+            thresholds: Thresholds {
+                max_gwp_kgco2eq: Some(1.0),
+                ..Default::default()
+            },
+            template: None,
+            only_on_breach: false,
+        };
+        let summary = summary();
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let breaches = check_thresholds(&config.thresholds, &summary);
+        let timestamp = Utc.with_ymd_and_hms(2024, 4, 12, 10, 15, 0).unwrap();
+        let message = render_message(
+            &config,
+            timestamp,
+            &summary,
+            &estimated_inventory,
+            &breaches,
+        )
+        .unwrap();
+        assert!(message.starts_with("Cloud impacts - eu-west-3 (24 hours of use)\n"));
+        assert!(message.contains("GWP: 1.75 kgCO2eq (manufacture 1.5, use 0.25)"));
+        assert!(
+            message.ends_with(":warning: GWP of 1.750 kgCO2eq exceeds the threshold of 1 kgCO2eq")
+        );
+    }
+
+    #[test]
+    fn payload_depends_on_webhook_kind() {
+        assert_eq!(
+            json!({"text": "a\nb"}),
+            webhook_payload(WebhookKind::Slack, "a\nb", false)
+        );
+        let teams = webhook_payload(WebhookKind::Teams, "a\nb", true);
+        assert_eq!("a", teams["summary"]);
+        assert_eq!("D70000", teams["themeColor"]);
+        assert_eq!("a\n\nb", teams["text"]);
+    }
+}
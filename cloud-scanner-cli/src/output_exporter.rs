@@ -0,0 +1,312 @@
+//! A module to abstract the destination where results are written (standard output, file, HTTP endpoint, S3 bucket...).
+//!
+//! The destination is selected by the scheme of an output URI:
+//! - `-` (or no URI): standard output
+//! - `file:///path/to/results.json` or a plain path: local file (compressed with gzip or zstd when the path ends with `.gz` or `.zst`)
+//! - `http://...` or `https://...`: HTTP POST of the results
+//! - `s3://bucket/prefix/`: object in S3 with a date-partitioned key
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::s3_exporter::{upload_to_s3, S3Location};
+
+/// A trait to implement to support a new destination of results.
+#[async_trait]
+pub trait OutputExporter {
+    /// Writes results, returns a description of the location where they were written.
+    ///
+    /// The extension (like `json` or `csv`) describes the format of the results, it may be used to name the output or to set its content type.
+    async fn export(&self, results: Vec<u8>, extension: &str) -> Result<String>;
+}
+
+/// Writes results to standard output
+pub struct StdoutExporter;
+
+#[async_trait]
+impl OutputExporter for StdoutExporter {
+    async fn export(&self, results: Vec<u8>, extension: &str) -> Result<String> {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&results)?;
+        // Keep the prompt on its own line after text results
+        if !results.ends_with(b"\n") && !is_binary(extension) {
+            stdout.write_all(b"\n")?;
+        }
+        stdout.flush()?;
+        Ok("stdout".to_string())
+    }
+}
+
+/// Compression of a result file, selected by the extension of its path
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Returns the compression matching the extension of a path (like `results.json.gz`), if any
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder
+                    .finish()
+                    .context("Cannot compress results with gzip")
+            }
+            Compression::Zstd => {
+                zstd::encode_all(data, 0).context("Cannot compress results with zstd")
+            }
+        }
+    }
+}
+
+/// Writes results to a local file (replaced if it already exists).
+///
+/// Results are compressed when the path ends with `.gz` (gzip) or `.zst` (zstd).
+pub struct FileExporter {
+    pub path: PathBuf,
+}
+
+#[async_trait]
+impl OutputExporter for FileExporter {
+    async fn export(&self, results: Vec<u8>, _extension: &str) -> Result<String> {
+        let results = match Compression::from_path(&self.path) {
+            Some(compression) => compression.compress(&results)?,
+            None => results,
+        };
+        tokio::fs::write(&self.path, results)
+            .await
+            .with_context(|| format!("Cannot write results to file {}", self.path.display()))?;
+        let location = self.path.display().to_string();
+        info!("Results written to {}", location);
+        Ok(location)
+    }
+}
+
+/// Sends results in the body of an HTTP POST request
+pub struct HttpExporter {
+    pub url: String,
+}
+
+#[async_trait]
+impl OutputExporter for HttpExporter {
+    async fn export(&self, results: Vec<u8>, extension: &str) -> Result<String> {
+        let response = reqwest::Client::new()
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, content_type(extension))
+            .body(results)
+            .send()
+            .await
+            .with_context(|| format!("Cannot post results to {}", self.url))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("{} returned an error ({}): {}", self.url, status, body);
+        }
+        info!("Results posted to {}", self.url);
+        Ok(self.url.clone())
+    }
+}
+
+/// Uploads results to S3 under a date-partitioned key
+pub struct S3Exporter {
+    pub location: S3Location,
+    /// Region of the scan, used to name the object
+    pub aws_region: String,
+}
+
+#[async_trait]
+impl OutputExporter for S3Exporter {
+    async fn export(&self, results: Vec<u8>, extension: &str) -> Result<String> {
+        upload_to_s3(&self.location, &self.aws_region, results, extension).await
+    }
+}
+
+/// Returns the content type matching an extension of result file
+pub(crate) fn content_type(extension: &str) -> &'static str {
+    match extension {
+        "json" => "application/json",
+        "csv" => "text/csv",
+        "ndjson" => "application/x-ndjson",
+        "yml" => "application/yaml",
+        "html" => "text/html",
+        "pdf" => "application/pdf",
+        // Like summary.json
+        extension if extension.ends_with(".json") => "application/json",
+        _ => "text/plain",
+    }
+}
+
+/// Returns true if results with this extension are not text
+fn is_binary(extension: &str) -> bool {
+    extension == "pdf"
+}
+
+/// Returns the exporter matching the scheme of an output URI (standard output if no URI is provided).
+pub fn exporter_for_uri(
+    output_uri: Option<&str>,
+    aws_region: &str,
+) -> Result<Box<dyn OutputExporter + Send + Sync>> {
+    let uri = match output_uri {
+        None | Some("-") => return Ok(Box::new(StdoutExporter)),
+        Some(uri) => uri,
+    };
+    if uri.starts_with("s3://") {
+        return Ok(Box::new(S3Exporter {
+            location: S3Location::try_from(uri)?,
+            aws_region: aws_region.to_string(),
+        }));
+    }
+    if uri.starts_with("http://") || uri.starts_with("https://") {
+        return Ok(Box::new(HttpExporter {
+            url: uri.to_string(),
+        }));
+    }
+    if let Some(path) = uri.strip_prefix("file://") {
+        return Ok(Box::new(FileExporter {
+            path: PathBuf::from(path),
+        }));
+    }
+    if let Some((scheme, _)) = uri.split_once("://") {
+        anyhow::bail!(
+            "Unsupported output {} (scheme {} is not supported, use a path, file://, http(s):// or s3://)",
+            uri,
+            scheme
+        );
+    }
+    Ok(Box::new(FileExporter {
+        path: PathBuf::from(uri),
+    }))
+}
+
+/// Returns the path of the local file written for an output URI, `None` when results are not written to a local file
+pub fn local_path_of_uri(output_uri: Option<&str>) -> Option<PathBuf> {
+    match output_uri {
+        None | Some("-") => None,
+        Some(uri) => match uri.strip_prefix("file://") {
+            Some(path) => Some(PathBuf::from(path)),
+            None if uri.contains("://") => None,
+            None => Some(PathBuf::from(uri)),
+        },
+    }
+}
+
+/// Returns a writer of results streamed to standard output or to a local file (compressed while written when the path ends with `.gz` or `.zst`), the only destinations that results can be streamed to
+pub fn stream_writer_for_uri(output_uri: Option<&str>) -> Result<Box<dyn Write + Send>> {
+    let path = match output_uri {
+        None | Some("-") => return Ok(Box::new(std::io::stdout())),
+        Some(uri) => match uri.strip_prefix("file://") {
+            Some(path) => path,
+            None if uri.contains("://") => anyhow::bail!(
+                "Cannot stream results to {} (streamed results are written to standard output or to a local file)",
+                uri
+            ),
+            None => uri,
+        },
+    };
+    let path = PathBuf::from(path);
+    let file = std::fs::File::create(&path)
+        .with_context(|| format!("Cannot write results to file {}", path.display()))?;
+    let file = std::io::BufWriter::new(file);
+    Ok(match Compression::from_path(&path) {
+        Some(Compression::Gzip) => Box::new(flate2::write::GzEncoder::new(
+            file,
+            flate2::Compression::default(),
+        )),
+        Some(Compression::Zstd) => Box::new(
+            zstd::Encoder::new(file, 0)
+                .context("Cannot compress results with zstd")?
+                .auto_finish(),
+        ),
+        None => Box::new(file),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_schemes_are_rejected() {
+        assert!(exporter_for_uri(None, "eu-west-1").is_ok());
+        assert!(exporter_for_uri(Some("-"), "eu-west-1").is_ok());
+        assert!(exporter_for_uri(Some("s3://bucket/prefix"), "eu-west-1").is_ok());
+        assert!(exporter_for_uri(Some("https://example.com/results"), "eu-west-1").is_ok());
+        assert!(exporter_for_uri(Some("results.json"), "eu-west-1").is_ok());
+        assert!(exporter_for_uri(Some("ftp://example.com/results"), "eu-west-1").is_err());
+        assert!(exporter_for_uri(Some("s3://"), "eu-west-1").is_err());
+    }
+
+    #[test]
+    fn local_paths_of_uris() {
+        assert_eq!(None, local_path_of_uri(None));
+        assert_eq!(None, local_path_of_uri(Some("-")));
+        assert_eq!(None, local_path_of_uri(Some("s3://bucket/prefix/")));
+        assert_eq!(
+            Some(PathBuf::from("/tmp/results.json")),
+            local_path_of_uri(Some("file:///tmp/results.json"))
+        );
+        assert_eq!(
+            Some(PathBuf::from("results.json.gz")),
+            local_path_of_uri(Some("results.json.gz"))
+        );
+    }
+
+    #[tokio::test]
+    async fn write_results_to_a_file() {
+        let path = std::env::temp_dir().join("cloud-scanner-output-exporter-test.json");
+        let uri = format!("file://{}", path.display());
+        let exporter = exporter_for_uri(Some(&uri), "eu-west-1").unwrap();
+        let location = exporter
+            .export(b"{\"a\":1}".to_vec(), "json")
+            .await
+            .unwrap();
+        assert_eq!(path.display().to_string(), location);
+        assert_eq!("{\"a\":1}", std::fs::read_to_string(&path).unwrap());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn compress_files_by_extension() {
+        use std::io::Read;
+        let results = b"{\"a\":1}".to_vec();
+        for (name, compression) in [
+            ("results.json.gz", Compression::Gzip),
+            ("results.ndjson.zst", Compression::Zstd),
+        ] {
+            assert_eq!(Some(compression), Compression::from_path(Path::new(name)));
+            let path = std::env::temp_dir().join(format!("cloud-scanner-test-{}", name));
+            FileExporter { path: path.clone() }
+                .export(results.clone(), "json")
+                .await
+                .unwrap();
+            let compressed = std::fs::read(&path).unwrap();
+            let mut decompressed = Vec::new();
+            match compression {
+                Compression::Gzip => {
+                    flate2::read::GzDecoder::new(compressed.as_slice())
+                        .read_to_end(&mut decompressed)
+                        .unwrap();
+                }
+                Compression::Zstd => {
+                    decompressed = zstd::decode_all(compressed.as_slice()).unwrap()
+                }
+            }
+            assert_eq!(results, decompressed);
+            std::fs::remove_file(path).unwrap();
+        }
+        assert_eq!(None, Compression::from_path(Path::new("results.json")));
+    }
+}
@@ -0,0 +1,251 @@
+//! A module to render the [Block]s of a [Report] as a PDF document (A4), like the HTML report.
+//!
+//! The text is written with the DejaVu Sans font embedded in the binary and in the documents, so that names, tags and regions outside of Latin-1 (like `Łódź` or `Москва`) are rendered as is. Bold text is drawn with an outline rather than with a second font, as fonts are embedded whole (about 750 KB each).
+use anyhow::{Context, Result};
+use printpdf::{
+    Color, IndirectFontRef, Mm, PdfDocument, PdfDocumentReference, PdfLayerReference, Rect, Rgb,
+    TextRenderingMode,
+};
+
+use crate::report::{Bar, Block, Report};
+
+const FONT: &[u8] = include_bytes!("../fonts/DejaVuSans.ttf");
+
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 15.0;
+/// Approximate number of characters of a line of body text
+const LINE_CHARACTERS: usize = 95;
+
+/// Writes text and charts from the top to the bottom of pages, adding pages when needed
+struct PageWriter {
+    doc: PdfDocumentReference,
+    layer: PdfLayerReference,
+    font: IndirectFontRef,
+    /// Vertical position of the next element (from the bottom of the page, in mm)
+    y: f32,
+}
+
+impl PageWriter {
+    fn new(title: &str) -> Result<Self> {
+        let (doc, page, layer) =
+            PdfDocument::new(title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "content");
+        let font = doc
+            .add_external_font(FONT)
+            .context("Cannot load PDF font")?;
+        let layer = doc.get_page(page).get_layer(layer);
+        Ok(PageWriter {
+            doc,
+            layer,
+            font,
+            y: PAGE_HEIGHT - MARGIN,
+        })
+    }
+
+    /// Starts a new page if less than `height` mm are left on the current one
+    fn reserve(&mut self, height: f32) {
+        if self.y - height < MARGIN {
+            let (page, layer) = self
+                .doc
+                .add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "content");
+            self.layer = self.doc.get_page(page).get_layer(layer);
+            self.y = PAGE_HEIGHT - MARGIN;
+        }
+    }
+
+    fn text_at(&self, text: &str, size: f32, x: f32, bold: bool) {
+        self.layer.set_fill_color(black());
+        if bold {
+            self.layer.set_outline_color(black());
+            self.layer.set_outline_thickness(size / 30.0);
+            self.layer
+                .set_text_rendering_mode(TextRenderingMode::FillStroke);
+        } else {
+            self.layer.set_text_rendering_mode(TextRenderingMode::Fill);
+        }
+        self.layer
+            .use_text(text, size, Mm(x), Mm(self.y), &self.font);
+    }
+
+    fn heading(&mut self, text: &str, size: f32) {
+        self.reserve(size * 0.8);
+        self.y -= size * 0.5;
+        self.text_at(text, size, MARGIN, true);
+        self.y -= size * 0.3;
+    }
+
+    fn paragraph(&mut self, text: &str) {
+        for line in wrap(text, LINE_CHARACTERS) {
+            self.reserve(5.0);
+            self.y -= 5.0;
+            self.text_at(&line, 10.0, MARGIN, false);
+        }
+    }
+
+    /// Writes a row of cells, each cell starting at the given horizontal position (mm from the left margin)
+    fn row(&mut self, cells: &[(f32, String)], bold: bool) {
+        self.reserve(5.0);
+        self.y -= 5.0;
+        for (x, text) in cells {
+            self.text_at(text, 10.0, MARGIN + x, bold);
+        }
+    }
+
+    /// Draws a horizontal bar chart (label, bar and value on each row)
+    fn bar_chart(&mut self, bars: &[Bar]) {
+        let max = bars.iter().map(|b| b.value).fold(0.0, f64::max);
+        let bar_start = MARGIN + 70.0;
+        let bar_max_width = 80.0;
+        for bar in bars {
+            self.reserve(7.0);
+            self.y -= 7.0;
+            let mut label = bar.label.clone();
+            if label.chars().count() > 40 {
+                label = label.chars().take(39).collect::<String>() + "~";
+            }
+            self.text_at(&label, 9.0, MARGIN, false);
+            let width = if max > 0.0 {
+                (bar.value / max) as f32 * bar_max_width
+            } else {
+                0.0
+            };
+            if width > 0.0 {
+                self.layer
+                    .set_fill_color(Color::Rgb(Rgb::new(0.31, 0.6, 0.02, None)));
+                self.layer.add_rect(Rect::new(
+                    Mm(bar_start),
+                    Mm(self.y - 1.0),
+                    Mm(bar_start + width),
+                    Mm(self.y + 3.5),
+                ));
+            }
+            self.text_at(
+                &format!("{:.4}", bar.value),
+                9.0,
+                bar_start + width + 2.0,
+                false,
+            );
+        }
+        self.y -= 3.0;
+    }
+}
+
+fn black() -> Color {
+    Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None))
+}
+
+/// Splits a text in lines of at most `width` characters (on spaces)
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Horizontal position of the cells of a table of `columns` columns (mm from the left margin), the first column being the widest
+fn column_position(index: usize, columns: usize) -> f32 {
+    if index == 0 {
+        0.0
+    } else {
+        let width = (PAGE_WIDTH - 2.0 * MARGIN - 80.0) / (columns - 1) as f32;
+        80.0 + (index - 1) as f32 * width
+    }
+}
+
+/// Renders a report as a PDF document
+pub fn to_pdf(report: &Report) -> Result<Vec<u8>> {
+    let title = report.title();
+    let mut writer = PageWriter::new(&title)?;
+    writer.heading(&title, 18.0);
+    for block in report.blocks() {
+        match block {
+            Block::Heading(text) => writer.heading(&text, 14.0),
+            Block::Fields(fields) => {
+                for (label, value) in fields {
+                    writer.row(&[(0.0, label.to_string()), (50.0, value)], false);
+                }
+            }
+            Block::Table { header, rows } => {
+                let columns = header.len();
+                let cells = |row: Vec<String>| {
+                    row.into_iter()
+                        .enumerate()
+                        .map(|(i, cell)| (column_position(i, columns), cell))
+                        .collect::<Vec<_>>()
+                };
+                writer.row(&cells(header), true);
+                for row in rows {
+                    writer.row(&cells(row), false);
+                }
+            }
+            Block::Paragraph(text) => writer.paragraph(&text),
+            Block::Chart(bars) => writer.bar_chart(&bars),
+            Block::List(items) => {
+                for item in items {
+                    writer.paragraph(&format!("- {}", item));
+                }
+            }
+        }
+    }
+
+    writer
+        .doc
+        .save_to_bytes()
+        .context("Cannot render report as PDF")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::ImpactsSummary;
+    use crate::model::EstimatedInventory;
+    use crate::report::ReportMetadata;
+
+    #[test]
+    fn long_text_is_wrapped_on_spaces() {
+        assert_eq!(vec!["aaa bbb", "ccc"], wrap("aaa bbb ccc", 8));
+        assert_eq!(vec!["a-very-long-word"], wrap("a-very-long-word", 8));
+        assert!(wrap("", 8).is_empty());
+    }
+
+    #[test]
+    fn render_report_as_pdf() {
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let metadata = ReportMetadata {
+            generated_at: chrono::Utc::now(),
+            cloud_scanner_version: "2.0.5".to_string(),
+            aws_region: "eu-west-3".to_string(),
+            boavizta_api_url: "https://api.boavizta.org".to_string(),
+            filter_tags: vec!["city=Łódź".to_string(), "city=Москва".to_string()],
+        };
+        let report = Report::new(metadata, &summary, &estimated_inventory);
+        let pdf = to_pdf(&report).unwrap();
+        assert!(pdf.starts_with(b"%PDF-"));
+        // Text is written with the glyphs of the embedded font, not in the WinAnsi encoding of the built-in fonts
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("/FontFile2"));
+        assert!(pdf.contains("/Identity-H"));
+        assert!(!pdf.contains("WinAnsiEncoding"));
+    }
+}
@@ -0,0 +1,671 @@
+//! Plugins shipping inventory or impact providers for other clouds (or referentials), or sinks of results, registered at runtime without changing cloud-scanner.
+//!
+//! A [Plugin] builds the usual providers of cloud-scanner: an [Inventoriable] listing resources, an [ImpactProvider] or a [ResultExporter]. Plugins are registered in the registry of the process:
+//! - by library users, with [register_plugin] (plugins implemented in Rust)
+//! - by [load_plugins], which loads the shared libraries of plugins found in the plugin directories (`--plugin-dir`), named `libcloud_scanner_plugin_<name>.so` (`.dylib` on macOS)
+//!
+//! A shared library of a plugin can be written in any language exposing a C ABI. It exports two functions, exchanging json encoded in UTF-8:
+//!
+//! ```c
+//! // Runs a command with a json request (NULL when the command has none), setting *response to the json response (0 returned) or to the message of the error (other values returned)
+//! int32_t cloud_scanner_plugin_call(const char *command, const char *request, char **response);
+//! // Frees a response of cloud_scanner_plugin_call
+//! void cloud_scanner_plugin_free(char *response);
+//! ```
+//!
+//! The commands are:
+//! - `describe`: no request, returns the description of the plugin, like `{"name": "scaleway", "provides": ["inventory"], "description": "Instances of Scaleway"}`
+//! - `inventory`: request `{"regions": ["fr-par"], "filter_tags": [], "include_block_storage": false, "options": {"project": "shop"}}`, returns an inventory (see [crate::inventory_file])
+//! - `impacts`: request `{"inventory": [...], "use_duration_hours": 730.0, "verbose": false, "options": {}}` (the resources of an inventory), returns the resources with their impacts (the json output of the estimate command, with or without metadata)
+//! - `supported-types`: no request, returns the types known by the impact provider, like `{"instance_types": {"scaleway": ["DEV1-S"]}, "server_archetypes": []}`
+//! - `export`: request `{"metadata": {...}, "estimated_inventory": {...}, "summary": {...}, "options": {}}` (the results of a scan, as in the json output of the estimate command), returns the location of the exported results as text (the name of the plugin when empty)
+//!
+//! The options of requests are the `--plugin-option` of the command line.
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::{c_char, CStr, CString};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+
+use crate::cloud_provider::Inventoriable;
+use crate::impact_provider::{ImpactProvider, ImpactsSummary};
+use crate::model::{CloudResource, EstimatedInventory, Inventory};
+use crate::result_envelope::{ImpactProviderMetadata, ResultMetadata};
+use crate::result_exporter::ResultExporter;
+use crate::supported_types::SupportedTypes;
+
+/// Prefix of the names of the shared libraries of plugins (after the prefix of shared libraries of the platform, like `lib`)
+pub const PLUGIN_PREFIX: &str = "cloud_scanner_plugin_";
+
+/// Options of the plugins, like `project=shop` (see [parse_options])
+pub type PluginOptions = BTreeMap<String, String>;
+
+/// What a plugin provides
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PluginCapability {
+    /// Inventories of resources ([Plugin::inventory])
+    Inventory,
+    /// Impacts of resources ([Plugin::impact_provider])
+    Impacts,
+    /// Sink of the results of scans ([Plugin::result_exporter])
+    Export,
+}
+
+/// Description of a plugin (response of the `describe` command of shared libraries)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PluginDescription {
+    pub name: String,
+    pub provides: Vec<PluginCapability>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Shared library of the plugin, none for plugins registered by library users
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub library: Option<PathBuf>,
+}
+
+/// A plugin building inventories, impact providers or sinks of results. Only the methods of what the plugin provides (see [PluginDescription::provides]) are called.
+pub trait Plugin: Send + Sync {
+    fn description(&self) -> PluginDescription;
+
+    /// Returns the inventory of the resources of the plugin in these regions
+    fn inventory(
+        &self,
+        _regions: &[String],
+        _options: &PluginOptions,
+    ) -> Result<Box<dyn Inventoriable + Send + Sync>> {
+        anyhow::bail!(
+            "Plugin {} does not provide inventories",
+            self.description().name
+        )
+    }
+
+    /// Returns the impact provider of the plugin
+    fn impact_provider(
+        &self,
+        _options: &PluginOptions,
+    ) -> Result<Box<dyn ImpactProvider + Send + Sync>> {
+        anyhow::bail!(
+            "Plugin {} does not provide impacts",
+            self.description().name
+        )
+    }
+
+    /// Returns the sink of results of the plugin
+    fn result_exporter(
+        &self,
+        _options: &PluginOptions,
+    ) -> Result<Box<dyn ResultExporter + Send + Sync>> {
+        anyhow::bail!("Plugin {} does not export results", self.description().name)
+    }
+}
+
+/// Plugins of the process, by name
+static PLUGINS: LazyLock<Mutex<BTreeMap<String, Arc<dyn Plugin>>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+/// Registers a plugin under the name of its description, replacing the plugin registered under the same name
+pub fn register_plugin(plugin: Arc<dyn Plugin>) {
+    let name = plugin.description().name;
+    if PLUGINS
+        .lock()
+        .unwrap()
+        .insert(name.clone(), plugin)
+        .is_some()
+    {
+        warn!("Plugin {} replaced by a new registration", name);
+    }
+}
+
+/// Returns the registered plugins, sorted by name
+pub fn registered_plugins() -> Vec<Arc<dyn Plugin>> {
+    PLUGINS.lock().unwrap().values().cloned().collect()
+}
+
+/// Returns the registered plugin of this name
+pub fn find_plugin(name: &str) -> Result<Arc<dyn Plugin>> {
+    let plugins = PLUGINS.lock().unwrap();
+    match plugins.get(name) {
+        Some(plugin) => Ok(plugin.clone()),
+        None => {
+            let names: Vec<&String> = plugins.keys().collect();
+            anyhow::bail!(
+                "No plugin {} (registered plugins: {:?}, see --plugin-dir)",
+                name,
+                names
+            )
+        }
+    }
+}
+
+/// Parses options of requests, like `project=shop`
+pub fn parse_options(options: &[String]) -> Result<PluginOptions> {
+    options
+        .iter()
+        .map(|option| {
+            option
+                .split_once('=')
+                .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+                .with_context(|| format!("Expected a plugin option like key=value, not {}", option))
+        })
+        .collect()
+}
+
+/// Returns the description of a plugin in the metadata of results
+pub fn plugin_metadata(plugin: &dyn Plugin) -> ImpactProviderMetadata {
+    let description = plugin.description();
+    ImpactProviderMetadata {
+        name: format!("Plugin {}", description.name),
+        url: description
+            .library
+            .map(|library| library.display().to_string())
+            .unwrap_or_default(),
+        version: None,
+    }
+}
+
+/// Returns the plugins as text: their name, what they provide, their description and shared library
+pub fn plugins_to_text(plugins: &[Arc<dyn Plugin>]) -> String {
+    if plugins.is_empty() {
+        return "No plugin\n".to_string();
+    }
+    plugins
+        .iter()
+        .map(|plugin| {
+            let description = plugin.description();
+            let provides: Vec<String> = description
+                .provides
+                .iter()
+                .map(|capability| format!("{:?}", capability).to_lowercase())
+                .collect();
+            format!(
+                "{} ({}): {} [{}]\n",
+                description.name,
+                provides.join(", "),
+                description.description.as_deref().unwrap_or("-"),
+                description
+                    .library
+                    .map(|library| library.display().to_string())
+                    .unwrap_or_else(|| "registered".to_string())
+            )
+        })
+        .collect()
+}
+
+/// Returns the names and paths of the shared libraries of plugins in the plugin directories, the first directory having a plugin of a name being used
+fn plugin_libraries(plugin_dirs: &[String]) -> Vec<(String, PathBuf)> {
+    let prefix = format!("{}{}", std::env::consts::DLL_PREFIX, PLUGIN_PREFIX);
+    let mut libraries: BTreeMap<String, PathBuf> = BTreeMap::new();
+    for dir in plugin_dirs {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            warn!("Cannot read plugin directory {}", dir);
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Some(name) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(&prefix))
+                .and_then(|name| name.strip_suffix(std::env::consts::DLL_SUFFIX))
+            else {
+                continue;
+            };
+            if path.is_file() {
+                libraries.entry(name.to_string()).or_insert(path);
+            }
+        }
+    }
+    libraries.into_iter().collect()
+}
+
+/// Loads the shared libraries of plugins of the plugin directories and registers their plugins, skipping (with a warning) the libraries that fail to load or to describe themselves, and the plugins already registered
+pub fn load_plugins(plugin_dirs: &[String]) {
+    for (name, path) in plugin_libraries(plugin_dirs) {
+        match LibraryPlugin::load(&path) {
+            Ok(plugin)
+                if PLUGINS
+                    .lock()
+                    .unwrap()
+                    .contains_key(&plugin.description.name) =>
+            {
+                warn!(
+                    "Ignoring plugin {} of {}, already registered",
+                    plugin.description.name,
+                    path.display()
+                );
+            }
+            Ok(plugin) => {
+                debug!("Loaded plugin {} of {}", name, path.display());
+                register_plugin(Arc::new(plugin));
+            }
+            Err(e) => warn!("Ignoring plugin {}: {:#}", name, e),
+        }
+    }
+}
+
+type CallFn = unsafe extern "C" fn(*const c_char, *const c_char, *mut *mut c_char) -> i32;
+type FreeFn = unsafe extern "C" fn(*mut c_char);
+
+/// The functions of the shared library of a plugin, loaded for the lifetime of the process
+struct Library {
+    path: PathBuf,
+    call: CallFn,
+    free: FreeFn,
+}
+
+impl Library {
+    /// Loads a shared library and its functions
+    #[cfg(unix)]
+    fn open(path: &Path) -> Result<Library> {
+        use std::os::unix::ffi::OsStrExt;
+
+        /// Returns the message of the last error of the dynamic linker
+        fn last_error() -> String {
+            // SAFETY: the message of dlerror is a valid nul terminated string, or NULL
+            unsafe {
+                let message = libc::dlerror();
+                if message.is_null() {
+                    "unknown error".to_string()
+                } else {
+                    CStr::from_ptr(message).to_string_lossy().into_owned()
+                }
+            }
+        }
+
+        let c_path = CString::new(path.as_os_str().as_bytes()).context("Path with a nul")?;
+        // SAFETY: the path is a valid nul terminated string. Loading a library runs its initializers, plugins being trusted like the executable itself. The library is never unloaded, its functions staying valid.
+        let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() {
+            anyhow::bail!("Cannot load {}: {}", path.display(), last_error());
+        }
+        let symbol = |name: &CStr| {
+            // SAFETY: the handle is a loaded library and the name a valid nul terminated string
+            let symbol = unsafe { libc::dlsym(handle, name.as_ptr()) };
+            if symbol.is_null() {
+                anyhow::bail!(
+                    "No function {} in {}",
+                    name.to_string_lossy(),
+                    path.display()
+                );
+            }
+            Ok(symbol)
+        };
+        let call = symbol(c"cloud_scanner_plugin_call")?;
+        let free = symbol(c"cloud_scanner_plugin_free")?;
+        // SAFETY: the functions of plugins have these signatures (see the documentation of the module)
+        let (call, free) = unsafe {
+            (
+                std::mem::transmute::<*mut libc::c_void, CallFn>(call),
+                std::mem::transmute::<*mut libc::c_void, FreeFn>(free),
+            )
+        };
+        Ok(Library {
+            path: path.to_path_buf(),
+            call,
+            free,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn open(path: &Path) -> Result<Library> {
+        anyhow::bail!(
+            "Cannot load {}: shared libraries of plugins are only supported on unix",
+            path.display()
+        )
+    }
+
+    /// Runs a command of the plugin with a json request (if any), returns its response
+    fn call(&self, command: &str, request: Option<&str>) -> Result<String> {
+        let c_command = CString::new(command).context("Command with a nul")?;
+        let c_request = request
+            .map(CString::new)
+            .transpose()
+            .context("Request with a nul")?;
+        let mut response: *mut c_char = std::ptr::null_mut();
+        // SAFETY: the command and request are valid nul terminated strings living during the call, the response is set by the plugin and freed by it
+        let (status, response) = unsafe {
+            let status = (self.call)(
+                c_command.as_ptr(),
+                c_request
+                    .as_ref()
+                    .map_or(std::ptr::null(), |request| request.as_ptr()),
+                &mut response,
+            );
+            if response.is_null() {
+                (status, String::new())
+            } else {
+                let text = CStr::from_ptr(response).to_string_lossy().into_owned();
+                (self.free)(response);
+                (status, text)
+            }
+        };
+        if status != 0 {
+            anyhow::bail!(
+                "Plugin {} {} failed ({}): {}",
+                self.path.display(),
+                command,
+                status,
+                response.trim()
+            );
+        }
+        Ok(response)
+    }
+
+    /// Runs a command of the plugin off the async runtime, the plugin blocking while it runs
+    async fn call_blocking(
+        self: &Arc<Self>,
+        command: &'static str,
+        request: Option<String>,
+    ) -> Result<String> {
+        let library = self.clone();
+        tokio::task::spawn_blocking(move || library.call(command, request.as_deref()))
+            .await
+            .context("Plugin interrupted")?
+    }
+}
+
+/// A plugin of a shared library, whose commands are called with json requests
+struct LibraryPlugin {
+    description: PluginDescription,
+    library: Arc<Library>,
+}
+
+impl LibraryPlugin {
+    /// Loads the plugin of a shared library, described by its `describe` command
+    fn load(path: &Path) -> Result<LibraryPlugin> {
+        let library = Library::open(path)?;
+        let response = library.call("describe", None)?;
+        let mut description: PluginDescription = serde_json::from_str(&response)
+            .with_context(|| format!("Invalid description of plugin {}", path.display()))?;
+        description.library = Some(path.to_path_buf());
+        Ok(LibraryPlugin {
+            description,
+            library: Arc::new(library),
+        })
+    }
+
+    fn provides(&self, capability: PluginCapability) -> bool {
+        self.description.provides.contains(&capability)
+    }
+}
+
+impl Plugin for LibraryPlugin {
+    fn description(&self) -> PluginDescription {
+        self.description.clone()
+    }
+
+    fn inventory(
+        &self,
+        regions: &[String],
+        options: &PluginOptions,
+    ) -> Result<Box<dyn Inventoriable + Send + Sync>> {
+        if !self.provides(PluginCapability::Inventory) {
+            anyhow::bail!(
+                "Plugin {} does not provide inventories",
+                self.description.name
+            );
+        }
+        Ok(Box::new(LibraryInventory {
+            name: self.description.name.clone(),
+            library: self.library.clone(),
+            regions: regions.to_vec(),
+            options: options.clone(),
+        }))
+    }
+
+    fn impact_provider(
+        &self,
+        options: &PluginOptions,
+    ) -> Result<Box<dyn ImpactProvider + Send + Sync>> {
+        if !self.provides(PluginCapability::Impacts) {
+            anyhow::bail!("Plugin {} does not provide impacts", self.description.name);
+        }
+        Ok(Box::new(LibraryImpactProvider {
+            name: self.description.name.clone(),
+            library: self.library.clone(),
+            options: options.clone(),
+        }))
+    }
+
+    fn result_exporter(
+        &self,
+        options: &PluginOptions,
+    ) -> Result<Box<dyn ResultExporter + Send + Sync>> {
+        if !self.provides(PluginCapability::Export) {
+            anyhow::bail!("Plugin {} does not export results", self.description.name);
+        }
+        Ok(Box::new(LibraryResultExporter {
+            name: self.description.name.clone(),
+            library: self.library.clone(),
+            options: options.clone(),
+        }))
+    }
+}
+
+/// Request of the `inventory` command of shared libraries
+#[derive(Serialize)]
+struct InventoryRequest<'a> {
+    regions: &'a [String],
+    filter_tags: &'a [String],
+    include_block_storage: bool,
+    options: &'a PluginOptions,
+}
+
+/// Request of the `impacts` command of shared libraries
+#[derive(Serialize)]
+struct ImpactsRequest<'a> {
+    inventory: &'a [CloudResource],
+    use_duration_hours: f32,
+    verbose: bool,
+    options: &'a PluginOptions,
+}
+
+/// Request of the `export` command of shared libraries
+#[derive(Serialize)]
+struct ExportRequest<'a> {
+    metadata: &'a ResultMetadata,
+    estimated_inventory: &'a EstimatedInventory,
+    summary: &'a ImpactsSummary,
+    options: &'a PluginOptions,
+}
+
+/// An inventory running the `inventory` command of a shared library
+struct LibraryInventory {
+    name: String,
+    library: Arc<Library>,
+    regions: Vec<String>,
+    options: PluginOptions,
+}
+
+#[async_trait]
+impl Inventoriable for LibraryInventory {
+    async fn list_resources(
+        &self,
+        tags: &[String],
+        include_block_storage: bool,
+    ) -> Result<Inventory> {
+        let request = InventoryRequest {
+            regions: &self.regions,
+            filter_tags: tags,
+            include_block_storage,
+            options: &self.options,
+        };
+        let response = self
+            .library
+            .call_blocking("inventory", Some(serde_json::to_string(&request)?))
+            .await?;
+        let (_, inventory) = crate::inventory_file::parse_inventory(&response)
+            .with_context(|| format!("Invalid inventory of plugin {}", self.name))?;
+        Ok(inventory)
+    }
+}
+
+/// An impact provider running the `impacts` command of a shared library
+struct LibraryImpactProvider {
+    name: String,
+    library: Arc<Library>,
+    options: PluginOptions,
+}
+
+#[async_trait]
+impl ImpactProvider for LibraryImpactProvider {
+    async fn get_impacts(
+        &self,
+        inventory: Inventory,
+        usage_duration_hours: &f32,
+        verbose: bool,
+    ) -> Result<EstimatedInventory> {
+        let request = ImpactsRequest {
+            inventory: &inventory.resources,
+            use_duration_hours: *usage_duration_hours,
+            verbose,
+            options: &self.options,
+        };
+        let response = self
+            .library
+            .call_blocking("impacts", Some(serde_json::to_string(&request)?))
+            .await?;
+        let (_, mut estimated_inventory) = crate::scan_diff::parse_scan(&response)
+            .with_context(|| format!("Invalid impacts of plugin {}", self.name))?;
+        estimated_inventory.execution_statistics = inventory.execution_statistics;
+        Ok(estimated_inventory)
+    }
+
+    async fn supported_types(&self) -> Result<SupportedTypes> {
+        let response = self.library.call_blocking("supported-types", None).await?;
+        serde_json::from_str(&response)
+            .with_context(|| format!("Invalid supported types of plugin {}", self.name))
+    }
+}
+
+/// A sink of results running the `export` command of a shared library
+struct LibraryResultExporter {
+    name: String,
+    library: Arc<Library>,
+    options: PluginOptions,
+}
+
+#[async_trait]
+impl ResultExporter for LibraryResultExporter {
+    async fn export_results(
+        &self,
+        metadata: &ResultMetadata,
+        estimated_inventory: &EstimatedInventory,
+        summary: &ImpactsSummary,
+    ) -> Result<String> {
+        let request = ExportRequest {
+            metadata,
+            estimated_inventory,
+            summary,
+            options: &self.options,
+        };
+        let response = self
+            .library
+            .call_blocking("export", Some(serde_json::to_string(&request)?))
+            .await?;
+        let location = response.trim();
+        if location.is_empty() {
+            return Ok(format!("plugin {}", self.name));
+        }
+        Ok(location.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inventory of a project of a test cloud
+    struct ProjectInventory {
+        project: String,
+    }
+
+    #[async_trait]
+    impl Inventoriable for ProjectInventory {
+        async fn list_resources(
+            &self,
+            _tags: &[String],
+            _include_block_storage: bool,
+        ) -> Result<Inventory> {
+            let json = format!(
+                r#"[{{"provider": "AWS", "id": "{}-1", "location": {{"aws_region": "eu-west-3", "iso_country_code": "FRA"}}, "resource_details": {{"Instance": {{"instance_type": "t3.micro", "usage": null}}}}, "tags": []}}]"#,
+                self.project
+            );
+            let (_, inventory) = crate::inventory_file::parse_inventory(&json)?;
+            Ok(inventory)
+        }
+    }
+
+    struct TestCloud;
+
+    impl Plugin for TestCloud {
+        fn description(&self) -> PluginDescription {
+            PluginDescription {
+                name: "test-cloud".to_string(),
+                provides: vec![PluginCapability::Inventory],
+                description: Some("Instances of a test cloud".to_string()),
+                library: None,
+            }
+        }
+
+        fn inventory(
+            &self,
+            _regions: &[String],
+            options: &PluginOptions,
+        ) -> Result<Box<dyn Inventoriable + Send + Sync>> {
+            let project = options.get("project").context("Missing project")?;
+            Ok(Box::new(ProjectInventory {
+                project: project.clone(),
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn registered_plugins_provide_inventories() {
+        register_plugin(Arc::new(TestCloud));
+        assert!(plugins_to_text(&registered_plugins())
+            .contains("test-cloud (inventory): Instances of a test cloud [registered]\n"));
+
+        let plugin = find_plugin("test-cloud").unwrap();
+        let options = parse_options(&["project=shop".to_string()]).unwrap();
+        let inventory = plugin
+            .inventory(&["eu-west-3".to_string()], &options)
+            .unwrap()
+            .list_resources(&[], false)
+            .await
+            .unwrap();
+        assert_eq!("shop-1", inventory.resources[0].id);
+        assert!(plugin.inventory(&[], &PluginOptions::new()).is_err());
+        assert!(plugin.impact_provider(&options).is_err());
+        assert!(plugin.result_exporter(&options).is_err());
+        assert_eq!("Plugin test-cloud", plugin_metadata(plugin.as_ref()).name);
+        assert!(find_plugin("ovh").is_err());
+        assert!(parse_options(&["project".to_string()]).is_err());
+    }
+
+    #[test]
+    fn invalid_libraries_of_plugins_are_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = |name: &str| {
+            dir.path().join(format!(
+                "{}{}{}{}",
+                std::env::consts::DLL_PREFIX,
+                PLUGIN_PREFIX,
+                name,
+                std::env::consts::DLL_SUFFIX
+            ))
+        };
+        std::fs::write(library("broken"), "not a shared library").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "").unwrap();
+        let plugin_dirs = vec![dir.path().to_string_lossy().to_string()];
+
+        let libraries = plugin_libraries(&plugin_dirs);
+        assert_eq!(vec![("broken".to_string(), library("broken"))], libraries);
+        assert!(LibraryPlugin::load(&library("broken")).is_err());
+        load_plugins(&plugin_dirs);
+        assert!(find_plugin("broken").is_err());
+    }
+}
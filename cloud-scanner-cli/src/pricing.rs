@@ -0,0 +1,227 @@
+//! Approximate costs of the resources, from a static price file, reported alongside their impacts to compare cost and carbon (FinOps).
+//!
+//! The price file (TOML) gives the hourly prices of instance types and the monthly prices per GB of volume types, regions overriding the default prices:
+//!
+//! ```toml
+//! currency = "USD"
+//!
+//! [instance_hourly]
+//! "m6g.xlarge" = 0.154
+//!
+//! [volume_gb_month]
+//! gp3 = 0.08
+//!
+//! [regions.eu-west-3.instance_hourly]
+//! "m6g.xlarge" = 0.1792
+//! ```
+//!
+//! Resources of types without a price have no cost.
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::model::{CloudResource, EstimatedInventory, ResourceDetails};
+
+/// Hours of a month, to convert monthly prices to hourly prices
+const HOURS_PER_MONTH: f64 = 730.0;
+
+fn default_currency() -> String {
+    "USD".to_string()
+}
+
+/// Prices of the resources of a region
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Prices {
+    /// Hourly price of each instance type
+    #[serde(default)]
+    pub instance_hourly: BTreeMap<String, f64>,
+    /// Monthly price of a GB of each volume type
+    #[serde(default)]
+    pub volume_gb_month: BTreeMap<String, f64>,
+}
+
+/// Returns the hourly price of a resource from prices of instance types and volume types, None if its type has no price
+fn hourly_price_of(
+    instance_hourly: &BTreeMap<String, f64>,
+    volume_gb_month: &BTreeMap<String, f64>,
+    resource: &CloudResource,
+) -> Option<f64> {
+    match &resource.resource_details {
+        ResourceDetails::Instance { instance_type, .. } => {
+            instance_hourly.get(instance_type).copied()
+        }
+        ResourceDetails::BlockStorage {
+            storage_type,
+            usage,
+            ..
+        } => volume_gb_month.get(storage_type).map(|price| {
+            price * usage.as_ref().map_or(0, |usage| usage.size_gb) as f64 / HOURS_PER_MONTH
+        }),
+        ResourceDetails::ObjectStorage | ResourceDetails::Server { .. } => None,
+    }
+}
+
+/// Content of a price file
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PriceList {
+    #[serde(default = "default_currency")]
+    pub currency: String,
+    /// Hourly price of each instance type, in all the regions
+    #[serde(default)]
+    pub instance_hourly: BTreeMap<String, f64>,
+    /// Monthly price of a GB of each volume type, in all the regions
+    #[serde(default)]
+    pub volume_gb_month: BTreeMap<String, f64>,
+    /// Prices of some regions, overriding the prices of all the regions
+    #[serde(default)]
+    pub regions: BTreeMap<String, Prices>,
+}
+
+/// Approximate cost of a resource
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ResourceCost {
+    pub currency: String,
+    pub hourly_cost: f64,
+    /// Cost over the duration of use of the estimation
+    pub cost: f64,
+}
+
+impl PriceList {
+    /// Parses the content of a price file
+    pub fn parse(content: &str) -> Result<PriceList> {
+        toml::from_str(content).context("Invalid price file")
+    }
+
+    /// Reads a price file
+    pub fn load(path: &str) -> Result<PriceList> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read price file {}", path))?;
+        PriceList::parse(&content).with_context(|| format!("Invalid price file {}", path))
+    }
+
+    /// Returns the hourly price of a resource in its region, None if its type has no price
+    pub fn hourly_price(&self, resource: &CloudResource) -> Option<f64> {
+        self.regions
+            .get(&resource.location.aws_region)
+            .and_then(|prices| {
+                hourly_price_of(&prices.instance_hourly, &prices.volume_gb_month, resource)
+            })
+            .or_else(|| hourly_price_of(&self.instance_hourly, &self.volume_gb_month, resource))
+    }
+
+    /// Sets the cost of the resources over their duration of use
+    pub fn attach_costs(&self, mut estimated_inventory: EstimatedInventory) -> EstimatedInventory {
+        for resource in estimated_inventory.impacting_resources.iter_mut() {
+            resource.cost = self
+                .hourly_price(&resource.cloud_resource)
+                .map(|hourly_cost| ResourceCost {
+                    currency: self.currency.clone(),
+                    hourly_cost,
+                    cost: hourly_cost * resource.impacts_duration_hours as f64,
+                });
+        }
+        estimated_inventory
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{CloudResourceWithImpacts, ImpactsSummary};
+    use crate::model::{CloudProvider, StorageUsage};
+    use crate::usage_location::UsageLocation;
+
+    const PRICE_FILE: &str = r#"
+currency = "EUR"
+
+[instance_hourly]
+"m6g.xlarge" = 0.15
+
+[volume_gb_month]
+gp3 = 0.073
+
+[regions.eu-west-3.instance_hourly]
+"m6g.xlarge" = 0.25
+"#;
+
+    fn resource(region: &str, resource_details: ResourceDetails) -> CloudResourceWithImpacts {
+        CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: "res-1".to_string(),
+                location: UsageLocation::try_from(region).unwrap(),
+                resource_details,
+                tags: Vec::new(),
+                relationships: Vec::new(),
+            },
+            impacts_values: None,
+            impacts_duration_hours: 10.0,
+            cost: None,
+        }
+    }
+
+    #[test]
+    fn costs_use_the_prices_of_the_region_of_resources() {
+        let prices = PriceList::parse(PRICE_FILE).unwrap();
+        let instance = |instance_type: &str| ResourceDetails::Instance {
+            instance_type: instance_type.to_string(),
+            usage: None,
+        };
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![
+                resource("eu-west-3", instance("m6g.xlarge")),
+                resource("eu-west-1", instance("m6g.xlarge")),
+                resource("eu-west-1", instance("m5.large")),
+                resource(
+                    "eu-west-3",
+                    ResourceDetails::BlockStorage {
+                        storage_type: "gp3".to_string(),
+                        usage: Some(StorageUsage {
+                            size_gb: 100,
+                            usage_duration_seconds: 0,
+                        }),
+                        attached_instances: None,
+                    },
+                ),
+            ],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let estimated_inventory = prices.attach_costs(estimated_inventory);
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            10.0,
+        )
+        .with_costs(&prices.currency, &estimated_inventory);
+        let cost = summary.cost.unwrap();
+        assert_eq!(3, cost.number_of_resources_priced);
+        assert!((cost.total_cost - 4.1).abs() < 1e-9);
+        assert_eq!(Some(0.0), cost.gwp_kgco2eq_per_currency_unit);
+
+        let costs: Vec<Option<ResourceCost>> = estimated_inventory
+            .impacting_resources
+            .into_iter()
+            .map(|resource| resource.cost)
+            .collect();
+        assert_eq!(
+            Some(ResourceCost {
+                currency: "EUR".to_string(),
+                hourly_cost: 0.25,
+                cost: 2.5,
+            }),
+            costs[0]
+        );
+        assert_eq!(Some(1.5), costs[1].as_ref().map(|cost| cost.cost));
+        assert_eq!(None, costs[2]);
+        assert!((costs[3].as_ref().unwrap().hourly_cost - 0.01).abs() < 1e-9);
+
+        assert!(PriceList::parse("[instance_hourly]\n\"m5.large\" = \"free\"").is_err());
+        assert_eq!("USD", PriceList::parse("").unwrap().currency);
+    }
+}
@@ -0,0 +1,133 @@
+//! Progress of the scans on stderr for interactive runs (inventory, utilization metrics, impact estimation N/M), so that large scans do not look frozen.
+//!
+//! Progress is reported by the scans wherever they run, and displayed on a single line (one entry per scanned region) only once enabled (by the CLI, when stderr is a terminal).
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Progress of each scanned region
+static PROGRESS: LazyLock<Mutex<BTreeMap<String, RegionProgress>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+/// Phases of the scan of a region
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Phase {
+    /// Listing the resources
+    Inventory,
+    /// Reading the utilization metrics of the resources
+    UtilizationMetrics,
+    /// Estimating the impacts of the resources with Boavizta API
+    ImpactEstimation,
+}
+
+impl Phase {
+    fn name(&self) -> &'static str {
+        match self {
+            Phase::Inventory => "inventory",
+            Phase::UtilizationMetrics => "utilization metrics",
+            Phase::ImpactEstimation => "impact estimation",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct RegionProgress {
+    phase: Phase,
+    done: usize,
+    total: usize,
+}
+
+/// Displays the progress of the scans on stderr from now on
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Reports that the scan of a region (named by its region, prefixed by its account when assuming a role) reached a phase, with `done` of its `total` resources processed (0 of 0 when unknown)
+pub fn report(scan: &str, phase: Phase, done: usize, total: usize) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut progress = PROGRESS.lock().unwrap();
+    progress.insert(scan.to_string(), RegionProgress { phase, done, total });
+    let line = progress_line(&progress);
+    let mut stderr = std::io::stderr().lock();
+    // Rewrites the current line of the terminal
+    let _ = write!(stderr, "\r\x1b[2K{}", line);
+    let _ = stderr.flush();
+}
+
+/// Clears the progress line once the scans are done
+pub fn finish() {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut progress = PROGRESS.lock().unwrap();
+    if !progress.is_empty() {
+        progress.clear();
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K");
+        let _ = stderr.flush();
+    }
+}
+
+/// Returns the line displaying the progress of the regions, like `eu-west-3: impact estimation 12/40 [###.......]`
+fn progress_line(progress: &BTreeMap<String, RegionProgress>) -> String {
+    let regions: Vec<String> = progress
+        .iter()
+        .map(|(region, progress)| {
+            let region = if region.is_empty() { "scan" } else { region };
+            if progress.total == 0 {
+                return format!("{}: {}", region, progress.phase.name());
+            }
+            let width = 10;
+            let filled = (progress.done * width / progress.total).min(width);
+            format!(
+                "{}: {} {}/{} [{}{}]",
+                region,
+                progress.phase.name(),
+                progress.done,
+                progress.total,
+                "#".repeat(filled),
+                ".".repeat(width - filled)
+            )
+        })
+        .collect();
+    regions.join(" | ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_of_regions_is_displayed_on_one_line() {
+        let mut progress = BTreeMap::new();
+        progress.insert(
+            "eu-west-3".to_string(),
+            RegionProgress {
+                phase: Phase::ImpactEstimation,
+                done: 12,
+                total: 40,
+            },
+        );
+        assert_eq!(
+            "eu-west-3: impact estimation 12/40 [###.......]",
+            progress_line(&progress)
+        );
+        progress.insert(
+            "eu-west-1".to_string(),
+            RegionProgress {
+                phase: Phase::Inventory,
+                done: 0,
+                total: 0,
+            },
+        );
+        assert_eq!(
+            "eu-west-1: inventory | eu-west-3: impact estimation 12/40 [###.......]",
+            progress_line(&progress)
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! Inventories of the resources of a Pulumi preview (json output of `pulumi preview --json`), so that the impacts of a stack are estimated before it is deployed, like the plans of Terraform (see [crate::terraform_inventory]).
+//!
+//! The resources of a preview are the resources remaining after its steps (resources deleted by the preview are not inventoried), with their inputs. Their CPU loads are unknown, and estimated with the default load of Boavizta API.
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+use crate::model::{
+    CloudProvider, CloudResource, CloudResourceTag, Inventory, ResourceDetails, StorageAttachment,
+    StorageUsage, DEFAULT_VOLUME_TYPE,
+};
+use crate::tag_filter::TagFilter;
+use crate::tag_normalization;
+use crate::usage_location::UsageLocation;
+
+/// Steps of a preview after which a resource no longer exists
+const REMOVING_OPS: [&str; 4] = ["delete", "delete-replaced", "discard", "read-discard"];
+
+/// Parses the resources of a preview (`pulumi preview --json`): instances, and with `include_block_storage` volumes (including the block devices of instances).
+///
+/// Resources are located in the region of the configuration of the stack (`aws:region`), in the region of their availability zone, or in `default_region` otherwise.
+pub fn parse_pulumi_preview(
+    content: &str,
+    default_region: &str,
+    tags: &[String],
+    include_block_storage: bool,
+) -> Result<Inventory> {
+    let json: Value = serde_json::from_str(content)
+        .context("Expected the json output of pulumi preview --json")?;
+    let steps = json["steps"]
+        .as_array()
+        .context("Expected the json output of pulumi preview --json (no steps)")?;
+    let stack_region = json["config"]["aws:region"].as_str();
+    let filter = TagFilter::parse_all(tags)?;
+
+    let mut resources = Vec::new();
+    for step in steps {
+        let op = step["op"].as_str().unwrap_or_default();
+        let state = &step["newState"];
+        if REMOVING_OPS.contains(&op) || state.is_null() {
+            continue;
+        }
+        let urn = step["urn"]
+            .as_str()
+            .or_else(|| state["urn"].as_str())
+            .unwrap_or_default();
+        // The name of a resource ends its URN, like urn:pulumi:dev::shop::aws:ec2/instance:Instance::web
+        let name = urn.rsplit("::").next().unwrap_or(urn);
+        let inputs = &state["inputs"];
+        let region = stack_region
+            .map(str::to_string)
+            .or_else(|| availability_zone_region(inputs))
+            .unwrap_or_else(|| default_region.to_string());
+        let location = UsageLocation::try_from(region.as_str())
+            .with_context(|| format!("Unsupported region of {}", name))?;
+        let tags = tag_normalization::normalize(resource_tags(inputs));
+        if !filter.matches(&tags) {
+            debug!("Filtered {} (tags do not match)", name);
+            continue;
+        }
+        let resource_with = |id: String, resource_details: ResourceDetails| CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: None,
+            id,
+            location: location.clone(),
+            resource_details,
+            tags: tags.clone(),
+            relationships: Vec::new(),
+        };
+        match state["type"].as_str() {
+            Some("aws:ec2/instance:Instance") => {
+                let Some(instance_type) = inputs["instanceType"].as_str() else {
+                    warn!("Ignoring instance {} (unknown instance type)", name);
+                    continue;
+                };
+                resources.push(resource_with(
+                    name.to_string(),
+                    ResourceDetails::Instance {
+                        instance_type: instance_type.to_string(),
+                        usage: None,
+                    },
+                ));
+                if include_block_storage {
+                    for (device, volume) in block_devices(inputs) {
+                        let id = format!("{}.{}", name, device);
+                        match volume_details(volume, "volumeType", "volumeSize", name) {
+                            Some(details) => resources.push(resource_with(id, details)),
+                            None => debug!("Ignoring volume {} (unknown size)", id),
+                        }
+                    }
+                }
+            }
+            Some("aws:ebs/volume:Volume") if include_block_storage => {
+                match volume_details(inputs, "type", "size", "") {
+                    Some(details) => resources.push(resource_with(name.to_string(), details)),
+                    None => warn!("Ignoring volume {} (unknown size)", name),
+                }
+            }
+            _ => debug!("Ignoring {} (not an estimated kind of resource)", urn),
+        }
+    }
+    Ok(Inventory {
+        resources,
+        execution_statistics: None,
+    })
+}
+
+/// Reads the resources of a preview from a json file (output of `pulumi preview --json`), see [parse_pulumi_preview]
+pub fn read_pulumi_preview(
+    path: &str,
+    default_region: &str,
+    tags: &[String],
+    include_block_storage: bool,
+) -> Result<Inventory> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read Pulumi preview {}", path))?;
+    parse_pulumi_preview(&content, default_region, tags, include_block_storage)
+        .with_context(|| format!("Cannot parse Pulumi preview {}", path))
+}
+
+/// Returns the region of the availability zone of a resource (like eu-west-3 for eu-west-3a)
+fn availability_zone_region(inputs: &Value) -> Option<String> {
+    let zone = inputs["availabilityZone"].as_str()?;
+    let region = zone.trim_end_matches(|c: char| c.is_ascii_lowercase());
+    (!region.is_empty()).then(|| region.to_string())
+}
+
+fn resource_tags(inputs: &Value) -> Vec<CloudResourceTag> {
+    let tags = inputs["tagsAll"]
+        .as_object()
+        .or_else(|| inputs["tags"].as_object());
+    tags.map(|tags| {
+        tags.iter()
+            .map(|(key, value)| CloudResourceTag {
+                key: key.clone(),
+                value: value.as_str().map(str::to_string),
+            })
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Returns the root and EBS block devices of an instance, with the name of their device
+fn block_devices(inputs: &Value) -> Vec<(String, &Value)> {
+    let mut devices = Vec::new();
+    if inputs["rootBlockDevice"].is_object() {
+        devices.push(("rootBlockDevice".to_string(), &inputs["rootBlockDevice"]));
+    }
+    if let Some(ebs_devices) = inputs["ebsBlockDevices"].as_array() {
+        for (index, device) in ebs_devices.iter().enumerate() {
+            let name = device["deviceName"]
+                .as_str()
+                .map(|name| format!("ebsBlockDevices[{}]", name))
+                .unwrap_or_else(|| format!("ebsBlockDevices[{}]", index));
+            devices.push((name, device));
+        }
+    }
+    devices
+}
+
+/// Returns the details of a volume of known size, attached to an instance (unless `instance` is empty)
+fn volume_details(
+    inputs: &Value,
+    type_key: &str,
+    size_key: &str,
+    instance: &str,
+) -> Option<ResourceDetails> {
+    let size_gb = inputs[size_key].as_i64()?;
+    let storage_type = inputs[type_key].as_str().unwrap_or(DEFAULT_VOLUME_TYPE);
+    Some(ResourceDetails::BlockStorage {
+        storage_type: storage_type.to_string(),
+        usage: Some(StorageUsage {
+            size_gb: size_gb as i32,
+            usage_duration_seconds: 3600,
+        }),
+        attached_instances: (!instance.is_empty()).then(|| {
+            vec![StorageAttachment {
+                instance_id: instance.to_string(),
+            }]
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREVIEW: &str = r#"{
+        "config": {"aws:region": "eu-west-3", "shop:env": "dev"},
+        "steps": [
+            {"op": "same", "urn": "urn:pulumi:dev::shop::pulumi:pulumi:Stack::shop-dev",
+             "newState": {"type": "pulumi:pulumi:Stack", "inputs": {}}},
+            {"op": "create", "urn": "urn:pulumi:dev::shop::aws:ec2/instance:Instance::web",
+             "newState": {"type": "aws:ec2/instance:Instance",
+                          "inputs": {"instanceType": "m6g.xlarge", "tags": {"team": "web"},
+                                     "rootBlockDevice": {"volumeSize": 30, "volumeType": "gp3"}}}},
+            {"op": "update", "urn": "urn:pulumi:dev::shop::aws:ebs/volume:Volume::data",
+             "newState": {"type": "aws:ebs/volume:Volume", "inputs": {"size": 200, "availabilityZone": "eu-west-3a"}}},
+            {"op": "delete", "urn": "urn:pulumi:dev::shop::aws:ec2/instance:Instance::legacy",
+             "oldState": {"type": "aws:ec2/instance:Instance", "inputs": {"instanceType": "m4.large"}}},
+            {"op": "create", "urn": "urn:pulumi:dev::shop::aws:s3/bucket:Bucket::logs",
+             "newState": {"type": "aws:s3/bucket:Bucket", "inputs": {}}}
+        ],
+        "changeSummary": {"create": 2, "delete": 1, "same": 1, "update": 1}
+    }"#;
+
+    #[test]
+    fn previews_are_inventoried() {
+        let inventory = parse_pulumi_preview(PREVIEW, "us-east-1", &[], false).unwrap();
+        assert_eq!(1, inventory.resources.len());
+        assert_eq!("web", inventory.resources[0].id);
+        assert_eq!("eu-west-3", inventory.resources[0].location.aws_region);
+
+        let inventory = parse_pulumi_preview(PREVIEW, "us-east-1", &[], true).unwrap();
+        let ids: Vec<&str> = inventory.resources.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(vec!["web", "web.rootBlockDevice", "data"], ids);
+        assert_eq!(
+            Some("gp2".to_string()),
+            inventory.resources[2].resource_details.resource_type()
+        );
+
+        let filtered =
+            parse_pulumi_preview(PREVIEW, "us-east-1", &["team=batch".to_string()], true).unwrap();
+        assert!(filtered.resources.is_empty());
+        assert!(parse_pulumi_preview(r#"{"resources": []}"#, "eu-west-1", &[], false).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+//! Per-client rate limiting of the standalone server, so that a misconfigured scraper cannot trigger a storm of scans against the cloud APIs.
+//!
+//! Each client (identified by its API key or token, or by its IP address) has a token bucket: a request consumes a token, tokens are refilled at `requests_per_minute` and at most `burst` are kept.
+use rocket::http::{Header, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::Responder;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::server_auth;
+
+/// Number of clients above which the buckets of idle clients are forgotten
+const MAX_IDLE_CLIENTS: usize = 1000;
+
+/// Settings of rate limiting
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Sustained number of requests per minute of a client
+    pub requests_per_minute: u32,
+    /// Number of requests a client can send at once (defaults to `requests_per_minute`)
+    pub burst: Option<u32>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token buckets of the clients
+pub struct RateLimiter {
+    capacity: f64,
+    /// Tokens refilled per second
+    refill_rate: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        let requests_per_minute = config.requests_per_minute.max(1);
+        RateLimiter {
+            capacity: f64::from(config.burst.unwrap_or(requests_per_minute).max(1)),
+            refill_rate: f64::from(requests_per_minute) / 60.0,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Consumes a token of the client at `now`, returns the delay before the next token when the bucket is empty
+    fn acquire_at(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_IDLE_CLIENTS {
+            let full_after = Duration::from_secs_f64(self.capacity / self.refill_rate);
+            buckets.retain(|_, bucket| now.duration_since(bucket.updated_at) < full_after);
+        }
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated_at: now,
+        });
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_rate).min(self.capacity);
+        bucket.updated_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.refill_rate,
+            ))
+        }
+    }
+
+    /// Consumes a token of the client, returns the delay before the next token when the bucket is empty
+    pub fn acquire(&self, client: &str) -> Result<(), Duration> {
+        self.acquire_at(client, Instant::now())
+    }
+}
+
+/// The key of the bucket of a request: its API key or token, or its IP address
+fn client_key(request: &Request<'_>) -> String {
+    let headers = request.headers();
+    match server_auth::request_token(
+        headers.get_one("Authorization"),
+        headers.get_one("X-API-Key"),
+    ) {
+        Some(token) => format!("token:{}", token),
+        None => format!(
+            "ip:{}",
+            request
+                .client_ip()
+                .map(|ip| ip.to_string())
+                .unwrap_or_default()
+        ),
+    }
+}
+
+/// Delay before the next request allowed, cached in the request for the [too_many_requests] catcher
+struct RetryAfter(Option<Duration>);
+
+/// A request guard that succeeds when the client has not exceeded its rate (or when rate limiting is disabled)
+pub struct RateLimited;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RateLimited {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(limiter) = request.rocket().state::<Option<RateLimiter>>() else {
+            return Outcome::Success(RateLimited);
+        };
+        let Some(limiter) = limiter else {
+            return Outcome::Success(RateLimited);
+        };
+        match limiter.acquire(&client_key(request)) {
+            Ok(()) => Outcome::Success(RateLimited),
+            Err(retry_after) => {
+                warn!(
+                    "Rate limit exceeded for request to {} from {:?}",
+                    request.uri(),
+                    request.client_ip()
+                );
+                request.local_cache(|| RetryAfter(Some(retry_after)));
+                Outcome::Error((Status::TooManyRequests, "Rate limit exceeded".to_string()))
+            }
+        }
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for RateLimited {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
+}
+
+/// The response to a client that exceeded its rate
+#[derive(Responder)]
+#[response(status = 429)]
+pub struct TooManyRequests {
+    message: String,
+    retry_after: Header<'static>,
+}
+
+/// Answers 429 with a `Retry-After` header (in seconds)
+#[rocket::catch(429)]
+pub fn too_many_requests(request: &Request<'_>) -> TooManyRequests {
+    let RetryAfter(retry_after) = request.local_cache(|| RetryAfter(None));
+    let seconds = retry_after.map(|delay| delay.as_secs_f64().ceil() as u64);
+    TooManyRequests {
+        message: "Rate limit exceeded, retry later".to_string(),
+        retry_after: Header::new("Retry-After", seconds.unwrap_or(1).max(1).to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clients_are_limited_independently() {
+        let limiter = RateLimiter::new(&RateLimitConfig {
+            requests_per_minute: 6,
+            burst: Some(2),
+        });
+        let start = Instant::now();
+        assert!(limiter.acquire_at("a", start).is_ok());
+        assert!(limiter.acquire_at("a", start).is_ok());
+        let retry_after = limiter.acquire_at("a", start).unwrap_err();
+        assert_eq!(10, retry_after.as_secs());
+        assert!(limiter.acquire_at("b", start).is_ok());
+
+        // A token is refilled every 10 seconds
+        assert!(limiter
+            .acquire_at("a", start + Duration::from_secs(5))
+            .is_err());
+        assert!(limiter
+            .acquire_at("a", start + Duration::from_secs(11))
+            .is_ok());
+        // The bucket holds at most the burst
+        let later = start + Duration::from_secs(3600);
+        assert!(limiter.acquire_at("a", later).is_ok());
+        assert!(limiter.acquire_at("a", later).is_ok());
+        assert!(limiter.acquire_at("a", later).is_err());
+    }
+}
@@ -0,0 +1,240 @@
+//! A module to push metrics with the Prometheus remote-write protocol (version 1.0) to Mimir, Cortex or Thanos receivers, so that batch scans need no scrape target nor Pushgateway.
+//!
+//! The metrics in the prometheus text format are converted to time series, encoded in protobuf and compressed with snappy.
+use anyhow::{Context, Result};
+use prost::Message;
+
+/// Messages of the remote-write protocol, generated from the protobuf definitions
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/prometheus.rs"));
+}
+
+use proto::{Label, Sample, TimeSeries, WriteRequest};
+
+/// Authentication of the requests to the receiver
+#[derive(Clone, Debug, PartialEq)]
+pub enum RemoteWriteAuth {
+    None,
+    /// Sent as `Authorization: Bearer <token>`
+    Bearer(String),
+    Basic {
+        username: String,
+        password: String,
+    },
+}
+
+///  Settings of the remote-write push
+#[derive(Clone, Debug)]
+pub struct RemoteWriteConfig {
+    /// Remote-write endpoint of the receiver (like http://mimir:9009/api/v1/push)
+    pub url: String,
+    pub auth: RemoteWriteAuth,
+}
+
+impl RemoteWriteConfig {
+    /// Returns the settings of a receiver, authenticated with a bearer token or a username (and password), if any
+    pub fn new(
+        url: String,
+        bearer_token: Option<String>,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> Self {
+        let auth = match (bearer_token, username) {
+            (Some(token), _) => RemoteWriteAuth::Bearer(token),
+            (None, Some(username)) => RemoteWriteAuth::Basic {
+                username,
+                password: password.unwrap_or_default(),
+            },
+            (None, None) => RemoteWriteAuth::None,
+        };
+        RemoteWriteConfig { url, auth }
+    }
+}
+
+/// Maximum length of a literal of snappy
+const MAX_LITERAL_LENGTH: usize = 65536;
+
+/// Compresses data in the snappy block format (as expected by receivers), as literals only: the payloads of metrics are small, so they are not worth a compressor
+fn snappy_block(data: &[u8]) -> Vec<u8> {
+    let mut block = Vec::with_capacity(data.len() + data.len() / MAX_LITERAL_LENGTH * 3 + 8);
+    let mut length = data.len() as u64;
+    // Uncompressed length, as a varint
+    loop {
+        let byte = (length & 0x7f) as u8;
+        length >>= 7;
+        if length == 0 {
+            block.push(byte);
+            break;
+        }
+        block.push(byte | 0x80);
+    }
+    for literal in data.chunks(MAX_LITERAL_LENGTH) {
+        let n = literal.len() - 1;
+        if n < 60 {
+            block.push((n as u8) << 2);
+        } else if n < 256 {
+            block.push(60 << 2);
+            block.push(n as u8);
+        } else {
+            block.push(61 << 2);
+            block.extend_from_slice(&(n as u16).to_le_bytes());
+        }
+        block.extend_from_slice(literal);
+    }
+    block
+}
+
+/// Returns the labels of a sample, like `name{label="value"}`, the name being the `__name__` label
+fn parse_labels(series: &str) -> Result<Vec<Label>> {
+    let (name, mut rest) = match series.find('{') {
+        Some(start) => (&series[..start], &series[start + 1..]),
+        None => (series, ""),
+    };
+    let mut labels = vec![Label {
+        name: "__name__".to_string(),
+        value: name.to_string(),
+    }];
+    while let Some((label, after)) = rest.split_once("=\"") {
+        let mut value = String::new();
+        let mut chars = after.char_indices();
+        let end = loop {
+            match chars.next() {
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, escaped)) => value.push(escaped),
+                    None => anyhow::bail!("Invalid escape in labels of {}", series),
+                },
+                Some((i, '"')) => break i,
+                Some((_, c)) => value.push(c),
+                None => anyhow::bail!("Unterminated label value in {}", series),
+            }
+        };
+        labels.push(Label {
+            name: label.trim_start_matches(',').to_string(),
+            value,
+        });
+        rest = &after[end + 1..];
+    }
+    // Receivers expect the labels sorted by name
+    labels.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(labels)
+}
+
+/// Converts metrics in the prometheus text format to time series of one sample, at a timestamp in milliseconds
+pub fn to_time_series(metrics: &str, timestamp_ms: i64) -> Result<Vec<TimeSeries>> {
+    let mut series = Vec::new();
+    for line in metrics.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // Label values may contain spaces, the value follows the labels
+        let labels_end = line.rfind('}').map_or(0, |end| end + 1);
+        let (name_and_labels, value) = match labels_end {
+            0 => line.split_once(' ').unwrap_or((line, "")),
+            end => (&line[..end], &line[end..]),
+        };
+        let value = value.split_whitespace().next().unwrap_or_default();
+        let value: f64 = value
+            .parse()
+            .with_context(|| format!("Invalid value of sample {}", line))?;
+        series.push(TimeSeries {
+            labels: parse_labels(name_and_labels)?,
+            samples: vec![Sample {
+                value,
+                timestamp: timestamp_ms,
+            }],
+        });
+    }
+    Ok(series)
+}
+
+/// Pushes metrics in the prometheus text format to a remote-write receiver, as samples at a timestamp in milliseconds
+pub async fn push_to_remote_write(
+    config: &RemoteWriteConfig,
+    metrics: &str,
+    timestamp_ms: i64,
+) -> Result<()> {
+    let request = WriteRequest {
+        timeseries: to_time_series(metrics, timestamp_ms)?,
+    };
+    let count = request.timeseries.len();
+    let body = snappy_block(&request.encode_to_vec());
+    let mut builder = reqwest::Client::new()
+        .post(&config.url)
+        .header("Content-Type", "application/x-protobuf")
+        .header("Content-Encoding", "snappy")
+        .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+        .body(body);
+    builder = match &config.auth {
+        RemoteWriteAuth::None => builder,
+        RemoteWriteAuth::Bearer(token) => builder.bearer_auth(token),
+        RemoteWriteAuth::Basic { username, password } => {
+            builder.basic_auth(username, Some(password))
+        }
+    };
+    let response = builder
+        .send()
+        .await
+        .with_context(|| format!("Cannot reach the remote-write receiver at {}", config.url))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let message = response.text().await.unwrap_or_default();
+        anyhow::bail!("Remote write failed with status {}: {}", status, message);
+    }
+    info!(
+        "Pushed {} series to remote-write receiver {}",
+        count, config.url
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_are_converted_to_compressed_time_series() {
+        let metrics = r#"# HELP boavizta_number_of_resources_total Number of resources detected during the inventory.
+# TYPE boavizta_number_of_resources_total gauge
+boavizta_number_of_resources_total{tenant="team a",awsregion="eu-west-3",country="FRA"} 5
+boavizta_resource_gwp_use_kgco2eq{resource_tags="[\"Name:a \\\"b\\\"\"]"} 0.25
+boavizta_duration_of_use_hours 730
+# EOF
+"#;
+        let series = to_time_series(metrics, 1700000000000).unwrap();
+        assert_eq!(3, series.len());
+        let labels: Vec<(&str, &str)> = series[0]
+            .labels
+            .iter()
+            .map(|label| (label.name.as_str(), label.value.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("__name__", "boavizta_number_of_resources_total"),
+                ("awsregion", "eu-west-3"),
+                ("country", "FRA"),
+                ("tenant", "team a")
+            ],
+            labels
+        );
+        assert_eq!(5.0, series[0].samples[0].value);
+        assert_eq!(1700000000000, series[0].samples[0].timestamp);
+        assert_eq!(r#"["Name:a \"b\""]"#, series[1].labels[1].value);
+        assert_eq!(1, series[2].labels.len());
+        assert!(to_time_series("boavizta_gwp_use_kgco2eq{a=\"b\"} x", 0).is_err());
+
+        // Varint of the length then one literal
+        assert_eq!(vec![3, 2 << 2, b'a', b'b', b'c'], snappy_block(b"abc"));
+        let data = vec![7; 70000];
+        let block = snappy_block(&data);
+        assert_eq!([0xf0, 0xa2, 0x04], block[..3]);
+        assert_eq!([61 << 2, 0xff, 0xff], block[3..6]);
+        assert_eq!(3 + 3 + 65536 + 3 + 4464, block.len());
+
+        let request = WriteRequest { timeseries: series };
+        let decoded = WriteRequest::decode(request.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(request, decoded);
+    }
+}
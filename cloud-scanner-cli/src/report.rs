@@ -0,0 +1,480 @@
+//! A module to build a human readable report of a scan (metadata, methodology notes, summary and charts) and render it as HTML.
+//!
+//! The content of a [Report] is laid out once as [Block]s, rendered as HTML and Markdown here and as PDF by the [pdf_report](crate::pdf_report) module.
+use chrono::{DateTime, Utc};
+
+use crate::impact_provider::ImpactsSummary;
+use crate::model::EstimatedInventory;
+use crate::units::OutputUnits;
+
+/// Maximum number of resources listed in the top resources chart
+const TOP_RESOURCES: usize = 10;
+
+/// Methodology notes included in every report
+pub const METHODOLOGY_NOTES: [&str; 5] = [
+    "Impacts are estimated with Boavizta API from the inventory of the account: type of instances, average CPU load and size of block storage.",
+    "Impacts of use (GWP, primary energy, ADP) depend on the carbon intensity of the electricity of the country where the region is located.",
+    "Manufacture (embodied) impacts are allocated to the resources proportionally to the usage duration, over the expected lifetime of the hardware.",
+    "Resources of an unknown type are listed in the inventory but not assessed: they are not counted in the totals.",
+    "Impacts of block storage only include manufacture, they are likely overestimated because they are based on the size of the volumes.",
+];
+
+/// Context of the scan described by a report
+#[derive(Clone, Debug)]
+pub struct ReportMetadata {
+    pub generated_at: DateTime<Utc>,
+    pub cloud_scanner_version: String,
+    pub aws_region: String,
+    pub boavizta_api_url: String,
+    pub filter_tags: Vec<String>,
+}
+
+/// Global warming potential of a single resource
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResourceGwp {
+    pub resource_id: String,
+    pub resource_kind: String,
+    pub gwp_manufacture_kgco2eq: f64,
+    pub gwp_use_kgco2eq: f64,
+}
+
+impl ResourceGwp {
+    pub fn total_kgco2eq(&self) -> f64 {
+        self.gwp_manufacture_kgco2eq + self.gwp_use_kgco2eq
+    }
+}
+
+/// A value displayed as a bar of a chart
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bar {
+    pub label: String,
+    pub value: f64,
+}
+
+/// A part of a report (after its title), rendered the same way by every format
+#[derive(Clone, Debug, PartialEq)]
+pub enum Block {
+    Heading(String),
+    /// Labelled values, like the metadata of the scan
+    Fields(Vec<(&'static str, String)>),
+    Table {
+        header: Vec<String>,
+        rows: Vec<Vec<String>>,
+    },
+    Paragraph(String),
+    Chart(Vec<Bar>),
+    List(Vec<String>),
+}
+
+/// The content of a report, independent of its rendering
+#[derive(Clone, Debug)]
+pub struct Report {
+    pub metadata: ReportMetadata,
+    pub summary: ImpactsSummary,
+    /// Resources with the highest GWP (manufacture and use), in descending order
+    pub top_resources: Vec<ResourceGwp>,
+    /// Units of the impacts of the summary and resources
+    pub units: OutputUnits,
+}
+
+impl Report {
+    pub fn new(
+        metadata: ReportMetadata,
+        summary: &ImpactsSummary,
+        estimated_inventory: &EstimatedInventory,
+    ) -> Self {
+        let mut top_resources: Vec<ResourceGwp> = estimated_inventory
+            .impacting_resources
+            .iter()
+            .filter_map(|resource| {
+                let impacts = resource.impacts_values.as_ref()?;
+                Some(ResourceGwp {
+                    resource_id: resource.cloud_resource.id.clone(),
+                    resource_kind: resource.cloud_resource.resource_details.kind().to_string(),
+                    gwp_manufacture_kgco2eq: impacts.gwp_manufacture_kgco2eq,
+                    gwp_use_kgco2eq: impacts.gwp_use_kgco2eq,
+                })
+            })
+            .collect();
+        top_resources.sort_by(|a, b| b.total_kgco2eq().total_cmp(&a.total_kgco2eq()));
+        top_resources.truncate(TOP_RESOURCES);
+        Report {
+            metadata,
+            summary: summary.clone(),
+            top_resources,
+            units: OutputUnits::default(),
+        }
+    }
+
+    /// Sets the units of the impacts (the summary and resources being converted to these units)
+    pub fn with_units(mut self, units: OutputUnits) -> Self {
+        self.units = units;
+        self
+    }
+
+    /// Title of the GWP charts, like `Global warming potential (kgCO2eq)`
+    pub fn gwp_title(&self) -> String {
+        format!("Global warming potential ({})", self.units.gwp.symbol())
+    }
+
+    /// Title of the chart of the top resources, like `Top resources by global warming potential (kgCO2eq)`
+    pub fn top_resources_title(&self) -> String {
+        format!(
+            "Top resources by global warming potential ({})",
+            self.units.gwp.symbol()
+        )
+    }
+
+    pub fn title(&self) -> String {
+        let regions = self.summary.aws_regions();
+        let region = if regions.is_empty() {
+            "default region"
+        } else {
+            &regions
+        };
+        format!("Cloud impacts report - {}", region)
+    }
+
+    /// Metadata of the scan as (label, value) pairs
+    pub fn metadata_rows(&self) -> Vec<(&'static str, String)> {
+        let tags = if self.metadata.filter_tags.is_empty() {
+            "none".to_string()
+        } else {
+            self.metadata.filter_tags.join(", ")
+        };
+        vec![
+            (
+                "Generated at",
+                self.metadata
+                    .generated_at
+                    .format("%Y-%m-%d %H:%M:%S UTC")
+                    .to_string(),
+            ),
+            (
+                "Cloud scanner version",
+                self.metadata.cloud_scanner_version.clone(),
+            ),
+            ("AWS region", self.metadata.aws_region.clone()),
+            ("Country", self.summary.countries()),
+            (
+                "Duration of use",
+                format!("{} hours", self.summary.duration_of_use_hours),
+            ),
+            ("Boavizta API", self.metadata.boavizta_api_url.clone()),
+            ("Filter tags", tags),
+            (
+                "Resources",
+                format!(
+                    "{} detected, {} assessed, {} not assessed",
+                    self.summary.number_of_resources_total,
+                    self.summary.number_of_resources_assessed,
+                    self.summary.number_of_resources_not_assessed
+                ),
+            ),
+        ]
+    }
+
+    /// Impacts of the summary as (impact, manufacture, use) rows
+    pub fn impacts_rows(&self) -> Vec<(String, f64, f64)> {
+        let summary = &self.summary;
+        vec![
+            (
+                self.gwp_title(),
+                summary.gwp_manufacture_kgco2eq,
+                summary.gwp_use_kgco2eq,
+            ),
+            (
+                format!("Primary energy ({})", self.units.energy.symbol()),
+                summary.pe_manufacture_megajoules,
+                summary.pe_use_megajoules,
+            ),
+            (
+                "Abiotic depletion potential (kgSbeq)".to_string(),
+                summary.adp_manufacture_kgsbeq,
+                summary.adp_use_kgsbeq,
+            ),
+        ]
+    }
+
+    /// Chart of the GWP of manufacture compared to the GWP of use
+    pub fn gwp_chart(&self) -> Vec<Bar> {
+        vec![
+            Bar {
+                label: "Manufacture".to_string(),
+                value: self.summary.gwp_manufacture_kgco2eq,
+            },
+            Bar {
+                label: "Use".to_string(),
+                value: self.summary.gwp_use_kgco2eq,
+            },
+        ]
+    }
+
+    /// Chart of the resources with the highest GWP
+    pub fn top_resources_chart(&self) -> Vec<Bar> {
+        self.top_resources
+            .iter()
+            .map(|r| Bar {
+                label: format!("{} ({})", r.resource_id, r.resource_kind),
+                value: r.total_kgco2eq(),
+            })
+            .collect()
+    }
+
+    /// Sections of the report, in the order they are rendered
+    pub fn blocks(&self) -> Vec<Block> {
+        let mut blocks = vec![
+            Block::Heading("Scan".to_string()),
+            Block::Fields(self.metadata_rows()),
+            Block::Heading("Impacts".to_string()),
+            Block::Table {
+                header: ["Impact", "Manufacture", "Use", "Total"]
+                    .map(String::from)
+                    .to_vec(),
+                rows: self
+                    .impacts_rows()
+                    .into_iter()
+                    .map(|(label, manufacture, usage)| {
+                        vec![
+                            label,
+                            format!("{:.4}", manufacture),
+                            format!("{:.4}", usage),
+                            format!("{:.4}", manufacture + usage),
+                        ]
+                    })
+                    .collect(),
+            },
+        ];
+        if let Some(sci) = &self.summary.sci {
+            blocks.push(Block::Paragraph(format!(
+                "Software Carbon Intensity: {:.6} {} per {} ({} {} served)",
+                sci.sci_kgco2eq_per_unit,
+                self.units.gwp.symbol(),
+                sci.functional_unit,
+                sci.functional_unit_quantity,
+                sci.functional_unit
+            )));
+        }
+        blocks.push(Block::Heading(self.gwp_title()));
+        blocks.push(Block::Chart(self.gwp_chart()));
+        if !self.top_resources.is_empty() {
+            blocks.push(Block::Heading(self.top_resources_title()));
+            blocks.push(Block::Chart(self.top_resources_chart()));
+        }
+        blocks.push(Block::Heading("Methodology".to_string()));
+        blocks.push(Block::List(
+            METHODOLOGY_NOTES
+                .iter()
+                .map(|note| note.to_string())
+                .collect(),
+        ));
+        blocks
+    }
+}
+
+/// Escape text inserted in HTML
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Render a horizontal bar chart as inline SVG
+fn svg_bar_chart(bars: &[Bar]) -> String {
+    let max = bars.iter().map(|b| b.value).fold(0.0, f64::max);
+    let row_height = 24;
+    let mut svg = format!(
+        "<svg width=\"720\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\" font-size=\"12\">",
+        bars.len() * row_height
+    );
+    for (i, bar) in bars.iter().enumerate() {
+        let y = i * row_height;
+        let width = if max > 0.0 {
+            (bar.value / max * 380.0).round()
+        } else {
+            0.0
+        };
+        svg.push_str(&format!(
+            "<text x=\"0\" y=\"{}\">{}</text><rect x=\"240\" y=\"{}\" width=\"{}\" height=\"16\" fill=\"#4e9a06\"/><text x=\"{}\" y=\"{}\">{:.4}</text>",
+            y + 14,
+            escape_html(&bar.label),
+            y + 2,
+            width,
+            245.0 + width,
+            y + 14,
+            bar.value
+        ));
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Renders a report as a standalone HTML page (inline style and SVG charts)
+pub fn to_html(report: &Report) -> String {
+    let title = escape_html(&report.title());
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<title>{}</title>\n", title));
+    html.push_str("<style>body{font-family:sans-serif;margin:2em;color:#222}table{border-collapse:collapse;margin-bottom:1em}td,th{border:1px solid #ccc;padding:4px 8px;text-align:left}th{background:#eee}</style>\n");
+    html.push_str("</head>\n<body>\n");
+    html.push_str(&format!("<h1>{}</h1>\n", title));
+    for block in report.blocks() {
+        match block {
+            Block::Heading(text) => html.push_str(&format!("<h2>{}</h2>\n", escape_html(&text))),
+            Block::Fields(fields) => {
+                html.push_str("<table>\n");
+                for (label, value) in fields {
+                    html.push_str(&format!(
+                        "<tr><th>{}</th><td>{}</td></tr>\n",
+                        escape_html(label),
+                        escape_html(&value)
+                    ));
+                }
+                html.push_str("</table>\n");
+            }
+            Block::Table { header, rows } => {
+                html.push_str("<table>\n<tr>");
+                for cell in header {
+                    html.push_str(&format!("<th>{}</th>", escape_html(&cell)));
+                }
+                html.push_str("</tr>\n");
+                for row in rows {
+                    html.push_str("<tr>");
+                    for cell in row {
+                        html.push_str(&format!("<td>{}</td>", escape_html(&cell)));
+                    }
+                    html.push_str("</tr>\n");
+                }
+                html.push_str("</table>\n");
+            }
+            Block::Paragraph(text) => html.push_str(&format!("<p>{}</p>\n", escape_html(&text))),
+            Block::Chart(bars) => {
+                html.push_str(&svg_bar_chart(&bars));
+                html.push('\n');
+            }
+            Block::List(items) => {
+                html.push_str("<ul>\n");
+                for item in items {
+                    html.push_str(&format!("<li>{}</li>\n", escape_html(&item)));
+                }
+                html.push_str("</ul>\n");
+            }
+        }
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Renders a report as Markdown (plain text readable, like in the body of an email)
+pub fn to_markdown(report: &Report) -> String {
+    let mut md = format!("# {}\n", report.title());
+    for block in report.blocks() {
+        match block {
+            Block::Heading(text) => md.push_str(&format!("\n## {}\n\n", text)),
+            Block::Fields(fields) => {
+                for (label, value) in fields {
+                    md.push_str(&format!("- {}: {}\n", label, value));
+                }
+            }
+            Block::Table { header, rows } => {
+                md.push_str(&format!("| {} |\n", header.join(" | ")));
+                md.push_str(&format!("|{}\n", " --- |".repeat(header.len())));
+                for row in rows {
+                    md.push_str(&format!("| {} |\n", row.join(" | ")));
+                }
+            }
+            Block::Paragraph(text) => md.push_str(&format!("\n{}\n", text)),
+            Block::Chart(bars) => {
+                for bar in bars {
+                    md.push_str(&format!("- {}: {:.4}\n", bar.label, bar.value));
+                }
+            }
+            Block::List(items) => {
+                for item in items {
+                    md.push_str(&format!("- {}\n", item));
+                }
+            }
+        }
+    }
+    md
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+    use crate::model::{CloudProvider, CloudResource, ResourceDetails};
+    use crate::usage_location::UsageLocation;
+    use chrono::TimeZone;
+
+    fn sample_report() -> Report {
+        let resource = |id: &str, gwp_use_kgco2eq: f64| CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: id.to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details: ResourceDetails::Instance {
+                    instance_type: "m6g.xlarge".to_string(),
+                    usage: None,
+                },
+                tags: Vec::new(),
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues {
+                gwp_use_kgco2eq,
+                ..Default::default()
+            }),
+            impacts_duration_hours: 1.0,
+            cost: None,
+        };
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![resource("small", 0.1), resource("<big>", 0.5)],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let metadata = ReportMetadata {
+            generated_at: Utc.with_ymd_and_hms(2024, 4, 12, 10, 15, 0).unwrap(),
+            cloud_scanner_version: "2.0.5".to_string(),
+            aws_region: "eu-west-3".to_string(),
+            boavizta_api_url: "https://api.boavizta.org".to_string(),
+            filter_tags: Vec::new(),
+        };
+        Report::new(metadata, &summary, &estimated_inventory)
+    }
+
+    #[test]
+    fn top_resources_are_sorted_by_gwp() {
+        let report = sample_report();
+        assert_eq!("<big>", report.top_resources[0].resource_id);
+        assert_eq!("small", report.top_resources[1].resource_id);
+    }
+
+    #[test]
+    fn render_report_as_html() {
+        let html = to_html(&sample_report());
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<h1>Cloud impacts report - eu-west-3</h1>"));
+        assert!(html.contains("2024-04-12 10:15:00 UTC"));
+        assert!(html.contains("&lt;big&gt; (Instance)"));
+        assert!(html.contains(METHODOLOGY_NOTES[0]));
+    }
+
+    #[test]
+    fn render_report_as_markdown() {
+        let markdown = to_markdown(&sample_report());
+        assert!(markdown.starts_with("# Cloud impacts report - eu-west-3\n"));
+        assert!(markdown.contains("- Generated at: 2024-04-12 10:15:00 UTC\n"));
+        assert!(markdown.contains("- <big> (Instance): 0.5000\n"));
+        assert!(
+            markdown.contains("| Global warming potential (kgCO2eq) | 0.0000 | 0.6000 | 0.6000 |")
+        );
+    }
+}
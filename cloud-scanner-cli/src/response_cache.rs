@@ -0,0 +1,115 @@
+//! A cache of the responses of the standalone server, so that repeated requests within the time to live (TTL) reuse the last result instead of scanning again.
+//!
+//! It protects the cloud APIs and Boavizta API from the load of frequent scrapes.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::aws_cloud_provider::AwsAccount;
+
+/// Returns the cache key of the parameters of a scan (filter tags are sorted, so that their order does not matter)
+pub fn cache_key(
+    account: Option<&AwsAccount>,
+    aws_region: &str,
+    filter_tags: &[String],
+    use_duration_hours: Option<f32>,
+    verbose: bool,
+    include_block_storage: bool,
+) -> String {
+    let mut tags = filter_tags.to_vec();
+    tags.sort();
+    format!(
+        "{}|{}|{}|{:?}|{}|{}",
+        account
+            .map(|account| account.role_arn.as_str())
+            .unwrap_or_default(),
+        aws_region,
+        tags.join(","),
+        use_duration_hours,
+        verbose,
+        include_block_storage
+    )
+}
+
+/// Results of requests, by parameters of the request. Disabled when the TTL is zero.
+#[derive(Clone)]
+pub struct ResponseCache<V> {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<String, (Instant, V)>>>,
+}
+
+impl<V: Clone> ResponseCache<V> {
+    pub fn new(ttl: Duration) -> Self {
+        ResponseCache {
+            ttl,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the value cached for the key, if it is younger than the TTL
+    pub fn get(&self, key: &str) -> Option<V> {
+        if self.ttl.is_zero() {
+            return None;
+        }
+        let entries = self.entries.lock().unwrap();
+        let value = entries
+            .get(key)
+            .filter(|(cached_at, _)| cached_at.elapsed() < self.ttl)
+            .map(|(_, value)| value.clone());
+        crate::server_telemetry::record_cache_lookup(value.is_some());
+        value
+    }
+
+    /// Caches a value, and forgets the expired ones
+    pub fn insert(&self, key: String, value: V) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (cached_at, _)| cached_at.elapsed() < self.ttl);
+        entries.insert(key, (Instant::now(), value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_expire_after_ttl() {
+        let cache: ResponseCache<String> = ResponseCache::new(Duration::from_millis(50));
+        let key = cache_key(None, "eu-west-3", &[], Some(1.0), false, false);
+        assert_eq!(None, cache.get(&key));
+        cache.insert(key.clone(), "metrics".to_string());
+        assert_eq!(Some("metrics".to_string()), cache.get(&key));
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(None, cache.get(&key));
+
+        let disabled: ResponseCache<String> = ResponseCache::new(Duration::ZERO);
+        disabled.insert(key.clone(), "metrics".to_string());
+        assert_eq!(None, disabled.get(&key));
+    }
+
+    #[test]
+    fn key_does_not_depend_on_tags_order() {
+        let tags = ["b=2".to_string(), "a=1".to_string()];
+        let sorted = ["a=1".to_string(), "b=2".to_string()];
+        assert_eq!(
+            cache_key(None, "eu-west-3", &tags, None, false, true),
+            cache_key(None, "eu-west-3", &sorted, None, false, true)
+        );
+        assert_ne!(
+            cache_key(None, "eu-west-3", &tags, None, false, true),
+            cache_key(None, "eu-west-1", &tags, None, false, true)
+        );
+        let account = AwsAccount {
+            account_id: "111111111111".to_string(),
+            role_arn: "arn:aws:iam::111111111111:role/cloud-scanner".to_string(),
+            external_id: None,
+        };
+        assert_ne!(
+            cache_key(None, "eu-west-3", &tags, None, false, true),
+            cache_key(Some(&account), "eu-west-3", &tags, None, false, true)
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! Pagination and field selection of the lists of resources returned by the standalone server, so that clients can fetch only what they display.
+//!
+//! Fields are json keys of the resources, nested keys are separated by dots (like `cloud_resource.id`).
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Number of resources of a page, when only the page number is requested
+pub const DEFAULT_PER_PAGE: usize = 100;
+
+/// Page and fields requested by a client (everything is returned when nothing is requested)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PageRequest {
+    /// Number of the page, starting at 1
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+    pub fields: Vec<String>,
+}
+
+impl PageRequest {
+    /// Reads the query parameters, fields can be repeated or separated by commas
+    pub fn new(page: Option<usize>, per_page: Option<usize>, fields: Option<Vec<String>>) -> Self {
+        let fields = fields
+            .unwrap_or_default()
+            .iter()
+            .flat_map(|field| field.split(','))
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(str::to_string)
+            .collect();
+        PageRequest {
+            page,
+            per_page,
+            fields,
+        }
+    }
+
+    fn is_paginated(&self) -> bool {
+        self.page.is_some() || self.per_page.is_some()
+    }
+}
+
+/// Position of a page in the whole list
+#[derive(Clone, Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Pagination {
+    pub page: usize,
+    pub per_page: usize,
+    pub total_count: usize,
+    pub total_pages: usize,
+}
+
+/// Copies the value at a dotted path of the source into the target (nothing when the path does not exist)
+fn copy_field(source: &Value, target: &mut Map<String, Value>, path: &str) {
+    let Some((key, rest)) = path.split_once('.') else {
+        if let Some(value) = source.get(path) {
+            target.insert(path.to_string(), value.clone());
+        }
+        return;
+    };
+    let Some(nested) = source.get(key) else {
+        return;
+    };
+    let entry = target
+        .entry(key.to_string())
+        .or_insert_with(|| Value::Object(Map::new()));
+    if let Value::Object(nested_target) = entry {
+        copy_field(nested, nested_target, rest);
+    }
+}
+
+/// Returns an item restricted to the fields
+fn select_fields(item: &Value, fields: &[String]) -> Value {
+    let mut selected = Map::new();
+    for field in fields {
+        copy_field(item, &mut selected, field);
+    }
+    Value::Object(selected)
+}
+
+/// Returns the json of a document whose list `list_key` is restricted to the requested page and fields.
+///
+/// A `pagination` object is added to the document when a page is requested.
+pub fn paginate<T: Serialize>(
+    document: &T,
+    list_key: &str,
+    request: &PageRequest,
+) -> Result<Value> {
+    let mut json = serde_json::to_value(document).context("Cannot serialize response")?;
+    let object = json
+        .as_object_mut()
+        .context("Response is not a json object")?;
+    let Some(Value::Array(items)) = object.get_mut(list_key) else {
+        anyhow::bail!("Response has no list {}", list_key);
+    };
+    if request.is_paginated() {
+        let page = request.page.unwrap_or(1).max(1);
+        let per_page = request.per_page.unwrap_or(DEFAULT_PER_PAGE).max(1);
+        let pagination = Pagination {
+            page,
+            per_page,
+            total_count: items.len(),
+            total_pages: items.len().div_ceil(per_page),
+        };
+        *items = items
+            .drain(..)
+            .skip((page - 1).saturating_mul(per_page))
+            .take(per_page)
+            .collect();
+        object.insert("pagination".to_string(), serde_json::to_value(pagination)?);
+    }
+    if !request.fields.is_empty() {
+        if let Some(Value::Array(items)) = object.get_mut(list_key) {
+            for item in items.iter_mut() {
+                *item = select_fields(item, &request.fields);
+            }
+        }
+    }
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn document() -> Value {
+        let resources: Vec<Value> = (1..=5)
+            .map(|i| json!({"cloud_resource": {"id": format!("i-{}", i), "location": "eu-west-3"}, "impacts_values": null}))
+            .collect();
+        json!({"impactingResources": resources, "executionStatistics": null})
+    }
+
+    #[test]
+    fn return_everything_when_nothing_is_requested() {
+        let page = paginate(&document(), "impactingResources", &PageRequest::default()).unwrap();
+        assert_eq!(document(), page);
+        assert!(paginate(&document(), "resources", &PageRequest::default()).is_err());
+    }
+
+    #[test]
+    fn return_requested_page() {
+        let request = PageRequest::new(Some(2), Some(2), None);
+        let page = paginate(&document(), "impactingResources", &request).unwrap();
+        let resources = page["impactingResources"].as_array().unwrap();
+        assert_eq!(2, resources.len());
+        assert_eq!("i-3", resources[0]["cloud_resource"]["id"]);
+        assert_eq!(
+            json!({"page": 2, "perPage": 2, "totalCount": 5, "totalPages": 3}),
+            page["pagination"]
+        );
+
+        let request = PageRequest::new(Some(4), Some(2), None);
+        let page = paginate(&document(), "impactingResources", &request).unwrap();
+        assert!(page["impactingResources"].as_array().unwrap().is_empty());
+
+        let request = PageRequest::new(Some(1), None, None);
+        let page = paginate(&document(), "impactingResources", &request).unwrap();
+        assert_eq!(DEFAULT_PER_PAGE, page["pagination"]["perPage"]);
+    }
+
+    #[test]
+    fn return_selected_fields() {
+        let request = PageRequest::new(
+            None,
+            None,
+            Some(vec![
+                "cloud_resource.id,impacts_values".to_string(),
+                "unknown".to_string(),
+            ]),
+        );
+        assert_eq!(
+            vec!["cloud_resource.id", "impacts_values", "unknown"],
+            request.fields
+        );
+        let page = paginate(&document(), "impactingResources", &request).unwrap();
+        assert_eq!(
+            json!({"cloud_resource": {"id": "i-1"}, "impacts_values": null}),
+            page["impactingResources"][0]
+        );
+        assert!(page.get("pagination").is_none());
+    }
+}
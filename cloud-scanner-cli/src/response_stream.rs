@@ -0,0 +1,139 @@
+//! Streaming of the large responses of the standalone server as NDJSON (one json object per line, sent in chunks), instead of buffering whole documents.
+//!
+//! A response is produced by a future writing into a buffer: the future is only polled when the chunks it already wrote are sent, so that a slow client slows down the production of its response (and the scan behind it) instead of growing the memory of the server.
+use anyhow::Result;
+use rocket::futures::future::BoxFuture;
+use rocket::futures::stream::{self, Stream};
+use rocket::futures::FutureExt;
+use rocket::http::ContentType;
+use serde::Serialize;
+use std::future::{poll_fn, Future};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+
+use crate::impact_provider::CloudResourceWithImpacts;
+use crate::result_stream::ResultStream;
+
+/// Number of resources written between two chunks of results that are already known (cached or listed)
+pub(crate) const RESOURCES_PER_CHUNK: usize = 100;
+
+/// Content type of NDJSON responses
+pub fn ndjson() -> ContentType {
+    ContentType::new("application", "x-ndjson")
+}
+
+/// A buffer of the chunks of a response, written by its producer and taken by the response
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the chunks written to `buffer` by `producer`, polling the producer only once its previous chunks are sent.
+///
+/// An error of the producer ends the response with an `{"error":...}` line (the status of the response being already sent).
+pub fn chunks<F>(buffer: SharedBuffer, producer: F) -> impl Stream<Item = Vec<u8>> + Send
+where
+    F: Future<Output = Result<()>> + Send + 'static,
+{
+    let producer: BoxFuture<'static, Result<()>> = producer.boxed();
+    stream::unfold(Some(producer), move |producer| {
+        let buffer = buffer.clone();
+        async move {
+            let mut producer = producer?;
+            let finished = poll_fn(|cx| match producer.as_mut().poll(cx) {
+                Poll::Ready(result) => Poll::Ready(Some(result)),
+                Poll::Pending if buffer.is_empty() => Poll::Pending,
+                // Written chunks are sent before producing more
+                Poll::Pending => Poll::Ready(None),
+            })
+            .await;
+            match finished {
+                None => Some((buffer.take(), Some(producer))),
+                Some(result) => {
+                    if let Err(e) = result {
+                        warn!("Cannot stream response: {:#}", e);
+                        let error = serde_json::json!({ "error": format!("{:#}", e) });
+                        let _ = writeln!(buffer.clone(), "{}", error);
+                    }
+                    Some((buffer.take(), None))
+                }
+            }
+        }
+    })
+}
+
+/// Writes resources with their impacts that are already known, chunk by chunk
+pub async fn write_resources_with_impacts(
+    result_stream: &ResultStream<SharedBuffer>,
+    resources_with_impacts: &[CloudResourceWithImpacts],
+) -> Result<()> {
+    for chunk in resources_with_impacts.chunks(RESOURCES_PER_CHUNK) {
+        result_stream.write_page(chunk)?;
+        tokio::task::yield_now().await;
+    }
+    Ok(())
+}
+
+/// Writes items (like the resources of an inventory) as json lines, chunk by chunk
+pub async fn write_lines<T: Serialize>(mut buffer: SharedBuffer, items: &[T]) -> Result<()> {
+    for chunk in items.chunks(RESOURCES_PER_CHUNK) {
+        for item in chunk {
+            serde_json::to_writer(&mut buffer, item)?;
+            buffer.write_all(b"\n")?;
+        }
+        tokio::task::yield_now().await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::futures::StreamExt;
+
+    #[tokio::test]
+    async fn producers_wait_for_chunks_to_be_sent() {
+        let buffer = SharedBuffer::default();
+        let written = Arc::new(Mutex::new(0));
+        let producer = {
+            let (mut buffer, written) = (buffer.clone(), written.clone());
+            async move {
+                for line in 0..3 {
+                    writeln!(buffer, "{}", line)?;
+                    *written.lock().unwrap() += 1;
+                    tokio::task::yield_now().await;
+                }
+                anyhow::bail!("Cannot scan")
+            }
+        };
+        let mut response = Box::pin(chunks(buffer, producer));
+        assert_eq!(b"0\n".to_vec(), response.next().await.unwrap());
+        // Nothing more is produced until the next chunk is requested
+        assert_eq!(1, *written.lock().unwrap());
+        assert_eq!(b"1\n".to_vec(), response.next().await.unwrap());
+        assert_eq!(b"2\n".to_vec(), response.next().await.unwrap());
+        let last = String::from_utf8(response.next().await.unwrap()).unwrap();
+        assert_eq!("{\"error\":\"Cannot scan\"}\n", last);
+        assert!(response.next().await.is_none());
+    }
+}
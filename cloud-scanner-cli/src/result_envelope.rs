@@ -0,0 +1,179 @@
+//! A module to wrap results in a metadata envelope, so that results stored over months remain interpretable.
+//!
+//! The envelope records the versions of cloud scanner, of the result schema and of the impact provider, the time of the scan and the parameters used.
+//! Results written before the envelope existed (bare results) can still be read with [read_results].
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::units::OutputUnits;
+
+/// Version of the format of the results, incremented on breaking changes
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Parameters of the scan that produced results
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct ScanParameters {
+    pub aws_region: String,
+    /// Duration of use considered to estimate impacts (not set for inventories)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub use_duration_hours: Option<f32>,
+    pub filter_tags: Vec<String>,
+    pub include_block_storage: bool,
+    /// States of the inventoried instances, all states when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_states: Vec<String>,
+    #[serde(default)]
+    pub verbose: bool,
+}
+
+/// The service used to estimate impacts
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct ImpactProviderMetadata {
+    pub name: String,
+    pub url: String,
+    /// Version of the API, when the provider reports it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// Metadata describing how results were produced
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct ResultMetadata {
+    pub schema_version: u32,
+    pub cloud_scanner_version: String,
+    /// Cloud provider of the resources (like `aws`)
+    pub provider: String,
+    /// Not set for inventories (no impacts)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impact_provider: Option<ImpactProviderMetadata>,
+    pub scan_timestamp: DateTime<Utc>,
+    pub parameters: ScanParameters,
+    /// Units of the impacts, kgCO2eq and MJ when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub units: Option<OutputUnits>,
+}
+
+impl ResultMetadata {
+    /// Returns the metadata of a scan of AWS started at `scan_timestamp`
+    pub fn new(
+        scan_timestamp: DateTime<Utc>,
+        parameters: ScanParameters,
+        impact_provider: Option<ImpactProviderMetadata>,
+    ) -> Self {
+        ResultMetadata {
+            schema_version: SCHEMA_VERSION,
+            cloud_scanner_version: crate::get_version(),
+            provider: "aws".to_string(),
+            impact_provider,
+            scan_timestamp,
+            parameters,
+            units: None,
+        }
+    }
+
+    /// Records the units of the impacts of the results
+    pub fn with_units(mut self, units: OutputUnits) -> Self {
+        self.units = Some(units);
+        self
+    }
+}
+
+/// Results with the metadata describing how they were produced
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ResultEnvelope<T> {
+    pub metadata: ResultMetadata,
+    pub data: T,
+}
+
+impl<T: Serialize> ResultEnvelope<T> {
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).context("Cannot format results as json")
+    }
+}
+
+/// Results as written by the current or by previous versions of cloud scanner
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+// Results are only read to be unwrapped, the size of the variants does not matter
+#[allow(clippy::large_enum_variant)]
+pub enum VersionedResults<T> {
+    Enveloped(ResultEnvelope<T>),
+    /// Results written before the envelope existed
+    Bare(T),
+}
+
+/// Reads results (json), with or without envelope.
+///
+/// Returns `None` as metadata for results written before the envelope existed.
+pub fn read_results<T: DeserializeOwned>(json: &str) -> Result<(Option<ResultMetadata>, T)> {
+    let results: VersionedResults<T> = serde_json::from_str(json)?;
+    Ok(results.into_parts())
+}
+
+impl<T> VersionedResults<T> {
+    /// Returns the metadata (`None` for results written before the envelope existed) and the results
+    pub fn into_parts(self) -> (Option<ResultMetadata>, T) {
+        match self {
+            VersionedResults::Enveloped(envelope) => {
+                if envelope.metadata.schema_version > SCHEMA_VERSION {
+                    warn!(
+                    "Results use schema version {} which is more recent than the version supported by this cloud scanner ({}), some fields may be ignored",
+                    envelope.metadata.schema_version, SCHEMA_VERSION
+                );
+                }
+                (Some(envelope.metadata), envelope.data)
+            }
+            VersionedResults::Bare(data) => (None, data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::EstimatedInventory;
+    use chrono::TimeZone;
+
+    #[test]
+    fn read_results_with_or_without_envelope() {
+        let inventory = EstimatedInventory {
+            impacting_resources: vec![],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let metadata = ResultMetadata::new(
+            Utc.with_ymd_and_hms(2024, 4, 12, 10, 15, 0).unwrap(),
+            ScanParameters {
+                aws_region: "eu-west-3".to_string(),
+                use_duration_hours: Some(1.0),
+                ..Default::default()
+            },
+            Some(ImpactProviderMetadata {
+                name: "Boavizta API".to_string(),
+                url: "https://api.boavizta.org".to_string(),
+                version: None,
+            }),
+        );
+        let json = ResultEnvelope {
+            metadata: metadata.clone(),
+            data: inventory.clone(),
+        }
+        .to_json()
+        .unwrap();
+        assert!(json.starts_with(r#"{"metadata":{"schema_version":1,"#));
+        assert!(json.contains(r#""scan_timestamp":"2024-04-12T10:15:00Z""#));
+
+        let (read_metadata, _): (_, EstimatedInventory) = read_results(&json).unwrap();
+        assert_eq!(Some(metadata), read_metadata);
+
+        let bare = serde_json::to_string(&inventory).unwrap();
+        let (read_metadata, read): (_, EstimatedInventory) = read_results(&bare).unwrap();
+        assert_eq!(None, read_metadata);
+        assert!(read.impacting_resources.is_empty());
+
+        assert!(read_results::<EstimatedInventory>("[]").is_err());
+    }
+}
@@ -0,0 +1,155 @@
+//! Sinks of the results of scans: a [ResultExporter] is handed the metadata, the resources with their impacts and the summary of each scan, to write them in any format to any destination. The built-in json, CSV and metrics outputs are implemented on it, and library users (see [crate::scanner]) or plugins (see [crate::plugins::Plugin::result_exporter]) add their own sinks the same way.
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::impact_provider::ImpactsSummary;
+use crate::metric_exporter::MetricOptions;
+use crate::model::EstimatedInventory;
+use crate::output_exporter::OutputExporter;
+use crate::result_envelope::ResultMetadata;
+
+/// A trait to implement to add a sink of the results of scans.
+#[async_trait]
+pub trait ResultExporter {
+    /// Exports the results of a scan, returns a description of the location where they were exported (like `stdout`, a path or a URL).
+    async fn export_results(
+        &self,
+        metadata: &ResultMetadata,
+        estimated_inventory: &EstimatedInventory,
+        summary: &ImpactsSummary,
+    ) -> Result<String>;
+}
+
+/// Writes the json output of the estimate command (or only its summary) to an output
+pub struct JsonResultExporter {
+    pub summary_only: bool,
+    pub output: Box<dyn OutputExporter + Send + Sync>,
+}
+
+#[async_trait]
+impl ResultExporter for JsonResultExporter {
+    async fn export_results(
+        &self,
+        metadata: &ResultMetadata,
+        estimated_inventory: &EstimatedInventory,
+        summary: &ImpactsSummary,
+    ) -> Result<String> {
+        let json = crate::impacts_to_json_string(
+            metadata,
+            estimated_inventory,
+            summary,
+            self.summary_only,
+        )?;
+        self.output.export(json.into_bytes(), "json").await
+    }
+}
+
+/// Writes one CSV row per resource to an output
+pub struct CsvResultExporter {
+    pub output: Box<dyn OutputExporter + Send + Sync>,
+}
+
+#[async_trait]
+impl ResultExporter for CsvResultExporter {
+    async fn export_results(
+        &self,
+        _metadata: &ResultMetadata,
+        estimated_inventory: &EstimatedInventory,
+        _summary: &ImpactsSummary,
+    ) -> Result<String> {
+        let csv = crate::impacts_to_csv(estimated_inventory)?;
+        self.output.export(csv.into_bytes(), "csv").await
+    }
+}
+
+/// Writes Prometheus metrics (summary, and the metrics of resources selected by the options) to an output
+pub struct MetricsResultExporter {
+    pub options: MetricOptions,
+    pub output: Box<dyn OutputExporter + Send + Sync>,
+}
+
+#[async_trait]
+impl ResultExporter for MetricsResultExporter {
+    async fn export_results(
+        &self,
+        _metadata: &ResultMetadata,
+        estimated_inventory: &EstimatedInventory,
+        summary: &ImpactsSummary,
+    ) -> Result<String> {
+        let metrics =
+            crate::impacts_to_metrics_of_tenant(estimated_inventory, summary, None, &self.options)?;
+        self.output.export(metrics.into_bytes(), "prom").await
+    }
+}
+
+/// Writes InfluxDB line protocol (summary and one point per resource) to an output
+pub struct LineProtocolResultExporter {
+    pub output: Box<dyn OutputExporter + Send + Sync>,
+}
+
+#[async_trait]
+impl ResultExporter for LineProtocolResultExporter {
+    async fn export_results(
+        &self,
+        _metadata: &ResultMetadata,
+        estimated_inventory: &EstimatedInventory,
+        summary: &ImpactsSummary,
+    ) -> Result<String> {
+        let lines = crate::impacts_to_line_protocol(estimated_inventory, summary)?;
+        self.output.export(lines.into_bytes(), "txt").await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output_exporter::FileExporter;
+    use crate::result_envelope::ScanParameters;
+
+    #[tokio::test]
+    async fn built_in_outputs_are_result_exporters() {
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: Vec::new(),
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = crate::build_summary_of_accounts(
+            &estimated_inventory,
+            &[],
+            &["eu-west-3".to_string()],
+            &730.0,
+        )
+        .unwrap();
+        let metadata =
+            crate::scan_metadata(chrono::Utc::now(), ScanParameters::default(), None).await;
+        let dir = std::env::temp_dir();
+        let json_path = dir.join(format!("cloud-scanner-results-{}.json", std::process::id()));
+        let metrics_path = dir.join(format!("cloud-scanner-results-{}.prom", std::process::id()));
+        let exporters: Vec<Box<dyn ResultExporter + Send + Sync>> = vec![
+            Box::new(JsonResultExporter {
+                summary_only: true,
+                output: Box::new(FileExporter {
+                    path: json_path.clone(),
+                }),
+            }),
+            Box::new(MetricsResultExporter {
+                options: MetricOptions::default(),
+                output: Box::new(FileExporter {
+                    path: metrics_path.clone(),
+                }),
+            }),
+        ];
+        for exporter in exporters.iter() {
+            exporter
+                .export_results(&metadata, &estimated_inventory, &summary)
+                .await
+                .unwrap();
+        }
+        let json = std::fs::read_to_string(&json_path).unwrap();
+        assert!(json.contains("\"number_of_resources_total\":0"));
+        let metrics = std::fs::read_to_string(&metrics_path).unwrap();
+        assert!(metrics.contains("boavizta_number_of_resources_total"));
+        std::fs::remove_file(json_path).unwrap();
+        std::fs::remove_file(metrics_path).unwrap();
+    }
+}
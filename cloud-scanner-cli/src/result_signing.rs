@@ -0,0 +1,383 @@
+//! Signatures of result files, so that auditors can check that results were produced by a given scanner and were not modified since.
+//!
+//! Results are signed with an Ed25519 key (generated with `signing generate-key`). The signature is written next to the results (`results.json.sig`): a json file with a statement (the SHA-256 digest of the result file, the version of cloud scanner and the metadata of the scan, like its parameters), the public key and the signature of the statement.
+//! The statement is kept as the exact string that was signed, so that it can be verified without depending on how json is formatted.
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use crate::result_envelope::ResultMetadata;
+
+/// Algorithm of the signatures
+pub const ALGORITHM: &str = "ed25519";
+
+/// Extension of the signature files, added to the name of the result file
+pub const SIGNATURE_EXTENSION: &str = "sig";
+
+/// What is signed: the digest of a result file and how the results were produced
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SignedStatement {
+    /// Name of the result file (without its directory)
+    pub file_name: String,
+    /// SHA-256 digest of the result file (hex)
+    pub sha256: String,
+    pub signed_at: DateTime<Utc>,
+    pub cloud_scanner_version: String,
+    /// Metadata of the scan (version of the impact provider, parameters...), when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<ResultMetadata>,
+}
+
+/// Content of a signature file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResultSignature {
+    pub algorithm: String,
+    /// Public key of the signing key (hex)
+    pub public_key: String,
+    /// The signed statement (json)
+    pub statement: String,
+    /// Signature of the statement (hex)
+    pub signature: String,
+}
+
+/// A private key signing results
+pub struct SigningKey {
+    key_pair: Ed25519KeyPair,
+}
+
+impl SigningKey {
+    /// Generates a new key, returns it with its PKCS#8 document (to save as the private key file)
+    pub fn generate() -> Result<(Self, Vec<u8>)> {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|_| anyhow::anyhow!("Cannot generate a signing key"))?;
+        let key = SigningKey::from_pkcs8(pkcs8.as_ref())?;
+        Ok((key, pkcs8.as_ref().to_vec()))
+    }
+
+    pub fn from_pkcs8(pkcs8: &[u8]) -> Result<Self> {
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(pkcs8)
+            .map_err(|e| anyhow::anyhow!("Invalid Ed25519 key (PKCS#8 expected): {}", e))?;
+        Ok(SigningKey { key_pair })
+    }
+
+    /// Reads a private key file (PKCS#8, as written by `signing generate-key`)
+    pub fn from_file(path: &str) -> Result<Self> {
+        let pkcs8 =
+            std::fs::read(path).with_context(|| format!("Cannot read signing key {}", path))?;
+        SigningKey::from_pkcs8(&pkcs8).with_context(|| format!("Cannot load signing key {}", path))
+    }
+
+    /// Returns the public key (hex), to give to the auditors
+    pub fn public_key(&self) -> String {
+        hex::encode(self.key_pair.public_key().as_ref())
+    }
+
+    /// Signs the content of a result file
+    pub fn sign(
+        &self,
+        file_name: &str,
+        content: &[u8],
+        metadata: Option<&ResultMetadata>,
+        signed_at: DateTime<Utc>,
+    ) -> Result<ResultSignature> {
+        let statement = SignedStatement {
+            file_name: file_name.to_string(),
+            sha256: hex::encode(Sha256::digest(content)),
+            signed_at,
+            cloud_scanner_version: crate::get_version(),
+            metadata: metadata.cloned(),
+        };
+        let statement = serde_json::to_string(&statement)?;
+        let signature = self.key_pair.sign(statement.as_bytes());
+        Ok(ResultSignature {
+            algorithm: ALGORITHM.to_string(),
+            public_key: self.public_key(),
+            statement,
+            signature: hex::encode(signature.as_ref()),
+        })
+    }
+
+    /// Signs a result file, writes the signature next to it (like `results.json.sig`), returns the path of the signature
+    pub fn sign_file(&self, path: &Path, metadata: Option<&ResultMetadata>) -> Result<PathBuf> {
+        let content = std::fs::read(path)
+            .with_context(|| format!("Cannot read results {} to sign them", path.display()))?;
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let signature = self.sign(&file_name, &content, metadata, Utc::now())?;
+        let signature_path = signature_path_of(path);
+        std::fs::write(&signature_path, serde_json::to_string_pretty(&signature)?)
+            .with_context(|| format!("Cannot write signature {}", signature_path.display()))?;
+        info!("Results signed in {}", signature_path.display());
+        Ok(signature_path)
+    }
+}
+
+/// Generates a signing key, writes the private key to a file (readable by its owner only on Unix) and the public key (hex) next to it with the `.pub` extension. Returns the public key.
+pub fn generate_key_file(path: &str, force: bool) -> Result<String> {
+    if !force && Path::new(path).exists() {
+        anyhow::bail!("{} already exists (use --force to replace it)", path);
+    }
+    let (key, pkcs8) = SigningKey::generate()?;
+    write_private_key(path, &pkcs8)?;
+    let public_key = key.public_key();
+    let public_key_path = format!("{}.pub", path);
+    std::fs::write(&public_key_path, format!("{}\n", public_key))
+        .with_context(|| format!("Cannot write public key {}", public_key_path))?;
+    Ok(public_key)
+}
+
+#[cfg(unix)]
+fn write_private_key(path: &str, pkcs8: &[u8]) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(pkcs8))
+        .with_context(|| format!("Cannot write signing key {}", path))
+}
+
+#[cfg(not(unix))]
+fn write_private_key(path: &str, pkcs8: &[u8]) -> Result<()> {
+    std::fs::write(path, pkcs8).with_context(|| format!("Cannot write signing key {}", path))
+}
+
+/// Returns the path of the signature of a result file (like `results.json.sig`)
+pub fn signature_path_of(path: &Path) -> PathBuf {
+    let mut signature_path = path.as_os_str().to_owned();
+    signature_path.push(".");
+    signature_path.push(SIGNATURE_EXTENSION);
+    PathBuf::from(signature_path)
+}
+
+/// Reads a public key given as hex, or as the path of a file containing it (like the `.pub` file of `signing generate-key`)
+pub fn read_public_key(key_or_path: &str) -> Result<String> {
+    let key = match std::fs::read_to_string(key_or_path) {
+        Ok(content) => content.trim().to_string(),
+        Err(_) => key_or_path.trim().to_string(),
+    };
+    let bytes = hex::decode(&key).with_context(|| {
+        format!(
+            "Invalid public key {} (hex, or the path of a .pub file, expected)",
+            key_or_path
+        )
+    })?;
+    anyhow::ensure!(
+        bytes.len() == 32,
+        "Invalid public key {} (32 bytes expected)",
+        key_or_path
+    );
+    Ok(key.to_lowercase())
+}
+
+/// Verifies the signature of the content of a result file with the public key of the signer (hex), returns the signed statement
+pub fn verify(
+    content: &[u8],
+    signature: &ResultSignature,
+    public_key: &str,
+) -> Result<SignedStatement> {
+    anyhow::ensure!(
+        signature.algorithm == ALGORITHM,
+        "Unsupported signature algorithm {} ({} expected)",
+        signature.algorithm,
+        ALGORITHM
+    );
+    anyhow::ensure!(
+        signature.public_key.eq_ignore_ascii_case(public_key),
+        "Results were signed with another key ({})",
+        signature.public_key
+    );
+    let public_key = hex::decode(public_key).context("Invalid public key")?;
+    let signature_bytes = hex::decode(&signature.signature).context("Invalid signature")?;
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(signature.statement.as_bytes(), &signature_bytes)
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "Invalid signature: the statement was modified or was not signed with this key"
+            )
+        })?;
+    let statement: SignedStatement =
+        serde_json::from_str(&signature.statement).context("Invalid signed statement")?;
+    let digest = hex::encode(Sha256::digest(content));
+    anyhow::ensure!(
+        statement.sha256 == digest,
+        "Results were modified since they were signed (SHA-256 {} instead of {})",
+        digest,
+        statement.sha256
+    );
+    Ok(statement)
+}
+
+/// Verifies a result file with its signature file (the file with the `.sig` extension by default), returns the signed statement
+pub fn verify_file(
+    path: &str,
+    signature_path: Option<&str>,
+    public_key: &str,
+) -> Result<SignedStatement> {
+    let signature_path = match signature_path {
+        Some(signature_path) => PathBuf::from(signature_path),
+        None => signature_path_of(Path::new(path)),
+    };
+    let signature: ResultSignature = serde_json::from_str(
+        &std::fs::read_to_string(&signature_path)
+            .with_context(|| format!("Cannot read signature {}", signature_path.display()))?,
+    )
+    .with_context(|| format!("Invalid signature file {}", signature_path.display()))?;
+    let content = std::fs::read(path).with_context(|| format!("Cannot read results {}", path))?;
+    verify(&content, &signature, public_key)
+        .with_context(|| format!("Cannot verify results {}", path))
+}
+
+impl SignedStatement {
+    /// Returns a human-readable description of the verified results
+    pub fn to_text(&self) -> Result<String> {
+        let mut text = String::new();
+        writeln!(text, "Signature valid for {}", self.file_name)?;
+        writeln!(text, "  SHA-256: {}", self.sha256)?;
+        writeln!(text, "  Signed at: {}", self.signed_at.to_rfc3339())?;
+        writeln!(
+            text,
+            "  Cloud scanner version: {}",
+            self.cloud_scanner_version
+        )?;
+        if let Some(metadata) = &self.metadata {
+            writeln!(
+                text,
+                "  Scanned at: {}",
+                metadata.scan_timestamp.to_rfc3339()
+            )?;
+            if let Some(impact_provider) = &metadata.impact_provider {
+                writeln!(
+                    text,
+                    "  Impact provider: {} ({}{})",
+                    impact_provider.name,
+                    impact_provider.url,
+                    impact_provider
+                        .version
+                        .as_ref()
+                        .map(|version| format!(", version {}", version))
+                        .unwrap_or_default()
+                )?;
+            }
+            let parameters = &metadata.parameters;
+            writeln!(text, "  Region: {}", parameters.aws_region)?;
+            if let Some(hours) = parameters.use_duration_hours {
+                writeln!(text, "  Use duration: {} hours", hours)?;
+            }
+            if !parameters.filter_tags.is_empty() {
+                writeln!(text, "  Tag filters: {}", parameters.filter_tags.join(", "))?;
+            }
+            writeln!(
+                text,
+                "  Block storage included: {}",
+                parameters.include_block_storage
+            )?;
+        }
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::result_envelope::ScanParameters;
+    use chrono::TimeZone;
+
+    #[test]
+    fn signed_results_are_verified_with_the_public_key() {
+        let (key, pkcs8) = SigningKey::generate().unwrap();
+        let public_key = key.public_key();
+        assert_eq!(64, public_key.len());
+        assert_eq!(
+            public_key,
+            SigningKey::from_pkcs8(&pkcs8).unwrap().public_key()
+        );
+
+        let at = Utc.with_ymd_and_hms(2024, 5, 2, 8, 0, 0).unwrap();
+        let metadata = ResultMetadata::new(
+            at,
+            ScanParameters {
+                aws_region: "eu-west-3".to_string(),
+                use_duration_hours: Some(720.0),
+                ..Default::default()
+            },
+            None,
+        );
+        let results = br#"{"impacting_resources":[]}"#;
+        let signature = key
+            .sign("results.json", results, Some(&metadata), at)
+            .unwrap();
+        let statement = verify(results, &signature, &public_key).unwrap();
+        assert_eq!("results.json", statement.file_name);
+        assert_eq!(Some(metadata), statement.metadata);
+        assert!(statement
+            .to_text()
+            .unwrap()
+            .contains("Use duration: 720 hours"));
+
+        // Modified results
+        let error = verify(br#"{"impacting_resources":[1]}"#, &signature, &public_key)
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("Results were modified"), "{}", error);
+
+        // Another key
+        let (other_key, _) = SigningKey::generate().unwrap();
+        assert!(verify(results, &signature, &other_key.public_key()).is_err());
+        let forged = ResultSignature {
+            public_key: other_key.public_key(),
+            ..signature.clone()
+        };
+        assert!(verify(results, &forged, &other_key.public_key()).is_err());
+
+        // Modified statement
+        let tampered = ResultSignature {
+            statement: signature.statement.replace("720.0", "72.0"),
+            ..signature
+        };
+        assert_ne!(signature.statement, tampered.statement);
+        let error = verify(results, &tampered, &public_key)
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("Invalid signature"), "{}", error);
+    }
+
+    #[test]
+    fn result_files_are_signed_next_to_them() {
+        let dir =
+            std::env::temp_dir().join(format!("cloud-scanner-signing-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let key_path = dir.join("signing.key").display().to_string();
+        let public_key = generate_key_file(&key_path, false).unwrap();
+        assert!(generate_key_file(&key_path, false).is_err());
+        assert_eq!(
+            public_key,
+            read_public_key(&format!("{}.pub", key_path)).unwrap()
+        );
+        assert_eq!(public_key, read_public_key(&public_key).unwrap());
+        assert!(read_public_key("not a key").is_err());
+
+        let results = dir.join("results.json");
+        std::fs::write(&results, "{}").unwrap();
+        let key = SigningKey::from_file(&key_path).unwrap();
+        let signature_path = key.sign_file(&results, None).unwrap();
+        assert_eq!(dir.join("results.json.sig"), signature_path);
+        let results = results.display().to_string();
+        let statement = verify_file(&results, None, &public_key).unwrap();
+        assert_eq!(None, statement.metadata);
+
+        std::fs::write(&results, "{ }").unwrap();
+        assert!(verify_file(&results, None, &public_key).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,645 @@
+//! A SQLite store that keeps the history of scans (summary and per-resource impacts).
+//!
+//! Each scan is persisted with its timestamp. Tables can be queried directly with `SELECT` statements:
+//! - `scans`: one row per scan with the summary columns and the full estimated inventory as json
+//! - `scan_resources`: one row per resource of a scan with its impacts
+//! - `scan_aggregates`: the impacts of the scans purged by the retention policy, aggregated over periods (see [crate::retention])
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::impact_provider::ImpactsSummary;
+use crate::model::EstimatedInventory;
+use crate::scan_aggregation::AggregatedImpacts;
+
+/// Schema migrations, applied in order. The index of a migration + 1 is the schema version it produces.
+///
+/// ⚠ Never modify an existing migration, always append a new one.
+const MIGRATIONS: &[&str] = &[
+    // Version 1: scans and their resources
+    "CREATE TABLE scans (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        timestamp TEXT NOT NULL,
+        aws_region TEXT NOT NULL,
+        country TEXT NOT NULL,
+        duration_of_use_hours REAL NOT NULL,
+        number_of_resources_total INTEGER NOT NULL,
+        number_of_resources_assessed INTEGER NOT NULL,
+        number_of_resources_not_assessed INTEGER NOT NULL,
+        adp_manufacture_kgsbeq REAL NOT NULL,
+        adp_use_kgsbeq REAL NOT NULL,
+        pe_manufacture_megajoules REAL NOT NULL,
+        pe_use_megajoules REAL NOT NULL,
+        gwp_manufacture_kgco2eq REAL NOT NULL,
+        gwp_use_kgco2eq REAL NOT NULL,
+        summary_json TEXT NOT NULL,
+        inventory_json TEXT NOT NULL
+    );
+    CREATE INDEX scans_timestamp ON scans(timestamp);
+    CREATE TABLE scan_resources (
+        scan_id INTEGER NOT NULL REFERENCES scans(id) ON DELETE CASCADE,
+        resource_id TEXT NOT NULL,
+        resource_type TEXT NOT NULL,
+        resource_subtype TEXT,
+        aws_region TEXT NOT NULL,
+        tags TEXT NOT NULL,
+        assessed INTEGER NOT NULL,
+        adp_manufacture_kgsbeq REAL,
+        adp_use_kgsbeq REAL,
+        pe_manufacture_megajoules REAL,
+        pe_use_megajoules REAL,
+        gwp_manufacture_kgco2eq REAL,
+        gwp_use_kgco2eq REAL
+    );
+    CREATE INDEX scan_resources_scan_id ON scan_resources(scan_id);",
+    // Version 2: aggregates of the scans purged by the retention policy
+    "CREATE TABLE scan_aggregates (
+        period TEXT NOT NULL,
+        tag_key TEXT,
+        period_start TEXT NOT NULL,
+        period_end TEXT NOT NULL,
+        account_id TEXT,
+        aws_region TEXT NOT NULL,
+        tag_value TEXT,
+        period_hours REAL NOT NULL,
+        covered_hours REAL NOT NULL,
+        average_number_of_resources REAL NOT NULL,
+        adp_manufacture_kgsbeq REAL NOT NULL,
+        adp_use_kgsbeq REAL NOT NULL,
+        pe_manufacture_megajoules REAL NOT NULL,
+        pe_use_megajoules REAL NOT NULL,
+        gwp_manufacture_kgco2eq REAL NOT NULL,
+        gwp_use_kgco2eq REAL NOT NULL
+    );
+    CREATE INDEX scan_aggregates_period_start ON scan_aggregates(period, period_start);
+    CREATE TABLE scan_rollups (
+        period TEXT PRIMARY KEY,
+        aggregated_until TEXT NOT NULL
+    );",
+];
+
+/// A scan retrieved from the store (without its detailed inventory)
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct StoredScan {
+    pub id: i64,
+    pub timestamp: DateTime<Utc>,
+    pub summary: ImpactsSummary,
+}
+
+/// A scan retrieved from the store with its detailed inventory
+#[derive(Clone, Serialize, JsonSchema)]
+pub struct StoredScanDetails {
+    #[serde(flatten)]
+    pub scan: StoredScan,
+    #[serde(flatten)]
+    pub estimated_inventory: EstimatedInventory,
+}
+
+///  A store of scan results backed by a SQLite database
+pub struct ResultStore {
+    connection: Connection,
+}
+
+impl ResultStore {
+    /// Opens (or creates) the store at the given path and migrates its schema to the latest version.
+    ///
+    /// Use `:memory:` as path for a transient in-memory store.
+    pub fn open(path: &str) -> Result<Self> {
+        let connection = Connection::open(path)
+            .with_context(|| format!("Cannot open result store at {}", path))?;
+        connection.pragma_update(None, "foreign_keys", true)?;
+        let mut store = ResultStore { connection };
+        store
+            .migrate()
+            .context("Cannot migrate result store schema")?;
+        Ok(store)
+    }
+
+    /// Returns the current version of the schema
+    pub fn schema_version(&self) -> Result<usize> {
+        let version: i64 = self
+            .connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        Ok(version as usize)
+    }
+
+    /// Applies the migrations that were not yet applied to the database
+    fn migrate(&mut self) -> Result<()> {
+        let current_version = self.schema_version()?;
+        if current_version > MIGRATIONS.len() {
+            anyhow::bail!(
+                "Result store schema version ({}) is newer than the one supported by this version of cloud scanner ({})",
+                current_version,
+                MIGRATIONS.len()
+            );
+        }
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(current_version) {
+            let version = index + 1;
+            info!("Migrating result store schema to version {}", version);
+            let tx = self.connection.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", version as i64)?;
+            tx.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Persists a scan (summary and resources with their impacts), returns the id of the scan.
+    pub fn save_scan(
+        &mut self,
+        timestamp: DateTime<Utc>,
+        summary: &ImpactsSummary,
+        estimated_inventory: &EstimatedInventory,
+    ) -> Result<i64> {
+        let tx = self.connection.transaction()?;
+        tx.execute(
+            "INSERT INTO scans (timestamp, aws_region, country, duration_of_use_hours,
+                number_of_resources_total, number_of_resources_assessed, number_of_resources_not_assessed,
+                adp_manufacture_kgsbeq, adp_use_kgsbeq, pe_manufacture_megajoules, pe_use_megajoules,
+                gwp_manufacture_kgco2eq, gwp_use_kgco2eq, summary_json, inventory_json)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            params![
+                timestamp.to_rfc3339(),
+                summary.aws_regions(),
+                summary.countries(),
+                summary.duration_of_use_hours,
+                summary.number_of_resources_total as i64,
+                summary.number_of_resources_assessed as i64,
+                summary.number_of_resources_not_assessed as i64,
+                summary.adp_manufacture_kgsbeq,
+                summary.adp_use_kgsbeq,
+                summary.pe_manufacture_megajoules,
+                summary.pe_use_megajoules,
+                summary.gwp_manufacture_kgco2eq,
+                summary.gwp_use_kgco2eq,
+                serde_json::to_string(summary)?,
+                serde_json::to_string(estimated_inventory)?,
+            ],
+        )?;
+        let scan_id = tx.last_insert_rowid();
+
+        {
+            let mut insert_resource = tx.prepare(
+                "INSERT INTO scan_resources (scan_id, resource_id, resource_type, resource_subtype, aws_region, tags, assessed,
+                    adp_manufacture_kgsbeq, adp_use_kgsbeq, pe_manufacture_megajoules, pe_use_megajoules,
+                    gwp_manufacture_kgco2eq, gwp_use_kgco2eq)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            )?;
+            for resource in estimated_inventory.impacting_resources.iter() {
+                let cloud_resource = &resource.cloud_resource;
+                let resource_type = cloud_resource.resource_details.kind();
+                let resource_subtype = cloud_resource.resource_details.resource_type();
+                let impacts = resource.impacts_values.as_ref();
+                insert_resource.execute(params![
+                    scan_id,
+                    cloud_resource.id,
+                    resource_type,
+                    resource_subtype,
+                    cloud_resource.location.aws_region,
+                    cloud_resource.tags_as_metric_label_value(),
+                    impacts.is_some(),
+                    impacts.map(|i| i.adp_manufacture_kgsbeq),
+                    impacts.map(|i| i.adp_use_kgsbeq),
+                    impacts.map(|i| i.pe_manufacture_megajoules),
+                    impacts.map(|i| i.pe_use_megajoules),
+                    impacts.map(|i| i.gwp_manufacture_kgco2eq),
+                    impacts.map(|i| i.gwp_use_kgco2eq),
+                ])?;
+            }
+        }
+        tx.commit()?;
+        debug!("Saved scan {} in result store", scan_id);
+        Ok(scan_id)
+    }
+
+    /// Returns all the scans of the store, oldest first
+    pub fn list_scans(&self) -> Result<Vec<StoredScan>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT id, timestamp, summary_json FROM scans ORDER BY timestamp, id")?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        let mut scans: Vec<StoredScan> = Vec::new();
+        for row in rows {
+            let (id, timestamp, summary_json) = row?;
+            scans.push(Self::to_stored_scan(id, &timestamp, &summary_json)?);
+        }
+        Ok(scans)
+    }
+
+    /// Returns the scans done between two dates (included, unbounded when none), oldest first
+    pub fn list_scans_between(
+        &self,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<Vec<StoredScan>> {
+        let mut scans = self.list_scans()?;
+        scans.retain(|scan| {
+            since.is_none_or(|since| scan.timestamp >= since)
+                && until.is_none_or(|until| scan.timestamp <= until)
+        });
+        Ok(scans)
+    }
+
+    /// Returns a single scan, or None if there is no scan with this id
+    pub fn get_scan(&self, id: i64) -> Result<Option<StoredScan>> {
+        let row = self
+            .connection
+            .query_row(
+                "SELECT id, timestamp, summary_json FROM scans WHERE id = ?1",
+                [id],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                    ))
+                },
+            )
+            .optional()?;
+        match row {
+            Some((id, timestamp, summary_json)) => {
+                Ok(Some(Self::to_stored_scan(id, &timestamp, &summary_json)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the detailed estimated inventory of a scan, or None if there is no scan with this id
+    pub fn get_scan_inventory(&self, id: i64) -> Result<Option<EstimatedInventory>> {
+        let inventory_json: Option<String> = self
+            .connection
+            .query_row(
+                "SELECT inventory_json FROM scans WHERE id = ?1",
+                [id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match inventory_json {
+            Some(json) => Ok(Some(
+                serde_json::from_str(&json).context("Cannot parse stored inventory")?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns a single scan with its detailed inventory, or None if there is no scan with this id
+    pub fn get_scan_details(&self, id: i64) -> Result<Option<StoredScanDetails>> {
+        let Some(scan) = self.get_scan(id)? else {
+            return Ok(None);
+        };
+        let estimated_inventory = self
+            .get_scan_inventory(id)?
+            .with_context(|| format!("Scan {} has no inventory", id))?;
+        Ok(Some(StoredScanDetails {
+            scan,
+            estimated_inventory,
+        }))
+    }
+
+    /// Deletes scans (with their resources), returns the number of scans deleted
+    pub fn delete_scans(&mut self, ids: &[i64]) -> Result<usize> {
+        let tx = self.connection.transaction()?;
+        let mut deleted = 0;
+        {
+            let mut delete_scan = tx.prepare("DELETE FROM scans WHERE id = ?1")?;
+            for id in ids.iter() {
+                deleted += delete_scan.execute([id])?;
+            }
+        }
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    /// Persists the aggregates of a period of aggregation (like `monthly`) grouped by a tag (if any), recording that the history is aggregated until a date
+    pub fn save_aggregates(
+        &mut self,
+        period: &str,
+        tag_key: Option<&str>,
+        rows: &[AggregatedImpacts],
+        aggregated_until: DateTime<Utc>,
+    ) -> Result<()> {
+        let tx = self.connection.transaction()?;
+        {
+            let mut insert_aggregate = tx.prepare(
+                "INSERT INTO scan_aggregates (period, tag_key, period_start, period_end, account_id, aws_region, tag_value,
+                    period_hours, covered_hours, average_number_of_resources,
+                    adp_manufacture_kgsbeq, adp_use_kgsbeq, pe_manufacture_megajoules, pe_use_megajoules,
+                    gwp_manufacture_kgco2eq, gwp_use_kgco2eq)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+            )?;
+            for row in rows.iter() {
+                insert_aggregate.execute(params![
+                    period,
+                    tag_key,
+                    row.period_start.to_rfc3339(),
+                    row.period_end.to_rfc3339(),
+                    row.account_id,
+                    row.aws_region,
+                    row.tag_value,
+                    row.period_hours,
+                    row.covered_hours,
+                    row.average_number_of_resources,
+                    row.adp_manufacture_kgsbeq,
+                    row.adp_use_kgsbeq,
+                    row.pe_manufacture_megajoules,
+                    row.pe_use_megajoules,
+                    row.gwp_manufacture_kgco2eq,
+                    row.gwp_use_kgco2eq,
+                ])?;
+            }
+        }
+        tx.execute(
+            "INSERT INTO scan_rollups (period, aggregated_until) VALUES (?1, ?2)
+             ON CONFLICT(period) DO UPDATE SET aggregated_until = excluded.aggregated_until",
+            params![period, aggregated_until.to_rfc3339()],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Returns the date until which the history is aggregated over a period of aggregation (like `monthly`), none when it is not
+    pub fn aggregated_until(&self, period: &str) -> Result<Option<DateTime<Utc>>> {
+        let aggregated_until: Option<String> = self
+            .connection
+            .query_row(
+                "SELECT aggregated_until FROM scan_rollups WHERE period = ?1",
+                [period],
+                |row| row.get(0),
+            )
+            .optional()?;
+        aggregated_until
+            .map(|until| parse_timestamp(&until))
+            .transpose()
+    }
+
+    /// Returns the aggregates of a period of aggregation (like `monthly`) grouped by a tag (if any), oldest period first
+    pub fn list_aggregates(
+        &self,
+        period: &str,
+        tag_key: Option<&str>,
+    ) -> Result<Vec<AggregatedImpacts>> {
+        let mut statement = self.connection.prepare(
+            "SELECT period_start, period_end, account_id, aws_region, tag_value,
+                period_hours, covered_hours, average_number_of_resources,
+                adp_manufacture_kgsbeq, adp_use_kgsbeq, pe_manufacture_megajoules, pe_use_megajoules,
+                gwp_manufacture_kgco2eq, gwp_use_kgco2eq
+             FROM scan_aggregates WHERE period = ?1 AND tag_key IS ?2
+             ORDER BY period_start, account_id, aws_region, tag_value",
+        )?;
+        let rows = statement.query_map(params![period, tag_key], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                AggregatedImpacts {
+                    period_start: DateTime::<Utc>::MIN_UTC,
+                    period_end: DateTime::<Utc>::MIN_UTC,
+                    account_id: row.get(2)?,
+                    aws_region: row.get(3)?,
+                    tag_value: row.get(4)?,
+                    period_hours: row.get(5)?,
+                    covered_hours: row.get(6)?,
+                    extrapolated: false,
+                    average_number_of_resources: row.get(7)?,
+                    adp_manufacture_kgsbeq: row.get(8)?,
+                    adp_use_kgsbeq: row.get(9)?,
+                    pe_manufacture_megajoules: row.get(10)?,
+                    pe_use_megajoules: row.get(11)?,
+                    gwp_manufacture_kgco2eq: row.get(12)?,
+                    gwp_use_kgco2eq: row.get(13)?,
+                },
+            ))
+        })?;
+        let mut aggregates = Vec::new();
+        for row in rows {
+            let (period_start, period_end, aggregate) = row?;
+            aggregates.push(AggregatedImpacts {
+                period_start: parse_timestamp(&period_start)?,
+                period_end: parse_timestamp(&period_end)?,
+                ..aggregate
+            });
+        }
+        Ok(aggregates)
+    }
+
+    /// Deletes the aggregates of the periods ended before a date, returns the number of aggregates deleted
+    pub fn delete_aggregates_before(&mut self, before: DateTime<Utc>) -> Result<usize> {
+        // Dates are all written in UTC by to_rfc3339, so that they sort as text
+        let deleted = self.connection.execute(
+            "DELETE FROM scan_aggregates WHERE period_end <= ?1",
+            [before.to_rfc3339()],
+        )?;
+        Ok(deleted)
+    }
+
+    fn to_stored_scan(id: i64, timestamp: &str, summary_json: &str) -> Result<StoredScan> {
+        let timestamp = DateTime::parse_from_rfc3339(timestamp)
+            .with_context(|| format!("Invalid timestamp for scan {}", id))?
+            .with_timezone(&Utc);
+        let summary: ImpactsSummary = serde_json::from_str(summary_json)
+            .with_context(|| format!("Cannot parse summary of scan {}", id))?;
+        Ok(StoredScan {
+            id,
+            timestamp,
+            summary,
+        })
+    }
+}
+
+fn parse_timestamp(timestamp: &str) -> Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(timestamp)
+        .with_context(|| format!("Invalid timestamp {}", timestamp))?
+        .with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+    use crate::model::{
+        CloudProvider, CloudResource, InstanceState, InstanceUsage, ResourceDetails,
+    };
+    use crate::usage_location::UsageLocation;
+
+    fn sample_inventory() -> EstimatedInventory {
+        let cloud_resource: CloudResource = CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: None,
+            id: "inst-1".to_string(),
+            location: UsageLocation::try_from("eu-west-3").unwrap(),
+            resource_details: ResourceDetails::Instance {
+                instance_type: "m6g.xlarge".to_string(),
+                usage: Some(InstanceUsage {
+                    average_cpu_load: 100.0,
+                    usage_duration_seconds: 3600,
+                    state: InstanceState::Running,
+                }),
+            },
+            tags: Vec::new(),
+            relationships: Vec::new(),
+        };
+        EstimatedInventory {
+            impacting_resources: vec![CloudResourceWithImpacts {
+                cloud_resource,
+                impacts_values: Some(ImpactsValues {
+                    adp_manufacture_kgsbeq: 0.1,
+                    adp_use_kgsbeq: 0.2,
+                    pe_manufacture_megajoules: 0.3,
+                    pe_use_megajoules: 0.4,
+                    gwp_manufacture_kgco2eq: 0.5,
+                    gwp_use_kgco2eq: 0.6,
+                    raw_data: None,
+                }),
+                impacts_duration_hours: 1.0,
+                cost: None,
+            }],
+            execution_statistics: None,
+            errors: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn a_new_store_is_migrated_to_latest_version() {
+        let store = ResultStore::open(":memory:").unwrap();
+        assert_eq!(MIGRATIONS.len(), store.schema_version().unwrap());
+    }
+
+    #[test]
+    fn reopening_a_store_does_not_reapply_migrations() {
+        let path = std::env::temp_dir().join(format!(
+            "cloud-scanner-store-test-{}.sqlite",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        {
+            let mut store = ResultStore::open(path).unwrap();
+            let inventory = sample_inventory();
+            let summary = ImpactsSummary::new("eu-west-3".into(), "FRA".into(), &inventory, 1.0);
+            store.save_scan(Utc::now(), &summary, &inventory).unwrap();
+        }
+        let store = ResultStore::open(path).unwrap();
+        assert_eq!(1, store.list_scans().unwrap().len());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn save_and_retrieve_scans() {
+        let mut store = ResultStore::open(":memory:").unwrap();
+        let inventory = sample_inventory();
+        let summary = ImpactsSummary::new("eu-west-3".into(), "FRA".into(), &inventory, 1.0);
+
+        let yesterday = Utc::now() - chrono::Duration::days(1);
+        let first = store.save_scan(yesterday, &summary, &inventory).unwrap();
+        let second = store.save_scan(Utc::now(), &summary, &inventory).unwrap();
+        assert_ne!(first, second);
+        let recent_scans = store
+            .list_scans_between(Some(yesterday + chrono::Duration::hours(1)), None)
+            .unwrap();
+        assert_eq!(
+            vec![second],
+            recent_scans.iter().map(|s| s.id).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            1,
+            store
+                .list_scans_between(None, Some(yesterday))
+                .unwrap()
+                .len()
+        );
+
+        let scans = store.list_scans().unwrap();
+        assert_eq!(2, scans.len());
+        assert_eq!(first, scans[0].id);
+        assert_eq!(0.6, scans[0].summary.gwp_use_kgco2eq);
+
+        let stored_inventory = store.get_scan_inventory(second).unwrap().unwrap();
+        assert_eq!(
+            "inst-1",
+            stored_inventory.impacting_resources[0].cloud_resource.id
+        );
+        assert!(store.get_scan(42).unwrap().is_none());
+
+        let details = store.get_scan_details(second).unwrap().unwrap();
+        let json = serde_json::to_value(&details).unwrap();
+        assert_eq!(second, json["id"]);
+        assert_eq!(
+            "inst-1",
+            json["impactingResources"][0]["cloud_resource"]["id"]
+        );
+        assert!(json["summary"].is_object());
+        assert!(store.get_scan_details(42).unwrap().is_none());
+
+        let resource_rows: i64 = store
+            .connection
+            .query_row(
+                "SELECT count(*) FROM scan_resources WHERE resource_type = 'Instance'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(2, resource_rows);
+    }
+
+    #[test]
+    fn aggregates_replace_deleted_scans() {
+        let mut store = ResultStore::open(":memory:").unwrap();
+        let inventory = sample_inventory();
+        let summary = ImpactsSummary::new("eu-west-3".into(), "FRA".into(), &inventory, 1.0);
+        let scan_id = store.save_scan(Utc::now(), &summary, &inventory).unwrap();
+        assert_eq!(1, store.delete_scans(&[scan_id, 42]).unwrap());
+        assert!(store.list_scans().unwrap().is_empty());
+        let resource_rows: i64 = store
+            .connection
+            .query_row("SELECT count(*) FROM scan_resources", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(0, resource_rows);
+
+        let date = |month: u32| -> DateTime<Utc> {
+            format!("2024-{:02}-01T00:00:00Z", month).parse().unwrap()
+        };
+        let aggregate = |month: u32| AggregatedImpacts {
+            period_start: date(month),
+            period_end: date(month + 1),
+            account_id: None,
+            aws_region: "eu-west-3".to_string(),
+            tag_value: Some("data".to_string()),
+            period_hours: 720.0,
+            covered_hours: 720.0,
+            extrapolated: false,
+            average_number_of_resources: 1.0,
+            adp_manufacture_kgsbeq: 0.0,
+            adp_use_kgsbeq: 0.0,
+            pe_manufacture_megajoules: 0.0,
+            pe_use_megajoules: 0.0,
+            gwp_manufacture_kgco2eq: 4.0,
+            gwp_use_kgco2eq: 6.0,
+        };
+        assert_eq!(None, store.aggregated_until("monthly").unwrap());
+        store
+            .save_aggregates(
+                "monthly",
+                Some("team"),
+                &[aggregate(1), aggregate(2)],
+                date(3),
+            )
+            .unwrap();
+        assert_eq!(Some(date(3)), store.aggregated_until("monthly").unwrap());
+        assert_eq!(
+            vec![aggregate(1), aggregate(2)],
+            store.list_aggregates("monthly", Some("team")).unwrap()
+        );
+        assert!(store.list_aggregates("monthly", None).unwrap().is_empty());
+        assert_eq!(1, store.delete_aggregates_before(date(2)).unwrap());
+        assert_eq!(
+            vec![aggregate(2)],
+            store.list_aggregates("monthly", Some("team")).unwrap()
+        );
+    }
+}
@@ -0,0 +1,344 @@
+//! Streaming of the results of a scan: the resources are written with their impacts as soon as each page of the inventory is estimated, and only the summary of the impacts is kept in memory, so that the memory of a scan does not grow with the number of its resources.
+//!
+//! Two formats are streamed:
+//! - `json`: the same document as the json results (the estimated inventory in a metadata envelope)
+//! - `ndjson`: one json object per line, the metadata first (`{"metadata":...}`), then each resource with its impacts, then the errors the scan continued past (`{"errors":[...]}`, if any), then the summary (`{"summary":...}`). The summary of each account of multi-account scans is written as soon as all its regions are scanned (`{"account":{"accountId":...,"summary":...}}`), before the resources of the accounts still being scanned
+//!
+//! Resources are written in the order they are estimated (regions scanned concurrently are interleaved).
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::sync::Mutex;
+
+use crate::impact_provider::{CloudResourceWithImpacts, ImpactsSummary};
+use crate::model::ExecutionStatistics;
+use crate::result_envelope::ResultMetadata;
+use crate::scan_errors::ScanError;
+
+/// Format of streamed results
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StreamFormat {
+    Json,
+    Ndjson,
+}
+
+impl TryFrom<&str> for StreamFormat {
+    type Error = anyhow::Error;
+
+    fn try_from(format: &str) -> Result<Self> {
+        match format {
+            "json" => Ok(StreamFormat::Json),
+            "ndjson" => Ok(StreamFormat::Ndjson),
+            _ => anyhow::bail!(
+                "Unsupported stream format {} (expecting json or ndjson)",
+                format
+            ),
+        }
+    }
+}
+
+struct StreamState<W> {
+    writer: W,
+    summary: ImpactsSummary,
+    written: usize,
+}
+
+/// Results of a scan being written
+pub struct ResultStream<W: Write + Send> {
+    format: StreamFormat,
+    state: Mutex<StreamState<W>>,
+}
+
+impl<W: Write + Send> ResultStream<W> {
+    /// Starts writing results with the metadata of the scan, `summary` being the summary of the scanned regions (without resources yet)
+    pub fn start(
+        mut writer: W,
+        format: StreamFormat,
+        metadata: &ResultMetadata,
+        summary: ImpactsSummary,
+    ) -> Result<Self> {
+        let metadata = serde_json::to_string(metadata)?;
+        match format {
+            StreamFormat::Json => write!(
+                writer,
+                r#"{{"metadata":{},"data":{{"impactingResources":["#,
+                metadata
+            ),
+            StreamFormat::Ndjson => writeln!(writer, r#"{{"metadata":{}}}"#, metadata),
+        }
+        .context("Cannot write results")?;
+        Ok(ResultStream {
+            format,
+            state: Mutex::new(StreamState {
+                writer,
+                summary,
+                written: 0,
+            }),
+        })
+    }
+
+    /// Writes resources with their impacts, and adds them to the summary
+    pub fn write_page(&self, resources_with_impacts: &[CloudResourceWithImpacts]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        for resource_with_impacts in resources_with_impacts {
+            let separator = match (self.format, state.written) {
+                (StreamFormat::Json, 0) => "",
+                (StreamFormat::Json, _) => ",",
+                (StreamFormat::Ndjson, _) => "",
+            };
+            state.writer.write_all(separator.as_bytes())?;
+            serde_json::to_writer(&mut state.writer, resource_with_impacts)
+                .context("Cannot write results")?;
+            if self.format == StreamFormat::Ndjson {
+                state.writer.write_all(b"\n")?;
+            }
+            state.summary.add(resource_with_impacts);
+            state.written += 1;
+        }
+        // Written pages are not held in buffers until the end of the scan
+        state.writer.flush().context("Cannot write results")
+    }
+
+    /// Writes the summary of an account whose regions are all scanned (ndjson only, the json document having a single summary)
+    pub fn write_account(&self, account_id: &str) -> Result<()> {
+        if self.format != StreamFormat::Ndjson {
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap();
+        let summary = state.summary.of_account(account_id);
+        writeln!(
+            state.writer,
+            r#"{{"account":{{"accountId":{},"summary":{}}}}}"#,
+            serde_json::to_string(account_id)?,
+            serde_json::to_string(&summary)?
+        )
+        .context("Cannot write results")?;
+        state.writer.flush().context("Cannot write results")
+    }
+
+    /// Returns the summary of the resources written so far
+    pub fn summary(&self) -> ImpactsSummary {
+        self.state.lock().unwrap().summary.clone()
+    }
+
+    /// Ends the results with the execution statistics of the scan and its errors (json) or its errors and the summary (ndjson), returns the writer
+    pub fn finish(
+        self,
+        execution_statistics: Option<&ExecutionStatistics>,
+        errors: &[ScanError],
+        summary: &ImpactsSummary,
+    ) -> Result<W> {
+        let mut state = self.state.into_inner().unwrap();
+        info!("Streamed {} resources with their impacts", state.written);
+        let errors = match errors {
+            [] => None,
+            errors => Some(serde_json::to_string(errors)?),
+        };
+        match (self.format, errors) {
+            (StreamFormat::Json, None) => writeln!(
+                state.writer,
+                r#"],"executionStatistics":{}}}}}"#,
+                serde_json::to_string(&execution_statistics)?
+            ),
+            (StreamFormat::Json, Some(errors)) => writeln!(
+                state.writer,
+                r#"],"executionStatistics":{},"errors":{}}}}}"#,
+                serde_json::to_string(&execution_statistics)?,
+                errors
+            ),
+            (StreamFormat::Ndjson, errors) => {
+                if let Some(errors) = errors {
+                    writeln!(state.writer, r#"{{"errors":{}}}"#, errors)
+                        .context("Cannot write results")?;
+                }
+                writeln!(
+                    state.writer,
+                    r#"{{"summary":{}}}"#,
+                    serde_json::to_string(summary)?
+                )
+            }
+        }
+        .context("Cannot write results")?;
+        state.writer.flush().context("Cannot write results")?;
+        Ok(state.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{ImpactsValues, RegionSummary};
+    use crate::model::{
+        CloudProvider, CloudResource, EstimatedInventory, InstanceState, InstanceUsage,
+        ResourceDetails,
+    };
+    use crate::result_envelope::{read_results, ScanParameters};
+    use crate::usage_location::UsageLocation;
+
+    fn instance(id: &str, gwp_use: f64) -> CloudResourceWithImpacts {
+        CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: id.to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details: ResourceDetails::Instance {
+                    instance_type: "m6g.xlarge".to_string(),
+                    usage: Some(InstanceUsage {
+                        average_cpu_load: 50.0,
+                        usage_duration_seconds: 300,
+                        state: InstanceState::Running,
+                    }),
+                },
+                tags: Vec::new(),
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues {
+                gwp_use_kgco2eq: gwp_use,
+                ..ImpactsValues::default()
+            }),
+            impacts_duration_hours: 1.0,
+            cost: None,
+        }
+    }
+
+    fn stream(format: StreamFormat) -> (String, ImpactsSummary) {
+        let empty = EstimatedInventory {
+            impacting_resources: Vec::new(),
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::of_scanned_regions(
+            vec![RegionSummary::new(
+                None,
+                "eu-west-1".to_string(),
+                "IRL".to_string(),
+            )],
+            &empty,
+            1.0,
+        );
+        let metadata = ResultMetadata::new(chrono::Utc::now(), ScanParameters::default(), None);
+        let stream = ResultStream::start(Vec::new(), format, &metadata, summary).unwrap();
+        stream.write_page(&[instance("i-1", 1.0)]).unwrap();
+        stream.write_page(&[]).unwrap();
+        stream
+            .write_page(&[instance("i-2", 2.0), instance("i-3", 4.0)])
+            .unwrap();
+        let summary = stream.summary();
+        let written = stream.finish(None, &[], &summary).unwrap();
+        (String::from_utf8(written).unwrap(), summary)
+    }
+
+    #[test]
+    fn results_are_written_page_by_page() {
+        let (json, summary) = stream(StreamFormat::Json);
+        let (metadata, inventory): (_, EstimatedInventory) = read_results(&json).unwrap();
+        assert!(metadata.is_some());
+        let ids: Vec<&str> = inventory
+            .impacting_resources
+            .iter()
+            .map(|resource| resource.cloud_resource.id.as_str())
+            .collect();
+        assert_eq!(vec!["i-1", "i-2", "i-3"], ids);
+
+        // The summary is built while writing, like the summary of the whole inventory
+        assert_eq!(3, summary.number_of_resources_total);
+        assert_eq!(7.0, summary.gwp_use_kgco2eq);
+        assert_eq!(
+            vec!["eu-west-1", "eu-west-3"],
+            summary
+                .regions
+                .iter()
+                .map(|region| region.aws_region.as_str())
+                .collect::<Vec<&str>>()
+        );
+        assert_eq!(3, summary.regions[1].number_of_resources_total);
+
+        let (ndjson, _) = stream(StreamFormat::Ndjson);
+        let lines: Vec<serde_json::Value> = ndjson
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(5, lines.len());
+        assert!(lines[0]["metadata"].is_object());
+        assert_eq!("i-2", lines[2]["cloud_resource"]["id"]);
+        assert_eq!(7.0, lines[4]["summary"]["gwp_use_kgco2eq"]);
+        assert!(StreamFormat::try_from("csv").is_err());
+    }
+
+    #[test]
+    fn accounts_are_summarized_once_scanned() {
+        let in_account = |id: &str, account_id: &str, gwp_use: f64| {
+            let mut resource = instance(id, gwp_use);
+            resource.cloud_resource.account_id = Some(account_id.to_string());
+            resource
+        };
+        let empty = EstimatedInventory {
+            impacting_resources: Vec::new(),
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::of_scanned_regions(Vec::new(), &empty, 1.0);
+        let metadata = ResultMetadata::new(chrono::Utc::now(), ScanParameters::default(), None);
+        let stream =
+            ResultStream::start(Vec::new(), StreamFormat::Ndjson, &metadata, summary).unwrap();
+        stream
+            .write_page(&[
+                in_account("i-1", "111111111111", 1.0),
+                in_account("i-2", "222222222222", 2.0),
+            ])
+            .unwrap();
+        stream.write_account("111111111111").unwrap();
+        stream
+            .write_page(&[in_account("i-3", "222222222222", 4.0)])
+            .unwrap();
+        stream.write_account("222222222222").unwrap();
+        let summary = stream.summary();
+        let written = String::from_utf8(stream.finish(None, &[], &summary).unwrap()).unwrap();
+        let lines: Vec<serde_json::Value> = written
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(7, lines.len());
+        assert_eq!("111111111111", lines[3]["account"]["accountId"]);
+        assert_eq!(
+            1,
+            lines[3]["account"]["summary"]["number_of_resources_total"]
+        );
+        assert_eq!(6.0, lines[5]["account"]["summary"]["gwp_use_kgco2eq"]);
+        assert_eq!(7.0, lines[6]["summary"]["gwp_use_kgco2eq"]);
+    }
+
+    #[test]
+    fn errors_are_written_with_the_results() {
+        let empty = EstimatedInventory {
+            impacting_resources: Vec::new(),
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let errors = vec![ScanError::of_resource(
+            crate::scan_errors::ScanPhase::ImpactEstimation,
+            &instance("i-1", 1.0).cloud_resource,
+            "Cannot get impacts".to_string(),
+        )];
+        let metadata = ResultMetadata::new(chrono::Utc::now(), ScanParameters::default(), None);
+        let write = |format: StreamFormat| {
+            let summary = ImpactsSummary::of_scanned_regions(Vec::new(), &empty, 1.0);
+            let stream = ResultStream::start(Vec::new(), format, &metadata, summary).unwrap();
+            stream.write_page(&[instance("i-1", 1.0)]).unwrap();
+            let summary = stream.summary();
+            String::from_utf8(stream.finish(None, &errors, &summary).unwrap()).unwrap()
+        };
+        let (_, inventory): (_, EstimatedInventory) =
+            read_results(&write(StreamFormat::Json)).unwrap();
+        assert_eq!(errors, inventory.errors);
+
+        let ndjson = write(StreamFormat::Ndjson);
+        let lines: Vec<serde_json::Value> = ndjson
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(4, lines.len());
+        assert_eq!("i-1", lines[2]["errors"][0]["resourceId"]);
+        assert!(lines[3]["summary"].is_object());
+    }
+}
@@ -0,0 +1,186 @@
+//! Retention policy of the result store (`[retention]` of the configuration file): scans older than a duration are rolled up into aggregates over periods (like monthly totals per account, region and value of a tag), then purged, so that stores of long-running deployments do not grow unbounded.
+//!
+//! ```toml
+//! # Keep the scans 90 days, and their monthly aggregates per team forever
+//! [retention]
+//! keep_scans = "90d"
+//! aggregate_period = "monthly"
+//! aggregate_by_tag = "team"
+//! ```
+//!
+//! Scans are only purged by whole periods of aggregation, once the whole period is older than `keep_scans`, and the last purged scan is kept: it covers the beginning of the history that is kept (see [crate::scan_aggregation]).
+//! The purge runs after each scan saved by `watch` and `estimate --store`, periodically in server mode, or on demand with the `purge` command.
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::scan_aggregation::AggregationPeriod;
+
+fn default_keep_scans() -> f32 {
+    90.0 * 24.0
+}
+
+fn default_aggregate_period() -> String {
+    "monthly".to_string()
+}
+
+fn default_purge_interval_hours() -> u64 {
+    24
+}
+
+/// The retention policy of the configuration file
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Duration during which the scans are kept with their resources (hours, or text like `90d`, the default)
+    #[serde(
+        default = "default_keep_scans",
+        deserialize_with = "crate::duration::deserialize_hours"
+    )]
+    pub keep_scans: f32,
+    /// Period of the aggregates replacing the purged scans: daily, weekly (starting on Monday), monthly (default), or none to purge the scans without aggregating them
+    #[serde(default = "default_aggregate_period")]
+    pub aggregate_period: String,
+    /// Tag whose values the aggregates are grouped by (like team), in addition to the account and region
+    pub aggregate_by_tag: Option<String>,
+    /// Duration during which the aggregates are kept (hours, or text like `10y`), forever when not set
+    #[serde(
+        default,
+        deserialize_with = "crate::duration::deserialize_optional_hours"
+    )]
+    pub keep_aggregates: Option<f32>,
+    /// Hours between two purges in server mode
+    #[serde(default = "default_purge_interval_hours")]
+    pub purge_interval_hours: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            keep_scans: default_keep_scans(),
+            aggregate_period: default_aggregate_period(),
+            aggregate_by_tag: None,
+            keep_aggregates: None,
+            purge_interval_hours: default_purge_interval_hours(),
+        }
+    }
+}
+
+impl RetentionConfig {
+    /// Returns the period of the aggregates, none when the scans are purged without aggregating them
+    pub fn aggregation_period(&self) -> Result<Option<AggregationPeriod>> {
+        if self.aggregate_period == "none" {
+            return Ok(None);
+        }
+        AggregationPeriod::try_from(self.aggregate_period.as_str())
+            .map(Some)
+            .context("Invalid aggregate_period of the retention policy")
+    }
+
+    /// Returns the date before which scans are purged: the start of the period of aggregation containing the end of `keep_scans`
+    pub fn scans_cutoff(&self, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+        let cutoff = now - hours(self.keep_scans);
+        Ok(match self.aggregation_period()? {
+            Some(period) => period.start_of(cutoff),
+            None => cutoff,
+        })
+    }
+
+    /// Returns the date before which aggregates are purged, if they are not kept forever
+    pub fn aggregates_cutoff(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.keep_aggregates.map(|keep| now - hours(keep))
+    }
+}
+
+fn hours(hours: f32) -> chrono::Duration {
+    chrono::Duration::seconds((hours as f64 * 3600.0) as i64)
+}
+
+/// Returns the ids of the scans (id and timestamp, oldest first) to purge: the scans done before the cutoff, except the last one, which covers the time following the cutoff
+pub fn scans_to_purge(scans: &[(i64, DateTime<Utc>)], cutoff: DateTime<Utc>) -> Vec<i64> {
+    let before_cutoff = scans
+        .iter()
+        .take_while(|(_, timestamp)| *timestamp < cutoff)
+        .count();
+    scans
+        .iter()
+        .take(before_cutoff.saturating_sub(1))
+        .map(|(id, _)| *id)
+        .collect()
+}
+
+/// What a purge of the result store did
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PurgeOutcome {
+    /// Date until which the history is aggregated, if it is
+    pub aggregated_until: Option<DateTime<Utc>>,
+    /// Number of aggregates added
+    pub added_aggregates: usize,
+    pub purged_scans: usize,
+    pub purged_aggregates: usize,
+}
+
+impl PurgeOutcome {
+    /// Returns true when the purge changed nothing
+    pub fn is_empty(&self) -> bool {
+        self.added_aggregates == 0 && self.purged_scans == 0 && self.purged_aggregates == 0
+    }
+
+    pub fn to_text(&self) -> String {
+        format!(
+            "{} scans purged, {} aggregates added{}, {} aggregates purged",
+            self.purged_scans,
+            self.added_aggregates,
+            self.aggregated_until
+                .map(|until| format!(" (history aggregated until {})", until.to_rfc3339()))
+                .unwrap_or_default(),
+            self.purged_aggregates
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn scans_are_purged_by_whole_periods() {
+        let retention: RetentionConfig = toml::from_str("keep_scans = \"90d\"").unwrap();
+        assert_eq!(RetentionConfig::default(), retention);
+        let now = Utc.with_ymd_and_hms(2024, 6, 15, 12, 0, 0).unwrap();
+        // 90 days before is March 17th, in March
+        assert_eq!(
+            Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap(),
+            retention.scans_cutoff(now).unwrap()
+        );
+        assert_eq!(None, retention.aggregates_cutoff(now));
+
+        let retention: RetentionConfig = toml::from_str(
+            "keep_scans = 48\naggregate_period = \"none\"\nkeep_aggregates = \"1y\"",
+        )
+        .unwrap();
+        assert_eq!(
+            Utc.with_ymd_and_hms(2024, 6, 13, 12, 0, 0).unwrap(),
+            retention.scans_cutoff(now).unwrap()
+        );
+        assert!(retention.aggregates_cutoff(now).unwrap() < now);
+
+        let retention = RetentionConfig {
+            aggregate_period: "yearly".to_string(),
+            ..Default::default()
+        };
+        assert!(retention.scans_cutoff(now).is_err());
+    }
+
+    #[test]
+    fn the_last_scan_before_the_cutoff_is_kept() {
+        let at = |day| Utc.with_ymd_and_hms(2024, 3, day, 0, 0, 0).unwrap();
+        let scans = vec![(1, at(1)), (2, at(2)), (3, at(3)), (4, at(4))];
+        assert_eq!(vec![1, 2], scans_to_purge(&scans, at(4)));
+        assert_eq!(vec![1], scans_to_purge(&scans, at(3)));
+        assert!(scans_to_purge(&scans, at(2)).is_empty());
+        assert!(scans_to_purge(&scans, at(1)).is_empty());
+        assert!(scans_to_purge(&[], at(1)).is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+//!  A module to upload results of cloud-scanner to an S3 bucket, using date-partitioned keys.
+use anyhow::{Context, Result};
+use aws_sdk_s3::primitives::ByteStream;
+use chrono::{DateTime, Utc};
+
+use crate::output_exporter::content_type;
+
+///  The destination of results in S3, parsed from an url like `s3://bucket/prefix/`
+#[derive(Clone, Debug, PartialEq)]
+pub struct S3Location {
+    pub bucket: String,
+    /// Prefix of the keys (empty, or ending with a slash)
+    pub prefix: String,
+}
+
+impl TryFrom<&str> for S3Location {
+    type Error = anyhow::Error;
+
+    fn try_from(url: &str) -> Result<Self, Self::Error> {
+        let path = url
+            .strip_prefix("s3://")
+            .with_context(|| format!("Invalid S3 url {} (should start with s3://)", url))?;
+        let (bucket, prefix) = match path.split_once('/') {
+            Some((bucket, prefix)) => (bucket, prefix),
+            None => (path, ""),
+        };
+        if bucket.is_empty() {
+            anyhow::bail!("Invalid S3 url {} (missing bucket name)", url);
+        }
+        let mut prefix = prefix.to_string();
+        if !prefix.is_empty() && !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+        Ok(S3Location {
+            bucket: bucket.to_string(),
+            prefix,
+        })
+    }
+}
+
+impl S3Location {
+    /// Returns a date-partitioned key for results of a scan, like `prefix/year=2024/month=04/day=12/cloud-scanner-eu-west-1-20240412T101500.123Z-<uuid>.json`
+    ///
+    /// Partitions use the `key=value` convention, so that results can be queried directly with Athena or other Hive-compatible tools.
+    /// Keys end with a random suffix, so that the results of scans of the same region uploaded at the same time do not replace each other.
+    pub fn partitioned_key(
+        &self,
+        timestamp: DateTime<Utc>,
+        aws_region: &str,
+        extension: &str,
+    ) -> String {
+        let region = if aws_region.is_empty() {
+            "default-region"
+        } else {
+            aws_region
+        };
+        format!(
+            "{}year={}/month={}/day={}/cloud-scanner-{}-{}-{}.{}",
+            self.prefix,
+            timestamp.format("%Y"),
+            timestamp.format("%m"),
+            timestamp.format("%d"),
+            region,
+            timestamp.format("%Y%m%dT%H%M%S%.3fZ"),
+            uuid::Uuid::new_v4().simple(),
+            extension
+        )
+    }
+}
+
+/// Uploads results to S3 under a date-partitioned key, returns the s3:// url of the uploaded object.
+pub async fn upload_to_s3(
+    location: &S3Location,
+    aws_region: &str,
+    body: Vec<u8>,
+    extension: &str,
+) -> Result<String> {
+    let key = location.partitioned_key(Utc::now(), aws_region, extension);
+    let sdk_config = aws_config::load_from_env().await;
+    let client = aws_sdk_s3::Client::new(&sdk_config);
+    client
+        .put_object()
+        .bucket(&location.bucket)
+        .key(&key)
+        .content_type(content_type(extension))
+        .body(ByteStream::from(body))
+        .send()
+        .await
+        .with_context(|| format!("Cannot upload results to s3://{}/{}", location.bucket, key))?;
+    let url = format!("s3://{}/{}", location.bucket, key);
+    info!("Results uploaded to {}", url);
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn parse_s3_urls() {
+        let location = S3Location::try_from("s3://my-bucket/some/prefix").unwrap();
+        assert_eq!("my-bucket", location.bucket);
+        assert_eq!("some/prefix/", location.prefix);
+
+        let location = S3Location::try_from("s3://my-bucket/").unwrap();
+        assert_eq!("", location.prefix);
+
+        let location = S3Location::try_from("s3://my-bucket").unwrap();
+        assert_eq!("", location.prefix);
+
+        assert!(S3Location::try_from("s3:///prefix").is_err());
+        assert!(S3Location::try_from("https://my-bucket/prefix").is_err());
+    }
+
+    #[test]
+    fn keys_are_partitioned_by_date() {
+        let location = S3Location::try_from("s3://my-bucket/scans/").unwrap();
+        let timestamp = Utc.with_ymd_and_hms(2024, 4, 12, 10, 15, 0).unwrap()
+            + chrono::TimeDelta::milliseconds(123);
+        let key = location.partitioned_key(timestamp, "eu-west-1", "json");
+        let suffix = key
+            .strip_prefix(
+                "scans/year=2024/month=04/day=12/cloud-scanner-eu-west-1-20240412T101500.123Z-",
+            )
+            .unwrap()
+            .strip_suffix(".json")
+            .unwrap();
+        assert!(uuid::Uuid::parse_str(suffix).is_ok());
+        assert!(location.partitioned_key(timestamp, "", "csv").starts_with(
+            "scans/year=2024/month=04/day=12/cloud-scanner-default-region-20240412T101500.123Z-"
+        ));
+    }
+
+    #[test]
+    fn keys_of_the_same_second_are_unique() {
+        let location = S3Location::try_from("s3://my-bucket/scans/").unwrap();
+        let timestamp = Utc.with_ymd_and_hms(2024, 4, 12, 10, 15, 0).unwrap();
+        assert_ne!(
+            location.partitioned_key(timestamp, "eu-west-1", "json"),
+            location.partitioned_key(timestamp, "eu-west-1", "json")
+        );
+        assert_ne!(
+            location.partitioned_key(timestamp, "eu-west-1", "json"),
+            location.partitioned_key(
+                timestamp + chrono::TimeDelta::milliseconds(1),
+                "eu-west-1",
+                "json"
+            )
+        );
+    }
+}
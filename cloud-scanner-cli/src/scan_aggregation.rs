@@ -0,0 +1,481 @@
+//! Aggregation of the history of scans over periods (`aggregate`): the impacts of the scans of a result store are rolled up into daily, weekly or monthly totals per account, region and value of a tag, the basis of the reports over a period.
+//!
+//! Each scan covers the time from its timestamp until the next scan, at most its duration of use, and its impacts are prorated to the hours it covers in each period: scans of varying frequencies add up to the same totals. The hours not covered by any scan are gaps of the history, reported with the totals (and extrapolated from the covered hours on request).
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Months, NaiveTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::allocation::AllocationRules;
+use crate::model::EstimatedInventory;
+
+/// A scan of the history and the part of the reporting period it covers
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CoveredScan {
+    pub scan_id: i64,
+    pub timestamp: DateTime<Utc>,
+    /// Duration of use of the estimations of the scan
+    pub duration_of_use_hours: f64,
+    pub covered_from: DateTime<Utc>,
+    pub covered_until: DateTime<Utc>,
+}
+
+impl CoveredScan {
+    pub fn covered_hours(&self) -> f64 {
+        hours_between(self.covered_from, self.covered_until)
+    }
+
+    /// Returns the hours of the scan covered between two dates
+    pub(crate) fn covered_hours_between(&self, from: DateTime<Utc>, until: DateTime<Utc>) -> f64 {
+        hours_between(self.covered_from.max(from), self.covered_until.min(until))
+    }
+}
+
+/// The scans covering a period, with their estimated inventory
+pub struct ScansOfPeriod {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub scans: Vec<(CoveredScan, EstimatedInventory)>,
+}
+
+/// Returns the hours between two dates (0 when the second is before the first)
+pub fn hours_between(from: DateTime<Utc>, until: DateTime<Utc>) -> f64 {
+    (until - from).num_seconds().max(0) as f64 / 3600.0
+}
+
+/// Returns the part of the reporting period covered by each scan (id, timestamp and duration of use, oldest first), leaving out the scans that cover nothing
+pub fn covered_scans(
+    scans: &[(i64, DateTime<Utc>, f64)],
+    period_start: DateTime<Utc>,
+    period_end: DateTime<Utc>,
+) -> Vec<CoveredScan> {
+    scans
+        .iter()
+        .enumerate()
+        .filter_map(|(index, (scan_id, timestamp, duration_of_use_hours))| {
+            let end_of_use =
+                *timestamp + chrono::Duration::seconds((duration_of_use_hours * 3600.0) as i64);
+            let mut covered_until = end_of_use.min(period_end);
+            if let Some((_, next_timestamp, _)) = scans.get(index + 1) {
+                covered_until = covered_until.min(*next_timestamp);
+            }
+            let covered_from = (*timestamp).max(period_start);
+            (covered_until > covered_from).then_some(CoveredScan {
+                scan_id: *scan_id,
+                timestamp: *timestamp,
+                duration_of_use_hours: *duration_of_use_hours,
+                covered_from,
+                covered_until,
+            })
+        })
+        .collect()
+}
+
+/// Periods of the aggregation (in UTC, weeks starting on Monday)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AggregationPeriod {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl TryFrom<&str> for AggregationPeriod {
+    type Error = anyhow::Error;
+
+    fn try_from(period: &str) -> Result<Self> {
+        match period {
+            "daily" => Ok(AggregationPeriod::Daily),
+            "weekly" => Ok(AggregationPeriod::Weekly),
+            "monthly" => Ok(AggregationPeriod::Monthly),
+            _ => anyhow::bail!(
+                "Unsupported period {} (expecting daily, weekly or monthly)",
+                period
+            ),
+        }
+    }
+}
+
+impl AggregationPeriod {
+    /// Returns the name of the period (like `monthly`)
+    pub fn name(&self) -> &'static str {
+        match self {
+            AggregationPeriod::Daily => "daily",
+            AggregationPeriod::Weekly => "weekly",
+            AggregationPeriod::Monthly => "monthly",
+        }
+    }
+
+    /// Returns the start of the period of a date
+    pub fn start_of(&self, date: DateTime<Utc>) -> DateTime<Utc> {
+        let day = date.date_naive();
+        let first_day = match self {
+            AggregationPeriod::Daily => day,
+            AggregationPeriod::Weekly => {
+                day - chrono::Duration::days(day.weekday().num_days_from_monday() as i64)
+            }
+            AggregationPeriod::Monthly => day.with_day(1).unwrap_or(day),
+        };
+        first_day.and_time(NaiveTime::MIN).and_utc()
+    }
+
+    /// Returns the start of the period following the one starting at a date
+    pub fn next(&self, start: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            AggregationPeriod::Daily => start + chrono::Duration::days(1),
+            AggregationPeriod::Weekly => start + chrono::Duration::days(7),
+            AggregationPeriod::Monthly => start
+                .checked_add_months(Months::new(1))
+                .unwrap_or(start + chrono::Duration::days(31)),
+        }
+    }
+}
+
+/// Impacts of the resources of an account, region and value of a tag over a period
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AggregatedImpacts {
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub account_id: Option<String>,
+    pub aws_region: String,
+    /// Value of the grouping tag, none for the resources without it (or without grouping tag)
+    pub tag_value: Option<String>,
+    pub period_hours: f64,
+    /// Hours of the period covered by scans (the same for all the rows of a period)
+    pub covered_hours: f64,
+    /// Whether the impacts are extrapolated from the covered hours to the whole period
+    pub extrapolated: bool,
+    /// Number of resources over the covered hours
+    pub average_number_of_resources: f64,
+    pub adp_manufacture_kgsbeq: f64,
+    pub adp_use_kgsbeq: f64,
+    pub pe_manufacture_megajoules: f64,
+    pub pe_use_megajoules: f64,
+    pub gwp_manufacture_kgco2eq: f64,
+    pub gwp_use_kgco2eq: f64,
+}
+
+impl AggregatedImpacts {
+    /// Returns the impacts extrapolated from the covered hours to the whole period (unchanged when fully covered or not covered)
+    pub fn extrapolated(self) -> Self {
+        if self.extrapolated || self.covered_hours <= 0.0 || self.covered_hours >= self.period_hours
+        {
+            return self;
+        }
+        let scale = self.period_hours / self.covered_hours;
+        AggregatedImpacts {
+            extrapolated: true,
+            adp_manufacture_kgsbeq: self.adp_manufacture_kgsbeq * scale,
+            adp_use_kgsbeq: self.adp_use_kgsbeq * scale,
+            pe_manufacture_megajoules: self.pe_manufacture_megajoules * scale,
+            pe_use_megajoules: self.pe_use_megajoules * scale,
+            gwp_manufacture_kgco2eq: self.gwp_manufacture_kgco2eq * scale,
+            gwp_use_kgco2eq: self.gwp_use_kgco2eq * scale,
+            ..self
+        }
+    }
+}
+
+/// Accumulates the impacts of a group over a period
+#[derive(Default)]
+struct GroupTotals {
+    resource_hours: f64,
+    adp_manufacture_kgsbeq: f64,
+    adp_use_kgsbeq: f64,
+    pe_manufacture_megajoules: f64,
+    pe_use_megajoules: f64,
+    gwp_manufacture_kgco2eq: f64,
+    gwp_use_kgco2eq: f64,
+}
+
+/// Returns the impacts of the scans (with their estimated inventory) aggregated by period between two dates, per account, region and value of the tag `tag_key` (if any, with the shared resources allocated by the allocation rules), oldest period first
+#[allow(clippy::too_many_arguments)]
+pub fn aggregate_scans(
+    scans: &[(CoveredScan, EstimatedInventory)],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    period: AggregationPeriod,
+    tag_key: Option<&str>,
+    allocations: &AllocationRules,
+    extrapolate: bool,
+) -> Vec<AggregatedImpacts> {
+    let mut rows: Vec<AggregatedImpacts> = Vec::new();
+    let mut period_start = period.start_of(start);
+    while period_start < end {
+        let next_start = period.next(period_start);
+        // Periods are cut at the boundaries of the aggregation
+        let (from, until) = (period_start.max(start), next_start.min(end));
+        let mut covered_hours = 0.0;
+        let mut groups: BTreeMap<(Option<String>, String, Option<String>), GroupTotals> =
+            BTreeMap::new();
+        for (scan, estimated_inventory) in scans.iter() {
+            let hours = scan.covered_hours_between(from, until);
+            if hours <= 0.0 || scan.duration_of_use_hours <= 0.0 {
+                continue;
+            }
+            covered_hours += hours;
+            let share = hours / scan.duration_of_use_hours;
+            for resource in estimated_inventory.impacting_resources.iter() {
+                let cloud_resource = &resource.cloud_resource;
+                let allocated = match tag_key {
+                    Some(tag_key) => allocations.shares(cloud_resource, tag_key),
+                    None => vec![(None, 1.0)],
+                };
+                for (tag_value, allocated_share) in allocated {
+                    let totals = groups
+                        .entry((
+                            cloud_resource.account_id.clone(),
+                            cloud_resource.location.aws_region.clone(),
+                            tag_value,
+                        ))
+                        .or_default();
+                    totals.resource_hours += hours * allocated_share;
+                    if let Some(impacts) = resource.impacts_values.as_ref() {
+                        let share = share * allocated_share;
+                        totals.adp_manufacture_kgsbeq += impacts.adp_manufacture_kgsbeq * share;
+                        totals.adp_use_kgsbeq += impacts.adp_use_kgsbeq * share;
+                        totals.pe_manufacture_megajoules +=
+                            impacts.pe_manufacture_megajoules * share;
+                        totals.pe_use_megajoules += impacts.pe_use_megajoules * share;
+                        totals.gwp_manufacture_kgco2eq += impacts.gwp_manufacture_kgco2eq * share;
+                        totals.gwp_use_kgco2eq += impacts.gwp_use_kgco2eq * share;
+                    }
+                }
+            }
+        }
+        let period_hours = hours_between(from, until);
+        for ((account_id, aws_region, tag_value), totals) in groups.into_iter() {
+            let row = AggregatedImpacts {
+                period_start: from,
+                period_end: until,
+                account_id,
+                aws_region,
+                tag_value,
+                period_hours,
+                covered_hours,
+                extrapolated: false,
+                average_number_of_resources: totals.resource_hours / covered_hours,
+                adp_manufacture_kgsbeq: totals.adp_manufacture_kgsbeq,
+                adp_use_kgsbeq: totals.adp_use_kgsbeq,
+                pe_manufacture_megajoules: totals.pe_manufacture_megajoules,
+                pe_use_megajoules: totals.pe_use_megajoules,
+                gwp_manufacture_kgco2eq: totals.gwp_manufacture_kgco2eq,
+                gwp_use_kgco2eq: totals.gwp_use_kgco2eq,
+            };
+            rows.push(if extrapolate { row.extrapolated() } else { row });
+        }
+        period_start = next_start;
+    }
+    rows
+}
+
+/// Returns the aggregated impacts as CSV (with a header line)
+pub fn to_csv(rows: &[AggregatedImpacts]) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for row in rows.iter() {
+        writer.serialize(row)?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+/// Returns the aggregated impacts as text, one line per account, region and tag value under each period
+pub fn to_text(rows: &[AggregatedImpacts]) -> String {
+    let mut text = String::new();
+    let mut current_period = None;
+    for row in rows.iter() {
+        if current_period != Some(row.period_start) {
+            current_period = Some(row.period_start);
+            let _ = writeln!(
+                text,
+                "{} to {} ({:.1} of {:.1} hours covered{}):",
+                row.period_start.to_rfc3339(),
+                row.period_end.to_rfc3339(),
+                row.covered_hours,
+                row.period_hours,
+                if row.extrapolated {
+                    ", extrapolated"
+                } else {
+                    ""
+                }
+            );
+        }
+        let _ = writeln!(
+            text,
+            "  {} {}{}: {:.1} resources, GWP {:.3} kgCO2eq (manufacture {:.3}, use {:.3}), PE {:.3} MJ",
+            row.account_id.as_deref().unwrap_or("-"),
+            row.aws_region,
+            row.tag_value
+                .as_ref()
+                .map(|value| format!(" [{}]", value))
+                .unwrap_or_default(),
+            row.average_number_of_resources,
+            row.gwp_manufacture_kgco2eq + row.gwp_use_kgco2eq,
+            row.gwp_manufacture_kgco2eq,
+            row.gwp_use_kgco2eq,
+            row.pe_manufacture_megajoules + row.pe_use_megajoules
+        );
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+    use crate::model::{CloudProvider, CloudResource, CloudResourceTag, ResourceDetails};
+    use crate::usage_location::UsageLocation;
+
+    fn date(day: u32, hour: u32) -> DateTime<Utc> {
+        format!("2024-05-{:02}T{:02}:00:00Z", day, hour)
+            .parse()
+            .unwrap()
+    }
+
+    fn inventory(teams: &[&str], gwp_use_kgco2eq: f64) -> EstimatedInventory {
+        EstimatedInventory {
+            impacting_resources: teams
+                .iter()
+                .enumerate()
+                .map(|(index, team)| CloudResourceWithImpacts {
+                    cloud_resource: CloudResource {
+                        provider: CloudProvider::AWS,
+                        id: format!("i-{}", index),
+                        location: UsageLocation::try_from("eu-west-3").unwrap(),
+                        resource_details: ResourceDetails::Instance {
+                            instance_type: "t3.micro".to_string(),
+                            usage: None,
+                        },
+                        tags: vec![CloudResourceTag {
+                            key: "team".to_string(),
+                            value: Some(team.to_string()),
+                        }],
+                        account_id: Some("111111111111".to_string()),
+                        relationships: Vec::new(),
+                    },
+                    impacts_values: Some(ImpactsValues {
+                        gwp_use_kgco2eq,
+                        ..Default::default()
+                    }),
+                    impacts_duration_hours: 24.0,
+                    cost: None,
+                })
+                .collect(),
+            execution_statistics: None,
+            errors: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn scans_cover_the_period_until_the_next_scan_or_their_duration_of_use() {
+        let scans = vec![
+            (1, date(1, 0), 24.0),
+            (2, date(1, 12), 24.0),
+            // Gap of a day after this scan
+            (3, date(2, 0), 24.0),
+            (4, date(4, 0), 24.0),
+        ];
+        let covered = covered_scans(&scans, date(1, 6), date(4, 12));
+        assert_eq!(
+            vec![6.0, 12.0, 24.0, 12.0],
+            covered
+                .iter()
+                .map(|scan| scan.covered_hours())
+                .collect::<Vec<_>>()
+        );
+        assert!(covered_scans(&scans, date(5, 0), date(6, 0)).is_empty());
+    }
+
+    #[test]
+    fn periods_start_on_days_mondays_and_first_days_of_months() {
+        // 2024-05-15 is a Wednesday
+        let date = date(15, 10);
+        assert_eq!(
+            "2024-05-15T00:00:00+00:00",
+            AggregationPeriod::Daily.start_of(date).to_rfc3339()
+        );
+        assert_eq!(
+            "2024-05-13T00:00:00+00:00",
+            AggregationPeriod::Weekly.start_of(date).to_rfc3339()
+        );
+        assert_eq!(
+            "2024-05-01T00:00:00+00:00",
+            AggregationPeriod::Monthly.start_of(date).to_rfc3339()
+        );
+        assert_eq!(
+            "2024-06-01T00:00:00+00:00",
+            AggregationPeriod::Monthly
+                .next(AggregationPeriod::Monthly.start_of(date))
+                .to_rfc3339()
+        );
+        assert!(AggregationPeriod::try_from("hourly").is_err());
+    }
+
+    #[test]
+    fn scans_of_varying_frequencies_are_prorated_to_the_days() {
+        let scans = vec![
+            // A scan of a day, then scans every 12 hours with a gap on the 3rd
+            (1, date(1, 0), 24.0),
+            (2, date(2, 0), 12.0),
+            (3, date(2, 12), 12.0),
+            (4, date(3, 12), 12.0),
+        ];
+        // 1 kgCO2eq a day for each resource
+        let inventories = [
+            inventory(&["a"], 1.0),
+            inventory(&["a", "b"], 0.5),
+            inventory(&["a", "b"], 0.5),
+            inventory(&["a"], 0.5),
+        ];
+        let scans: Vec<(CoveredScan, EstimatedInventory)> =
+            covered_scans(&scans, date(1, 0), date(4, 0))
+                .into_iter()
+                .zip(inventories)
+                .collect();
+        let rows = aggregate_scans(
+            &scans,
+            date(1, 0),
+            date(4, 0),
+            AggregationPeriod::Daily,
+            Some("team"),
+            &AllocationRules::default(),
+            false,
+        );
+        let gwp: Vec<(u32, Option<&str>, f64, f64)> = rows
+            .iter()
+            .map(|row| {
+                (
+                    row.period_start.day(),
+                    row.tag_value.as_deref(),
+                    row.covered_hours,
+                    row.gwp_use_kgco2eq,
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (1, Some("a"), 24.0, 1.0),
+                (2, Some("a"), 24.0, 1.0),
+                (2, Some("b"), 24.0, 1.0),
+                (3, Some("a"), 12.0, 0.5),
+            ],
+            gwp
+        );
+
+        let extrapolated = aggregate_scans(
+            &scans,
+            date(1, 0),
+            date(4, 0),
+            AggregationPeriod::Monthly,
+            None,
+            &AllocationRules::default(),
+            true,
+        );
+        assert_eq!(1, extrapolated.len());
+        // 3.5 kgCO2eq over 60 of 72 hours
+        assert!(extrapolated[0].extrapolated);
+        assert!((extrapolated[0].gwp_use_kgco2eq - 4.2).abs() < 1e-9);
+        assert!(to_csv(&extrapolated)
+            .unwrap()
+            .starts_with("period_start,period_end,account_id,aws_region,tag_value"));
+    }
+}
@@ -0,0 +1,453 @@
+//! A module to compare two scans (json outputs of the `estimate` command) and report the resources added, removed or changed, with the delta of total impacts.
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+use crate::model::{EstimatedInventory, InstanceState, ResourceDetails};
+use crate::result_envelope::ResultMetadata;
+
+/// Impacts differences smaller than this are considered as rounding noise
+const EPSILON: f64 = 1e-9;
+
+/// Difference between an old and a new value
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct ValueDelta {
+    pub old: f64,
+    pub new: f64,
+    /// new - old
+    pub absolute: f64,
+    /// Relative change in percent of the old value (not defined when the old value is 0)
+    pub percent: Option<f64>,
+}
+
+impl ValueDelta {
+    pub fn new(old: f64, new: f64) -> Self {
+        let absolute = new - old;
+        let percent = if old.abs() > EPSILON {
+            Some(absolute / old * 100.0)
+        } else {
+            None
+        };
+        ValueDelta {
+            old,
+            new,
+            absolute,
+            percent,
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.absolute.abs() <= EPSILON
+    }
+}
+
+/// A resource that exists in only one of the scans
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct ResourceEntry {
+    pub resource_id: String,
+    pub resource_kind: String,
+    pub resource_type: Option<String>,
+    /// Total GWP (manufacture and use), if the resource was assessed
+    pub gwp_kgco2eq: Option<f64>,
+}
+
+/// A resource that exists in both scans, with different characteristics or impacts
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct ResourceChange {
+    pub resource_id: String,
+    pub resource_kind: String,
+    /// Description of the changes of characteristics (like `type t3.micro -> t3.large`)
+    pub changes: Vec<String>,
+    /// Delta of total GWP (manufacture and use)
+    pub gwp_kgco2eq: ValueDelta,
+}
+
+/// Delta of the totals of two scans
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct TotalsDelta {
+    pub number_of_resources: ValueDelta,
+    pub number_of_resources_assessed: ValueDelta,
+    pub adp_manufacture_kgsbeq: ValueDelta,
+    pub adp_use_kgsbeq: ValueDelta,
+    pub pe_manufacture_megajoules: ValueDelta,
+    pub pe_use_megajoules: ValueDelta,
+    pub gwp_manufacture_kgco2eq: ValueDelta,
+    pub gwp_use_kgco2eq: ValueDelta,
+}
+
+/// The differences between two scans
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct ScanDiff {
+    pub added: Vec<ResourceEntry>,
+    pub removed: Vec<ResourceEntry>,
+    pub changed: Vec<ResourceChange>,
+    pub totals: TotalsDelta,
+}
+
+fn total_gwp(impacts: &Option<ImpactsValues>) -> Option<f64> {
+    impacts
+        .as_ref()
+        .map(|i| i.gwp_manufacture_kgco2eq + i.gwp_use_kgco2eq)
+}
+
+fn entry(resource: &CloudResourceWithImpacts) -> ResourceEntry {
+    let details = &resource.cloud_resource.resource_details;
+    ResourceEntry {
+        resource_id: resource.cloud_resource.id.clone(),
+        resource_kind: details.kind().to_string(),
+        resource_type: details.resource_type(),
+        gwp_kgco2eq: total_gwp(&resource.impacts_values),
+    }
+}
+
+fn state(details: &ResourceDetails) -> Option<&'static str> {
+    match details {
+        ResourceDetails::Instance {
+            usage: Some(usage), ..
+        } => match usage.state {
+            InstanceState::Running => Some("Running"),
+            InstanceState::Stopped => Some("Stopped"),
+        },
+        _ => None,
+    }
+}
+
+/// Returns the changes of characteristics between two versions of a resource
+fn characteristics_changes(
+    old: &CloudResourceWithImpacts,
+    new: &CloudResourceWithImpacts,
+) -> Vec<String> {
+    let old_details = &old.cloud_resource.resource_details;
+    let new_details = &new.cloud_resource.resource_details;
+    let mut changes = Vec::new();
+    if old_details.kind() != new_details.kind() {
+        changes.push(format!(
+            "kind {} -> {}",
+            old_details.kind(),
+            new_details.kind()
+        ));
+    }
+    if old_details.resource_type() != new_details.resource_type() {
+        changes.push(format!(
+            "type {} -> {}",
+            old_details.resource_type().unwrap_or_default(),
+            new_details.resource_type().unwrap_or_default()
+        ));
+    }
+    if state(old_details) != state(new_details) {
+        changes.push(format!(
+            "state {} -> {}",
+            state(old_details).unwrap_or("unknown"),
+            state(new_details).unwrap_or("unknown")
+        ));
+    }
+    match (&old.impacts_values, &new.impacts_values) {
+        (Some(_), None) => changes.push("no longer assessed".to_string()),
+        (None, Some(_)) => changes.push("now assessed".to_string()),
+        _ => {}
+    }
+    changes
+}
+
+fn impacts_changed(old: &Option<ImpactsValues>, new: &Option<ImpactsValues>) -> bool {
+    match (old, new) {
+        (Some(o), Some(n)) => [
+            n.adp_manufacture_kgsbeq - o.adp_manufacture_kgsbeq,
+            n.adp_use_kgsbeq - o.adp_use_kgsbeq,
+            n.pe_manufacture_megajoules - o.pe_manufacture_megajoules,
+            n.pe_use_megajoules - o.pe_use_megajoules,
+            n.gwp_manufacture_kgco2eq - o.gwp_manufacture_kgco2eq,
+            n.gwp_use_kgco2eq - o.gwp_use_kgco2eq,
+        ]
+        .iter()
+        .any(|d| d.abs() > EPSILON),
+        (None, None) => false,
+        _ => true,
+    }
+}
+
+/// Sum of the impacts of the assessed resources of a scan
+fn totals(inventory: &EstimatedInventory) -> (usize, ImpactsValues) {
+    let mut assessed = 0;
+    let mut sum = ImpactsValues::default();
+    for impacts in inventory
+        .impacting_resources
+        .iter()
+        .filter_map(|r| r.impacts_values.as_ref())
+    {
+        assessed += 1;
+        sum.adp_manufacture_kgsbeq += impacts.adp_manufacture_kgsbeq;
+        sum.adp_use_kgsbeq += impacts.adp_use_kgsbeq;
+        sum.pe_manufacture_megajoules += impacts.pe_manufacture_megajoules;
+        sum.pe_use_megajoules += impacts.pe_use_megajoules;
+        sum.gwp_manufacture_kgco2eq += impacts.gwp_manufacture_kgco2eq;
+        sum.gwp_use_kgco2eq += impacts.gwp_use_kgco2eq;
+    }
+    (assessed, sum)
+}
+
+/// Compares two scans, resources are matched by id
+pub fn diff_scans(old: &EstimatedInventory, new: &EstimatedInventory) -> ScanDiff {
+    let old_by_id: BTreeMap<&str, &CloudResourceWithImpacts> = old
+        .impacting_resources
+        .iter()
+        .map(|r| (r.cloud_resource.id.as_str(), r))
+        .collect();
+    let new_by_id: BTreeMap<&str, &CloudResourceWithImpacts> = new
+        .impacting_resources
+        .iter()
+        .map(|r| (r.cloud_resource.id.as_str(), r))
+        .collect();
+
+    let added = new_by_id
+        .iter()
+        .filter(|(id, _)| !old_by_id.contains_key(*id))
+        .map(|(_, r)| entry(r))
+        .collect();
+    let removed = old_by_id
+        .iter()
+        .filter(|(id, _)| !new_by_id.contains_key(*id))
+        .map(|(_, r)| entry(r))
+        .collect();
+    let mut changed = Vec::new();
+    for (id, old_resource) in old_by_id.iter() {
+        let Some(new_resource) = new_by_id.get(id) else {
+            continue;
+        };
+        let changes = characteristics_changes(old_resource, new_resource);
+        if changes.is_empty()
+            && !impacts_changed(&old_resource.impacts_values, &new_resource.impacts_values)
+        {
+            continue;
+        }
+        changed.push(ResourceChange {
+            resource_id: id.to_string(),
+            resource_kind: new_resource
+                .cloud_resource
+                .resource_details
+                .kind()
+                .to_string(),
+            changes,
+            gwp_kgco2eq: ValueDelta::new(
+                total_gwp(&old_resource.impacts_values).unwrap_or(0.0),
+                total_gwp(&new_resource.impacts_values).unwrap_or(0.0),
+            ),
+        });
+    }
+
+    let (old_assessed, old_totals) = totals(old);
+    let (new_assessed, new_totals) = totals(new);
+    let totals = TotalsDelta {
+        number_of_resources: ValueDelta::new(
+            old.impacting_resources.len() as f64,
+            new.impacting_resources.len() as f64,
+        ),
+        number_of_resources_assessed: ValueDelta::new(old_assessed as f64, new_assessed as f64),
+        adp_manufacture_kgsbeq: ValueDelta::new(
+            old_totals.adp_manufacture_kgsbeq,
+            new_totals.adp_manufacture_kgsbeq,
+        ),
+        adp_use_kgsbeq: ValueDelta::new(old_totals.adp_use_kgsbeq, new_totals.adp_use_kgsbeq),
+        pe_manufacture_megajoules: ValueDelta::new(
+            old_totals.pe_manufacture_megajoules,
+            new_totals.pe_manufacture_megajoules,
+        ),
+        pe_use_megajoules: ValueDelta::new(
+            old_totals.pe_use_megajoules,
+            new_totals.pe_use_megajoules,
+        ),
+        gwp_manufacture_kgco2eq: ValueDelta::new(
+            old_totals.gwp_manufacture_kgco2eq,
+            new_totals.gwp_manufacture_kgco2eq,
+        ),
+        gwp_use_kgco2eq: ValueDelta::new(old_totals.gwp_use_kgco2eq, new_totals.gwp_use_kgco2eq),
+    };
+
+    ScanDiff {
+        added,
+        removed,
+        changed,
+        totals,
+    }
+}
+
+/// Parses the json output of the `estimate` command, its impacts being converted to the default units (in which scans are compared)
+pub fn parse_scan(content: &str) -> Result<(Option<ResultMetadata>, EstimatedInventory)> {
+    let (metadata, scan) = crate::result_envelope::read_results(content)
+        .context("Expected the json output of the estimate command")?;
+    let scan = match metadata.as_ref().and_then(|metadata| metadata.units) {
+        Some(units) => units.revert_inventory(scan),
+        None => scan,
+    };
+    Ok((metadata, scan))
+}
+
+/// Reads an estimated inventory from a json file (output of the `estimate` command)
+pub fn read_scan(path: &str) -> Result<EstimatedInventory> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("Cannot read scan {}", path))?;
+    let (_, scan) = parse_scan(&content).with_context(|| format!("Cannot parse scan {}", path))?;
+    Ok(scan)
+}
+
+fn format_delta(delta: &ValueDelta) -> String {
+    let percent = match delta.percent {
+        Some(p) => format!(", {:+.1}%", p),
+        None => "".to_string(),
+    };
+    format!(
+        "{} -> {} ({:+}{})",
+        delta.old, delta.new, delta.absolute, percent
+    )
+}
+
+fn format_entry(sign: char, entry: &ResourceEntry) -> String {
+    let resource_type = match &entry.resource_type {
+        Some(t) => format!(" {}", t),
+        None => "".to_string(),
+    };
+    let gwp = match entry.gwp_kgco2eq {
+        Some(gwp) => format!("GWP {} kgCO2eq", gwp),
+        None => "not assessed".to_string(),
+    };
+    format!(
+        "  {} {} ({}{}) {}\n",
+        sign, entry.resource_id, entry.resource_kind, resource_type, gwp
+    )
+}
+
+/// Formats the differences between two scans as human readable text
+pub fn to_text(diff: &ScanDiff) -> String {
+    let mut text = String::new();
+    let _ = writeln!(text, "Added resources ({}):", diff.added.len());
+    for entry in diff.added.iter() {
+        text.push_str(&format_entry('+', entry));
+    }
+    let _ = writeln!(text, "Removed resources ({}):", diff.removed.len());
+    for entry in diff.removed.iter() {
+        text.push_str(&format_entry('-', entry));
+    }
+    let _ = writeln!(text, "Changed resources ({}):", diff.changed.len());
+    for change in diff.changed.iter() {
+        let mut details = change.changes.clone();
+        if !change.gwp_kgco2eq.is_zero() {
+            details.push(format!("GWP {} kgCO2eq", format_delta(&change.gwp_kgco2eq)));
+        }
+        let _ = writeln!(
+            text,
+            "  ~ {} ({}): {}",
+            change.resource_id,
+            change.resource_kind,
+            details.join(", ")
+        );
+    }
+    let totals = &diff.totals;
+    let _ = writeln!(text, "Totals:");
+    for (label, delta) in [
+        ("Resources", &totals.number_of_resources),
+        ("Resources assessed", &totals.number_of_resources_assessed),
+        ("GWP manufacture (kgCO2eq)", &totals.gwp_manufacture_kgco2eq),
+        ("GWP use (kgCO2eq)", &totals.gwp_use_kgco2eq),
+        (
+            "Primary energy manufacture (MJ)",
+            &totals.pe_manufacture_megajoules,
+        ),
+        ("Primary energy use (MJ)", &totals.pe_use_megajoules),
+        ("ADP manufacture (kgSbeq)", &totals.adp_manufacture_kgsbeq),
+        ("ADP use (kgSbeq)", &totals.adp_use_kgsbeq),
+    ] {
+        let _ = writeln!(text, "  {}: {}", label, format_delta(delta));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{CloudProvider, CloudResource, InstanceUsage};
+    use crate::usage_location::UsageLocation;
+
+    fn instance(id: &str, instance_type: &str, gwp_use_kgco2eq: f64) -> CloudResourceWithImpacts {
+        CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: id.to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details: ResourceDetails::Instance {
+                    instance_type: instance_type.to_string(),
+                    usage: Some(InstanceUsage {
+                        average_cpu_load: 10.0,
+                        usage_duration_seconds: 3600,
+                        state: InstanceState::Running,
+                    }),
+                },
+                tags: Vec::new(),
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues {
+                gwp_use_kgco2eq,
+                ..Default::default()
+            }),
+            impacts_duration_hours: 1.0,
+            cost: None,
+        }
+    }
+
+    fn inventory(resources: Vec<CloudResourceWithImpacts>) -> EstimatedInventory {
+        EstimatedInventory {
+            impacting_resources: resources,
+            execution_statistics: None,
+            errors: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn percent_is_not_defined_for_a_zero_old_value() {
+        assert_eq!(Some(50.0), ValueDelta::new(2.0, 3.0).percent);
+        assert_eq!(None, ValueDelta::new(0.0, 3.0).percent);
+    }
+
+    #[test]
+    fn diff_detects_added_removed_and_changed_resources() {
+        let old = inventory(vec![
+            instance("kept", "t3.micro", 1.0),
+            instance("resized", "t3.micro", 1.0),
+            instance("removed", "t3.micro", 1.0),
+        ]);
+        let new = inventory(vec![
+            instance("kept", "t3.micro", 1.0),
+            instance("resized", "t3.large", 3.0),
+            instance("added", "m6g.xlarge", 2.0),
+        ]);
+
+        let diff = diff_scans(&old, &new);
+        assert_eq!(1, diff.added.len());
+        assert_eq!("added", diff.added[0].resource_id);
+        assert_eq!(1, diff.removed.len());
+        assert_eq!("removed", diff.removed[0].resource_id);
+        assert_eq!(1, diff.changed.len());
+        assert_eq!("resized", diff.changed[0].resource_id);
+        assert_eq!(vec!["type t3.micro -> t3.large"], diff.changed[0].changes);
+        assert_eq!(2.0, diff.changed[0].gwp_kgco2eq.absolute);
+        assert_eq!(ValueDelta::new(3.0, 6.0), diff.totals.gwp_use_kgco2eq);
+
+        let text = to_text(&diff);
+        assert!(text.contains("  + added (Instance m6g.xlarge) GWP 2 kgCO2eq\n"));
+        assert!(text.contains(
+            "  ~ resized (Instance): type t3.micro -> t3.large, GWP 1 -> 3 (+2, +200.0%) kgCO2eq\n"
+        ));
+        assert!(text.contains("  GWP use (kgCO2eq): 3 -> 6 (+3, +100.0%)\n"));
+    }
+
+    #[test]
+    fn identical_scans_have_no_changes() {
+        let scan = inventory(vec![instance("kept", "t3.micro", 1.0)]);
+        let diff = diff_scans(&scan, &scan.clone());
+        assert!(diff.added.is_empty() && diff.removed.is_empty() && diff.changed.is_empty());
+        assert_eq!(Some(0.0), diff.totals.gwp_use_kgco2eq.percent);
+    }
+}
@@ -0,0 +1,204 @@
+//! Errors that do not abort scans: the scan continues past the failure of the inventory of a region, of the CPU loads of a batch of instances or of the impacts of a resource, and reports these errors in the `errors` of its results (by phase of the scan).
+//!
+//! Errors of the utilization metrics and of the impact estimation are recorded wherever they occur, and collected by the scan of their region (see [collect]).
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use crate::model::{CloudResource, EstimatedInventory};
+
+/// Phases of a scan
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanPhase {
+    /// Listing the resources of a region
+    Inventory,
+    /// Reading the CPU loads of instances from CloudWatch
+    UtilizationMetrics,
+    /// Estimating the impacts of resources with Boavizta API
+    ImpactEstimation,
+}
+
+/// An error that did not abort a scan
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanError {
+    pub phase: ScanPhase,
+    /// Account of the scan (not set for the account of the environment)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_id: Option<String>,
+    pub aws_region: String,
+    /// Resource whose impacts or CPU load are missing, not set for the errors of a whole region or batch of instances
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource_id: Option<String>,
+    pub message: String,
+    /// The budget of AWS API calls of the scan was spent (see [crate::aws_settings]): the resources or CPU loads of the phase were not all read
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub budget_exhausted: bool,
+}
+
+impl ScanError {
+    /// Returns the error of a region (of an account, empty for the account of the environment)
+    pub fn of_region(
+        phase: ScanPhase,
+        account_id: &str,
+        aws_region: &str,
+        message: String,
+    ) -> Self {
+        ScanError {
+            phase,
+            account_id: (!account_id.is_empty()).then(|| account_id.to_string()),
+            aws_region: aws_region.to_string(),
+            resource_id: None,
+            message,
+            budget_exhausted: false,
+        }
+    }
+
+    /// Returns the error of a region whose scan stopped calling AWS APIs, its budget of calls being spent
+    pub fn budget_exhausted(
+        phase: ScanPhase,
+        account_id: &str,
+        aws_region: &str,
+        message: String,
+    ) -> Self {
+        ScanError {
+            budget_exhausted: true,
+            ..Self::of_region(phase, account_id, aws_region, message)
+        }
+    }
+
+    /// Returns the error of a resource
+    pub fn of_resource(phase: ScanPhase, resource: &CloudResource, message: String) -> Self {
+        ScanError {
+            phase,
+            account_id: resource.account_id.clone(),
+            aws_region: resource.location.aws_region.clone(),
+            resource_id: Some(resource.id.clone()),
+            message,
+            budget_exhausted: false,
+        }
+    }
+}
+
+tokio::task_local! {
+    static SCAN_ERRORS: Arc<Mutex<Vec<ScanError>>>;
+}
+
+/// Runs a scan (or a part of it), and returns its output with the errors recorded while it ran
+pub(crate) async fn collect<F: Future>(future: F) -> (F::Output, Vec<ScanError>) {
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let output = SCAN_ERRORS.scope(errors.clone(), future).await;
+    let errors = std::mem::take(&mut *errors.lock().unwrap());
+    (output, errors)
+}
+
+/// Records an error of the current scan (only logged by callers outside of a scan)
+pub(crate) fn record(error: ScanError) {
+    let _ = SCAN_ERRORS.try_with(|errors| errors.lock().unwrap().push(error));
+}
+
+/// Fails when the inventory of all the scanned regions failed (like with invalid credentials), instead of returning empty results, and warns of the errors the scan continued past otherwise (inventories cut off by the budget of AWS API calls did not fail)
+pub(crate) fn check_inventories(
+    estimated_inventory: EstimatedInventory,
+    scanned_regions: usize,
+) -> anyhow::Result<EstimatedInventory> {
+    let failed_inventories: Vec<&ScanError> = estimated_inventory
+        .errors
+        .iter()
+        .filter(|error| error.phase == ScanPhase::Inventory && !error.budget_exhausted)
+        .collect();
+    if let Some(first) = failed_inventories.first() {
+        if failed_inventories.len() >= scanned_regions {
+            anyhow::bail!("{}", first.message);
+        }
+    }
+    if !estimated_inventory.errors.is_empty() {
+        warn!(
+            "The scan continued past {} errors, reported in the errors of its results",
+            estimated_inventory.errors.len()
+        );
+    }
+    Ok(estimated_inventory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn errors_are_collected_by_their_scan() {
+        let scan = |region: &'static str| async move {
+            record(ScanError::of_region(
+                ScanPhase::UtilizationMetrics,
+                "",
+                region,
+                "Throttled".to_string(),
+            ));
+            tokio::task::yield_now().await;
+            region
+        };
+        // Concurrent scans keep their own errors
+        let ((first, first_errors), (second, second_errors)) =
+            tokio::join!(collect(scan("eu-west-1")), collect(scan("eu-west-3")));
+        assert_eq!(("eu-west-1", "eu-west-3"), (first, second));
+        assert_eq!(1, first_errors.len());
+        assert_eq!("eu-west-1", first_errors[0].aws_region);
+        assert_eq!(None, first_errors[0].account_id);
+        assert_eq!("eu-west-3", second_errors[0].aws_region);
+
+        // Errors recorded outside of a scan are dropped
+        record(ScanError::of_region(
+            ScanPhase::Inventory,
+            "111111111111",
+            "eu-west-1",
+            "Access denied".to_string(),
+        ));
+        let json = serde_json::to_value(&first_errors[0]).unwrap();
+        assert_eq!("utilization_metrics", json["phase"]);
+        assert_eq!("eu-west-1", json["awsRegion"]);
+        assert!(json.get("resourceId").is_none());
+    }
+
+    #[test]
+    fn scans_fail_only_when_all_inventories_fail() {
+        let inventory = |errors: Vec<ScanError>| EstimatedInventory {
+            impacting_resources: Vec::new(),
+            execution_statistics: None,
+            errors,
+        };
+        let failed = |region: &str| {
+            ScanError::of_region(
+                ScanPhase::Inventory,
+                "",
+                region,
+                format!("Cannot perform resources inventory of {}", region),
+            )
+        };
+        let throttled = ScanError::of_region(
+            ScanPhase::UtilizationMetrics,
+            "",
+            "eu-west-3",
+            "Throttled".to_string(),
+        );
+        let partial = check_inventories(inventory(vec![failed("eu-west-1"), throttled]), 2);
+        assert_eq!(2, partial.unwrap().errors.len());
+        let all_failed =
+            check_inventories(inventory(vec![failed("eu-west-1"), failed("eu-west-3")]), 2);
+        assert_eq!(
+            "Cannot perform resources inventory of eu-west-1",
+            all_failed.err().unwrap().to_string()
+        );
+        assert!(check_inventories(inventory(Vec::new()), 1).is_ok());
+        assert!(check_inventories(inventory(vec![failed("eu-west-1")]), 1).is_err());
+        // Inventories cut off by the budget of API calls are partial, not failed
+        let cut_off = ScanError::budget_exhausted(
+            ScanPhase::Inventory,
+            "",
+            "eu-west-1",
+            "Budget of AWS API calls spent".to_string(),
+        );
+        assert!(check_inventories(inventory(vec![cut_off]), 1).is_ok());
+    }
+}
@@ -0,0 +1,305 @@
+//! Commands run before and after the scans of the estimate command (`[hooks]` of the configuration file, or `--pre-scan-hook` and `--post-scan-hook`), for lightweight integrations like creating tickets or uploading results without changing cloud-scanner.
+//!
+//! ```toml
+//! [hooks]
+//! pre_scan = ["aws sso login --profile prod"]
+//! post_scan = ["aws s3 cp \"$CLOUD_SCANNER_RESULT_FILE\" s3://reports/latest.json"]
+//! timeout_seconds = 300
+//! ```
+//!
+//! Commands are run by the shell (`sh -c`, `cmd /C` on Windows) in order, their output being logged, with these variables:
+//! - `CLOUD_SCANNER_HOOK`: `pre_scan` or `post_scan`
+//! - `CLOUD_SCANNER_USE_DURATION_HOURS`, and `CLOUD_SCANNER_OUTPUT` when the results are written to an output
+//! - after the scan: `CLOUD_SCANNER_RESULT_FILE` (the json output of the estimate command, in the default units, removed after the hooks), `CLOUD_SCANNER_REGIONS`, `CLOUD_SCANNER_NUMBER_OF_RESOURCES`, `CLOUD_SCANNER_NUMBER_OF_RESOURCES_ASSESSED`, `CLOUD_SCANNER_GWP_MANUFACTURE_KGCO2EQ`, `CLOUD_SCANNER_GWP_USE_KGCO2EQ`, `CLOUD_SCANNER_PE_MANUFACTURE_MEGAJOULES`, `CLOUD_SCANNER_PE_USE_MEGAJOULES`, `CLOUD_SCANNER_ADP_MANUFACTURE_KGSBEQ`, `CLOUD_SCANNER_ADP_USE_KGSBEQ`, `CLOUD_SCANNER_NUMBER_OF_ERRORS` and `CLOUD_SCANNER_NUMBER_OF_BREACHES` (of `--fail-if-*`)
+//!
+//! A failing pre-scan hook (non zero exit status) cancels the scan, a failing post-scan hook fails the command after the results were written.
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::impact_provider::ImpactsSummary;
+
+/// Commands run before and after scans
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HooksConfig {
+    #[serde(default)]
+    pub pre_scan: Vec<String>,
+    #[serde(default)]
+    pub post_scan: Vec<String>,
+    /// Seconds after which a hook is stopped and fails (no timeout by default)
+    pub timeout_seconds: Option<u64>,
+}
+
+/// When hooks are run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HookStage {
+    PreScan,
+    PostScan,
+}
+
+impl HookStage {
+    fn name(&self) -> &'static str {
+        match self {
+            HookStage::PreScan => "pre_scan",
+            HookStage::PostScan => "post_scan",
+        }
+    }
+}
+
+/// Results of a scan described to post-scan hooks
+pub struct ScanOutcome<'a> {
+    /// The json output of the estimate command
+    pub results_json: &'a str,
+    pub regions: &'a [String],
+    pub summary: &'a ImpactsSummary,
+    pub number_of_errors: usize,
+    pub number_of_breaches: usize,
+}
+
+impl HooksConfig {
+    /// Returns the hooks with the commands of the command line run after the commands of the configuration
+    pub fn with_commands(mut self, pre_scan: Vec<String>, post_scan: Vec<String>) -> Self {
+        self.pre_scan.extend(pre_scan);
+        self.post_scan.extend(post_scan);
+        self
+    }
+
+    pub fn has_post_scan(&self) -> bool {
+        !self.post_scan.is_empty()
+    }
+
+    /// Runs the pre-scan hooks, failing on the first failing hook
+    pub async fn run_pre_scan(&self, variables: &[(String, String)]) -> Result<()> {
+        self.run(HookStage::PreScan, &self.pre_scan, variables)
+            .await
+    }
+
+    /// Runs the post-scan hooks with the variables of the outcome of the scan, its results being written to a temporary file during the hooks
+    pub async fn run_post_scan(
+        &self,
+        variables: &[(String, String)],
+        outcome: &ScanOutcome<'_>,
+    ) -> Result<()> {
+        if self.post_scan.is_empty() {
+            return Ok(());
+        }
+        // Created exclusively with a random name (readable by the user only on unix), and removed when dropped
+        let mut result_file = tempfile::Builder::new()
+            .prefix("cloud-scanner-results-")
+            .suffix(".json")
+            .tempfile()
+            .context("Cannot create a temporary file for the results")?;
+        result_file
+            .write_all(outcome.results_json.as_bytes())
+            .and_then(|()| result_file.flush())
+            .with_context(|| {
+                format!(
+                    "Cannot write the results to {}",
+                    result_file.path().display()
+                )
+            })?;
+        let mut variables = variables.to_vec();
+        variables.extend(outcome_variables(outcome, result_file.path()));
+        let run = self
+            .run(HookStage::PostScan, &self.post_scan, &variables)
+            .await;
+        result_file
+            .close()
+            .context("Cannot remove the temporary file of the results")?;
+        run
+    }
+
+    async fn run(
+        &self,
+        stage: HookStage,
+        commands: &[String],
+        variables: &[(String, String)],
+    ) -> Result<()> {
+        let timeout = self.timeout_seconds.map(Duration::from_secs);
+        for command in commands {
+            run_hook(stage, command, variables, timeout)
+                .await
+                .with_context(|| format!("Failure of {} hook {}", stage.name(), command))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the variables of a scan passed to every hook
+pub fn scan_variables(use_duration_hours: f32, output: Option<&str>) -> Vec<(String, String)> {
+    let mut variables = vec![(
+        "CLOUD_SCANNER_USE_DURATION_HOURS".to_string(),
+        use_duration_hours.to_string(),
+    )];
+    if let Some(output) = output.filter(|output| *output != "-") {
+        variables.push(("CLOUD_SCANNER_OUTPUT".to_string(), output.to_string()));
+    }
+    variables
+}
+
+/// Returns the variables of the outcome of a scan passed to post-scan hooks
+fn outcome_variables(outcome: &ScanOutcome<'_>, result_file: &Path) -> Vec<(String, String)> {
+    let summary = outcome.summary;
+    [
+        (
+            "CLOUD_SCANNER_RESULT_FILE",
+            result_file.display().to_string(),
+        ),
+        ("CLOUD_SCANNER_REGIONS", outcome.regions.join(",")),
+        (
+            "CLOUD_SCANNER_NUMBER_OF_RESOURCES",
+            summary.number_of_resources_total.to_string(),
+        ),
+        (
+            "CLOUD_SCANNER_NUMBER_OF_RESOURCES_ASSESSED",
+            summary.number_of_resources_assessed.to_string(),
+        ),
+        (
+            "CLOUD_SCANNER_GWP_MANUFACTURE_KGCO2EQ",
+            summary.gwp_manufacture_kgco2eq.to_string(),
+        ),
+        (
+            "CLOUD_SCANNER_GWP_USE_KGCO2EQ",
+            summary.gwp_use_kgco2eq.to_string(),
+        ),
+        (
+            "CLOUD_SCANNER_PE_MANUFACTURE_MEGAJOULES",
+            summary.pe_manufacture_megajoules.to_string(),
+        ),
+        (
+            "CLOUD_SCANNER_PE_USE_MEGAJOULES",
+            summary.pe_use_megajoules.to_string(),
+        ),
+        (
+            "CLOUD_SCANNER_ADP_MANUFACTURE_KGSBEQ",
+            summary.adp_manufacture_kgsbeq.to_string(),
+        ),
+        (
+            "CLOUD_SCANNER_ADP_USE_KGSBEQ",
+            summary.adp_use_kgsbeq.to_string(),
+        ),
+        (
+            "CLOUD_SCANNER_NUMBER_OF_ERRORS",
+            outcome.number_of_errors.to_string(),
+        ),
+        (
+            "CLOUD_SCANNER_NUMBER_OF_BREACHES",
+            outcome.number_of_breaches.to_string(),
+        ),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value))
+    .collect()
+}
+
+/// Runs a command with the shell, logging its output, failing when its exit status is not zero
+async fn run_hook(
+    stage: HookStage,
+    command: &str,
+    variables: &[(String, String)],
+    timeout: Option<Duration>,
+) -> Result<()> {
+    #[cfg(unix)]
+    let mut shell = tokio::process::Command::new("sh");
+    #[cfg(unix)]
+    shell.arg("-c");
+    #[cfg(not(unix))]
+    let mut shell = tokio::process::Command::new("cmd");
+    #[cfg(not(unix))]
+    shell.arg("/C");
+    shell
+        .arg(command)
+        .env("CLOUD_SCANNER_HOOK", stage.name())
+        .envs(variables.iter().map(|(name, value)| (name, value)))
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true);
+    debug!("Running {} hook {}", stage.name(), command);
+    let output = shell.output();
+    let output = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, output)
+            .await
+            .with_context(|| format!("Timeout after {} seconds", timeout.as_secs()))?,
+        None => output.await,
+    }
+    .context("Cannot run the command")?;
+    for line in String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .chain(String::from_utf8_lossy(&output.stderr).lines())
+    {
+        info!("Hook {}: {}", command, line);
+    }
+    if !output.status.success() {
+        anyhow::bail!(
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn failing_pre_scan_hooks_cancel_the_scan() {
+        let hooks = HooksConfig::default().with_commands(
+            vec!["test \"$CLOUD_SCANNER_HOOK\" = pre_scan".to_string()],
+            vec![],
+        );
+        let variables = scan_variables(730.0, None);
+        assert!(hooks.run_pre_scan(&variables).await.is_ok());
+
+        let hooks =
+            hooks.with_commands(vec!["echo no credentials >&2; exit 3".to_string()], vec![]);
+        let error = hooks.run_pre_scan(&variables).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("no credentials"));
+
+        let hooks = HooksConfig {
+            pre_scan: vec!["sleep 5".to_string()],
+            timeout_seconds: Some(0),
+            ..Default::default()
+        };
+        assert!(hooks.run_pre_scan(&variables).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn post_scan_hooks_read_the_results() {
+        let copy = std::env::temp_dir().join(format!("cloud-scanner-hook-{}", std::process::id()));
+        let hooks = HooksConfig::default().with_commands(
+            vec![],
+            vec![format!(
+                "echo \"$CLOUD_SCANNER_REGIONS $CLOUD_SCANNER_NUMBER_OF_RESOURCES $CLOUD_SCANNER_OUTPUT\" > {0}; cat \"$CLOUD_SCANNER_RESULT_FILE\" >> {0}; echo >> {0}; echo \"$CLOUD_SCANNER_RESULT_FILE\" >> {0}",
+                copy.display()
+            )],
+        );
+        let estimated_inventory = crate::model::EstimatedInventory {
+            impacting_resources: Vec::new(),
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let regions = vec!["eu-west-3".to_string(), "eu-west-1".to_string()];
+        let summary =
+            crate::build_summary_of_accounts(&estimated_inventory, &[], &regions, &730.0).unwrap();
+        let outcome = ScanOutcome {
+            results_json: "{\"data\":{}}",
+            regions: &regions,
+            summary: &summary,
+            number_of_errors: 0,
+            number_of_breaches: 0,
+        };
+        hooks
+            .run_post_scan(&scan_variables(730.0, Some("results.json")), &outcome)
+            .await
+            .unwrap();
+        let copied = std::fs::read_to_string(&copy).unwrap();
+        let (copied, result_file) = copied.trim_end().rsplit_once('\n').unwrap();
+        assert_eq!("eu-west-3,eu-west-1 0 results.json\n{\"data\":{}}", copied);
+        assert!(!Path::new(result_file).exists());
+        std::fs::remove_file(copy).unwrap();
+    }
+}
@@ -0,0 +1,360 @@
+//! Asynchronous scan jobs of the standalone server: a scan is submitted, runs in the background and its status and result are polled.
+//!
+//! Jobs are kept in memory, only the most recent finished jobs are retained. Changes of a job can be followed as they happen with [JobStore::subscribe].
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+use crate::aws_cloud_provider::{AwsAccount, AwsCloudProvider};
+use crate::boavizta_api_v1::BoaviztaApiV1;
+use crate::cloud_provider::Inventoriable;
+use crate::model::EstimatedInventory;
+
+/// Number of finished (completed or failed) jobs kept with their results
+pub const MAX_FINISHED_JOBS: usize = 100;
+
+fn default_use_duration_hours() -> f32 {
+    1.0
+}
+
+/// Parameters of a scan
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct ScanRequest {
+    pub aws_region: String,
+    /// Tags written as tag_name=tag_value
+    #[serde(default)]
+    pub filter_tags: Vec<String>,
+    /// Defaults to one hour
+    #[serde(default = "default_use_duration_hours")]
+    pub use_duration_hours: f32,
+    #[serde(default)]
+    pub verbose_output: bool,
+    #[serde(default)]
+    pub include_block_storage: bool,
+    /// Id of the account to scan (from the accounts allowed by the server)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_id: Option<String>,
+    /// Role to assume to scan another account (from the accounts allowed by the server)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role_arn: Option<String>,
+    /// URL to which the job (and the summary of its impacts) is posted when it finishes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_url: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+}
+
+/// A scan job and its progress
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct Job {
+    pub id: String,
+    pub status: JobStatus,
+    /// Current step of the scan
+    pub progress: String,
+    /// Number of resources found by the inventory (once listed)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources_count: Option<usize>,
+    /// Number of resources whose impacts are estimated (during the estimation)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources_estimated: Option<usize>,
+    pub request: ScanRequest,
+    pub submitted_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Cause of the failure of the scan
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Tenant that submitted the job, the only one that can see it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+impl Job {
+    /// Returns true when the job was submitted by the tenant (or without tenant when none)
+    pub fn is_visible_to(&self, tenant: Option<&str>) -> bool {
+        self.tenant.as_deref() == tenant
+    }
+}
+
+struct JobEntry {
+    /// The job, whose changes are published to subscribers
+    job: watch::Sender<Job>,
+    result: Option<EstimatedInventory>,
+}
+
+/// The result of a job, when requested
+pub enum JobResult {
+    Completed(EstimatedInventory),
+    /// The job is pending, running or failed
+    NotAvailable(Box<Job>),
+}
+
+#[derive(Default)]
+struct Jobs {
+    entries: HashMap<String, JobEntry>,
+    /// Ids of the finished jobs, oldest first
+    finished: VecDeque<String>,
+}
+
+/// Jobs of the server (shared between requests and the tasks running the scans)
+#[derive(Clone, Default)]
+pub struct JobStore {
+    jobs: Arc<Mutex<Jobs>>,
+}
+
+impl JobStore {
+    /// Registers a new pending job of the tenant (if any)
+    pub fn submit(&self, request: ScanRequest, tenant: Option<String>) -> Job {
+        let job = Job {
+            id: uuid::Uuid::new_v4().to_string(),
+            status: JobStatus::Pending,
+            progress: "Waiting to start".to_string(),
+            resources_count: None,
+            resources_estimated: None,
+            request,
+            submitted_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+            error: None,
+            tenant,
+        };
+        self.jobs.lock().unwrap().entries.insert(
+            job.id.clone(),
+            JobEntry {
+                job: watch::Sender::new(job.clone()),
+                result: None,
+            },
+        );
+        job
+    }
+
+    pub fn get(&self, id: &str) -> Option<Job> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .entries
+            .get(id)
+            .map(|entry| entry.job.borrow().clone())
+    }
+
+    /// Returns a receiver of the changes of a job, closed when the job is forgotten
+    pub fn subscribe(&self, id: &str) -> Option<watch::Receiver<Job>> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .entries
+            .get(id)
+            .map(|entry| entry.job.subscribe())
+    }
+
+    pub fn result(&self, id: &str) -> Option<JobResult> {
+        let jobs = self.jobs.lock().unwrap();
+        let entry = jobs.entries.get(id)?;
+        Some(match &entry.result {
+            Some(result) => JobResult::Completed(result.clone()),
+            None => JobResult::NotAvailable(Box::new(entry.job.borrow().clone())),
+        })
+    }
+
+    fn update(&self, id: &str, update: impl FnOnce(&mut Job)) {
+        if let Some(entry) = self.jobs.lock().unwrap().entries.get(id) {
+            entry.job.send_modify(update);
+        }
+    }
+
+    /// Records the end of a job, and forgets the oldest finished jobs beyond [MAX_FINISHED_JOBS]
+    fn finish(&self, id: &str, result: Result<EstimatedInventory>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(entry) = jobs.entries.get_mut(id) else {
+            return;
+        };
+        let error = match result {
+            Ok(estimated_inventory) => {
+                entry.result = Some(estimated_inventory);
+                None
+            }
+            Err(e) => Some(format!("{:#}", e)),
+        };
+        entry.job.send_modify(|job| {
+            job.finished_at = Some(Utc::now());
+            match error {
+                None => {
+                    job.status = JobStatus::Completed;
+                    job.progress = "Completed".to_string();
+                }
+                Some(error) => {
+                    job.status = JobStatus::Failed;
+                    job.progress = "Failed".to_string();
+                    job.error = Some(error);
+                }
+            }
+        });
+        jobs.finished.push_back(id.to_string());
+        while jobs.finished.len() > MAX_FINISHED_JOBS {
+            if let Some(oldest) = jobs.finished.pop_front() {
+                jobs.entries.remove(&oldest);
+            }
+        }
+    }
+
+    /// Runs a submitted job (inventory then estimation of impacts, in the account when one is provided) and records its result
+    pub async fn run(&self, id: &str, api_url: &str, account: Option<&AwsAccount>) {
+        let Some(job) = self.get(id) else {
+            return;
+        };
+        self.update(id, |job| {
+            job.status = JobStatus::Running;
+            job.started_at = Some(Utc::now());
+        });
+        let result = self.scan(id, &job.request, api_url, account).await;
+        if let Err(e) = &result {
+            error!("Scan job {} failed: {:#}", id, e);
+        }
+        self.finish(id, result);
+    }
+
+    async fn scan(
+        &self,
+        id: &str,
+        request: &ScanRequest,
+        api_url: &str,
+        account: Option<&AwsAccount>,
+    ) -> Result<EstimatedInventory> {
+        self.update(id, |job| job.progress = "Listing resources".to_string());
+        let aws_provider = AwsCloudProvider::new_in_account(&request.aws_region, account).await;
+        let inventory = aws_provider
+            .list_resources(&request.filter_tags, request.include_block_storage)
+            .await
+            .context("Cannot perform resources inventory")?;
+
+        let count = inventory.resources.len();
+        self.update(id, |job| {
+            job.progress = format!("Estimating impacts of {} resources", count);
+            job.resources_count = Some(count);
+        });
+        let api = BoaviztaApiV1::new(api_url);
+        api.get_impacts_with_progress(
+            inventory,
+            &request.use_duration_hours,
+            request.verbose_output,
+            |estimated, total| {
+                self.update(id, |job| {
+                    job.progress =
+                        format!("Estimated impacts of {}/{} resources", estimated, total);
+                    job.resources_estimated = Some(estimated);
+                })
+            },
+        )
+        .await
+        .context("Failure while retrieving impacts")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> ScanRequest {
+        serde_json::from_str(r#"{"aws_region": "eu-west-3"}"#).unwrap()
+    }
+
+    fn empty_inventory() -> EstimatedInventory {
+        EstimatedInventory {
+            impacting_resources: vec![],
+            execution_statistics: None,
+            errors: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn scan_request_has_defaults() {
+        let request = request();
+        assert_eq!(1.0, request.use_duration_hours);
+        assert!(request.filter_tags.is_empty());
+        assert!(!request.include_block_storage);
+        assert_eq!(None, request.callback_url);
+    }
+
+    #[test]
+    fn follow_a_job_until_completion() {
+        let store = JobStore::default();
+        let job = store.submit(request(), None);
+        assert_eq!(JobStatus::Pending, job.status);
+        assert!(matches!(
+            store.result(&job.id),
+            Some(JobResult::NotAvailable(_))
+        ));
+
+        store.finish(&job.id, Ok(empty_inventory()));
+        let finished = store.get(&job.id).unwrap();
+        assert_eq!(JobStatus::Completed, finished.status);
+        assert!(finished.finished_at.is_some());
+        assert!(matches!(
+            store.result(&job.id),
+            Some(JobResult::Completed(_))
+        ));
+
+        let failed = store.submit(request(), None);
+        store.finish(&failed.id, Err(anyhow::anyhow!("No credentials")));
+        let failed = store.get(&failed.id).unwrap();
+        assert_eq!(JobStatus::Failed, failed.status);
+        assert_eq!(Some("No credentials".to_string()), failed.error);
+
+        assert!(store.get("unknown").is_none());
+        assert!(store.result("unknown").is_none());
+
+        let job_of_tenant = store.submit(request(), Some("team-a".to_string()));
+        assert!(job_of_tenant.is_visible_to(Some("team-a")));
+        assert!(!job_of_tenant.is_visible_to(Some("team-b")));
+        assert!(!job_of_tenant.is_visible_to(None));
+        assert!(job.is_visible_to(None));
+    }
+
+    #[tokio::test]
+    async fn subscribers_receive_job_changes() {
+        let store = JobStore::default();
+        let job = store.submit(request(), None);
+        let mut events = store.subscribe(&job.id).unwrap();
+        assert_eq!(JobStatus::Pending, events.borrow_and_update().status);
+
+        store.update(&job.id, |job| job.resources_estimated = Some(3));
+        events.changed().await.unwrap();
+        assert_eq!(Some(3), events.borrow_and_update().resources_estimated);
+
+        store.finish(&job.id, Ok(empty_inventory()));
+        events.changed().await.unwrap();
+        assert_eq!(JobStatus::Completed, events.borrow_and_update().status);
+        assert!(store.subscribe("unknown").is_none());
+    }
+
+    #[test]
+    fn only_recent_finished_jobs_are_kept() {
+        let store = JobStore::default();
+        let first = store.submit(request(), None);
+        store.finish(&first.id, Ok(empty_inventory()));
+        let pending = store.submit(request(), None);
+        for _ in 0..MAX_FINISHED_JOBS {
+            let job = store.submit(request(), None);
+            store.finish(&job.id, Ok(empty_inventory()));
+        }
+        assert!(store.get(&first.id).is_none());
+        assert!(store.get(&pending.id).is_some());
+        assert_eq!(
+            MAX_FINISHED_JOBS + 1,
+            store.jobs.lock().unwrap().entries.len()
+        );
+    }
+}
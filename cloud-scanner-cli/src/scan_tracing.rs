@@ -0,0 +1,390 @@
+//! Export of the traces of scans (`--otlp-endpoint`) to an OpenTelemetry collector (like Jaeger or Grafana Tempo), with OTLP over http in the json encoding.
+//!
+//! Scans are instrumented with `tracing` spans (the scan of each region, its inventory, utilization metrics and estimation of impacts, with one span per AWS or Boavizta API call). The `OtlpLayer` turns them into OpenTelemetry spans (`otel.kind` setting their kind, other fields becoming attributes) and exports each trace when its root span closes, at the end of a scan.
+use anyhow::{Context, Result};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::{Context as LayerContext, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Name of the service of the traces, unless set by `OTEL_SERVICE_NAME`
+pub const DEFAULT_SERVICE_NAME: &str = "cloud-scanner";
+
+/// A span, open or closed
+#[derive(Clone, Debug, PartialEq)]
+struct SpanData {
+    /// Id of the trace (16 bytes in hex, as expected by the json encoding of OTLP)
+    trace_id: String,
+    /// Id of the span (8 bytes in hex)
+    span_id: String,
+    parent_span_id: Option<String>,
+    name: String,
+    /// Kind of the span, as numbered by OTLP (1 for internal, 3 for client)
+    kind: u8,
+    attributes: Vec<(String, String)>,
+    start_unix_nanos: u128,
+    end_unix_nanos: u128,
+}
+
+/// Messages handled by the exporter, in order
+enum Message {
+    Trace(Vec<SpanData>),
+    /// Answered once the traces sent before are exported
+    Flush(oneshot::Sender<()>),
+}
+
+static EXPORTER: OnceLock<mpsc::UnboundedSender<Message>> = OnceLock::new();
+
+fn unix_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+/// Returns a random id of `bytes` bytes, in hex
+fn random_id(bytes: usize) -> String {
+    hex::encode(&uuid::Uuid::new_v4().as_bytes()[..bytes])
+}
+
+/// Returns the OTLP number of the kind of span set by an `otel.kind` field (internal by default)
+fn span_kind(kind: &str) -> u8 {
+    match kind {
+        "server" => 2,
+        "client" => 3,
+        "producer" => 4,
+        "consumer" => 5,
+        _ => 1,
+    }
+}
+
+/// Records the fields of a span as its kind and attributes
+struct FieldVisitor<'a>(&'a mut SpanData);
+
+impl FieldVisitor<'_> {
+    fn record(&mut self, field: &Field, value: String) {
+        if field.name() == "otel.kind" {
+            self.0.kind = span_kind(&value);
+        } else if let Some(attribute) = self
+            .0
+            .attributes
+            .iter_mut()
+            .find(|(key, _)| key == field.name())
+        {
+            attribute.1 = value;
+        } else {
+            self.0.attributes.push((field.name().to_string(), value));
+        }
+    }
+}
+
+impl Visit for FieldVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record(field, format!("{:?}", value));
+    }
+}
+
+/// A layer collecting the spans of traces, handing each trace to the exporter when its root span closes
+struct OtlpLayer {
+    /// Closed spans of the traces whose root span is still open
+    spans: Mutex<Vec<SpanData>>,
+    exporter: mpsc::UnboundedSender<Message>,
+}
+
+impl OtlpLayer {
+    fn new(exporter: mpsc::UnboundedSender<Message>) -> Self {
+        OtlpLayer {
+            spans: Mutex::new(Vec::new()),
+            exporter,
+        }
+    }
+
+    /// Records a closed span, returning the spans of its trace when it is the root span
+    fn finish(&self, span: SpanData) -> Option<Vec<SpanData>> {
+        let mut spans = self.spans.lock().unwrap();
+        if span.parent_span_id.is_some() {
+            spans.push(span);
+            return None;
+        }
+        let (mut trace, others): (Vec<SpanData>, Vec<SpanData>) = spans
+            .drain(..)
+            .partition(|other| other.trace_id == span.trace_id);
+        *spans = others;
+        trace.push(span);
+        Some(trace)
+    }
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attributes: &Attributes<'_>, id: &Id, context: LayerContext<'_, S>) {
+        let Some(span) = context.span(id) else {
+            return;
+        };
+        let parent = span.parent().and_then(|parent| {
+            parent
+                .extensions()
+                .get::<SpanData>()
+                .map(|data| (data.trace_id.clone(), data.span_id.clone()))
+        });
+        let (trace_id, parent_span_id) = match parent {
+            Some((trace_id, span_id)) => (trace_id, Some(span_id)),
+            None => (random_id(16), None),
+        };
+        let mut data = SpanData {
+            trace_id,
+            span_id: random_id(8),
+            parent_span_id,
+            name: span.name().to_string(),
+            kind: 1,
+            attributes: Vec::new(),
+            start_unix_nanos: unix_nanos(),
+            end_unix_nanos: 0,
+        };
+        attributes.record(&mut FieldVisitor(&mut data));
+        span.extensions_mut().insert(data);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, context: LayerContext<'_, S>) {
+        if let Some(span) = context.span(id) {
+            if let Some(data) = span.extensions_mut().get_mut::<SpanData>() {
+                values.record(&mut FieldVisitor(data));
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, context: LayerContext<'_, S>) {
+        let Some(span) = context.span(&id) else {
+            return;
+        };
+        let Some(mut data) = span.extensions_mut().remove::<SpanData>() else {
+            return;
+        };
+        data.end_unix_nanos = unix_nanos();
+        if let Some(trace) = self.finish(data) {
+            let _ = self.exporter.send(Message::Trace(trace));
+        }
+    }
+}
+
+/// Exports traces to the OTLP endpoint of a collector
+struct Exporter {
+    /// URL of the traces of the collector (like `http://localhost:4318/v1/traces`)
+    traces_url: String,
+    service_name: String,
+    client: reqwest::Client,
+}
+
+impl Exporter {
+    fn new(otlp_endpoint: &str, service_name: &str) -> Self {
+        Exporter {
+            traces_url: format!("{}/v1/traces", otlp_endpoint.trim_end_matches('/')),
+            service_name: service_name.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Returns the spans of a trace as an OTLP export request (json encoding)
+    fn to_otlp_json(&self, spans: &[SpanData]) -> serde_json::Value {
+        let attribute = |key: &str, value: &str| serde_json::json!({ "key": key, "value": { "stringValue": value } });
+        let spans: Vec<serde_json::Value> = spans
+            .iter()
+            .map(|span| {
+                serde_json::json!({
+                    "traceId": span.trace_id,
+                    "spanId": span.span_id,
+                    "parentSpanId": span.parent_span_id.clone().unwrap_or_default(),
+                    "name": span.name,
+                    "kind": span.kind,
+                    "startTimeUnixNano": span.start_unix_nanos.to_string(),
+                    "endTimeUnixNano": span.end_unix_nanos.to_string(),
+                    "attributes": span
+                        .attributes
+                        .iter()
+                        .map(|(key, value)| attribute(key, value))
+                        .collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [
+                        attribute("service.name", &self.service_name),
+                        attribute("service.version", &crate::get_version()),
+                    ]
+                },
+                "scopeSpans": [{
+                    "scope": { "name": "cloud-scanner-cli", "version": crate::get_version() },
+                    "spans": spans,
+                }]
+            }]
+        })
+    }
+
+    async fn export(&self, spans: &[SpanData]) -> Result<()> {
+        let response = self
+            .client
+            .post(&self.traces_url)
+            .json(&self.to_otlp_json(spans))
+            .send()
+            .await
+            .with_context(|| format!("Cannot reach the OTLP collector at {}", self.traces_url))?;
+        let status = response.status();
+        if !status.is_success() {
+            let message = response.text().await.unwrap_or_default();
+            anyhow::bail!(
+                "Export of traces failed with status {}: {}",
+                status,
+                message
+            );
+        }
+        debug!(
+            "Exported a trace of {} spans to {}",
+            spans.len(),
+            self.traces_url
+        );
+        Ok(())
+    }
+
+    /// Exports the traces received until all senders are dropped
+    async fn run(self, mut messages: mpsc::UnboundedReceiver<Message>) {
+        while let Some(message) = messages.recv().await {
+            match message {
+                Message::Trace(trace) => {
+                    // Scans do not fail when their trace cannot be exported
+                    if let Err(e) = self.export(&trace).await {
+                        warn!(
+                            "Cannot export the trace of {}: {:#}",
+                            trace[trace.len() - 1].name,
+                            e
+                        );
+                    }
+                }
+                Message::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+}
+
+/// Enables the export of the traces of scans to the OTLP endpoint of a collector (like `http://localhost:4318`), the service being named after `OTEL_SERVICE_NAME` (cloud-scanner by default).
+///
+/// Only spans of level info and above are collected. Must be called in a tokio runtime.
+pub fn enable(otlp_endpoint: &str) -> Result<()> {
+    let service_name = std::env::var("OTEL_SERVICE_NAME")
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string());
+    info!(
+        "Exporting traces of scans to {} as {}",
+        otlp_endpoint, service_name
+    );
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(Exporter::new(otlp_endpoint, &service_name).run(receiver));
+    // Logs are not written through tracing (see access_log), only spans are collected
+    let subscriber = tracing_subscriber::registry()
+        .with(OtlpLayer::new(sender.clone()).with_filter(LevelFilter::INFO));
+    tracing::subscriber::set_global_default(subscriber).context("Cannot collect spans")?;
+    let _ = EXPORTER.set(sender);
+    Ok(())
+}
+
+/// Waits for the traces of the closed scans to be exported (or to fail), before exiting. Returns immediately when tracing is not enabled.
+pub async fn flush() {
+    if let Some(exporter) = EXPORTER.get() {
+        let (done, exported) = oneshot::channel();
+        if exporter.send(Message::Flush(done)).is_ok() {
+            let _ = exported.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::Instrument;
+
+    #[tokio::test]
+    async fn spans_are_exported_with_their_trace_when_the_root_span_closes() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let subscriber = tracing_subscriber::registry()
+            .with(OtlpLayer::new(sender).with_filter(LevelFilter::INFO));
+        let _subscriber = tracing::subscriber::set_default(subscriber);
+
+        let scan_region = tracing::info_span!(
+            "scan_region",
+            cloud.region = "eu-west-3",
+            cloud.account.id = tracing::field::Empty,
+        );
+        scan_region.record("cloud.account.id", "123456789012");
+        async {
+            async {}
+                .instrument(tracing::info_span!(
+                    "ec2.DescribeInstances",
+                    otel.kind = "client"
+                ))
+                .await;
+            async {}.instrument(tracing::debug_span!("ignored")).await;
+        }
+        .instrument(scan_region)
+        .await;
+
+        let Some(Message::Trace(trace)) = receiver.recv().await else {
+            panic!("The trace was not exported");
+        };
+        assert_eq!(
+            vec!["ec2.DescribeInstances", "scan_region"],
+            trace
+                .iter()
+                .map(|span| span.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        let (call, root) = (&trace[0], &trace[1]);
+        assert_eq!(32, root.trace_id.len());
+        assert_eq!(16, root.span_id.len());
+        assert_eq!(None, root.parent_span_id);
+        assert_eq!(root.trace_id, call.trace_id);
+        assert_eq!(Some(&root.span_id), call.parent_span_id.as_ref());
+        assert_eq!(3, call.kind);
+        assert_eq!(1, root.kind);
+        assert_eq!(
+            vec![
+                ("cloud.region".to_string(), "eu-west-3".to_string()),
+                ("cloud.account.id".to_string(), "123456789012".to_string())
+            ],
+            root.attributes
+        );
+        assert!(root.end_unix_nanos >= call.end_unix_nanos);
+
+        let exporter = Exporter::new("http://localhost:4318/", "scanner-test");
+        assert_eq!("http://localhost:4318/v1/traces", exporter.traces_url);
+        let request = exporter.to_otlp_json(&trace);
+        let resource_spans = &request["resourceSpans"][0];
+        assert_eq!(
+            "scanner-test",
+            resource_spans["resource"]["attributes"][0]["value"]["stringValue"]
+        );
+        let spans = &resource_spans["scopeSpans"][0]["spans"];
+        assert_eq!(3, spans[0]["kind"]);
+        assert_eq!(root.span_id, spans[0]["parentSpanId"]);
+        assert_eq!("", spans[1]["parentSpanId"]);
+        assert_eq!(
+            "eu-west-3",
+            spans[1]["attributes"][0]["value"]["stringValue"]
+        );
+    }
+}
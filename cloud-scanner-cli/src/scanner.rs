@@ -0,0 +1,538 @@
+//! A builder of scans, to embed cloud-scanner in other Rust tools without running the CLI: the scanned source (AWS accounts and regions, or an inventory produced elsewhere), filters, impact provider and exporters of the results are set once, then each call of [Scanner::scan] returns typed results.
+//!
+//! ```no_run
+//! use cloud_scanner_cli::scanner::{ResultFormat, Scanner};
+//!
+//! # async fn scan() -> anyhow::Result<()> {
+//! let scanner = Scanner::builder()
+//!     .regions(["eu-west-3", "eu-west-1"])
+//!     .filter_tags(["env=prod"])
+//!     .include_block_storage(true)
+//!     .use_duration_hours(730.0)
+//!     .export_to(ResultFormat::OpenMetrics, Some("metrics.prom"))?
+//!     .build()?;
+//! let results = scanner.scan().await?;
+//! println!(
+//!     "{} resources, {} kgCO2eq",
+//!     results.summary.number_of_resources_total,
+//!     results.summary.gwp_manufacture_kgco2eq + results.summary.gwp_use_kgco2eq
+//! );
+//! # Ok(())
+//! # }
+//! ```
+use anyhow::{Context, Result};
+use chrono::Utc;
+use std::sync::Arc;
+
+use crate::aws_cloud_provider::{AwsAccount, ResourceSelection};
+use crate::boavizta_api_v1::BoaviztaApiV1;
+use crate::ignore_rules::IgnoreRules;
+use crate::impact_provider::{ImpactProvider, ImpactsSummary};
+use crate::model::{EstimatedInventory, Inventory};
+use crate::output_exporter::{exporter_for_uri, OutputExporter};
+use crate::result_envelope::{ResultMetadata, ScanParameters};
+use crate::result_exporter::{
+    CsvResultExporter, JsonResultExporter, LineProtocolResultExporter, MetricsResultExporter,
+    ResultExporter,
+};
+
+/// URL of the public instance of Boavizta API, used unless another URL or impact provider is set
+pub const DEFAULT_BOAVIZTA_API_URL: &str = "https://api.boavizta.org";
+
+/// The resources scanned
+#[derive(Clone)]
+pub enum ScanSource {
+    /// The resources of AWS accounts (by assuming their roles, the account of the environment without accounts) in the scanned regions
+    Aws { accounts: Vec<AwsAccount> },
+    /// An inventory produced elsewhere (like by [crate::inventory_file::read_inventory]), estimated without listing cloud resources
+    Inventory(Inventory),
+}
+
+/// Format of the results written by the built-in exporters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResultFormat {
+    /// The json output of the estimate command (resources and their impacts, with metadata)
+    Json,
+    /// The summary of the impacts as json, with metadata
+    SummaryJson,
+    /// One row per resource
+    Csv,
+    /// Prometheus metrics (summary and one series per resource)
+    OpenMetrics,
+    /// InfluxDB line protocol (summary and one point per resource)
+    LineProtocol,
+}
+
+impl ResultFormat {
+    /// Returns the extension of the results in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ResultFormat::Json | ResultFormat::SummaryJson => "json",
+            ResultFormat::Csv => "csv",
+            ResultFormat::OpenMetrics => "prom",
+            ResultFormat::LineProtocol => "txt",
+        }
+    }
+
+    /// Returns the built-in exporter writing results in this format to an output
+    pub fn exporter(
+        &self,
+        output: Box<dyn OutputExporter + Send + Sync>,
+    ) -> Box<dyn ResultExporter + Send + Sync> {
+        match self {
+            ResultFormat::Json | ResultFormat::SummaryJson => Box::new(JsonResultExporter {
+                summary_only: *self == ResultFormat::SummaryJson,
+                output,
+            }),
+            ResultFormat::Csv => Box::new(CsvResultExporter { output }),
+            ResultFormat::OpenMetrics => Box::new(MetricsResultExporter {
+                options: Default::default(),
+                output,
+            }),
+            ResultFormat::LineProtocol => Box::new(LineProtocolResultExporter { output }),
+        }
+    }
+
+    /// Returns the results of a scan in this format
+    pub fn format(&self, results: &ScanResults) -> Result<String> {
+        match self {
+            ResultFormat::Json | ResultFormat::SummaryJson => crate::impacts_to_json_string(
+                &results.metadata,
+                &results.estimated_inventory,
+                &results.summary,
+                *self == ResultFormat::SummaryJson,
+            ),
+            ResultFormat::Csv => crate::impacts_to_csv(&results.estimated_inventory),
+            ResultFormat::OpenMetrics => {
+                crate::impacts_to_metrics(&results.estimated_inventory, &results.summary)
+            }
+            ResultFormat::LineProtocol => {
+                crate::impacts_to_line_protocol(&results.estimated_inventory, &results.summary)
+            }
+        }
+    }
+}
+
+/// The results of a scan
+#[derive(Clone)]
+pub struct ScanResults {
+    pub metadata: ResultMetadata,
+    /// The resources with their impacts, and the errors the scan continued past
+    pub estimated_inventory: EstimatedInventory,
+    /// The impacts summarized by account and region
+    pub summary: ImpactsSummary,
+    /// Number of resources excluded by the ignore rules
+    pub number_of_excluded_resources: usize,
+    /// Locations of the results written by the exporters (like `stdout` or a path), in the order of the exporters
+    pub exports: Vec<String>,
+}
+
+/// Builds a [Scanner], see [Scanner::builder]
+#[derive(Default)]
+pub struct ScannerBuilder {
+    source: Option<ScanSource>,
+    regions: Vec<String>,
+    filter_tags: Vec<String>,
+    selection: ResourceSelection,
+    include_block_storage: bool,
+    ignore_rules: IgnoreRules,
+    use_duration_hours: Option<f32>,
+    verbose: bool,
+    api_url: Option<String>,
+    impact_provider: Option<Arc<dyn ImpactProvider + Send + Sync>>,
+    exporters: Vec<Box<dyn ResultExporter + Send + Sync>>,
+}
+
+impl ScannerBuilder {
+    /// Scans this source (the AWS account of the environment by default)
+    pub fn source(mut self, source: ScanSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Scans these AWS accounts (by assuming their roles)
+    pub fn aws_accounts(self, accounts: Vec<AwsAccount>) -> Self {
+        self.source(ScanSource::Aws { accounts })
+    }
+
+    /// Estimates this inventory instead of listing cloud resources
+    pub fn inventory(self, inventory: Inventory) -> Self {
+        self.source(ScanSource::Inventory(inventory))
+    }
+
+    /// Scans these regions (required to scan AWS accounts, the regions of the resources by default for inventories)
+    pub fn regions<I, S>(mut self, regions: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.regions = regions.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Only lists the resources of AWS accounts with these tags (like `env=prod`, see [crate::tag_filter])
+    pub fn filter_tags<I, S>(mut self, filter_tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.filter_tags = filter_tags.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Only lists the selected kinds of resources and states of instances of AWS accounts
+    pub fn selection(mut self, selection: ResourceSelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Also lists the block storage of AWS accounts (experimental)
+    pub fn include_block_storage(mut self, include_block_storage: bool) -> Self {
+        self.include_block_storage = include_block_storage;
+        self
+    }
+
+    /// Excludes the resources matching these rules from the scan
+    pub fn ignore_rules(mut self, ignore_rules: IgnoreRules) -> Self {
+        self.ignore_rules = ignore_rules;
+        self
+    }
+
+    /// Estimates the impacts for this duration of use (required)
+    pub fn use_duration_hours(mut self, use_duration_hours: f32) -> Self {
+        self.use_duration_hours = Some(use_duration_hours);
+        self
+    }
+
+    /// Keeps the raw (verbose) responses of the impact provider in the results
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Estimates the impacts with the instance of Boavizta API at this URL (without the trailing slash)
+    pub fn boavizta_api_url(mut self, api_url: impl Into<String>) -> Self {
+        self.api_url = Some(api_url.into());
+        self
+    }
+
+    /// Estimates the impacts with this provider instead of Boavizta API
+    pub fn impact_provider(
+        mut self,
+        impact_provider: Arc<dyn ImpactProvider + Send + Sync>,
+    ) -> Self {
+        self.impact_provider = Some(impact_provider);
+        self
+    }
+
+    /// Writes the results of each scan in this format to an output
+    pub fn exporter(
+        self,
+        format: ResultFormat,
+        output: Box<dyn OutputExporter + Send + Sync>,
+    ) -> Self {
+        self.result_exporter(format.exporter(output))
+    }
+
+    /// Hands the results of each scan to this sink
+    pub fn result_exporter(mut self, exporter: Box<dyn ResultExporter + Send + Sync>) -> Self {
+        self.exporters.push(exporter);
+        self
+    }
+
+    /// Writes the results of each scan in this format to the destination of an output URI (standard output without URI, see [crate::output_exporter])
+    pub fn export_to(self, format: ResultFormat, output_uri: Option<&str>) -> Result<Self> {
+        let region = self.regions.join(",");
+        let exporter = exporter_for_uri(output_uri, &region)?;
+        Ok(self.exporter(format, exporter))
+    }
+
+    /// Returns the scanner, failing when the duration of use is missing, or when no region is set to scan AWS accounts
+    pub fn build(self) -> Result<Scanner> {
+        let use_duration_hours = self
+            .use_duration_hours
+            .context("Missing the duration of use of the scan")?;
+        if use_duration_hours <= 0.0 {
+            anyhow::bail!(
+                "The duration of use must be positive, not {}",
+                use_duration_hours
+            );
+        }
+        let source = self.source.unwrap_or(ScanSource::Aws {
+            accounts: Vec::new(),
+        });
+        let regions = match &source {
+            ScanSource::Aws { .. } if self.regions.is_empty() => {
+                anyhow::bail!("Missing the regions to scan")
+            }
+            ScanSource::Inventory(inventory) if self.regions.is_empty() => {
+                crate::inventory_file::inventory_regions(None, inventory)
+            }
+            _ => self.regions,
+        };
+        if self.api_url.is_some() && self.impact_provider.is_some() {
+            anyhow::bail!("Set either the URL of Boavizta API or another impact provider");
+        }
+        Ok(Scanner {
+            source,
+            regions,
+            filter_tags: self.filter_tags,
+            selection: self.selection,
+            include_block_storage: self.include_block_storage,
+            ignore_rules: self.ignore_rules,
+            use_duration_hours,
+            verbose: self.verbose,
+            api_url: self
+                .api_url
+                .unwrap_or_else(|| DEFAULT_BOAVIZTA_API_URL.to_string()),
+            impact_provider: self.impact_provider,
+            exporters: self.exporters,
+        })
+    }
+}
+
+/// Scans a source with the same parameters on each call of [Scanner::scan]
+pub struct Scanner {
+    source: ScanSource,
+    regions: Vec<String>,
+    filter_tags: Vec<String>,
+    selection: ResourceSelection,
+    include_block_storage: bool,
+    ignore_rules: IgnoreRules,
+    use_duration_hours: f32,
+    verbose: bool,
+    api_url: String,
+    impact_provider: Option<Arc<dyn ImpactProvider + Send + Sync>>,
+    exporters: Vec<Box<dyn ResultExporter + Send + Sync>>,
+}
+
+impl Scanner {
+    pub fn builder() -> ScannerBuilder {
+        ScannerBuilder::default()
+    }
+
+    /// Returns the resources with their impacts and their summary, after handing them to the exporters
+    pub async fn scan(&self) -> Result<ScanResults> {
+        let scan_timestamp = Utc::now();
+        let (estimated_inventory, excluded) = self.estimate().await?;
+        let accounts = match &self.source {
+            ScanSource::Aws { accounts } => accounts.as_slice(),
+            ScanSource::Inventory(_) => &[],
+        };
+        let mut summary = crate::build_summary_of_accounts(
+            &estimated_inventory,
+            accounts,
+            &self.regions,
+            &self.use_duration_hours,
+        )?;
+        if !self.ignore_rules.is_empty() {
+            summary.number_of_resources_excluded = Some(excluded);
+        }
+        let parameters = ScanParameters {
+            aws_region: self.regions.join(","),
+            use_duration_hours: Some(self.use_duration_hours),
+            filter_tags: self.filter_tags.clone(),
+            include_block_storage: self.include_block_storage,
+            include_states: self.selection.instance_states.clone(),
+            verbose: self.verbose,
+        };
+        // The metadata only describes Boavizta API
+        let api_url = self
+            .impact_provider
+            .is_none()
+            .then_some(self.api_url.as_str());
+        let metadata = crate::scan_metadata(scan_timestamp, parameters, api_url).await;
+        let mut results = ScanResults {
+            metadata,
+            estimated_inventory,
+            summary,
+            number_of_excluded_resources: excluded,
+            exports: Vec::new(),
+        };
+        for exporter in self.exporters.iter() {
+            let location = exporter
+                .export_results(
+                    &results.metadata,
+                    &results.estimated_inventory,
+                    &results.summary,
+                )
+                .await
+                .context("Cannot export the results of the scan")?;
+            results.exports.push(location);
+        }
+        Ok(results)
+    }
+
+    /// Returns the resources of the source not matching the ignore rules with their impacts, and the number of excluded resources
+    async fn estimate(&self) -> Result<(EstimatedInventory, usize)> {
+        let inventory = match (&self.source, &self.impact_provider) {
+            // Regions and accounts are estimated as they are scanned
+            (ScanSource::Aws { accounts }, None) => {
+                return crate::estimate_impacts_in_accounts_excluding(
+                    accounts,
+                    &self.use_duration_hours,
+                    &self.filter_tags,
+                    &self.regions,
+                    &self.api_url,
+                    self.verbose,
+                    self.include_block_storage,
+                    &self.selection,
+                    &self.ignore_rules,
+                )
+                .await;
+            }
+            (ScanSource::Aws { accounts }, Some(_)) => {
+                crate::get_inventory_in_accounts(
+                    accounts,
+                    &self.filter_tags,
+                    &self.regions,
+                    self.include_block_storage,
+                    &self.selection,
+                )
+                .await?
+            }
+            (ScanSource::Inventory(inventory), _) => inventory.clone(),
+        };
+        let (inventory, excluded) = self.ignore_rules.exclude(inventory);
+        let estimated_inventory = match &self.impact_provider {
+            Some(impact_provider) => {
+                impact_provider
+                    .get_impacts(inventory, &self.use_duration_hours, self.verbose)
+                    .await
+            }
+            None => {
+                BoaviztaApiV1::new(&self.api_url)
+                    .get_impacts(inventory, &self.use_duration_hours, self.verbose)
+                    .await
+            }
+        }
+        .context("Failure while retrieving impacts")?;
+        Ok((estimated_inventory, excluded))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+    use crate::model::{CloudProvider, CloudResource, ResourceDetails};
+    use crate::supported_types::SupportedTypes;
+    use crate::usage_location::UsageLocation;
+    use async_trait::async_trait;
+
+    /// Estimates every resource with the same impacts
+    struct FixedImpacts;
+
+    #[async_trait]
+    impl ImpactProvider for FixedImpacts {
+        async fn get_impacts(
+            &self,
+            inventory: Inventory,
+            usage_duration_hours: &f32,
+            _verbose: bool,
+        ) -> Result<EstimatedInventory> {
+            Ok(EstimatedInventory {
+                impacting_resources: inventory
+                    .resources
+                    .into_iter()
+                    .map(|cloud_resource| CloudResourceWithImpacts {
+                        cloud_resource,
+                        impacts_values: Some(ImpactsValues {
+                            gwp_use_kgco2eq: 1.5,
+                            ..Default::default()
+                        }),
+                        impacts_duration_hours: *usage_duration_hours,
+                        cost: None,
+                    })
+                    .collect(),
+                execution_statistics: None,
+                errors: Vec::new(),
+            })
+        }
+
+        async fn supported_types(&self) -> Result<SupportedTypes> {
+            Ok(SupportedTypes::default())
+        }
+    }
+
+    /// Keeps the number of resources of the results it is handed
+    struct CountingSink(std::sync::Mutex<Vec<usize>>);
+
+    #[async_trait]
+    impl ResultExporter for CountingSink {
+        async fn export_results(
+            &self,
+            _metadata: &ResultMetadata,
+            estimated_inventory: &EstimatedInventory,
+            _summary: &ImpactsSummary,
+        ) -> Result<String> {
+            let mut counts = self.0.lock().unwrap();
+            counts.push(estimated_inventory.impacting_resources.len());
+            Ok(format!("sink #{}", counts.len()))
+        }
+    }
+
+    fn instance(id: &str) -> CloudResource {
+        CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: None,
+            id: id.to_string(),
+            location: UsageLocation::try_from("eu-west-3").unwrap(),
+            resource_details: ResourceDetails::Instance {
+                instance_type: "t3.micro".to_string(),
+                usage: None,
+            },
+            tags: Vec::new(),
+            relationships: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn inventories_are_scanned_with_the_impact_provider() {
+        let inventory = Inventory {
+            resources: vec![instance("i-1"), instance("i-2"), instance("i-3")],
+            execution_statistics: None,
+        };
+        let scanner = Scanner::builder()
+            .inventory(inventory)
+            .use_duration_hours(10.0)
+            .ignore_rules(IgnoreRules::parse("i-3").unwrap())
+            .impact_provider(Arc::new(FixedImpacts))
+            .result_exporter(Box::new(CountingSink(Default::default())))
+            .build()
+            .unwrap();
+        let results = scanner.scan().await.unwrap();
+        assert_eq!(vec!["sink #1".to_string()], results.exports);
+        assert_eq!(2, results.summary.number_of_resources_total);
+        assert_eq!(3.0, results.summary.gwp_use_kgco2eq);
+        assert_eq!(1, results.number_of_excluded_resources);
+        assert_eq!("eu-west-3", results.metadata.parameters.aws_region);
+        assert!(results.metadata.impact_provider.is_none());
+
+        let csv = ResultFormat::Csv.format(&results).unwrap();
+        assert!(csv.contains("i-2"));
+    }
+
+    #[test]
+    fn scanners_need_a_duration_and_regions() {
+        assert!(Scanner::builder().regions(["eu-west-3"]).build().is_err());
+        assert!(Scanner::builder().use_duration_hours(1.0).build().is_err());
+        assert!(Scanner::builder()
+            .regions(["eu-west-3"])
+            .use_duration_hours(0.0)
+            .build()
+            .is_err());
+        assert!(Scanner::builder()
+            .regions(["eu-west-3"])
+            .use_duration_hours(1.0)
+            .boavizta_api_url("http://localhost:5000")
+            .impact_provider(Arc::new(FixedImpacts))
+            .build()
+            .is_err());
+        assert!(Scanner::builder()
+            .regions(["eu-west-3"])
+            .use_duration_hours(1.0)
+            .build()
+            .is_ok());
+    }
+}
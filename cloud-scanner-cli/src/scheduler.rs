@@ -0,0 +1,220 @@
+//! Scans run periodically by the standalone server, so that `/metrics` serves the latest completed scan instead of scanning on every scrape.
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::graceful_shutdown::InFlightScans;
+use crate::impact_provider::ImpactsSummary;
+use crate::model::EstimatedInventory;
+use crate::result_envelope::{ResultMetadata, ScanParameters};
+
+fn default_use_duration_hours() -> f32 {
+    1.0
+}
+
+/// A scan run every `interval_minutes` (one scheduled scan per region)
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduledScan {
+    pub aws_region: String,
+    pub interval_minutes: u64,
+    #[serde(default)]
+    pub filter_tags: Vec<String>,
+    /// Defaults to one hour, like the metrics route (hours, or text like `30d`)
+    #[serde(
+        default = "default_use_duration_hours",
+        deserialize_with = "crate::duration::deserialize_hours"
+    )]
+    pub use_duration_hours: f32,
+    #[serde(default)]
+    pub include_block_storage: bool,
+}
+
+impl ScheduledScan {
+    /// Returns true when the parameters of a request (when provided) are the ones of the scheduled scan
+    pub fn matches(
+        &self,
+        aws_region: &str,
+        filter_tags: Option<&[String]>,
+        use_duration_hours: Option<f32>,
+        include_block_storage: Option<bool>,
+    ) -> bool {
+        self.aws_region == aws_region
+            && filter_tags.is_none_or(|tags| tags == self.filter_tags.as_slice())
+            && use_duration_hours.is_none_or(|hours| hours == self.use_duration_hours)
+            && include_block_storage.is_none_or(|include| include == self.include_block_storage)
+    }
+}
+
+/// The result of the last completed run of a scheduled scan
+#[derive(Clone)]
+pub struct CompletedScan {
+    pub scan: ScheduledScan,
+    pub completed_at: DateTime<Utc>,
+    pub estimated_inventory: EstimatedInventory,
+    pub summary: ImpactsSummary,
+    /// How the results were produced
+    pub metadata: ResultMetadata,
+}
+
+/// The latest completed scans, by region
+#[derive(Clone, Default)]
+pub struct LatestScans {
+    scans: Arc<RwLock<HashMap<String, CompletedScan>>>,
+}
+
+impl LatestScans {
+    fn set(&self, completed_scan: CompletedScan) {
+        self.scans
+            .write()
+            .unwrap()
+            .insert(completed_scan.scan.aws_region.clone(), completed_scan);
+    }
+
+    /// Returns the latest completed scan matching the parameters of a request
+    pub fn matching(
+        &self,
+        aws_region: &str,
+        filter_tags: Option<&[String]>,
+        use_duration_hours: Option<f32>,
+        include_block_storage: Option<bool>,
+    ) -> Option<CompletedScan> {
+        self.scans
+            .read()
+            .unwrap()
+            .get(aws_region)
+            .filter(|completed| {
+                completed.scan.matches(
+                    aws_region,
+                    filter_tags,
+                    use_duration_hours,
+                    include_block_storage,
+                )
+            })
+            .cloned()
+    }
+}
+
+async fn run_scan(
+    scan: &ScheduledScan,
+    api_url: &str,
+    store: Option<&str>,
+) -> Result<CompletedScan> {
+    let started_at = Utc::now();
+    let estimated_inventory = crate::estimate_impacts(
+        &scan.use_duration_hours,
+        &scan.filter_tags,
+        &scan.aws_region,
+        api_url,
+        false,
+        scan.include_block_storage,
+    )
+    .await?;
+    let summary = crate::build_summary(
+        &estimated_inventory,
+        &scan.aws_region,
+        &scan.use_duration_hours,
+    )?;
+    if let Some(store_path) = store {
+        crate::store_impacts(store_path, &estimated_inventory, &summary)?;
+    }
+    let parameters = ScanParameters {
+        aws_region: scan.aws_region.clone(),
+        use_duration_hours: Some(scan.use_duration_hours),
+        filter_tags: scan.filter_tags.clone(),
+        include_block_storage: scan.include_block_storage,
+        include_states: Vec::new(),
+        verbose: false,
+    };
+    Ok(CompletedScan {
+        scan: scan.clone(),
+        completed_at: Utc::now(),
+        estimated_inventory,
+        summary,
+        metadata: crate::scan_metadata(started_at, parameters, Some(api_url)).await,
+    })
+}
+
+/// Starts the scheduled scans in the background: each scan runs at startup, then every `interval_minutes`.
+///
+/// Results are saved in the result store when a path is provided. Failures are logged and the previous result is kept. Running scans are tracked in `in_flight`, so that they can finish before the server stops.
+pub fn spawn_scheduled_scans(
+    scans: Vec<ScheduledScan>,
+    api_url: String,
+    store: Option<String>,
+    latest: LatestScans,
+    in_flight: InFlightScans,
+) {
+    for scan in scans {
+        let api_url = api_url.clone();
+        let store = store.clone();
+        let latest = latest.clone();
+        let in_flight = in_flight.clone();
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(scan.interval_minutes.max(1) * 60));
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let _scan = in_flight.start();
+                info!("Running scheduled scan of {}", scan.aws_region);
+                match run_scan(&scan, &api_url, store.as_deref()).await {
+                    Ok(completed_scan) => latest.set(completed_scan),
+                    Err(e) => error!("Scheduled scan of {} failed: {:?}", scan.aws_region, e),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serve_latest_scan_matching_request() {
+        let scan = ScheduledScan {
+            aws_region: "eu-west-3".to_string(),
+            interval_minutes: 60,
+            filter_tags: vec!["Env=prod".to_string()],
+            use_duration_hours: 1.0,
+            include_block_storage: false,
+        };
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let latest = LatestScans::default();
+        assert!(latest.matching("eu-west-3", None, None, None).is_none());
+
+        latest.set(CompletedScan {
+            scan,
+            completed_at: Utc::now(),
+            estimated_inventory,
+            summary,
+            metadata: ResultMetadata::new(Utc::now(), ScanParameters::default(), None),
+        });
+        let tags = vec!["Env=prod".to_string()];
+        assert!(latest.matching("eu-west-3", None, None, None).is_some());
+        assert!(latest
+            .matching("eu-west-3", Some(&tags), Some(1.0), Some(false))
+            .is_some());
+        assert!(latest
+            .matching("eu-west-3", Some(&[]), None, None)
+            .is_none());
+        assert!(latest
+            .matching("eu-west-3", None, Some(24.0), None)
+            .is_none());
+        assert!(latest.matching("eu-west-1", None, None, None).is_none());
+    }
+}
@@ -0,0 +1,450 @@
+//! Authentication of the requests to the standalone server, with static API keys or OpenID Connect (OIDC) access tokens.
+//!
+//! Clients pass the key or token as a bearer token (`Authorization: Bearer <token>`) or in the `X-API-Key` header.
+//! The API keys of tenants authenticate the requests of the tenant.
+//! OIDC tokens are JWT validated against the keys published by the issuer (signature, issuer, audience and expiry).
+//! Tokens must name their key (`kid`), and are only accepted with the algorithm of this key (its `alg`, the configured algorithms, or RS256 for the RSA keys without algorithm), never with the algorithm of their own header.
+use anyhow::{Context, Result};
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{
+    Object, SecurityRequirement, SecurityScheme, SecuritySchemeData,
+};
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use serde::Deserialize;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+use crate::access_log::RequestTenant;
+use crate::tenants::TenantConfig;
+
+/// Minimum delay between two downloads of the keys of the OIDC issuer (to handle key rotation without flooding the issuer)
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Settings of authentication, requests are not authenticated when no API key and no OIDC issuer are configured
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub api_keys: Vec<String>,
+    pub oidc: Option<OidcConfig>,
+}
+
+/// An OIDC issuer whose access tokens are accepted
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct OidcConfig {
+    /// URL of the issuer (like `https://accounts.example.com/realms/cloud`), the keys are discovered from `<issuer>/.well-known/openid-configuration`
+    pub issuer: String,
+    /// Expected audience (`aud` claim) of the tokens
+    pub audience: String,
+    /// Accepted signing algorithms (like `["RS256", "ES256"]`), for the keys of the issuer without algorithm (`alg`). Keys with another algorithm are rejected when set.
+    #[serde(default)]
+    pub algorithms: Vec<Algorithm>,
+}
+
+/// Validates the JWT of an OIDC issuer
+struct OidcValidator {
+    config: OidcConfig,
+    jwks_uri: String,
+    keys: RwLock<(JwkSet, Instant)>,
+}
+
+#[derive(Deserialize)]
+struct OidcDiscovery {
+    jwks_uri: String,
+}
+
+async fn fetch_jwks(jwks_uri: &str) -> Result<JwkSet> {
+    reqwest::get(jwks_uri)
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("Cannot retrieve OIDC keys from {}", jwks_uri))?
+        .json()
+        .await
+        .with_context(|| format!("Invalid OIDC keys at {}", jwks_uri))
+}
+
+impl OidcValidator {
+    async fn discover(config: &OidcConfig) -> Result<Self> {
+        let discovery_url = format!(
+            "{}/.well-known/openid-configuration",
+            config.issuer.trim_end_matches('/')
+        );
+        let discovery: OidcDiscovery = reqwest::get(&discovery_url)
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Cannot retrieve OIDC configuration {}", discovery_url))?
+            .json()
+            .await
+            .with_context(|| format!("Invalid OIDC configuration {}", discovery_url))?;
+        let keys = fetch_jwks(&discovery.jwks_uri).await?;
+        Ok(Self::with_keys(config.clone(), discovery.jwks_uri, keys))
+    }
+
+    fn with_keys(config: OidcConfig, jwks_uri: String, keys: JwkSet) -> Self {
+        OidcValidator {
+            config,
+            jwks_uri,
+            keys: RwLock::new((keys, Instant::now())),
+        }
+    }
+
+    /// Returns the algorithms of the tokens signed by a key: the algorithm of the key, the configured algorithms, or RS256 for RSA keys (the default algorithm of OIDC)
+    fn algorithms(&self, jwk: &Jwk) -> Result<Vec<Algorithm>> {
+        let configured = &self.config.algorithms;
+        match jwk.common.key_algorithm {
+            Some(key_algorithm) => {
+                let algorithm =
+                    Algorithm::from_str(&key_algorithm.to_string()).with_context(|| {
+                        format!("OIDC key with an unsupported algorithm {}", key_algorithm)
+                    })?;
+                if !configured.is_empty() && !configured.contains(&algorithm) {
+                    anyhow::bail!(
+                        "OIDC key with an algorithm that is not accepted ({:?})",
+                        algorithm
+                    );
+                }
+                Ok(vec![algorithm])
+            }
+            None if !configured.is_empty() => Ok(configured.clone()),
+            None if matches!(jwk.algorithm, AlgorithmParameters::RSA(_)) => {
+                Ok(vec![Algorithm::RS256])
+            }
+            None => anyhow::bail!("OIDC key without algorithm, set the algorithms of the issuer"),
+        }
+    }
+
+    /// Returns the key (and its algorithms) that signed a token, downloads the keys again if it is unknown (key rotation)
+    async fn find_key(&self, kid: &str) -> Result<(DecodingKey, Vec<Algorithm>)> {
+        let key = |keys: &JwkSet| {
+            keys.find(kid).map(|jwk| {
+                let key = DecodingKey::from_jwk(jwk).context("Unsupported OIDC key")?;
+                Ok((key, self.algorithms(jwk)?))
+            })
+        };
+        if let Some(key) = key(&self.keys.read().await.0) {
+            return key;
+        }
+        let mut keys = self.keys.write().await;
+        if keys.1.elapsed() >= JWKS_REFRESH_INTERVAL {
+            *keys = (fetch_jwks(&self.jwks_uri).await?, Instant::now());
+        }
+        key(&keys.0).context("Token signed with an unknown key")?
+    }
+
+    async fn validate(&self, token: &str) -> Result<()> {
+        let header = jsonwebtoken::decode_header(token).context("Invalid token")?;
+        let kid = header.kid.context("Invalid token without key id (kid)")?;
+        let (key, algorithms) = self.find_key(&kid).await?;
+        // The algorithm of the header must be one of the key (checked by decode)
+        let mut validation = Validation::new(algorithms[0]);
+        validation.algorithms = algorithms;
+        validation.set_issuer(&[&self.config.issuer]);
+        validation.set_audience(&[&self.config.audience]);
+        jsonwebtoken::decode::<serde_json::Value>(token, &key, &validation)
+            .context("Invalid token")?;
+        Ok(())
+    }
+}
+
+/// Compares secrets in a time that does not depend on the position of the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Returns the token of a request, from the `Authorization` (bearer) or `X-API-Key` headers
+pub(crate) fn request_token<'a>(
+    authorization: Option<&'a str>,
+    api_key: Option<&'a str>,
+) -> Option<&'a str> {
+    authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or(api_key)
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+/// Authenticates the requests of the server (clones share the keys of the OIDC issuer)
+#[derive(Clone)]
+pub struct Authenticator {
+    api_keys: Vec<String>,
+    oidc: Option<Arc<OidcValidator>>,
+    tenants: Vec<TenantConfig>,
+}
+
+impl Authenticator {
+    /// Returns an authenticator for the configuration and the tenants, the OIDC configuration and keys of the issuer are retrieved first
+    pub async fn new(config: &AuthConfig, tenants: &[TenantConfig]) -> Result<Self> {
+        let oidc = match &config.oidc {
+            Some(oidc_config) => Some(Arc::new(OidcValidator::discover(oidc_config).await?)),
+            None => None,
+        };
+        Ok(Authenticator {
+            api_keys: config.api_keys.clone(),
+            oidc,
+            tenants: tenants.to_vec(),
+        })
+    }
+
+    /// Returns false when requests do not need to be authenticated
+    pub fn is_enabled(&self) -> bool {
+        !self.api_keys.is_empty() || self.oidc.is_some() || !self.tenants.is_empty()
+    }
+
+    /// Checks that the token is one of the API keys, a key of a tenant or a valid OIDC token
+    ///
+    /// Returns the tenant of the key, none for the keys of the server and OIDC tokens.
+    pub async fn authenticate(&self, token: &str) -> Result<Option<TenantConfig>> {
+        let is_key = |key: &String| constant_time_eq(key.as_bytes(), token.as_bytes());
+        if let Some(tenant) = self
+            .tenants
+            .iter()
+            .find(|tenant| tenant.api_keys.iter().any(is_key))
+        {
+            return Ok(Some(tenant.clone()));
+        }
+        if self.api_keys.iter().any(is_key) {
+            return Ok(None);
+        }
+        match &self.oidc {
+            Some(oidc) => oidc.validate(token).await.map(|()| None),
+            None => anyhow::bail!("Invalid API key"),
+        }
+    }
+}
+
+/// A request guard that succeeds when the request is authenticated (or when authentication is disabled)
+pub struct Authenticated {
+    /// The tenant of the request, none when it is not authenticated by the key of a tenant
+    pub tenant: Option<TenantConfig>,
+}
+
+impl Authenticated {
+    /// Name of the tenant of the request
+    pub fn tenant_name(&self) -> Option<&str> {
+        self.tenant.as_ref().map(|tenant| tenant.name.as_str())
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Authenticated {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let authenticator = match request.rocket().state::<Authenticator>() {
+            Some(authenticator) if authenticator.is_enabled() => authenticator,
+            _ => return Outcome::Success(Authenticated { tenant: None }),
+        };
+        let headers = request.headers();
+        let Some(token) = request_token(
+            headers.get_one("Authorization"),
+            headers.get_one("X-API-Key"),
+        ) else {
+            return Outcome::Error((
+                Status::Unauthorized,
+                "Missing API key or bearer token".to_string(),
+            ));
+        };
+        match authenticator.authenticate(token).await {
+            Ok(tenant) => {
+                let name = tenant.as_ref().map(|tenant| tenant.name.clone());
+                request.local_cache(|| RequestTenant(name));
+                Outcome::Success(Authenticated { tenant })
+            }
+            Err(e) => {
+                warn!("Rejecting request to {}: {:#}", request.uri(), e);
+                Outcome::Error((Status::Unauthorized, e.to_string()))
+            }
+        }
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for Authenticated {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        let scheme = SecurityScheme {
+            description: Some("API key or OIDC access token".to_string()),
+            data: SecuritySchemeData::Http {
+                scheme: "bearer".to_string(),
+                bearer_format: None,
+            },
+            extensions: Object::default(),
+        };
+        let mut requirement = SecurityRequirement::new();
+        requirement.insert("bearer".to_string(), Vec::new());
+        Ok(RequestHeaderInput::Security(
+            "bearer".to_string(),
+            scheme,
+            requirement,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    const SECRET: &[u8] = b"cloud-scanner-test-secret-0123";
+
+    fn oidc_authenticator() -> Authenticator {
+        let keys: JwkSet = serde_json::from_value(json!({
+            "keys": [
+                {"kty": "oct", "kid": "k1", "alg": "HS256", "k": "Y2xvdWQtc2Nhbm5lci10ZXN0LXNlY3JldC0wMTIz"},
+                {"kty": "oct", "kid": "k2", "k": "Y2xvdWQtc2Nhbm5lci10ZXN0LXNlY3JldC0wMTIz"}
+            ]
+        }))
+        .unwrap();
+        let config = OidcConfig {
+            issuer: "https://issuer.example.com".to_string(),
+            audience: "cloud-scanner".to_string(),
+            algorithms: Vec::new(),
+        };
+        Authenticator {
+            api_keys: Vec::new(),
+            tenants: Vec::new(),
+            oidc: Some(Arc::new(OidcValidator::with_keys(
+                config,
+                "https://issuer.example.com/keys".to_string(),
+                keys,
+            ))),
+        }
+    }
+
+    fn token(audience: &str) -> String {
+        signed_token(audience, Algorithm::HS256, Some("k1"))
+    }
+
+    fn signed_token(audience: &str, algorithm: Algorithm, kid: Option<&str>) -> String {
+        let header = Header {
+            kid: kid.map(str::to_string),
+            ..Header::new(algorithm)
+        };
+        let claims = json!({
+            "iss": "https://issuer.example.com",
+            "aud": audience,
+            "sub": "ci",
+            "exp": chrono::Utc::now().timestamp() + 600,
+        });
+        jsonwebtoken::encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+    }
+
+    #[test]
+    fn token_is_read_from_bearer_or_api_key_header() {
+        assert_eq!(Some("abc"), request_token(Some("Bearer abc"), None));
+        assert_eq!(Some("def"), request_token(None, Some("def")));
+        assert_eq!(Some("def"), request_token(Some("Basic xyz"), Some("def")));
+        assert_eq!(None, request_token(Some("Bearer "), None));
+        assert_eq!(None, request_token(None, None));
+    }
+
+    #[tokio::test]
+    async fn authenticate_with_static_api_keys() {
+        let authenticator = Authenticator::new(
+            &AuthConfig {
+                api_keys: vec!["key-1".to_string(), "key-2".to_string()],
+                oidc: None,
+            },
+            &[],
+        )
+        .await
+        .unwrap();
+        assert!(authenticator.is_enabled());
+        assert!(authenticator.authenticate("key-2").await.is_ok());
+        assert!(authenticator.authenticate("key-3").await.is_err());
+        assert!(authenticator.authenticate("key-").await.is_err());
+
+        let disabled = Authenticator::new(&AuthConfig::default(), &[])
+            .await
+            .unwrap();
+        assert!(!disabled.is_enabled());
+    }
+
+    #[tokio::test]
+    async fn tenants_are_authenticated_by_their_keys() {
+        let tenant = TenantConfig {
+            name: "team-a".to_string(),
+            api_keys: vec!["key-a".to_string()],
+            account: None,
+            allowed_regions: Vec::new(),
+        };
+        let authenticator = Authenticator::new(
+            &AuthConfig {
+                api_keys: vec!["server-key".to_string()],
+                oidc: None,
+            },
+            std::slice::from_ref(&tenant),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            Some(tenant),
+            authenticator.authenticate("key-a").await.unwrap()
+        );
+        assert_eq!(
+            None,
+            authenticator.authenticate("server-key").await.unwrap()
+        );
+        assert!(authenticator.authenticate("key-b").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn authenticate_with_oidc_tokens() {
+        let authenticator = oidc_authenticator();
+        assert!(authenticator
+            .authenticate(&token("cloud-scanner"))
+            .await
+            .is_ok());
+        assert!(authenticator
+            .authenticate(&token("another-service"))
+            .await
+            .is_err());
+        assert!(authenticator.authenticate("not-a-token").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn oidc_tokens_are_validated_with_the_algorithm_of_their_key() {
+        let authenticator = oidc_authenticator();
+        // The algorithm of the header is not the one of the key
+        let other_algorithm = signed_token("cloud-scanner", Algorithm::HS384, Some("k1"));
+        assert!(authenticator.authenticate(&other_algorithm).await.is_err());
+        // Tokens must name their key
+        let without_kid = signed_token("cloud-scanner", Algorithm::HS256, None);
+        let error = authenticator.authenticate(&without_kid).await.unwrap_err();
+        assert!(error.to_string().contains("kid"));
+        // Keys without algorithm are only accepted with the configured algorithms
+        let unpinned = signed_token("cloud-scanner", Algorithm::HS256, Some("k2"));
+        assert!(authenticator.authenticate(&unpinned).await.is_err());
+
+        let oidc = authenticator.oidc.as_ref().unwrap();
+        let jwk = |value: serde_json::Value| serde_json::from_value::<Jwk>(value).unwrap();
+        let rsa = jwk(json!({"kty": "RSA", "kid": "r1", "n": "AQAB", "e": "AQAB"}));
+        assert_eq!(vec![Algorithm::RS256], oidc.algorithms(&rsa).unwrap());
+        let encryption =
+            jwk(json!({"kty": "RSA", "kid": "r2", "alg": "RSA-OAEP", "n": "AQAB", "e": "AQAB"}));
+        assert!(oidc.algorithms(&encryption).is_err());
+
+        let configured = OidcValidator::with_keys(
+            OidcConfig {
+                algorithms: vec![Algorithm::ES256],
+                ..oidc.config.clone()
+            },
+            oidc.jwks_uri.clone(),
+            JwkSet { keys: Vec::new() },
+        );
+        assert_eq!(vec![Algorithm::ES256], configured.algorithms(&rsa).unwrap());
+        let rs512 =
+            jwk(json!({"kty": "RSA", "kid": "r3", "alg": "RS512", "n": "AQAB", "e": "AQAB"}));
+        assert!(configured.algorithms(&rs512).is_err());
+    }
+}
@@ -0,0 +1,421 @@
+//! Operational metrics of the server itself (requests, durations of scans, latencies of the Boavizta API, cache hits, errors and resources not assessed), served on `/internal/metrics` separately from the metrics of impacts.
+//!
+//! Metrics are recorded in a registry of the process, so that the scans and the calls to the Boavizta API can record them wherever they run.
+//!
+//! The durations of the stages of the scans (inventory, CloudWatch queries, estimation of impacts) are also logged as `key=value` lines (target `cloud_scanner::timing`), to see where slow scans spend their time without a server.
+use anyhow::{Context, Result};
+use log::{debug, info};
+use prometheus_client::encoding::text::encode;
+use prometheus_client::encoding::{EncodeLabelSet, EncodeLabelValue};
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
+use prometheus_client::registry::{Registry, Unit};
+#[cfg(feature = "server")]
+use rocket::fairing::{Fairing, Info, Kind};
+#[cfg(feature = "server")]
+use rocket::{Request, Response};
+use std::sync::atomic::AtomicU64;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+struct RequestLabels {
+    method: String,
+    /// Path of the matched route (like `/jobs/<id>`), so that ids do not create new series
+    route: String,
+    status: u16,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+struct ScanLabels {
+    kind: ScanKind,
+    outcome: Outcome,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+struct StageLabels {
+    stage: ScanStage,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+struct BoaviztaLabels {
+    endpoint: String,
+    outcome: Outcome,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+struct CacheLabels {
+    result: CacheResult,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+struct ErrorLabels {
+    source: ErrorSource,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+struct ProviderLabels {
+    /// Operation of the API of the cloud provider (like `DescribeInstances`)
+    operation: String,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+struct UnassessedLabels {
+    reason: UnassessedReason,
+}
+
+/// Why the impacts of a resource were not estimated
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum UnassessedReason {
+    /// The Boavizta API failed (like an unknown instance type)
+    BoaviztaError,
+    /// Boavizta API does not estimate this kind of resource (like object storage)
+    UnsupportedResource,
+}
+
+/// What a scan returns
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum ScanKind {
+    Inventory,
+    Impacts,
+}
+
+/// A stage of a scan
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum ScanStage {
+    /// Listing the resources of a region (including their CloudWatch queries)
+    Inventory,
+    /// A query of the CPU load of an instance to CloudWatch
+    CloudwatchQuery,
+    /// Estimating the impacts of an inventory with the Boavizta API
+    ImpactEstimation,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+enum Outcome {
+    Success,
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+enum CacheResult {
+    Hit,
+    Miss,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+enum ErrorSource {
+    /// Responses with a 5xx status
+    Http,
+    Scan,
+    Boavizta,
+    /// Calls to the API of the cloud provider
+    Provider,
+}
+
+fn outcome(success: bool) -> Outcome {
+    if success {
+        Outcome::Success
+    } else {
+        Outcome::Error
+    }
+}
+
+fn duration_histogram() -> Histogram {
+    // From 10ms to about 20 minutes
+    Histogram::new(exponential_buckets(0.01, 2.0, 18))
+}
+
+struct Telemetry {
+    registry: Registry,
+    requests: Family<RequestLabels, Counter>,
+    scan_durations: Family<ScanLabels, Histogram, fn() -> Histogram>,
+    stage_durations: Family<StageLabels, Histogram, fn() -> Histogram>,
+    boavizta_latencies: Family<BoaviztaLabels, Histogram, fn() -> Histogram>,
+    cache_lookups: Family<CacheLabels, Counter>,
+    cache_hit_ratio: Gauge<f64, AtomicU64>,
+    errors: Family<ErrorLabels, Counter>,
+    provider_errors: Family<ProviderLabels, Counter>,
+    unassessed_resources: Family<UnassessedLabels, Counter>,
+}
+
+impl Telemetry {
+    fn new() -> Self {
+        let mut registry = Registry::with_prefix("cloud_scanner");
+        let requests = Family::<RequestLabels, Counter>::default();
+        registry.register(
+            "http_requests",
+            "Requests served, by route and status",
+            requests.clone(),
+        );
+        let scan_durations: Family<ScanLabels, Histogram, fn() -> Histogram> =
+            Family::new_with_constructor(duration_histogram);
+        registry.register_with_unit(
+            "scan_duration",
+            "Duration of the scans (inventory and estimation of impacts)",
+            Unit::Seconds,
+            scan_durations.clone(),
+        );
+        let stage_durations: Family<StageLabels, Histogram, fn() -> Histogram> =
+            Family::new_with_constructor(duration_histogram);
+        registry.register_with_unit(
+            "scan_stage_duration",
+            "Duration of the stages of the scans (inventory, CloudWatch queries, estimation of impacts)",
+            Unit::Seconds,
+            stage_durations.clone(),
+        );
+        let boavizta_latencies: Family<BoaviztaLabels, Histogram, fn() -> Histogram> =
+            Family::new_with_constructor(duration_histogram);
+        registry.register_with_unit(
+            "boavizta_request_duration",
+            "Latency of the calls to the Boavizta API, by endpoint",
+            Unit::Seconds,
+            boavizta_latencies.clone(),
+        );
+        let cache_lookups = Family::<CacheLabels, Counter>::default();
+        registry.register(
+            "cache_lookups",
+            "Lookups in the response cache, by result (hit or miss)",
+            cache_lookups.clone(),
+        );
+        let cache_hit_ratio = Gauge::<f64, AtomicU64>::default();
+        registry.register(
+            "cache_hit_ratio",
+            "Share of the lookups in the response cache that were hits (between 0 and 1)",
+            cache_hit_ratio.clone(),
+        );
+        let errors = Family::<ErrorLabels, Counter>::default();
+        registry.register(
+            "errors",
+            "Errors, by source (http for 5xx responses, scan, boavizta or provider)",
+            errors.clone(),
+        );
+        let provider_errors = Family::<ProviderLabels, Counter>::default();
+        registry.register(
+            "provider_api_errors",
+            "Failed calls to the API of the cloud provider, by operation",
+            provider_errors.clone(),
+        );
+        let unassessed_resources = Family::<UnassessedLabels, Counter>::default();
+        registry.register(
+            "unassessed_resources",
+            "Resources whose impacts were not estimated, by reason",
+            unassessed_resources.clone(),
+        );
+        Telemetry {
+            registry,
+            requests,
+            scan_durations,
+            stage_durations,
+            boavizta_latencies,
+            cache_lookups,
+            cache_hit_ratio,
+            errors,
+            provider_errors,
+            unassessed_resources,
+        }
+    }
+
+    fn error(&self, source: ErrorSource) {
+        self.errors.get_or_create(&ErrorLabels { source }).inc();
+    }
+}
+
+static TELEMETRY: LazyLock<Telemetry> = LazyLock::new(Telemetry::new);
+
+/// Records a request served by the server
+pub fn record_request(method: &str, route: &str, status: u16) {
+    TELEMETRY
+        .requests
+        .get_or_create(&RequestLabels {
+            method: method.to_string(),
+            route: route.to_string(),
+            status,
+        })
+        .inc();
+    if status >= 500 {
+        TELEMETRY.error(ErrorSource::Http);
+    }
+}
+
+/// Records the duration of a scan, and counts it as an error when it failed
+pub fn record_scan(kind: ScanKind, duration: Duration, success: bool) {
+    TELEMETRY
+        .scan_durations
+        .get_or_create(&ScanLabels {
+            kind,
+            outcome: outcome(success),
+        })
+        .observe(duration.as_secs_f64());
+    info!(
+        target: "cloud_scanner::timing",
+        "scan kind={:?} success={} duration_ms={}",
+        kind,
+        success,
+        duration.as_millis()
+    );
+    if !success {
+        TELEMETRY.error(ErrorSource::Scan);
+    }
+}
+
+/// Records the duration of a stage of a scan, of a region when known (CloudWatch queries being logged at debug level, one per instance)
+pub fn record_scan_stage(stage: ScanStage, aws_region: Option<&str>, duration: Duration) {
+    TELEMETRY
+        .stage_durations
+        .get_or_create(&StageLabels { stage })
+        .observe(duration.as_secs_f64());
+    let region = aws_region.unwrap_or("-");
+    let duration_ms = duration.as_millis();
+    match stage {
+        ScanStage::CloudwatchQuery => debug!(
+            target: "cloud_scanner::timing",
+            "stage={:?} aws_region={} duration_ms={}",
+            stage,
+            region,
+            duration_ms
+        ),
+        _ => info!(
+            target: "cloud_scanner::timing",
+            "stage={:?} aws_region={} duration_ms={}",
+            stage,
+            region,
+            duration_ms
+        ),
+    }
+}
+
+/// Records the latency of a call to an endpoint of the Boavizta API (like `instance` or `ssd`), and counts it as an error when it failed
+pub fn record_boavizta_call(endpoint: &str, duration: Duration, success: bool) {
+    TELEMETRY
+        .boavizta_latencies
+        .get_or_create(&BoaviztaLabels {
+            endpoint: endpoint.to_string(),
+            outcome: outcome(success),
+        })
+        .observe(duration.as_secs_f64());
+    if !success {
+        TELEMETRY.error(ErrorSource::Boavizta);
+    }
+}
+
+/// Counts a failed call to an operation of the API of the cloud provider (like `DescribeInstances`)
+pub fn record_provider_error(operation: &str) {
+    TELEMETRY
+        .provider_errors
+        .get_or_create(&ProviderLabels {
+            operation: operation.to_string(),
+        })
+        .inc();
+    TELEMETRY.error(ErrorSource::Provider);
+}
+
+/// Counts a resource whose impacts were not estimated
+pub fn record_unassessed_resource(reason: UnassessedReason) {
+    TELEMETRY
+        .unassessed_resources
+        .get_or_create(&UnassessedLabels { reason })
+        .inc();
+}
+
+/// Records a lookup in a response cache
+pub fn record_cache_lookup(hit: bool) {
+    let result = if hit {
+        CacheResult::Hit
+    } else {
+        CacheResult::Miss
+    };
+    TELEMETRY
+        .cache_lookups
+        .get_or_create(&CacheLabels { result })
+        .inc();
+}
+
+/// Returns the metrics of the server (OpenMetrics format)
+pub fn encode_metrics() -> Result<String> {
+    let lookups = |result| {
+        TELEMETRY
+            .cache_lookups
+            .get_or_create(&CacheLabels { result })
+            .get()
+    };
+    let (hits, misses) = (lookups(CacheResult::Hit), lookups(CacheResult::Miss));
+    if hits + misses > 0 {
+        TELEMETRY
+            .cache_hit_ratio
+            .set(hits as f64 / (hits + misses) as f64);
+    }
+    let mut buffer = String::new();
+    encode(&mut buffer, &TELEMETRY.registry).context("Cannot encode server metrics")?;
+    Ok(buffer)
+}
+
+/// A fairing recording the requests served
+#[cfg(feature = "server")]
+pub struct RequestMetrics;
+
+#[cfg(feature = "server")]
+#[rocket::async_trait]
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request metrics",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let route = request
+            .route()
+            .map(|route| route.uri.path().to_string())
+            .unwrap_or_else(|| "unmatched".to_string());
+        record_request(request.method().as_str(), &route, response.status().code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_metrics_are_encoded() {
+        record_request("GET", "/jobs/<id>", 200);
+        record_request("GET", "/metrics", 500);
+        record_scan(ScanKind::Impacts, Duration::from_millis(1500), true);
+        record_scan_stage(
+            ScanStage::CloudwatchQuery,
+            Some("eu-west-3"),
+            Duration::from_millis(80),
+        );
+        record_boavizta_call("instance", Duration::from_millis(120), false);
+        record_cache_lookup(true);
+        record_cache_lookup(false);
+        record_provider_error("DescribeInstances");
+        record_unassessed_resource(UnassessedReason::UnsupportedResource);
+
+        let metrics = encode_metrics().unwrap();
+        assert!(metrics.contains(
+            r#"cloud_scanner_http_requests_total{method="GET",route="/jobs/<id>",status="200"}"#
+        ));
+        assert!(metrics.contains(
+            r#"cloud_scanner_scan_duration_seconds_count{kind="Impacts",outcome="Success"}"#
+        ));
+        assert!(metrics.contains(
+            r#"cloud_scanner_scan_stage_duration_seconds_count{stage="CloudwatchQuery"}"#
+        ));
+        assert!(metrics.contains(
+            r#"cloud_scanner_boavizta_request_duration_seconds_count{endpoint="instance",outcome="Error"}"#
+        ));
+        assert!(metrics.contains(r#"cloud_scanner_cache_lookups_total{result="Hit"}"#));
+        assert!(metrics.contains("cloud_scanner_cache_hit_ratio"));
+        assert!(metrics.contains(r#"cloud_scanner_errors_total{source="Http"}"#));
+        assert!(metrics.contains(r#"cloud_scanner_errors_total{source="Boavizta"}"#));
+        assert!(metrics.contains(r#"cloud_scanner_errors_total{source="Provider"}"#));
+        assert!(metrics
+            .contains(r#"cloud_scanner_provider_api_errors_total{operation="DescribeInstances"}"#));
+        assert!(metrics
+            .contains(r#"cloud_scanner_unassessed_resources_total{reason="UnsupportedResource"}"#));
+    }
+}
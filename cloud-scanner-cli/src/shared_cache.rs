@@ -0,0 +1,150 @@
+//! A cache shared by several processes in Redis (like the replicas of the standalone server, or the invocations of the Lambda functions), for the CPU loads of instances (see [crate::utilization_cache]) and the impacts of the queries of Boavizta API.
+//!
+//! The shared cache comes in addition to the memory of each process, once configured with [configure]. It is optional for the scans: its failures are logged, and the scans read the metrics or query Boavizta API as without cache.
+use anyhow::{Context, Result};
+use redis::aio::ConnectionManager;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+/// Prefix of the keys of the cache, so that it can share a Redis database with other applications
+const KEY_PREFIX: &str = "cloud-scanner:";
+
+/// Seconds the impacts of a query of Boavizta API are shared, unless set with [set_impacts_ttl]
+pub const DEFAULT_IMPACTS_TTL_SECONDS: u64 = 86400;
+
+static IMPACTS_TTL_SECONDS: AtomicU64 = AtomicU64::new(DEFAULT_IMPACTS_TTL_SECONDS);
+
+struct SharedCache {
+    client: redis::Client,
+    /// Connected on first use, and reconnected by the manager when the connection is lost
+    connection: OnceCell<ConnectionManager>,
+}
+
+static CACHE: OnceLock<SharedCache> = OnceLock::new();
+
+/// Shares the cache of the process with the Redis server at `url` (like `redis://cache:6379`, or `rediss://` with TLS)
+pub fn configure(url: &str) -> Result<()> {
+    // The url is not logged, as it may contain a password
+    let client = redis::Client::open(url).context("Invalid URL of the shared cache")?;
+    let cache = SharedCache {
+        client,
+        connection: OnceCell::new(),
+    };
+    if CACHE.set(cache).is_err() {
+        debug!("The shared cache is already configured");
+    }
+    Ok(())
+}
+
+/// Returns true when the cache is shared with other processes
+pub fn is_configured() -> bool {
+    CACHE.get().is_some()
+}
+
+/// Sets the duration the impacts of a query of Boavizta API are shared (zero disables the sharing of impacts)
+pub fn set_impacts_ttl(ttl: Duration) {
+    IMPACTS_TTL_SECONDS.store(ttl.as_secs(), Ordering::Relaxed);
+}
+
+pub(crate) fn impacts_ttl() -> Duration {
+    Duration::from_secs(IMPACTS_TTL_SECONDS.load(Ordering::Relaxed))
+}
+
+/// Returns the key of the impacts of a query (json) of a Boavizta API
+pub(crate) fn impacts_key(api_url: &str, query: &str) -> String {
+    let digest = Sha256::digest(format!("{}\n{}", api_url, query));
+    format!("impacts:{}", hex::encode(digest))
+}
+
+async fn connection() -> Option<ConnectionManager> {
+    let cache = CACHE.get()?;
+    let connection = cache
+        .connection
+        .get_or_try_init(|| ConnectionManager::new(cache.client.clone()))
+        .await;
+    match connection {
+        Ok(connection) => Some(connection.clone()),
+        Err(e) => {
+            warn!("Cannot connect to the shared cache: {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the values of keys (`None` for missing keys, or for all the keys when the cache is not configured or fails)
+pub(crate) async fn get_all(keys: &[String]) -> Vec<Option<String>> {
+    let missing = || vec![None; keys.len()];
+    if keys.is_empty() {
+        return Vec::new();
+    }
+    let Some(mut connection) = connection().await else {
+        return missing();
+    };
+    let prefixed: Vec<String> = keys
+        .iter()
+        .map(|key| format!("{}{}", KEY_PREFIX, key))
+        .collect();
+    let values: redis::RedisResult<Vec<Option<String>>> = redis::cmd("MGET")
+        .arg(&prefixed)
+        .query_async(&mut connection)
+        .await;
+    match values {
+        Ok(values) => values,
+        Err(e) => {
+            warn!("Cannot read the shared cache: {}", e);
+            missing()
+        }
+    }
+}
+
+/// Sets the values of keys, expiring after `ttl` (nothing is set with a zero TTL, or when the cache is not configured)
+pub(crate) async fn set_all(entries: &[(String, String)], ttl: Duration) {
+    if entries.is_empty() || ttl.is_zero() {
+        return;
+    }
+    let Some(mut connection) = connection().await else {
+        return;
+    };
+    let mut pipeline = redis::pipe();
+    for (key, value) in entries {
+        pipeline
+            .cmd("SET")
+            .arg(format!("{}{}", KEY_PREFIX, key))
+            .arg(value)
+            .arg("EX")
+            .arg(ttl.as_secs().max(1))
+            .ignore();
+    }
+    let set: redis::RedisResult<()> = pipeline.query_async(&mut connection).await;
+    if let Err(e) = set {
+        warn!("Cannot write to the shared cache: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn the_cache_is_optional() {
+        assert!(configure("http://cache:6379").is_err());
+        assert!(!is_configured());
+        let keys = vec!["cpu-load:10:i-1".to_string(), "cpu-load:10:i-2".to_string()];
+        assert_eq!(vec![None, None], get_all(&keys).await);
+        set_all(
+            &[(keys[0].clone(), "42".to_string())],
+            Duration::from_secs(60),
+        )
+        .await;
+
+        // Queries of impacts are shared by the users of the same API
+        let query = r#"{"instance_type":"m6g.xlarge"}"#;
+        let key = impacts_key("https://api.boavizta.org", query);
+        assert!(key.starts_with("impacts:"));
+        assert_eq!(key, impacts_key("https://api.boavizta.org", query));
+        assert_ne!(key, impacts_key("http://localhost:5000", query));
+    }
+}
@@ -0,0 +1,599 @@
+//! Showback reports (`showback`): the footprint of each team (value of a tag) over the last complete period of the scans of a result store, compared to the previous period, with its top resources and rightsizing suggestions, so that each team sees the footprint it is responsible for.
+//!
+//! Impacts are prorated to the hours covered by the scans like the [aggregation of scans](crate::scan_aggregation), and the shared resources are split across teams by the [allocation rules](crate::allocation).
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::allocation::AllocationRules;
+use crate::model::{EstimatedInventory, InstanceState, ResourceDetails};
+use crate::report::escape_html;
+use crate::scan_aggregation::{hours_between, AggregationPeriod, CoveredScan};
+
+/// Maximum number of top resources listed per team
+const TOP_RESOURCES: usize = 5;
+
+/// Average CPU load (in percent) below which a running instance is considered idle
+const IDLE_CPU_LOAD: f64 = 5.0;
+
+/// Average CPU load (in percent) below which a running instance is considered oversized
+const OVERSIZED_CPU_LOAD: f64 = 20.0;
+
+/// The GWP of a resource allocated to a team over the period
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ResourceFootprint {
+    pub resource_id: String,
+    pub resource_kind: String,
+    pub gwp_kgco2eq: f64,
+}
+
+/// A running instance with a low CPU load in the last scan of the period
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RightsizingSuggestion {
+    pub resource_id: String,
+    pub instance_type: String,
+    pub average_cpu_load: f64,
+    /// GWP of the instance allocated to the team over the period
+    pub gwp_kgco2eq: f64,
+    pub suggestion: String,
+}
+
+/// The footprint of a team over the period
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TeamShowback {
+    /// Value of the tag of the team, none for the resources without the tag
+    pub tag_value: Option<String>,
+    /// Number of resources over the covered hours
+    pub average_number_of_resources: f64,
+    pub adp_kgsbeq: f64,
+    pub pe_megajoules: f64,
+    pub gwp_manufacture_kgco2eq: f64,
+    pub gwp_use_kgco2eq: f64,
+    /// GWP (manufacture and use) of the team over the previous period
+    pub previous_gwp_kgco2eq: f64,
+    /// Change of the GWP compared to the previous period, in percent (none when the team had no GWP over the previous period)
+    pub gwp_change_percent: Option<f64>,
+    /// Resources with the highest GWP, in descending order
+    pub top_resources: Vec<ResourceFootprint>,
+    /// Suggestions on the instances with a low CPU load, highest GWP first
+    pub rightsizing: Vec<RightsizingSuggestion>,
+}
+
+impl TeamShowback {
+    pub fn gwp_kgco2eq(&self) -> f64 {
+        self.gwp_manufacture_kgco2eq + self.gwp_use_kgco2eq
+    }
+
+    /// Name of the team, `untagged` for the resources without the tag
+    pub fn name(&self) -> &str {
+        self.tag_value.as_deref().unwrap_or("untagged")
+    }
+
+    /// Change of the GWP compared to the previous period, like `+12.5 %` or `new`
+    fn trend(&self) -> String {
+        match self.gwp_change_percent {
+            Some(change) => format!("{:+.1} %", change),
+            None => "new".to_string(),
+        }
+    }
+}
+
+/// The showback report of the teams over a period
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ShowbackReport {
+    /// Key of the tag of the teams
+    pub tag_key: String,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub previous_period_start: DateTime<Utc>,
+    pub period_hours: f64,
+    /// Hours of the period covered by scans
+    pub covered_hours: f64,
+    /// Teams by descending GWP
+    pub teams: Vec<TeamShowback>,
+}
+
+/// Accumulates the footprint of a team over a period
+#[derive(Default)]
+struct TeamTotals {
+    resource_hours: f64,
+    adp_kgsbeq: f64,
+    pe_megajoules: f64,
+    gwp_manufacture_kgco2eq: f64,
+    gwp_use_kgco2eq: f64,
+    resources: BTreeMap<String, ResourceFootprint>,
+}
+
+/// Returns the hours covered by the scans between two dates and the footprint of each value of the tag
+fn team_totals(
+    scans: &[(CoveredScan, EstimatedInventory)],
+    from: DateTime<Utc>,
+    until: DateTime<Utc>,
+    tag_key: &str,
+    allocations: &AllocationRules,
+) -> (f64, BTreeMap<Option<String>, TeamTotals>) {
+    let mut covered_hours = 0.0;
+    let mut teams: BTreeMap<Option<String>, TeamTotals> = BTreeMap::new();
+    for (scan, estimated_inventory) in scans.iter() {
+        let hours = scan.covered_hours_between(from, until);
+        if hours <= 0.0 || scan.duration_of_use_hours <= 0.0 {
+            continue;
+        }
+        covered_hours += hours;
+        let share = hours / scan.duration_of_use_hours;
+        for resource in estimated_inventory.impacting_resources.iter() {
+            let cloud_resource = &resource.cloud_resource;
+            for (tag_value, allocated_share) in allocations.shares(cloud_resource, tag_key) {
+                let totals = teams.entry(tag_value).or_default();
+                totals.resource_hours += hours * allocated_share;
+                let Some(impacts) = resource.impacts_values.as_ref() else {
+                    continue;
+                };
+                let share = share * allocated_share;
+                let gwp_manufacture_kgco2eq = impacts.gwp_manufacture_kgco2eq * share;
+                let gwp_use_kgco2eq = impacts.gwp_use_kgco2eq * share;
+                totals.adp_kgsbeq +=
+                    (impacts.adp_manufacture_kgsbeq + impacts.adp_use_kgsbeq) * share;
+                totals.pe_megajoules +=
+                    (impacts.pe_manufacture_megajoules + impacts.pe_use_megajoules) * share;
+                totals.gwp_manufacture_kgco2eq += gwp_manufacture_kgco2eq;
+                totals.gwp_use_kgco2eq += gwp_use_kgco2eq;
+                totals
+                    .resources
+                    .entry(cloud_resource.id.clone())
+                    .or_insert_with(|| ResourceFootprint {
+                        resource_id: cloud_resource.id.clone(),
+                        resource_kind: cloud_resource.resource_details.kind().to_string(),
+                        gwp_kgco2eq: 0.0,
+                    })
+                    .gwp_kgco2eq += gwp_manufacture_kgco2eq + gwp_use_kgco2eq;
+            }
+        }
+    }
+    (covered_hours, teams)
+}
+
+/// Returns the suggestions on the running instances of a team with a low CPU load in an inventory
+fn rightsizing_suggestions(
+    estimated_inventory: &EstimatedInventory,
+    tag_key: &str,
+    allocations: &AllocationRules,
+    team: &Option<String>,
+    footprints: &BTreeMap<String, ResourceFootprint>,
+) -> Vec<RightsizingSuggestion> {
+    let mut suggestions: Vec<RightsizingSuggestion> = estimated_inventory
+        .impacting_resources
+        .iter()
+        .filter_map(|resource| {
+            let cloud_resource = &resource.cloud_resource;
+            let ResourceDetails::Instance {
+                instance_type,
+                usage: Some(usage),
+            } = &cloud_resource.resource_details
+            else {
+                return None;
+            };
+            if usage.state != InstanceState::Running || usage.average_cpu_load >= OVERSIZED_CPU_LOAD
+            {
+                return None;
+            }
+            if !allocations
+                .shares(cloud_resource, tag_key)
+                .iter()
+                .any(|(tag_value, _)| tag_value == team)
+            {
+                return None;
+            }
+            let suggestion = if usage.average_cpu_load < IDLE_CPU_LOAD {
+                "Idle: stop it when unused or remove it"
+            } else {
+                "Oversized: move to a smaller instance type"
+            };
+            Some(RightsizingSuggestion {
+                resource_id: cloud_resource.id.clone(),
+                instance_type: instance_type.clone(),
+                average_cpu_load: usage.average_cpu_load,
+                gwp_kgco2eq: footprints
+                    .get(&cloud_resource.id)
+                    .map(|footprint| footprint.gwp_kgco2eq)
+                    .unwrap_or_default(),
+                suggestion: suggestion.to_string(),
+            })
+        })
+        .collect();
+    suggestions.sort_by(|a, b| b.gwp_kgco2eq.total_cmp(&a.gwp_kgco2eq));
+    suggestions
+}
+
+/// Returns the showback report of the teams (values of the tag `tag_key`, with the shared resources allocated by the allocation rules) over the last period complete at a date, from the scans (with their estimated inventory) of this period and the previous one
+pub fn build_showback_report(
+    scans: &[(CoveredScan, EstimatedInventory)],
+    tag_key: &str,
+    allocations: &AllocationRules,
+    period: AggregationPeriod,
+    at: DateTime<Utc>,
+) -> ShowbackReport {
+    let (previous_period_start, period_start, period_end) = report_periods(period, at);
+    let (covered_hours, current) =
+        team_totals(scans, period_start, period_end, tag_key, allocations);
+    let (_, previous) = team_totals(
+        scans,
+        previous_period_start,
+        period_start,
+        tag_key,
+        allocations,
+    );
+    // Rightsizing is suggested from the usage of the resources in the last scan of the period
+    let last_inventory = scans
+        .iter()
+        .rev()
+        .find(|(scan, _)| scan.covered_hours_between(period_start, period_end) > 0.0)
+        .map(|(_, estimated_inventory)| estimated_inventory);
+
+    let mut teams: Vec<TeamShowback> = current
+        .into_iter()
+        .map(|(tag_value, totals)| {
+            let previous_gwp_kgco2eq = previous
+                .get(&tag_value)
+                .map(|previous| previous.gwp_manufacture_kgco2eq + previous.gwp_use_kgco2eq)
+                .unwrap_or_default();
+            let gwp_kgco2eq = totals.gwp_manufacture_kgco2eq + totals.gwp_use_kgco2eq;
+            let rightsizing = last_inventory
+                .map(|estimated_inventory| {
+                    rightsizing_suggestions(
+                        estimated_inventory,
+                        tag_key,
+                        allocations,
+                        &tag_value,
+                        &totals.resources,
+                    )
+                })
+                .unwrap_or_default();
+            let mut top_resources: Vec<ResourceFootprint> =
+                totals.resources.into_values().collect();
+            top_resources.sort_by(|a, b| b.gwp_kgco2eq.total_cmp(&a.gwp_kgco2eq));
+            top_resources.truncate(TOP_RESOURCES);
+            TeamShowback {
+                tag_value,
+                average_number_of_resources: totals.resource_hours / covered_hours,
+                adp_kgsbeq: totals.adp_kgsbeq,
+                pe_megajoules: totals.pe_megajoules,
+                gwp_manufacture_kgco2eq: totals.gwp_manufacture_kgco2eq,
+                gwp_use_kgco2eq: totals.gwp_use_kgco2eq,
+                previous_gwp_kgco2eq,
+                gwp_change_percent: (previous_gwp_kgco2eq > 0.0)
+                    .then_some((gwp_kgco2eq - previous_gwp_kgco2eq) / previous_gwp_kgco2eq * 100.0),
+                top_resources,
+                rightsizing,
+            }
+        })
+        .collect();
+    teams.sort_by(|a, b| b.gwp_kgco2eq().total_cmp(&a.gwp_kgco2eq()));
+
+    ShowbackReport {
+        tag_key: tag_key.to_string(),
+        period_start,
+        period_end,
+        previous_period_start,
+        period_hours: hours_between(period_start, period_end),
+        covered_hours,
+        teams,
+    }
+}
+
+/// Returns the start of the previous period, and the start and end of the last period complete at a date
+pub fn report_periods(
+    period: AggregationPeriod,
+    at: DateTime<Utc>,
+) -> (DateTime<Utc>, DateTime<Utc>, DateTime<Utc>) {
+    let period_end = period.start_of(at);
+    let period_start = period.start_of(period_end - chrono::Duration::seconds(1));
+    let previous_period_start = period.start_of(period_start - chrono::Duration::seconds(1));
+    (previous_period_start, period_start, period_end)
+}
+
+impl ShowbackReport {
+    /// Keeps a single team (its value of the tag)
+    pub fn of_team(mut self, team: &str) -> Result<Self> {
+        self.teams
+            .retain(|showback| showback.tag_value.as_deref() == Some(team));
+        if self.teams.is_empty() {
+            anyhow::bail!(
+                "No resource of the team {}={} over the period",
+                self.tag_key,
+                team
+            );
+        }
+        Ok(self)
+    }
+
+    pub fn title(&self) -> String {
+        format!(
+            "Showback per {} - {} to {}",
+            self.tag_key,
+            self.period_start.format("%Y-%m-%d"),
+            self.period_end.format("%Y-%m-%d")
+        )
+    }
+
+    /// Coverage of the period by the scans, like `720.0 of 744.0 hours covered by scans`
+    fn coverage(&self) -> String {
+        format!(
+            "{:.1} of {:.1} hours covered by scans, compared to the period starting on {}",
+            self.covered_hours,
+            self.period_hours,
+            self.previous_period_start.format("%Y-%m-%d")
+        )
+    }
+
+    /// Renders the report as Markdown, a section per team
+    pub fn to_markdown(&self) -> String {
+        let mut md = format!("# {}\n\n{}\n\n", self.title(), self.coverage());
+        md.push_str("| Team | Resources | GWP (kgCO2eq) | Previous period | Trend |\n| --- | --- | --- | --- | --- |\n");
+        for team in self.teams.iter() {
+            let _ = writeln!(
+                md,
+                "| {} | {:.1} | {:.3} | {:.3} | {} |",
+                team.name(),
+                team.average_number_of_resources,
+                team.gwp_kgco2eq(),
+                team.previous_gwp_kgco2eq,
+                team.trend()
+            );
+        }
+        for team in self.teams.iter() {
+            let _ = write!(
+                md,
+                "\n## {}\n\n- GWP: {:.3} kgCO2eq (manufacture {:.3}, use {:.3}), {} compared to the previous period\n- Primary energy: {:.3} MJ\n- Abiotic depletion potential: {:.6} kgSbeq\n",
+                team.name(),
+                team.gwp_kgco2eq(),
+                team.gwp_manufacture_kgco2eq,
+                team.gwp_use_kgco2eq,
+                team.trend(),
+                team.pe_megajoules,
+                team.adp_kgsbeq
+            );
+            if !team.top_resources.is_empty() {
+                md.push_str("\n### Top resources\n\n");
+                for resource in team.top_resources.iter() {
+                    let _ = writeln!(
+                        md,
+                        "- {} ({}): {:.3} kgCO2eq",
+                        resource.resource_id, resource.resource_kind, resource.gwp_kgco2eq
+                    );
+                }
+            }
+            if !team.rightsizing.is_empty() {
+                md.push_str("\n### Rightsizing suggestions\n\n");
+                for suggestion in team.rightsizing.iter() {
+                    let _ = writeln!(
+                        md,
+                        "- {} ({}, {:.1} % average CPU load, {:.3} kgCO2eq): {}",
+                        suggestion.resource_id,
+                        suggestion.instance_type,
+                        suggestion.average_cpu_load,
+                        suggestion.gwp_kgco2eq,
+                        suggestion.suggestion
+                    );
+                }
+            }
+        }
+        md
+    }
+
+    /// Renders the report as a standalone HTML page, a section per team
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        html.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+        let _ = writeln!(html, "<title>{}</title>", escape_html(&self.title()));
+        html.push_str("<style>body{font-family:sans-serif;margin:2em;color:#222}table{border-collapse:collapse;margin-bottom:1em}td,th{border:1px solid #ccc;padding:4px 8px;text-align:left}th{background:#eee}</style>\n");
+        html.push_str("</head>\n<body>\n");
+        let _ = writeln!(html, "<h1>{}</h1>", escape_html(&self.title()));
+        let _ = writeln!(html, "<p>{}</p>", self.coverage());
+        html.push_str("<table>\n<tr><th>Team</th><th>Resources</th><th>GWP (kgCO2eq)</th><th>Previous period</th><th>Trend</th></tr>\n");
+        for team in self.teams.iter() {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{:.1}</td><td>{:.3}</td><td>{:.3}</td><td>{}</td></tr>",
+                escape_html(team.name()),
+                team.average_number_of_resources,
+                team.gwp_kgco2eq(),
+                team.previous_gwp_kgco2eq,
+                team.trend()
+            );
+        }
+        html.push_str("</table>\n");
+        for team in self.teams.iter() {
+            let _ = writeln!(html, "<h2>{}</h2>", escape_html(team.name()));
+            let _ = writeln!(
+                html,
+                "<ul>\n<li>GWP: {:.3} kgCO2eq (manufacture {:.3}, use {:.3}), {} compared to the previous period</li>\n<li>Primary energy: {:.3} MJ</li>\n<li>Abiotic depletion potential: {:.6} kgSbeq</li>\n</ul>",
+                team.gwp_kgco2eq(),
+                team.gwp_manufacture_kgco2eq,
+                team.gwp_use_kgco2eq,
+                team.trend(),
+                team.pe_megajoules,
+                team.adp_kgsbeq
+            );
+            if !team.top_resources.is_empty() {
+                html.push_str("<h3>Top resources</h3>\n<table>\n<tr><th>Resource</th><th>Kind</th><th>GWP (kgCO2eq)</th></tr>\n");
+                for resource in team.top_resources.iter() {
+                    let _ = writeln!(
+                        html,
+                        "<tr><td>{}</td><td>{}</td><td>{:.3}</td></tr>",
+                        escape_html(&resource.resource_id),
+                        resource.resource_kind,
+                        resource.gwp_kgco2eq
+                    );
+                }
+                html.push_str("</table>\n");
+            }
+            if !team.rightsizing.is_empty() {
+                html.push_str("<h3>Rightsizing suggestions</h3>\n<table>\n<tr><th>Instance</th><th>Type</th><th>Average CPU load</th><th>GWP (kgCO2eq)</th><th>Suggestion</th></tr>\n");
+                for suggestion in team.rightsizing.iter() {
+                    let _ = writeln!(
+                        html,
+                        "<tr><td>{}</td><td>{}</td><td>{:.1} %</td><td>{:.3}</td><td>{}</td></tr>",
+                        escape_html(&suggestion.resource_id),
+                        escape_html(&suggestion.instance_type),
+                        suggestion.average_cpu_load,
+                        suggestion.gwp_kgco2eq,
+                        suggestion.suggestion
+                    );
+                }
+                html.push_str("</table>\n");
+            }
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+    use crate::model::{CloudProvider, CloudResource, CloudResourceTag, InstanceUsage};
+    use crate::usage_location::UsageLocation;
+
+    fn date(month: u32, day: u32) -> DateTime<Utc> {
+        format!("2024-{:02}-{:02}T00:00:00Z", month, day)
+            .parse()
+            .unwrap()
+    }
+
+    fn instance(
+        id: &str,
+        team: &str,
+        cpu_load: f64,
+        gwp_use_kgco2eq: f64,
+    ) -> CloudResourceWithImpacts {
+        CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                id: id.to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details: ResourceDetails::Instance {
+                    instance_type: "m5.xlarge".to_string(),
+                    usage: Some(InstanceUsage {
+                        average_cpu_load: cpu_load,
+                        usage_duration_seconds: 3600,
+                        state: InstanceState::Running,
+                    }),
+                },
+                tags: vec![CloudResourceTag {
+                    key: "team".to_string(),
+                    value: Some(team.to_string()),
+                }],
+                account_id: None,
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues {
+                gwp_use_kgco2eq,
+                ..Default::default()
+            }),
+            impacts_duration_hours: 24.0,
+            cost: None,
+        }
+    }
+
+    /// A daily scan of a day, with the impacts of its resources over 24 hours
+    fn scan(
+        scan_id: i64,
+        day: DateTime<Utc>,
+        resources: Vec<CloudResourceWithImpacts>,
+    ) -> (CoveredScan, EstimatedInventory) {
+        (
+            CoveredScan {
+                scan_id,
+                timestamp: day,
+                duration_of_use_hours: 24.0,
+                covered_from: day,
+                covered_until: day + chrono::Duration::days(1),
+            },
+            EstimatedInventory {
+                impacting_resources: resources,
+                execution_statistics: None,
+                errors: Vec::new(),
+            },
+        )
+    }
+
+    #[test]
+    fn the_last_complete_period_is_reported() {
+        assert_eq!(
+            (date(4, 1), date(5, 1), date(6, 1)),
+            report_periods(
+                AggregationPeriod::Monthly,
+                "2024-06-15T10:00:00Z".parse().unwrap()
+            )
+        );
+        assert_eq!(
+            (date(5, 20), date(5, 27), date(6, 3)),
+            report_periods(AggregationPeriod::Weekly, date(6, 3))
+        );
+    }
+
+    #[test]
+    fn teams_are_compared_to_the_previous_period() {
+        let scans = vec![
+            scan(1, date(5, 6), vec![instance("i-web", "web", 50.0, 2.0)]),
+            scan(
+                2,
+                date(5, 13),
+                vec![
+                    instance("i-web", "web", 50.0, 2.0),
+                    instance("i-idle", "web", 1.0, 1.0),
+                    instance("i-data", "data", 10.0, 4.0),
+                ],
+            ),
+        ];
+        let report = build_showback_report(
+            &scans,
+            "team",
+            &AllocationRules::default(),
+            AggregationPeriod::Weekly,
+            date(5, 20),
+        );
+        assert_eq!(date(5, 13), report.period_start);
+        assert_eq!(24.0, report.covered_hours);
+        let names: Vec<&str> = report.teams.iter().map(|team| team.name()).collect();
+        assert_eq!(vec!["data", "web"], names);
+
+        let web = &report.teams[1];
+        assert_eq!(3.0, web.gwp_kgco2eq());
+        assert_eq!(2.0, web.previous_gwp_kgco2eq);
+        assert_eq!(Some(50.0), web.gwp_change_percent);
+        assert_eq!("i-web", web.top_resources[0].resource_id);
+        assert_eq!(1, web.rightsizing.len());
+        assert!(web.rightsizing[0].suggestion.starts_with("Idle"));
+
+        let data = &report.teams[0];
+        assert_eq!(None, data.gwp_change_percent);
+        assert!(data.rightsizing[0].suggestion.starts_with("Oversized"));
+
+        let markdown = report.clone().of_team("web").unwrap().to_markdown();
+        assert!(markdown.contains("| web | 2.0 | 3.000 | 2.000 | +50.0 % |"));
+        assert!(!markdown.contains("## data"));
+        assert!(report.to_html().contains("<h2>data</h2>"));
+    }
+
+    #[test]
+    fn unknown_teams_are_rejected() {
+        let scans = vec![scan(
+            1,
+            date(5, 13),
+            vec![instance("i-web", "web", 50.0, 2.0)],
+        )];
+        let report = build_showback_report(
+            &scans,
+            "team",
+            &AllocationRules::default(),
+            AggregationPeriod::Weekly,
+            date(5, 20),
+        );
+        assert!(report.of_team("ops").is_err());
+    }
+}
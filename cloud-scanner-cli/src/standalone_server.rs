@@ -1,29 +1,300 @@
 //! An HTTP endpoint that exposes the results of cloud-scanner like inventory, impacts or metricc.
+//!
+//! Rocket passes each query parameter and each managed state of a route as an argument of its handler, hence `clippy::too_many_arguments` being allowed on the larger ones.
 
+use crate::access_log::{AccessLog, RequestId};
+use crate::aws_cloud_provider::{AwsAccount, ResourceSelection};
+use crate::compression::{Compression, CompressionConfig};
+use crate::cors::{Cors, CorsConfig};
+use crate::graceful_shutdown::{DrainScans, InFlightScans};
+use crate::grpc_server::GrpcService;
+use crate::health::{self, HealthReport};
+use crate::ignore_rules::IgnoreRules;
+use crate::metric_exporter::MetricOptions;
 use crate::model::{EstimatedInventory, Inventory};
+use crate::rate_limit::{RateLimitConfig, RateLimited, RateLimiter};
+use crate::response_cache::{cache_key, ResponseCache};
+use crate::response_page::{paginate, PageRequest};
+use crate::response_stream::{chunks, ndjson, SharedBuffer};
+use crate::result_envelope::{ScanParameters, VersionedResults};
+use crate::result_store::ResultStore;
+use crate::result_stream::{ResultStream, StreamFormat};
+use crate::scan_jobs::{Job, JobResult, JobStatus, JobStore, ScanRequest};
+use crate::scheduler::{LatestScans, ScheduledScan};
+use crate::server_auth::{AuthConfig, Authenticated, Authenticator};
+use crate::server_telemetry::RequestMetrics;
+use crate::tenants::TenantConfig;
+use anyhow::Context;
+use rocket::config::TlsConfig;
+use rocket::futures::stream::{self, BoxStream, StreamExt};
+use rocket::http::{ContentType, Status};
+use rocket::response::status;
+use rocket::response::stream::{ByteStream, Event, EventStream};
 use rocket::State;
-use rocket::{get, serde::json::Json};
+use rocket::{get, post, serde::json::Json};
+use rocket_okapi::settings::UrlObject;
 use rocket_okapi::{openapi, openapi_get_routes, swagger_ui::*};
+use serde::Deserialize;
+use std::io::Write;
 
 ///  Configuration for the metric server
 pub struct Config {
     pub boavizta_url: String,
+    /// Address the server listens on (Rocket default when none)
+    pub address: Option<std::net::IpAddr>,
+    /// Port of the server (Rocket default when none)
+    pub port: Option<u16>,
+    /// Authentication of the requests that trigger scans
+    pub auth: AuthConfig,
+    /// Serve HTTPS instead of HTTP
+    pub tls: Option<TlsFiles>,
+    /// Scans run periodically, whose latest results are served by /metrics
+    pub scheduled_scans: Vec<ScheduledScan>,
+    /// Result store where the scheduled scans are saved
+    pub store: Option<String>,
+    /// Duration during which responses of /metrics, /inventory and /impacts are reused for identical requests (zero to disable)
+    pub cache_ttl: std::time::Duration,
+    /// Accounts that requests can scan (by account id or role ARN), by assuming their role
+    pub accounts: Vec<AwsAccount>,
+    /// Limits the rate of the requests that trigger scans, per client
+    pub rate_limit: Option<RateLimitConfig>,
+    /// CORS policy, cross-origin requests are not allowed when none is configured
+    pub cors: Option<CorsConfig>,
+    /// Port of the gRPC API, on the address of the REST API (not served when none)
+    pub grpc_port: Option<u16>,
+    /// Secret used to sign the callbacks of scan jobs
+    pub callback_secret: Option<String>,
+    /// Internal hosts that callbacks of scan jobs can be posted to (see [crate::job_callbacks])
+    pub callback_allowed_hosts: Vec<String>,
+    /// Tenants sharing the server, authenticated by their API keys
+    pub tenants: Vec<TenantConfig>,
+    /// Serve the GraphQL endpoint on /graphql
+    pub graphql: bool,
+    /// Number of worker threads of the server (Rocket default when none)
+    pub workers: Option<usize>,
+    /// Duration after which the scans of requests fail with 504 (no timeout when none)
+    pub request_timeout: Option<std::time::Duration>,
+    /// Duration during which requests and scans in flight can finish when the server stops (Rocket default when none)
+    pub shutdown_grace: Option<std::time::Duration>,
+    /// Compression of the responses
+    pub compression: CompressionConfig,
+    /// Print an access log line per request
+    pub access_log: bool,
+    /// Namespace, constant labels and tags as labels of the metrics
+    pub metric_options: MetricOptions,
+}
+
+/// Returns the account selected by a request, or 403 when it is not allowed
+///
+/// Requests of a tenant always scan the account of the tenant, in the regions allowed to the tenant.
+pub(crate) fn selected_account<'a>(
+    config: &'a Config,
+    tenant: Option<&'a TenantConfig>,
+    aws_regions: &[&str],
+    account_id: Option<&str>,
+    role_arn: Option<&str>,
+) -> Result<Option<&'a AwsAccount>, status::Forbidden<String>> {
+    crate::tenants::select_account(tenant, &config.accounts, aws_regions, account_id, role_arn)
+        .map_err(|e| {
+            warn!("Rejecting request: {:#}", e);
+            status::Forbidden(e.to_string())
+        })
+}
+
+/// Fails with 400 when a tag filter of a request is not a valid expression
+pub(crate) fn validate_filter_tags(filter_tags: &[String]) -> Result<(), status::Custom<String>> {
+    crate::tag_filter::TagFilter::parse_all(filter_tags)
+        .map(|_| ())
+        .map_err(|e| status::Custom(Status::BadRequest, format!("{:#}", e)))
+}
+
+/// Certificate chain and private key (PEM files) used to serve HTTPS
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TlsFiles {
+    /// Certificate chain, like the `fullchain.pem` of Let's Encrypt
+    pub certs: String,
+    /// Private key (PKCS#8, PKCS#1 or SEC1)
+    pub key: String,
+}
+
+/// Runs the scan of a request (with its request id in the logs), or fails with 504 when it lasts longer than the request timeout of the server
+pub(crate) async fn within_request_timeout<T>(
+    config: &Config,
+    request_id: &RequestId,
+    scan: impl std::future::Future<Output = T>,
+) -> Result<T, status::Custom<String>> {
+    let scan = request_id.scope(scan);
+    let Some(timeout) = config.request_timeout else {
+        return Ok(scan.await);
+    };
+    rocket::tokio::time::timeout(timeout, scan)
+        .await
+        .map_err(|_| {
+            warn!("Scan of request timed out after {:?}", timeout);
+            status::Custom(
+                Status::GatewayTimeout,
+                format!(
+                    "Scan did not complete within {} seconds, submit it as a job with POST /scan",
+                    timeout.as_secs()
+                ),
+            )
+        })
+}
+
+/// Returns the Rocket configuration (from the `ROCKET_*` environment variables and `Rocket.toml`), with the settings of the configuration (address, port, workers, grace period and TLS) when they are set
+fn rocket_figment(config: &Config) -> anyhow::Result<rocket::figment::Figment> {
+    let mut figment = rocket::Config::figment();
+    if let Some(address) = config.address {
+        figment = figment.merge(("address", address));
+    }
+    if let Some(port) = config.port {
+        figment = figment.merge(("port", port));
+    }
+    if let Some(workers) = config.workers {
+        figment = figment.merge(("workers", workers));
+    }
+    if let Some(grace) = config.shutdown_grace {
+        figment = figment.merge(("shutdown.grace", grace.as_secs()));
+    }
+    match config.tls.as_ref() {
+        Some(tls) => {
+            for path in [&tls.certs, &tls.key] {
+                std::fs::metadata(path)
+                    .with_context(|| format!("Cannot read TLS file {}", path))?;
+            }
+            info!("Serving HTTPS with certificate {}", tls.certs);
+            Ok(figment.merge(("tls", TlsConfig::from_paths(&tls.certs, &tls.key))))
+        }
+        None => Ok(figment),
+    }
 }
 
 /// Start the server
-pub async fn run(config: Config) -> Result<(), rocket::Error> {
-    let _rocket = rocket::build()
-        .mount("/", openapi_get_routes![index, metrics, inventory, impacts])
+pub async fn run(config: Config) -> anyhow::Result<()> {
+    let authenticator = Authenticator::new(&config.auth, &config.tenants).await?;
+    if !config.tenants.is_empty() {
+        info!("Serving {} tenants", config.tenants.len());
+    }
+    if !authenticator.is_enabled() {
+        warn!("API authentication is disabled: anyone reaching the server can trigger scans with its cloud credentials");
+    }
+    let figment = rocket_figment(&config)?;
+    let cache_ttl = config.cache_ttl;
+    if !cache_ttl.is_zero() {
+        info!("Caching responses for {:?}", cache_ttl);
+    }
+    let rate_limiter = config.rate_limit.as_ref().map(RateLimiter::new);
+    if let Some(rate_limit) = &config.rate_limit {
+        info!(
+            "Limiting clients to {} requests per minute",
+            rate_limit.requests_per_minute
+        );
+    }
+    let cors = config.cors.clone();
+    let jobs = JobStore::default();
+    let in_flight = InFlightScans::default();
+    if let Some(port) = config.grpc_port {
+        let rocket_config: rocket::Config =
+            figment.extract().context("Invalid Rocket configuration")?;
+        let address = std::net::SocketAddr::new(rocket_config.address, port);
+        let service = GrpcService::new(
+            config.boavizta_url.clone(),
+            config.accounts.clone(),
+            authenticator.clone(),
+            jobs.clone(),
+            in_flight.clone(),
+        );
+        rocket::tokio::spawn(async move {
+            if let Err(e) = crate::grpc_server::serve(service, address).await {
+                error!("gRPC API stopped: {:#}", e);
+            }
+        });
+    }
+    let impacts_cache = ResponseCache::<EstimatedInventory>::new(cache_ttl);
+    let graphql_schema = config.graphql.then(|| {
+        info!("Serving GraphQL on /graphql");
+        crate::graphql_api::schema(
+            config.boavizta_url.clone(),
+            config.accounts.clone(),
+            impacts_cache.clone(),
+        )
+    });
+    let latest_scans = LatestScans::default();
+    crate::scheduler::spawn_scheduled_scans(
+        config.scheduled_scans.clone(),
+        config.boavizta_url.clone(),
+        config.store.clone(),
+        latest_scans.clone(),
+        in_flight.clone(),
+    );
+    let mut rocket = rocket::custom(figment)
+        .attach(AccessLog::new(config.access_log))
+        .attach(RequestMetrics)
+        .attach(DrainScans::new(in_flight.clone()));
+    if let Some(cors) = cors {
+        info!(
+            "Allowing cross-origin requests from {:?}",
+            cors.allowed_origins
+        );
+        rocket = rocket.attach(Cors::new(cors));
+    }
+    if config.compression.enabled {
+        rocket = rocket.attach(Compression::new(config.compression.clone()));
+    }
+    rocket = rocket
+        .mount(
+            "/",
+            openapi_get_routes![
+                index,
+                healthz,
+                readyz,
+                internal_metrics,
+                metrics,
+                inventory,
+                inventory_metrics,
+                inventory_stream,
+                impacts,
+                impacts_stream,
+                estimate,
+                estimate_stream,
+                submit_scan,
+                job,
+                job_events,
+                job_result,
+                scans,
+                scan
+            ],
+        )
+        .mount("/v2", crate::api_v2::routes())
+        .mount("/dashboard", crate::web_dashboard::routes())
         .mount(
             "/swagger-ui/",
             make_swagger_ui(&SwaggerUIConfig {
                 url: "../openapi.json".to_owned(),
+                urls: vec![
+                    UrlObject::new("v1", "../openapi.json"),
+                    UrlObject::new("v2", "../v2/openapi.json"),
+                ],
                 ..Default::default()
             }),
         )
         .manage(config)
-        .launch()
-        .await?;
+        .register("/", rocket::catchers![crate::rate_limit::too_many_requests])
+        .manage(authenticator)
+        .manage(rate_limiter)
+        .manage(jobs)
+        .manage(in_flight)
+        .manage(latest_scans)
+        .manage(ResponseCache::<String>::new(cache_ttl))
+        .manage(ResponseCache::<Inventory>::new(cache_ttl))
+        .manage(impacts_cache);
+    if let Some(schema) = graphql_schema {
+        rocket = rocket
+            .mount("/", crate::graphql_api::routes())
+            .manage(schema);
+    }
+    let _rocket = rocket.launch().await?;
     Ok(())
 }
 
@@ -33,7 +304,43 @@ pub async fn run(config: Config) -> Result<(), rocket::Error> {
 fn index(config: &State<Config>) -> String {
     warn!("Getting request on /");
     let version: String = crate::get_version();
-    format!("Cloud scanner metric server  {} is running.\n\nUsing Boavizta API at: {}.\nMetrics are exposed on /metrics path and require passing a **region** in query string.\n e.g.  http://localhost:8000/metrics?aws_region=eu-west-3 \n See also /swagger-ui .", version, config.boavizta_url)
+    format!("Cloud scanner metric server  {} is running.\n\nUsing Boavizta API at: {}.\nMetrics are exposed on /metrics path and require passing a **region** in query string.\n Long scans can be submitted as jobs with POST /scan.\n When API keys or OIDC are configured, requests must pass a bearer token.\n e.g.  http://localhost:8000/metrics?aws_region=eu-west-3 \n See also /swagger-ui and the dashboard on /dashboard .", version, config.boavizta_url)
+}
+
+/// # Returns the liveness of the server.
+///
+/// Always 200 while the server answers (dependencies are not checked), for liveness probes. Does not require authentication.
+#[openapi(tag = "health")]
+#[get("/healthz")]
+fn healthz() -> Json<HealthReport> {
+    Json(health::liveness())
+}
+
+/// # Returns the readiness of the server.
+///
+/// Checks the cloud credentials of the server and the Boavizta API: 200 when both are usable, 503 otherwise (with the status of each check), for readiness probes and uptime monitoring. Does not require authentication.
+#[openapi(tag = "health")]
+#[get("/readyz")]
+async fn readyz(config: &State<Config>) -> status::Custom<Json<HealthReport>> {
+    let report = health::readiness(&config.boavizta_url).await;
+    let status = if report.is_ok() {
+        Status::Ok
+    } else {
+        Status::ServiceUnavailable
+    };
+    status::Custom(status, Json(report))
+}
+
+/// # Returns the operational metrics of the server.
+///
+/// Prometheus metrics of the server itself (requests, durations of scans, latencies of the Boavizta API, cache hits and errors), distinct from the metrics of impacts of /metrics.
+///
+/// Requires authentication like the other routes when it is enabled, as their labels reveal the accounts and resources that were scanned.
+#[openapi(tag = "health")]
+#[get("/internal/metrics")]
+fn internal_metrics(_auth: Authenticated) -> Result<String, status::Custom<String>> {
+    crate::server_telemetry::encode_metrics()
+        .map_err(|e| status::Custom(Status::InternalServerError, e.to_string()))
 }
 
 /// # Returns Prometheus metrics.
@@ -42,89 +349,1116 @@ fn index(config: &State<Config>) -> String {
 ///
 /// Results are estimated for one hour of use by default.
 ///
+/// When a scheduled scan of the region is configured (with the same parameters, if provided), the metrics of its latest completed scan are returned without scanning.
+///
+/// Another account of the allow-list can be scanned by passing its account_id or role_arn (403 when it is not allowed).
+///
+/// The impacts of each resource (labelled with its id, kind, instance type and region) are added with resource_impacts=true, the histograms of the impacts of resources with histograms=true. With exemplars=true, each bucket of the histograms carries an OpenMetrics exemplar: the id of the resource with the highest impacts of the bucket.
+///
+/// With verbose_output=true, the impacts are queried with the details of Boavizta API, which adds the carbon intensity of the electricity of the region used to estimate them.
+///
 /// Example query: http://localhost:8000/metrics?aws_region=eu-west-3&filter_tag=Name=boatest&filter_tag=OtherTag=other-value&use_duration_hours=1.0&include_storage=true
 #[openapi(tag = "metrics")]
-#[get("/metrics?<aws_region>&<filter_tags>&<use_duration_hours>&<include_block_storage>")]
+#[get(
+    "/metrics?<aws_region>&<filter_tags>&<use_duration_hours>&<include_block_storage>&<account_id>&<role_arn>&<resource_impacts>&<histograms>&<exemplars>&<verbose_output>"
+)]
+#[allow(clippy::too_many_arguments)]
 async fn metrics(
+    auth: Authenticated,
+    _rate: RateLimited,
+    request_id: RequestId,
     config: &State<Config>,
+    latest_scans: &State<LatestScans>,
+    cache: &State<ResponseCache<String>>,
     aws_region: &str,
     filter_tags: Option<Vec<String>>,
     use_duration_hours: Option<f32>,
     include_block_storage: Option<bool>,
-) -> String {
+    account_id: Option<&str>,
+    role_arn: Option<&str>,
+    resource_impacts: Option<bool>,
+    histograms: Option<bool>,
+    exemplars: Option<bool>,
+    verbose_output: Option<bool>,
+) -> Result<String, status::Custom<String>> {
     warn!("Getting something on /metrics");
+    let verbose_output = verbose_output.unwrap_or(false);
+    let options = MetricOptions {
+        resource_impacts: resource_impacts.unwrap_or(false),
+        histograms: histograms.unwrap_or(false),
+        exemplars: exemplars.unwrap_or(false),
+        ..config.metric_options.clone()
+    };
+    let account = selected_account(
+        config,
+        auth.tenant.as_ref(),
+        &[aws_region],
+        account_id,
+        role_arn,
+    )
+    .map_err(|status::Forbidden(message)| status::Custom(Status::Forbidden, message))?;
+    let requested_tags = filter_tags.as_deref().filter(|tags| !tags.is_empty());
+    // Scheduled scans use the credentials of the server, and are not shared with tenants (nor verbose)
+    let latest = match (account, &auth.tenant) {
+        (None, None) if !verbose_output => latest_scans.matching(
+            aws_region,
+            requested_tags,
+            use_duration_hours,
+            include_block_storage,
+        ),
+        _ => None,
+    };
+    if let Some(latest) = latest {
+        info!(
+            "Returning metrics of the scheduled scan completed at {}",
+            latest.completed_at
+        );
+        return Ok(crate::impacts_to_metrics_of_tenant(
+            &latest.estimated_inventory,
+            &latest.summary,
+            None,
+            &MetricOptions {
+                scan_info: Some(latest.metadata),
+                ..options
+            },
+        )
+        .unwrap());
+    }
     let hours_use_time = use_duration_hours.unwrap_or(1.0);
+    let filter_tags = filter_tags.unwrap_or_default();
+    validate_filter_tags(&filter_tags)?;
+    let include_block_storage = include_block_storage.unwrap_or(false);
+    // Metrics are labelled with the tenant
+    let key = format!(
+        "{}|{}|{}|{}|{}",
+        auth.tenant_name().unwrap_or_default(),
+        options.resource_impacts,
+        options.histograms,
+        options.exemplars,
+        cache_key(
+            account,
+            aws_region,
+            &filter_tags,
+            Some(hours_use_time),
+            verbose_output,
+            include_block_storage,
+        )
+    );
+    if let Some(metrics) = cache.get(&key) {
+        info!("Returning cached metrics");
+        return Ok(metrics);
+    }
     warn!("Filtering on tags {:?}", filter_tags);
-    let metrics = crate::get_impacts_as_metrics(
-        &hours_use_time,
-        &filter_tags.unwrap_or_default(),
-        aws_region,
-        &config.boavizta_url,
-        include_block_storage.unwrap_or(false),
+    let scan_timestamp = chrono::Utc::now();
+    let estimated_inventory = within_request_timeout(
+        config,
+        &request_id,
+        crate::estimate_impacts_in_account(
+            account,
+            &hours_use_time,
+            &filter_tags,
+            aws_region,
+            &config.boavizta_url,
+            verbose_output,
+            include_block_storage,
+        ),
+    )
+    .await?
+    .unwrap();
+    let summary = crate::build_summary(&estimated_inventory, aws_region, &hours_use_time).unwrap();
+    let parameters = ScanParameters {
+        aws_region: aws_region.to_string(),
+        use_duration_hours: Some(hours_use_time),
+        filter_tags: filter_tags.clone(),
+        include_block_storage,
+        include_states: Vec::new(),
+        verbose: verbose_output,
+    };
+    let metadata =
+        crate::scan_metadata(scan_timestamp, parameters, Some(&config.boavizta_url)).await;
+    let metrics = crate::impacts_to_metrics_of_tenant(
+        &estimated_inventory,
+        &summary,
+        auth.tenant_name(),
+        &MetricOptions {
+            scan_info: Some(metadata),
+            ..options
+        },
     )
-    .await;
-    metrics.unwrap()
+    .unwrap();
+    cache.insert(key, metrics.clone());
+    Ok(metrics)
 }
 
 /// # Returns the inventory as json.
 ///
 /// Region is mandatory. Filter_tags (if any) should be written as string containing tag_name=tag_value
 ///
+/// Resources can be paginated (page starts at 1, per_page defaults to 100) and restricted to some fields (like fields=id,location).
+///
 /// Example query: http://localhost:8000/inventorynew?aws_region=eu-west-3&filter_tag=Name=boatest&filter_tag=OtherTag=other-value
 #[openapi(tag = "inventory")]
-#[get("/inventory?<aws_region>&<filter_tags>&<include_block_storage>")]
+#[get(
+    "/inventory?<aws_region>&<filter_tags>&<include_block_storage>&<account_id>&<role_arn>&<page>&<per_page>&<fields>"
+)]
+#[allow(clippy::too_many_arguments)]
 async fn inventory(
-    _config: &State<Config>,
+    auth: Authenticated,
+    _rate: RateLimited,
+    request_id: RequestId,
+    config: &State<Config>,
+    cache: &State<ResponseCache<Inventory>>,
     aws_region: &str,
     filter_tags: Option<Vec<String>>,
     include_block_storage: Option<bool>,
-) -> Json<Inventory> {
+    account_id: Option<&str>,
+    role_arn: Option<&str>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+    fields: Option<Vec<String>>,
+) -> Result<Json<serde_json::Value>, status::Custom<String>> {
     warn!("Getting something on /inventory");
-    warn!("Filtering on tags {:?}", filter_tags);
-    Json(
-        crate::get_inventory(
-            &filter_tags.unwrap_or_default(),
-            aws_region,
-            include_block_storage.unwrap_or(false),
-        )
-        .await
-        .unwrap(),
+    let account = selected_account(
+        config,
+        auth.tenant.as_ref(),
+        &[aws_region],
+        account_id,
+        role_arn,
     )
+    .map_err(|status::Forbidden(message)| status::Custom(Status::Forbidden, message))?;
+    let page = PageRequest::new(page, per_page, fields);
+    let filter_tags = filter_tags.unwrap_or_default();
+    validate_filter_tags(&filter_tags)?;
+    let include_block_storage = include_block_storage.unwrap_or(false);
+    let key = cache_key(
+        account,
+        aws_region,
+        &filter_tags,
+        None,
+        false,
+        include_block_storage,
+    );
+    let inventory = match cache.get(&key) {
+        Some(inventory) => {
+            info!("Returning cached inventory");
+            inventory
+        }
+        None => {
+            warn!("Filtering on tags {:?}", filter_tags);
+            let inventory = within_request_timeout(
+                config,
+                &request_id,
+                crate::get_inventory_in_account(
+                    account,
+                    &filter_tags,
+                    aws_region,
+                    include_block_storage,
+                ),
+            )
+            .await?
+            .unwrap();
+            cache.insert(key, inventory.clone());
+            inventory
+        }
+    };
+    Ok(Json(paginate(&inventory, "resources", &page).unwrap()))
+}
+
+/// A streamed NDJSON response
+type NdjsonStream = (ContentType, ByteStream<BoxStream<'static, Vec<u8>>>);
+
+/// # Streams the inventory as NDJSON.
+///
+/// Like /inventory, without pagination: the metadata is sent first (`{"metadata":...}`), then each resource on its own line, written as the client reads them instead of being buffered. Cached inventories of /inventory are streamed.
+///
+/// Example query: curl -N "http://localhost:8000/inventory/stream?aws_region=eu-west-3"
+#[openapi(tag = "inventory")]
+#[get(
+    "/inventory/stream?<aws_region>&<filter_tags>&<include_block_storage>&<account_id>&<role_arn>"
+)]
+#[allow(clippy::too_many_arguments)]
+async fn inventory_stream(
+    auth: Authenticated,
+    _rate: RateLimited,
+    request_id: RequestId,
+    config: &State<Config>,
+    cache: &State<ResponseCache<Inventory>>,
+    aws_region: &str,
+    filter_tags: Option<Vec<String>>,
+    include_block_storage: Option<bool>,
+    account_id: Option<&str>,
+    role_arn: Option<&str>,
+) -> Result<NdjsonStream, status::Custom<String>> {
+    let account = selected_account(
+        config,
+        auth.tenant.as_ref(),
+        &[aws_region],
+        account_id,
+        role_arn,
+    )
+    .map_err(|status::Forbidden(message)| status::Custom(Status::Forbidden, message))?;
+    let filter_tags = filter_tags.unwrap_or_default();
+    validate_filter_tags(&filter_tags)?;
+    let include_block_storage = include_block_storage.unwrap_or(false);
+    let key = cache_key(
+        account,
+        aws_region,
+        &filter_tags,
+        None,
+        false,
+        include_block_storage,
+    );
+    let inventory = match cache.get(&key) {
+        Some(inventory) => inventory,
+        None => {
+            let inventory = within_request_timeout(
+                config,
+                &request_id,
+                crate::get_inventory_in_account(
+                    account,
+                    &filter_tags,
+                    aws_region,
+                    include_block_storage,
+                ),
+            )
+            .await?
+            .map_err(|e| status::Custom(Status::InternalServerError, format!("{:#}", e)))?;
+            cache.insert(key, inventory.clone());
+            inventory
+        }
+    };
+    let parameters = ScanParameters {
+        aws_region: aws_region.to_string(),
+        use_duration_hours: None,
+        filter_tags,
+        include_block_storage,
+        include_states: Vec::new(),
+        verbose: false,
+    };
+    let buffer = SharedBuffer::default();
+    let producer = {
+        let mut buffer = buffer.clone();
+        async move {
+            let metadata = crate::scan_metadata(chrono::Utc::now(), parameters, None).await;
+            writeln!(buffer, "{}", serde_json::json!({ "metadata": metadata }))?;
+            crate::response_stream::write_lines(buffer, &inventory.resources).await
+        }
+    };
+    Ok((ndjson(), ByteStream::from(chunks(buffer, producer).boxed())))
+}
+
+/// # Returns the number of resources of the inventory as Prometheus metrics.
+///
+/// Counts the resources by kind, type and state, without estimating their impacts: cheap enough to be scraped more often than /metrics. Region is mandatory. Filter_tags (if any) should be written as string containing tag_name=tag_value
+///
+/// Another account of the allow-list can be scanned by passing its account_id or role_arn (403 when it is not allowed).
+///
+/// Example query: http://localhost:8000/inventory_metrics?aws_region=eu-west-3&filter_tag=Name=boatest
+#[openapi(tag = "metrics")]
+#[get(
+    "/inventory_metrics?<aws_region>&<filter_tags>&<include_block_storage>&<account_id>&<role_arn>"
+)]
+#[allow(clippy::too_many_arguments)]
+async fn inventory_metrics(
+    auth: Authenticated,
+    _rate: RateLimited,
+    request_id: RequestId,
+    config: &State<Config>,
+    cache: &State<ResponseCache<Inventory>>,
+    aws_region: &str,
+    filter_tags: Option<Vec<String>>,
+    include_block_storage: Option<bool>,
+    account_id: Option<&str>,
+    role_arn: Option<&str>,
+) -> Result<String, status::Custom<String>> {
+    warn!("Getting something on /inventory_metrics");
+    let account = selected_account(
+        config,
+        auth.tenant.as_ref(),
+        &[aws_region],
+        account_id,
+        role_arn,
+    )
+    .map_err(|status::Forbidden(message)| status::Custom(Status::Forbidden, message))?;
+    let filter_tags = filter_tags.unwrap_or_default();
+    validate_filter_tags(&filter_tags)?;
+    let include_block_storage = include_block_storage.unwrap_or(false);
+    // Shares the cache of /inventory
+    let key = cache_key(
+        account,
+        aws_region,
+        &filter_tags,
+        None,
+        false,
+        include_block_storage,
+    );
+    let inventory = match cache.get(&key) {
+        Some(inventory) => {
+            info!("Returning metrics of the cached inventory");
+            inventory
+        }
+        None => {
+            let inventory = within_request_timeout(
+                config,
+                &request_id,
+                crate::get_inventory_in_account(
+                    account,
+                    &filter_tags,
+                    aws_region,
+                    include_block_storage,
+                ),
+            )
+            .await?
+            .unwrap();
+            cache.insert(key, inventory.clone());
+            inventory
+        }
+    };
+    let parameters = ScanParameters {
+        aws_region: aws_region.to_string(),
+        use_duration_hours: None,
+        filter_tags,
+        include_block_storage,
+        include_states: Vec::new(),
+        verbose: false,
+    };
+    Ok(crate::inventory_to_metrics_of_tenant(
+        &inventory,
+        auth.tenant_name(),
+        &MetricOptions {
+            scan_info: Some(crate::scan_metadata(chrono::Utc::now(), parameters, None).await),
+            ..config.metric_options.clone()
+        },
+    )
+    .unwrap())
 }
 
 /// # Returns the impacts (use and embedded) as json.
 ///
 /// Region is mandatory. Filter_tags (if any) should be written as string containing tag_name=tag_value
 ///
+/// Another account of the allow-list can be scanned by passing its account_id or role_arn (403 when it is not allowed).
+///
+/// Resources can be paginated (page starts at 1, per_page defaults to 100) and restricted to some fields (like fields=cloud_resource.id,impacts_values).
+///
 /// Example query: http://localhost:8000/impacts?aws_region=eu-west-3&filter_tag=Name=boatest&filter_tag=OtherTag=other-value&use_duration_hours=1.0
 #[openapi(tag = "impacts")]
 #[get(
-    "/impacts?<aws_region>&<filter_tags>&<use_duration_hours>&<verbose_output>&<include_block_storage>"
+    "/impacts?<aws_region>&<filter_tags>&<use_duration_hours>&<verbose_output>&<include_block_storage>&<account_id>&<role_arn>&<page>&<per_page>&<fields>"
 )]
+#[allow(clippy::too_many_arguments)]
 async fn impacts(
+    auth: Authenticated,
+    _rate: RateLimited,
+    request_id: RequestId,
     _config: &State<Config>,
+    cache: &State<ResponseCache<EstimatedInventory>>,
     aws_region: &str,
     filter_tags: Option<Vec<String>>,
     use_duration_hours: Option<f32>,
     verbose_output: Option<bool>,
     include_block_storage: Option<bool>,
-) -> Json<EstimatedInventory> {
+    account_id: Option<&str>,
+    role_arn: Option<&str>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+    fields: Option<Vec<String>>,
+) -> Result<Json<serde_json::Value>, status::Custom<String>> {
+    let account = selected_account(
+        _config,
+        auth.tenant.as_ref(),
+        &[aws_region],
+        account_id,
+        role_arn,
+    )
+    .map_err(|status::Forbidden(message)| status::Custom(Status::Forbidden, message))?;
+    let page = PageRequest::new(page, per_page, fields);
     let hours_use_time = use_duration_hours.unwrap_or(1.0);
+    let filter_tags = filter_tags.unwrap_or_default();
+    validate_filter_tags(&filter_tags)?;
+    let verbose_output = verbose_output.unwrap_or(false);
+    let include_block_storage = include_block_storage.unwrap_or(false);
+    let key = cache_key(
+        account,
+        aws_region,
+        &filter_tags,
+        Some(hours_use_time),
+        verbose_output,
+        include_block_storage,
+    );
+    if let Some(estimated_inventory) = cache.get(&key) {
+        info!("Returning cached impacts");
+        return Ok(Json(
+            paginate(&estimated_inventory, "impactingResources", &page).unwrap(),
+        ));
+    }
     //let hours_use_time: f32 = 1.0;
     warn!(
         "Requesting /impacts for a duration of {} hours",
         hours_use_time
     );
     warn!("Filtering on tags {:?}", filter_tags);
-    let res = crate::estimate_impacts(
-        &hours_use_time,
-        &filter_tags.unwrap_or_default(),
-        aws_region,
-        &_config.boavizta_url,
-        verbose_output.unwrap_or(false),
-        include_block_storage.unwrap_or(false),
+    let res = within_request_timeout(
+        _config,
+        &request_id,
+        crate::estimate_impacts_in_account(
+            account,
+            &hours_use_time,
+            &filter_tags,
+            aws_region,
+            &_config.boavizta_url,
+            verbose_output,
+            include_block_storage,
+        ),
     )
-    .await
+    .await?
     .unwrap();
-    Json(res)
+    // Partial results are not cached, the next request scans again
+    if res.errors.is_empty() {
+        cache.insert(key, res.clone());
+    }
+    Ok(Json(paginate(&res, "impactingResources", &page).unwrap()))
+}
+
+/// # Streams the impacts (use and embedded) as NDJSON.
+///
+/// Like /impacts, without pagination: the metadata is sent first (`{"metadata":...}`), then each resource with its impacts as soon as its page of the inventory is estimated, then the summary (`{"summary":...}`). The scan waits for slow clients instead of buffering its results. Cached results of /impacts are streamed, streamed scans are not cached.
+///
+/// A scan failing after the response started ends it with an `{"error":...}` line.
+///
+/// Example query: curl -N "http://localhost:8000/impacts/stream?aws_region=eu-west-3&use_duration_hours=730"
+#[openapi(tag = "impacts")]
+#[get(
+    "/impacts/stream?<aws_region>&<filter_tags>&<use_duration_hours>&<verbose_output>&<include_block_storage>&<account_id>&<role_arn>"
+)]
+#[allow(clippy::too_many_arguments)]
+async fn impacts_stream(
+    auth: Authenticated,
+    _rate: RateLimited,
+    config: &State<Config>,
+    cache: &State<ResponseCache<EstimatedInventory>>,
+    aws_region: &str,
+    filter_tags: Option<Vec<String>>,
+    use_duration_hours: Option<f32>,
+    verbose_output: Option<bool>,
+    include_block_storage: Option<bool>,
+    account_id: Option<&str>,
+    role_arn: Option<&str>,
+) -> Result<NdjsonStream, status::Custom<String>> {
+    let account = selected_account(
+        config,
+        auth.tenant.as_ref(),
+        &[aws_region],
+        account_id,
+        role_arn,
+    )
+    .map_err(|status::Forbidden(message)| status::Custom(Status::Forbidden, message))?;
+    let hours_use_time = use_duration_hours.unwrap_or(1.0);
+    let filter_tags = filter_tags.unwrap_or_default();
+    validate_filter_tags(&filter_tags)?;
+    let verbose_output = verbose_output.unwrap_or(false);
+    let include_block_storage = include_block_storage.unwrap_or(false);
+    let key = cache_key(
+        account,
+        aws_region,
+        &filter_tags,
+        Some(hours_use_time),
+        verbose_output,
+        include_block_storage,
+    );
+    let cached = cache.get(&key);
+    let accounts: Vec<AwsAccount> = account.cloned().into_iter().collect();
+    let regions = vec![aws_region.to_string()];
+    let boavizta_url = config.boavizta_url.clone();
+    let parameters = ScanParameters {
+        aws_region: aws_region.to_string(),
+        use_duration_hours: Some(hours_use_time),
+        filter_tags: filter_tags.clone(),
+        include_block_storage,
+        include_states: Vec::new(),
+        verbose: verbose_output,
+    };
+    let buffer = SharedBuffer::default();
+    let producer = {
+        let buffer = buffer.clone();
+        async move {
+            let metadata =
+                crate::scan_metadata(chrono::Utc::now(), parameters, Some(&boavizta_url)).await;
+            let no_resources = EstimatedInventory {
+                impacting_resources: Vec::new(),
+                execution_statistics: None,
+                errors: Vec::new(),
+            };
+            let summary = crate::build_summary_of_accounts(
+                &no_resources,
+                &accounts,
+                &regions,
+                &hours_use_time,
+            )?;
+            let result_stream =
+                ResultStream::start(buffer, StreamFormat::Ndjson, &metadata, summary)?;
+            let (execution_statistics, errors) = match cached {
+                Some(estimated_inventory) => {
+                    crate::response_stream::write_resources_with_impacts(
+                        &result_stream,
+                        &estimated_inventory.impacting_resources,
+                    )
+                    .await?;
+                    (
+                        estimated_inventory.execution_statistics,
+                        estimated_inventory.errors,
+                    )
+                }
+                None => {
+                    let (execution_statistics, errors, _) =
+                        crate::stream_impacts_in_accounts_excluding(
+                            &accounts,
+                            &hours_use_time,
+                            &filter_tags,
+                            &regions,
+                            &boavizta_url,
+                            verbose_output,
+                            include_block_storage,
+                            &ResourceSelection::default(),
+                            &IgnoreRules::default(),
+                            &|page| result_stream.write_page(page),
+                            &|_| Ok(()),
+                        )
+                        .await?;
+                    (execution_statistics, errors)
+                }
+            };
+            let summary = result_stream.summary();
+            result_stream.finish(execution_statistics.as_ref(), &errors, &summary)?;
+            Ok(())
+        }
+    };
+    Ok((ndjson(), ByteStream::from(chunks(buffer, producer).boxed())))
+}
+
+/// # Estimates the impacts of an uploaded inventory.
+///
+/// The body is an inventory (as returned by /inventory, or by `cloud-scanner-cli inventory` with its metadata envelope) produced elsewhere, like in a CI pipeline: its resources are estimated without listing cloud resources, so the server needs no cloud credentials.
+///
+/// Results are estimated for one hour of use by default. Resources can be paginated and restricted to some fields, like /impacts.
+///
+/// Example query: http://localhost:8000/estimate?use_duration_hours=730
+#[openapi(tag = "impacts")]
+#[post(
+    "/estimate?<use_duration_hours>&<verbose_output>&<page>&<per_page>&<fields>",
+    data = "<inventory>"
+)]
+#[allow(clippy::too_many_arguments)]
+async fn estimate(
+    _auth: Authenticated,
+    _rate: RateLimited,
+    request_id: RequestId,
+    config: &State<Config>,
+    inventory: Json<VersionedResults<Inventory>>,
+    use_duration_hours: Option<f32>,
+    verbose_output: Option<bool>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+    fields: Option<Vec<String>>,
+) -> Result<Json<serde_json::Value>, status::Custom<String>> {
+    let page = PageRequest::new(page, per_page, fields);
+    let hours_use_time = use_duration_hours.unwrap_or(1.0);
+    let (_, inventory) = inventory.into_inner().into_parts();
+    info!(
+        "Estimating an uploaded inventory of {} resources",
+        inventory.resources.len()
+    );
+    let estimated_inventory = within_request_timeout(
+        config,
+        &request_id,
+        crate::estimate_inventory_impacts(
+            inventory,
+            &hours_use_time,
+            &config.boavizta_url,
+            verbose_output.unwrap_or(false),
+        ),
+    )
+    .await?
+    .map_err(|e| status::Custom(Status::InternalServerError, format!("{:#}", e)))?;
+    Ok(Json(
+        paginate(&estimated_inventory, "impactingResources", &page).unwrap(),
+    ))
+}
+
+/// # Streams the impacts of an uploaded inventory as NDJSON.
+///
+/// Like /estimate, without pagination: the metadata is sent first (`{"metadata":...}`), then the resources with their impacts as soon as each page of the inventory is estimated, then the summary (`{"summary":...}`). The estimation waits for slow clients instead of buffering its results.
+///
+/// Example query: curl -N -X POST -H "Content-Type: application/json" --data @inventory.json "http://localhost:8000/estimate/stream?use_duration_hours=730"
+#[openapi(tag = "impacts")]
+#[post(
+    "/estimate/stream?<use_duration_hours>&<verbose_output>",
+    data = "<inventory>"
+)]
+async fn estimate_stream(
+    _auth: Authenticated,
+    _rate: RateLimited,
+    config: &State<Config>,
+    inventory: Json<VersionedResults<Inventory>>,
+    use_duration_hours: Option<f32>,
+    verbose_output: Option<bool>,
+) -> NdjsonStream {
+    let hours_use_time = use_duration_hours.unwrap_or(1.0);
+    let verbose_output = verbose_output.unwrap_or(false);
+    let (uploaded, inventory) = inventory.into_inner().into_parts();
+    info!(
+        "Streaming the estimation of an uploaded inventory of {} resources",
+        inventory.resources.len()
+    );
+    let parameters = ScanParameters {
+        use_duration_hours: Some(hours_use_time),
+        verbose: verbose_output,
+        ..uploaded
+            .map(|metadata| metadata.parameters)
+            .unwrap_or_default()
+    };
+    let boavizta_url = config.boavizta_url.clone();
+    let buffer = SharedBuffer::default();
+    let producer = {
+        let buffer = buffer.clone();
+        async move {
+            let started = std::time::Instant::now();
+            let metadata =
+                crate::scan_metadata(chrono::Utc::now(), parameters, Some(&boavizta_url)).await;
+            let no_resources = EstimatedInventory {
+                impacting_resources: Vec::new(),
+                execution_statistics: None,
+                errors: Vec::new(),
+            };
+            let summary = crate::impact_provider::ImpactsSummary::of_scanned_regions(
+                Vec::new(),
+                &no_resources,
+                hours_use_time as f64,
+            );
+            let result_stream =
+                ResultStream::start(buffer, StreamFormat::Ndjson, &metadata, summary)?;
+            let api = crate::boavizta_api_v1::BoaviztaApiV1::new(&boavizta_url);
+            let (written, errors) = crate::scan_errors::collect(async {
+                for page in inventory
+                    .resources
+                    .chunks(crate::response_stream::RESOURCES_PER_CHUNK)
+                {
+                    let page_with_impacts = api
+                        .get_resources_with_impacts(
+                            page,
+                            &hours_use_time,
+                            verbose_output,
+                            |_, _| (),
+                        )
+                        .await;
+                    result_stream.write_page(&page_with_impacts)?;
+                }
+                anyhow::Ok(())
+            })
+            .await;
+            written?;
+            let execution_statistics = crate::model::ExecutionStatistics {
+                inventory_duration: std::time::Duration::ZERO,
+                impact_estimation_duration: started.elapsed(),
+                total_duration: started.elapsed(),
+            };
+            let summary = result_stream.summary();
+            result_stream.finish(Some(&execution_statistics), &errors, &summary)?;
+            Ok(())
+        }
+    };
+    (ndjson(), ByteStream::from(chunks(buffer, producer).boxed()))
+}
+
+/// # Submits a scan job.
+///
+/// The scan runs in the background: poll /jobs/{id} for its status and progress, then get the estimated inventory from /jobs/{id}/result.
+///
+/// Another account of the allow-list can be scanned by passing its account_id or role_arn (403 when it is not allowed).
+///
+/// When a callback_url is passed, the job (with the summary of its impacts) is posted to it when the job finishes, signed in the X-Cloud-Scanner-Signature header when the server has a callback secret (400 when the URL is invalid, not https, or resolves to an internal address that is not allowed).
+///
+/// Example body: {"aws_region": "eu-west-3", "filter_tags": ["Name=boatest"], "use_duration_hours": 1.0, "callback_url": "https://ci.example.com/hooks/scan"}
+#[openapi(tag = "jobs")]
+#[post("/scan", data = "<request>")]
+async fn submit_scan(
+    auth: Authenticated,
+    _rate: RateLimited,
+    request_id: RequestId,
+    config: &State<Config>,
+    jobs: &State<JobStore>,
+    in_flight: &State<InFlightScans>,
+    request: Json<ScanRequest>,
+) -> Result<status::Accepted<Json<Job>>, status::Custom<String>> {
+    let account = selected_account(
+        config,
+        auth.tenant.as_ref(),
+        &[&request.aws_region],
+        request.account_id.as_deref(),
+        request.role_arn.as_deref(),
+    )
+    .map_err(|status::Forbidden(message)| status::Custom(Status::Forbidden, message))?
+    .cloned();
+    validate_filter_tags(&request.filter_tags)?;
+    let callback_url = request.callback_url.clone();
+    if let Some(url) = &callback_url {
+        crate::job_callbacks::resolve_callback_url(url, &config.callback_allowed_hosts)
+            .await
+            .map_err(|e| status::Custom(Status::BadRequest, format!("{:#}", e)))?;
+    }
+    let job = jobs.submit(request.into_inner(), auth.tenant_name().map(str::to_string));
+    warn!("Submitted scan job {}", job.id);
+    let jobs = jobs.inner().clone();
+    let id = job.id.clone();
+    let api_url = config.boavizta_url.clone();
+    let callback_secret = config.callback_secret.clone();
+    let callback_allowed_hosts = config.callback_allowed_hosts.clone();
+    let scan = in_flight.start();
+    rocket::tokio::spawn(async move {
+        let _scan = scan;
+        request_id
+            .scope(async {
+                jobs.run(&id, &api_url, account.as_ref()).await;
+                if let Some(url) = callback_url {
+                    crate::job_callbacks::notify(
+                        &jobs,
+                        &id,
+                        &url,
+                        callback_secret.as_deref(),
+                        &callback_allowed_hosts,
+                    )
+                    .await;
+                }
+            })
+            .await;
+    });
+    Ok(status::Accepted(Json(job)))
+}
+
+/// # Returns the status and progress of a scan job.
+#[openapi(tag = "jobs")]
+#[get("/jobs/<id>")]
+async fn job(auth: Authenticated, jobs: &State<JobStore>, id: String) -> Option<Json<Job>> {
+    jobs.get(&id)
+        .filter(|job| job.is_visible_to(auth.tenant_name()))
+        .map(Json)
+}
+
+/// # Streams the progress of a scan job as server-sent events.
+///
+/// An event is sent with the job each time it changes (status, resources listed, N/M resources estimated). Events are named `progress`, the last one is named `completed` or `failed`.
+///
+/// Example: curl -N http://localhost:8000/jobs/{id}/events
+#[openapi(tag = "jobs")]
+#[get("/jobs/<id>/events")]
+async fn job_events(
+    auth: Authenticated,
+    jobs: &State<JobStore>,
+    id: String,
+) -> Option<EventStream<BoxStream<'static, Event>>> {
+    let changes = jobs
+        .subscribe(&id)
+        .filter(|changes| changes.borrow().is_visible_to(auth.tenant_name()))?;
+    // The state is the receiver of changes (none after the last event), and whether the current job is already sent
+    let events = stream::unfold((Some(changes), false), |(changes, sent)| async move {
+        let mut changes = changes?;
+        // Closed when the job is forgotten
+        if sent && changes.changed().await.is_err() {
+            return None;
+        }
+        let job = changes.borrow_and_update().clone();
+        Some(match job.status {
+            JobStatus::Completed => (Event::json(&job).event("completed"), (None, true)),
+            JobStatus::Failed => (Event::json(&job).event("failed"), (None, true)),
+            _ => (Event::json(&job).event("progress"), (Some(changes), true)),
+        })
+    });
+    Some(EventStream::from(events.boxed()))
+}
+
+/// # Returns the impacts estimated by a completed scan job.
+///
+/// Returns 409 (with the status of the job) when the job is not completed. Resources can be paginated and restricted to some fields, like /impacts.
+#[openapi(tag = "jobs")]
+#[get("/jobs/<id>/result?<page>&<per_page>&<fields>")]
+async fn job_result(
+    auth: Authenticated,
+    jobs: &State<JobStore>,
+    id: String,
+    page: Option<usize>,
+    per_page: Option<usize>,
+    fields: Option<Vec<String>>,
+) -> Option<Result<Json<serde_json::Value>, status::Conflict<Json<Job>>>> {
+    let page = PageRequest::new(page, per_page, fields);
+    if !jobs.get(&id)?.is_visible_to(auth.tenant_name()) {
+        return None;
+    }
+    Some(match jobs.result(&id)? {
+        JobResult::Completed(estimated_inventory) => Ok(Json(
+            paginate(&estimated_inventory, "impactingResources", &page).unwrap(),
+        )),
+        JobResult::NotAvailable(job) => Err(status::Conflict(Json(*job))),
+    })
+}
+
+/// Opens the result store of the server, fails with 404 when none is configured and 403 for tenants (scheduled scans use the credentials of the server)
+fn open_result_store(
+    config: &Config,
+    auth: &Authenticated,
+) -> Result<ResultStore, status::Custom<String>> {
+    if let Some(tenant) = auth.tenant_name() {
+        return Err(status::Custom(
+            Status::Forbidden,
+            format!("Scan history is not available to tenant {}", tenant),
+        ));
+    }
+    let Some(path) = &config.store else {
+        return Err(status::Custom(
+            Status::NotFound,
+            "No result store is configured".to_string(),
+        ));
+    };
+    ResultStore::open(path).map_err(store_error)
+}
+
+fn store_error(e: anyhow::Error) -> status::Custom<String> {
+    error!("Cannot read result store: {:#}", e);
+    status::Custom(Status::InternalServerError, format!("{:#}", e))
+}
+
+/// Parses an optional RFC 3339 date of a query, fails with 400 when invalid
+fn parse_date(
+    name: &str,
+    value: Option<&str>,
+) -> Result<Option<chrono::DateTime<chrono::Utc>>, status::Custom<String>> {
+    value
+        .map(|value| {
+            chrono::DateTime::parse_from_rfc3339(value)
+                .map(|date| date.with_timezone(&chrono::Utc))
+                .map_err(|e| {
+                    status::Custom(
+                        Status::BadRequest,
+                        format!("Invalid {} (expecting an RFC 3339 date): {}", name, e),
+                    )
+                })
+        })
+        .transpose()
+}
+
+/// # Returns the history of the scans saved in the result store.
+///
+/// Scans (with their id, timestamp and summary) are returned oldest first, optionally between two RFC 3339 dates. They can be paginated and restricted to some fields (like fields=timestamp,summary.gwp_use_kgco2eq).
+///
+/// Returns 404 when the server has no result store.
+///
+/// Example query: http://localhost:8000/scans?since=2024-05-01T00:00:00Z
+#[openapi(tag = "scans")]
+#[get("/scans?<since>&<until>&<page>&<per_page>&<fields>")]
+#[allow(clippy::too_many_arguments)]
+async fn scans(
+    auth: Authenticated,
+    _rate: RateLimited,
+    config: &State<Config>,
+    since: Option<&str>,
+    until: Option<&str>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+    fields: Option<Vec<String>>,
+) -> Result<Json<serde_json::Value>, status::Custom<String>> {
+    let since = parse_date("since", since)?;
+    let until = parse_date("until", until)?;
+    let page = PageRequest::new(page, per_page, fields);
+    let store = open_result_store(config, &auth)?;
+    let scans = store
+        .list_scans_between(since, until)
+        .map_err(store_error)?;
+    Ok(Json(
+        paginate(&serde_json::json!({ "scans": scans }), "scans", &page).unwrap(),
+    ))
+}
+
+/// # Returns a scan saved in the result store, with the impacts of its resources.
+///
+/// Resources can be paginated and restricted to some fields, like /impacts. Returns 404 when the scan does not exist or when the server has no result store.
+#[openapi(tag = "scans")]
+#[get("/scans/<id>?<page>&<per_page>&<fields>")]
+#[allow(clippy::too_many_arguments)]
+async fn scan(
+    auth: Authenticated,
+    _rate: RateLimited,
+    config: &State<Config>,
+    id: i64,
+    page: Option<usize>,
+    per_page: Option<usize>,
+    fields: Option<Vec<String>>,
+) -> Result<Json<serde_json::Value>, status::Custom<String>> {
+    let page = PageRequest::new(page, per_page, fields);
+    let store = open_result_store(config, &auth)?;
+    match store.get_scan_details(id).map_err(store_error)? {
+        Some(details) => Ok(Json(
+            paginate(&details, "impactingResources", &page).unwrap(),
+        )),
+        None => Err(status::Custom(
+            Status::NotFound,
+            format!("No scan with id {}", id),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::ContentType;
+
+    fn test_config() -> Config {
+        Config {
+            boavizta_url: "http://localhost:5000".to_string(),
+            address: None,
+            port: None,
+            auth: Default::default(),
+            tls: None,
+            scheduled_scans: Vec::new(),
+            store: None,
+            cache_ttl: std::time::Duration::ZERO,
+            accounts: Vec::new(),
+            rate_limit: None,
+            cors: None,
+            grpc_port: None,
+            callback_secret: None,
+            callback_allowed_hosts: Vec::new(),
+            tenants: Vec::new(),
+            graphql: false,
+            workers: None,
+            request_timeout: None,
+            shutdown_grace: None,
+            compression: Default::default(),
+            access_log: false,
+            metric_options: MetricOptions::default(),
+        }
+    }
+
+    #[test]
+    fn tls_files_are_added_to_rocket_configuration() {
+        let config: rocket::Config = rocket_figment(&test_config()).unwrap().extract().unwrap();
+        assert!(config.tls.is_none());
+
+        let dir = std::env::temp_dir().join(format!("cloud-scanner-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let certs = dir.join("fullchain.pem").to_string_lossy().to_string();
+        let key = dir.join("privkey.pem").to_string_lossy().to_string();
+        let tls = TlsFiles {
+            certs: certs.clone(),
+            key: key.clone(),
+        };
+        let config = Config {
+            tls: Some(tls),
+            ..test_config()
+        };
+        assert!(rocket_figment(&config).is_err());
+
+        std::fs::write(&certs, "").unwrap();
+        std::fs::write(&key, "").unwrap();
+        let config: rocket::Config = rocket_figment(&config).unwrap().extract().unwrap();
+        assert!(config.tls_enabled());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn internal_metrics_require_authentication_when_enabled() {
+        let auth = AuthConfig {
+            api_keys: vec!["secret".to_string()],
+            oidc: None,
+        };
+        let authenticator = Authenticator::new(&auth, &[]).await.unwrap();
+        let rocket = rocket::build()
+            .mount("/", openapi_get_routes![internal_metrics])
+            .manage(authenticator);
+        let client = rocket::local::asynchronous::Client::untracked(rocket)
+            .await
+            .unwrap();
+        let response = client.get("/internal/metrics").dispatch().await;
+        assert_eq!(Status::Unauthorized, response.status());
+        let response = client
+            .get("/internal/metrics")
+            .header(rocket::http::Header::new("X-API-Key", "secret"))
+            .dispatch()
+            .await;
+        assert_eq!(Status::Ok, response.status());
+    }
+
+    #[test]
+    fn uploaded_inventories_are_estimated() {
+        let rocket = rocket::build()
+            .mount("/", openapi_get_routes![estimate])
+            .manage(test_config());
+        let client = rocket::local::blocking::Client::untracked(rocket).unwrap();
+        let response = client
+            .post("/estimate?use_duration_hours=24&page=1")
+            .header(ContentType::JSON)
+            .body(r#"{"resources": [], "executionStatistics": null}"#)
+            .dispatch();
+        assert_eq!(Status::Ok, response.status());
+        let estimated: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(serde_json::json!([]), estimated["impactingResources"]);
+        assert_eq!(0, estimated["pagination"]["totalCount"]);
+
+        let response = client
+            .post("/estimate")
+            .header(ContentType::JSON)
+            .body(r#"{"metadata": {"schema_version": 1, "cloud_scanner_version": "2.0.5", "provider": "aws", "scan_timestamp": "2024-04-12T10:15:00Z", "parameters": {"aws_region": "eu-west-3", "filter_tags": [], "include_block_storage": false}}, "data": {"resources": [], "executionStatistics": null}}"#)
+            .dispatch();
+        assert_eq!(Status::Ok, response.status());
+
+        let response = client
+            .post("/estimate")
+            .header(ContentType::JSON)
+            .body(r#"{"instances": []}"#)
+            .dispatch();
+        assert_eq!(Status::UnprocessableEntity, response.status());
+    }
+
+    #[test]
+    fn uploaded_inventories_are_streamed_as_ndjson() {
+        let rocket = rocket::build()
+            .mount("/", openapi_get_routes![estimate_stream])
+            .manage(test_config());
+        let client = rocket::local::blocking::Client::untracked(rocket).unwrap();
+        let response = client
+            .post("/estimate/stream?use_duration_hours=24")
+            .header(ContentType::JSON)
+            .body(r#"{"resources": [], "executionStatistics": null}"#)
+            .dispatch();
+        assert_eq!(Status::Ok, response.status());
+        assert_eq!(Some(ndjson()), response.content_type());
+        let body = response.into_string().unwrap();
+        let lines: Vec<serde_json::Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(2, lines.len());
+        assert_eq!(
+            24.0,
+            lines[0]["metadata"]["parameters"]["use_duration_hours"]
+        );
+        assert_eq!(0, lines[1]["summary"]["number_of_resources_total"]);
+    }
+
+    #[test]
+    fn server_settings_are_added_to_rocket_configuration() {
+        let config = Config {
+            address: Some("0.0.0.0".parse().unwrap()),
+            port: Some(9000),
+            workers: Some(4),
+            shutdown_grace: Some(std::time::Duration::from_secs(60)),
+            ..test_config()
+        };
+        let config: rocket::Config = rocket_figment(&config).unwrap().extract().unwrap();
+        assert_eq!(
+            "0.0.0.0".parse::<std::net::IpAddr>().unwrap(),
+            config.address
+        );
+        assert_eq!(9000, config.port);
+        assert_eq!(4, config.workers);
+        assert_eq!(60, config.shutdown.grace);
+    }
 }
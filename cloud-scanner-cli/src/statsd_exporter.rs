@@ -0,0 +1,254 @@
+//! A module to send the summary of impacts as StatsD gauges over UDP, to a StatsD server or a Datadog agent (DogStatsD), without a Prometheus intermediary.
+//!
+//! Plain StatsD has no tags: the gauges hold the totals of the summary. With DogStatsD, there is one gauge per region of the summary, tagged with its region, country and account.
+use anyhow::{Context, Result};
+
+use crate::impact_provider::ImpactsSummary;
+
+/// Prefix of the names of the gauges
+pub const DEFAULT_PREFIX: &str = "boavizta";
+
+/// Maximum size of a datagram: the gauges are sent several per datagram, within the usual payload of an ethernet frame
+const MAX_PACKET_SIZE: usize = 1432;
+
+///  Settings of the StatsD export
+#[derive(Clone, Debug)]
+pub struct StatsdConfig {
+    /// Address of the StatsD server or of the Datadog agent (like localhost:8125)
+    pub address: String,
+    /// Prefix of the names of the gauges (like `boavizta`, for `boavizta.gwp_use_kgco2eq`)
+    pub prefix: String,
+    /// Sends DogStatsD gauges, with tags
+    pub dogstatsd: bool,
+    /// Tags added to every DogStatsD gauge (like `env:prod`)
+    pub tags: Vec<String>,
+}
+
+/// Returns the values of a (sub) summary, by name
+fn gauges(
+    number_of_resources: [usize; 3],
+    impacts: [f64; 6],
+    duration_of_use_hours: f64,
+) -> Vec<(&'static str, String)> {
+    let [total, assessed, not_assessed] = number_of_resources;
+    let [adp_manufacture, adp_use, pe_manufacture, pe_use, gwp_manufacture, gwp_use] = impacts;
+    vec![
+        ("number_of_resources_total", total.to_string()),
+        ("number_of_resources_assessed", assessed.to_string()),
+        ("number_of_resources_not_assessed", not_assessed.to_string()),
+        ("duration_of_use_hours", duration_of_use_hours.to_string()),
+        ("adp_manufacture_kgsbeq", adp_manufacture.to_string()),
+        ("adp_use_kgsbeq", adp_use.to_string()),
+        ("pe_manufacture_megajoules", pe_manufacture.to_string()),
+        ("pe_use_megajoules", pe_use.to_string()),
+        ("gwp_manufacture_kgco2eq", gwp_manufacture.to_string()),
+        ("gwp_use_kgco2eq", gwp_use.to_string()),
+    ]
+}
+
+/// Removes the characters with a meaning in the StatsD protocol from a tag
+fn sanitize_tag(tag: &str) -> String {
+    tag.replace(['|', ',', '#', '\n'], "_")
+}
+
+/// Return an ImpactsSummary as StatsD gauges (one line each, like `boavizta.gwp_use_kgco2eq:12.5|g`)
+pub fn get_summary_gauges(summary: &ImpactsSummary, config: &StatsdConfig) -> Vec<String> {
+    let gauge = |name: &str, value: &str| format!("{}.{}:{}|g", config.prefix, name, value);
+    let mut lines = Vec::new();
+    if !config.dogstatsd {
+        for (name, value) in gauges(
+            [
+                summary.number_of_resources_total,
+                summary.number_of_resources_assessed,
+                summary.number_of_resources_not_assessed,
+            ],
+            [
+                summary.adp_manufacture_kgsbeq,
+                summary.adp_use_kgsbeq,
+                summary.pe_manufacture_megajoules,
+                summary.pe_use_megajoules,
+                summary.gwp_manufacture_kgco2eq,
+                summary.gwp_use_kgco2eq,
+            ],
+            summary.duration_of_use_hours,
+        ) {
+            lines.push(gauge(name, &value));
+        }
+        if let Some(sci) = &summary.sci {
+            lines.push(gauge(
+                "sci_kgco2eq_per_unit",
+                &sci.sci_kgco2eq_per_unit.to_string(),
+            ));
+        }
+        return lines;
+    }
+
+    for region in summary.regions.iter() {
+        let mut tags: Vec<String> = config.tags.iter().map(|tag| sanitize_tag(tag)).collect();
+        tags.push(format!("awsregion:{}", sanitize_tag(&region.aws_region)));
+        tags.push(format!("country:{}", sanitize_tag(&region.country)));
+        if let Some(account_id) = &region.account_id {
+            tags.push(format!("account_id:{}", sanitize_tag(account_id)));
+        }
+        let tags = tags.join(",");
+        for (name, value) in gauges(
+            [
+                region.number_of_resources_total,
+                region.number_of_resources_assessed,
+                region.number_of_resources_not_assessed,
+            ],
+            [
+                region.adp_manufacture_kgsbeq,
+                region.adp_use_kgsbeq,
+                region.pe_manufacture_megajoules,
+                region.pe_use_megajoules,
+                region.gwp_manufacture_kgco2eq,
+                region.gwp_use_kgco2eq,
+            ],
+            summary.duration_of_use_hours,
+        ) {
+            lines.push(format!("{}|#{}", gauge(name, &value), tags));
+        }
+    }
+    // The SCI score is computed for the whole scan
+    if let Some(sci) = &summary.sci {
+        let mut line = gauge(
+            "sci_kgco2eq_per_unit",
+            &sci.sci_kgco2eq_per_unit.to_string(),
+        );
+        let mut tags: Vec<String> = config.tags.iter().map(|tag| sanitize_tag(tag)).collect();
+        tags.push(format!(
+            "functional_unit:{}",
+            sanitize_tag(&sci.functional_unit)
+        ));
+        line.push_str(&format!("|#{}", tags.join(",")));
+        lines.push(line);
+    }
+    lines
+}
+
+/// Groups gauges into the payloads of datagrams (gauges separated by new lines)
+fn packets(lines: &[String]) -> Vec<String> {
+    let mut packets: Vec<String> = Vec::new();
+    let mut packet = String::new();
+    for line in lines {
+        if !packet.is_empty() && packet.len() + 1 + line.len() > MAX_PACKET_SIZE {
+            packets.push(std::mem::take(&mut packet));
+        }
+        if !packet.is_empty() {
+            packet.push('\n');
+        }
+        packet.push_str(line);
+    }
+    if !packet.is_empty() {
+        packets.push(packet);
+    }
+    packets
+}
+
+/// Sends gauges to the StatsD server (over UDP, so delivery is not confirmed)
+pub async fn send_to_statsd(config: &StatsdConfig, lines: Vec<String>) -> Result<()> {
+    let socket = tokio::net::UdpSocket::bind("0.0.0.0:0")
+        .await
+        .context("Cannot open a socket to send gauges to StatsD")?;
+    socket
+        .connect(&config.address)
+        .await
+        .with_context(|| format!("Cannot resolve StatsD address {}", config.address))?;
+    for packet in packets(&lines) {
+        socket
+            .send(packet.as_bytes())
+            .await
+            .with_context(|| format!("Cannot send gauges to StatsD at {}", config.address))?;
+    }
+    info!("Sent {} gauges to StatsD {}", lines.len(), config.address);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::RegionSummary;
+
+    fn summary() -> ImpactsSummary {
+        let region = |aws_region: &str, gwp: f64| RegionSummary {
+            gwp_use_kgco2eq: gwp,
+            number_of_resources_total: 2,
+            number_of_resources_assessed: 2,
+            ..RegionSummary::new(
+                Some("111111111111".to_string()),
+                aws_region.to_string(),
+                "FRA".to_string(),
+            )
+        };
+        ImpactsSummary {
+            number_of_resources_total: 4,
+            number_of_resources_assessed: 4,
+            number_of_resources_not_assessed: 0,
+            number_of_resources_excluded: None,
+            duration_of_use_hours: 730.0,
+            adp_manufacture_kgsbeq: 0.0001,
+            adp_use_kgsbeq: 0.0,
+            pe_manufacture_megajoules: 1234.5,
+            pe_use_megajoules: 100.0,
+            gwp_manufacture_kgco2eq: 12.4,
+            gwp_use_kgco2eq: 1.5,
+            regions: vec![region("eu-west-3", 0.5), region("eu-west-1", 1.0)],
+            sci: None,
+            groups: Vec::new(),
+            cost: None,
+            kubernetes: None,
+            parents: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn summary_is_sent_as_gauges_tagged_with_dogstatsd() {
+        let mut config = StatsdConfig {
+            address: "localhost:8125".to_string(),
+            prefix: DEFAULT_PREFIX.to_string(),
+            dogstatsd: false,
+            tags: vec!["env:prod".to_string()],
+        };
+        let gauges = get_summary_gauges(&summary(), &config);
+        assert_eq!(10, gauges.len());
+        assert_eq!("boavizta.number_of_resources_total:4|g", gauges[0]);
+        assert_eq!("boavizta.adp_manufacture_kgsbeq:0.0001|g", gauges[4]);
+        assert_eq!("boavizta.gwp_use_kgco2eq:1.5|g", gauges[9]);
+
+        config.dogstatsd = true;
+        let gauges = get_summary_gauges(&summary(), &config);
+        assert_eq!(20, gauges.len());
+        assert_eq!(
+            "boavizta.gwp_use_kgco2eq:0.5|g|#env:prod,awsregion:eu-west-3,country:FRA,account_id:111111111111",
+            gauges[9]
+        );
+        assert!(
+            gauges[19].starts_with("boavizta.gwp_use_kgco2eq:1|g|#env:prod,awsregion:eu-west-1")
+        );
+
+        let lines: Vec<String> = (0..100).map(|_| "a".repeat(100)).collect();
+        let packets = packets(&lines);
+        assert_eq!(8, packets.len());
+        assert!(packets.iter().all(|packet| packet.len() <= MAX_PACKET_SIZE));
+        assert_eq!(lines.join("\n"), packets.join("\n"));
+    }
+
+    #[tokio::test]
+    async fn gauges_are_sent_over_udp() {
+        let server = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = StatsdConfig {
+            address: server.local_addr().unwrap().to_string(),
+            prefix: "greenops".to_string(),
+            dogstatsd: false,
+            tags: Vec::new(),
+        };
+        send_to_statsd(&config, get_summary_gauges(&summary(), &config))
+            .await
+            .unwrap();
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        let size = server.recv(&mut buffer).await.unwrap();
+        let packet = String::from_utf8_lossy(&buffer[..size]);
+        assert!(packet.starts_with("greenops.number_of_resources_total:4|g\ngreenops.number_of_resources_assessed:4|g\n"), "{}", packet);
+    }
+}
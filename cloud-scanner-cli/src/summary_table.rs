@@ -0,0 +1,264 @@
+//! Human-readable table of the summary of a scan, written by `estimate` on a terminal instead of json: aligned columns, numbers rounded to significant digits with the thousands separator of the locale.
+use std::fmt::Write;
+
+use crate::impact_provider::ImpactsSummary;
+use crate::units::OutputUnits;
+
+/// Significant digits of the impacts of the table
+const SIGNIFICANT_DIGITS: i32 = 3;
+
+/// Separators of the numbers of a locale
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NumberFormat {
+    pub thousands_separator: char,
+    pub decimal_separator: char,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat {
+            thousands_separator: ',',
+            decimal_separator: '.',
+        }
+    }
+}
+
+impl NumberFormat {
+    /// Returns the separators of a locale like `fr_FR.UTF-8` or `de_DE` (the English ones for unknown locales)
+    pub fn from_locale(locale: &str) -> Self {
+        let language = locale
+            .split(['_', '-', '.', '@'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        let (thousands_separator, decimal_separator) = match language.as_str() {
+            "de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" | "el" | "ro" | "hr" | "sl" => {
+                ('.', ',')
+            }
+            "fr" | "ru" | "pl" | "cs" | "sk" | "sv" | "nb" | "nn" | "no" | "fi" | "uk" | "hu"
+            | "bg" | "et" | "lv" | "lt" => (' ', ','),
+            _ => (',', '.'),
+        };
+        NumberFormat {
+            thousands_separator,
+            decimal_separator,
+        }
+    }
+
+    /// Returns the separators of the locale of the environment (`LC_ALL`, `LC_NUMERIC` or `LANG`)
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_NUMERIC", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|locale| !locale.is_empty())
+            .map(|locale| NumberFormat::from_locale(&locale))
+            .unwrap_or_default()
+    }
+
+    /// Formats a number rounded to significant digits (integers being not rounded), like `1,234` or `0.00123`
+    pub fn format(&self, value: f64) -> String {
+        if value == 0.0 || !value.is_finite() {
+            return "0".to_string();
+        }
+        let magnitude = value.abs().log10().floor() as i32;
+        let decimals = (SIGNIFICANT_DIGITS - 1 - magnitude).max(0) as usize;
+        let rounded = format!("{:.decimals$}", value.abs(), decimals = decimals);
+        let (integer, fraction) = rounded.split_once('.').unwrap_or((&rounded, ""));
+        let mut text = String::new();
+        if value < 0.0 {
+            text.push('-');
+        }
+        for (i, digit) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                text.push(self.thousands_separator);
+            }
+            text.push(digit);
+        }
+        if !fraction.is_empty() {
+            text.push(self.decimal_separator);
+            text.push_str(fraction);
+        }
+        text
+    }
+}
+
+/// Formats rows as aligned columns, the first column being aligned left and the others (numbers) right
+fn aligned(rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| {
+            rows.iter()
+                .filter_map(|row| row.get(column))
+                .map(|cell| cell.chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let mut table = String::new();
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .enumerate()
+            .map(|(column, (cell, width))| match column {
+                0 => format!("{:<width$}", cell, width = width),
+                _ => format!("{:>width$}", cell, width = width),
+            })
+            .collect();
+        let _ = writeln!(table, "{}", cells.join("  ").trim_end());
+    }
+    table
+}
+
+/// Returns the summary as text tables: the impacts by criterion, and the impacts of each region of multi-region scans
+pub fn to_table(summary: &ImpactsSummary, units: &OutputUnits, format: &NumberFormat) -> String {
+    let mut text = String::new();
+    let _ = writeln!(
+        text,
+        "Impacts of {} resources ({} assessed, {} not assessed) over {} hours\n",
+        summary.number_of_resources_total,
+        summary.number_of_resources_assessed,
+        summary.number_of_resources_not_assessed,
+        format.format(summary.duration_of_use_hours)
+    );
+    let impact_row = |impact: String, manufacture: f64, usage: f64| {
+        vec![
+            impact,
+            format.format(manufacture),
+            format.format(usage),
+            format.format(manufacture + usage),
+        ]
+    };
+    text.push_str(&aligned(&[
+        vec![
+            "Impact".to_string(),
+            "Manufacture".to_string(),
+            "Use".to_string(),
+            "Total".to_string(),
+        ],
+        impact_row(
+            format!("Global warming potential ({})", units.gwp.symbol()),
+            summary.gwp_manufacture_kgco2eq,
+            summary.gwp_use_kgco2eq,
+        ),
+        impact_row(
+            format!("Primary energy ({})", units.energy.symbol()),
+            summary.pe_manufacture_megajoules,
+            summary.pe_use_megajoules,
+        ),
+        impact_row(
+            "Abiotic depletion potential (kgSbeq)".to_string(),
+            summary.adp_manufacture_kgsbeq,
+            summary.adp_use_kgsbeq,
+        ),
+    ]));
+
+    if summary.regions.len() > 1 {
+        let mut rows = vec![vec![
+            "Region".to_string(),
+            "Resources".to_string(),
+            format!("GWP ({})", units.gwp.symbol()),
+            format!("Primary energy ({})", units.energy.symbol()),
+        ]];
+        for region in summary.regions.iter() {
+            let name = match &region.account_id {
+                Some(account_id) => format!("{} ({})", region.aws_region, account_id),
+                None => region.aws_region.clone(),
+            };
+            rows.push(vec![
+                name,
+                region.number_of_resources_total.to_string(),
+                format.format(region.gwp_manufacture_kgco2eq + region.gwp_use_kgco2eq),
+                format.format(region.pe_manufacture_megajoules + region.pe_use_megajoules),
+            ]);
+        }
+        text.push('\n');
+        text.push_str(&aligned(&rows));
+    }
+
+    if let Some(sci) = &summary.sci {
+        let _ = writeln!(
+            text,
+            "\nSoftware Carbon Intensity: {} {} per {}",
+            format.format(sci.sci_kgco2eq_per_unit),
+            units.gwp.symbol(),
+            sci.functional_unit
+        );
+    }
+    if let Some(cost) = &summary.cost {
+        let _ = writeln!(
+            text,
+            "\nCost: {} {} ({} resources priced)",
+            format.format(cost.total_cost),
+            cost.currency,
+            cost.number_of_resources_priced
+        );
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::RegionSummary;
+
+    #[test]
+    fn numbers_are_rounded_with_the_separators_of_the_locale() {
+        let english = NumberFormat::from_locale("en_US.UTF-8");
+        assert_eq!("1,234,568", english.format(1234567.89));
+        assert_eq!("12.3", english.format(12.345));
+        assert_eq!("0.00123", english.format(0.0012345));
+        assert_eq!("-1,500", english.format(-1500.2));
+        assert_eq!("0", english.format(0.0));
+        assert_eq!("1.234", NumberFormat::from_locale("de_DE").format(1234.0));
+        assert_eq!(
+            "1 234",
+            NumberFormat::from_locale("fr_FR.UTF-8").format(1234.0)
+        );
+        assert_eq!("2,35", NumberFormat::from_locale("fr").format(2.345));
+        assert_eq!(english, NumberFormat::from_locale("C"));
+    }
+
+    #[test]
+    fn table_shows_the_impacts_by_criterion_and_region() {
+        let region = |aws_region: &str, gwp: f64| RegionSummary {
+            gwp_use_kgco2eq: gwp,
+            number_of_resources_total: 2,
+            ..RegionSummary::new(None, aws_region.to_string(), "FRA".to_string())
+        };
+        let summary = ImpactsSummary {
+            number_of_resources_total: 4,
+            number_of_resources_assessed: 3,
+            number_of_resources_not_assessed: 1,
+            number_of_resources_excluded: None,
+            duration_of_use_hours: 730.0,
+            adp_manufacture_kgsbeq: 0.0001,
+            adp_use_kgsbeq: 0.0,
+            pe_manufacture_megajoules: 1234.5,
+            pe_use_megajoules: 100.0,
+            gwp_manufacture_kgco2eq: 12.4,
+            gwp_use_kgco2eq: 1500.0,
+            regions: vec![region("eu-west-3", 500.0), region("eu-west-1", 1000.0)],
+            sci: None,
+            groups: Vec::new(),
+            cost: None,
+            kubernetes: None,
+            parents: Vec::new(),
+        };
+        let table = to_table(&summary, &OutputUnits::default(), &NumberFormat::default());
+        assert_eq!(
+            "Impacts of 4 resources (3 assessed, 1 not assessed) over 730 hours
+
+Impact                                Manufacture    Use     Total
+Global warming potential (kgCO2eq)           12.4  1,500     1,512
+Primary energy (MJ)                         1,234    100     1,334
+Abiotic depletion potential (kgSbeq)     0.000100      0  0.000100
+
+Region     Resources  GWP (kgCO2eq)  Primary energy (MJ)
+eu-west-3          2            500                    0
+eu-west-1          2          1,000                    0
+",
+            table
+        );
+    }
+}
@@ -0,0 +1,283 @@
+//! Types of resources known by the impact provider (`list-supported-types` command), cross-checked against an inventory to report the resources that would not be estimated (or only estimated with default values) before running a scan.
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::boavizta_api_v1::{cloud_provider, BoaviztaApiV1, HDD_VOLUME_TYPES, SSD_VOLUME_TYPES};
+use crate::impact_provider::ImpactProvider;
+use crate::model::{CloudResource, Inventory, ResourceDetails};
+
+/// Types of resources known by an impact provider
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SupportedTypes {
+    /// Instance types by cloud provider, as named by the impact provider (like `aws`)
+    pub instance_types: BTreeMap<String, Vec<String>>,
+    /// Archetypes of servers (estimated when the hardware of a server is unknown)
+    pub server_archetypes: Vec<String>,
+}
+
+/// How the resources of a type are estimated
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TypeSupport {
+    /// Estimated with the specs of their type
+    Supported,
+    /// Estimated with default specs (like volumes of an unknown type, estimated as ssd)
+    EstimatedAsDefault,
+    /// Not estimated (like instances of a type unknown by the impact provider)
+    Unsupported,
+}
+
+/// Support of a type of resources of the inventory
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TypeCoverage {
+    pub kind: String,
+    /// Provider of the resources (like `AWS` or `OnPremises`)
+    pub provider: String,
+    pub resource_type: String,
+    pub number_of_resources: usize,
+    pub support: TypeSupport,
+}
+
+/// Types known by the impact provider and, when checked against an inventory, the support of the types of its resources
+#[derive(Clone, Debug, Serialize)]
+pub struct CoverageReport {
+    pub supported_types: SupportedTypes,
+    /// Types of the resources of the inventory (empty without inventory)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub coverage: Vec<TypeCoverage>,
+}
+
+/// Returns the types known by Boavizta API at this url
+pub async fn supported_types_of_boavizta_api(api_url: &str) -> Result<SupportedTypes> {
+    BoaviztaApiV1::new(api_url).supported_types().await
+}
+
+/// Returns the support of the type of a resource by the impact provider
+fn type_support(supported_types: &SupportedTypes, resource: &CloudResource) -> TypeSupport {
+    match &resource.resource_details {
+        ResourceDetails::Instance { instance_type, .. } => {
+            let known = supported_types
+                .instance_types
+                .get(cloud_provider(&resource.provider))
+                .is_some_and(|types| types.contains(instance_type));
+            if known {
+                TypeSupport::Supported
+            } else {
+                TypeSupport::Unsupported
+            }
+        }
+        ResourceDetails::BlockStorage { storage_type, .. } => {
+            let storage_type = storage_type.as_str();
+            if HDD_VOLUME_TYPES.contains(&storage_type) || SSD_VOLUME_TYPES.contains(&storage_type)
+            {
+                TypeSupport::Supported
+            } else {
+                TypeSupport::EstimatedAsDefault
+            }
+        }
+        ResourceDetails::ObjectStorage => TypeSupport::Unsupported,
+        ResourceDetails::Server { hardware, .. } => match hardware {
+            Some(_) => TypeSupport::Supported,
+            None => TypeSupport::EstimatedAsDefault,
+        },
+    }
+}
+
+/// Returns the support of the types of the resources of an inventory, by kind, provider and type
+pub fn check_coverage(supported_types: SupportedTypes, inventory: &Inventory) -> CoverageReport {
+    let mut coverage: BTreeMap<(&'static str, String, String), TypeCoverage> = BTreeMap::new();
+    for resource in inventory.resources.iter() {
+        let kind = resource.resource_details.kind();
+        let provider = format!("{:?}", resource.provider);
+        let resource_type = resource
+            .resource_details
+            .resource_type()
+            .unwrap_or_default();
+        coverage
+            .entry((kind, provider.clone(), resource_type.clone()))
+            .or_insert_with(|| TypeCoverage {
+                kind: kind.to_string(),
+                provider,
+                resource_type,
+                number_of_resources: 0,
+                support: type_support(&supported_types, resource),
+            })
+            .number_of_resources += 1;
+    }
+    CoverageReport {
+        supported_types,
+        coverage: coverage.into_values().collect(),
+    }
+}
+
+impl CoverageReport {
+    /// Returns the report without inventory
+    pub fn of_types(supported_types: SupportedTypes) -> Self {
+        CoverageReport {
+            supported_types,
+            coverage: Vec::new(),
+        }
+    }
+
+    /// Returns the types of the inventory that are not estimated with their specs
+    pub fn gaps(&self) -> Vec<&TypeCoverage> {
+        self.coverage
+            .iter()
+            .filter(|coverage| coverage.support != TypeSupport::Supported)
+            .collect()
+    }
+
+    /// Returns the report as text: the types known by the impact provider without inventory, or the number of types by provider and the types of the inventory that are not supported
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        if self.coverage.is_empty() {
+            for (provider, types) in self.supported_types.instance_types.iter() {
+                text.push_str(&format!("{} instance types ({}):\n", provider, types.len()));
+                for instance_type in types {
+                    text.push_str(&format!("  {}\n", instance_type));
+                }
+            }
+            text.push_str(&format!(
+                "Server archetypes ({}):\n",
+                self.supported_types.server_archetypes.len()
+            ));
+            for archetype in self.supported_types.server_archetypes.iter() {
+                text.push_str(&format!("  {}\n", archetype));
+            }
+            return text;
+        }
+        for (provider, types) in self.supported_types.instance_types.iter() {
+            text.push_str(&format!("{}: {} instance types\n", provider, types.len()));
+        }
+        let gaps = self.gaps();
+        let resources: usize = self.coverage.iter().map(|c| c.number_of_resources).sum();
+        let resources_in_gaps: usize = gaps.iter().map(|c| c.number_of_resources).sum();
+        text.push_str(&format!(
+            "Inventory: {} types ({} resources), {} not supported ({} resources)\n",
+            self.coverage.len(),
+            resources,
+            gaps.len(),
+            resources_in_gaps
+        ));
+        for gap in gaps {
+            let support = match gap.support {
+                TypeSupport::EstimatedAsDefault => "estimated with default specs",
+                _ => "not estimated",
+            };
+            text.push_str(&format!(
+                "{} {} {} ({} resources): {}\n",
+                gap.provider,
+                gap.kind,
+                if gap.resource_type.is_empty() {
+                    "-"
+                } else {
+                    &gap.resource_type
+                },
+                gap.number_of_resources,
+                support
+            ));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::CloudProvider;
+    use crate::usage_location::UsageLocation;
+
+    fn resource(
+        id: &str,
+        provider: CloudProvider,
+        resource_details: ResourceDetails,
+    ) -> CloudResource {
+        CloudResource {
+            provider,
+            id: id.to_string(),
+            location: UsageLocation::try_from("eu-west-3").unwrap(),
+            resource_details,
+            tags: Vec::new(),
+            account_id: None,
+            relationships: Vec::new(),
+        }
+    }
+
+    fn instance(id: &str, provider: CloudProvider, instance_type: &str) -> CloudResource {
+        resource(
+            id,
+            provider,
+            ResourceDetails::Instance {
+                instance_type: instance_type.to_string(),
+                usage: None,
+            },
+        )
+    }
+
+    #[test]
+    fn types_of_the_inventory_are_checked() {
+        let supported_types = SupportedTypes {
+            instance_types: BTreeMap::from([
+                (
+                    "aws".to_string(),
+                    vec!["m6g.xlarge".to_string(), "t3.micro".to_string()],
+                ),
+                ("azure".to_string(), vec!["Standard_D2s_v3".to_string()]),
+            ]),
+            server_archetypes: vec!["platform_compute_medium".to_string()],
+        };
+        let inventory = Inventory {
+            resources: vec![
+                instance("i-1", CloudProvider::AWS, "t3.micro"),
+                instance("i-2", CloudProvider::AWS, "t3.micro"),
+                instance("i-3", CloudProvider::AWS, "x9.huge"),
+                instance("vm-1", CloudProvider::Azure, "t3.micro"),
+                resource(
+                    "vol-1",
+                    CloudProvider::AWS,
+                    ResourceDetails::BlockStorage {
+                        storage_type: "io2".to_string(),
+                        usage: None,
+                        attached_instances: None,
+                    },
+                ),
+            ],
+            execution_statistics: None,
+        };
+        let report = check_coverage(supported_types, &inventory);
+        assert_eq!(4, report.coverage.len());
+        let t3 = &report.coverage[1];
+        assert_eq!(
+            ("t3.micro", 2),
+            (t3.resource_type.as_str(), t3.number_of_resources)
+        );
+        assert_eq!(TypeSupport::Supported, t3.support);
+
+        let gaps: Vec<(&str, &str, TypeSupport)> = report
+            .gaps()
+            .iter()
+            .map(|gap| {
+                (
+                    gap.provider.as_str(),
+                    gap.resource_type.as_str(),
+                    gap.support,
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("AWS", "io2", TypeSupport::EstimatedAsDefault),
+                ("AWS", "x9.huge", TypeSupport::Unsupported),
+                ("Azure", "t3.micro", TypeSupport::Unsupported),
+            ],
+            gaps
+        );
+        assert!(report
+            .to_text()
+            .contains("Inventory: 4 types (5 resources), 3 not supported (3 resources)"));
+        assert!(CoverageReport::of_types(report.supported_types)
+            .to_text()
+            .contains("  m6g.xlarge\n"));
+    }
+}
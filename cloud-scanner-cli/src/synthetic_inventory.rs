@@ -0,0 +1,260 @@
+//! A generator of synthetic inventories of a configurable size and shape (regions, accounts, instance types, volumes, tags), to benchmark the scans of large inventories without cloud credentials (see the benchmarks in `benches/`).
+//!
+//! Inventories are pseudo-random but deterministic: a shape (with its seed) always generates the same resources.
+use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+use crate::model::{
+    CloudProvider, CloudResource, CloudResourceTag, EstimatedInventory, InstanceState,
+    InstanceUsage, Inventory, ResourceDetails, StorageAttachment, StorageUsage,
+};
+use crate::usage_location::UsageLocation;
+
+/// Instance types of the generated instances, unless set in the shape
+pub const DEFAULT_INSTANCE_TYPES: [&str; 6] = [
+    "t3.micro",
+    "t3.medium",
+    "m5.large",
+    "m6g.xlarge",
+    "c5.2xlarge",
+    "r5.4xlarge",
+];
+
+/// Types of the generated volumes (SSD and HDD)
+const VOLUME_TYPES: [&str; 5] = ["gp2", "gp3", "io1", "st1", "sc1"];
+
+/// Number of values of each generated tag
+const TAG_VALUES: u64 = 10;
+
+/// Size and shape of a synthetic inventory
+#[derive(Clone, Debug)]
+pub struct InventoryShape {
+    pub instances: usize,
+    /// Volumes, attached to the instances in turn (or detached when there are more volumes than instances)
+    pub volumes: usize,
+    /// Regions of the resources, in turn
+    pub regions: Vec<String>,
+    /// Accounts of the resources, in turn (0 for the account of the environment)
+    pub accounts: usize,
+    pub instance_types: Vec<String>,
+    /// Tags of each resource (like `tag0=value3`)
+    pub tags_per_resource: usize,
+    /// Distinct CPU loads of the instances: the fewer they are, the more queries of impacts are identical
+    pub distinct_cpu_loads: usize,
+    /// Share of the instances that are stopped, in percent
+    pub stopped_percent: u8,
+    pub seed: u64,
+}
+
+impl Default for InventoryShape {
+    fn default() -> Self {
+        InventoryShape {
+            instances: 1000,
+            volumes: 500,
+            regions: vec!["eu-west-3".to_string(), "us-east-1".to_string()],
+            accounts: 0,
+            instance_types: DEFAULT_INSTANCE_TYPES.map(String::from).to_vec(),
+            tags_per_resource: 3,
+            distinct_cpu_loads: 100,
+            stopped_percent: 10,
+            seed: 42,
+        }
+    }
+}
+
+/// A pseudo-random generator (splitmix64), good enough for synthetic data
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number below `bound` (which must not be 0)
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+impl InventoryShape {
+    /// Returns an inventory of this shape, the instances first
+    pub fn generate(&self) -> Inventory {
+        let mut random = SplitMix64(self.seed);
+        let locations: Vec<UsageLocation> = self
+            .regions
+            .iter()
+            .filter_map(|region| UsageLocation::try_from(region.as_str()).ok())
+            .collect();
+        let locations = match locations.is_empty() {
+            true => vec![UsageLocation::try_from("eu-west-3").unwrap()],
+            false => locations,
+        };
+        let instance_types = match self.instance_types.is_empty() {
+            true => DEFAULT_INSTANCE_TYPES.map(String::from).to_vec(),
+            false => self.instance_types.clone(),
+        };
+        let account_id = |index: usize| match self.accounts {
+            0 => None,
+            accounts => Some(format!("{:012}", 100000000000 + index % accounts)),
+        };
+        let tags = |random: &mut SplitMix64| -> Vec<CloudResourceTag> {
+            (0..self.tags_per_resource)
+                .map(|tag| CloudResourceTag {
+                    key: format!("tag{}", tag),
+                    value: Some(format!("value{}", random.below(TAG_VALUES))),
+                })
+                .collect()
+        };
+
+        let mut resources = Vec::with_capacity(self.instances + self.volumes);
+        for index in 0..self.instances {
+            let distinct_cpu_loads = self.distinct_cpu_loads.max(1) as u64;
+            let average_cpu_load =
+                100.0 * random.below(distinct_cpu_loads) as f64 / distinct_cpu_loads as f64;
+            let state = match random.below(100) < self.stopped_percent as u64 {
+                true => InstanceState::Stopped,
+                false => InstanceState::Running,
+            };
+            let instance_type =
+                instance_types[random.below(instance_types.len() as u64) as usize].clone();
+            resources.push(CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: account_id(index),
+                id: format!("i-{:017x}", index),
+                location: locations[index % locations.len()].clone(),
+                resource_details: ResourceDetails::Instance {
+                    instance_type,
+                    usage: Some(InstanceUsage {
+                        average_cpu_load,
+                        usage_duration_seconds: 300,
+                        state,
+                    }),
+                },
+                tags: tags(&mut random),
+                relationships: Vec::new(),
+            });
+        }
+        for index in 0..self.volumes {
+            let attached_instances = (index < self.instances).then(|| {
+                vec![StorageAttachment {
+                    instance_id: format!("i-{:017x}", index),
+                }]
+            });
+            resources.push(CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: account_id(index),
+                id: format!("vol-{:017x}", index),
+                location: locations[index % locations.len()].clone(),
+                resource_details: ResourceDetails::BlockStorage {
+                    storage_type: VOLUME_TYPES[random.below(VOLUME_TYPES.len() as u64) as usize]
+                        .to_string(),
+                    usage: Some(StorageUsage {
+                        size_gb: 8 << random.below(8),
+                        usage_duration_seconds: 3600,
+                    }),
+                    attached_instances,
+                },
+                tags: tags(&mut random),
+                relationships: Vec::new(),
+            });
+        }
+        Inventory {
+            resources,
+            execution_statistics: None,
+        }
+    }
+}
+
+/// Returns the resources of an inventory with synthetic impacts (growing with the CPU load of instances and the size of volumes), to benchmark what follows the estimation (summaries and outputs)
+pub fn with_synthetic_impacts(
+    inventory: Inventory,
+    usage_duration_hours: f32,
+) -> EstimatedInventory {
+    let impacting_resources = inventory
+        .resources
+        .into_iter()
+        .map(|cloud_resource| {
+            let scale = match &cloud_resource.resource_details {
+                ResourceDetails::Instance {
+                    usage: Some(usage), ..
+                } => 1.0 + usage.average_cpu_load / 100.0,
+                ResourceDetails::BlockStorage {
+                    usage: Some(usage), ..
+                } => usage.size_gb as f64 / 1000.0,
+                _ => 0.0,
+            };
+            let hours = usage_duration_hours as f64;
+            CloudResourceWithImpacts {
+                cloud_resource,
+                impacts_values: Some(ImpactsValues {
+                    adp_manufacture_kgsbeq: 2.1e-7 * scale * hours,
+                    adp_use_kgsbeq: 1.1e-9 * scale * hours,
+                    pe_manufacture_megajoules: 0.021 * scale * hours,
+                    pe_use_megajoules: 0.21 * scale * hours,
+                    gwp_manufacture_kgco2eq: 0.0016 * scale * hours,
+                    gwp_use_kgco2eq: 0.00184 * scale * hours,
+                    raw_data: None,
+                }),
+                impacts_duration_hours: usage_duration_hours,
+                cost: None,
+            }
+        })
+        .collect();
+    EstimatedInventory {
+        impacting_resources,
+        execution_statistics: None,
+        errors: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inventories_have_the_requested_shape() {
+        let shape = InventoryShape {
+            instances: 20,
+            volumes: 30,
+            accounts: 3,
+            ..InventoryShape::default()
+        };
+        let inventory = shape.generate();
+        assert_eq!(50, inventory.resources.len());
+        assert_eq!(
+            20,
+            inventory
+                .resources
+                .iter()
+                .filter(|resource| matches!(
+                    resource.resource_details,
+                    ResourceDetails::Instance { .. }
+                ))
+                .count()
+        );
+        let last = &inventory.resources[49];
+        assert_eq!("us-east-1", last.location.aws_region);
+        assert_eq!(Some("100000000002"), last.account_id.as_deref());
+        assert_eq!(3, last.tags.len());
+        // Volumes beyond the number of instances are detached
+        assert!(matches!(
+            last.resource_details,
+            ResourceDetails::BlockStorage {
+                attached_instances: None,
+                ..
+            }
+        ));
+
+        // Deterministic for a seed
+        let again = serde_json::to_string(&shape.generate().resources).unwrap();
+        assert_eq!(serde_json::to_string(&inventory.resources).unwrap(), again);
+
+        let estimated = with_synthetic_impacts(inventory, 730.0);
+        assert!(estimated
+            .impacting_resources
+            .iter()
+            .all(|resource| resource.impacts_values.is_some()));
+    }
+}
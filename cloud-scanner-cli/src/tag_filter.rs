@@ -0,0 +1,369 @@
+//! Filter expressions on the tags of resources, like `env=prod AND team!=infra AND name~web-*`.
+//!
+//! A condition compares the value of a tag:
+//! - `key=value`: the tag is set to this value (`key` alone: the tag is set without a value)
+//! - `key!=value`: the tag is not set to this value (or the resource does not have the tag)
+//! - `key~pattern`: the value of the tag matches a pattern, where `*` matches any characters and `?` one character (`key~*` matches any resource with the tag)
+//! - `key!~pattern`: the resource does not have a matching tag
+//!
+//! Conditions are combined with `AND`, `OR` (`AND` binds tighter), `NOT` and parentheses. Keywords are upper case and separated by spaces, so that tag values can contain lower case `and` or `or`.
+//!
+//! Several filters (like several `--filter-tags` or `filter_tag` parameters) must all match.
+use anyhow::{Context, Result};
+
+use crate::model::CloudResourceTag;
+
+/// How a condition compares the value of a tag
+#[derive(Clone, Debug, PartialEq)]
+pub enum Comparison {
+    Equals,
+    NotEquals,
+    Matches,
+    NotMatches,
+}
+
+/// A parsed filter expression
+#[derive(Clone, Debug, PartialEq)]
+pub enum TagFilter {
+    Condition {
+        key: String,
+        comparison: Comparison,
+        value: Option<String>,
+    },
+    Not(Box<TagFilter>),
+    And(Vec<TagFilter>),
+    Or(Vec<TagFilter>),
+}
+
+impl TagFilter {
+    /// Parses a filter expression
+    pub fn parse(expression: &str) -> Result<TagFilter> {
+        let tokens = tokenize(expression);
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+        };
+        let filter = parser
+            .parse_or()
+            .with_context(|| format!("Invalid tag filter '{}'", expression))?;
+        if let Some(token) = parser.tokens.get(parser.position) {
+            anyhow::bail!(
+                "Invalid tag filter '{}': unexpected {:?}",
+                expression,
+                token
+            );
+        }
+        Ok(filter)
+    }
+
+    /// Parses several filters, that must all match (none matches any resource)
+    pub fn parse_all(expressions: &[String]) -> Result<TagFilter> {
+        let filters = expressions
+            .iter()
+            .map(|expression| TagFilter::parse(expression))
+            .collect::<Result<Vec<TagFilter>>>()?;
+        Ok(TagFilter::And(filters))
+    }
+
+    /// Returns true when the tags of a resource match the filter
+    pub fn matches(&self, tags: &[CloudResourceTag]) -> bool {
+        match self {
+            TagFilter::Condition {
+                key,
+                comparison,
+                value,
+            } => {
+                let tag = tags.iter().find(|tag| &tag.key == key);
+                let glob_matches = || {
+                    tag.is_some_and(|tag| {
+                        glob_matches(
+                            value.as_deref().unwrap_or_default(),
+                            tag.value.as_deref().unwrap_or_default(),
+                        )
+                    })
+                };
+                match comparison {
+                    Comparison::Equals => tag.is_some_and(|tag| &tag.value == value),
+                    Comparison::NotEquals => tag.is_none_or(|tag| &tag.value != value),
+                    Comparison::Matches => glob_matches(),
+                    Comparison::NotMatches => !glob_matches(),
+                }
+            }
+            TagFilter::Not(filter) => !filter.matches(tags),
+            TagFilter::And(filters) => filters.iter().all(|filter| filter.matches(tags)),
+            TagFilter::Or(filters) => filters.iter().any(|filter| filter.matches(tags)),
+        }
+    }
+}
+
+/// Returns true when a value matches a pattern (`*` for any characters, `?` for one character)
+pub(crate) fn glob_matches(pattern: &str, value: &str) -> bool {
+    // Byte positions in the pattern and in the value (matched without collecting their characters, as every resource is matched)
+    let (mut p, mut v) = (0, 0);
+    // Position of the last `*` of the pattern, and of the value when it was reached
+    let mut backtrack: Option<(usize, usize)> = None;
+    while let Some(c) = value[v..].chars().next() {
+        match pattern[p..].chars().next() {
+            Some(pc) if pc == '?' || pc == c => {
+                p += pc.len_utf8();
+                v += c.len_utf8();
+            }
+            Some('*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    let next = matched + value[matched..].chars().next().map_or(1, char::len_utf8);
+                    p = star + 1;
+                    v = next;
+                    backtrack = Some((star, next));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].chars().all(|c| c == '*')
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    Condition(String),
+}
+
+/// Splits an expression into keywords, parentheses and conditions (the words between them)
+fn tokenize(expression: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut condition: Vec<&str> = Vec::new();
+    let spaced = expression.replace('(', " ( ").replace(')', " ) ");
+    for word in spaced.split_whitespace() {
+        let keyword = match word {
+            "AND" => Some(Token::And),
+            "OR" => Some(Token::Or),
+            "NOT" => Some(Token::Not),
+            "(" => Some(Token::Open),
+            ")" => Some(Token::Close),
+            _ => None,
+        };
+        match keyword {
+            Some(keyword) => {
+                if !condition.is_empty() {
+                    tokens.push(Token::Condition(condition.join(" ")));
+                    condition.clear();
+                }
+                tokens.push(keyword);
+            }
+            None => condition.push(word),
+        }
+    }
+    if !condition.is_empty() {
+        tokens.push(Token::Condition(condition.join(" ")));
+    }
+    tokens
+}
+
+/// Parses a condition, like `env=prod` or `name~web-*`
+fn parse_condition(condition: &str) -> Result<TagFilter> {
+    let operator = ["!=", "!~", "=", "~"]
+        .iter()
+        .filter_map(|operator| condition.find(operator).map(|i| (i, *operator)))
+        .min_by_key(|(i, operator)| (*i, std::cmp::Reverse(operator.len())));
+    let (key, comparison, value) = match operator {
+        Some((i, operator)) => {
+            let comparison = match operator {
+                "!=" => Comparison::NotEquals,
+                "!~" => Comparison::NotMatches,
+                "=" => Comparison::Equals,
+                _ => Comparison::Matches,
+            };
+            let value = condition[i + operator.len()..].trim().to_string();
+            (&condition[..i], comparison, Some(value))
+        }
+        None => (condition, Comparison::Equals, None),
+    };
+    let key = key.trim();
+    if key.is_empty() {
+        anyhow::bail!("Missing tag key in '{}'", condition);
+    }
+    Ok(TagFilter::Condition {
+        key: key.to_string(),
+        comparison,
+        value,
+    })
+}
+
+/// A recursive descent parser: OR of ANDs of (negated) conditions or expressions in parentheses
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn next_is(&mut self, expected: &Token) -> bool {
+        let is_expected = self.tokens.get(self.position) == Some(expected);
+        if is_expected {
+            self.position += 1;
+        }
+        is_expected
+    }
+
+    fn parse_or(&mut self) -> Result<TagFilter> {
+        let mut filters = vec![self.parse_and()?];
+        while self.next_is(&Token::Or) {
+            filters.push(self.parse_and()?);
+        }
+        Ok(if filters.len() == 1 {
+            filters.remove(0)
+        } else {
+            TagFilter::Or(filters)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<TagFilter> {
+        let mut filters = vec![self.parse_unary()?];
+        while self.next_is(&Token::And) {
+            filters.push(self.parse_unary()?);
+        }
+        Ok(if filters.len() == 1 {
+            filters.remove(0)
+        } else {
+            TagFilter::And(filters)
+        })
+    }
+
+    fn parse_unary(&mut self) -> Result<TagFilter> {
+        match self.next() {
+            Some(Token::Not) => Ok(TagFilter::Not(Box::new(self.parse_unary()?))),
+            Some(Token::Open) => {
+                let filter = self.parse_or()?;
+                if !self.next_is(&Token::Close) {
+                    anyhow::bail!("Missing closing parenthesis");
+                }
+                Ok(filter)
+            }
+            Some(Token::Condition(condition)) => parse_condition(&condition),
+            Some(token) => anyhow::bail!("Expected a condition, found {:?}", token),
+            None => anyhow::bail!("Expected a condition"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(tags: &[(&str, Option<&str>)]) -> Vec<CloudResourceTag> {
+        tags.iter()
+            .map(|(key, value)| CloudResourceTag {
+                key: key.to_string(),
+                value: value.map(str::to_string),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn conditions_are_parsed() {
+        assert_eq!(
+            TagFilter::Condition {
+                key: "name 1".to_string(),
+                comparison: Comparison::Equals,
+                value: Some("val 1".to_string()),
+            },
+            TagFilter::parse("name 1=val 1").unwrap()
+        );
+        assert_eq!(
+            TagFilter::Condition {
+                key: "team".to_string(),
+                comparison: Comparison::NotEquals,
+                value: Some("infra".to_string()),
+            },
+            TagFilter::parse("team != infra").unwrap()
+        );
+        assert_eq!(
+            TagFilter::Condition {
+                key: "Name".to_string(),
+                comparison: Comparison::Equals,
+                value: None,
+            },
+            TagFilter::parse("Name").unwrap()
+        );
+        for invalid in [
+            "",
+            "env=prod AND",
+            "(env=prod",
+            "env=prod)",
+            "=prod",
+            "OR env=prod",
+        ] {
+            assert!(TagFilter::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn expressions_match_tags() {
+        let web = tags(&[
+            ("env", Some("prod")),
+            ("team", Some("web")),
+            ("name", Some("web-frontend")),
+            ("legacy", None),
+        ]);
+        let infra = tags(&[("env", Some("prod")), ("team", Some("infra"))]);
+        let matches = |expression: &str| {
+            let filter = TagFilter::parse(expression).unwrap();
+            (filter.matches(&web), filter.matches(&infra))
+        };
+        assert_eq!(
+            (true, false),
+            matches("env=prod AND team!=infra AND name~web-*")
+        );
+        assert_eq!((true, true), matches("team=web OR team=infra"));
+        assert_eq!((false, true), matches("NOT team=web"));
+        assert_eq!((false, true), matches("name!~web-*"));
+        assert_eq!((true, false), matches("name~*"));
+        assert_eq!((true, false), matches("name~web-?rontend"));
+        assert_eq!((true, false), matches("legacy"));
+        assert_eq!(
+            (true, true),
+            matches("env=prod AND (team=infra OR name~*-frontend)")
+        );
+        assert_eq!((false, true), matches("env=dev OR team=infra AND env=prod"));
+    }
+
+    #[test]
+    fn all_filters_must_match() {
+        let resource = tags(&[("env", Some("prod")), ("team", Some("web"))]);
+        let filters = |filters: &[&str]| {
+            let filters: Vec<String> = filters.iter().map(|f| f.to_string()).collect();
+            TagFilter::parse_all(&filters).unwrap().matches(&resource)
+        };
+        assert!(filters(&[]));
+        assert!(filters(&["env=prod", "team=web"]));
+        assert!(!filters(&["env=prod", "team=infra"]));
+        assert!(TagFilter::parse_all(&["env=prod".to_string(), "AND".to_string()]).is_err());
+    }
+
+    #[test]
+    fn patterns_match_values() {
+        assert!(glob_matches("web-*", "web-frontend"));
+        assert!(glob_matches("*-frontend", "web-frontend"));
+        assert!(glob_matches("w*b*d", "web-frontend"));
+        assert!(glob_matches("*", ""));
+        assert!(!glob_matches("web-?", "web-frontend"));
+        assert!(!glob_matches("api-*", "web-frontend"));
+        // Wildcards match characters, not bytes
+        assert!(glob_matches("caf?-*", "café-paris"));
+        assert!(glob_matches("*é", "café"));
+        assert!(!glob_matches("caf??", "café"));
+    }
+}
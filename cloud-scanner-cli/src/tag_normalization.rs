@@ -0,0 +1,163 @@
+//! Normalization of the tags of resources, set once for the process (with the `[tags]` section of the configuration file) and applied when resources are inventoried, so that filtering and grouping by tag work despite inconsistent tagging practices.
+//!
+//! ```toml
+//! [tags]
+//! # Case of the keys: "preserve" (default), "lower" or "upper"
+//! key_case = "lower"
+//! # Keys replacing their synonyms (matched regardless of their case)
+//! synonyms = { team = ["owner_team", "OwnerTeam"], env = ["environment", "stage"] }
+//! # Values of the tags added to the resources that do not have them (or have no value for them)
+//! defaults = { env = "unknown" }
+//! ```
+//!
+//! Keys reserved by AWS (starting with `aws:`) are kept as they are. When several tags of a resource have the same normalized key, the first one with a value is kept.
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use crate::model::CloudResourceTag;
+
+/// Case of the normalized keys
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyCase {
+    /// Keys are kept as they are
+    #[default]
+    Preserve,
+    Lower,
+    Upper,
+}
+
+/// Rules normalizing the tags of the inventoried resources
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TagNormalization {
+    /// Case of the keys (kept as they are by default), the keys of synonyms and defaults are used as written
+    #[serde(default)]
+    pub key_case: KeyCase,
+    /// Synonyms of keys (like `owner_team` for `team`), replaced by their key
+    #[serde(default)]
+    pub synonyms: BTreeMap<String, Vec<String>>,
+    /// Values of the tags added to the resources that do not have them
+    #[serde(default)]
+    pub defaults: BTreeMap<String, String>,
+}
+
+impl TagNormalization {
+    pub fn is_empty(&self) -> bool {
+        self.key_case == KeyCase::Preserve && self.synonyms.is_empty() && self.defaults.is_empty()
+    }
+
+    /// Returns the normalized key of a tag
+    fn key(&self, key: &str) -> String {
+        if key.starts_with("aws:") {
+            return key.to_string();
+        }
+        let synonym = self.synonyms.iter().find(|(canonical, synonyms)| {
+            canonical.eq_ignore_ascii_case(key)
+                || synonyms
+                    .iter()
+                    .any(|synonym| synonym.eq_ignore_ascii_case(key))
+        });
+        match (synonym, self.key_case) {
+            (Some((canonical, _)), _) => canonical.clone(),
+            (None, KeyCase::Preserve) => key.to_string(),
+            (None, KeyCase::Lower) => key.to_lowercase(),
+            (None, KeyCase::Upper) => key.to_uppercase(),
+        }
+    }
+
+    /// Returns the tags with their normalized keys, without duplicated keys and with the default tags
+    pub fn normalize(&self, tags: Vec<CloudResourceTag>) -> Vec<CloudResourceTag> {
+        if self.is_empty() {
+            return tags;
+        }
+        let mut normalized: Vec<CloudResourceTag> = Vec::with_capacity(tags.len());
+        for tag in tags {
+            let key = self.key(&tag.key);
+            match normalized.iter_mut().find(|t| t.key == key) {
+                Some(existing) => {
+                    if existing.value.as_deref().unwrap_or_default().is_empty() {
+                        existing.value = tag.value;
+                    }
+                }
+                None => normalized.push(CloudResourceTag {
+                    key,
+                    value: tag.value,
+                }),
+            }
+        }
+        for (key, value) in self.defaults.iter() {
+            match normalized.iter_mut().find(|t| &t.key == key) {
+                Some(existing) if existing.value.as_deref().unwrap_or_default().is_empty() => {
+                    existing.value = Some(value.clone())
+                }
+                Some(_) => {}
+                None => normalized.push(CloudResourceTag {
+                    key: key.clone(),
+                    value: Some(value.clone()),
+                }),
+            }
+        }
+        normalized
+    }
+}
+
+static RULES: OnceLock<TagNormalization> = OnceLock::new();
+
+/// Sets the normalization of the tags of the inventoried resources (only the first rules are kept)
+pub fn configure(rules: TagNormalization) {
+    if RULES.set(rules).is_err() {
+        warn!("Tag normalization is already configured, ignoring new rules");
+    }
+}
+
+/// Normalizes the tags of a resource with the configured rules (tags are kept as they are when none is configured)
+pub fn normalize(tags: Vec<CloudResourceTag>) -> Vec<CloudResourceTag> {
+    match RULES.get() {
+        Some(rules) => rules.normalize(tags),
+        None => tags,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(key: &str, value: Option<&str>) -> CloudResourceTag {
+        CloudResourceTag {
+            key: key.to_string(),
+            value: value.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn tags_are_normalized() {
+        let rules: TagNormalization = toml::from_str(
+            r#"
+            key_case = "lower"
+            synonyms = { team = ["owner_team"] }
+            defaults = { env = "unknown", team = "none" }
+            "#,
+        )
+        .unwrap();
+        let tags = rules.normalize(vec![
+            tag("Team", None),
+            tag("OWNER_TEAM", Some("web")),
+            tag("Cost-Center", Some("42")),
+            tag("aws:autoscaling:groupName", Some("workers")),
+        ]);
+        assert_eq!(
+            vec![
+                tag("team", Some("web")),
+                tag("cost-center", Some("42")),
+                tag("aws:autoscaling:groupName", Some("workers")),
+                tag("env", Some("unknown")),
+            ],
+            tags
+        );
+
+        let tags = vec![tag("Team", Some("web"))];
+        assert_eq!(tags, TagNormalization::default().normalize(tags.clone()));
+    }
+}
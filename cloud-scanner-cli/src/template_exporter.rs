@@ -0,0 +1,153 @@
+//! A module to render the results of a scan through a user-defined [Tera](https://keats.github.io/tera/docs/) template.
+//!
+//! Templates receive the following variables:
+//! - `summary`: the summary of impacts (same fields as the json summary)
+//! - `resources`: the resources with their impacts (same fields as the json output)
+//! - `aws_region` (the regions of the scan, separated by commas), `generated_at` (RFC 3339) and `cloud_scanner_version`
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::path::Path;
+use tera::Tera;
+
+use crate::impact_provider::ImpactsSummary;
+use crate::model::EstimatedInventory;
+
+/// Returns the extension of the results of a template, deduced from its file name (like `wiki.md.tera` returns `md`).
+///
+/// Returns `txt` when the template name has no inner extension.
+pub fn output_extension(template_path: &str) -> String {
+    let file_name = Path::new(template_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let stem = file_name
+        .strip_suffix(".tera")
+        .unwrap_or(file_name.as_str());
+    match Path::new(stem).extension() {
+        Some(extension) => extension.to_string_lossy().to_string(),
+        None => "txt".to_string(),
+    }
+}
+
+/// Returns the variables of a scan that are available to templates
+pub(crate) fn scan_context(
+    timestamp: DateTime<Utc>,
+    summary: &ImpactsSummary,
+    estimated_inventory: &EstimatedInventory,
+) -> tera::Context {
+    let mut context = tera::Context::new();
+    context.insert("summary", summary);
+    context.insert("resources", &estimated_inventory.impacting_resources);
+    context.insert("aws_region", &summary.aws_regions());
+    context.insert(
+        "generated_at",
+        &timestamp.to_rfc3339_opts(SecondsFormat::Secs, true),
+    );
+    context.insert("cloud_scanner_version", &crate::get_version());
+    context
+}
+
+/// Renders a template with the given variables
+pub(crate) fn render_context(template: &str, context: &tera::Context) -> Result<String> {
+    // Autoescaping is disabled: templates produce arbitrary text formats, not only HTML
+    Tera::one_off(template, context, false).map_err(|e| {
+        // Tera errors carry the useful message in their source
+        let mut message = e.to_string();
+        let mut source = std::error::Error::source(&e);
+        while let Some(cause) = source {
+            message.push_str(&format!(": {}", cause));
+            source = cause.source();
+        }
+        anyhow::anyhow!(message)
+    })
+}
+
+/// Renders a scan with the content of a template
+pub fn render(
+    template: &str,
+    timestamp: DateTime<Utc>,
+    summary: &ImpactsSummary,
+    estimated_inventory: &EstimatedInventory,
+) -> Result<String> {
+    render_context(
+        template,
+        &scan_context(timestamp, summary, estimated_inventory),
+    )
+}
+
+/// Renders a scan with the template located at `template_path`
+pub fn render_file(
+    template_path: &str,
+    summary: &ImpactsSummary,
+    estimated_inventory: &EstimatedInventory,
+) -> Result<String> {
+    let template = std::fs::read_to_string(template_path)
+        .with_context(|| format!("Cannot read template {}", template_path))?;
+    render(&template, Utc::now(), summary, estimated_inventory)
+        .with_context(|| format!("Cannot render template {}", template_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+    use crate::model::{CloudProvider, CloudResource, ResourceDetails};
+    use crate::usage_location::UsageLocation;
+    use chrono::TimeZone;
+
+    #[test]
+    fn extension_is_deduced_from_template_name() {
+        assert_eq!("md", output_extension("templates/wiki.md.tera"));
+        assert_eq!("csv", output_extension("custom.csv"));
+        assert_eq!("txt", output_extension("report.tera"));
+    }
+
+    #[test]
+    fn render_summary_and_resources() {
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![CloudResourceWithImpacts {
+                cloud_resource: CloudResource {
+                    provider: CloudProvider::AWS,
+                    account_id: None,
+                    id: "inst-1".to_string(),
+                    location: UsageLocation::try_from("eu-west-3").unwrap(),
+                    resource_details: ResourceDetails::Instance {
+                        instance_type: "m6g.xlarge".to_string(),
+                        usage: None,
+                    },
+                    tags: Vec::new(),
+                    relationships: Vec::new(),
+                },
+                impacts_values: Some(ImpactsValues {
+                    gwp_use_kgco2eq: 0.5,
+                    ..Default::default()
+                }),
+                impacts_duration_hours: 1.0,
+                cost: None,
+            }],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let template = "{{ aws_region }} {{ generated_at }}\n{% for r in resources %}|{{ r.cloud_resource.id }}|{{ r.impacts_values.gwp_use_kgco2eq }}|\n{% endfor %}total: {{ summary.gwp_use_kgco2eq }}";
+        let timestamp = Utc.with_ymd_and_hms(2024, 4, 12, 10, 15, 0).unwrap();
+
+        let rendered = render(template, timestamp, &summary, &estimated_inventory).unwrap();
+        assert_eq!(
+            "eu-west-3 2024-04-12T10:15:00Z\n|inst-1|0.5|\ntotal: 0.5",
+            rendered
+        );
+        assert!(render(
+            "{{ unknown_variable }}",
+            timestamp,
+            &summary,
+            &estimated_inventory
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! Tenants of the standalone server, so that one deployment can be shared by several teams.
+//!
+//! A tenant is identified by its API keys. Its requests scan its own account (by assuming its role), in the regions it is allowed to scan. Its jobs are only visible to the tenant, and its metrics are labelled with its name.
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::aws_cloud_provider::AwsAccount;
+
+/// A tenant of the server
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TenantConfig {
+    /// Name of the tenant, added as `tenant` label to its metrics
+    pub name: String,
+    pub api_keys: Vec<String>,
+    /// Account scanned for the tenant (with the credentials of the server when none)
+    pub account: Option<AwsAccount>,
+    /// Regions the tenant can scan (any region when empty)
+    #[serde(default)]
+    pub allowed_regions: Vec<String>,
+}
+
+impl TenantConfig {
+    pub fn allows_region(&self, aws_region: &str) -> bool {
+        self.allowed_regions.is_empty() || self.allowed_regions.iter().any(|r| r == aws_region)
+    }
+
+    /// Returns the account scanned for a request of the tenant.
+    ///
+    /// Fails when a region is not allowed, or when the request selects another account than the one of the tenant.
+    pub fn scan_account(
+        &self,
+        aws_regions: &[&str],
+        account_id: Option<&str>,
+        role_arn: Option<&str>,
+    ) -> Result<Option<&AwsAccount>> {
+        if let Some(region) = aws_regions.iter().find(|r| !self.allows_region(r)) {
+            anyhow::bail!("Tenant {} cannot scan region {}", self.name, region);
+        }
+        let selects_another_account = match &self.account {
+            Some(account) => {
+                account_id.is_some_and(|id| id != account.account_id)
+                    || role_arn.is_some_and(|arn| arn != account.role_arn)
+            }
+            None => account_id.is_some() || role_arn.is_some(),
+        };
+        if selects_another_account {
+            anyhow::bail!("Tenant {} can only scan its own account", self.name);
+        }
+        Ok(self.account.as_ref())
+    }
+}
+
+/// Returns the account scanned for a request: the account of its tenant, or the account of the allow-list selected by the request (if any)
+pub fn select_account<'a>(
+    tenant: Option<&'a TenantConfig>,
+    allowed: &'a [AwsAccount],
+    aws_regions: &[&str],
+    account_id: Option<&str>,
+    role_arn: Option<&str>,
+) -> Result<Option<&'a AwsAccount>> {
+    match tenant {
+        Some(tenant) => tenant.scan_account(aws_regions, account_id, role_arn),
+        None => AwsAccount::select(allowed, account_id, role_arn),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tenants_scan_their_account_in_allowed_regions() {
+        let account = AwsAccount {
+            account_id: "111111111111".to_string(),
+            role_arn: "arn:aws:iam::111111111111:role/cloud-scanner".to_string(),
+            external_id: None,
+        };
+        let tenant = TenantConfig {
+            name: "team-a".to_string(),
+            api_keys: vec!["key-a".to_string()],
+            account: Some(account.clone()),
+            allowed_regions: vec!["eu-west-3".to_string()],
+        };
+        assert_eq!(
+            Some(&account),
+            tenant.scan_account(&["eu-west-3"], None, None).unwrap()
+        );
+        assert_eq!(
+            Some(&account),
+            tenant
+                .scan_account(&["eu-west-3"], Some("111111111111"), None)
+                .unwrap()
+        );
+        assert!(tenant.scan_account(&["eu-west-1"], None, None).is_err());
+        assert!(tenant
+            .scan_account(&["eu-west-3", "eu-west-1"], None, None)
+            .is_err());
+        assert!(tenant
+            .scan_account(&["eu-west-3"], Some("222222222222"), None)
+            .is_err());
+
+        let server_credentials = TenantConfig {
+            account: None,
+            allowed_regions: Vec::new(),
+            ..tenant
+        };
+        assert_eq!(
+            None,
+            server_credentials
+                .scan_account(&["us-east-1"], None, None)
+                .unwrap()
+        );
+        assert!(server_credentials
+            .scan_account(&["us-east-1"], Some("111111111111"), None)
+            .is_err());
+    }
+}
@@ -0,0 +1,285 @@
+//! Inventories of the resources of a Terraform plan or state (json output of `terraform show -json`), so that the impacts of an infrastructure are estimated before it is deployed, like in the pipelines applying it.
+//!
+//! The resources of a plan are the resources planned after its apply (resources destroyed by the plan are not inventoried). Their CPU loads are unknown, and estimated with the default load of Boavizta API.
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+use crate::model::{
+    CloudProvider, CloudResource, CloudResourceTag, Inventory, ResourceDetails, StorageAttachment,
+    StorageUsage, DEFAULT_VOLUME_TYPE,
+};
+use crate::tag_filter::TagFilter;
+use crate::tag_normalization;
+use crate::usage_location::UsageLocation;
+
+/// Parses the resources of a plan or state (`terraform show -json`): instances, and with `include_block_storage` volumes (including the block devices of instances).
+///
+/// Resources are located in the region of the aws provider of the plan, in the region of their availability zone, or in `default_region` otherwise.
+pub fn parse_terraform_json(
+    content: &str,
+    default_region: &str,
+    tags: &[String],
+    include_block_storage: bool,
+) -> Result<Inventory> {
+    let json: Value = serde_json::from_str(content)
+        .context("Expected the json output of terraform show -json")?;
+    let root_module = json
+        .pointer("/planned_values/root_module")
+        .or_else(|| json.pointer("/values/root_module"))
+        .context(
+            "Expected the json output of terraform show -json (no planned_values nor values)",
+        )?;
+    let provider_region = json
+        .pointer("/configuration/provider_config/aws/expressions/region/constant_value")
+        .and_then(Value::as_str);
+    let filter = TagFilter::parse_all(tags)?;
+
+    let mut managed_resources = Vec::new();
+    collect_module_resources(root_module, &mut managed_resources);
+    let mut resources = Vec::new();
+    for resource in managed_resources {
+        let values = &resource["values"];
+        let address = resource["address"].as_str().unwrap_or_default();
+        let region = provider_region
+            .map(str::to_string)
+            .or_else(|| availability_zone_region(values))
+            .unwrap_or_else(|| default_region.to_string());
+        let location = UsageLocation::try_from(region.as_str())
+            .with_context(|| format!("Unsupported region of {}", address))?;
+        let tags = tag_normalization::normalize(resource_tags(values));
+        if !filter.matches(&tags) {
+            debug!("Filtered {} (tags do not match)", address);
+            continue;
+        }
+        let resource_with = |id: String, resource_details: ResourceDetails| CloudResource {
+            provider: CloudProvider::AWS,
+            account_id: None,
+            id,
+            location: location.clone(),
+            resource_details,
+            tags: tags.clone(),
+            relationships: Vec::new(),
+        };
+        match resource["type"].as_str() {
+            Some("aws_instance") => {
+                let Some(instance_type) = values["instance_type"].as_str() else {
+                    warn!("Ignoring instance {} (unknown instance type)", address);
+                    continue;
+                };
+                resources.push(resource_with(
+                    address.to_string(),
+                    ResourceDetails::Instance {
+                        instance_type: instance_type.to_string(),
+                        usage: None,
+                    },
+                ));
+                if include_block_storage {
+                    for (device, volume) in block_devices(values) {
+                        let id = format!("{}.{}", address, device);
+                        if let Some(details) = volume_details(volume, "volume_type", "volume_size")
+                        {
+                            let attached = StorageAttachment {
+                                instance_id: address.to_string(),
+                            };
+                            resources.push(resource_with(id, with_attachment(details, attached)));
+                        }
+                    }
+                }
+            }
+            Some("aws_ebs_volume") if include_block_storage => {
+                match volume_details(values, "type", "size") {
+                    Some(details) => resources.push(resource_with(address.to_string(), details)),
+                    None => warn!("Ignoring volume {} (unknown size)", address),
+                }
+            }
+            _ => debug!("Ignoring {} (not an estimated kind of resource)", address),
+        }
+    }
+    Ok(Inventory {
+        resources,
+        execution_statistics: None,
+    })
+}
+
+/// Reads the resources of a plan or state from a json file (output of `terraform show -json`), see [parse_terraform_json]
+pub fn read_terraform_json(
+    path: &str,
+    default_region: &str,
+    tags: &[String],
+    include_block_storage: bool,
+) -> Result<Inventory> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read Terraform plan or state {}", path))?;
+    parse_terraform_json(&content, default_region, tags, include_block_storage)
+        .with_context(|| format!("Cannot parse Terraform plan or state {}", path))
+}
+
+/// Collects the managed resources (not the data sources) of a module and of its child modules
+fn collect_module_resources<'a>(module: &'a Value, resources: &mut Vec<&'a Value>) {
+    if let Some(module_resources) = module["resources"].as_array() {
+        resources.extend(
+            module_resources
+                .iter()
+                .filter(|resource| resource["mode"].as_str() != Some("data")),
+        );
+    }
+    if let Some(child_modules) = module["child_modules"].as_array() {
+        for child_module in child_modules {
+            collect_module_resources(child_module, resources);
+        }
+    }
+}
+
+/// Returns the region of the availability zone of a resource (like eu-west-3 for eu-west-3a), known once it is deployed
+fn availability_zone_region(values: &Value) -> Option<String> {
+    let zone = values["availability_zone"].as_str()?;
+    let region = zone.trim_end_matches(|c: char| c.is_ascii_lowercase());
+    (!region.is_empty()).then(|| region.to_string())
+}
+
+/// Returns the tags of a resource, including the default tags of the provider when they are known
+fn resource_tags(values: &Value) -> Vec<CloudResourceTag> {
+    let tags = values["tags_all"]
+        .as_object()
+        .or_else(|| values["tags"].as_object());
+    tags.map(|tags| {
+        tags.iter()
+            .map(|(key, value)| CloudResourceTag {
+                key: key.clone(),
+                value: value.as_str().map(str::to_string),
+            })
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Returns the root and EBS block devices of an instance, with the name of their device
+fn block_devices(values: &Value) -> Vec<(String, &Value)> {
+    let mut devices = Vec::new();
+    if let Some(root_devices) = values["root_block_device"].as_array() {
+        devices.extend(
+            root_devices
+                .iter()
+                .map(|device| ("root_block_device".to_string(), device)),
+        );
+    }
+    if let Some(ebs_devices) = values["ebs_block_device"].as_array() {
+        for (index, device) in ebs_devices.iter().enumerate() {
+            let name = device["device_name"]
+                .as_str()
+                .map(|name| format!("ebs_block_device[{}]", name))
+                .unwrap_or_else(|| format!("ebs_block_device[{}]", index));
+            devices.push((name, device));
+        }
+    }
+    devices
+}
+
+/// Returns the details of a volume of known size (`None` when its size is only known after apply)
+fn volume_details(values: &Value, type_key: &str, size_key: &str) -> Option<ResourceDetails> {
+    let size_gb = values[size_key].as_i64()?;
+    let storage_type = values[type_key].as_str().unwrap_or(DEFAULT_VOLUME_TYPE);
+    Some(ResourceDetails::BlockStorage {
+        storage_type: storage_type.to_string(),
+        usage: Some(StorageUsage {
+            size_gb: size_gb as i32,
+            usage_duration_seconds: 3600,
+        }),
+        attached_instances: None,
+    })
+}
+
+fn with_attachment(details: ResourceDetails, attachment: StorageAttachment) -> ResourceDetails {
+    match details {
+        ResourceDetails::BlockStorage {
+            storage_type,
+            usage,
+            ..
+        } => ResourceDetails::BlockStorage {
+            storage_type,
+            usage,
+            attached_instances: Some(vec![attachment]),
+        },
+        details => details,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAN: &str = r#"{
+        "format_version": "1.2",
+        "planned_values": {
+            "root_module": {
+                "resources": [
+                    {"address": "aws_instance.web[0]", "mode": "managed", "type": "aws_instance", "name": "web", "index": 0,
+                     "values": {"instance_type": "m6g.xlarge", "tags": {"team": "web"}, "tags_all": null,
+                                "root_block_device": [{"volume_size": 20, "volume_type": "gp3"}], "ebs_block_device": []}},
+                    {"address": "aws_ebs_volume.data", "mode": "managed", "type": "aws_ebs_volume", "name": "data",
+                     "values": {"size": 100, "type": null, "tags": null}},
+                    {"address": "data.aws_ami.debian", "mode": "data", "type": "aws_ami", "name": "debian", "values": {}}
+                ],
+                "child_modules": [
+                    {"address": "module.batch", "resources": [
+                        {"address": "module.batch.aws_instance.worker", "mode": "managed", "type": "aws_instance", "name": "worker",
+                         "values": {"instance_type": "c5.large", "tags": {"team": "batch"}}},
+                        {"address": "module.batch.aws_s3_bucket.logs", "mode": "managed", "type": "aws_s3_bucket", "name": "logs", "values": {}}
+                    ]}
+                ]
+            }
+        },
+        "configuration": {"provider_config": {"aws": {"name": "aws", "expressions": {"region": {"constant_value": "eu-west-3"}}}}}
+    }"#;
+
+    #[test]
+    fn plans_are_inventoried() {
+        let inventory = parse_terraform_json(PLAN, "us-east-1", &[], false).unwrap();
+        let ids: Vec<&str> = inventory.resources.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(
+            vec!["aws_instance.web[0]", "module.batch.aws_instance.worker"],
+            ids
+        );
+        assert_eq!("eu-west-3", inventory.resources[0].location.aws_region);
+        assert_eq!(
+            Some("m6g.xlarge".to_string()),
+            inventory.resources[0].resource_details.resource_type()
+        );
+        assert_eq!("team", inventory.resources[0].tags[0].key);
+
+        let inventory = parse_terraform_json(PLAN, "us-east-1", &[], true).unwrap();
+        let volumes: Vec<&CloudResource> = inventory
+            .resources
+            .iter()
+            .filter(|r| r.resource_details.kind() == "BlockStorage")
+            .collect();
+        assert_eq!(2, volumes.len());
+        assert_eq!("aws_instance.web[0].root_block_device", volumes[0].id);
+        assert_eq!(
+            Some("gp3".to_string()),
+            volumes[0].resource_details.resource_type()
+        );
+        assert_eq!(
+            Some("gp2".to_string()),
+            volumes[1].resource_details.resource_type()
+        );
+
+        let filtered =
+            parse_terraform_json(PLAN, "us-east-1", &["team=batch".to_string()], false).unwrap();
+        assert_eq!(1, filtered.resources.len());
+    }
+
+    #[test]
+    fn states_are_located_by_availability_zone() {
+        let state = r#"{"format_version": "1.0", "values": {"root_module": {"resources": [
+            {"address": "aws_instance.app", "mode": "managed", "type": "aws_instance", "name": "app",
+             "values": {"instance_type": "t3.micro", "availability_zone": "eu-central-1b", "tags": {}}},
+            {"address": "aws_instance.other", "mode": "managed", "type": "aws_instance", "name": "other",
+             "values": {"instance_type": "t3.micro", "availability_zone": null}}
+        ]}}}"#;
+        let inventory = parse_terraform_json(state, "eu-west-1", &[], false).unwrap();
+        assert_eq!("eu-central-1", inventory.resources[0].location.aws_region);
+        assert_eq!("eu-west-1", inventory.resources[1].location.aws_region);
+        assert!(parse_terraform_json(r#"{"resources": []}"#, "eu-west-1", &[], false).is_err());
+    }
+}
@@ -0,0 +1,313 @@
+//! A module to rank the resources of an estimated inventory by impact, to list the top emitters of a scan.
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::impact_provider::{CloudResourceWithImpacts, ImpactsValues};
+use crate::model::EstimatedInventory;
+use crate::units::OutputUnits;
+
+/// The impact used to rank resources
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RankingCriterion {
+    /// Global warming potential (manufacture and use)
+    Gwp,
+    GwpManufacture,
+    GwpUse,
+    /// Primary energy (manufacture and use)
+    Pe,
+    PeManufacture,
+    PeUse,
+    /// Abiotic resources depletion potential (manufacture and use)
+    Adp,
+    AdpManufacture,
+    AdpUse,
+}
+
+impl RankingCriterion {
+    const ALL: [RankingCriterion; 9] = [
+        RankingCriterion::Gwp,
+        RankingCriterion::GwpManufacture,
+        RankingCriterion::GwpUse,
+        RankingCriterion::Pe,
+        RankingCriterion::PeManufacture,
+        RankingCriterion::PeUse,
+        RankingCriterion::Adp,
+        RankingCriterion::AdpManufacture,
+        RankingCriterion::AdpUse,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RankingCriterion::Gwp => "gwp",
+            RankingCriterion::GwpManufacture => "gwp_manufacture",
+            RankingCriterion::GwpUse => "gwp_use",
+            RankingCriterion::Pe => "pe",
+            RankingCriterion::PeManufacture => "pe_manufacture",
+            RankingCriterion::PeUse => "pe_use",
+            RankingCriterion::Adp => "adp",
+            RankingCriterion::AdpManufacture => "adp_manufacture",
+            RankingCriterion::AdpUse => "adp_use",
+        }
+    }
+
+    pub fn unit(&self) -> &'static str {
+        match self {
+            RankingCriterion::Gwp | RankingCriterion::GwpManufacture | RankingCriterion::GwpUse => {
+                "kgCO2eq"
+            }
+            RankingCriterion::Pe | RankingCriterion::PeManufacture | RankingCriterion::PeUse => {
+                "MJ"
+            }
+            RankingCriterion::Adp | RankingCriterion::AdpManufacture | RankingCriterion::AdpUse => {
+                "kgSbeq"
+            }
+        }
+    }
+
+    /// Returns the value of the criterion for the impacts of a resource
+    pub fn value(&self, impacts: &ImpactsValues) -> f64 {
+        match self {
+            RankingCriterion::Gwp => impacts.gwp_manufacture_kgco2eq + impacts.gwp_use_kgco2eq,
+            RankingCriterion::GwpManufacture => impacts.gwp_manufacture_kgco2eq,
+            RankingCriterion::GwpUse => impacts.gwp_use_kgco2eq,
+            RankingCriterion::Pe => impacts.pe_manufacture_megajoules + impacts.pe_use_megajoules,
+            RankingCriterion::PeManufacture => impacts.pe_manufacture_megajoules,
+            RankingCriterion::PeUse => impacts.pe_use_megajoules,
+            RankingCriterion::Adp => impacts.adp_manufacture_kgsbeq + impacts.adp_use_kgsbeq,
+            RankingCriterion::AdpManufacture => impacts.adp_manufacture_kgsbeq,
+            RankingCriterion::AdpUse => impacts.adp_use_kgsbeq,
+        }
+    }
+}
+
+impl fmt::Display for RankingCriterion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for RankingCriterion {
+    type Err = String;
+
+    fn from_str(criterion: &str) -> Result<Self, Self::Err> {
+        RankingCriterion::ALL
+            .into_iter()
+            .find(|c| c.name() == criterion)
+            .ok_or_else(|| {
+                let names: Vec<&str> = RankingCriterion::ALL.iter().map(|c| c.name()).collect();
+                format!(
+                    "Unknown criterion {} (expected one of {})",
+                    criterion,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// A resource of the top emitters
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct RankedResource {
+    /// Position in the ranking, starting at 1
+    pub rank: usize,
+    pub resource_id: String,
+    pub resource_kind: String,
+    pub resource_type: Option<String>,
+    pub aws_region: String,
+    /// Value of the ranking criterion
+    pub value: f64,
+    /// Share of the value in the total of the assessed resources (between 0 and 1)
+    pub share: f64,
+}
+
+/// The resources with the highest impacts, in descending order
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct TopEmitters {
+    pub criterion: RankingCriterion,
+    pub unit: String,
+    pub resources: Vec<RankedResource>,
+}
+
+impl TopEmitters {
+    /// Sets the unit of the values (the resources being converted to these units)
+    pub fn with_units(mut self, units: &OutputUnits) -> Self {
+        self.unit = match self.criterion {
+            RankingCriterion::Gwp | RankingCriterion::GwpManufacture | RankingCriterion::GwpUse => {
+                units.gwp.symbol().to_string()
+            }
+            RankingCriterion::Pe | RankingCriterion::PeManufacture | RankingCriterion::PeUse => {
+                units.energy.symbol().to_string()
+            }
+            RankingCriterion::Adp | RankingCriterion::AdpManufacture | RankingCriterion::AdpUse => {
+                self.unit
+            }
+        };
+        self
+    }
+}
+
+/// Returns the `count` assessed resources with the highest value of the criterion
+pub fn top_emitters(
+    estimated_inventory: &EstimatedInventory,
+    criterion: RankingCriterion,
+    count: usize,
+) -> TopEmitters {
+    let mut values: Vec<(&CloudResourceWithImpacts, f64)> = estimated_inventory
+        .impacting_resources
+        .iter()
+        .filter_map(|r| Some((r, criterion.value(r.impacts_values.as_ref()?))))
+        .collect();
+    let total: f64 = values.iter().map(|(_, value)| value).sum();
+    values.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let resources = values
+        .into_iter()
+        .take(count)
+        .enumerate()
+        .map(|(i, (resource, value))| {
+            let cloud_resource = &resource.cloud_resource;
+            RankedResource {
+                rank: i + 1,
+                resource_id: cloud_resource.id.clone(),
+                resource_kind: cloud_resource.resource_details.kind().to_string(),
+                resource_type: cloud_resource.resource_details.resource_type(),
+                aws_region: cloud_resource.location.aws_region.clone(),
+                value,
+                share: if total > 0.0 { value / total } else { 0.0 },
+            }
+        })
+        .collect();
+    TopEmitters {
+        criterion,
+        unit: criterion.unit().to_string(),
+        resources,
+    }
+}
+
+/// Formats the top emitters as a text table
+pub fn to_table(top: &TopEmitters) -> String {
+    let header = [
+        "Rank".to_string(),
+        "Resource".to_string(),
+        "Kind".to_string(),
+        "Type".to_string(),
+        "Region".to_string(),
+        format!("{} ({})", top.criterion, top.unit),
+        "Share".to_string(),
+    ];
+    let rows: Vec<[String; 7]> = top
+        .resources
+        .iter()
+        .map(|r| {
+            [
+                r.rank.to_string(),
+                r.resource_id.clone(),
+                r.resource_kind.clone(),
+                r.resource_type.clone().unwrap_or_default(),
+                r.aws_region.clone(),
+                format!("{:.6}", r.value),
+                format!("{:.1}%", r.share * 100.0),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.clone().map(|h| h.chars().count());
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        let _ = writeln!(table, "{}", cells.join("  ").trim_end());
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{CloudProvider, CloudResource, ResourceDetails};
+    use crate::usage_location::UsageLocation;
+
+    fn instance(
+        id: &str,
+        gwp_use_kgco2eq: f64,
+        pe_use_megajoules: f64,
+    ) -> CloudResourceWithImpacts {
+        CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: id.to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details: ResourceDetails::Instance {
+                    instance_type: "t3.micro".to_string(),
+                    usage: None,
+                },
+                tags: Vec::new(),
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues {
+                gwp_use_kgco2eq,
+                pe_use_megajoules,
+                ..Default::default()
+            }),
+            impacts_duration_hours: 1.0,
+            cost: None,
+        }
+    }
+
+    #[test]
+    fn rank_resources_by_criterion() {
+        let mut not_assessed = instance("not-assessed", 0.0, 0.0);
+        not_assessed.impacts_values = None;
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![
+                instance("small", 1.0, 30.0),
+                instance("big", 3.0, 10.0),
+                not_assessed,
+                instance("medium", 2.0, 20.0),
+                instance("tiny", 0.0, 0.0),
+            ],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+
+        let top = top_emitters(&estimated_inventory, RankingCriterion::Gwp, 2);
+        let ids: Vec<&str> = top
+            .resources
+            .iter()
+            .map(|r| r.resource_id.as_str())
+            .collect();
+        assert_eq!(vec!["big", "medium"], ids);
+        assert_eq!(2, top.resources[1].rank);
+        assert_eq!(0.5, top.resources[0].share);
+
+        let top = top_emitters(&estimated_inventory, RankingCriterion::PeUse, 1);
+        assert_eq!("small", top.resources[0].resource_id);
+        assert_eq!("MJ", top.unit);
+
+        let table = to_table(&top);
+        assert_eq!(
+            "Rank  Resource  Kind      Type      Region     pe_use (MJ)  Share\n1     small     Instance  t3.micro  eu-west-3  30.000000    50.0%\n",
+            table
+        );
+    }
+
+    #[test]
+    fn parse_criterion() {
+        assert_eq!(
+            RankingCriterion::GwpUse,
+            "gwp_use".parse::<RankingCriterion>().unwrap()
+        );
+        assert!("co2".parse::<RankingCriterion>().is_err());
+    }
+}
@@ -0,0 +1,504 @@
+//! Trend analysis of the history of scans (`trend`): the daily impacts of the scans of a result store over a window are analysed per criterion and per value of a tag, to report whether they increase, their weekly seasonality and the sudden jumps worth investigating.
+//!
+//! Daily impacts are [aggregated](crate::scan_aggregation) from the scans and extrapolated to the whole day, the days without scans being left out of the analysis.
+//! - The direction is given by the linear regression of the daily impacts, as the change of the fitted impacts over the window relative to their mean.
+//! - A weekly seasonality is reported when the detrended impacts of the days of the week differ by more than 20 % of their mean, over at least two weeks.
+//! - A jump is a day whose impacts differ from the mean of the previous 7 days (at least 3) by more than 3 standard deviations and 20 %.
+use chrono::{DateTime, Datelike, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::allocation::AllocationRules;
+use crate::impact_provider::ImpactsValues;
+use crate::model::EstimatedInventory;
+use crate::scan_aggregation::{aggregate_scans, AggregatedImpacts, AggregationPeriod, CoveredScan};
+use crate::top_emitters::RankingCriterion;
+
+/// Change over the window (in percent of the mean) below which impacts are stable
+const STABLE_CHANGE_PERCENT: f64 = 5.0;
+
+/// Minimum number of days of impacts to look for a weekly seasonality
+const SEASONALITY_MIN_DAYS: usize = 14;
+
+/// Minimum difference between the days of the week (in percent of the mean) of a weekly seasonality
+const SEASONALITY_MIN_AMPLITUDE_PERCENT: f64 = 20.0;
+
+/// Number of previous days a day is compared to when looking for jumps
+const JUMP_BASELINE_DAYS: usize = 7;
+
+/// Number of standard deviations of the previous days a jump exceeds
+const JUMP_STANDARD_DEVIATIONS: f64 = 3.0;
+
+/// Minimum change (in percent of the mean of the previous days) of a jump
+const JUMP_MIN_CHANGE_PERCENT: f64 = 20.0;
+
+/// Direction of the impacts over the window
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrendDirection {
+    Increasing,
+    Decreasing,
+    Stable,
+}
+
+impl TrendDirection {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrendDirection::Increasing => "increasing",
+            TrendDirection::Decreasing => "decreasing",
+            TrendDirection::Stable => "stable",
+        }
+    }
+}
+
+/// A weekly seasonality of the impacts
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct WeeklySeasonality {
+    /// Day of the week with the highest impacts, like `Mon`
+    pub peak_weekday: String,
+    /// Day of the week with the lowest impacts
+    pub trough_weekday: String,
+    /// Difference between the peak and the trough, in percent of the mean
+    pub amplitude_percent: f64,
+}
+
+/// A day whose impacts jumped compared to the previous days
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Jump {
+    pub day: DateTime<Utc>,
+    pub value: f64,
+    /// Mean of the previous days
+    pub expected_value: f64,
+    pub change_percent: f64,
+}
+
+/// The trend of a criterion for a value of the tag
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CriterionTrend {
+    pub criterion: RankingCriterion,
+    pub unit: String,
+    /// Value of the grouping tag, none for the resources without it (or without grouping tag)
+    pub tag_value: Option<String>,
+    /// Number of days with scans
+    pub days: usize,
+    /// Mean of the daily impacts
+    pub daily_mean: f64,
+    /// Change of the fitted daily impacts over the window, in percent of the mean
+    pub change_percent: f64,
+    pub direction: TrendDirection,
+    pub seasonality: Option<WeeklySeasonality>,
+    pub jumps: Vec<Jump>,
+}
+
+/// The trends of the impacts of a window
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TrendReport {
+    pub window_start: DateTime<Utc>,
+    pub window_end: DateTime<Utc>,
+    /// Key of the grouping tag
+    pub tag_key: Option<String>,
+    pub trends: Vec<CriterionTrend>,
+}
+
+/// Returns the impacts of an aggregated row
+fn impacts(row: &AggregatedImpacts) -> ImpactsValues {
+    ImpactsValues {
+        adp_manufacture_kgsbeq: row.adp_manufacture_kgsbeq,
+        adp_use_kgsbeq: row.adp_use_kgsbeq,
+        pe_manufacture_megajoules: row.pe_manufacture_megajoules,
+        pe_use_megajoules: row.pe_use_megajoules,
+        gwp_manufacture_kgco2eq: row.gwp_manufacture_kgco2eq,
+        gwp_use_kgco2eq: row.gwp_use_kgco2eq,
+        raw_data: None,
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        0.0
+    } else {
+        values.iter().sum::<f64>() / values.len() as f64
+    }
+}
+
+/// Returns the slope and intercept of the linear regression of the values over their x
+fn linear_regression(points: &[(f64, f64)]) -> (f64, f64) {
+    let x_mean = mean(&points.iter().map(|(x, _)| *x).collect::<Vec<f64>>());
+    let y_mean = mean(&points.iter().map(|(_, y)| *y).collect::<Vec<f64>>());
+    let covariance: f64 = points
+        .iter()
+        .map(|(x, y)| (x - x_mean) * (y - y_mean))
+        .sum();
+    let variance: f64 = points.iter().map(|(x, _)| (x - x_mean).powi(2)).sum();
+    let slope = if variance > 0.0 {
+        covariance / variance
+    } else {
+        0.0
+    };
+    (slope, y_mean - slope * x_mean)
+}
+
+/// Returns the weekly seasonality of detrended daily values, if any
+fn weekly_seasonality(days: &[(DateTime<Utc>, f64)], daily_mean: f64) -> Option<WeeklySeasonality> {
+    if days.len() < SEASONALITY_MIN_DAYS || daily_mean <= 0.0 {
+        return None;
+    }
+    let mut weekdays: BTreeMap<u32, Vec<f64>> = BTreeMap::new();
+    for (day, value) in days.iter() {
+        weekdays
+            .entry(day.weekday().num_days_from_monday())
+            .or_default()
+            .push(*value);
+    }
+    if weekdays.len() < 7 {
+        return None;
+    }
+    let weekday_means: Vec<(u32, f64)> = weekdays
+        .iter()
+        .map(|(weekday, values)| (*weekday, mean(values)))
+        .collect();
+    let (peak, peak_mean) = weekday_means
+        .iter()
+        .copied()
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    let (trough, trough_mean) = weekday_means
+        .iter()
+        .copied()
+        .min_by(|a, b| a.1.total_cmp(&b.1))?;
+    let amplitude_percent = (peak_mean - trough_mean) / daily_mean * 100.0;
+    let weekday_name = |weekday: u32| {
+        chrono::Weekday::try_from(weekday as u8)
+            .map(|weekday| weekday.to_string())
+            .unwrap_or_default()
+    };
+    (amplitude_percent >= SEASONALITY_MIN_AMPLITUDE_PERCENT).then(|| WeeklySeasonality {
+        peak_weekday: weekday_name(peak),
+        trough_weekday: weekday_name(trough),
+        amplitude_percent,
+    })
+}
+
+/// Returns the days whose values jumped compared to the previous days
+fn jumps(days: &[(DateTime<Utc>, f64)]) -> Vec<Jump> {
+    days.iter()
+        .enumerate()
+        .filter_map(|(index, (day, value))| {
+            let previous: Vec<f64> = days[index.saturating_sub(JUMP_BASELINE_DAYS)..index]
+                .iter()
+                .map(|(_, value)| *value)
+                .collect();
+            if previous.len() < 3 {
+                return None;
+            }
+            let expected_value = mean(&previous);
+            let standard_deviation = (previous
+                .iter()
+                .map(|value| (value - expected_value).powi(2))
+                .sum::<f64>()
+                / previous.len() as f64)
+                .sqrt();
+            let change = value - expected_value;
+            let change_percent = change / expected_value * 100.0;
+            (expected_value > 0.0
+                && change.abs() > JUMP_STANDARD_DEVIATIONS * standard_deviation
+                && change_percent.abs() >= JUMP_MIN_CHANGE_PERCENT)
+                .then_some(Jump {
+                    day: *day,
+                    value: *value,
+                    expected_value,
+                    change_percent,
+                })
+        })
+        .collect()
+}
+
+/// Returns the trend of daily values (oldest first)
+fn criterion_trend(
+    criterion: RankingCriterion,
+    tag_value: Option<String>,
+    days: &[(DateTime<Utc>, f64)],
+) -> CriterionTrend {
+    let values: Vec<f64> = days.iter().map(|(_, value)| *value).collect();
+    let daily_mean = mean(&values);
+    let Some((first_day, _)) = days.first() else {
+        return CriterionTrend {
+            criterion,
+            unit: criterion.unit().to_string(),
+            tag_value,
+            days: 0,
+            daily_mean,
+            change_percent: 0.0,
+            direction: TrendDirection::Stable,
+            seasonality: None,
+            jumps: Vec::new(),
+        };
+    };
+    let day_index = |day: &DateTime<Utc>| (*day - *first_day).num_days() as f64;
+    let points: Vec<(f64, f64)> = days
+        .iter()
+        .map(|(day, value)| (day_index(day), *value))
+        .collect();
+    let (slope, intercept) = linear_regression(&points);
+    let window_days = points.last().map(|(x, _)| *x).unwrap_or_default();
+    let change_percent = if daily_mean > 0.0 {
+        slope * window_days / daily_mean * 100.0
+    } else {
+        0.0
+    };
+    let direction = if change_percent >= STABLE_CHANGE_PERCENT {
+        TrendDirection::Increasing
+    } else if change_percent <= -STABLE_CHANGE_PERCENT {
+        TrendDirection::Decreasing
+    } else {
+        TrendDirection::Stable
+    };
+    // The seasonality is looked for around the trend
+    let detrended: Vec<(DateTime<Utc>, f64)> = days
+        .iter()
+        .map(|(day, value)| {
+            (
+                *day,
+                value - (slope * day_index(day) + intercept) + daily_mean,
+            )
+        })
+        .collect();
+    CriterionTrend {
+        criterion,
+        unit: criterion.unit().to_string(),
+        tag_value,
+        days: days.len(),
+        daily_mean,
+        change_percent,
+        direction,
+        seasonality: weekly_seasonality(&detrended, daily_mean),
+        jumps: jumps(days),
+    }
+}
+
+/// Returns the trends of the criteria of the scans (with their estimated inventory) between two dates, per value of the tag `tag_key` (if any, with the shared resources allocated by the allocation rules)
+pub fn build_trend_report(
+    scans: &[(CoveredScan, EstimatedInventory)],
+    window_start: DateTime<Utc>,
+    window_end: DateTime<Utc>,
+    criteria: &[RankingCriterion],
+    tag_key: Option<&str>,
+    allocations: &AllocationRules,
+) -> TrendReport {
+    let rows = aggregate_scans(
+        scans,
+        window_start,
+        window_end,
+        AggregationPeriod::Daily,
+        tag_key,
+        allocations,
+        true,
+    );
+    // Days without scans have no row, groups without resources on a day with scans are at 0
+    let covered_days: BTreeSet<DateTime<Utc>> = rows.iter().map(|row| row.period_start).collect();
+    let mut groups: BTreeMap<Option<String>, BTreeMap<DateTime<Utc>, Vec<ImpactsValues>>> =
+        BTreeMap::new();
+    for row in rows.iter() {
+        groups
+            .entry(row.tag_value.clone())
+            .or_default()
+            .entry(row.period_start)
+            .or_default()
+            .push(impacts(row));
+    }
+    let mut trends = Vec::new();
+    for criterion in criteria.iter() {
+        for (tag_value, group_days) in groups.iter() {
+            let days: Vec<(DateTime<Utc>, f64)> = covered_days
+                .iter()
+                .map(|day| {
+                    let value = group_days
+                        .get(day)
+                        .map(|impacts| impacts.iter().map(|impacts| criterion.value(impacts)).sum())
+                        .unwrap_or_default();
+                    (*day, value)
+                })
+                .collect();
+            trends.push(criterion_trend(*criterion, tag_value.clone(), &days));
+        }
+    }
+    TrendReport {
+        window_start,
+        window_end,
+        tag_key: tag_key.map(String::from),
+        trends,
+    }
+}
+
+impl TrendReport {
+    /// Number of jumps of all the trends
+    pub fn number_of_jumps(&self) -> usize {
+        self.trends.iter().map(|trend| trend.jumps.len()).sum()
+    }
+
+    /// Returns the trends as text, a line per criterion and value of the tag followed by its jumps
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "Trends from {} to {}:\n",
+            self.window_start.to_rfc3339(),
+            self.window_end.to_rfc3339()
+        );
+        for trend in self.trends.iter() {
+            let group = match (&self.tag_key, &trend.tag_value) {
+                (Some(tag_key), Some(tag_value)) => format!(" [{}={}]", tag_key, tag_value),
+                (Some(tag_key), None) => format!(" [no {}]", tag_key),
+                (None, _) => String::new(),
+            };
+            let seasonality = trend
+                .seasonality
+                .as_ref()
+                .map(|seasonality| {
+                    format!(
+                        ", weekly seasonality (peak on {}, trough on {}, {:.1} % of the mean)",
+                        seasonality.peak_weekday,
+                        seasonality.trough_weekday,
+                        seasonality.amplitude_percent
+                    )
+                })
+                .unwrap_or_default();
+            let _ = writeln!(
+                text,
+                "  {}{}: {} ({:+.1} % over the window), {:.3} {} a day on average over {} days{}",
+                trend.criterion,
+                group,
+                trend.direction.name(),
+                trend.change_percent,
+                trend.daily_mean,
+                trend.unit,
+                trend.days,
+                seasonality
+            );
+            for jump in trend.jumps.iter() {
+                let _ = writeln!(
+                    text,
+                    "    jump on {}: {:.3} {} instead of about {:.3} ({:+.1} %)",
+                    jump.day.format("%Y-%m-%d"),
+                    jump.value,
+                    trend.unit,
+                    jump.expected_value,
+                    jump.change_percent
+                );
+            }
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::CloudResourceWithImpacts;
+    use crate::model::{CloudProvider, CloudResource, ResourceDetails};
+    use crate::usage_location::UsageLocation;
+
+    fn day(index: i64) -> DateTime<Utc> {
+        // A Monday
+        "2024-04-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap() + chrono::Duration::days(index)
+    }
+
+    /// Daily scans of a bucket emitting a GWP per day
+    fn daily_scans(gwp: &[f64]) -> Vec<(CoveredScan, EstimatedInventory)> {
+        gwp.iter()
+            .enumerate()
+            .map(|(index, gwp_use_kgco2eq)| {
+                (
+                    CoveredScan {
+                        scan_id: index as i64,
+                        timestamp: day(index as i64),
+                        duration_of_use_hours: 24.0,
+                        covered_from: day(index as i64),
+                        covered_until: day(index as i64 + 1),
+                    },
+                    EstimatedInventory {
+                        impacting_resources: vec![CloudResourceWithImpacts {
+                            cloud_resource: CloudResource {
+                                provider: CloudProvider::AWS,
+                                id: "bucket".to_string(),
+                                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                                resource_details: ResourceDetails::ObjectStorage,
+                                tags: Vec::new(),
+                                account_id: None,
+                                relationships: Vec::new(),
+                            },
+                            impacts_values: Some(ImpactsValues {
+                                gwp_use_kgco2eq: *gwp_use_kgco2eq,
+                                ..Default::default()
+                            }),
+                            impacts_duration_hours: 24.0,
+                            cost: None,
+                        }],
+                        execution_statistics: None,
+                        errors: Vec::new(),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn gwp_trend(gwp: &[f64]) -> CriterionTrend {
+        let scans = daily_scans(gwp);
+        let report = build_trend_report(
+            &scans,
+            day(0),
+            day(gwp.len() as i64),
+            &[RankingCriterion::Gwp],
+            None,
+            &AllocationRules::default(),
+        );
+        assert_eq!(1, report.trends.len());
+        report.trends[0].clone()
+    }
+
+    #[test]
+    fn increasing_impacts_are_detected() {
+        let gwp: Vec<f64> = (0..28).map(|index| 10.0 + index as f64 * 0.1).collect();
+        let trend = gwp_trend(&gwp);
+        assert_eq!(28, trend.days);
+        assert_eq!(TrendDirection::Increasing, trend.direction);
+        assert!((trend.change_percent - 2.7 / 11.35 * 100.0).abs() < 0.001);
+        assert_eq!(None, trend.seasonality);
+        assert!(trend.jumps.is_empty());
+
+        assert_eq!(TrendDirection::Stable, gwp_trend(&[10.0; 28]).direction);
+    }
+
+    #[test]
+    fn weekly_seasonality_is_detected() {
+        // Lower impacts on week ends
+        let gwp: Vec<f64> = (0..28)
+            .map(|index| if index % 7 >= 5 { 5.0 } else { 10.0 })
+            .collect();
+        let trend = gwp_trend(&gwp);
+        let seasonality = trend.seasonality.unwrap();
+        assert_eq!("Sat", seasonality.trough_weekday);
+        assert!(seasonality.amplitude_percent > 50.0);
+    }
+
+    #[test]
+    fn sudden_jumps_are_flagged() {
+        let mut gwp = vec![10.0, 10.5, 9.5, 10.0, 10.2, 9.8, 10.0, 10.1];
+        gwp.push(25.0);
+        let trend = gwp_trend(&gwp);
+        assert_eq!(1, trend.jumps.len());
+        let jump = &trend.jumps[0];
+        assert_eq!(day(8), jump.day);
+        assert_eq!(25.0, jump.value);
+        assert!(jump.change_percent > 100.0);
+
+        let report = TrendReport {
+            window_start: day(0),
+            window_end: day(9),
+            tag_key: None,
+            trends: vec![trend],
+        };
+        assert_eq!(1, report.number_of_jumps());
+        assert!(report
+            .to_text()
+            .contains("jump on 2024-04-09: 25.000 kgCO2eq"));
+    }
+}
@@ -0,0 +1,526 @@
+//! An interactive terminal UI to explore the results of a scan: resources sorted and filtered by impact, details of a resource (with the raw data of Boavizta API) and export of the current view.
+//!
+//! Keys: `↑`/`↓` select a resource, `Enter` shows its details, `s` changes the sort criterion, `r` reverses the order, `/` edits the filter, `e` / `c` export the current view as json / CSV, `q` quits.
+use anyhow::{Context, Result};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Cell, Clear, Paragraph, Row, Table, TableState, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::cmp::Ordering;
+
+use crate::csv_exporter;
+use crate::impact_provider::CloudResourceWithImpacts;
+use crate::model::EstimatedInventory;
+use crate::top_emitters::RankingCriterion;
+
+/// Files written by the exports of the current view (in the working directory)
+const JSON_EXPORT: &str = "cloud-scanner-view.json";
+const CSV_EXPORT: &str = "cloud-scanner-view.csv";
+
+/// Impacts displayed for each resource (manufacture and use)
+const TOTALS: [RankingCriterion; 3] = [
+    RankingCriterion::Gwp,
+    RankingCriterion::Pe,
+    RankingCriterion::Adp,
+];
+
+/// How the resources are sorted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortKey {
+    Impact(RankingCriterion),
+    Id,
+}
+
+impl SortKey {
+    const ALL: [SortKey; 4] = [
+        SortKey::Impact(RankingCriterion::Gwp),
+        SortKey::Impact(RankingCriterion::Pe),
+        SortKey::Impact(RankingCriterion::Adp),
+        SortKey::Id,
+    ];
+
+    fn next(&self) -> SortKey {
+        let position = SortKey::ALL.iter().position(|key| key == self).unwrap_or(0);
+        SortKey::ALL[(position + 1) % SortKey::ALL.len()]
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SortKey::Impact(criterion) => criterion.name(),
+            SortKey::Id => "id",
+        }
+    }
+
+    fn compare(&self, a: &CloudResourceWithImpacts, b: &CloudResourceWithImpacts) -> Ordering {
+        match self {
+            SortKey::Impact(criterion) => impact(a, *criterion).total_cmp(&impact(b, *criterion)),
+            SortKey::Id => a.cloud_resource.id.cmp(&b.cloud_resource.id),
+        }
+    }
+}
+
+/// Returns the impact of a resource (0 when it was not assessed)
+fn impact(resource: &CloudResourceWithImpacts, criterion: RankingCriterion) -> f64 {
+    resource
+        .impacts_values
+        .as_ref()
+        .map_or(0.0, |impacts| criterion.value(impacts))
+}
+
+/// What the keys act on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// The table of resources
+    Browse,
+    /// Editing the filter
+    Filter,
+    /// The details of the selected resource, scrolled by this number of lines
+    Details { scroll: u16 },
+}
+
+/// State of the explorer, updated by the keys pressed
+pub struct Explorer {
+    resources: Vec<CloudResourceWithImpacts>,
+    pub sort: SortKey,
+    pub descending: bool,
+    /// Case insensitive text searched in the id, type, region, account and tags (`key=value`) of resources
+    pub filter: String,
+    pub mode: Mode,
+    /// Position of the selected resource in the current view
+    pub selected: usize,
+    /// Message of the last action (like an export)
+    pub status: String,
+    pub quit: bool,
+}
+
+impl Explorer {
+    pub fn new(estimated_inventory: EstimatedInventory) -> Self {
+        Explorer {
+            resources: estimated_inventory.impacting_resources,
+            sort: SortKey::Impact(RankingCriterion::Gwp),
+            descending: true,
+            filter: String::new(),
+            mode: Mode::Browse,
+            selected: 0,
+            status: String::new(),
+            quit: false,
+        }
+    }
+
+    /// Returns the resources of the current view: filtered and sorted
+    pub fn view(&self) -> Vec<&CloudResourceWithImpacts> {
+        let filter = self.filter.to_lowercase();
+        let mut view: Vec<&CloudResourceWithImpacts> = self
+            .resources
+            .iter()
+            .filter(|resource| filter.is_empty() || searched_text(resource).contains(&filter))
+            .collect();
+        view.sort_by(|a, b| {
+            let ordering = self.sort.compare(a, b);
+            if self.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        view
+    }
+
+    /// Returns the current view as an estimated inventory, like the json output of the estimate command
+    pub fn view_as_inventory(&self) -> EstimatedInventory {
+        EstimatedInventory {
+            impacting_resources: self.view().into_iter().cloned().collect(),
+            execution_statistics: None,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Updates the state with a key pressed
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        let count = self.view().len();
+        match self.mode {
+            Mode::Browse => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+                KeyCode::Down | KeyCode::Char('j') => {
+                    self.selected = (self.selected + 1).min(count.saturating_sub(1))
+                }
+                KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+                KeyCode::Home => self.selected = 0,
+                KeyCode::End => self.selected = count.saturating_sub(1),
+                KeyCode::Enter if count > 0 => self.mode = Mode::Details { scroll: 0 },
+                KeyCode::Char('s') => {
+                    self.sort = self.sort.next();
+                    self.selected = 0;
+                }
+                KeyCode::Char('r') => {
+                    self.descending = !self.descending;
+                    self.selected = 0;
+                }
+                KeyCode::Char('/') => self.mode = Mode::Filter,
+                KeyCode::Char('e') => self.status = self.export_json(JSON_EXPORT),
+                KeyCode::Char('c') => self.status = self.export_csv(CSV_EXPORT),
+                _ => {}
+            },
+            Mode::Filter => {
+                match key.code {
+                    KeyCode::Enter => self.mode = Mode::Browse,
+                    KeyCode::Esc => {
+                        self.filter.clear();
+                        self.mode = Mode::Browse;
+                    }
+                    KeyCode::Backspace => {
+                        self.filter.pop();
+                    }
+                    KeyCode::Char(c) => self.filter.push(c),
+                    _ => {}
+                }
+                self.selected = 0;
+            }
+            Mode::Details { scroll } => match key.code {
+                KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q') => self.mode = Mode::Browse,
+                KeyCode::Down | KeyCode::Char('j') => {
+                    self.mode = Mode::Details {
+                        scroll: scroll.saturating_add(1),
+                    }
+                }
+                KeyCode::Up | KeyCode::Char('k') => {
+                    self.mode = Mode::Details {
+                        scroll: scroll.saturating_sub(1),
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+
+    fn export_json(&self, path: &str) -> String {
+        let exported = serde_json::to_string_pretty(&self.view_as_inventory())
+            .context("Cannot format the view as json")
+            .and_then(|json| std::fs::write(path, json).context("Cannot write the export"));
+        export_status(exported, path, self.view().len())
+    }
+
+    fn export_csv(&self, path: &str) -> String {
+        let exported = csv_exporter::get_resources_csv(&self.view_as_inventory())
+            .and_then(|csv| std::fs::write(path, csv).context("Cannot write the export"));
+        export_status(exported, path, self.view().len())
+    }
+}
+
+fn export_status(exported: Result<()>, path: &str, count: usize) -> String {
+    match exported {
+        Ok(()) => format!("Exported {} resources to {}", count, path),
+        Err(e) => format!("{:#}", e),
+    }
+}
+
+/// Returns the text searched by the filter (lower case)
+fn searched_text(resource: &CloudResourceWithImpacts) -> String {
+    let cloud_resource = &resource.cloud_resource;
+    let mut text = vec![
+        cloud_resource.id.clone(),
+        cloud_resource.resource_details.kind().to_string(),
+        cloud_resource
+            .resource_details
+            .resource_type()
+            .unwrap_or_default(),
+        cloud_resource.location.aws_region.clone(),
+        cloud_resource.account_id.clone().unwrap_or_default(),
+    ];
+    text.extend(
+        cloud_resource
+            .tags
+            .iter()
+            .map(|tag| format!("{}={}", tag.key, tag.value.as_deref().unwrap_or_default())),
+    );
+    text.join(" ").to_lowercase()
+}
+
+fn format_value(value: f64) -> String {
+    if value == 0.0 || value.abs() >= 100.0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.3}", value)
+    }
+}
+
+fn render(frame: &mut Frame, explorer: &Explorer) {
+    let [header, body, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let view = explorer.view();
+
+    let order = if explorer.descending { "↓" } else { "↑" };
+    let total_gwp: f64 = view
+        .iter()
+        .map(|resource| impact(resource, RankingCriterion::Gwp))
+        .sum();
+    frame.render_widget(
+        Line::from(format!(
+            " {} resources, {} kgCO2eq | sort: {} {} | filter: {}",
+            view.len(),
+            format_value(total_gwp),
+            explorer.sort.name(),
+            order,
+            if explorer.filter.is_empty() && explorer.mode != Mode::Filter {
+                "-"
+            } else {
+                &explorer.filter
+            }
+        ))
+        .bold(),
+        header,
+    );
+
+    let rows = view.iter().map(|resource| {
+        let cloud_resource = &resource.cloud_resource;
+        let impacts = TOTALS.map(|criterion| match &resource.impacts_values {
+            Some(impacts) => format_value(criterion.value(impacts)),
+            None => "-".to_string(),
+        });
+        Row::new(vec![
+            Cell::from(cloud_resource.id.clone()),
+            Cell::from(
+                cloud_resource
+                    .resource_details
+                    .resource_type()
+                    .unwrap_or_else(|| cloud_resource.resource_details.kind().to_string()),
+            ),
+            Cell::from(cloud_resource.location.aws_region.clone()),
+            Cell::from(impacts[0].clone()),
+            Cell::from(impacts[1].clone()),
+            Cell::from(impacts[2].clone()),
+        ])
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Fill(2),
+            Constraint::Fill(1),
+            Constraint::Length(16),
+            Constraint::Length(14),
+            Constraint::Length(14),
+            Constraint::Length(14),
+        ],
+    )
+    .header(
+        Row::new(vec![
+            "Id",
+            "Type",
+            "Region",
+            "GWP (kgCO2eq)",
+            "PE (MJ)",
+            "ADP (kgSbeq)",
+        ])
+        .bold(),
+    )
+    .block(Block::bordered().title(" Resources "))
+    .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    let mut state = TableState::default().with_selected(Some(explorer.selected));
+    frame.render_stateful_widget(table, body, &mut state);
+
+    let help = match explorer.mode {
+        Mode::Browse => "↑↓ select  Enter details  s sort  r reverse  / filter  e export json  c export csv  q quit",
+        Mode::Filter => "type to filter  Enter apply  Esc clear",
+        Mode::Details { .. } => "↑↓ scroll  Esc back",
+    };
+    let footer_text = if explorer.status.is_empty() {
+        help.to_string()
+    } else {
+        format!("{} | {}", explorer.status, help)
+    };
+    frame.render_widget(Line::from(footer_text).dim(), footer);
+
+    if let (Mode::Details { scroll }, Some(resource)) = (explorer.mode, view.get(explorer.selected))
+    {
+        let area = popup_area(body);
+        let details = serde_json::to_string_pretty(resource).unwrap_or_else(|e| e.to_string());
+        frame.render_widget(Clear, area);
+        frame.render_widget(
+            Paragraph::new(details)
+                .block(Block::bordered().title(format!(" {} ", resource.cloud_resource.id)))
+                .wrap(Wrap { trim: false })
+                .scroll((scroll, 0)),
+            area,
+        );
+    }
+}
+
+/// Returns the area of the details of a resource, centered over the table
+fn popup_area(area: Rect) -> Rect {
+    let [_, vertical, _] = Layout::vertical([
+        Constraint::Percentage(5),
+        Constraint::Percentage(90),
+        Constraint::Percentage(5),
+    ])
+    .areas(area);
+    let [_, popup, _] = Layout::horizontal([
+        Constraint::Percentage(10),
+        Constraint::Percentage(80),
+        Constraint::Percentage(10),
+    ])
+    .areas(vertical);
+    popup
+}
+
+fn run(terminal: &mut DefaultTerminal, explorer: &mut Explorer) -> Result<()> {
+    while !explorer.quit {
+        terminal
+            .draw(|frame| render(frame, explorer))
+            .context("Cannot draw the terminal UI")?;
+        if let Event::Key(key) = event::read().context("Cannot read the keys pressed")? {
+            if key.kind == KeyEventKind::Press {
+                explorer.handle_key(key);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Explores an estimated inventory in the terminal, until the user quits
+pub fn explore(estimated_inventory: EstimatedInventory) -> Result<()> {
+    let mut explorer = Explorer::new(estimated_inventory);
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, &mut explorer);
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::ImpactsValues;
+    use crate::model::{CloudProvider, CloudResource, CloudResourceTag, ResourceDetails};
+    use crate::usage_location::UsageLocation;
+
+    fn resource(id: &str, team: &str, gwp: f64, pe: f64) -> CloudResourceWithImpacts {
+        CloudResourceWithImpacts {
+            cloud_resource: CloudResource {
+                provider: CloudProvider::AWS,
+                account_id: None,
+                id: id.to_string(),
+                location: UsageLocation::try_from("eu-west-3").unwrap(),
+                resource_details: ResourceDetails::Instance {
+                    instance_type: "m5.large".to_string(),
+                    usage: None,
+                },
+                tags: vec![CloudResourceTag {
+                    key: "team".to_string(),
+                    value: Some(team.to_string()),
+                }],
+                relationships: Vec::new(),
+            },
+            impacts_values: Some(ImpactsValues {
+                gwp_use_kgco2eq: gwp,
+                pe_use_megajoules: pe,
+                ..Default::default()
+            }),
+            impacts_duration_hours: 1.0,
+            cost: None,
+        }
+    }
+
+    fn explorer() -> Explorer {
+        Explorer::new(EstimatedInventory {
+            impacting_resources: vec![
+                resource("i-1", "web", 1.0, 30.0),
+                resource("i-2", "infra", 3.0, 10.0),
+                resource("i-3", "web", 2.0, 20.0),
+            ],
+            execution_statistics: None,
+            errors: Vec::new(),
+        })
+    }
+
+    fn ids(explorer: &Explorer) -> Vec<&str> {
+        explorer
+            .view()
+            .iter()
+            .map(|r| r.cloud_resource.id.as_str())
+            .collect()
+    }
+
+    fn press(explorer: &mut Explorer, codes: &[KeyCode]) {
+        for code in codes {
+            explorer.handle_key(KeyEvent::from(*code));
+        }
+    }
+
+    #[test]
+    fn resources_are_sorted_by_impact_or_id() {
+        let mut explorer = explorer();
+        assert_eq!(vec!["i-2", "i-3", "i-1"], ids(&explorer));
+        press(&mut explorer, &[KeyCode::Char('s')]);
+        assert_eq!(SortKey::Impact(RankingCriterion::Pe), explorer.sort);
+        assert_eq!(vec!["i-1", "i-3", "i-2"], ids(&explorer));
+        press(
+            &mut explorer,
+            &[KeyCode::Char('s'), KeyCode::Char('s'), KeyCode::Char('r')],
+        );
+        assert_eq!(SortKey::Id, explorer.sort);
+        assert_eq!(vec!["i-1", "i-2", "i-3"], ids(&explorer));
+    }
+
+    #[test]
+    fn resources_are_filtered_by_text() {
+        let mut explorer = explorer();
+        press(
+            &mut explorer,
+            &[
+                KeyCode::Char('/'),
+                KeyCode::Char('W'),
+                KeyCode::Char('e'),
+                KeyCode::Char('b'),
+                KeyCode::Enter,
+            ],
+        );
+        assert_eq!("Web", explorer.filter);
+        assert_eq!(Mode::Browse, explorer.mode);
+        assert_eq!(vec!["i-3", "i-1"], ids(&explorer));
+        assert_eq!(2, explorer.view_as_inventory().impacting_resources.len());
+        press(&mut explorer, &[KeyCode::Char('/'), KeyCode::Esc]);
+        assert_eq!(3, explorer.view().len());
+    }
+
+    #[test]
+    fn keys_select_and_show_resources() {
+        let mut explorer = explorer();
+        press(
+            &mut explorer,
+            &[KeyCode::Down, KeyCode::Down, KeyCode::Down],
+        );
+        assert_eq!(2, explorer.selected);
+        press(&mut explorer, &[KeyCode::Enter, KeyCode::Down]);
+        assert_eq!(Mode::Details { scroll: 1 }, explorer.mode);
+        press(&mut explorer, &[KeyCode::Esc]);
+        assert_eq!(Mode::Browse, explorer.mode);
+        assert!(!explorer.quit);
+        press(&mut explorer, &[KeyCode::Char('q')]);
+        assert!(explorer.quit);
+    }
+
+    #[test]
+    fn the_view_and_details_are_rendered() {
+        let backend = ratatui::backend::TestBackend::new(120, 30);
+        let mut terminal = ratatui::Terminal::new(backend).unwrap();
+        let mut explorer = explorer();
+        terminal.draw(|frame| render(frame, &explorer)).unwrap();
+        let screen = format!("{:?}", terminal.backend().buffer());
+        assert!(screen.contains("3 resources, 6.000 kgCO2eq"), "{}", screen);
+        assert!(screen.contains("i-2"));
+
+        press(&mut explorer, &[KeyCode::Enter]);
+        terminal.draw(|frame| render(frame, &explorer)).unwrap();
+        let screen = format!("{:?}", terminal.backend().buffer());
+        assert!(
+            screen.contains("\"instance_type\": \"m5.large\""),
+            "{}",
+            screen
+        );
+    }
+}
@@ -0,0 +1,271 @@
+//! Units of the impacts in the outputs (`--units`): GWP in gCO2eq, kgCO2eq or tCO2eq, primary energy in MJ or kWh.
+//!
+//! Impacts are estimated in kgCO2eq and MJ (the default units), then converted when writing the results: values of the json results, metrics and reports use the chosen units (recorded in the metadata of json results), their field and metric names keep the names of the default units.
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::impact_provider::{ImpactsSummary, ImpactsValues};
+use crate::model::EstimatedInventory;
+
+/// Unit of the global warming potential
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum GwpUnit {
+    #[serde(rename = "gCO2eq")]
+    Grams,
+    #[default]
+    #[serde(rename = "kgCO2eq")]
+    Kilograms,
+    #[serde(rename = "tCO2eq")]
+    Tonnes,
+}
+
+impl GwpUnit {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            GwpUnit::Grams => "gCO2eq",
+            GwpUnit::Kilograms => "kgCO2eq",
+            GwpUnit::Tonnes => "tCO2eq",
+        }
+    }
+
+    /// Returns the value in this unit of a value in kgCO2eq
+    pub fn from_kgco2eq(&self, kgco2eq: f64) -> f64 {
+        match self {
+            GwpUnit::Grams => kgco2eq * 1000.0,
+            GwpUnit::Kilograms => kgco2eq,
+            GwpUnit::Tonnes => kgco2eq / 1000.0,
+        }
+    }
+}
+
+/// Unit of the primary energy
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum EnergyUnit {
+    #[default]
+    #[serde(rename = "MJ")]
+    Megajoules,
+    #[serde(rename = "kWh")]
+    KilowattHours,
+}
+
+impl EnergyUnit {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            EnergyUnit::Megajoules => "MJ",
+            EnergyUnit::KilowattHours => "kWh",
+        }
+    }
+
+    /// Returns the value in this unit of a value in MJ
+    pub fn from_megajoules(&self, megajoules: f64) -> f64 {
+        match self {
+            EnergyUnit::Megajoules => megajoules,
+            EnergyUnit::KilowattHours => megajoules / 3.6,
+        }
+    }
+}
+
+/// Units of the impacts of results
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct OutputUnits {
+    pub gwp: GwpUnit,
+    pub energy: EnergyUnit,
+}
+
+impl fmt::Display for OutputUnits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}, {}", self.gwp.symbol(), self.energy.symbol())
+    }
+}
+
+/// Units accepted by `--units` (case insensitive, `CO2e` being accepted for `CO2eq`)
+pub const UNITS: [&str; 5] = ["gCO2eq", "kgCO2eq", "tCO2eq", "MJ", "kWh"];
+
+impl OutputUnits {
+    /// Parses units like `tCO2eq` or `kWh`, the units not given being the default units
+    pub fn parse(units: &[String]) -> Result<OutputUnits> {
+        let mut output_units = OutputUnits::default();
+        for unit in units {
+            match unit.trim().to_lowercase().trim_end_matches("eq") {
+                "gco2" | "gco2e" => output_units.gwp = GwpUnit::Grams,
+                "kgco2" | "kgco2e" => output_units.gwp = GwpUnit::Kilograms,
+                "tco2" | "tco2e" => output_units.gwp = GwpUnit::Tonnes,
+                "mj" => output_units.energy = EnergyUnit::Megajoules,
+                "kwh" => output_units.energy = EnergyUnit::KilowattHours,
+                _ => anyhow::bail!("Unknown unit {} (expecting {})", unit, UNITS.join(", ")),
+            }
+        }
+        Ok(output_units)
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == OutputUnits::default()
+    }
+
+    fn convert_values(&self, impacts: &mut ImpactsValues) {
+        impacts.gwp_manufacture_kgco2eq = self.gwp.from_kgco2eq(impacts.gwp_manufacture_kgco2eq);
+        impacts.gwp_use_kgco2eq = self.gwp.from_kgco2eq(impacts.gwp_use_kgco2eq);
+        impacts.pe_manufacture_megajoules = self
+            .energy
+            .from_megajoules(impacts.pe_manufacture_megajoules);
+        impacts.pe_use_megajoules = self.energy.from_megajoules(impacts.pe_use_megajoules);
+    }
+
+    /// Converts the impacts of the resources (estimated in the default units) to these units
+    pub fn convert_inventory(
+        &self,
+        mut estimated_inventory: EstimatedInventory,
+    ) -> EstimatedInventory {
+        for resource in estimated_inventory.impacting_resources.iter_mut() {
+            if let Some(impacts) = resource.impacts_values.as_mut() {
+                self.convert_values(impacts);
+            }
+        }
+        estimated_inventory
+    }
+
+    /// Converts the impacts of a summary (with its regions, groups, SCI score and cost comparison) to these units
+    pub fn convert_summary(&self, mut summary: ImpactsSummary) -> ImpactsSummary {
+        let gwp = |value: &mut f64| *value = self.gwp.from_kgco2eq(*value);
+        let energy = |value: &mut f64| *value = self.energy.from_megajoules(*value);
+        gwp(&mut summary.gwp_manufacture_kgco2eq);
+        gwp(&mut summary.gwp_use_kgco2eq);
+        energy(&mut summary.pe_manufacture_megajoules);
+        energy(&mut summary.pe_use_megajoules);
+        for region in summary.regions.iter_mut() {
+            gwp(&mut region.gwp_manufacture_kgco2eq);
+            gwp(&mut region.gwp_use_kgco2eq);
+            energy(&mut region.pe_manufacture_megajoules);
+            energy(&mut region.pe_use_megajoules);
+        }
+        for group in summary.groups.iter_mut() {
+            gwp(&mut group.gwp_manufacture_kgco2eq);
+            gwp(&mut group.gwp_use_kgco2eq);
+            energy(&mut group.pe_manufacture_megajoules);
+            energy(&mut group.pe_use_megajoules);
+        }
+        if let Some(sci) = summary.sci.as_mut() {
+            gwp(&mut sci.sci_kgco2eq_per_unit);
+        }
+        if let Some(cost) = summary.cost.as_mut() {
+            if let Some(gwp_per_currency_unit) = cost.gwp_kgco2eq_per_currency_unit.as_mut() {
+                gwp(gwp_per_currency_unit);
+            }
+        }
+        summary
+    }
+
+    /// Converts impacts of resources written in these units back to the default units (like scans read to be compared)
+    pub fn revert_inventory(
+        &self,
+        mut estimated_inventory: EstimatedInventory,
+    ) -> EstimatedInventory {
+        let to_kgco2eq = self.gwp.from_kgco2eq(1.0);
+        let to_megajoules = self.energy.from_megajoules(1.0);
+        for resource in estimated_inventory.impacting_resources.iter_mut() {
+            if let Some(impacts) = resource.impacts_values.as_mut() {
+                impacts.gwp_manufacture_kgco2eq /= to_kgco2eq;
+                impacts.gwp_use_kgco2eq /= to_kgco2eq;
+                impacts.pe_manufacture_megajoules /= to_megajoules;
+                impacts.pe_use_megajoules /= to_megajoules;
+            }
+        }
+        estimated_inventory
+    }
+
+    /// Adds the units to the descriptions (HELP) of the GWP and primary energy metrics, whose values are in these units
+    pub fn describe_metrics(&self, metrics: &str) -> String {
+        if self.is_default() {
+            return metrics.to_string();
+        }
+        metrics
+            .lines()
+            .map(|line| {
+                let unit = match line.strip_prefix("# HELP ") {
+                    Some(help) if help.contains("_kgco2eq") => Some(self.gwp.symbol()),
+                    Some(help) if help.contains("_megajoules") => Some(self.energy.symbol()),
+                    _ => None,
+                };
+                match unit {
+                    Some(unit) => format!("{} (in {})\n", line.trim_end_matches('.'), unit),
+                    None => format!("{}\n", line),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impact_provider::CloudResourceWithImpacts;
+    use crate::model::{CloudProvider, CloudResource, ResourceDetails};
+    use crate::usage_location::UsageLocation;
+
+    #[test]
+    fn units_are_parsed_and_impacts_converted() {
+        let units = OutputUnits::parse(&["tCO2e".to_string(), "kwh".to_string()]).unwrap();
+        assert_eq!(GwpUnit::Tonnes, units.gwp);
+        assert_eq!(EnergyUnit::KilowattHours, units.energy);
+        assert_eq!("tCO2eq, kWh", units.to_string());
+        assert!(OutputUnits::parse(&[]).unwrap().is_default());
+        assert!(OutputUnits::parse(&["lbCO2".to_string()]).is_err());
+
+        let estimated_inventory = EstimatedInventory {
+            impacting_resources: vec![CloudResourceWithImpacts {
+                cloud_resource: CloudResource {
+                    provider: CloudProvider::AWS,
+                    account_id: None,
+                    id: "i-1".to_string(),
+                    location: UsageLocation::try_from("eu-west-3").unwrap(),
+                    resource_details: ResourceDetails::ObjectStorage,
+                    tags: Vec::new(),
+                    relationships: Vec::new(),
+                },
+                impacts_values: Some(ImpactsValues {
+                    gwp_use_kgco2eq: 2000.0,
+                    pe_use_megajoules: 36.0,
+                    adp_use_kgsbeq: 1.0,
+                    ..Default::default()
+                }),
+                impacts_duration_hours: 1.0,
+                cost: None,
+            }],
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        let summary = ImpactsSummary::new(
+            "eu-west-3".to_string(),
+            "FRA".to_string(),
+            &estimated_inventory,
+            1.0,
+        );
+        let summary = units.convert_summary(summary);
+        assert_eq!(2.0, summary.gwp_use_kgco2eq);
+        assert_eq!(2.0, summary.regions[0].gwp_use_kgco2eq);
+        assert_eq!(10.0, summary.pe_use_megajoules);
+
+        let converted = units.convert_inventory(estimated_inventory);
+        let impacts = converted.impacting_resources[0]
+            .impacts_values
+            .clone()
+            .unwrap();
+        assert_eq!(2.0, impacts.gwp_use_kgco2eq);
+        assert_eq!(10.0, impacts.pe_use_megajoules);
+        assert_eq!(1.0, impacts.adp_use_kgsbeq);
+        let reverted = units.revert_inventory(converted);
+        let impacts = reverted.impacting_resources[0]
+            .impacts_values
+            .clone()
+            .unwrap();
+        assert_eq!(2000.0, impacts.gwp_use_kgco2eq);
+        assert_eq!(36.0, impacts.pe_use_megajoules);
+
+        assert_eq!(
+            "# HELP boavizta_gwp_use_kgco2eq Global Warming Potential of use (in tCO2eq)\n# HELP boavizta_pe_use_megajoules Energy consumed during use (in kWh)\n# HELP boavizta_adp_use_kgsbeq Abiotic resources depletion potential of use.\n",
+            units.describe_metrics("# HELP boavizta_gwp_use_kgco2eq Global Warming Potential of use.\n# HELP boavizta_pe_use_megajoules Energy consumed during use.\n# HELP boavizta_adp_use_kgsbeq Abiotic resources depletion potential of use.\n")
+        );
+    }
+}
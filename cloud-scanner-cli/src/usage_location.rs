@@ -1,8 +1,7 @@
 //! The location where cloud resources are running.
 
 use isocountry::CountryCode;
-use rocket_okapi::okapi::schemars;
-use rocket_okapi::okapi::schemars::JsonSchema;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -10,6 +9,8 @@ use thiserror::Error;
 pub enum RegionError {
     #[error("Unsupported region ({0})")]
     UnsupportedRegion(String),
+    #[error("Unknown country code ({0})")]
+    UnknownCountry(String),
 }
 
 ///  The location where cloud resources are running.
@@ -17,7 +18,7 @@ pub enum RegionError {
 /// TODO! the usage location should be abstracted and vendor specific implementation should be part of the cloud_provider model (region names are tied to a specific cloud provider)
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct UsageLocation {
-    /// The AWS region (like eu-west-1)
+    /// The AWS region (like eu-west-1), or the Azure or GCP region of Azure and GCP resources (like westeurope or europe-west1)
     pub aws_region: String,
     /// The 3-letters ISO country code corresponding to the country of the aws_region
     pub iso_country_code: String,
@@ -34,51 +35,172 @@ impl TryFrom<&str> for UsageLocation {
     type Error = RegionError;
 }
 
+impl UsageLocation {
+    /// Returns the location of a region that is not a cloud region (like the site of a data center), in the country of an ISO 3166 code (alpha-3 like FRA, or alpha-2 like FR)
+    pub fn of_country(region: &str, country_code: &str) -> Result<Self, RegionError> {
+        let code = country_code.trim().to_uppercase();
+        let cc = CountryCode::for_alpha3(&code)
+            .or_else(|_| CountryCode::for_alpha2(&code))
+            .map_err(|_| RegionError::UnknownCountry(String::from(country_code)))?;
+        Ok(UsageLocation {
+            aws_region: String::from(region),
+            iso_country_code: cc.alpha3().to_owned(),
+        })
+    }
+}
+
+/// The AWS regions supported by cloud scanner, with the country where they run
+pub const AWS_REGION_COUNTRIES: [(&str, CountryCode); 31] = [
+    ("af-south-1", CountryCode::ZAF),
+    ("ap-east-1", CountryCode::HKG),
+    ("ap-northeast-1", CountryCode::JPN),
+    ("ap-northeast-2", CountryCode::KOR),
+    ("ap-northeast-3", CountryCode::JPN),
+    ("ap-south-1", CountryCode::IND),
+    ("ap-south-2", CountryCode::IND),
+    ("ap-southeast-1", CountryCode::SGP),
+    ("ap-southeast-2", CountryCode::AUS),
+    ("ap-southeast-3", CountryCode::IDN),
+    ("ap-southeast-4", CountryCode::AUS),
+    ("ca-central-1", CountryCode::CAN),
+    ("ca-west-1", CountryCode::CAN),
+    ("cn-north-1", CountryCode::CHN),
+    ("cn-northwest-1", CountryCode::CHN),
+    ("eu-central-1", CountryCode::DEU),
+    ("eu-central-2", CountryCode::CHE),
+    ("eu-north-1", CountryCode::SWE),
+    ("eu-south-1", CountryCode::ITA),
+    ("eu-south-2", CountryCode::ESP),
+    ("eu-west-1", CountryCode::IRL),
+    ("eu-west-2", CountryCode::GBR),
+    ("eu-west-3", CountryCode::FRA),
+    ("il-central-1", CountryCode::ISR),
+    ("me-central-1", CountryCode::ARE),
+    ("me-south-1", CountryCode::BHR),
+    ("sa-east-1", CountryCode::BRA),
+    ("us-east-1", CountryCode::USA),
+    ("us-east-2", CountryCode::USA),
+    ("us-west-1", CountryCode::USA),
+    ("us-west-2", CountryCode::USA),
+];
+
+/// The Azure regions supported by cloud scanner, with the country where they run (their names do not overlap the names of AWS regions)
+pub const AZURE_REGION_COUNTRIES: [(&str, CountryCode); 49] = [
+    ("australiacentral", CountryCode::AUS),
+    ("australiacentral2", CountryCode::AUS),
+    ("australiaeast", CountryCode::AUS),
+    ("australiasoutheast", CountryCode::AUS),
+    ("brazilsouth", CountryCode::BRA),
+    ("brazilsoutheast", CountryCode::BRA),
+    ("canadacentral", CountryCode::CAN),
+    ("canadaeast", CountryCode::CAN),
+    ("centralindia", CountryCode::IND),
+    ("centralus", CountryCode::USA),
+    ("eastasia", CountryCode::HKG),
+    ("eastus", CountryCode::USA),
+    ("eastus2", CountryCode::USA),
+    ("francecentral", CountryCode::FRA),
+    ("francesouth", CountryCode::FRA),
+    ("germanynorth", CountryCode::DEU),
+    ("germanywestcentral", CountryCode::DEU),
+    ("israelcentral", CountryCode::ISR),
+    ("italynorth", CountryCode::ITA),
+    ("japaneast", CountryCode::JPN),
+    ("japanwest", CountryCode::JPN),
+    ("koreacentral", CountryCode::KOR),
+    ("koreasouth", CountryCode::KOR),
+    ("mexicocentral", CountryCode::MEX),
+    ("northcentralus", CountryCode::USA),
+    ("northeurope", CountryCode::IRL),
+    ("norwayeast", CountryCode::NOR),
+    ("norwaywest", CountryCode::NOR),
+    ("polandcentral", CountryCode::POL),
+    ("qatarcentral", CountryCode::QAT),
+    ("southafricanorth", CountryCode::ZAF),
+    ("southafricawest", CountryCode::ZAF),
+    ("southcentralus", CountryCode::USA),
+    ("southeastasia", CountryCode::SGP),
+    ("southindia", CountryCode::IND),
+    ("spaincentral", CountryCode::ESP),
+    ("swedencentral", CountryCode::SWE),
+    ("switzerlandnorth", CountryCode::CHE),
+    ("switzerlandwest", CountryCode::CHE),
+    ("uaecentral", CountryCode::ARE),
+    ("uaenorth", CountryCode::ARE),
+    ("uksouth", CountryCode::GBR),
+    ("ukwest", CountryCode::GBR),
+    ("westcentralus", CountryCode::USA),
+    ("westeurope", CountryCode::NLD),
+    ("westindia", CountryCode::IND),
+    ("westus", CountryCode::USA),
+    ("westus2", CountryCode::USA),
+    ("westus3", CountryCode::USA),
+];
+
+/// The GCP regions supported by cloud scanner, with the country where they run (their names do not overlap the names of AWS regions, like europe-west1 and eu-west-1)
+pub const GCP_REGION_COUNTRIES: [(&str, CountryCode); 41] = [
+    ("africa-south1", CountryCode::ZAF),
+    ("asia-east1", CountryCode::TWN),
+    ("asia-east2", CountryCode::HKG),
+    ("asia-northeast1", CountryCode::JPN),
+    ("asia-northeast2", CountryCode::JPN),
+    ("asia-northeast3", CountryCode::KOR),
+    ("asia-south1", CountryCode::IND),
+    ("asia-south2", CountryCode::IND),
+    ("asia-southeast1", CountryCode::SGP),
+    ("asia-southeast2", CountryCode::IDN),
+    ("australia-southeast1", CountryCode::AUS),
+    ("australia-southeast2", CountryCode::AUS),
+    ("europe-central2", CountryCode::POL),
+    ("europe-north1", CountryCode::FIN),
+    ("europe-southwest1", CountryCode::ESP),
+    ("europe-west1", CountryCode::BEL),
+    ("europe-west10", CountryCode::DEU),
+    ("europe-west12", CountryCode::ITA),
+    ("europe-west2", CountryCode::GBR),
+    ("europe-west3", CountryCode::DEU),
+    ("europe-west4", CountryCode::NLD),
+    ("europe-west6", CountryCode::CHE),
+    ("europe-west8", CountryCode::ITA),
+    ("europe-west9", CountryCode::FRA),
+    ("me-central1", CountryCode::QAT),
+    ("me-central2", CountryCode::SAU),
+    ("me-west1", CountryCode::ISR),
+    ("northamerica-northeast1", CountryCode::CAN),
+    ("northamerica-northeast2", CountryCode::CAN),
+    ("northamerica-south1", CountryCode::MEX),
+    ("southamerica-east1", CountryCode::BRA),
+    ("southamerica-west1", CountryCode::CHL),
+    ("us-central1", CountryCode::USA),
+    ("us-east1", CountryCode::USA),
+    ("us-east4", CountryCode::USA),
+    ("us-east5", CountryCode::USA),
+    ("us-south1", CountryCode::USA),
+    ("us-west1", CountryCode::USA),
+    ("us-west2", CountryCode::USA),
+    ("us-west3", CountryCode::USA),
+    ("us-west4", CountryCode::USA),
+];
+
 /// Converts AWS region as String into an ISO country code, returns FRA if not found
 ///
 /// TODO! : do not convert to FRA by default, should rather fail explicitly if region is not found.
 fn get_country_from_aws_region(aws_region: &str) -> Result<CountryCode, RegionError> {
-    let cc: CountryCode = match aws_region {
-        "af-south-1" => CountryCode::ZAF,
-        "ap-east-1" => CountryCode::HKG,
-        "ap-northeast-1" => CountryCode::JPN,
-        "ap-northeast-2" => CountryCode::KOR,
-        "ap-northeast-3" => CountryCode::JPN,
-        "ap-south-1" => CountryCode::IND,
-        "ap-south-2" => CountryCode::IND,
-        "ap-southeast-1" => CountryCode::SGP,
-        "ap-southeast-2" => CountryCode::AUS,
-        "ap-southeast-3" => CountryCode::IDN,
-        "ap-southeast-4" => CountryCode::AUS,
-        "ca-central-1" => CountryCode::CAN,
-        "ca-west-1" => CountryCode::CAN,
-        "cn-north-1" => CountryCode::CHN,
-        "cn-northwest-1" => CountryCode::CHN,
-        "eu-central-1" => CountryCode::DEU,
-        "eu-central-2" => CountryCode::CHE,
-        "eu-north-1" => CountryCode::SWE,
-        "eu-south-1" => CountryCode::ITA,
-        "eu-south-2" => CountryCode::ESP,
-        "eu-west-1" => CountryCode::IRL,
-        "eu-west-2" => CountryCode::GBR,
-        "eu-west-3" => CountryCode::FRA,
-        "il-central-1" => CountryCode::ISR,
-        "me-central-1" => CountryCode::ARE,
-        "me-south-1" => CountryCode::BHR,
-        "sa-east-1" => CountryCode::BRA,
-        "us-east-1" => CountryCode::USA,
-        "us-east-2" => CountryCode::USA,
-        "us-west-1" => CountryCode::USA,
-        "us-west-2" => CountryCode::USA,
-        _ => {
+    match AWS_REGION_COUNTRIES
+        .iter()
+        .chain(AZURE_REGION_COUNTRIES.iter())
+        .chain(GCP_REGION_COUNTRIES.iter())
+        .find(|(region, _)| *region == aws_region)
+    {
+        Some((_, cc)) => Ok(*cc),
+        None => {
             error!(
                 "Unsupported region: unable to match aws region [{}] to country code",
                 aws_region
             );
-            return Err(RegionError::UnsupportedRegion(String::from(aws_region)));
+            Err(RegionError::UnsupportedRegion(String::from(aws_region)))
         }
-    };
-    Ok(cc)
+    }
 }
 
 #[cfg(test)]
@@ -96,6 +218,9 @@ mod tests {
 
         let location = UsageLocation::try_from("eu-west-3").unwrap();
         assert_eq!("FRA", location.iso_country_code);
+
+        let location = UsageLocation::try_from("westeurope").unwrap();
+        assert_eq!("NLD", location.iso_country_code);
     }
 
     #[test]
@@ -111,4 +236,12 @@ mod tests {
         let res = UsageLocation::try_from("");
         assert!(res.is_err());
     }
+
+    #[test]
+    fn sites_are_located_by_their_country() {
+        let location = UsageLocation::of_country("paris-1", "fr").unwrap();
+        assert_eq!("paris-1", location.aws_region);
+        assert_eq!("FRA", location.iso_country_code);
+        assert!(UsageLocation::of_country("lab", "XXX").is_err());
+    }
 }
@@ -0,0 +1,186 @@
+//! A cache of the CPU loads of instances read from CloudWatch, by instance and window of the measure, for a short time to live (TTL) set once for the process.
+//!
+//! Back-to-back scans (like the scrapes of metrics of the standalone server) reuse the loads read by the last scans instead of paying again for the slow gathering of utilization metrics. Only measured loads are cached: instances without data points (likely stopped) are queried again by the next scan.
+//!
+//! The loads are also shared with other processes when the cache is shared (see [crate::shared_cache]).
+use std::collections::BTreeMap;
+#[cfg(feature = "shared-cache")]
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Seconds the CPU load of an instance is reused, unless set with [set_ttl]
+pub const DEFAULT_TTL_SECONDS: u64 = 60;
+
+static TTL_SECONDS: AtomicU64 = AtomicU64::new(DEFAULT_TTL_SECONDS);
+
+/// Sets the duration the CPU load of an instance is reused by the next scans (zero disables the cache)
+pub fn set_ttl(ttl: Duration) {
+    TTL_SECONDS.store(ttl.as_secs(), Ordering::Relaxed);
+}
+
+fn ttl() -> Duration {
+    Duration::from_secs(TTL_SECONDS.load(Ordering::Relaxed))
+}
+
+/// CPU loads, by instance id and window of the measure (in minutes), with the instant they were read
+struct CpuLoads {
+    entries: BTreeMap<(String, i64), (Instant, f64)>,
+}
+
+impl CpuLoads {
+    const fn new() -> Self {
+        CpuLoads {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the CPU load of an instance, if it was read less than `ttl` ago
+    fn get(&self, instance_id: &str, window_minutes: i64, ttl: Duration) -> Option<f64> {
+        self.entries
+            .get(&(instance_id.to_string(), window_minutes))
+            .filter(|(read_at, _)| read_at.elapsed() < ttl)
+            .map(|(_, cpu_load)| *cpu_load)
+    }
+
+    /// Caches the CPU load of an instance, and forgets the expired ones
+    fn insert(&mut self, instance_id: &str, window_minutes: i64, cpu_load: f64, ttl: Duration) {
+        self.insert_read_at(instance_id, window_minutes, cpu_load, Instant::now(), ttl);
+    }
+
+    /// Caches the CPU load of an instance read at an instant (like by another process), and forgets the expired ones
+    fn insert_read_at(
+        &mut self,
+        instance_id: &str,
+        window_minutes: i64,
+        cpu_load: f64,
+        read_at: Instant,
+        ttl: Duration,
+    ) {
+        if ttl.is_zero() {
+            return;
+        }
+        self.entries
+            .retain(|_, (read_at, _)| read_at.elapsed() < ttl);
+        self.entries.insert(
+            (instance_id.to_string(), window_minutes),
+            (read_at, cpu_load),
+        );
+    }
+}
+
+static CPU_LOADS: Mutex<CpuLoads> = Mutex::new(CpuLoads::new());
+
+/// Returns the CPU load of an instance on a window, read by a scan less than the TTL ago
+pub(crate) fn cached_cpu_load(instance_id: &str, window_minutes: i64) -> Option<f64> {
+    CPU_LOADS
+        .lock()
+        .unwrap()
+        .get(instance_id, window_minutes, ttl())
+}
+
+/// Caches the CPU load of an instance on a window (if the cache is enabled)
+pub(crate) fn cache_cpu_load(instance_id: &str, window_minutes: i64, cpu_load: f64) {
+    CPU_LOADS
+        .lock()
+        .unwrap()
+        .insert(instance_id, window_minutes, cpu_load, ttl());
+}
+
+/// A CPU load in the shared cache, with the time it was read
+#[cfg(feature = "shared-cache")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SharedCpuLoad {
+    cpu_load: f64,
+    read_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[cfg(feature = "shared-cache")]
+fn shared_key(instance_id: &str, window_minutes: i64) -> String {
+    format!("cpu-load:{}:{}", window_minutes, instance_id)
+}
+
+/// Returns the CPU loads of instances on a window, read by other processes less than the TTL ago (see [crate::shared_cache]), and keeps them in the cache of the process
+#[cfg(feature = "shared-cache")]
+pub(crate) async fn shared_cpu_loads(
+    instance_ids: &[String],
+    window_minutes: i64,
+) -> HashMap<String, f64> {
+    let ttl = ttl();
+    if ttl.is_zero() || instance_ids.is_empty() || !crate::shared_cache::is_configured() {
+        return HashMap::new();
+    }
+    let keys: Vec<String> = instance_ids
+        .iter()
+        .map(|instance_id| shared_key(instance_id, window_minutes))
+        .collect();
+    let values = crate::shared_cache::get_all(&keys).await;
+    let now = chrono::Utc::now();
+    let mut cpu_loads = CPU_LOADS.lock().unwrap();
+    let mut shared = HashMap::new();
+    for (instance_id, value) in instance_ids.iter().zip(values) {
+        let Some(shared_load) =
+            value.and_then(|value| serde_json::from_str::<SharedCpuLoad>(&value).ok())
+        else {
+            continue;
+        };
+        let age = (now - shared_load.read_at).to_std().unwrap_or_default();
+        if age >= ttl {
+            continue;
+        }
+        let read_at = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+        cpu_loads.insert_read_at(
+            instance_id,
+            window_minutes,
+            shared_load.cpu_load,
+            read_at,
+            ttl,
+        );
+        shared.insert(instance_id.clone(), shared_load.cpu_load);
+    }
+    shared
+}
+
+/// Shares the CPU loads of instances on a window, just read by a scan, with other processes (if the cache is shared)
+#[cfg(feature = "shared-cache")]
+pub(crate) async fn share_cpu_loads(cpu_loads: &[(String, f64)], window_minutes: i64) {
+    let read_at = chrono::Utc::now();
+    let entries: Vec<(String, String)> = cpu_loads
+        .iter()
+        .filter_map(|(instance_id, cpu_load)| {
+            let shared_load = SharedCpuLoad {
+                cpu_load: *cpu_load,
+                read_at,
+            };
+            let value = serde_json::to_string(&shared_load).ok()?;
+            Some((shared_key(instance_id, window_minutes), value))
+        })
+        .collect();
+    crate::shared_cache::set_all(&entries, ttl()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_loads_expire_after_ttl() {
+        let ttl = Duration::from_millis(50);
+        let mut cpu_loads = CpuLoads::new();
+        cpu_loads.insert("i-1", 10, 42.0, ttl);
+        assert_eq!(Some(42.0), cpu_loads.get("i-1", 10, ttl));
+        // Loads are measured on a window
+        assert_eq!(None, cpu_loads.get("i-1", 60, ttl));
+        assert_eq!(None, cpu_loads.get("i-2", 10, ttl));
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(None, cpu_loads.get("i-1", 10, ttl));
+        cpu_loads.insert("i-2", 10, 1.0, ttl);
+        assert_eq!(1, cpu_loads.entries.len());
+
+        // Nothing is cached when disabled
+        cpu_loads.insert("i-3", 10, 1.0, Duration::ZERO);
+        assert_eq!(None, cpu_loads.get("i-3", 10, ttl));
+    }
+}
@@ -0,0 +1,279 @@
+//! Continuous scans of the `watch` command: scans repeat at a fixed interval, each being appended to the result store and/or pushed as metrics, without running the standalone server.
+use anyhow::{Context, Result};
+use std::time::Duration;
+
+use crate::allocation::AllocationRules;
+use crate::aws_cloud_provider::{AwsAccount, ResourceSelection};
+use crate::cloudwatch_exporter::CloudWatchConfig;
+use crate::datadog_exporter::DatadogConfig;
+use crate::ignore_rules::IgnoreRules;
+use crate::influxdb_exporter::InfluxDbConfig;
+use crate::metric_exporter::MetricOptions;
+use crate::remote_write::RemoteWriteConfig;
+use crate::result_envelope::ScanParameters;
+use crate::retention::RetentionConfig;
+use crate::statsd_exporter::StatsdConfig;
+
+/// Settings of the continuous scans
+#[derive(Clone, Debug)]
+pub struct WatchConfig {
+    /// Interval between the starts of two scans
+    pub interval: Duration,
+    /// Stops after this number of scans (runs until interrupted otherwise)
+    pub max_scans: Option<u64>,
+    pub use_duration_hours: f32,
+    pub filter_tags: Vec<String>,
+    /// Accounts scanned by assuming their roles (none for the account of the environment)
+    pub accounts: Vec<AwsAccount>,
+    pub aws_regions: Vec<String>,
+    pub api_url: String,
+    pub include_block_storage: bool,
+    /// Scanned resources
+    pub selection: ResourceSelection,
+    /// Resources excluded from the scans
+    pub ignore_rules: IgnoreRules,
+    /// SQLite result store receiving each scan
+    pub store: Option<String>,
+    /// Retention policy applied to the result store after each scan
+    pub retention: Option<RetentionConfig>,
+    /// Rules allocating the shared resources in the aggregates of the retention policy
+    pub allocations: AllocationRules,
+    pub influxdb: Option<InfluxDbConfig>,
+    /// Prometheus Pushgateway receiving the metrics of each scan (like http://localhost:9091)
+    pub pushgateway_url: Option<String>,
+    /// StatsD server (or Datadog agent) receiving the summary of each scan
+    pub statsd: Option<StatsdConfig>,
+    /// CloudWatch namespace receiving the summary of each scan
+    pub cloudwatch: Option<CloudWatchConfig>,
+    /// Datadog account receiving the impacts of each scan
+    pub datadog: Option<DatadogConfig>,
+    /// `.prom` file of the textfile collector of node_exporter, receiving the metrics of each scan
+    pub textfile: Option<String>,
+    /// Prometheus remote-write receiver of the metrics of each scan
+    pub remote_write: Option<RemoteWriteConfig>,
+    /// Options of the pushed metrics of resources
+    pub metric_options: MetricOptions,
+    /// Alert rules evaluated after each scan
+    #[cfg(feature = "reports")]
+    pub alerting: Option<crate::alerting::Alerting>,
+}
+
+/// Parses an interval like `90s`, `30m`, `1h` or `1d`
+pub fn parse_interval(interval: &str) -> Result<Duration> {
+    let interval = interval.trim();
+    let split = interval
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(interval.len());
+    let (value, unit) = interval.split_at(split);
+    let value: u64 = value
+        .parse()
+        .with_context(|| format!("Invalid interval '{}', expecting like 30m or 1h", interval))?;
+    let seconds_per_unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => anyhow::bail!(
+            "Invalid unit of interval '{}', expecting s, m, h or d",
+            interval
+        ),
+    };
+    let seconds = value
+        .checked_mul(seconds_per_unit)
+        .with_context(|| format!("Interval '{}' is too long", interval))?;
+    if seconds == 0 {
+        anyhow::bail!("The interval must be longer than 0");
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Runs a scan and sends its results to the destinations of the configuration
+async fn run_scan(config: &WatchConfig) -> Result<()> {
+    let scan_timestamp = chrono::Utc::now();
+    let scan = crate::estimate_impacts_in_accounts_excluding(
+        &config.accounts,
+        &config.use_duration_hours,
+        &config.filter_tags,
+        &config.aws_regions,
+        &config.api_url,
+        false,
+        config.include_block_storage,
+        &config.selection,
+        &config.ignore_rules,
+    )
+    .await;
+    crate::progress::finish();
+    let (estimated_inventory, excluded) = scan.context("Cannot perform standard scan")?;
+    let mut summary = crate::build_summary_of_accounts(
+        &estimated_inventory,
+        &config.accounts,
+        &config.aws_regions,
+        &config.use_duration_hours,
+    )?;
+    if !config.ignore_rules.is_empty() {
+        summary = summary.with_excluded_resources(excluded);
+    }
+    #[cfg(feature = "stores")]
+    if let Some(store_path) = &config.store {
+        crate::store_impacts(store_path, &estimated_inventory, &summary)?;
+        if let Some(retention) = &config.retention {
+            crate::purge_store(
+                store_path,
+                retention,
+                &config.allocations,
+                chrono::Utc::now(),
+            )?;
+        }
+    }
+    let parameters = ScanParameters {
+        aws_region: config.aws_regions.join(","),
+        use_duration_hours: Some(config.use_duration_hours),
+        filter_tags: config.filter_tags.clone(),
+        include_block_storage: config.include_block_storage,
+        include_states: config.selection.instance_states.clone(),
+        verbose: false,
+    };
+    let metric_options = MetricOptions {
+        scan_info: Some(
+            crate::scan_metadata(scan_timestamp, parameters, Some(&config.api_url)).await,
+        ),
+        ..config.metric_options.clone()
+    };
+    if let Some(influxdb_config) = &config.influxdb {
+        let lines = crate::impacts_to_line_protocol(&estimated_inventory, &summary)?;
+        crate::influxdb_exporter::push_to_influxdb(influxdb_config, lines).await?;
+    }
+    if let Some(pushgateway_url) = &config.pushgateway_url {
+        let metrics = crate::impacts_to_metrics_of_tenant(
+            &estimated_inventory,
+            &summary,
+            None,
+            &metric_options,
+        )?;
+        crate::metric_exporter::push_to_pushgateway(pushgateway_url, metrics).await?;
+    }
+    if let Some(statsd_config) = &config.statsd {
+        let gauges = crate::statsd_exporter::get_summary_gauges(&summary, statsd_config);
+        crate::statsd_exporter::send_to_statsd(statsd_config, gauges).await?;
+    }
+    if let Some(cloudwatch_config) = &config.cloudwatch {
+        crate::cloudwatch_exporter::publish_to_cloudwatch(cloudwatch_config, &summary).await?;
+    }
+    if let Some(datadog_config) = &config.datadog {
+        let series = crate::datadog_exporter::get_series(
+            &summary,
+            &estimated_inventory.impacting_resources,
+            datadog_config,
+            scan_timestamp.timestamp(),
+        );
+        crate::datadog_exporter::submit_to_datadog(datadog_config, series).await?;
+    }
+    if let Some(textfile) = &config.textfile {
+        let metrics = crate::impacts_to_metrics_of_tenant(
+            &estimated_inventory,
+            &summary,
+            None,
+            &metric_options,
+        )?;
+        crate::metric_exporter::write_textfile(textfile, &metrics)?;
+    }
+    if let Some(remote_write_config) = &config.remote_write {
+        let metrics = crate::impacts_to_metrics_of_tenant(
+            &estimated_inventory,
+            &summary,
+            None,
+            &metric_options,
+        )?;
+        crate::remote_write::push_to_remote_write(
+            remote_write_config,
+            &metrics,
+            scan_timestamp.timestamp_millis(),
+        )
+        .await?;
+    }
+    #[cfg(feature = "reports")]
+    if let Some(alerting) = &config.alerting {
+        alerting.evaluate(&estimated_inventory).await?;
+    }
+    info!(
+        "Scan completed: {} resources, {:.3} kgCO2eq",
+        summary.number_of_resources_total,
+        summary.gwp_manufacture_kgco2eq + summary.gwp_use_kgco2eq
+    );
+    Ok(())
+}
+
+/// Scans at every interval until interrupted (Ctrl-C) or until the maximum number of scans is reached.
+///
+/// A failed scan is logged and the next scans still run.
+pub async fn watch(config: WatchConfig) -> Result<()> {
+    #[cfg(feature = "reports")]
+    let alerts = config.alerting.is_some();
+    #[cfg(not(feature = "reports"))]
+    let alerts = false;
+    if !alerts
+        && config.store.is_none()
+        && config.influxdb.is_none()
+        && config.pushgateway_url.is_none()
+        && config.statsd.is_none()
+        && config.cloudwatch.is_none()
+        && config.datadog.is_none()
+        && config.textfile.is_none()
+        && config.remote_write.is_none()
+    {
+        anyhow::bail!(
+            "Nothing to do with the scans: pass a result store (--store), a metrics destination (--influxdb-url, --pushgateway-url, --statsd-address, --cloudwatch-namespace, --datadog-api-key, --textfile or --remote-write-url) or --alert"
+        );
+    }
+    let mut scans = 0;
+    loop {
+        let started = tokio::time::Instant::now();
+        if let Err(e) = run_scan(&config).await {
+            error!("Scan failed: {:#}", e);
+        }
+        scans += 1;
+        if config.max_scans.is_some_and(|max| scans >= max) {
+            return Ok(());
+        }
+        let next_scan = started + config.interval;
+        info!(
+            "Next scan in {:?}",
+            next_scan.saturating_duration_since(tokio::time::Instant::now())
+        );
+        tokio::select! {
+            _ = tokio::time::sleep_until(next_scan) => {}
+            _ = tokio::signal::ctrl_c() => {
+                info!("Interrupted, stopping after {} scans", scans);
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_are_parsed_with_their_unit() {
+        assert_eq!(Duration::from_secs(90), parse_interval("90s").unwrap());
+        assert_eq!(Duration::from_secs(1800), parse_interval("30m").unwrap());
+        assert_eq!(Duration::from_secs(3600), parse_interval("1h").unwrap());
+        assert_eq!(
+            Duration::from_secs(2 * 86400),
+            parse_interval("2d").unwrap()
+        );
+        for invalid in [
+            "",
+            "1",
+            "h",
+            "1w",
+            "0m",
+            "-1h",
+            "1.5h",
+            "999999999999999999d",
+        ] {
+            assert!(parse_interval(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! A small web dashboard served by the standalone server on `/dashboard`: the latest stored scan, its breakdown by tag and the trend of the stored scans.
+//!
+//! Its assets are embedded in the binary. It reads the scan history API (`/scans`), so the server needs a result store.
+use rocket::response::content::{RawCss, RawHtml, RawJavaScript};
+use rocket::{get, routes, Route};
+
+const INDEX_HTML: &str = include_str!("../dashboard/index.html");
+const DASHBOARD_JS: &str = include_str!("../dashboard/dashboard.js");
+const DASHBOARD_CSS: &str = include_str!("../dashboard/dashboard.css");
+
+#[get("/")]
+fn index() -> RawHtml<&'static str> {
+    RawHtml(INDEX_HTML)
+}
+
+#[get("/dashboard.js")]
+fn script() -> RawJavaScript<&'static str> {
+    RawJavaScript(DASHBOARD_JS)
+}
+
+#[get("/dashboard.css")]
+fn stylesheet() -> RawCss<&'static str> {
+    RawCss(DASHBOARD_CSS)
+}
+
+/// Returns the routes of the dashboard, to mount on `/dashboard`
+pub fn routes() -> Vec<Route> {
+    routes![index, script, stylesheet]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::{ContentType, Status};
+
+    #[test]
+    fn dashboard_assets_are_served() {
+        let rocket = rocket::build().mount("/dashboard", routes());
+        let client = rocket::local::blocking::Client::untracked(rocket).unwrap();
+        for (path, content_type) in [
+            ("/dashboard", ContentType::HTML),
+            ("/dashboard/dashboard.js", ContentType::JavaScript),
+            ("/dashboard/dashboard.css", ContentType::CSS),
+        ] {
+            let response = client.get(path).dispatch();
+            assert_eq!(Status::Ok, response.status());
+            assert_eq!(Some(content_type), response.content_type());
+        }
+        let html = client.get("/dashboard").dispatch().into_string().unwrap();
+        assert!(html.contains("/dashboard/dashboard.js"));
+    }
+}
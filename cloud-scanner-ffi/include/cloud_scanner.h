@@ -0,0 +1,40 @@
+/*
+ * C ABI of cloud-scanner (libcloud_scanner), built with `cargo build --release -p cloud-scanner-ffi`.
+ *
+ * Strings returned by the library are owned by the caller and freed with cloud_scanner_free_string.
+ * Functions failing return NULL, the message of the error being returned by cloud_scanner_last_error
+ * (on the same thread).
+ */
+#ifndef CLOUD_SCANNER_H
+#define CLOUD_SCANNER_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * Estimates the impacts of an inventory (json of an inventory file, like the output of the inventory
+ * command) for the duration of use (hours), with Boavizta API at boavizta_api_url (the public API for
+ * NULL). Returns the json output of the estimate command, or only its summary when summary_only is not
+ * 0, NULL on failure.
+ */
+char *cloud_scanner_estimate_inventory(const char *inventory_json, float use_duration_hours,
+                                       const char *boavizta_api_url, int summary_only);
+
+/*
+ * Returns the message of the last error of the calling thread (owned by the library, valid until the
+ * next call on the thread), NULL if the last call succeeded.
+ */
+const char *cloud_scanner_last_error(void);
+
+/* Returns the version of cloud-scanner (freed with cloud_scanner_free_string) */
+char *cloud_scanner_version(void);
+
+/* Frees a string returned by the library (NULL is ignored) */
+void cloud_scanner_free_string(char *string);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* CLOUD_SCANNER_H */
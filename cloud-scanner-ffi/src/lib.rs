@@ -0,0 +1,154 @@
+//! A minimal C ABI of cloud-scanner (`libcloud_scanner`, see `include/cloud_scanner.h`), to embed the estimation of impacts in agents and daemons that are not written in Rust: an inventory goes in as json, the results come out as json.
+//!
+//! Strings returned by the library are owned by the caller and freed with [cloud_scanner_free_string]. Functions failing return `NULL`, the message of the error being returned by [cloud_scanner_last_error] (on the same thread).
+use anyhow::{Context, Result};
+use cloud_scanner_cli::scanner::{ResultFormat, Scanner};
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::AssertUnwindSafe;
+use std::sync::OnceLock;
+
+thread_local! {
+    /// Message of the last error of the thread
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Runtime of the estimations, started by the first estimation and shared by the next ones
+fn runtime() -> Result<&'static tokio::runtime::Runtime> {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    if let Some(runtime) = RUNTIME.get() {
+        return Ok(runtime);
+    }
+    let runtime = tokio::runtime::Runtime::new().context("Cannot start the runtime")?;
+    Ok(RUNTIME.get_or_init(|| runtime))
+}
+
+/// Returns the string of a C string, none for `NULL`
+///
+/// # Safety
+///
+/// `string` is `NULL` or a valid nul terminated string
+unsafe fn optional_str<'a>(string: *const c_char, name: &str) -> Result<Option<&'a str>> {
+    if string.is_null() {
+        return Ok(None);
+    }
+    let string = CStr::from_ptr(string)
+        .to_str()
+        .with_context(|| format!("Expected {} encoded in UTF-8", name))?;
+    Ok(Some(string))
+}
+
+/// Returns the result as a C string owned by the caller, or `NULL` after recording the error (a panic being an error)
+fn into_c_string(result: std::thread::Result<Result<String>>) -> *mut c_char {
+    let result = match result {
+        Ok(result) => result.and_then(|json| CString::new(json).context("Results with a nul")),
+        Err(_) => Err(anyhow::anyhow!("Panic during the estimation")),
+    };
+    match result {
+        Ok(json) => {
+            LAST_ERROR.with(|last_error| *last_error.borrow_mut() = None);
+            json.into_raw()
+        }
+        Err(e) => {
+            let message = CString::new(format!("{:#}", e).replace('\0', " "))
+                .expect("Nul characters are replaced");
+            LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Estimates the impacts of an inventory (json of an inventory file, like the output of the inventory command) for the duration of use (hours), with Boavizta API at `boavizta_api_url` (the public API for `NULL`). Returns the json output of the estimate command, or only its summary when `summary_only` is not 0, `NULL` on failure.
+///
+/// # Safety
+///
+/// `inventory_json` is a valid nul terminated string, `boavizta_api_url` is `NULL` or a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn cloud_scanner_estimate_inventory(
+    inventory_json: *const c_char,
+    use_duration_hours: f32,
+    boavizta_api_url: *const c_char,
+    summary_only: i32,
+) -> *mut c_char {
+    let estimation = AssertUnwindSafe(|| -> Result<String> {
+        let inventory_json =
+            optional_str(inventory_json, "the inventory")?.context("Missing the inventory")?;
+        let boavizta_api_url = optional_str(boavizta_api_url, "the URL of Boavizta API")?;
+        let (_, inventory) = cloud_scanner_cli::inventory_file::parse_inventory(inventory_json)?;
+        let mut builder = Scanner::builder()
+            .inventory(inventory)
+            .use_duration_hours(use_duration_hours);
+        if let Some(boavizta_api_url) = boavizta_api_url {
+            builder = builder.boavizta_api_url(boavizta_api_url);
+        }
+        let scanner = builder.build()?;
+        let results = runtime()?.block_on(scanner.scan())?;
+        let format = if summary_only != 0 {
+            ResultFormat::SummaryJson
+        } else {
+            ResultFormat::Json
+        };
+        format.format(&results)
+    });
+    into_c_string(std::panic::catch_unwind(estimation))
+}
+
+/// Returns the message of the last error of the calling thread (owned by the library, valid until the next call on the thread), `NULL` if the last call succeeded.
+#[no_mangle]
+pub extern "C" fn cloud_scanner_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Returns the version of cloud-scanner (owned by the caller, freed with [cloud_scanner_free_string])
+#[no_mangle]
+pub extern "C" fn cloud_scanner_version() -> *mut c_char {
+    into_c_string(Ok(Ok(cloud_scanner_cli::get_version())))
+}
+
+/// Frees a string returned by the library (`NULL` is ignored)
+///
+/// # Safety
+///
+/// `string` is `NULL` or a string returned by the library, not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn cloud_scanner_free_string(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failures_are_reported_by_the_last_error() {
+        unsafe {
+            let estimated =
+                cloud_scanner_estimate_inventory(std::ptr::null(), 1.0, std::ptr::null(), 0);
+            assert!(estimated.is_null());
+            let error = CStr::from_ptr(cloud_scanner_last_error()).to_str().unwrap();
+            assert_eq!("Missing the inventory", error);
+
+            let inventory = CString::new("not json").unwrap();
+            let estimated =
+                cloud_scanner_estimate_inventory(inventory.as_ptr(), 1.0, std::ptr::null(), 0);
+            assert!(estimated.is_null());
+            assert!(!cloud_scanner_last_error().is_null());
+
+            let version = cloud_scanner_version();
+            assert!(cloud_scanner_last_error().is_null());
+            assert_eq!(
+                cloud_scanner_cli::get_version(),
+                CStr::from_ptr(version).to_str().unwrap()
+            );
+            cloud_scanner_free_string(version);
+            cloud_scanner_free_string(std::ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Last step of a fanned out scan (see the state machine in serverless.yml): combines the summaries of the scans of the regions, and writes the summary of all regions to S3.
+use cloud_scanner_cli::impact_provider::ImpactsSummary;
+use lambda_runtime::{service_fn, Error, LambdaEvent};
+use pkg_version::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[macro_use]
+extern crate log;
+
+#[derive(Deserialize, Debug)]
+struct Config {
+    /// Destination of results in S3 (like s3://bucket/prefix/)
+    s3_output_url: String,
+    /// Optional destination of the summaries in S3, written next to the results (with the summary.json extension) if not set
+    s3_summary_url: Option<String>,
+    /// Optional CloudWatch namespace receiving the summary of all regions as custom metrics (like Boavizta/CloudScanner)
+    cloudwatch_namespace: Option<String>,
+}
+
+/// The output of the scan of a region by the scheduled-scan function, or its input with the error caught by the state machine
+#[derive(Deserialize, Debug)]
+struct PartialScan {
+    aws_region: String,
+    results_location: Option<String>,
+    summary_location: Option<String>,
+    summary: Option<ImpactsSummary>,
+    /// Error of the scan (like `{"Error": "States.Timeout", "Cause": "..."}`)
+    error: Option<Value>,
+}
+
+/// The location of the results of a region
+#[derive(Serialize, Debug, PartialEq)]
+struct RegionScan {
+    aws_region: String,
+    results_location: Option<String>,
+    summary_location: Option<String>,
+}
+
+/// The summary of all the regions, with the location of their results
+#[derive(Serialize, Debug)]
+struct FannedOutScan {
+    summary: ImpactsSummary,
+    scans: Vec<RegionScan>,
+    /// Regions whose scan failed, not part of the summary
+    failed_regions: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    cloud_scanner_cli::access_log::init_logger_from_env(
+        cloud_scanner_cli::access_log::LogFormat::Json,
+    )?;
+    lambda_runtime::run(service_fn(aggregate_scans)).await?;
+    Ok(())
+}
+
+async fn aggregate_scans(event: LambdaEvent<Vec<PartialScan>>) -> Result<Value, Error> {
+    let config = match envy::from_env::<Config>() {
+        Ok(config) => config,
+        Err(error) => panic!("{:#?}", error),
+    };
+
+    info!(
+        "Cloud scanner {}, using scanner lib {}",
+        get_version(),
+        cloud_scanner_cli::get_version()
+    );
+    info!("Using config {:?}", config);
+
+    let fanned_out_scan = combine(event.payload);
+    if !fanned_out_scan.failed_regions.is_empty() {
+        warn!(
+            "Scans of regions {:?} failed, they are missing from the summary",
+            fanned_out_scan.failed_regions
+        );
+    }
+
+    let (summary_url, summary_extension) = match &config.s3_summary_url {
+        Some(s3_summary_url) => (s3_summary_url, "json"),
+        None => (&config.s3_output_url, "summary.json"),
+    };
+    let summary_location = cloud_scanner_cli::write_results(
+        Some(summary_url),
+        "all-regions",
+        serde_json::to_string_pretty(&fanned_out_scan)?,
+        summary_extension,
+    )
+    .await?;
+
+    if let Some(namespace) = &config.cloudwatch_namespace {
+        let cloudwatch_config = cloud_scanner_cli::cloudwatch_exporter::CloudWatchConfig {
+            namespace: namespace.clone(),
+            dimensions: Vec::new(),
+        };
+        cloud_scanner_cli::cloudwatch_exporter::publish_to_cloudwatch(
+            &cloudwatch_config,
+            &fanned_out_scan.summary,
+        )
+        .await?;
+    }
+    Ok(serde_json::json!({
+        "summary_location": summary_location,
+        "failed_regions": fanned_out_scan.failed_regions,
+    }))
+}
+
+/// Combines the summaries of the regions scanned successfully
+fn combine(partial_scans: Vec<PartialScan>) -> FannedOutScan {
+    let mut summaries = Vec::new();
+    let mut scans = Vec::new();
+    let mut failed_regions = Vec::new();
+    for partial_scan in partial_scans {
+        match (partial_scan.summary, partial_scan.error) {
+            (Some(summary), None) => {
+                summaries.push(summary);
+                scans.push(RegionScan {
+                    aws_region: partial_scan.aws_region,
+                    results_location: partial_scan.results_location,
+                    summary_location: partial_scan.summary_location,
+                });
+            }
+            _ => failed_regions.push(partial_scan.aws_region),
+        }
+    }
+    FannedOutScan {
+        summary: ImpactsSummary::combine(summaries),
+        scans,
+        failed_regions,
+    }
+}
+
+/// Return current version of cloud-scanner-lambda
+fn get_version() -> String {
+    const MAJOR: u32 = pkg_version_major!();
+    const MINOR: u32 = pkg_version_minor!();
+    const PATCH: u32 = pkg_version_patch!();
+    format!("{}.{}.{}", MAJOR, MINOR, PATCH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn summaries_of_the_regions_are_combined() {
+        let summary = |aws_region: &str, gwp_use_kgco2eq: f64| {
+            json!({
+                "number_of_resources_total": 2,
+                "number_of_resources_assessed": 2,
+                "number_of_resources_not_assessed": 0,
+                "duration_of_use_hours": 24.0,
+                "adp_manufacture_kgsbeq": 0.0,
+                "adp_use_kgsbeq": 0.0,
+                "pe_manufacture_megajoules": 0.0,
+                "pe_use_megajoules": 0.0,
+                "gwp_manufacture_kgco2eq": 0.0,
+                "gwp_use_kgco2eq": gwp_use_kgco2eq,
+                "regions": [{
+                    "aws_region": aws_region,
+                    "country": "IRL",
+                    "number_of_resources_total": 2,
+                    "number_of_resources_assessed": 2,
+                    "number_of_resources_not_assessed": 0,
+                    "adp_manufacture_kgsbeq": 0.0,
+                    "adp_use_kgsbeq": 0.0,
+                    "pe_manufacture_megajoules": 0.0,
+                    "pe_use_megajoules": 0.0,
+                    "gwp_manufacture_kgco2eq": 0.0,
+                    "gwp_use_kgco2eq": gwp_use_kgco2eq
+                }]
+            })
+        };
+        let partial_scans: Vec<PartialScan> = serde_json::from_value(json!([
+            {
+                "aws_region": "eu-west-1",
+                "results_location": "s3://bucket/results/eu-west-1.json",
+                "summary_location": "s3://bucket/summaries/eu-west-1.json",
+                "summary": summary("eu-west-1", 1.5)
+            },
+            {
+                "aws_region": "eu-west-2",
+                "results_location": "s3://bucket/results/eu-west-2.json",
+                "summary_location": "s3://bucket/summaries/eu-west-2.json",
+                "summary": summary("eu-west-2", 2.5)
+            },
+            {
+                "aws_region": "us-east-1",
+                "use_duration_hours": 24,
+                "error": { "Error": "States.Timeout", "Cause": "" }
+            }
+        ]))
+        .unwrap();
+
+        let fanned_out_scan = combine(partial_scans);
+        assert_eq!(4, fanned_out_scan.summary.number_of_resources_total);
+        assert_eq!(4.0, fanned_out_scan.summary.gwp_use_kgco2eq);
+        assert_eq!("eu-west-1,eu-west-2", fanned_out_scan.summary.aws_regions());
+        assert_eq!(2, fanned_out_scan.scans.len());
+        assert_eq!(
+            vec!["us-east-1".to_string()],
+            fanned_out_scan.failed_regions
+        );
+    }
+}
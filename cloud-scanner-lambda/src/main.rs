@@ -5,6 +5,7 @@ use lambda_runtime::Error;
 use pkg_version::*;
 use serde_json::json;
 
+#[macro_use]
 extern crate log;
 
 use serde::Deserialize;
@@ -12,10 +13,21 @@ use serde::Deserialize;
 #[derive(Deserialize, Debug)]
 struct Config {
     boavizta_api_url: String,
+    /// Optional destination of results in S3 (like s3://bucket/prefix/)
+    s3_output_url: Option<String>,
+    /// Optional CloudWatch namespace receiving the summary as custom metrics (like Boavizta/CloudScanner)
+    cloudwatch_namespace: Option<String>,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
+    cloud_scanner_cli::access_log::init_logger_from_env(
+        cloud_scanner_cli::access_log::LogFormat::Json,
+    )?;
+    // Read apart from the logged config, as the url may contain a password
+    if let Ok(cache_url) = std::env::var("CLOUD_SCANNER_CACHE_URL") {
+        cloud_scanner_cli::shared_cache::configure(&cache_url)?;
+    }
     lambda_http::run(lambda_http::service_fn(scan)).await?;
     Ok(())
 }
@@ -26,20 +38,20 @@ async fn scan(event: Request) -> Result<impl IntoResponse, Error> {
         Err(error) => panic!("{:#?}", error),
     };
 
-    println!(
+    info!(
         "Cloud scanner {}, using scanner lib {}",
         get_version(),
         cloud_scanner_cli::get_version()
     );
-    println!("Using config {:#?}", config);
-    println!("Scan account invoked with event : {:?}", event);
+    info!("Using config {:?}", config);
+    info!("Scan account invoked with event : {:?}", event);
 
     let query_string_parameters = event.query_string_parameters();
 
     let use_duration_hours = match query_string_parameters.first("use_duration_hours") {
         Some(use_duration_hours) => use_duration_hours.parse::<f32>().unwrap(),
         None => {
-            println!("Missing 'use_duration_hours' parameter in path");
+            warn!("Missing 'use_duration_hours' parameter in path");
             return Ok(response(
                 StatusCode::BAD_REQUEST,
                 json!({ "message": "Missing 'use_duration_hours' parameter in path" }).to_string(),
@@ -50,7 +62,7 @@ async fn scan(event: Request) -> Result<impl IntoResponse, Error> {
     let aws_region = match query_string_parameters.first("aws_region") {
         Some(aws_region) => aws_region,
         None => {
-            println!("No 'aws_region' parameter in path, will fallback to default");
+            info!("No 'aws_region' parameter in path, will fallback to default");
             ""
         }
     };
@@ -59,7 +71,7 @@ async fn scan(event: Request) -> Result<impl IntoResponse, Error> {
         Some(filter_tags) => filter_tags.iter().map(|t| t.to_string()).collect(),
         None => {
             let filter_tags: Vec<String> = Vec::new();
-            println!("No 'filter_tag' parameter in path, will fallback to default");
+            info!("No 'filter_tag' parameter in path, will fallback to default");
             filter_tags
         }
     };
@@ -79,11 +91,11 @@ async fn scan(event: Request) -> Result<impl IntoResponse, Error> {
         None => false,
     };
 
-    println!("Using use time of {}", use_duration_hours);
-    println!("Using aws_region {}", aws_region);
-    println!("Using tag filers {:?}", filter_tags);
+    info!("Using use time of {}", use_duration_hours);
+    info!("Using aws_region {}", aws_region);
+    info!("Using tag filers {:?}", filter_tags);
 
-    let impacts: String = cloud_scanner_cli::get_impacts_as_json_string(
+    let (impacts, summary) = cloud_scanner_cli::get_impacts_as_json_string_with_summary(
         &use_duration_hours,
         &filter_tags,
         aws_region,
@@ -94,6 +106,44 @@ async fn scan(event: Request) -> Result<impl IntoResponse, Error> {
     )
     .await
     .unwrap();
+
+    if let Some(s3_output_url) = &config.s3_output_url {
+        let uploaded = cloud_scanner_cli::write_results(
+            Some(s3_output_url),
+            aws_region,
+            impacts.clone(),
+            "json",
+        )
+        .await;
+        if let Err(e) = uploaded {
+            error!("Cannot upload results to {}: {:?}", s3_output_url, e);
+            return Ok(response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({ "message": "Cannot upload results to S3" }).to_string(),
+            ));
+        }
+    }
+    if let Some(namespace) = &config.cloudwatch_namespace {
+        let cloudwatch_config = cloud_scanner_cli::cloudwatch_exporter::CloudWatchConfig {
+            namespace: namespace.clone(),
+            dimensions: Vec::new(),
+        };
+        let published = cloud_scanner_cli::cloudwatch_exporter::publish_to_cloudwatch(
+            &cloudwatch_config,
+            &summary,
+        )
+        .await;
+        if let Err(e) = published {
+            error!(
+                "Cannot publish metrics to CloudWatch {}: {:?}",
+                namespace, e
+            );
+            return Ok(response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({ "message": "Cannot publish metrics to CloudWatch" }).to_string(),
+            ));
+        }
+    }
     Ok(response(StatusCode::OK, impacts))
 }
 
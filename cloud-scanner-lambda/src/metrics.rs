@@ -2,6 +2,7 @@ use lambda_http::{http::StatusCode, IntoResponse, Request, RequestExt, Response}
 use lambda_runtime::Error;
 use pkg_version::*;
 
+#[macro_use]
 extern crate log;
 
 use serde::Deserialize;
@@ -13,6 +14,13 @@ struct Config {
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
+    cloud_scanner_cli::access_log::init_logger_from_env(
+        cloud_scanner_cli::access_log::LogFormat::Json,
+    )?;
+    // Read apart from the logged config, as the url may contain a password
+    if let Ok(cache_url) = std::env::var("CLOUD_SCANNER_CACHE_URL") {
+        cloud_scanner_cli::shared_cache::configure(&cache_url)?;
+    }
     lambda_http::run(lambda_http::service_fn(summary)).await?;
     Ok(())
 }
@@ -23,20 +31,20 @@ async fn summary(event: Request) -> Result<impl IntoResponse, Error> {
         Err(error) => panic!("{:#?}", error),
     };
 
-    println!(
+    info!(
         "Cloud scanner {}, using scanner lib {}",
         get_version(),
         cloud_scanner_cli::get_version()
     );
-    println!("Using config {:#?}", config);
-    println!("Scan account invoked with event : {:?}", event);
+    info!("Using config {:?}", config);
+    info!("Scan account invoked with event : {:?}", event);
 
     let query_string_parameters = event.query_string_parameters();
 
     let aws_region = match query_string_parameters.first("aws_region") {
         Some(aws_region) => aws_region,
         None => {
-            println!("No 'aws_region' parameter in path, will fallback to default");
+            info!("No 'aws_region' parameter in path, will fallback to default");
             ""
         }
     };
@@ -45,7 +53,7 @@ async fn summary(event: Request) -> Result<impl IntoResponse, Error> {
         Some(filter_tags) => filter_tags.iter().map(|t| t.to_string()).collect(),
         None => {
             let filter_tags: Vec<String> = Vec::new();
-            println!("No 'filter_tag' parameter in path, will fallback to default");
+            info!("No 'filter_tag' parameter in path, will fallback to default");
             filter_tags
         }
     };
@@ -55,10 +63,10 @@ async fn summary(event: Request) -> Result<impl IntoResponse, Error> {
         None => false,
     };
 
-    println!("Using fixed use time of 1 hour.");
-    println!("Using aws_region: {}", aws_region);
-    println!("Using tag filers: {:?}", filter_tags);
-    println!("Include block storage: {:?}", include_block_storage);
+    info!("Using fixed use time of 1 hour.");
+    info!("Using aws_region: {}", aws_region);
+    info!("Using tag filers: {:?}", filter_tags);
+    info!("Include block storage: {:?}", include_block_storage);
 
     let impacts: String = cloud_scanner_cli::get_impacts_as_metrics(
         &1.0,
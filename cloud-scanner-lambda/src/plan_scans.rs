@@ -0,0 +1,82 @@
+//! First step of a fanned out scan (see the state machine in serverless.yml): returns the input of the scan of each region, scanned by separate invocations of the scheduled-scan function.
+use lambda_runtime::{service_fn, Error, LambdaEvent};
+use pkg_version::*;
+use serde_json::{Map, Value};
+
+#[macro_use]
+extern crate log;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    cloud_scanner_cli::access_log::init_logger_from_env(
+        cloud_scanner_cli::access_log::LogFormat::Json,
+    )?;
+    lambda_runtime::run(service_fn(plan_scans)).await?;
+    Ok(())
+}
+
+/// Reads the parameters of the scan (like `use_duration_hours` or `filter_tags`) and the regions to scan (`aws_regions`, all the regions enabled in the account if not set)
+async fn plan_scans(event: LambdaEvent<Value>) -> Result<Value, Error> {
+    info!(
+        "Cloud scanner {}, using scanner lib {}",
+        get_version(),
+        cloud_scanner_cli::get_version()
+    );
+    info!("Plan scans invoked with event : {:?}", event.payload);
+
+    let mut parameters = match event.payload {
+        Value::Object(parameters) => parameters,
+        // Like the event of a schedule without input
+        _ => Map::new(),
+    };
+    let aws_regions: Vec<String> = match parameters.remove("aws_regions") {
+        Some(aws_regions) => serde_json::from_value(aws_regions)?,
+        None => {
+            cloud_scanner_cli::aws_cloud_provider::AwsCloudProvider::list_enabled_regions().await?
+        }
+    };
+    info!("Planning scans of regions {:?}", aws_regions);
+    Ok(Value::Array(scans_of_regions(&parameters, &aws_regions)))
+}
+
+/// Returns the input of the scan of each region: the parameters of the scan, with the region
+fn scans_of_regions(parameters: &Map<String, Value>, aws_regions: &[String]) -> Vec<Value> {
+    aws_regions
+        .iter()
+        .map(|aws_region| {
+            let mut scan = parameters.clone();
+            scan.insert("aws_region".to_string(), Value::from(aws_region.as_str()));
+            Value::Object(scan)
+        })
+        .collect()
+}
+
+/// Return current version of cloud-scanner-lambda
+fn get_version() -> String {
+    const MAJOR: u32 = pkg_version_major!();
+    const MINOR: u32 = pkg_version_minor!();
+    const PATCH: u32 = pkg_version_patch!();
+    format!("{}.{}.{}", MAJOR, MINOR, PATCH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn one_scan_is_planned_per_region() {
+        let parameters = json!({ "use_duration_hours": 24, "filter_tags": ["env=prod"] });
+        let scans = scans_of_regions(
+            parameters.as_object().unwrap(),
+            &["eu-west-1".to_string(), "us-east-1".to_string()],
+        );
+        assert_eq!(
+            vec![
+                json!({ "use_duration_hours": 24, "filter_tags": ["env=prod"], "aws_region": "eu-west-1" }),
+                json!({ "use_duration_hours": 24, "filter_tags": ["env=prod"], "aws_region": "us-east-1" }),
+            ],
+            scans
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Scan invoked on a schedule (like an EventBridge rule), writing results and their summary to S3 instead of returning them.
+use lambda_runtime::{service_fn, Error, LambdaEvent};
+use pkg_version::*;
+use serde_json::{json, Value};
+
+#[macro_use]
+extern crate log;
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+struct Config {
+    boavizta_api_url: String,
+    /// Destination of results in S3 (like s3://bucket/prefix/)
+    s3_output_url: String,
+    /// Optional destination of the summaries in S3, written next to the results (with the summary.json extension) if not set
+    s3_summary_url: Option<String>,
+    /// Optional CloudWatch namespace receiving the summary as custom metrics (like Boavizta/CloudScanner)
+    cloudwatch_namespace: Option<String>,
+}
+
+/// Parameters of a scheduled scan, read from the constant input of the schedule.
+///
+/// The event sent by a schedule without input (`"detail-type": "Scheduled Event"`) scans the default region for one hour of use.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default)]
+struct ScheduledScan {
+    /// Duration of use, should match the interval between two scans
+    use_duration_hours: f32,
+    aws_region: String,
+    filter_tags: Vec<String>,
+    verbose_output: bool,
+    include_block_storage: bool,
+}
+
+impl Default for ScheduledScan {
+    fn default() -> Self {
+        ScheduledScan {
+            use_duration_hours: 1.0,
+            aws_region: String::new(),
+            filter_tags: Vec::new(),
+            verbose_output: false,
+            include_block_storage: false,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    cloud_scanner_cli::access_log::init_logger_from_env(
+        cloud_scanner_cli::access_log::LogFormat::Json,
+    )?;
+    // Read apart from the logged config, as the url may contain a password
+    if let Ok(cache_url) = std::env::var("CLOUD_SCANNER_CACHE_URL") {
+        cloud_scanner_cli::shared_cache::configure(&cache_url)?;
+    }
+    lambda_runtime::run(service_fn(scheduled_scan)).await?;
+    Ok(())
+}
+
+async fn scheduled_scan(event: LambdaEvent<Value>) -> Result<Value, Error> {
+    let config = match envy::from_env::<Config>() {
+        Ok(config) => config,
+        Err(error) => panic!("{:#?}", error),
+    };
+
+    info!(
+        "Cloud scanner {}, using scanner lib {}",
+        get_version(),
+        cloud_scanner_cli::get_version()
+    );
+    info!("Using config {:?}", config);
+    info!("Scheduled scan invoked with event : {:?}", event.payload);
+
+    let scan: ScheduledScan = serde_json::from_value(event.payload)?;
+    info!("Using scan parameters {:?}", scan);
+
+    let (impacts, summary_json, summary) =
+        cloud_scanner_cli::get_impacts_and_summary_as_json_strings(
+            &scan.use_duration_hours,
+            &scan.filter_tags,
+            &scan.aws_region,
+            &config.boavizta_api_url,
+            scan.verbose_output,
+            scan.include_block_storage,
+        )
+        .await?;
+
+    let results_location = cloud_scanner_cli::write_results(
+        Some(&config.s3_output_url),
+        &scan.aws_region,
+        impacts,
+        "json",
+    )
+    .await?;
+    let (summary_url, summary_extension) = match &config.s3_summary_url {
+        Some(s3_summary_url) => (s3_summary_url, "json"),
+        None => (&config.s3_output_url, "summary.json"),
+    };
+    let summary_location = cloud_scanner_cli::write_results(
+        Some(summary_url),
+        &scan.aws_region,
+        summary_json,
+        summary_extension,
+    )
+    .await?;
+
+    if let Some(namespace) = &config.cloudwatch_namespace {
+        let cloudwatch_config = cloud_scanner_cli::cloudwatch_exporter::CloudWatchConfig {
+            namespace: namespace.clone(),
+            dimensions: Vec::new(),
+        };
+        cloud_scanner_cli::cloudwatch_exporter::publish_to_cloudwatch(&cloudwatch_config, &summary)
+            .await?;
+    }
+    // The summary is returned for the aggregation of fanned out scans (see aggregate_scans.rs)
+    Ok(json!({
+        "aws_region": scan.aws_region,
+        "results_location": results_location,
+        "summary_location": summary_location,
+        "summary": summary,
+    }))
+}
+
+/// Return current version of cloud-scanner-lambda
+fn get_version() -> String {
+    const MAJOR: u32 = pkg_version_major!();
+    const MINOR: u32 = pkg_version_minor!();
+    const PATCH: u32 = pkg_version_patch!();
+    format!("{}.{}.{}", MAJOR, MINOR, PATCH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parameters_of_scheduled_scans() {
+        let event = json!({
+            "version": "0",
+            "id": "53dc4d37-cffa-4f76-80c9-8b7d4a4d2eaa",
+            "detail-type": "Scheduled Event",
+            "source": "aws.events",
+            "account": "123456789012",
+            "time": "2024-05-02T08:00:00Z",
+            "region": "eu-west-1",
+            "resources": ["arn:aws:events:eu-west-1:123456789012:rule/scan"],
+            "detail": {}
+        });
+        let scan: ScheduledScan = serde_json::from_value(event).unwrap();
+        assert_eq!(ScheduledScan::default(), scan);
+
+        let input = json!({
+            "use_duration_hours": 24,
+            "aws_region": "eu-west-3",
+            "filter_tags": ["Name=test"],
+            "include_block_storage": true
+        });
+        let scan: ScheduledScan = serde_json::from_value(input).unwrap();
+        assert_eq!(24.0, scan.use_duration_hours);
+        assert_eq!("eu-west-3", scan.aws_region);
+        assert_eq!(vec!["Name=test".to_string()], scan.filter_tags);
+        assert!(scan.include_block_storage);
+        assert!(!scan.verbose_output);
+    }
+}
@@ -0,0 +1,521 @@
+//! The `CloudScan` custom resource: the scan it declares (provider, regions, schedule, filters and sinks of the results), and the status written by the operator.
+//!
+//! ```yaml
+//! apiVersion: cloud-scanner.boavizta.org/v1alpha1
+//! kind: CloudScan
+//! metadata:
+//!   name: production
+//! spec:
+//!   provider: aws
+//!   regions: [eu-west-1, eu-west-3]
+//!   schedule: 1h
+//!   filterTags: ["env=prod"]
+//!   sinks:
+//!     - format: json
+//!       output: s3://my-results-bucket/production/
+//! ```
+//!
+//! The schema of the resource is the CustomResourceDefinition of `k8s/cloudscan-crd.yaml`. The fields it cannot check (like the schedule or the duration of use) are validated by the operator, which reports an invalid spec in the status of the resource instead of failing to read it.
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use cloud_scanner_cli::impact_provider::ImpactsSummary;
+use cloud_scanner_cli::scanner::{ResultFormat, Scanner};
+use kube::CustomResource;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
+
+/// Type of the condition telling whether the last scan succeeded
+pub const READY_CONDITION: &str = "Ready";
+/// Type of the condition telling whether a scan is running
+pub const SCANNING_CONDITION: &str = "Scanning";
+
+fn default_provider() -> String {
+    "aws".to_string()
+}
+
+/// The scan declared by a `CloudScan` resource
+#[derive(CustomResource, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[kube(
+    group = "cloud-scanner.boavizta.org",
+    version = "v1alpha1",
+    kind = "CloudScan",
+    namespaced,
+    status = "CloudScanStatus",
+    shortname = "cscan",
+    schema = "disabled"
+)]
+#[serde(rename_all = "camelCase")]
+pub struct CloudScanSpec {
+    /// Cloud provider of the scanned resources (only aws is supported)
+    #[serde(default = "default_provider")]
+    pub provider: String,
+    pub regions: Vec<String>,
+    /// Interval between two scans, like 30m, 1h or 1d
+    pub schedule: String,
+    /// Duration of use of the estimated impacts (hours, or text like 30d), the interval of the schedule by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub use_duration_hours: Option<Value>,
+    #[serde(default)]
+    pub filter_tags: Vec<String>,
+    #[serde(default)]
+    pub include_block_storage: bool,
+    /// Destinations of the results of each scan
+    #[serde(default)]
+    pub sinks: Vec<CloudScanSink>,
+    /// Stops scheduling scans, without deleting the resource
+    #[serde(default)]
+    pub suspend: bool,
+}
+
+fn default_sink_format() -> String {
+    "json".to_string()
+}
+
+/// A destination of the results of the scans
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct CloudScanSink {
+    /// json (default), summary-json, csv, openmetrics or line-protocol
+    #[serde(default = "default_sink_format")]
+    pub format: String,
+    /// Output URI of the results, like s3://bucket/prefix/ or https://... (see `--output` of the CLI)
+    pub output: String,
+}
+
+impl CloudScanSink {
+    pub fn result_format(&self) -> Result<ResultFormat> {
+        Ok(match self.format.as_str() {
+            "json" => ResultFormat::Json,
+            "summary-json" => ResultFormat::SummaryJson,
+            "csv" => ResultFormat::Csv,
+            "openmetrics" => ResultFormat::OpenMetrics,
+            "line-protocol" => ResultFormat::LineProtocol,
+            format => anyhow::bail!(
+                "Unknown format of sink {} (expecting json, summary-json, csv, openmetrics or line-protocol)",
+                format
+            ),
+        })
+    }
+}
+
+impl CloudScanSpec {
+    /// Returns the interval between two scans, failing when the spec cannot be scanned
+    pub fn interval(&self) -> Result<Duration> {
+        if self.provider != "aws" {
+            anyhow::bail!(
+                "Unsupported provider {} (only aws is supported)",
+                self.provider
+            );
+        }
+        if self.regions.is_empty() {
+            anyhow::bail!("No region to scan");
+        }
+        for sink in self.sinks.iter() {
+            sink.result_format()?;
+        }
+        self.use_duration_hours()?;
+        let interval =
+            cloud_scanner_cli::watch::parse_interval(&self.schedule).context("Invalid schedule")?;
+        if next_scan_time(Utc::now(), interval).is_none() {
+            anyhow::bail!("Invalid schedule: interval {} is too long", self.schedule);
+        }
+        Ok(interval)
+    }
+
+    /// Returns the duration of use of the spec, in hours
+    fn use_duration_hours(&self) -> Result<Option<f32>> {
+        cloud_scanner_cli::duration::deserialize_optional_hours(
+            self.use_duration_hours.clone().unwrap_or(Value::Null),
+        )
+        .context("Invalid useDurationHours")
+    }
+
+    /// Returns the scanner of the spec, writing the results to its sinks
+    pub fn scanner(&self, interval: Duration, api_url: &str) -> Result<Scanner> {
+        let use_duration_hours = self
+            .use_duration_hours()?
+            .unwrap_or(interval.as_secs_f32() / 3600.0);
+        let mut builder = Scanner::builder()
+            .regions(self.regions.iter())
+            .filter_tags(self.filter_tags.iter())
+            .include_block_storage(self.include_block_storage)
+            .use_duration_hours(use_duration_hours)
+            .boavizta_api_url(api_url);
+        for sink in self.sinks.iter() {
+            builder = builder.export_to(sink.result_format()?, Some(&sink.output))?;
+        }
+        builder.build()
+    }
+}
+
+impl CloudScan {
+    /// Generation of the spec, incremented by the API server on each change of the spec
+    pub fn generation(&self) -> i64 {
+        self.metadata.generation.unwrap_or_default()
+    }
+
+    /// Returns when the scan is due: at once when it was never run or when its spec changed since its last run, when its schedule elapsed otherwise, never when it is suspended
+    pub fn due_time(&self, interval: Duration) -> Option<DateTime<Utc>> {
+        if self.spec.suspend {
+            return None;
+        }
+        match &self.status {
+            Some(status) if status.observed_generation == Some(self.generation()) => {
+                match status.last_scan_time {
+                    Some(last_scan_time) => next_scan_time(last_scan_time, interval),
+                    None => Some(DateTime::<Utc>::MIN_UTC),
+                }
+            }
+            _ => Some(DateTime::<Utc>::MIN_UTC),
+        }
+    }
+
+    /// Returns true when the scan is due (see [CloudScan::due_time])
+    pub fn is_due(&self, interval: Duration, now: DateTime<Utc>) -> bool {
+        self.due_time(interval)
+            .is_some_and(|due_time| now >= due_time)
+    }
+}
+
+/// Returns the time of the scan following a scan, none when it cannot be represented (see [CloudScanSpec::interval])
+fn next_scan_time(last_scan_time: DateTime<Utc>, interval: Duration) -> Option<DateTime<Utc>> {
+    chrono::Duration::from_std(interval)
+        .ok()
+        .and_then(|interval| last_scan_time.checked_add_signed(interval))
+}
+
+/// The totals of the last successful scan
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CloudScanStatusSummary {
+    pub number_of_resources: usize,
+    pub number_of_resources_assessed: usize,
+    /// GWP of manufacture and use
+    pub gwp_kgco2eq: f64,
+    /// PE of manufacture and use
+    pub pe_megajoules: f64,
+}
+
+impl From<&ImpactsSummary> for CloudScanStatusSummary {
+    fn from(summary: &ImpactsSummary) -> Self {
+        CloudScanStatusSummary {
+            number_of_resources: summary.number_of_resources_total,
+            number_of_resources_assessed: summary.number_of_resources_assessed,
+            gwp_kgco2eq: summary.gwp_manufacture_kgco2eq + summary.gwp_use_kgco2eq,
+            pe_megajoules: summary.pe_manufacture_megajoules + summary.pe_use_megajoules,
+        }
+    }
+}
+
+/// A condition of the status, following the conventions of Kubernetes (like `kubectl wait --for=condition=Ready`)
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Condition {
+    #[serde(rename = "type")]
+    pub condition_type: String,
+    /// True or False
+    pub status: String,
+    pub reason: String,
+    pub message: String,
+    pub last_transition_time: DateTime<Utc>,
+    pub observed_generation: i64,
+}
+
+/// The status of a `CloudScan` resource, written by the operator
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CloudScanStatus {
+    /// Generation of the spec of the last scan
+    pub observed_generation: Option<i64>,
+    pub last_scan_time: Option<DateTime<Utc>>,
+    pub last_successful_scan_time: Option<DateTime<Utc>>,
+    pub next_scan_time: Option<DateTime<Utc>>,
+    pub summary: Option<CloudScanStatusSummary>,
+    /// Locations of the results of the last successful scan
+    #[serde(default)]
+    pub exports: Vec<String>,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+}
+
+impl CloudScanStatus {
+    /// Sets a condition, keeping its transition time when its status does not change
+    fn set_condition(
+        &mut self,
+        condition_type: &str,
+        status: bool,
+        reason: &str,
+        message: String,
+        generation: i64,
+        now: DateTime<Utc>,
+    ) {
+        let status = if status { "True" } else { "False" }.to_string();
+        let position = self
+            .conditions
+            .iter()
+            .position(|c| c.condition_type == condition_type);
+        let last_transition_time = match position.map(|i| &self.conditions[i]) {
+            Some(previous) if previous.status == status => previous.last_transition_time,
+            _ => now,
+        };
+        let condition = Condition {
+            condition_type: condition_type.to_string(),
+            status,
+            reason: reason.to_string(),
+            message,
+            last_transition_time,
+            observed_generation: generation,
+        };
+        match position {
+            Some(i) => self.conditions[i] = condition,
+            None => self.conditions.push(condition),
+        }
+    }
+
+    /// Returns the status of a resource whose spec cannot be scanned
+    pub fn invalid_spec(
+        mut self,
+        generation: i64,
+        error: &anyhow::Error,
+        now: DateTime<Utc>,
+    ) -> Self {
+        self.observed_generation = Some(generation);
+        self.next_scan_time = None;
+        self.set_condition(
+            READY_CONDITION,
+            false,
+            "InvalidSpec",
+            format!("{:#}", error),
+            generation,
+            now,
+        );
+        self
+    }
+
+    /// Returns the status of a resource whose scan started
+    pub fn scanning(mut self, generation: i64, now: DateTime<Utc>) -> Self {
+        self.set_condition(
+            SCANNING_CONDITION,
+            true,
+            "ScanStarted",
+            format!("Scan started at {}", now.to_rfc3339()),
+            generation,
+            now,
+        );
+        self
+    }
+
+    /// Returns the status of a resource whose scan succeeded
+    pub fn succeeded(
+        mut self,
+        generation: i64,
+        summary: &ImpactsSummary,
+        exports: Vec<String>,
+        interval: Duration,
+        now: DateTime<Utc>,
+    ) -> Self {
+        self.completed(generation, interval, now);
+        self.last_successful_scan_time = Some(now);
+        self.summary = Some(summary.into());
+        self.exports = exports;
+        self.set_condition(
+            READY_CONDITION,
+            true,
+            "ScanSucceeded",
+            format!(
+                "{} resources scanned ({} assessed)",
+                summary.number_of_resources_total, summary.number_of_resources_assessed
+            ),
+            generation,
+            now,
+        );
+        self
+    }
+
+    /// Returns the status of a resource whose scan failed, keeping the results of the last successful scan
+    pub fn failed(
+        mut self,
+        generation: i64,
+        error: &anyhow::Error,
+        interval: Duration,
+        now: DateTime<Utc>,
+    ) -> Self {
+        self.completed(generation, interval, now);
+        self.set_condition(
+            READY_CONDITION,
+            false,
+            "ScanFailed",
+            format!("{:#}", error),
+            generation,
+            now,
+        );
+        self
+    }
+
+    fn completed(&mut self, generation: i64, interval: Duration, now: DateTime<Utc>) {
+        self.observed_generation = Some(generation);
+        self.last_scan_time = Some(now);
+        self.next_scan_time = next_scan_time(now, interval);
+        self.set_condition(
+            SCANNING_CONDITION,
+            false,
+            "ScanCompleted",
+            format!("Scan completed at {}", now.to_rfc3339()),
+            generation,
+            now,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn summary() -> ImpactsSummary {
+        let estimated_inventory = cloud_scanner_cli::model::EstimatedInventory {
+            impacting_resources: Vec::new(),
+            execution_statistics: None,
+            errors: Vec::new(),
+        };
+        ImpactsSummary::new(
+            "eu-west-1".to_string(),
+            "IRL".to_string(),
+            &estimated_inventory,
+            1.0,
+        )
+    }
+
+    fn cloud_scan(spec: Value, status: Option<CloudScanStatus>) -> CloudScan {
+        serde_json::from_value(json!({
+            "apiVersion": "cloud-scanner.boavizta.org/v1alpha1",
+            "kind": "CloudScan",
+            "metadata": { "name": "production", "namespace": "platform", "generation": 2, "uid": "1234" },
+            "spec": spec,
+            "status": status,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn specs_of_cloud_scans_are_validated() {
+        let scan = cloud_scan(
+            json!({
+                "regions": ["eu-west-1", "eu-west-3"],
+                "schedule": "1d",
+                "filterTags": ["env=prod"],
+                "sinks": [{ "output": "s3://bucket/production/" }, { "format": "csv", "output": "results.csv" }]
+            }),
+            None,
+        );
+        assert_eq!("aws", scan.spec.provider);
+        assert_eq!(None, scan.spec.use_duration_hours().unwrap());
+        assert_eq!(
+            ResultFormat::Json,
+            scan.spec.sinks[0].result_format().unwrap()
+        );
+        assert_eq!(Duration::from_secs(86400), scan.spec.interval().unwrap());
+
+        let scan = cloud_scan(
+            json!({ "regions": ["eu-west-1"], "schedule": "1d", "useDurationHours": "30d" }),
+            None,
+        );
+        assert_eq!(Some(720.0), scan.spec.use_duration_hours().unwrap());
+
+        for invalid in [
+            json!({ "regions": ["eu-west-1"], "schedule": "1d", "provider": "azure" }),
+            json!({ "regions": [], "schedule": "1d" }),
+            json!({ "regions": ["eu-west-1"], "schedule": "daily" }),
+            json!({ "regions": ["eu-west-1"], "schedule": "1d", "sinks": [{ "format": "xml", "output": "-" }] }),
+            json!({ "regions": ["eu-west-1"], "schedule": "1d", "useDurationHours": "30x" }),
+            json!({ "regions": ["eu-west-1"], "schedule": "1d", "useDurationHours": -1 }),
+            // Beyond the dates that can be represented
+            json!({ "regions": ["eu-west-1"], "schedule": "106751991167300d" }),
+        ] {
+            let scan = cloud_scan(invalid.clone(), None);
+            assert!(scan.spec.interval().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn scans_are_due_when_never_run_changed_or_elapsed() {
+        let spec = json!({ "regions": ["eu-west-1"], "schedule": "1h" });
+        let now = Utc.with_ymd_and_hms(2024, 5, 2, 8, 0, 0).unwrap();
+        let interval = Duration::from_secs(3600);
+        let scan = cloud_scan(spec.clone(), None);
+        assert!(scan.is_due(interval, now));
+
+        let status = CloudScanStatus::default().succeeded(2, &summary(), Vec::new(), interval, now);
+        let scan = cloud_scan(spec.clone(), Some(status.clone()));
+        assert_eq!(
+            Some(now + chrono::Duration::minutes(60)),
+            scan.due_time(interval)
+        );
+        assert!(!scan.is_due(interval, now + chrono::Duration::minutes(59)));
+        assert!(scan.is_due(interval, now + chrono::Duration::minutes(60)));
+
+        // The spec changed since the last scan
+        let changed = CloudScanStatus {
+            observed_generation: Some(1),
+            ..status
+        };
+        let scan = cloud_scan(spec, Some(changed));
+        assert!(scan.is_due(interval, now));
+
+        let suspended = cloud_scan(
+            json!({ "regions": ["eu-west-1"], "schedule": "1h", "suspend": true }),
+            None,
+        );
+        assert_eq!(None, suspended.due_time(interval));
+        assert!(!suspended.is_due(interval, now));
+    }
+
+    #[test]
+    fn conditions_keep_their_transition_time() {
+        let interval = Duration::from_secs(3600);
+        let at = |hour| Utc.with_ymd_and_hms(2024, 5, 2, hour, 0, 0).unwrap();
+        let summary = ImpactsSummary {
+            number_of_resources_total: 3,
+            number_of_resources_assessed: 2,
+            gwp_manufacture_kgco2eq: 1.0,
+            gwp_use_kgco2eq: 0.5,
+            ..summary()
+        };
+        let status = CloudScanStatus::default().scanning(2, at(8)).succeeded(
+            2,
+            &summary,
+            vec!["s3://bucket/results.json".to_string()],
+            interval,
+            at(8),
+        );
+        let status = status
+            .scanning(2, at(9))
+            .succeeded(2, &summary, Vec::new(), interval, at(9));
+        let ready = &status.conditions[1];
+        assert_eq!(
+            (READY_CONDITION, "True"),
+            (ready.condition_type.as_str(), ready.status.as_str())
+        );
+        assert_eq!(at(8), ready.last_transition_time);
+        assert_eq!(Some(at(10)), status.next_scan_time);
+        assert_eq!(1.5, status.summary.as_ref().unwrap().gwp_kgco2eq);
+
+        let failed = status.scanning(2, at(10)).failed(
+            2,
+            &anyhow::anyhow!("Cannot list instances"),
+            interval,
+            at(10),
+        );
+        let ready = &failed.conditions[1];
+        assert_eq!(
+            ("False", "ScanFailed"),
+            (ready.status.as_str(), ready.reason.as_str())
+        );
+        assert_eq!(at(10), ready.last_transition_time);
+        // The results of the last successful scan are kept
+        assert_eq!(Some(at(9)), failed.last_successful_scan_time);
+        assert_eq!(3, failed.summary.unwrap().number_of_resources);
+        assert_eq!("False", failed.conditions[0].status);
+    }
+}
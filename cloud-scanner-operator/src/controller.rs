@@ -0,0 +1,170 @@
+//! Reconciliation of the `CloudScan` resources: a [kube::runtime::Controller] watches the resources and runs their scans when they are due, one at a time, writing their outcome to the status of the resources.
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use kube::api::{Patch, PatchParams};
+use kube::runtime::controller::{self, Action};
+use kube::runtime::{watcher, Controller};
+use kube::{Api, Client, ResourceExt};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::cloud_scan::{CloudScan, CloudScanStatus};
+
+/// Delay before reconciling a resource again after a failure to update its status
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Delay before reconciling a resource again when the cache of the controller has not seen the status of its last scan yet
+const STALE_STATUS_DELAY: Duration = Duration::from_secs(5);
+
+/// Settings of the operator
+#[derive(Clone, Debug)]
+pub struct OperatorConfig {
+    /// Namespace of the watched resources (all namespaces when none)
+    pub namespace: Option<String>,
+    /// URL of Boavizta API
+    pub api_url: String,
+}
+
+struct Context {
+    client: Client,
+    config: OperatorConfig,
+    /// Time of the last scan of each resource (by uid), to recognize the resources of the cache whose status predates it
+    last_scans: Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Cannot update the status of CloudScan {0}: {1}")]
+    Status(String, kube::Error),
+}
+
+/// Runs the due scans of the `CloudScan` resources until interrupted (Ctrl-C or `SIGTERM`), a failed scan being reported in the status of its resource
+pub async fn run(client: Client, config: OperatorConfig) {
+    info!(
+        "Watching CloudScan resources of {}",
+        config.namespace.as_deref().unwrap_or("all namespaces")
+    );
+    let cloud_scans: Api<CloudScan> = match &config.namespace {
+        Some(namespace) => Api::namespaced(client.clone(), namespace),
+        None => Api::all(client.clone()),
+    };
+    let context = Arc::new(Context {
+        client,
+        config,
+        last_scans: Mutex::default(),
+    });
+    Controller::new(cloud_scans, watcher::Config::default())
+        // Scans run one at a time, not to be throttled by the APIs of the provider and Boavizta API
+        .with_config(controller::Config::default().concurrency(1))
+        .shutdown_on_signal()
+        .run(reconcile, error_policy, context)
+        .for_each(|reconciled| async move {
+            if let Err(e) = reconciled {
+                warn!("{}", e);
+            }
+        })
+        .await;
+    info!("Interrupted, stopping the operator");
+}
+
+/// Replaces the status of a resource
+async fn patch_status(
+    context: &Context,
+    scan: &CloudScan,
+    status: &CloudScanStatus,
+) -> Result<(), Error> {
+    let namespace = scan.namespace().unwrap_or_default();
+    let cloud_scans: Api<CloudScan> = Api::namespaced(context.client.clone(), &namespace);
+    cloud_scans
+        .patch_status(
+            &scan.name_any(),
+            &PatchParams::default(),
+            &Patch::Merge(serde_json::json!({ "status": status })),
+        )
+        .await
+        .map_err(|e| Error::Status(format!("{}/{}", namespace, scan.name_any()), e))?;
+    Ok(())
+}
+
+/// Runs the scan of a resource if it is due and writes its outcome to the status of the resource, then waits until the next scan
+async fn reconcile(scan: Arc<CloudScan>, context: Arc<Context>) -> Result<Action, Error> {
+    let name = format!(
+        "{}/{}",
+        scan.namespace().unwrap_or_default(),
+        scan.name_any()
+    );
+    let uid = scan.uid().unwrap_or_default();
+    let generation = scan.generation();
+    let previous = scan.status.clone().unwrap_or_default();
+    let last_scan = context.last_scans.lock().unwrap().get(&uid).copied();
+    if last_scan.is_some_and(|last_scan| previous.last_scan_time < Some(last_scan)) {
+        // Not to scan again a resource whose status was just written
+        return Ok(Action::requeue(STALE_STATUS_DELAY));
+    }
+    let interval = match scan.spec.interval() {
+        Ok(interval) => interval,
+        Err(e) => {
+            // Reported once per change of the spec
+            if previous.observed_generation != Some(generation) {
+                warn!("Invalid CloudScan {}: {:#}", name, e);
+                let status = previous.invalid_spec(generation, &e, Utc::now());
+                patch_status(&context, &scan, &status).await?;
+            }
+            return Ok(Action::await_change());
+        }
+    };
+    if !scan.is_due(interval, Utc::now()) {
+        return Ok(wait_until_due(&scan, interval));
+    }
+    info!("Scanning CloudScan {}", name);
+    let scanning = previous.scanning(generation, Utc::now());
+    patch_status(&context, &scan, &scanning).await?;
+    let results = match scan.spec.scanner(interval, &context.config.api_url) {
+        Ok(scanner) => scanner.scan().await,
+        Err(e) => Err(e),
+    };
+    let status = match results {
+        Ok(results) => scanning.succeeded(
+            generation,
+            &results.summary,
+            results.exports,
+            interval,
+            Utc::now(),
+        ),
+        Err(e) => {
+            error!("Scan of CloudScan {} failed: {:#}", name, e);
+            scanning.failed(generation, &e, interval, Utc::now())
+        }
+    };
+    patch_status(&context, &scan, &status).await?;
+    if let Some(last_scan_time) = status.last_scan_time {
+        context
+            .last_scans
+            .lock()
+            .unwrap()
+            .insert(uid, last_scan_time);
+    }
+    Ok(Action::requeue(interval))
+}
+
+/// Returns the action waiting for the next scan of a resource (for a change of the resource when it is suspended)
+fn wait_until_due(scan: &CloudScan, interval: Duration) -> Action {
+    match scan.due_time(interval) {
+        Some(due_time) => {
+            Action::requeue((due_time - Utc::now()).to_std().unwrap_or(Duration::ZERO))
+        }
+        None => Action::await_change(),
+    }
+}
+
+fn error_policy(scan: Arc<CloudScan>, error: &Error, _context: Arc<Context>) -> Action {
+    warn!(
+        "Cannot reconcile CloudScan {}, retrying in {:?}: {}",
+        scan.name_any(),
+        RETRY_DELAY,
+        error
+    );
+    Action::requeue(RETRY_DELAY)
+}
@@ -0,0 +1,66 @@
+//! Kubernetes operator of cloud-scanner: `CloudScan` custom resources of a cluster declare scans (provider, regions, schedule, filters and sinks of the results), the operator runs them when they are due and writes their outcome to the status of the resources, so that platform teams declare the scans of their fleet like any other Kubernetes resource.
+//!
+//! The operator connects to the cluster it runs in with its service account, or with the current context of the kubeconfig outside of a cluster.
+use clap::Parser;
+
+#[macro_use]
+extern crate log;
+
+mod cloud_scan;
+mod controller;
+mod traces;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Arguments {
+    /// Boavizta API URL if you want to use your own instance (URL without the trailing slash, e.g. https://api.boavizta.org)
+    #[arg(
+        short,
+        long,
+        env = "BOAVIZTA_API_URL",
+        default_value = "https://api.boavizta.org"
+    )]
+    boavizta_api_url: String,
+
+    /// Namespace of the CloudScan resources (by default, all namespaces)
+    #[arg(long, env = "CLOUD_SCANNER_OPERATOR_NAMESPACE")]
+    namespace: Option<String>,
+
+    /// Export traces of the scans (inventory, utilization metrics, estimation of impacts and their API calls) to this OTLP/HTTP endpoint of an OpenTelemetry collector (like http://localhost:4318), the service being named after OTEL_SERVICE_NAME (cloud-scanner-operator by default)
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Arguments::parse();
+    cloud_scanner_cli::access_log::init_logger_from_env(
+        cloud_scanner_cli::access_log::LogFormat::Text,
+    )?;
+    info!(
+        "Cloud scanner operator, using scanner lib {}",
+        cloud_scanner_cli::get_version()
+    );
+    cloud_scanner_cli::boavizta_api_v1::set_keep_raw_data(false);
+    let tracer_provider = args
+        .otlp_endpoint
+        .as_deref()
+        .map(traces::init)
+        .transpose()?;
+    let client = kube::Client::try_default().await?;
+    controller::run(
+        client,
+        controller::OperatorConfig {
+            namespace: args.namespace,
+            api_url: args.boavizta_api_url,
+        },
+    )
+    .await;
+    if let Some(tracer_provider) = tracer_provider {
+        // Exports the spans of the last scans
+        if let Err(e) = tracer_provider.shutdown() {
+            warn!("Cannot export the last traces: {}", e);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,73 @@
+//! Export of the traces of scans (`--otlp-endpoint`) to an OpenTelemetry collector (like Jaeger or Grafana Tempo) with OTLP over http.
+//!
+//! The scans of cloud-scanner are instrumented with `tracing` spans (the scan of each region, its inventory, utilization metrics and estimation of impacts, with one span per AWS or Boavizta API call), turned into OpenTelemetry spans by `tracing-opentelemetry`.
+use anyhow::{Context, Result};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Name of the service of the traces, unless set by `OTEL_SERVICE_NAME`
+pub const DEFAULT_SERVICE_NAME: &str = "cloud-scanner-operator";
+
+/// Returns the name of the service of the traces
+fn service_name() -> String {
+    std::env::var("OTEL_SERVICE_NAME")
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string())
+}
+
+/// Returns the URL of the traces of an OTLP endpoint (like `http://localhost:4318`)
+fn traces_url(otlp_endpoint: &str) -> String {
+    format!("{}/v1/traces", otlp_endpoint.trim_end_matches('/'))
+}
+
+/// Exports the spans of the operator to the OTLP endpoint of a collector, in batches. The returned provider is shut down before exiting, to export the last spans.
+pub fn init(otlp_endpoint: &str) -> Result<TracerProvider> {
+    let service_name = service_name();
+    info!(
+        "Exporting traces of scans to {} as {}",
+        otlp_endpoint, service_name
+    );
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(traces_url(otlp_endpoint)),
+        )
+        .with_trace_config(opentelemetry_sdk::trace::Config::default().with_resource(
+            Resource::new(vec![
+                KeyValue::new("service.name", service_name),
+                KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+            ]),
+        ))
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .context("Cannot export traces")?;
+    let tracer = provider.tracer("cloud-scanner-operator");
+    // Logs are not written through tracing (see cloud_scanner_cli::access_log), only spans are collected
+    let subscriber =
+        tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_global_default(subscriber).context("Cannot collect spans")?;
+    Ok(provider)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traces_are_sent_to_the_traces_of_the_endpoint() {
+        assert_eq!(
+            "http://localhost:4318/v1/traces",
+            traces_url("http://localhost:4318/")
+        );
+        assert_eq!(
+            "https://otlp.example.com/v1/traces",
+            traces_url("https://otlp.example.com")
+        );
+    }
+}
@@ -0,0 +1,32 @@
+from typing import Any, Optional, Union
+
+def inventory(
+    regions: list[str],
+    filter_tags: list[str] = [],
+    include_block_storage: bool = False,
+    role_arns: list[str] = [],
+) -> dict[str, Any]:
+    """Lists the resources of AWS accounts in the regions, without estimating their impacts (json output of the inventory command)."""
+
+def estimate(
+    use_duration_hours: float,
+    regions: list[str] = [],
+    filter_tags: list[str] = [],
+    include_block_storage: bool = False,
+    role_arns: list[str] = [],
+    inventory: Optional[Union[dict[str, Any], list[Any], str]] = None,
+    boavizta_api_url: Optional[str] = None,
+    verbose: bool = False,
+    summary_only: bool = False,
+) -> dict[str, Any]:
+    """Estimates the impacts of the resources of AWS accounts, or of an inventory, for the duration of use (json output of the estimate command)."""
+
+def summarize(
+    results: Union[dict[str, Any], str],
+    regions: list[str] = [],
+    use_duration_hours: Optional[float] = None,
+) -> dict[str, Any]:
+    """Summarizes the impacts of the results of estimate by region."""
+
+def version() -> str:
+    """Returns the version of cloud-scanner."""
@@ -0,0 +1,206 @@
+//! Python bindings of cloud-scanner (the `cloud_scanner` module of the `cloud-scanner` package, built with maturin), for the pipelines and notebooks of data teams: inventories, estimations and summaries are returned as the Python objects of their json (dicts and lists, ready for `pandas.json_normalize`).
+//!
+//! ```python
+//! import cloud_scanner
+//!
+//! results = cloud_scanner.estimate(730, regions=["eu-west-3"], include_block_storage=True)
+//! summary = cloud_scanner.summarize(results)
+//! ```
+use anyhow::Context;
+use cloud_scanner_cli::aws_cloud_provider::{AwsAccount, ResourceSelection};
+use cloud_scanner_cli::scanner::{ResultFormat, Scanner};
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use std::future::Future;
+
+/// Returns the error of a scan as a Python exception (with its causes)
+fn to_py_err(error: anyhow::Error) -> PyErr {
+    PyRuntimeError::new_err(format!("{:#}", error))
+}
+
+/// Runs a scan to completion, releasing the GIL while it runs
+fn block_on<T, F>(py: Python<'_>, future: F) -> PyResult<T>
+where
+    T: Send,
+    F: Future<Output = anyhow::Result<T>> + Send,
+{
+    py.allow_threads(|| {
+        tokio::runtime::Runtime::new()
+            .context("Cannot start the runtime of the scan")?
+            .block_on(future)
+    })
+    .map_err(to_py_err)
+}
+
+/// Returns the Python object of a json document
+fn loads<'py>(py: Python<'py>, json: &str) -> PyResult<Bound<'py, PyAny>> {
+    py.import_bound("json")?.call_method1("loads", (json,))
+}
+
+/// Returns the json document of a Python object, or the string itself
+fn dumps(value: &Bound<'_, PyAny>) -> PyResult<String> {
+    if let Ok(json) = value.extract::<String>() {
+        return Ok(json);
+    }
+    value
+        .py()
+        .import_bound("json")?
+        .call_method1("dumps", (value,))?
+        .extract()
+}
+
+/// Returns the accounts of role ARNs (the account of the environment without roles)
+fn accounts(role_arns: &[String]) -> PyResult<Vec<AwsAccount>> {
+    role_arns
+        .iter()
+        .map(|role_arn| AwsAccount::from_role_arn(role_arn).map_err(to_py_err))
+        .collect()
+}
+
+/// Lists the resources of AWS accounts (by assuming the roles, the account of the environment without roles) in the regions, without estimating their impacts. Returns the json output of the inventory command.
+#[pyfunction]
+#[pyo3(signature = (regions, filter_tags = Vec::new(), include_block_storage = false, role_arns = Vec::new()))]
+fn inventory<'py>(
+    py: Python<'py>,
+    regions: Vec<String>,
+    filter_tags: Vec<String>,
+    include_block_storage: bool,
+    role_arns: Vec<String>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let accounts = accounts(&role_arns)?;
+    let json = block_on(py, async move {
+        cloud_scanner_cli::get_inventory_of_accounts_as_json(
+            &accounts,
+            &filter_tags,
+            &regions,
+            include_block_storage,
+            &ResourceSelection::default(),
+            None,
+        )
+        .await
+    })?;
+    loads(py, &json)
+}
+
+/// Estimates the impacts of the resources of AWS accounts in the regions, or of an inventory (the output of `inventory`, as object or json), for the duration of use (hours). Returns the json output of the estimate command (or only its summary).
+#[pyfunction]
+#[pyo3(signature = (
+    use_duration_hours,
+    regions = Vec::new(),
+    filter_tags = Vec::new(),
+    include_block_storage = false,
+    role_arns = Vec::new(),
+    inventory = None,
+    boavizta_api_url = None,
+    verbose = false,
+    summary_only = false,
+))]
+#[allow(clippy::too_many_arguments)]
+fn estimate<'py>(
+    py: Python<'py>,
+    use_duration_hours: f32,
+    regions: Vec<String>,
+    filter_tags: Vec<String>,
+    include_block_storage: bool,
+    role_arns: Vec<String>,
+    inventory: Option<&Bound<'py, PyAny>>,
+    boavizta_api_url: Option<String>,
+    verbose: bool,
+    summary_only: bool,
+) -> PyResult<Bound<'py, PyAny>> {
+    let mut builder = Scanner::builder()
+        .regions(regions)
+        .filter_tags(filter_tags)
+        .include_block_storage(include_block_storage)
+        .use_duration_hours(use_duration_hours)
+        .verbose(verbose);
+    builder = match inventory {
+        Some(inventory) => {
+            let (_, inventory) = cloud_scanner_cli::inventory_file::parse_inventory(&dumps(
+                inventory,
+            )?)
+            .map_err(to_py_err)?;
+            builder.inventory(inventory)
+        }
+        None => builder.aws_accounts(accounts(&role_arns)?),
+    };
+    if let Some(boavizta_api_url) = boavizta_api_url {
+        builder = builder.boavizta_api_url(boavizta_api_url);
+    }
+    let scanner = builder.build().map_err(to_py_err)?;
+    let format = if summary_only {
+        ResultFormat::SummaryJson
+    } else {
+        ResultFormat::Json
+    };
+    let json = block_on(py, async move {
+        let results = scanner.scan().await?;
+        format.format(&results)
+    })?;
+    loads(py, &json)
+}
+
+/// Summarizes the impacts of the results of `estimate` (as object or json, like a saved scan) by region: the regions and duration of use of the scan are used unless passed. Returns the json of the summary.
+#[pyfunction]
+#[pyo3(signature = (results, regions = Vec::new(), use_duration_hours = None))]
+fn summarize<'py>(
+    py: Python<'py>,
+    results: &Bound<'py, PyAny>,
+    regions: Vec<String>,
+    use_duration_hours: Option<f32>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let (metadata, estimated_inventory) =
+        cloud_scanner_cli::scan_diff::parse_scan(&dumps(results)?).map_err(to_py_err)?;
+    let regions = if !regions.is_empty() {
+        regions
+    } else if let Some(metadata) = &metadata {
+        metadata
+            .parameters
+            .aws_region
+            .split(',')
+            .map(str::to_string)
+            .collect()
+    } else {
+        let mut regions: Vec<String> = estimated_inventory
+            .impacting_resources
+            .iter()
+            .map(|resource| resource.cloud_resource.location.aws_region.clone())
+            .collect();
+        regions.sort();
+        regions.dedup();
+        regions
+    };
+    let use_duration_hours = use_duration_hours
+        .or_else(|| metadata.and_then(|metadata| metadata.parameters.use_duration_hours))
+        .or_else(|| {
+            estimated_inventory
+                .impacting_resources
+                .first()
+                .map(|resource| resource.impacts_duration_hours)
+        })
+        .ok_or_else(|| PyRuntimeError::new_err("Missing the duration of use of the results"))?;
+    let summary = cloud_scanner_cli::build_summary_of_accounts(
+        &estimated_inventory,
+        &[],
+        &regions,
+        &use_duration_hours,
+    )
+    .map_err(to_py_err)?;
+    let json = serde_json::to_string(&summary).map_err(|e| to_py_err(e.into()))?;
+    loads(py, &json)
+}
+
+/// Returns the version of cloud-scanner
+#[pyfunction]
+fn version() -> String {
+    cloud_scanner_cli::get_version()
+}
+
+#[pymodule]
+fn cloud_scanner(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(inventory, m)?)?;
+    m.add_function(wrap_pyfunction!(estimate, m)?)?;
+    m.add_function(wrap_pyfunction!(summarize, m)?)?;
+    m.add_function(wrap_pyfunction!(version, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,51 @@
+"""Smoke tests of the cloud_scanner module, run in CI on the wheel built by maturin.
+
+They need neither cloud credentials nor Boavizta API: the estimated inventory is empty.
+"""
+import re
+
+import pytest
+
+import cloud_scanner
+
+EMPTY_INVENTORY = {"resources": [], "executionStatistics": None}
+
+# Refused at once, the version of the API being left out of the metadata
+UNREACHABLE_API = "http://localhost:1"
+
+
+def test_version():
+    assert re.match(r"^\d+\.\d+\.\d+", cloud_scanner.version())
+
+
+def test_estimate_and_summarize_an_inventory():
+    results = cloud_scanner.estimate(
+        730,
+        regions=["eu-west-3"],
+        inventory=EMPTY_INVENTORY,
+        boavizta_api_url=UNREACHABLE_API,
+    )
+    assert results["data"]["impactingResources"] == []
+    assert results["metadata"]["parameters"]["aws_region"] == "eu-west-3"
+
+    summary = cloud_scanner.summarize(results)
+    assert summary["number_of_resources_total"] == 0
+    assert summary["duration_of_use_hours"] == 730
+
+
+def test_summary_only():
+    summary = cloud_scanner.estimate(
+        24,
+        regions=["eu-west-3"],
+        inventory='{"resources": [], "executionStatistics": null}',
+        boavizta_api_url=UNREACHABLE_API,
+        summary_only=True,
+    )
+    assert summary["data"]["number_of_resources_total"] == 0
+
+
+def test_errors_raise_runtime_errors():
+    with pytest.raises(RuntimeError):
+        cloud_scanner.estimate(0, regions=["eu-west-3"], inventory=EMPTY_INVENTORY)
+    with pytest.raises(RuntimeError):
+        cloud_scanner.summarize({"instances": []})